@@ -0,0 +1,83 @@
+//! Best-effort error notifications through the `xdg_activation_v1` protocol.
+//!
+//! When something goes wrong, we ask the compositor for an activation token, and then spawn
+//! `notify-send` with it in `XDG_ACTIVATION_TOKEN`, so that the compositor can bring focus to the
+//! notification. If the compositor doesn't support the protocol (or `notify-send` isn't
+//! installed), we only log the error, as usual.
+
+use log::{debug, warn};
+
+use smithay_client_toolkit::reexports::protocols::xdg::activation::v1::client::{
+    xdg_activation_token_v1::{self, XdgActivationTokenV1},
+    xdg_activation_v1::XdgActivationV1,
+};
+
+use wayland_client::{globals::GlobalList, Connection, Dispatch, QueueHandle};
+
+use crate::Daemon;
+
+#[derive(Clone)]
+pub struct Activation {
+    global: XdgActivationV1,
+    qh: QueueHandle<Daemon>,
+}
+
+impl Activation {
+    /// Returns `None` if the compositor doesn't support `xdg_activation_v1`
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Daemon>) -> Option<Self> {
+        match globals.bind::<XdgActivationV1, _, _>(qh, 1..=1, ()) {
+            Ok(global) => Some(Self {
+                global,
+                qh: qh.clone(),
+            }),
+            Err(e) => {
+                debug!("xdg_activation_v1 is not available, errors will only be logged: {e}");
+                None
+            }
+        }
+    }
+
+    /// Requests a token to show `msg` with. The notification itself is sent once the compositor
+    /// gives us the token
+    pub fn notify_error(&self, msg: &str) {
+        let token = self.global.get_activation_token(&self.qh, msg.to_string());
+        token.set_app_id("swww-daemon".to_string());
+        token.commit();
+        crate::wake_poll();
+    }
+}
+
+impl Dispatch<XdgActivationV1, ()> for Daemon {
+    fn event(
+        _state: &mut Self,
+        _proxy: &XdgActivationV1,
+        _event: <XdgActivationV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // xdg_activation_v1 has no events
+    }
+}
+
+impl Dispatch<XdgActivationTokenV1, String> for Daemon {
+    fn event(
+        _state: &mut Self,
+        proxy: &XdgActivationTokenV1,
+        event: xdg_activation_token_v1::Event,
+        msg: &String,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_activation_token_v1::Event::Done { token } = event {
+            proxy.destroy();
+            if let Err(e) = std::process::Command::new("notify-send")
+                .args(["--app-name=swww", "swww-daemon error", msg])
+                .env("XDG_ACTIVATION_TOKEN", token)
+                .spawn()
+            {
+                warn!("failed to spawn notify-send to show error: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Adjusts the colors and sharpness of the image an output displays, see `swww img --blur` and
+//! `swww adjust`.
+//!
+//! Like the tint (see `crate::tint`), we adjust what we drew every time we copy it to the buffer,
+//! rather than the pixels we are sent. Animations are thus adjusted frame by frame, even though
+//! they only draw what changed, and `swww adjust` changes the adjustments of what we display
+//! without the client sending it again. Unlike the tint, the adjustments belong to the image, and
+//! go away with it.
+
+use rayon::prelude::*;
+use utils::ipc::{Adjustments, Tone};
+
+/// Blurring more only makes us slower, since whatever is on the output has long become a flat color
+const MAX_BLUR: f32 = 1000.0;
+
+/// The usual sepia matrix, from red, green and blue to sepia red, green and blue
+const SEPIA: [[f32; 3]; 3] = [
+    [0.393, 0.769, 0.189],
+    [0.349, 0.686, 0.168],
+    [0.272, 0.534, 0.131],
+];
+
+pub struct Adjust {
+    /// The sizes of the box blurs that, one after the other, make the gaussian one
+    boxes: Vec<usize>,
+    /// What brightness and contrast make of every value of a channel, before the saturation
+    levels: [f32; 256],
+    saturation: f32,
+    tone: Option<Tone>,
+    /// What we adjusted last, kept so that we don't allocate a canvas for every frame
+    adjusted: Vec<u8>,
+    /// Where the blur puts the result of its first pass
+    scratch: Vec<u8>,
+}
+
+impl Adjust {
+    /// Returns `None` if `adjustments` change nothing
+    pub fn new(adjustments: &Adjustments) -> Option<Self> {
+        if *adjustments == Adjustments::default() {
+            return None;
+        }
+        let brightness = adjustments.brightness as f32 * 255.0 / 100.0;
+        let contrast = 1.0 + adjustments.contrast as f32 / 100.0;
+        Some(Self {
+            boxes: adjustments
+                .blur
+                .filter(|sigma| *sigma > 0.0)
+                .map(|sigma| gaussian_boxes(sigma.min(MAX_BLUR)))
+                .unwrap_or_default(),
+            levels: std::array::from_fn(|col| (col as f32 + brightness - 127.5) * contrast + 127.5),
+            saturation: 1.0 + adjustments.saturation as f32 / 100.0,
+            tone: adjustments.tone,
+            adjusted: Vec::new(),
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Adjusts `src`, `xrgb8888` pixels (`[b, g, r, x]` in memory), `dim` in size. We blur it
+    /// first, then shift its brightness, contrast and saturation, and apply the tone last
+    pub fn apply(&mut self, src: &[u8], dim: (usize, usize)) -> &[u8] {
+        self.adjusted.clear();
+        self.adjusted.extend_from_slice(src);
+        if dim.0 > 0 && dim.1 > 0 {
+            self.scratch.resize(src.len(), 0);
+            for &size in &self.boxes {
+                box_blur_rows(&self.adjusted, &mut self.scratch, dim, size / 2);
+                box_blur_columns(&self.scratch, &mut self.adjusted, dim, size / 2);
+            }
+        }
+        let (levels, saturation, tone) = (&self.levels, self.saturation, self.tone);
+        self.adjusted.par_chunks_exact_mut(4).for_each(|pixel| {
+            let [r, g, b] = [pixel[2], pixel[1], pixel[0]].map(|col| levels[col as usize]);
+            // Rec. 709 luma coefficients, like the client's color temperatures
+            let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            let rgb = [r, g, b].map(|col| (luma + (col - luma) * saturation).clamp(0.0, 255.0));
+            let [r, g, b] = match tone {
+                Some(tone) => apply_tone(rgb, tone),
+                None => rgb,
+            }
+            .map(|col| col.round().clamp(0.0, 255.0) as u8);
+            pixel[..3].copy_from_slice(&[b, g, r]);
+        });
+        &self.adjusted
+    }
+}
+
+fn apply_tone([r, g, b]: [f32; 3], tone: Tone) -> [f32; 3] {
+    match tone {
+        Tone::Grayscale => [0.2126 * r + 0.7152 * g + 0.0722 * b; 3],
+        Tone::Invert => [255.0 - r, 255.0 - g, 255.0 - b],
+        Tone::Sepia => SEPIA.map(|row| row[0] * r + row[1] * g + row[2] * b),
+    }
+}
+
+/// The odd sizes of the three box blurs that, one after the other, come closest to a gaussian blur
+/// of standard deviation `sigma`, see "Fast Almost-Gaussian Filtering" (Kovesi, 2010)
+fn gaussian_boxes(sigma: f32) -> Vec<usize> {
+    const PASSES: f32 = 3.0;
+    let variance = 12.0 * sigma * sigma;
+    let ideal = (variance / PASSES + 1.0).sqrt();
+    let mut lower = ideal.floor() as usize;
+    if lower % 2 == 0 {
+        lower = lower.saturating_sub(1);
+    }
+    let l = lower as f32;
+    let smaller = ((variance - PASSES * l * l - 4.0 * PASSES * l - 3.0 * PASSES) / (-4.0 * l - 4.0))
+        .round()
+        .clamp(0.0, PASSES) as usize;
+    (0..PASSES as usize)
+        .map(|i| if i < smaller { lower } else { lower + 2 })
+        .filter(|&size| size > 1)
+        .collect()
+}
+
+/// Averages every pixel of `src` with the `radius` pixels to each side of it, repeating those at
+/// the edges, into `dst`
+fn box_blur_rows(src: &[u8], dst: &mut [u8], (width, _): (usize, usize), radius: usize) {
+    let size = 2 * radius as u32 + 1;
+    let last = width - 1;
+    dst.par_chunks_exact_mut(width * 4)
+        .zip(src.par_chunks_exact(width * 4))
+        .for_each(|(dst, src)| {
+            for channel in 0..3 {
+                let at = |x: usize| src[x.min(last) * 4 + channel] as u32;
+                let mut sum = radius as u32 * at(0) + (0..=radius).map(at).sum::<u32>();
+                for x in 0..width {
+                    dst[x * 4 + channel] = ((sum + size / 2) / size) as u8;
+                    sum = sum + at(x + radius + 1) - at(x.saturating_sub(radius));
+                }
+            }
+        });
+}
+
+/// Like [`box_blur_rows`], but with the pixels above and below
+fn box_blur_columns(src: &[u8], dst: &mut [u8], (width, height): (usize, usize), radius: usize) {
+    let size = 2 * radius as u32 + 1;
+    let stride = width * 4;
+    let row = |y: usize| &src[y.min(height - 1) * stride..][..stride];
+    // a running sum per channel of every pixel of a row, which is kinder to the cache than going
+    // down every column in turn
+    let mut sums = vec![0; stride];
+    for (i, sum) in sums.iter_mut().enumerate() {
+        *sum =
+            radius as u32 * row(0)[i] as u32 + (0..=radius).map(|y| row(y)[i] as u32).sum::<u32>();
+    }
+    for (y, dst) in dst.chunks_exact_mut(stride).enumerate() {
+        let (add, remove) = (row(y + radius + 1), row(y.saturating_sub(radius)));
+        for (i, sum) in sums.iter_mut().enumerate() {
+            if i % 4 != 3 {
+                dst[i] = ((*sum + size / 2) / size) as u8;
+            }
+            *sum = *sum + add[i] as u32 - remove[i] as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adjusted(adjustments: Adjustments) -> [u8; 3] {
+        // [b, g, r, x], of rgb(200, 100, 50)
+        let canvas = [50, 100, 200, 0xff].repeat(4);
+        let mut adjust = Adjust::new(&adjustments).unwrap();
+        let pixel = &adjust.apply(&canvas, (2, 2))[..4];
+        assert_eq!(pixel[3], 0xff);
+        [pixel[2], pixel[1], pixel[0]]
+    }
+
+    #[test]
+    fn adjustments_should_shift_the_colors() {
+        assert!(Adjust::new(&Adjustments::default()).is_none());
+        let brighter = adjusted(Adjustments {
+            brightness: 20,
+            ..Default::default()
+        });
+        assert_eq!(brighter, [251, 151, 101]);
+        let [r, g, b] = adjusted(Adjustments {
+            saturation: -100,
+            ..Default::default()
+        });
+        assert!(r == g && g == b, "{:?}", [r, g, b]);
+        let [r, _, b] = adjusted(Adjustments {
+            contrast: 50,
+            ..Default::default()
+        });
+        assert!(r > 200 && b < 50, "{:?}", [r, b]);
+    }
+
+    #[test]
+    fn tones_should_replace_the_colors() {
+        let toned = |tone| {
+            adjusted(Adjustments {
+                tone: Some(tone),
+                ..Default::default()
+            })
+        };
+        assert_eq!(toned(Tone::Invert), [55, 155, 205]);
+        let [r, g, b] = toned(Tone::Grayscale);
+        assert!(r == g && g == b, "{:?}", [r, g, b]);
+        let [r, g, b] = toned(Tone::Sepia);
+        assert!(r > g && g > b, "{:?}", [r, g, b]);
+    }
+
+    #[test]
+    fn blurs_should_spread_every_pixel_evenly_around_it() {
+        let (width, height) = (15, 15);
+        let mut canvas = vec![0; width * height * 4];
+        let center = (7 * width + 7) * 4;
+        canvas[center..center + 3].copy_from_slice(&[255; 3]);
+        let mut adjust = Adjust::new(&Adjustments {
+            blur: Some(1.5),
+            ..Default::default()
+        })
+        .unwrap();
+        let blurred = adjust.apply(&canvas, (width, height));
+        let blue = |x: usize, y: usize| blurred[(y * width + x) * 4];
+
+        assert!(blue(7, 7) < 255 && blue(7, 7) > blue(8, 7));
+        assert!(blue(8, 7) > blue(9, 7) && blue(9, 7) > 0);
+        assert_eq!(blue(0, 0), 0);
+        for (x, y) in [(9, 7), (8, 8), (10, 9)] {
+            let mirrored = [(14 - x, y), (x, 14 - y), (14 - x, 14 - y)];
+            assert!(mirrored.iter().all(|&(x2, y2)| blue(x2, y2) == blue(x, y)));
+        }
+        // the alpha channel isn't blurred
+        assert!(blurred.chunks_exact(4).all(|pixel| pixel[3] == 0));
+    }
+
+    #[test]
+    fn blurs_should_leave_flat_colors_as_they_are_up_to_the_edges() {
+        let canvas = [50, 100, 200, 0xff].repeat(3 * 2);
+        for sigma in [0.5, 2.0, 40.0] {
+            let mut adjust = Adjust::new(&Adjustments {
+                blur: Some(sigma),
+                ..Default::default()
+            })
+            .unwrap();
+            assert_eq!(adjust.apply(&canvas, (3, 2)), canvas);
+        }
+    }
+}
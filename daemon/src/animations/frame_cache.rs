@@ -0,0 +1,262 @@
+//! Keeps track of how much memory the frames of running animations take.
+//!
+//! Every running animation holds all of its frames in memory, unless the client streams them to it
+//! loop after loop, see `Playback::stream`. When we are given a memory limit, the frames that
+//! don't fit in what the other animations leave, whether they come with the animation or after
+//! it, are spilled to the disk, see `spill`, and aren't counted. Only if that fails are they
+//! refused, so that the animation stops on the last frame it got. Animations that play are never
+//! stopped to make room.
+//!
+//! An animation that was replaced only frees its frames once its thread notices, so we don't count
+//! those that no longer play on any of their wallpapers, which the new animation is likely taking
+//! over from.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+/// Whether the animation still plays on any of its wallpapers
+pub type Playing = Box<dyn Fn() -> bool + Send>;
+
+struct Entry {
+    id: u64,
+    bytes: usize,
+    /// The `Wallpaper::output_id` of every output the animation was made for
+    outputs: Vec<u32>,
+    playing: Playing,
+}
+
+pub struct FrameCache {
+    limit: Option<usize>,
+    entries: Vec<Entry>,
+    next_id: u64,
+}
+
+impl FrameCache {
+    /// `limit` is in bytes. `None` means we take any frames
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            entries: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Starts tracking `bytes` worth of frames, of an animation on `outputs` that plays for as
+    /// long as `playing` says, if they fit under the limit
+    pub fn insert(
+        cache: &Arc<Mutex<Self>>,
+        bytes: usize,
+        outputs: Vec<u32>,
+        playing: Playing,
+    ) -> Result<CachedFrames, String> {
+        let mut this = cache.lock().unwrap();
+        this.check_room(None, bytes)?;
+        let id = this.next_id;
+        this.next_id += 1;
+        this.entries.push(Entry {
+            id,
+            bytes,
+            outputs,
+            playing,
+        });
+        Ok(CachedFrames {
+            id,
+            bytes: Arc::new(AtomicUsize::new(bytes)),
+            cache: Arc::clone(cache),
+        })
+    }
+
+    /// Whether `bytes` more fit under the limit, next to the frames of every other animation that
+    /// still plays
+    fn check_room(&self, id: Option<u64>, bytes: usize) -> Result<(), String> {
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        let taken: usize = self
+            .entries
+            .iter()
+            .filter(|e| Some(e.id) == id || (e.playing)())
+            .map(|e| e.bytes)
+            .sum();
+        let room = limit.saturating_sub(taken);
+        if bytes > room {
+            return Err(format!(
+                "its frames take {bytes} bytes, but only {room} of the memory limit are left"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether `bytes` worth of frames, of an animation about to replace those on `outputs`, will
+    /// fit under the limit once it does. Those that only play on `outputs` won't be counted then
+    pub fn fits_replacing(&self, outputs: &[u32], bytes: usize) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        let taken: usize = self
+            .entries
+            .iter()
+            .filter(|e| (e.playing)() && !e.outputs.iter().all(|o| outputs.contains(o)))
+            .map(|e| e.bytes)
+            .sum();
+        bytes <= limit.saturating_sub(taken)
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Replaces the limit. The frames we already took stay, even if they no longer fit
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// How many bytes the frames of all running animations take
+    pub fn total_bytes(&self) -> usize {
+        self.entries.iter().map(|e| e.bytes).sum()
+    }
+
+    fn grow(&mut self, id: u64, bytes: usize) -> Result<(), String> {
+        self.check_room(Some(id), bytes)?;
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) {
+            entry.bytes += bytes;
+        }
+        Ok(())
+    }
+
+    fn shrink(&mut self, id: u64, bytes: usize) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) {
+            entry.bytes = entry.bytes.saturating_sub(bytes);
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.entries.retain(|e| e.id != id);
+    }
+}
+
+/// The frames of a single animation. Stops being tracked once dropped
+pub struct CachedFrames {
+    id: u64,
+    /// How many bytes the frames take, for `swww stats`. 0 once they are freed
+    bytes: Arc<AtomicUsize>,
+    cache: Arc<Mutex<FrameCache>>,
+}
+
+impl CachedFrames {
+    /// Tracks `bytes` more worth of frames, that just arrived, if they fit under the limit, like
+    /// [`FrameCache::insert`] does
+    pub fn grow(&self, bytes: usize) -> Result<(), String> {
+        self.cache.lock().unwrap().grow(self.id, bytes)?;
+        self.bytes.fetch_add(bytes, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Stops tracking `bytes` worth of the frames, that we dropped
+    pub fn shrink(&self, bytes: usize) {
+        let _ = self
+            .bytes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |b| {
+                Some(b.saturating_sub(bytes))
+            });
+        self.cache.lock().unwrap().shrink(self.id, bytes);
+    }
+
+    /// How many bytes the frames take, until they are freed
+    pub fn bytes(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.bytes)
+    }
+}
+
+impl Drop for CachedFrames {
+    fn drop(&mut self) {
+        self.bytes.store(0, Ordering::Release);
+        self.cache.lock().unwrap().remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicBool;
+
+    const MIB: usize = 1024 * 1024;
+
+    fn playing() -> Playing {
+        Box::new(|| true)
+    }
+
+    fn limited(mib: usize) -> Arc<Mutex<FrameCache>> {
+        Arc::new(Mutex::new(FrameCache::new(Some(mib * MIB))))
+    }
+
+    #[test]
+    fn animations_over_the_limit_should_be_refused_without_stopping_others() {
+        let cache = limited(12);
+        let first = FrameCache::insert(&cache, 10 * MIB, vec![0], playing()).unwrap();
+        assert!(FrameCache::insert(&cache, 10 * MIB, vec![0], playing()).is_err());
+        assert!(FrameCache::insert(&cache, 13 * MIB, vec![0], playing()).is_err());
+        assert_eq!(first.bytes().load(Ordering::Acquire), 10 * MIB);
+        assert_eq!(cache.lock().unwrap().total_bytes(), 10 * MIB);
+
+        // until the first one is done
+        drop(first);
+        assert!(FrameCache::insert(&cache, 10 * MIB, vec![0], playing()).is_ok());
+    }
+
+    #[test]
+    fn replaced_animations_should_make_room_for_the_next() {
+        let cache = limited(12);
+        let replaced = Arc::new(AtomicBool::new(false));
+        let still_playing = {
+            let replaced = Arc::clone(&replaced);
+            Box::new(move || !replaced.load(Ordering::Acquire))
+        };
+        let old = FrameCache::insert(&cache, 10 * MIB, vec![0], still_playing).unwrap();
+        replaced.store(true, Ordering::Release);
+        // even though the old one's thread didn't free its frames yet
+        let new = FrameCache::insert(&cache, 10 * MIB, vec![0], playing()).unwrap();
+        assert_eq!(cache.lock().unwrap().total_bytes(), 20 * MIB);
+        drop(old);
+        assert_eq!(cache.lock().unwrap().total_bytes(), 10 * MIB);
+        drop(new);
+    }
+
+    #[test]
+    fn animations_should_know_whether_they_fit_in_place_of_those_they_replace() {
+        let cache = limited(12);
+        let _both = FrameCache::insert(&cache, 4 * MIB, vec![1, 2], playing()).unwrap();
+        let _first = FrameCache::insert(&cache, 6 * MIB, vec![1], playing()).unwrap();
+        let this = cache.lock().unwrap();
+        assert!(this.fits_replacing(&[1], 8 * MIB));
+        assert!(!this.fits_replacing(&[1], 9 * MIB));
+        assert!(this.fits_replacing(&[1, 2], 12 * MIB));
+        assert!(!this.fits_replacing(&[3], 3 * MIB));
+        assert!(FrameCache::new(None).fits_replacing(&[], usize::MAX));
+    }
+
+    #[test]
+    fn frames_that_arrive_later_should_only_be_taken_if_they_fit() {
+        let cache = limited(12);
+        let first = FrameCache::insert(&cache, 4 * MIB, vec![0], playing()).unwrap();
+        let second = FrameCache::insert(&cache, 4 * MIB, vec![0], playing()).unwrap();
+        first.grow(2 * MIB).unwrap();
+        assert!(second.grow(4 * MIB).is_err());
+        assert_eq!(second.bytes().load(Ordering::Acquire), 4 * MIB);
+        // dropping frames we played makes room
+        first.shrink(4 * MIB);
+        second.grow(4 * MIB).unwrap();
+        assert_eq!(cache.lock().unwrap().total_bytes(), 10 * MIB);
+    }
+
+    #[test]
+    fn should_take_any_frames_without_a_limit() {
+        let cache = Arc::new(Mutex::new(FrameCache::new(None)));
+        let first = FrameCache::insert(&cache, 10 * MIB, vec![0], playing()).unwrap();
+        let _second = FrameCache::insert(&cache, usize::MAX / 2, vec![0], playing()).unwrap();
+        first.grow(usize::MAX / 4).unwrap();
+    }
+}
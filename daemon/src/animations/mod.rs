@@ -1,44 +1,246 @@
-use log::error;
-use rkyv::{boxed::ArchivedBox, string::ArchivedString, Deserialize};
+use log::{error, info, warn};
+use rkyv::Deserialize;
 
 use std::{
-    sync::Arc,
+    os::unix::net::UnixStream,
+    sync::{Arc, Mutex, RwLock, Weak},
     thread::{self, Scope},
     time::Duration,
 };
 
 use utils::ipc::{
-    Answer, ArchivedAnimation, ArchivedImg, ArchivedRequest, ArchivedTransition, BgImg, Request,
+    Answer, ArchivedAnimation, ArchivedAtEnd, ArchivedImg, ArchivedPlayback, ArchivedRequest,
+    ArchivedTransition, ArchivedTransitionPreview, ArchivedTransitionType, BgImg, Error, ErrorKind,
+    Request, Transform,
 };
 
-use crate::wallpaper::{AnimationToken, Wallpaper};
+use utils::comp_decomp::ArchivedBitPack;
+
+use crate::{
+    battery::{self, BatteryPolicy},
+    wallpaper::{AnimationToken, Wallpaper},
+};
 
 mod anim_barrier;
+mod frame_cache;
+mod spill;
+mod stream;
 mod transitions;
 use transitions::Transition;
 
-use self::anim_barrier::ArcAnimBarrier;
+use self::{
+    anim_barrier::ArcAnimBarrier,
+    frame_cache::FrameCache,
+    spill::Bytes,
+    stream::{FrameStream, Frames},
+};
 
 ///The default thread stack size of 2MiB is way too overkill for our purposes
 const STACK_SIZE: usize = 1 << 17; //128KiB
+/// How often a paused animation checks whether it was resumed
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Renders every frame of a transition off-screen, without needing a wayland connection
+pub fn render_preview(preview: &ArchivedTransitionPreview) -> Result<Vec<Box<[u8]>>, String> {
+    let dimensions = (preview.dimensions.0, preview.dimensions.1);
+    let expected = dimensions.0 as usize * dimensions.1 as usize * 3;
+    if preview.old_img.len() != expected || preview.new_img.len() != expected {
+        return Err(format!(
+            "images are of wrong size! Image lens: {} and {}, expected size: {expected}",
+            preview.old_img.len(),
+            preview.new_img.len()
+        ));
+    }
+    let transition = Transition::headless(&preview.old_img, dimensions, preview.transition.clone());
+    Ok(transition.render(&preview.new_img))
+}
+
+/// What to do when a new image arrives while a transition is still playing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InterruptPolicy {
+    /// Let the transition finish, and play the new one after it
+    Keep,
+    /// Start transitioning to the new image from wherever the old transition was
+    #[default]
+    Restart,
+    /// Stop the old transition, and cut straight to the new image
+    Skip,
+}
+
+/// What to do when an animation frame takes longer than its delay to show
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FrameDropRecovery {
+    /// Skip the frames we missed, to stay in sync with the wall clock
+    #[default]
+    Warp,
+    /// Show every frame anyway, falling behind
+    Hold,
+}
+
+impl FrameDropRecovery {
+    /// Whether to skip the next frame, that lasts `delay`, when we are `behind` the clock. If so,
+    /// its delay is taken off `behind`. Frames without a delay are never skipped, or an animation
+    /// made of them would never catch up
+    fn skips(self, behind: &mut Duration, delay: Duration) -> bool {
+        match self {
+            Self::Warp if !delay.is_zero() && delay <= *behind => {
+                *behind -= delay;
+                true
+            }
+            Self::Warp | Self::Hold => false,
+        }
+    }
+}
+
+/// How a transition starts on a wallpaper
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Start {
+    /// Right away, from whatever the wallpaper is displaying
+    Now,
+    /// Right away, but cutting straight to the new image
+    Cut,
+    /// Once every transition before this ticket is done. See [`Wallpaper::queue_transition`]
+    Queued(usize),
+}
+
+impl InterruptPolicy {
+    /// Decides how a new transition starts on `wallpaper`, queueing it if needed. Unless it is
+    /// queued, whatever the wallpaper was doing must be stopped right away
+    pub fn start(self, wallpaper: &Wallpaper) -> Start {
+        if wallpaper.is_transitioning() || wallpaper.expects_animation() {
+            self.interrupt(|| wallpaper.queue_transition())
+        } else {
+            Start::Now
+        }
+    }
+
+    fn interrupt(self, queue: impl FnOnce() -> usize) -> Start {
+        match self {
+            Self::Keep => Start::Queued(queue()),
+            Self::Restart => Start::Now,
+            Self::Skip => Start::Cut,
+        }
+    }
+}
+
+/// Stops whatever `wallpaper` was doing, for a transition that starts on it now. A 'crossfade'
+/// lets the animation it was playing keep playing under the new image instead, fading it out
+pub fn stop_for(wallpaper: &Wallpaper, transition: &ArchivedTransition) {
+    let crossfade = matches!(
+        transition.transition_type,
+        ArchivedTransitionType::Crossfade
+    ) && wallpaper.start_crossfade(
+        Duration::try_from_secs_f32(transition.duration).unwrap_or_default(),
+        transition.gamma_correct,
+    );
+    if !crossfade {
+        wallpaper.inc_animation_id();
+    }
+}
+
+/// What a transition goes to
+enum NewImg<'a> {
+    /// An image the client sent
+    Img(&'a ArchivedImg),
+    /// A color or gradient we draw ourselves, see [`render_fill`]
+    Fill(BgImg),
+}
+
+impl NewImg<'_> {
+    fn bg_img(&self) -> BgImg {
+        match self {
+            Self::Img(img) => BgImg::Img(img.path.to_string()),
+            Self::Fill(fill) => fill.clone(),
+        }
+    }
+}
+
+/// A color or gradient as pixels of `dimensions`, in the same order as the images clients send
+/// us, on a buffer drawn with `transform`
+pub fn render_fill(fill: &BgImg, dimensions: (u32, u32), transform: Transform) -> Vec<u8> {
+    match fill {
+        BgImg::Color([r, g, b]) => {
+            [*b, *g, *r].repeat(dimensions.0 as usize * dimensions.1 as usize)
+        }
+        BgImg::Gradient(gradient) => gradient.render(dimensions, transform),
+        BgImg::Img(_) | BgImg::Shader { .. } => unreachable!("images and shaders are not fills"),
+    }
+}
+
+/// What to play on outputs showing their first image: either the transition's startup variant, or
+/// a cut straight to the image
+fn startup_transition(transition: &ArchivedTransition) -> ArchivedTransition {
+    if !transition.first_frame {
+        return cut(transition);
+    }
+    let mut startup = transition.clone();
+    startup.transition_type = transition.startup.transition_type.clone();
+    startup.step = transition.startup.step;
+    startup.angle = transition.startup.angle;
+    startup.pos = transition.startup.pos.clone();
+    startup
+}
+
+/// Whether `pixels` are another scene than what `wallpaper` displays, so that `transition` must
+/// cut to them, see [`utils::scene`]
+fn is_scene_change(wallpaper: &Wallpaper, transition: &ArchivedTransition, pixels: &[u8]) -> bool {
+    let Some(threshold) = transition.scene_change_threshold.as_ref() else {
+        return false;
+    };
+    wallpaper
+        .with_drawn(|drawn| utils::scene::is_scene_change((drawn, 4), (pixels, 3), *threshold))
+        .unwrap_or(false)
+}
+
+/// Shows the new image right away
+fn cut(transition: &ArchivedTransition) -> ArchivedTransition {
+    let mut cut = transition.clone();
+    cut.transition_type = ArchivedTransitionType::Simple;
+    cut.step = u8::MAX;
+    cut
+}
 
 pub struct Animator {
     anim_barrier: ArcAnimBarrier,
+    frame_cache: Arc<Mutex<FrameCache>>,
+    /// The animations still waiting for frames, by their `request`, and where they were in it
+    streams: Vec<((u64, u32), Weak<FrameStream>)>,
+    pub interrupt_policy: InterruptPolicy,
+    pub frame_drop_recovery: FrameDropRecovery,
 }
 
 impl Animator {
     pub fn new() -> Self {
         Self {
             anim_barrier: ArcAnimBarrier::new(),
+            frame_cache: Arc::new(Mutex::new(FrameCache::new(None))),
+            streams: Vec::new(),
+            interrupt_policy: InterruptPolicy::default(),
+            frame_drop_recovery: FrameDropRecovery::default(),
         }
     }
 
+    /// Limits how many bytes the frames of all running animations may take together, or lets them
+    /// take any, with `None`
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.frame_cache.lock().unwrap().set_limit(bytes);
+    }
+
+    /// How many bytes the frames of every running animation take
+    pub fn resident_bytes(&self) -> usize {
+        self.frame_cache.lock().unwrap().total_bytes()
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.frame_cache.lock().unwrap().limit()
+    }
+
     fn spawn_transition_thread<'a, 'b>(
         scope: &'a Scope<'b, '_>,
         transition: &'b ArchivedTransition,
-        img: &'b ArchivedBox<[u8]>,
-        path: &'b ArchivedString,
-        mut wallpapers: Vec<Arc<Wallpaper>>,
+        img: NewImg<'b>,
+        wallpapers: Vec<(Arc<Wallpaper>, Start)>,
+        start_gate: &'b RwLock<()>,
     ) where
         'a: 'b,
     {
@@ -49,51 +251,180 @@ impl Animator {
                 if wallpapers.is_empty() {
                     return;
                 }
-                for w in wallpapers.iter_mut() {
-                    w.set_img_info(BgImg::Img(path.to_string()));
-                }
-                let dimensions = wallpapers[0].get_dimensions();
+                let queued: Vec<_> = wallpapers
+                    .iter()
+                    .filter_map(|(w, start)| match start {
+                        Start::Queued(ticket) => Some((Arc::clone(w), *ticket)),
+                        _ => None,
+                    })
+                    .collect();
+                // we must always take our turn, even if we fail, or the queue would never move
+                let skip_turns = || {
+                    for (wallpaper, ticket) in &queued {
+                        wallpaper.wait_turn(*ticket);
+                        wallpaper.end_turn();
+                    }
+                };
 
-                if img.len() == dimensions.0 as usize * dimensions.1 as usize * 3 {
-                    Transition::new(wallpapers, dimensions, transition.clone()).execute(img);
-                } else {
+                let dimensions = wallpapers[0].0.get_dimensions();
+                let decoded;
+                let pixels: &[u8] = match &img {
+                    NewImg::Img(img) if img.jpeg => {
+                        match utils::jpeg::decode(&img.img, dimensions) {
+                            Ok(rgb) => {
+                                decoded = rgb;
+                                &decoded
+                            }
+                            Err(e) => {
+                                error!("failed to decode image sent as a JPEG: {e}");
+                                skip_turns();
+                                return;
+                            }
+                        }
+                    }
+                    NewImg::Img(img) => &img.img,
+                    NewImg::Fill(fill) => {
+                        decoded = render_fill(fill, dimensions, wallpapers[0].0.transform());
+                        &decoded
+                    }
+                };
+                if pixels.len() != dimensions.0 as usize * dimensions.1 as usize * 3 {
                     error!(
                         "image is of wrong size! Image len: {}, expected size: {}",
-                        img.len(),
+                        pixels.len(),
                         dimensions.0 as usize * dimensions.1 as usize * 3
                     );
+                    skip_turns();
+                    return;
+                }
+
+                for (wallpaper, ticket) in &queued {
+                    wallpaper.wait_turn(*ticket);
+                    stop_for(wallpaper, transition);
+                }
+                // outputs showing their first image, outputs we interrupted (or that crossfade,
+                // which blends the animation they were playing in by itself), and everything else
+                let mut groups = [Vec::new(), Vec::new(), Vec::new()];
+                for (wallpaper, start) in wallpapers {
+                    let group = match start {
+                        _ if wallpaper.is_first_img() => 0,
+                        _ if wallpaper.is_crossfading() => 1,
+                        _ if is_scene_change(&wallpaper, transition, pixels) => 1,
+                        Start::Cut => 1,
+                        _ => 2,
+                    };
+                    wallpaper.set_img_info(img.bg_img());
+                    if let NewImg::Img(img) = &img {
+                        wallpaper
+                            .set_remake(img.remake.deserialize(&mut rkyv::Infallible).unwrap());
+                        wallpaper.set_adjustments(
+                            &img.adjustments.deserialize(&mut rkyv::Infallible).unwrap(),
+                        );
+                        wallpaper
+                            .set_dither(img.dither.deserialize(&mut rkyv::Infallible).unwrap());
+                    }
+                    groups[group].push(wallpaper);
+                }
+                let mut transitions: Vec<_> = groups
+                    .into_iter()
+                    .zip([
+                        startup_transition(transition),
+                        cut(transition),
+                        transition.clone(),
+                    ])
+                    .filter(|(group, _)| !group.is_empty())
+                    .map(|(group, transition)| Transition::new(group, dimensions, transition))
+                    .collect();
+                for (wallpaper, _) in &queued {
+                    wallpaper.end_turn();
                 }
+
+                // wait until every transition of the request is ready to start
+                drop(start_gate.read());
+                let last = transitions.pop();
+                thread::scope(|s| {
+                    for transition in transitions {
+                        if let Err(e) = thread::Builder::new()
+                            .name("transition".to_string())
+                            .stack_size(STACK_SIZE)
+                            .spawn_scoped(s, move || transition.execute(pixels))
+                        {
+                            error!("failed to spawn 'transition' thread: {}", e);
+                        }
+                    }
+                    if let Some(transition) = last {
+                        transition.execute(pixels);
+                    }
+                });
             })
         {
             error!("failed to spawn 'transition' thread: {}", e);
         }
     }
 
-    pub fn transition(&mut self, bytes: Vec<u8>, wallpapers: Vec<Vec<Arc<Wallpaper>>>) -> Answer {
+    pub fn transition(
+        &mut self,
+        bytes: Vec<u8>,
+        wallpapers: Vec<Vec<(Arc<Wallpaper>, Start)>>,
+    ) -> Answer {
         match thread::Builder::new()
             .stack_size(1 << 15)
             .name("transition spawner".to_string())
             .spawn(move || {
-                if let ArchivedRequest::Img((transition, imgs)) = Request::receive(&bytes) {
-                    thread::scope(|s| {
-                        for ((ArchivedImg { img, path }, _), wallpapers) in
-                            imgs.iter().zip(wallpapers)
-                        {
-                            Self::spawn_transition_thread(s, transition, img, path, wallpapers);
-                        }
-                    });
-                }
+                let requests = match Request::receive(&bytes) {
+                    Ok(ArchivedRequest::Img(request)) => std::slice::from_ref(request),
+                    Ok(ArchivedRequest::Batch(requests)) => requests,
+                    Ok(ArchivedRequest::Clear(clear)) => {
+                        // every group of wallpapers gets the same fill, drawn at its own size
+                        let Some(transition) = clear.transition.as_ref() else {
+                            return;
+                        };
+                        let fill = clear.fill();
+                        let imgs =
+                            std::iter::repeat_with(|| (transition, NewImg::Fill(fill.clone())));
+                        return Self::spawn_transitions(imgs, wallpapers);
+                    }
+                    _ => return,
+                };
+                let imgs = requests.iter().flat_map(|(transition, imgs)| {
+                    imgs.iter()
+                        .map(move |(img, _)| (transition, NewImg::Img(img)))
+                });
+                Self::spawn_transitions(imgs, wallpapers);
             }) {
             Ok(_) => Answer::Ok,
-            Err(e) => Answer::Err(e.to_string()),
+            Err(e) => Answer::Err(e.to_string().into()),
         }
     }
 
+    /// Plays a transition to every image, on the wallpapers alongside it
+    fn spawn_transitions<'a>(
+        imgs: impl Iterator<Item = (&'a ArchivedTransition, NewImg<'a>)>,
+        wallpapers: Vec<Vec<(Arc<Wallpaper>, Start)>>,
+    ) {
+        // Transitions only start once we release this lock. This way, all of them start at the
+        // same time, even if some take longer to get ready than others
+        let start_gate = RwLock::new(());
+        thread::scope(|s| {
+            let gate_guard = start_gate.write();
+            for ((transition, img), wallpapers) in imgs.zip(wallpapers) {
+                Self::spawn_transition_thread(s, transition, img, wallpapers, &start_gate);
+            }
+            drop(gate_guard);
+        });
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn spawn_animation_thread<'a, 'b>(
         scope: &'a Scope<'b, '_>,
         animation: &'b ArchivedAnimation,
-        mut wallpapers: Vec<Arc<Wallpaper>>,
+        options: &'b ArchivedPlayback,
+        wallpapers: Vec<(Arc<Wallpaper>, usize)>,
         barrier: ArcAnimBarrier,
+        frame_cache: Arc<Mutex<FrameCache>>,
+        spilled: bool,
+        stream: Option<Arc<FrameStream>>,
+        recovery: FrameDropRecovery,
     ) where
         'a: 'b,
     {
@@ -102,15 +433,47 @@ impl Animator {
             .stack_size(STACK_SIZE) //the default of 2MB is way too overkill for this
             .spawn_scoped(scope, move || {
                 /* We only need to animate if we have > 1 frame */
-                if animation.animation.len() == 1 {
+                if animation.animation.len() == 1 && stream.is_none() {
+                    for (wallpaper, _) in &wallpapers {
+                        wallpaper.claim_animation(options.request);
+                    }
                     return;
                 }
                 log::debug!("Starting animation");
 
-                let mut tokens: Vec<AnimationToken> = wallpapers
+                // our image may still be waiting for its transition to start
+                for (wallpaper, queue_end) in &wallpapers {
+                    wallpaper.wait_queue(*queue_end);
+                }
+                // something may have replaced our image in the meantime
+                let (mut wallpapers, mut tokens): (Vec<_>, Vec<AnimationToken>) = wallpapers
+                    .into_iter()
+                    .filter_map(|(w, _)| {
+                        let token = w.claim_animation(options.request)?;
+                        Some((w, token))
+                    })
+                    .unzip();
+                let playing: Vec<_> = wallpapers
                     .iter()
-                    .map(|w| w.create_animation_token())
+                    .zip(&tokens)
+                    .map(|(w, token)| w.still_playing(token))
                     .collect();
+                // spilled frames don't count, see `spill`
+                let bytes = if spilled {
+                    0
+                } else {
+                    stream::packed_len(&animation.animation)
+                        + stream::packed_len(&animation.reverse)
+                };
+                let outputs = wallpapers.iter().map(|w| w.output_id()).collect();
+                let playing = Box::new(move || playing.iter().any(|playing| playing()));
+                let mut frames = match FrameCache::insert(&frame_cache, bytes, outputs, playing) {
+                    Ok(cached) => Frames::new(animation, stream, cached),
+                    Err(e) => {
+                        warn!("not playing the animation of {}: {e}", animation.path);
+                        return;
+                    }
+                };
 
                 for (wallpaper, token) in wallpapers.iter().zip(&tokens) {
                     loop {
@@ -125,43 +488,293 @@ impl Animator {
                     }
                 }
 
-                let mut now = std::time::Instant::now();
+                for (wallpaper, token) in wallpapers.iter().zip(&tokens) {
+                    if wallpaper.has_animation_id(token) {
+                        wallpaper.set_animation_frame(0);
+                        wallpaper.set_playback_speed(options.speed);
+                        wallpaper.animation_started(token, options.from_cache, frames.bytes());
+                    }
+                }
+                let mut speed = options.speed;
+
+                // frames with an alpha channel must be drawn to buffers that have one too
+                if animation.animation[0].0.has_alpha() {
+                    for (wallpaper, token) in wallpapers.iter().zip(&tokens) {
+                        if wallpaper.has_animation_id(token) {
+                            wallpaper.set_alpha(true);
+                        }
+                    }
+                }
 
-                for (frame, duration) in animation.animation.iter().cycle() {
+                // we loop back to the first frame once we know which one is the last
+                let mut playback = Playback::new(
+                    frames.len().unwrap_or(usize::MAX),
+                    !animation.reverse.is_empty(),
+                    options.loops.as_ref().copied(),
+                );
+                // whether any of our outputs still plays us, and so should wait for frames that
+                // are still on their way
+                let playing = |wallpapers: &[Arc<Wallpaper>], tokens: &[AnimationToken]| {
+                    wallpapers
+                        .iter()
+                        .zip(tokens)
+                        .any(|(w, token)| w.has_animation_id(token))
+                };
+                // frames only hold their differences to the previous one, so to start further in,
+                // we must still go through (but not show) the frames before
+                let start_frame = options.start_frame as usize % playback.frames;
+                if start_frame != 0 {
+                    while playback.frame != start_frame {
+                        if !frames.wait_next(&mut playback, || playing(&wallpapers, &tokens)) {
+                            return;
+                        }
+                        let (frame, _) = frames.get(playback.advance());
+                        for (wallpaper, token) in wallpapers.iter().zip(&tokens) {
+                            if !wallpaper.has_animation_id(token) {
+                                continue;
+                            }
+                            let (_, buffer) =
+                                wallpaper.canvas_change(|canvas| frame.unpack(canvas));
+                            if playback.frame == start_frame && !wallpaper.is_hidden() {
+                                wallpaper.draw(&buffer);
+                            } else {
+                                wallpaper.mark_undrawn();
+                            }
+                            wallpaper.set_animation_frame(playback.frame);
+                        }
+                    }
+                    crate::wake_poll();
+                }
+                // with `--battery-policy reduce-fps`, when we last sent a frame to the compositor,
+                // and whether the canvas has frames we didn't send since, for any reason
+                let (mut last_drawn, mut undrawn) = (None::<std::time::Instant>, false);
+                // the wallpapers that moved on to another image with a 'crossfade', which we keep
+                // playing on until it is over
+                let mut fading = Fading(Vec::new());
+                // the loops we told subscribers about. We may get back to the first frame while
+                // skipping frames, or going to it for `--battery-policy first-frame`
+                let mut looped = playback.looped;
+                loop {
+                    let ours = || {
+                        wallpapers
+                            .iter()
+                            .zip(&tokens)
+                            .filter(|(w, token)| w.has_animation_id(token))
+                    };
+                    // the outputs share the frames, which only hold their differences to the
+                    // previous one, so pausing (or stepping) one of them pauses them all
+                    // but nobody sees the outputs that are off or covered, so only if all of them,
+                    // and the outputs mirroring them, are hidden do we stop too
+                    let mut stepping = false;
+                    while ours().any(|(w, _)| w.is_paused())
+                        || (ours().next().is_some() && ours().all(|(w, _)| w.is_unseen()))
+                        || matches!(
+                            battery::low_power_policy(),
+                            Some(BatteryPolicy::Pause | BatteryPolicy::FirstFrame)
+                        )
+                    {
+                        if battery::low_power_policy() == Some(BatteryPolicy::FirstFrame)
+                            && playback.frame != 0
+                        {
+                            // we can only get there by going through the frames in between
+                            while playback.frame != 0 {
+                                if !frames
+                                    .wait_next(&mut playback, || playing(&wallpapers, &tokens))
+                                {
+                                    return;
+                                }
+                                let (frame, _) = frames.get(playback.advance());
+                                for (wallpaper, _) in ours() {
+                                    let (_, buffer) =
+                                        wallpaper.canvas_change(|canvas| frame.unpack(canvas));
+                                    if playback.frame == 0 && !wallpaper.is_hidden() {
+                                        wallpaper.draw(&buffer);
+                                    } else {
+                                        wallpaper.mark_undrawn();
+                                    }
+                                    wallpaper.set_animation_frame(playback.frame);
+                                }
+                            }
+                            crate::wake_poll();
+                            if playback.is_finished() {
+                                return;
+                            }
+                        }
+                        // every output that `swww step` was called on takes one step
+                        stepping = ours().fold(false, |stepped, (w, _)| w.take_step() || stepped);
+                        if stepping {
+                            break;
+                        }
+                        thread::sleep(PAUSE_POLL_INTERVAL);
+                    }
+                    // we only know whether the next step is the last once its frame arrived, and
+                    // waiting for frames that are still on their way isn't falling behind
+                    if !frames.wait_next(&mut playback, || playing(&wallpapers, &tokens)) {
+                        log::debug!("Stopping animation, its next frames never arrived");
+                        return;
+                    }
+                    // the last frame already got its delay, at the end of the previous iteration
+                    if playback.is_last_step() {
+                        match options.at_end {
+                            ArchivedAtEnd::HoldLast => {
+                                // the last frame must be the one on the screen
+                                if undrawn {
+                                    for (wallpaper, _) in ours() {
+                                        let (_, buffer) = wallpaper.canvas_change(|_| ());
+                                        if !wallpaper.is_hidden() {
+                                            wallpaper.draw(&buffer);
+                                        }
+                                    }
+                                    crate::wake_poll();
+                                }
+                                return;
+                            }
+                            ArchivedAtEnd::FirstFrame => (),
+                            ArchivedAtEnd::Clear(color) => {
+                                for (wallpaper, token) in wallpapers.iter().zip(&tokens) {
+                                    if wallpaper.has_animation_id(token) {
+                                        wallpaper.set_img_info(BgImg::Color(color));
+                                        let buffer = wallpaper.clear(color);
+                                        wallpaper.draw(&buffer);
+                                    }
+                                }
+                                crate::wake_poll();
+                                return;
+                            }
+                        }
+                    }
+                    let now = std::time::Instant::now();
+                    let (frame, duration) = frames.get(playback.advance());
+                    // `swww playback-speed` may have changed the speed of any of our outputs, and
+                    // since they share the frames, that changes it for all of them
+                    if let Some(changed) = ours()
+                        .map(|(w, _)| w.playback_speed())
+                        .find(|s| *s != speed)
+                    {
+                        speed = changed;
+                        for (wallpaper, _) in ours() {
+                            wallpaper.set_playback_speed(speed);
+                        }
+                    }
                     let duration: Duration = duration.deserialize(&mut rkyv::Infallible).unwrap();
+                    let duration = duration.div_f32(speed);
                     barrier.wait(duration.div_f32(2.0));
 
+                    // a frame that stays on the screen for long enough is always sent
+                    let throttled = match battery::low_power_policy() {
+                        Some(BatteryPolicy::ReduceFps(fps)) => {
+                            let interval = Duration::from_secs(1) / fps;
+                            duration < interval
+                                && last_drawn.is_some_and(|drawn| drawn.elapsed() < interval)
+                        }
+                        _ => false,
+                    };
+                    if !throttled {
+                        last_drawn = Some(now);
+                    }
+                    undrawn = throttled;
+
+                    // the frame's delay is only a lower bound: we then wait for the compositor to
+                    // ask for it, for at most another delay, so that we draw in step with the
+                    // output, and not at all while it can't show us
+                    let frame_deadline = std::time::Instant::now() + duration;
+                    fading.unpack(frame, true);
+                    let wrapped = playback.looped != looped;
+                    looped = playback.looped;
                     let mut i = 0;
                     while i < wallpapers.len() {
                         let token = &tokens[i];
                         if !wallpapers[i].has_animation_id(token) {
-                            wallpapers.swap_remove(i);
-                            tokens.swap_remove(i);
+                            let moved = (wallpapers.swap_remove(i), tokens.swap_remove(i));
+                            fading.add(moved, Some(frame));
                             continue;
                         }
 
-                        let (success, buffer) =
-                            wallpapers[i].canvas_change(|canvas| frame.unpack(canvas));
+                        // we check our id again while holding the canvas, since a crossfade
+                        // copies it, and we mustn't unpack this frame twice
+                        let width = wallpapers[i].buffer_width();
+                        let (damage, buffer) = wallpapers[i].canvas_change(|canvas| {
+                            wallpapers[i]
+                                .has_animation_id(token)
+                                .then(|| frame.unpack_with_damage(canvas, width))
+                        });
+                        let Some(damage) = damage else {
+                            let moved = (wallpapers.swap_remove(i), tokens.swap_remove(i));
+                            fading.add(moved, Some(frame));
+                            continue;
+                        };
 
-                        if !success {
+                        // our size may have changed while we unpacked, which stops us, and leaves
+                        // a frame made for the old size in the new buffer, that we mustn't draw.
+                        // A crossfade copied the canvas after we unpacked, so it has this frame
+                        if !wallpapers[i].has_animation_id(token) {
+                            let moved = (wallpapers.swap_remove(i), tokens.swap_remove(i));
+                            fading.add(moved, None);
+                            continue;
+                        }
+                        let Some(damage) = damage else {
                             error!("failed to unpack frame, canvas is smaller than expected");
                             wallpapers.swap_remove(i);
                             tokens.swap_remove(i);
                             continue;
-                        }
+                        };
 
-                        wallpapers[i].draw(&buffer);
+                        // the canvas must still follow the frames, but the compositor needn't
+                        if !wallpapers[i].is_hidden()
+                            && !throttled
+                            && !wallpapers[i].over_max_fps(duration)
+                            && wallpapers[i].wait_frame(frame_deadline)
+                        {
+                            wallpapers[i].draw_damaged(&buffer, &damage);
+                        } else {
+                            wallpapers[i].mark_undrawn();
+                            undrawn = true;
+                        }
+                        wallpapers[i].set_animation_frame(playback.frame);
+                        if wrapped {
+                            wallpapers[i].animation_looped();
+                        }
                         i += 1;
                     }
 
-                    if wallpapers.is_empty() {
+                    crate::wake_poll();
+                    if (wallpapers.is_empty() && fading.0.is_empty()) || playback.is_finished() {
                         return;
                     }
 
-                    let timeout = duration.saturating_sub(now.elapsed());
-                    spin_sleep::sleep(timeout);
-                    crate::wake_poll();
-                    now = std::time::Instant::now();
+                    let elapsed = now.elapsed();
+                    if elapsed > duration {
+                        crate::metrics::frame_dropped();
+                    }
+                    // Every frame only holds its difference to the previous one, so we must still
+                    // unpack the frames we skip, we just don't show them. Frames have delays of
+                    // their own, so we skip those whose whole delay already went by
+                    let mut behind = elapsed.saturating_sub(duration);
+                    loop {
+                        // the end of the last loop must still be shown, and `swww step` must
+                        // advance exactly as many frames as it asked for
+                        if playback.is_last_step() || stepping {
+                            break;
+                        }
+                        // nor do we wait for the frames we skip
+                        let Some(delay) = frames.next_delay(&playback) else {
+                            break;
+                        };
+                        if !recovery.skips(&mut behind, delay.div_f32(speed)) {
+                            break;
+                        }
+                        if !frames.wait_next(&mut playback, || false) {
+                            break;
+                        }
+                        let (frame, _) = frames.get(playback.advance());
+                        for wallpaper in &wallpapers {
+                            let _ = wallpaper.canvas_change(|canvas| frame.unpack(canvas));
+                            wallpaper.mark_undrawn();
+                            wallpaper.frame_skipped();
+                        }
+                        fading.unpack(frame, false);
+                    }
+                    spin_sleep::sleep(duration.saturating_sub(elapsed));
                 }
             })
         {
@@ -171,21 +784,573 @@ impl Animator {
 
     pub fn animate(&mut self, bytes: Vec<u8>, wallpapers: Vec<Vec<Arc<Wallpaper>>>) -> Answer {
         let barrier = self.anim_barrier.clone();
+        let frame_cache = Arc::clone(&self.frame_cache);
+        let recovery = self.frame_drop_recovery;
+        if let Ok(ArchivedRequest::Animation(animations)) = Request::receive(&bytes) {
+            for (animation, _, _) in animations.iter() {
+                let corrupt = stream::first_corrupt(&animation.animation)
+                    .or_else(|| stream::first_corrupt(&animation.reverse));
+                if let Some(i) = corrupt {
+                    return Answer::Err(Error::new(
+                        ErrorKind::ProtocolMismatch,
+                        format!("frame {i} of {} arrived corrupt", animation.path),
+                    ));
+                }
+            }
+        }
+        // we only play animations on the wallpapers that still expect them
+        let requests: Vec<u64> = match Request::receive(&bytes) {
+            Ok(ArchivedRequest::Animation(animations)) => animations
+                .iter()
+                .map(|(_, playback, _)| playback.request)
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.streams.retain(|(_, stream)| stream.strong_count() > 0);
+        let streams: Vec<Option<Arc<FrameStream>>> = match Request::receive(&bytes) {
+            Ok(ArchivedRequest::Animation(animations)) => animations
+                .iter()
+                .enumerate()
+                .map(|(i, (_, playback, _))| {
+                    let stream = playback
+                        .more_frames
+                        .then(|| Arc::new(FrameStream::new(playback.stream)))?;
+                    let key = (playback.request, i as u32);
+                    self.streams.push((key, Arc::downgrade(&stream)));
+                    Some(stream)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let wallpapers: Vec<Vec<(Arc<Wallpaper>, usize)>> = wallpapers
+            .into_iter()
+            .zip(requests)
+            .map(|(wallpapers, request)| {
+                wallpapers
+                    .into_iter()
+                    .filter_map(|w| w.animation_arrived(request).map(|end| (w, end)))
+                    .collect()
+            })
+            .collect();
         match thread::Builder::new()
             .stack_size(1 << 15)
             .name("animation spawner".to_string())
             .spawn(move || {
+                let bytes = fit_in_memory(bytes, &wallpapers, &frame_cache);
+                let spilled = bytes.is_spilled();
                 thread::scope(|s| {
-                    if let ArchivedRequest::Animation(animations) = Request::receive(&bytes) {
-                        for ((animation, _), wallpapers) in animations.iter().zip(wallpapers) {
+                    if let Ok(ArchivedRequest::Animation(animations)) = Request::receive(&bytes) {
+                        for (((animation, playback, _), wallpapers), stream) in
+                            animations.iter().zip(wallpapers).zip(streams)
+                        {
                             let barrier = barrier.clone();
-                            Self::spawn_animation_thread(s, animation, wallpapers, barrier);
+                            let frame_cache = Arc::clone(&frame_cache);
+                            Self::spawn_animation_thread(
+                                s,
+                                animation,
+                                playback,
+                                wallpapers,
+                                barrier,
+                                frame_cache,
+                                spilled,
+                                stream,
+                                recovery,
+                            );
                         }
                     }
                 });
             }) {
             Ok(_) => Answer::Ok,
-            Err(e) => Answer::Err(e.to_string()),
+            Err(e) => Answer::Err(e.to_string().into()),
+        }
+    }
+}
+
+/// Spills `bytes`, an `ArchivedRequest::Animation`, to the disk if its frames don't fit under the
+/// memory limit once they replace those playing on `wallpapers`, see `FrameCache::fits_replacing`
+fn fit_in_memory(
+    bytes: Vec<u8>,
+    wallpapers: &[Vec<(Arc<Wallpaper>, usize)>],
+    frame_cache: &Mutex<FrameCache>,
+) -> Bytes {
+    let needed: usize = match Request::receive(&bytes) {
+        Ok(ArchivedRequest::Animation(animations)) => animations
+            .iter()
+            .map(|(animation, _, _)| {
+                stream::packed_len(&animation.animation) + stream::packed_len(&animation.reverse)
+            })
+            .sum(),
+        _ => 0,
+    };
+    let outputs: Vec<u32> = wallpapers
+        .iter()
+        .flatten()
+        .map(|(w, _)| w.output_id())
+        .collect();
+    if frame_cache.lock().unwrap().fits_replacing(&outputs, needed) {
+        return Bytes::InMemory(bytes);
+    }
+    match spill::spill(bytes) {
+        Ok(spilled) => {
+            info!("spilling {needed} bytes of animation frames over the memory limit to the disk");
+            spilled
+        }
+        Err((bytes, e)) => {
+            warn!("animation frames are over the memory limit, and {e}");
+            Bytes::InMemory(bytes)
+        }
+    }
+}
+
+impl Animator {
+    /// Hands the frames in `bytes`, a `Request::AnimationFrames`, to the animation waiting for
+    /// them, if something still plays it
+    /// `None` when we only answer `client` once we start playing the frames, see
+    /// `Playback::stream`
+    pub fn add_frames(&mut self, bytes: Vec<u8>, client: &UnixStream) -> Option<Answer> {
+        let (key, last) = match Request::receive(&bytes) {
+            Ok(ArchivedRequest::AnimationFrames(frames)) => {
+                if let Some(i) = stream::first_corrupt(&frames.frames) {
+                    return Some(Answer::Err(Error::new(
+                        ErrorKind::ProtocolMismatch,
+                        format!("frame {i} of these frames arrived corrupt"),
+                    )));
+                }
+                ((frames.request, frames.animation), frames.last)
+            }
+            _ => {
+                return Some(Answer::Err(Error::new(
+                    ErrorKind::ProtocolMismatch,
+                    "expected animation frames",
+                )))
+            }
+        };
+        let Some(i) = self.streams.iter().position(|(k, _)| *k == key) else {
+            return Some(Answer::Err(
+                "no animation is waiting for these frames"
+                    .to_string()
+                    .into(),
+            ));
+        };
+        let Some(stream) = self.streams[i].1.upgrade() else {
+            self.streams.swap_remove(i);
+            return Some(Answer::Err(
+                "nothing plays this animation anymore".to_string().into(),
+            ));
+        };
+        // streamed animations take frames for as long as they play
+        if !stream.is_streamed() {
+            if last {
+                self.streams.swap_remove(i);
+            }
+            stream.push(bytes, None);
+            return Some(Answer::Ok);
+        }
+        match client.try_clone() {
+            Ok(client) => {
+                stream.push(bytes, Some(client));
+                None
+            }
+            Err(e) => {
+                warn!("can't wait to answer the client until we play its frames: {e}");
+                stream.push(bytes, None);
+                Some(Answer::Ok)
+            }
+        }
+    }
+}
+
+/// The wallpapers an animation keeps playing on while a 'crossfade' fades it out, see
+/// [`Wallpaper::start_crossfade`]. Once we drop them, they stop blending us in
+struct Fading(Vec<(Arc<Wallpaper>, AnimationToken)>);
+
+impl Fading {
+    /// Keeps playing on a wallpaper our animation no longer holds, if it is crossfading from us.
+    /// `frame` is the one we are showing, unless the crossfade's copy of our canvas already has it
+    fn add(
+        &mut self,
+        (wallpaper, token): (Arc<Wallpaper>, AnimationToken),
+        frame: Option<&ArchivedBitPack>,
+    ) {
+        let fading = match frame {
+            Some(frame) => wallpaper.fade_out(&token, |canvas| {
+                let _ = frame.unpack(canvas);
+            }),
+            None => wallpaper.fade_out(&token, |_| ()),
+        };
+        if fading {
+            self.0.push((wallpaper, token));
+        }
+    }
+
+    /// Unpacks the next `frame` into the crossfades, drawing them if `draw`
+    fn unpack(&mut self, frame: &ArchivedBitPack, draw: bool) {
+        self.0.retain(|(wallpaper, token)| {
+            let fading = wallpaper.fade_out(token, |canvas| {
+                let _ = frame.unpack(canvas);
+            });
+            // the last time, it draws the new image alone
+            if !fading {
+                wallpaper.end_crossfade(token);
+            }
+            if (draw || !fading) && !wallpaper.is_hidden() {
+                let (_, buffer) = wallpaper.canvas_change(|_| ());
+                wallpaper.draw(&buffer);
+            }
+            fading
+        });
+    }
+}
+
+impl Drop for Fading {
+    fn drop(&mut self) {
+        for (wallpaper, token) in self.0.drain(..) {
+            wallpaper.end_crossfade(&token);
+            if !wallpaper.is_hidden() {
+                let (_, buffer) = wallpaper.canvas_change(|_| ());
+                wallpaper.draw(&buffer);
+            }
+        }
+        crate::wake_poll();
+    }
+}
+
+/// Which frame to unpack next, see [`utils::ipc::Animation`]
+#[derive(Debug, PartialEq)]
+enum Step {
+    Forward(usize),
+    Backward(usize),
+}
+
+/// Where an animation is, and which way it is going
+#[derive(Clone)]
+struct Playback {
+    /// The frame currently on the screen
+    frame: usize,
+    frames: usize,
+    /// 1 when playing forward, -1 when playing backward. Only boomerang animations go backward
+    direction: i8,
+    boomerang: bool,
+    /// How many more times we will get back to the first frame, or `None`, if we loop forever
+    loops_left: Option<u32>,
+    /// How many times we got back to the first frame so far
+    looped: usize,
+}
+
+impl Playback {
+    fn new(frames: usize, boomerang: bool, loops: Option<u32>) -> Self {
+        Self {
+            frame: 0,
+            frames,
+            direction: 1,
+            boomerang,
+            loops_left: loops,
+            looped: 0,
+        }
+    }
+
+    /// Whether the next step ends the last loop
+    fn is_last_step(&self) -> bool {
+        if self.loops_left != Some(1) {
+            return false;
+        }
+        let mut next = self.clone();
+        next.advance();
+        next.is_finished()
+    }
+
+    fn is_finished(&self) -> bool {
+        self.loops_left == Some(0)
+    }
+
+    fn advance(&mut self) -> Step {
+        if self.boomerang {
+            if self.direction == 1 && self.frame == self.frames - 1 {
+                self.direction = -1;
+            } else if self.direction == -1 && self.frame == 0 {
+                self.direction = 1;
+            }
+        }
+
+        let step = if self.direction == 1 {
+            let step = Step::Forward(self.frame);
+            self.frame = (self.frame + 1) % self.frames;
+            step
+        } else {
+            self.frame -= 1;
+            Step::Backward(self.frame)
+        };
+        if self.frame == 0 {
+            self.loops_left = self.loops_left.map(|n| n.saturating_sub(1));
+            self.looped += 1;
+        }
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames_shown(playback: &mut Playback, n: usize) -> Vec<usize> {
+        (0..n)
+            .map(|_| {
+                playback.advance();
+                playback.frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frame_drop_recovery_should_skip_the_frames_whose_delay_went_by() {
+        let ms = Duration::from_millis;
+        // the first frame took 81ms longer than it should have
+        let skipped = |recovery: FrameDropRecovery, delays: &[u64]| {
+            let mut behind = ms(81);
+            delays
+                .iter()
+                .take_while(|delay| recovery.skips(&mut behind, ms(**delay)))
+                .count()
+        };
+        assert_eq!(skipped(FrameDropRecovery::Warp, &[40, 40, 40]), 2);
+        assert_eq!(skipped(FrameDropRecovery::Hold, &[40, 40, 40]), 0);
+        // frames of different lengths
+        assert_eq!(skipped(FrameDropRecovery::Warp, &[10, 10, 100, 10]), 2);
+        assert_eq!(skipped(FrameDropRecovery::Warp, &[100, 10]), 0);
+        assert_eq!(skipped(FrameDropRecovery::Warp, &[0, 10]), 0);
+    }
+
+    #[test]
+    fn should_loop_from_the_last_frame_to_the_first() {
+        let mut playback = Playback::new(5, false, None);
+        assert_eq!(frames_shown(&mut playback, 7), [1, 2, 3, 4, 0, 1, 2]);
+        // only getting back to the first frame is a loop, not starting on it
+        assert_eq!(playback.looped, 1);
+    }
+
+    #[test]
+    fn should_know_when_the_last_loop_ends() {
+        let mut playback = Playback::new(3, false, Some(2));
+        let mut last_steps = Vec::new();
+        while !playback.is_finished() {
+            last_steps.push(playback.is_last_step());
+            playback.advance();
+        }
+        assert_eq!(last_steps, [false, false, false, false, false, true]);
+
+        let mut boomerang = Playback::new(3, true, Some(1));
+        assert_eq!(frames_shown(&mut boomerang, 3), [1, 2, 1]);
+        assert!(boomerang.is_last_step());
+        boomerang.advance();
+        assert!(boomerang.is_finished());
+
+        assert!(!Playback::new(2, false, None).is_last_step());
+    }
+
+    #[test]
+    fn boomerang_should_play_forward_then_backward() {
+        let mut playback = Playback::new(5, true, None);
+        let mut shown = vec![playback.frame];
+        shown.extend(frames_shown(&mut playback, 9));
+        assert_eq!(shown, [0, 1, 2, 3, 4, 3, 2, 1, 0, 1]);
+        assert_eq!(playback.looped, 1);
+    }
+
+    #[test]
+    fn boomerang_should_use_the_reverse_frames_backward() {
+        let mut playback = Playback::new(3, true, None);
+        let steps: Vec<_> = (0..5).map(|_| playback.advance()).collect();
+        assert_eq!(
+            steps,
+            [
+                Step::Forward(0),
+                Step::Forward(1),
+                Step::Backward(1),
+                Step::Backward(0),
+                Step::Forward(0),
+            ]
+        );
+    }
+
+    fn archived_transition(
+        transition_type: utils::ipc::TransitionType,
+        first_frame: bool,
+    ) -> Vec<u8> {
+        use utils::ipc::{Coord, Direction, Position, StartupTransition, TransitionType};
+        let transition = utils::ipc::Transition {
+            transition_type,
+            duration: 1.0,
+            step: 90,
+            fps: 30,
+            fps_adaptive: false,
+            angle: 45.0,
+            pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+            bezier: (0.54, 0.0, 0.34, 0.99),
+            wave: (20.0, 20.0),
+            invert_y: false,
+            direction: Direction::Left,
+            bg_color: [0, 0, 0],
+            reversed: false,
+            gamma_correct: false,
+            loops: 1,
+            first_frame,
+            startup: StartupTransition {
+                transition_type: TransitionType::Fade,
+                step: 30,
+                angle: 90.0,
+                pos: Position::new(Coord::Pixel(10.0), Coord::Pixel(20.0)),
+            },
+            queue: false,
+            scene_change_threshold: None,
+        };
+        rkyv::to_bytes::<_, 256>(&transition).unwrap().into_vec()
+    }
+
+    #[test]
+    fn animations_over_the_memory_limit_should_be_spilled_to_the_disk() {
+        use utils::{
+            comp_decomp::{BitPack, CompressionBackend},
+            ipc::{Animation, AtEnd},
+        };
+        spill::tests::use_temp_cache();
+        let frame = BitPack::pack(&[0; 12], &[1; 12], CompressionBackend::None, 1).unwrap();
+        let animation = Animation {
+            animation: Box::new([(frame, Duration::from_millis(10))]),
+            reverse: Box::new([]),
+            path: String::new(),
+            dimensions: (2, 2),
+            cache_key: None,
+            compression: CompressionBackend::None,
+        };
+        let playback = utils::ipc::Playback {
+            speed: 1.0,
+            loops: None,
+            at_end: AtEnd::HoldLast,
+            start_frame: 0,
+            request: 1,
+            more_frames: false,
+            stream: false,
+            from_cache: false,
+        };
+        let request = Request::Animation(Box::new([(animation, playback, Box::new([]))]));
+        let bytes = rkyv::to_bytes::<_, 256>(&request).unwrap().into_vec();
+
+        let unlimited = Mutex::new(FrameCache::new(None));
+        assert!(!fit_in_memory(bytes.clone(), &[], &unlimited).is_spilled());
+        let full = Mutex::new(FrameCache::new(Some(1)));
+        let spilled = fit_in_memory(bytes, &[], &full);
+        assert!(spilled.is_spilled());
+        assert!(matches!(
+            Request::receive(&spilled),
+            Ok(ArchivedRequest::Animation(_))
+        ));
+    }
+
+    #[test]
+    fn first_imgs_should_use_the_startup_transition() {
+        let bytes = archived_transition(utils::ipc::TransitionType::Wipe, true);
+        let transition = unsafe { rkyv::archived_root::<utils::ipc::Transition>(&bytes) };
+        let startup = startup_transition(transition);
+        assert!(matches!(
+            startup.transition_type,
+            ArchivedTransitionType::Fade
+        ));
+        assert_eq!((startup.step, startup.angle), (30, 90.0));
+        assert_eq!(startup.duration, 1.0);
+
+        let bytes = archived_transition(utils::ipc::TransitionType::Wipe, false);
+        let transition = unsafe { rkyv::archived_root::<utils::ipc::Transition>(&bytes) };
+        let startup = startup_transition(transition);
+        assert!(matches!(
+            startup.transition_type,
+            ArchivedTransitionType::Simple
+        ));
+        assert_eq!(startup.step, u8::MAX);
+    }
+
+    #[test]
+    fn interrupt_policies_should_decide_how_transitions_start() {
+        assert_eq!(InterruptPolicy::Keep.interrupt(|| 7), Start::Queued(7));
+        assert_eq!(
+            InterruptPolicy::Restart.interrupt(|| unreachable!()),
+            Start::Now
+        );
+        assert_eq!(
+            InterruptPolicy::Skip.interrupt(|| unreachable!()),
+            Start::Cut
+        );
+    }
+
+    const DIM: (u32, u32) = (4, 4);
+
+    fn img(color: u8) -> Vec<u8> {
+        vec![color; (DIM.0 * DIM.1 * 3) as usize]
+    }
+
+    /// Drops the padding byte of every pixel of a headless frame
+    fn to_img(frame: &[u8]) -> Vec<u8> {
+        frame
+            .chunks_exact(4)
+            .flat_map(|pix| &pix[0..3])
+            .copied()
+            .collect()
+    }
+
+    /// Renders a fade from `old` to `new`, started as `start` says
+    fn fade(old: &[u8], new: &[u8], start: Start) -> Vec<Vec<u8>> {
+        let bytes = archived_transition(utils::ipc::TransitionType::Fade, true);
+        let transition = unsafe { rkyv::archived_root::<utils::ipc::Transition>(&bytes) };
+        let transition = match start {
+            Start::Cut => cut(transition),
+            Start::Now | Start::Queued(_) => transition.clone(),
+        };
+        let frames = Transition::headless(old, DIM, transition).render(new);
+        frames.iter().map(|frame| to_img(frame)).collect()
+    }
+
+    /// Interrupts a fade from 0 to 100 halfway through with a new image of 200, returning the
+    /// frames shown from the interruption on
+    fn interrupted_fade(policy: InterruptPolicy) -> Vec<Vec<u8>> {
+        let first = fade(&img(0), &img(100), Start::Now);
+        let (shown, remaining) = first.split_at(first.len() / 2);
+        let interrupted = shown.last().unwrap().clone();
+        assert!(interrupted.iter().all(|&c| c > 0 && c < 100));
+        match policy.interrupt(|| 0) {
+            start @ Start::Queued(_) => {
+                let mut frames = remaining.to_vec();
+                frames.extend(fade(remaining.last().unwrap(), &img(200), start));
+                frames
+            }
+            start => fade(&interrupted, &img(200), start),
         }
     }
+
+    #[test]
+    fn keep_should_finish_the_transition_before_the_new_one() {
+        let frames = interrupted_fade(InterruptPolicy::Keep);
+        let finished = frames.iter().position(|f| *f == img(100)).unwrap();
+        assert!(finished > 0, "the old transition must go on");
+        assert!(frames[..finished]
+            .iter()
+            .all(|f| f.iter().all(|&c| c < 100)));
+        assert!(frames[finished..]
+            .iter()
+            .all(|f| f.iter().all(|&c| c >= 100)));
+        assert_eq!(*frames.last().unwrap(), img(200));
+    }
+
+    #[test]
+    fn restart_should_transition_from_the_interrupted_frame() {
+        let frames = interrupted_fade(InterruptPolicy::Restart);
+        assert!(frames.len() > 1);
+        // a fade from the old target would never go below it
+        assert!(frames[0].iter().all(|&c| c > 0 && c < 100));
+        assert_eq!(*frames.last().unwrap(), img(200));
+    }
+
+    #[test]
+    fn skip_should_cut_to_the_new_image() {
+        let frames = interrupted_fade(InterruptPolicy::Skip);
+        assert_eq!(frames, [img(200)]);
+    }
 }
@@ -0,0 +1,120 @@
+//! Frames that don't fit under the memory limit, see `frame_cache`.
+//!
+//! Rather than refusing them, we write them to a file in the cache directory, and map it in memory.
+//! The kernel then reads them back as we play them, and may drop them from memory again whenever
+//! it needs the room, which it can't do with the frames we hold ourselves. Spilled frames thus
+//! don't count towards the limit.
+
+use std::{ffi::c_void, io::Write, num::NonZeroUsize, ops::Deref, ptr::NonNull};
+
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+
+/// The bytes of a request, in memory or spilled to the disk
+pub enum Bytes {
+    InMemory(Vec<u8>),
+    Spilled(Spilled),
+}
+
+impl Bytes {
+    pub fn is_spilled(&self) -> bool {
+        matches!(self, Self::Spilled(_))
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::InMemory(bytes) => bytes,
+            Self::Spilled(spilled) => spilled,
+        }
+    }
+}
+
+/// Bytes we wrote to the disk, mapped in memory. Unmapped once dropped, which deletes the file
+pub struct Spilled {
+    ptr: NonNull<c_void>,
+    len: NonZeroUsize,
+}
+
+// SAFETY: the mapping is private and read only, so nothing changes the bytes behind our back
+unsafe impl Send for Spilled {}
+unsafe impl Sync for Spilled {}
+
+impl Deref for Spilled {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: we mapped `len` readable bytes at `ptr`, which stay mapped until we are dropped
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast(), self.len.get()) }
+    }
+}
+
+impl Drop for Spilled {
+    fn drop(&mut self) {
+        // SAFETY: nothing borrows the bytes anymore, since they borrow us
+        if let Err(e) = unsafe { munmap(self.ptr.as_ptr(), self.len.get()) } {
+            log::error!("failed to unmap spilled frames: {e}");
+        }
+    }
+}
+
+/// Writes `bytes` to the disk, and maps them back in memory. They stay where they are if we fail
+pub fn spill(bytes: Vec<u8>) -> Result<Bytes, (Vec<u8>, String)> {
+    match write_and_map(&bytes) {
+        Ok(spilled) => Ok(Bytes::Spilled(spilled)),
+        Err(e) => Err((bytes, e)),
+    }
+}
+
+fn write_and_map(bytes: &[u8]) -> Result<Spilled, String> {
+    let len = NonZeroUsize::new(bytes.len()).ok_or("there is nothing to spill")?;
+    let mut file = utils::cache::spill_file()?;
+    file.write_all(bytes)
+        .map_err(|e| format!("failed to write the frames to the disk: {e}"))?;
+    // SAFETY: we map the whole file we just wrote, and only ever read it
+    let ptr = unsafe {
+        mmap(
+            None,
+            len,
+            ProtFlags::PROT_READ,
+            MapFlags::MAP_PRIVATE,
+            Some(&file),
+            0,
+        )
+    }
+    .map_err(|e| format!("failed to map the spilled frames: {e}"))?;
+    Ok(Spilled {
+        ptr: NonNull::new(ptr).ok_or("mmap returned a null pointer")?,
+        len,
+    })
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Spills to a cache of our own, rather than the user's
+    pub fn use_temp_cache() {
+        static CACHE: std::sync::Once = std::sync::Once::new();
+        CACHE.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("swww-spill-test-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::env::set_var("XDG_CACHE_HOME", dir);
+        });
+    }
+
+    #[test]
+    fn spilled_bytes_should_read_back_the_same() {
+        use_temp_cache();
+        let bytes: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let spilled = spill(bytes.clone()).unwrap_or_else(|(_, e)| panic!("{e}"));
+        assert!(spilled.is_spilled());
+        assert_eq!(&*spilled, &bytes[..]);
+
+        // and stay where they are if we can't
+        let (empty, _) = spill(Vec::new()).err().unwrap();
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,488 @@
+//! Animations that arrive a few frames at a time, see `Playback::more_frames`.
+//!
+//! For long animations, the client sends us the first frames as soon as it made them, and the
+//! rest in `Request::AnimationFrames` while it makes them, so that we can start playing long
+//! before it is done. If we catch up with the frames that arrived, we keep showing the last of
+//! them until the next ones do. Until the last frames arrive, we don't know how many there are,
+//! so we never loop back to the first one before that.
+//!
+//! With `Playback::stream`, the client keeps sending the frames, loop after loop, and we drop
+//! them once we played them. We only answer the request some frames came in once we start
+//! playing them, and the client waits for that answer before making more, so we never hold more
+//! than the frames we are playing, and those that come right after.
+
+use log::{debug, warn};
+use rkyv::Deserialize;
+
+use std::{
+    collections::VecDeque,
+    os::unix::net::UnixStream,
+    sync::{atomic::AtomicUsize, Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use utils::{
+    comp_decomp::BitPack,
+    ipc::{Answer, ArchivedAnimation, ArchivedRequest, Request},
+};
+
+use super::{
+    frame_cache::CachedFrames,
+    spill::{self, Bytes},
+    Playback, Step,
+};
+
+/// How long we wait for the next frames before deciding the client is gone
+const STREAM_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often we check whether we should stop waiting
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+type Frame = rkyv::Archived<(BitPack, Duration)>;
+
+/// The frames of a `Request::AnimationFrames`
+struct Chunk {
+    bytes: Bytes,
+    /// The client waiting for us to start playing them, with `Playback::stream`
+    client: Option<UnixStream>,
+}
+
+impl Chunk {
+    fn frames(&self) -> &[Frame] {
+        &frames_in(&self.bytes).frames
+    }
+}
+
+/// Where the frames an animation is still waiting for go, once they arrive
+pub struct FrameStream {
+    /// Whether the animation drops its frames once it played them, see `Playback::stream`
+    streamed: bool,
+    /// The frames that arrived, that the animation hasn't taken yet
+    arrived: Mutex<Vec<Chunk>>,
+    cvar: Condvar,
+}
+
+impl FrameStream {
+    pub fn new(streamed: bool) -> Self {
+        Self {
+            streamed,
+            arrived: Mutex::new(Vec::new()),
+            cvar: Condvar::new(),
+        }
+    }
+
+    pub fn is_streamed(&self) -> bool {
+        self.streamed
+    }
+
+    /// `bytes` must hold a `Request::AnimationFrames`. We answer `client` once we start playing
+    /// its frames
+    pub fn push(&self, bytes: Vec<u8>, client: Option<UnixStream>) {
+        self.arrived.lock().unwrap().push(Chunk {
+            bytes: Bytes::InMemory(bytes),
+            client,
+        });
+        self.cvar.notify_all();
+    }
+}
+
+fn frames_in(bytes: &[u8]) -> &utils::ipc::ArchivedAnimationFrames {
+    // SAFETY: `Request::receive` checked these bytes when they arrived, and we look at them once
+    // per frame, so we don't check them all over again
+    match unsafe { rkyv::archived_root::<Request>(bytes) } {
+        ArchivedRequest::AnimationFrames(frames) => frames,
+        _ => unreachable!("only animation frames are pushed to a stream"),
+    }
+}
+
+pub(super) fn packed_len(frames: &[Frame]) -> usize {
+    frames.iter().map(|(frame, _)| frame.compressed_len()).sum()
+}
+
+/// The first of `frames` that no longer matches its checksum. We refuse those before playing any of
+/// them, since we would only find out when we got to it
+pub(super) fn first_corrupt(frames: &[Frame]) -> Option<usize> {
+    frames.iter().position(|(frame, _)| !frame.is_intact())
+}
+
+/// The frames of an animation, including those still on their way to us
+pub struct Frames<'a> {
+    animation: &'a ArchivedAnimation,
+    /// The frames after the animation's own, that we didn't drop
+    more: VecDeque<Chunk>,
+    /// Which of `more` every frame after the animation's own is in, and where in it. Only without
+    /// `Playback::stream`, since we then never drop any of them
+    index: Vec<(usize, usize)>,
+    /// Until the last frames arrive, or, with `Playback::stream`, until the animation stops
+    stream: Option<Arc<FrameStream>>,
+    streamed: bool,
+    /// How many frames arrived, counting the animation's own, and those we dropped
+    received: usize,
+    /// How many frames there are going forward, once the last of them arrived
+    len: Option<usize>,
+    /// With `Playback::stream`, the frame the next step plays, counting every frame we played
+    /// since the first, whatever loop they were in, and whether we handed it out already
+    next: usize,
+    taken: bool,
+    /// How many of the frames after the animation's own we dropped
+    dropped: usize,
+    cached: CachedFrames,
+}
+
+impl<'a> Frames<'a> {
+    /// `cached` must track the animation's own frames
+    pub fn new(
+        animation: &'a ArchivedAnimation,
+        stream: Option<Arc<FrameStream>>,
+        cached: CachedFrames,
+    ) -> Self {
+        Self {
+            animation,
+            more: VecDeque::new(),
+            index: Vec::new(),
+            streamed: stream.as_ref().is_some_and(|s| s.is_streamed()),
+            len: stream.is_none().then_some(animation.animation.len()),
+            stream,
+            received: animation.animation.len(),
+            next: 0,
+            taken: false,
+            dropped: 0,
+            cached,
+        }
+    }
+
+    /// How many frames there are going forward, or `None` until they all arrived
+    pub fn len(&self) -> Option<usize> {
+        self.len
+    }
+
+    /// How many bytes the frames we hold take, see [`CachedFrames::bytes`]
+    pub fn bytes(&self) -> Arc<AtomicUsize> {
+        self.cached.bytes()
+    }
+
+    /// Takes the frames that arrived since we last looked. Those that don't fit under the memory
+    /// limit are spilled to the disk. If we can't, they are refused, along with every frame after
+    /// them, so that we stop once we played the frames we have
+    fn take_arrived(&mut self) {
+        let Some(stream) = &self.stream else {
+            return;
+        };
+        let arrived = std::mem::take(&mut *stream.arrived.lock().unwrap());
+        let mut last = false;
+        for mut chunk in arrived {
+            if let Err(e) = self.cached.grow(packed_len(chunk.frames())) {
+                let Bytes::InMemory(bytes) = chunk.bytes else {
+                    unreachable!("only the frames we took are spilled");
+                };
+                match spill::spill(bytes) {
+                    Ok(spilled) => {
+                        debug!("spilling an animation's frames to the disk: {e}");
+                        chunk.bytes = spilled;
+                    }
+                    Err((_, spill_error)) => {
+                        warn!("refusing the rest of an animation's frames: {e}, and {spill_error}");
+                        self.stream = None;
+                        return;
+                    }
+                }
+            }
+            let frames = frames_in(&chunk.bytes);
+            if !self.streamed {
+                let i = self.more.len();
+                self.index.extend((0..frames.frames.len()).map(|j| (i, j)));
+            }
+            self.received += frames.frames.len();
+            if frames.last && self.len.is_none() {
+                self.len = Some(self.received);
+            }
+            last |= frames.last;
+            self.more.push_back(chunk);
+        }
+        if last && !self.streamed {
+            self.stream = None;
+        }
+    }
+
+    /// Waits for frame `i` to arrive, for as long as `keep_waiting` says to. Returns whether it
+    /// did
+    fn wait_for(&mut self, i: usize, keep_waiting: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        loop {
+            self.take_arrived();
+            if i < self.received {
+                return true;
+            }
+            let Some(stream) = &self.stream else {
+                return false;
+            };
+            if !keep_waiting() || start.elapsed() > STREAM_TIMEOUT {
+                return false;
+            }
+            let arrived = stream.arrived.lock().unwrap();
+            if arrived.is_empty() {
+                drop(
+                    stream
+                        .cvar
+                        .wait_timeout(arrived, WAIT_POLL_INTERVAL)
+                        .unwrap(),
+                );
+            }
+        }
+    }
+
+    /// Which of `more` frame `i` is in, and where in it, with `Playback::stream`
+    fn find(&self, i: usize) -> Option<(usize, usize)> {
+        let mut j = i.checked_sub(self.animation.animation.len() + self.dropped)?;
+        for (chunk, frames) in self.more.iter().map(Chunk::frames).enumerate() {
+            match j.checked_sub(frames.len()) {
+                Some(rest) => j = rest,
+                None => return Some((chunk, j)),
+            }
+        }
+        None
+    }
+
+    /// Drops the frames we played, with `Playback::stream`, and tells the client waiting for the
+    /// ones we play next that we started them
+    fn move_on(&mut self) {
+        // the frames we play next arrived, so they are either the animation's own, or in `more`
+        let Some((played, _)) = self.find(self.next) else {
+            return;
+        };
+        for chunk in self.more.drain(..played) {
+            let frames = chunk.frames();
+            self.dropped += frames.len();
+            if !chunk.bytes.is_spilled() {
+                self.cached.shrink(packed_len(frames));
+            }
+        }
+        if let Some(client) = self.more[0].client.take() {
+            if let Err(e) = Answer::Ok.send(&client) {
+                debug!("failed to tell the client we play its frames: {e}");
+            }
+        }
+    }
+
+    /// Waits for the frame `playback`'s next step needs, for as long as `keep_waiting` says to.
+    /// Returns whether it arrived. Once the last frames do, `playback` loops back to the first
+    pub fn wait_next(&mut self, playback: &mut Playback, keep_waiting: impl Fn() -> bool) -> bool {
+        if self.streamed {
+            // every frame we handed out was played, since we only hand them out to play them
+            if self.taken {
+                self.next += 1;
+                self.taken = false;
+            }
+            if !self.wait_for(self.next, keep_waiting) {
+                return false;
+            }
+            self.move_on();
+            self.taken = true;
+        } else if playback.direction == 1 && !self.wait_for(playback.frame, keep_waiting) {
+            return false;
+        }
+        if let Some(len) = self.len {
+            playback.frames = len;
+        }
+        true
+    }
+
+    /// The delay of the frame `playback`'s next step shows, if it arrived already. Unlike
+    /// [`Frames::wait_next`], this never waits, nor hands the frame out
+    pub fn next_delay(&mut self, playback: &Playback) -> Option<Duration> {
+        self.take_arrived();
+        let frame = if self.streamed {
+            let next = self.next + usize::from(self.taken);
+            match self.find(next) {
+                Some((chunk, j)) => &self.more[chunk].frames()[j],
+                None => self.animation.animation.get().get(next)?,
+            }
+        } else {
+            let step = playback.clone().advance();
+            if matches!(step, Step::Forward(i) if i >= self.received) {
+                return None;
+            }
+            self.get(step)
+        };
+        Some(frame.1.deserialize(&mut rkyv::Infallible).unwrap())
+    }
+
+    /// The frame a step shows, and its delay
+    pub fn get(&self, step: Step) -> &Frame {
+        match step {
+            Step::Forward(_) if self.streamed => match self.find(self.next) {
+                Some((chunk, j)) => &self.more[chunk].frames()[j],
+                None => &self.animation.animation[self.next],
+            },
+            Step::Forward(i) => match i.checked_sub(self.animation.animation.len()) {
+                None => &self.animation.animation[i],
+                Some(i) => {
+                    let (chunk, j) = self.index[i];
+                    &self.more[chunk].frames()[j]
+                }
+            },
+            Step::Backward(i) => &self.animation.reverse[i],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animations::frame_cache::FrameCache;
+    use utils::{
+        comp_decomp::CompressionBackend,
+        ipc::{read_socket, Animation, AnimationFrames, ArchivedAnswer},
+    };
+
+    fn frames(colors: &[u8]) -> Box<[(BitPack, Duration)]> {
+        colors
+            .windows(2)
+            .map(|pair| {
+                let (prev, cur) = ([pair[0]; 12], [pair[1]; 12]);
+                let frame = BitPack::pack(&prev, &cur, CompressionBackend::None, 1).unwrap();
+                (frame, Duration::from_millis(10))
+            })
+            .collect()
+    }
+
+    fn more_frames(colors: &[u8], last: bool) -> Vec<u8> {
+        let request = Request::AnimationFrames(AnimationFrames {
+            request: 1,
+            animation: 0,
+            frames: frames(colors),
+            last,
+        });
+        rkyv::to_bytes::<_, 256>(&request).unwrap().into_vec()
+    }
+
+    fn play(frames: &mut Frames, playback: &mut Playback, canvas: &mut [u8]) -> bool {
+        frames.wait_next(playback, || false) && frames.get(playback.advance()).0.unpack(canvas)
+    }
+
+    fn animation(colors: &[u8]) -> rkyv::AlignedVec {
+        let animation = Animation {
+            animation: frames(colors),
+            reverse: Box::new([]),
+            path: String::new(),
+            dimensions: (2, 2),
+            cache_key: None,
+            compression: CompressionBackend::None,
+        };
+        rkyv::to_bytes::<_, 256>(&animation).unwrap()
+    }
+
+    /// Tracks the frames of `animation`, under `limit` bytes if there is one
+    fn cached(animation: &ArchivedAnimation, limit: Option<usize>) -> CachedFrames {
+        let cache = Arc::new(Mutex::new(FrameCache::new(limit)));
+        let bytes = packed_len(&animation.animation);
+        FrameCache::insert(&cache, bytes, vec![0], Box::new(|| true)).unwrap()
+    }
+
+    /// Whether the daemon answered `client`, without waiting for it
+    fn answered(client: &UnixStream) -> bool {
+        client.set_nonblocking(true).unwrap();
+        match read_socket(client) {
+            Ok(bytes) => matches!(Answer::receive(&bytes), Ok(ArchivedAnswer::Ok)),
+            Err(_) => false,
+        }
+    }
+
+    #[test]
+    fn should_play_frames_as_they_arrive() {
+        let bytes = animation(&[0, 1, 2]);
+        let animation = unsafe { rkyv::archived_root::<Animation>(&bytes) };
+        let stream = Arc::new(FrameStream::new(false));
+        let mut frames = Frames::new(
+            animation,
+            Some(Arc::clone(&stream)),
+            cached(animation, None),
+        );
+        let mut playback = Playback::new(usize::MAX, false, None);
+        let mut canvas = [0; 16];
+
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert_eq!(canvas[0], 2);
+        // we hold the last frame that arrived
+        assert!(!play(&mut frames, &mut playback, &mut canvas));
+        assert_eq!(playback.frame, 2);
+        assert_eq!(frames.len(), None);
+
+        stream.push(more_frames(&[2, 3], false), None);
+        stream.push(more_frames(&[3, 0], true), None);
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert_eq!(frames.len(), Some(4));
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        // and only then loop back to the first one
+        assert_eq!((playback.frame, canvas[0]), (0, 0));
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert_eq!((playback.frame, canvas[0]), (1, 1));
+    }
+
+    #[test]
+    fn should_drop_streamed_frames_once_played() {
+        let bytes = animation(&[0, 1, 2]);
+        let animation = unsafe { rkyv::archived_root::<Animation>(&bytes) };
+        let stream = Arc::new(FrameStream::new(true));
+        let mut frames = Frames::new(
+            animation,
+            Some(Arc::clone(&stream)),
+            cached(animation, None),
+        );
+        let mut playback = Playback::new(usize::MAX, false, None);
+        let mut canvas = [0; 16];
+        let (first, first_client) = UnixStream::pair().unwrap();
+        let (second, second_client) = UnixStream::pair().unwrap();
+        stream.push(more_frames(&[2, 3, 0], true), Some(first));
+        // the next loop
+        stream.push(more_frames(&[0, 1, 2], false), Some(second));
+
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert!(!answered(&first_client));
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        // it may send more frames once we play those it sent
+        assert!(answered(&first_client));
+        assert!(!answered(&second_client));
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert_eq!((frames.len(), playback.frame, canvas[0]), (Some(4), 0, 0));
+
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        assert_eq!((playback.frame, canvas[0]), (1, 1));
+        assert!(answered(&second_client));
+        assert_eq!(frames.more.len(), 1);
+        assert!(play(&mut frames, &mut playback, &mut canvas));
+        // and then wait for the rest of the loop
+        assert!(!play(&mut frames, &mut playback, &mut canvas));
+        assert_eq!((playback.frame, canvas[0]), (2, 2));
+    }
+
+    #[test]
+    fn frames_over_the_memory_limit_should_be_spilled_to_the_disk() {
+        spill::tests::use_temp_cache();
+        let bytes = animation(&[0, 1, 2]);
+        let animation = unsafe { rkyv::archived_root::<Animation>(&bytes) };
+        let stream = Arc::new(FrameStream::new(false));
+        // room for the animation's own frames, and the next two
+        let limit = packed_len(&animation.animation) * 2;
+        let cached = cached(animation, Some(limit));
+        let mut frames = Frames::new(animation, Some(Arc::clone(&stream)), cached);
+        let mut playback = Playback::new(usize::MAX, false, None);
+        let mut canvas = [0; 16];
+
+        stream.push(more_frames(&[2, 3, 4], false), None);
+        stream.push(more_frames(&[4, 5, 0], true), None);
+        for color in [1, 2, 3, 4, 5, 0, 1] {
+            assert!(play(&mut frames, &mut playback, &mut canvas));
+            assert_eq!(canvas[0], color);
+        }
+        assert!(!frames.more[0].bytes.is_spilled());
+        assert!(frames.more[1].bytes.is_spilled());
+        // the spilled frames aren't counted
+        assert_eq!(
+            frames.bytes().load(std::sync::atomic::Ordering::Acquire),
+            limit
+        );
+        assert_eq!(frames.len(), Some(6));
+    }
+}
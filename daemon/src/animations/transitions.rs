@@ -6,7 +6,10 @@ use std::{
 use rayon::prelude::*;
 
 use log::debug;
-use utils::ipc::{ArchivedPosition, ArchivedTransitionType};
+use utils::{
+    gamma,
+    ipc::{ArchivedDirection, ArchivedPosition, ArchivedTransitionType, Transform},
+};
 
 use crate::wallpaper::{AnimationToken, Wallpaper};
 
@@ -14,6 +17,9 @@ use keyframe::{
     functions::BezierCurve, keyframes, mint::Vector2, num_traits::Pow, AnimationSequence,
 };
 
+#[cfg(feature = "gpu")]
+mod gpu;
+
 macro_rules! change_cols {
     ($step:ident, $old:ident, $new:ident, $done:ident) => {
         for (old_col, new_col) in $old.iter_mut().zip($new) {
@@ -42,9 +48,27 @@ macro_rules! change_cols {
     };
 }
 
+/// Where a transition draws its frames to
+enum Target {
+    /// The wallpapers on the screen
+    Wallpapers {
+        animation_tokens: Vec<AnimationToken>,
+        wallpapers: Vec<Arc<Wallpaper>>,
+    },
+    /// An off-screen canvas, keeping every frame we draw, for previews. Here, time only passes
+    /// when we draw a frame, so the same transition always results in the same frames
+    Headless {
+        canvas: Box<[u8]>,
+        frames: Vec<Box<[u8]>>,
+        /// In seconds
+        frame_time: f64,
+        /// The frame the current loop started at
+        first_frame: usize,
+    },
+}
+
 pub struct Transition {
-    animation_tokens: Vec<AnimationToken>,
-    wallpapers: Vec<Arc<Wallpaper>>,
+    target: Target,
     dimensions: (u32, u32),
     transition_type: ArchivedTransitionType,
     duration: f32,
@@ -52,9 +76,24 @@ pub struct Transition {
     fps: Duration,
     angle: f64,
     pos: ArchivedPosition,
+    /// Where the pointer is, when `pos` asks to follow it and we know where it is
+    cursor: Option<(f32, f32)>,
     bezier: BezierCurve,
     wave: (f32, f32),
     invert_y: bool,
+    direction: ArchivedDirection,
+    /// How the outputs are rotated or flipped. `angle` and `direction` are already transformed
+    /// with it, but `pos` is not
+    transform: Transform,
+    bg_color: [u8; 3],
+    reversed: bool,
+    /// Whether fades blend in linear light, see [`utils::gamma`]
+    gamma_correct: bool,
+    loops: u8,
+    /// Whether we are playing the last loop, which is the only one that settles on the new image
+    last_loop: bool,
+    /// What the canvases looked like before the transition, so that every loop can start from it
+    old_canvases: Vec<(usize, Box<[u8]>)>,
 }
 
 /// All transitions return whether or not they completed
@@ -64,19 +103,80 @@ impl Transition {
         dimensions: (u32, u32),
         transition: utils::ipc::ArchivedTransition,
     ) -> Self {
-        Transition {
-            animation_tokens: wallpapers
-                .iter()
-                .map(|w| w.create_animation_token())
-                .collect(),
+        // the fastest output sets the pace, so that it never looks choppy
+        let refresh_rate = wallpapers.iter().filter_map(|w| w.refresh_rate()).max();
+        let fps_adaptive = transition.fps_adaptive;
+        // and the lowest `--max-fps` caps it, like it does the frames clients make for them
+        let max_fps = utils::ipc::shared_max_fps(wallpapers.iter().map(|w| w.max_fps()));
+        let cursor = if transition.pos.follow_cursor {
+            wallpapers.iter().find_map(|w| w.cursor_position())
+        } else {
+            None
+        };
+        // the client only sends the same image to outputs with the same transform
+        let transform = wallpapers
+            .first()
+            .map_or(Transform::Normal, |w| w.transform());
+        let animation_tokens: Vec<_> = wallpapers
+            .iter()
+            .map(|w| w.create_animation_token())
+            .collect();
+        for (wallpaper, token) in wallpapers.iter().zip(&animation_tokens) {
+            wallpaper.start_transition(token);
+        }
+        let target = Target::Wallpapers {
+            animation_tokens,
             wallpapers,
+        };
+        let mut transition = Self::with_target(target, dimensions, transition);
+        transition.cursor = cursor;
+        transition.transform = transform;
+        transition.angle = transform.to_buffer_angle(transition.angle);
+        transition.direction = buffer_direction(transition.direction, transform);
+        if let (true, Some(hz)) = (fps_adaptive, refresh_rate) {
+            transition.fps = Duration::from_nanos(1_000_000_000 / hz as u64);
+        }
+        if let Some(max_fps) = max_fps {
+            transition.fps = transition.fps.max(Duration::from_secs(1) / max_fps);
+        }
+        transition
+    }
+
+    /// Creates a transition that draws off-screen, starting from `old_img` (which, like the new
+    /// image, has 3 bytes per pixel). See [`Transition::render`]
+    pub fn headless(
+        old_img: &[u8],
+        dimensions: (u32, u32),
+        transition: utils::ipc::ArchivedTransition,
+    ) -> Self {
+        let mut canvas = vec![0; dimensions.0 as usize * dimensions.1 as usize * 4];
+        for (pix, old) in canvas.chunks_exact_mut(4).zip(old_img.chunks_exact(3)) {
+            pix[0..3].copy_from_slice(old);
+        }
+        let target = Target::Headless {
+            canvas: canvas.into_boxed_slice(),
+            frames: Vec::new(),
+            frame_time: 1.0 / transition.fps.max(1) as f64,
+            first_frame: 0,
+        };
+        Self::with_target(target, dimensions, transition)
+    }
+
+    fn with_target(
+        target: Target,
+        dimensions: (u32, u32),
+        transition: utils::ipc::ArchivedTransition,
+    ) -> Self {
+        Transition {
+            target,
             dimensions,
             transition_type: transition.transition_type,
             duration: transition.duration,
             step: transition.step,
-            fps: Duration::from_nanos(1_000_000_000 / transition.fps as u64),
+            fps: Duration::from_nanos(1_000_000_000 / transition.fps.max(1) as u64),
             angle: transition.angle,
             pos: transition.pos,
+            cursor: None,
             bezier: BezierCurve::from(
                 Vector2 {
                     x: transition.bezier.0,
@@ -89,40 +189,227 @@ impl Transition {
             ),
             wave: transition.wave,
             invert_y: transition.invert_y,
+            direction: transition.direction,
+            transform: Transform::Normal,
+            bg_color: transition.bg_color,
+            reversed: transition.reversed,
+            gamma_correct: transition.gamma_correct,
+            loops: transition.loops.max(1),
+            last_loop: true,
+            old_canvases: Vec::new(),
         }
     }
 
     pub fn execute(mut self, new_img: &[u8]) {
+        self.run(new_img);
+        if let Target::Wallpapers {
+            animation_tokens,
+            wallpapers,
+        } = &self.target
+        {
+            for (wallpaper, token) in wallpapers.iter().zip(animation_tokens) {
+                wallpaper.end_transition(token);
+            }
+        }
+    }
+
+    /// Runs a headless transition, returning every frame it drew, in the canvas' format (4 bytes
+    /// per pixel)
+    pub fn render(mut self, new_img: &[u8]) -> Vec<Box<[u8]>> {
+        self.run(new_img);
+        match self.target {
+            Target::Headless { frames, .. } => frames,
+            Target::Wallpapers { .. } => Vec::new(),
+        }
+    }
+
+    /// Looping transitions play `loops` times, each taking an equal part of the total duration
+    fn run(&mut self, new_img: &[u8]) {
         debug!("Starting transitions");
+        let loops = match self.transition_type {
+            // this one isn't timed, so there is nothing to subdivide
+            ArchivedTransitionType::Simple => 1,
+            _ => self.loops,
+        };
+        if loops > 1 {
+            self.old_canvases = self.snapshot();
+            self.duration /= loops as f32;
+        }
+        for i in 1..=loops {
+            self.last_loop = i == loops;
+            self.run_once(new_img);
+        }
+        debug!("Transitions finished");
+    }
+
+    fn run_once(&mut self, new_img: &[u8]) {
         match self.transition_type {
             ArchivedTransitionType::Simple => self.simple(new_img),
             ArchivedTransitionType::Wipe => self.wipe(new_img),
             ArchivedTransitionType::Grow => self.grow(new_img),
             ArchivedTransitionType::Outer => self.outer(new_img),
             ArchivedTransitionType::Wave => self.wave(new_img),
-            ArchivedTransitionType::Fade => self.fade(new_img),
+            // outputs that were animated cut to the new image, and blend the animation in by
+            // themselves, so only still images get here
+            ArchivedTransitionType::Fade | ArchivedTransitionType::Crossfade => self.fade(new_img),
+            ArchivedTransitionType::Slide => self.slide(new_img),
+            ArchivedTransitionType::ZoomOut => self.zoom_out(new_img),
+            ArchivedTransitionType::Burn => self.burn(new_img),
         };
-        debug!("Transitions finished");
     }
 
     fn send_frame(&mut self, now: &mut Instant) {
+        let (animation_tokens, wallpapers) = match &mut self.target {
+            Target::Wallpapers {
+                animation_tokens,
+                wallpapers,
+            } => (animation_tokens, wallpapers),
+            Target::Headless { canvas, frames, .. } => {
+                frames.push(canvas.clone());
+                return;
+            }
+        };
         let fps = self.fps;
         let mut i = 0;
-        while i < self.wallpapers.len() {
-            let token = &self.animation_tokens[i];
-            if !self.wallpapers[i].has_animation_id(token) {
-                self.wallpapers.swap_remove(i);
-                self.animation_tokens.swap_remove(i);
+        while i < wallpapers.len() {
+            let token = &animation_tokens[i];
+            if !wallpapers[i].has_animation_id(token) {
+                wallpapers.swap_remove(i);
+                animation_tokens.swap_remove(i);
                 continue;
             }
             i += 1;
         }
-        let timeout = fps.saturating_sub(now.elapsed());
-        spin_sleep::sleep(timeout);
+        let elapsed = now.elapsed();
+        if elapsed > fps {
+            crate::metrics::frame_dropped();
+        }
+        spin_sleep::sleep(fps.saturating_sub(elapsed));
         crate::wake_poll();
         *now = Instant::now();
     }
 
+    /// Calls `f` with every canvas we are drawing to, and then presents them. `f` also receives an
+    /// id for the canvas, which stays the same during the whole transition
+    fn draw<F>(&mut self, mut f: F)
+    where
+        F: FnMut(usize, &mut [u8]),
+    {
+        match &mut self.target {
+            Target::Wallpapers { wallpapers, .. } => {
+                for wallpaper in wallpapers.iter() {
+                    let id = Arc::as_ptr(wallpaper) as usize;
+                    let (_, buffer) = wallpaper.canvas_change(|canvas| f(id, canvas));
+                    wallpaper.draw(&buffer);
+                }
+            }
+            Target::Headless { canvas, .. } => f(0, canvas),
+        }
+    }
+
+    /// Copies of the current canvases, with the same ids given by [`Transition::draw`]
+    fn snapshot(&self) -> Vec<(usize, Box<[u8]>)> {
+        match &self.target {
+            Target::Wallpapers { wallpapers, .. } => wallpapers
+                .iter()
+                .map(|w| {
+                    let id = Arc::as_ptr(w) as usize;
+                    (id, w.canvas_change(|canvas| Box::from(&*canvas)).0)
+                })
+                .collect(),
+            Target::Headless { canvas, .. } => vec![(0, canvas.clone())],
+        }
+    }
+
+    /// How many seconds have passed since `start`. Headless transitions ignore the real time, and
+    /// advance one `fps` interval per frame instead
+    fn elapsed(&self, start: Instant) -> f64 {
+        match &self.target {
+            Target::Wallpapers { .. } => start.elapsed().as_secs_f64(),
+            // durations come to us as f32s, so we leave some room for rounding errors
+            Target::Headless {
+                frames,
+                frame_time,
+                first_frame,
+                ..
+            } => (frames.len() - first_frame) as f64 * frame_time + 1e-6,
+        }
+    }
+
+    /// Ends a transition that ran for its full duration by quickly fading the remaining
+    /// differences away. Headless transitions instead just make their last frame the new image,
+    /// so that they always have exactly as many frames as their duration and fps imply
+    fn finish(&mut self, new_img: &[u8]) {
+        if !self.last_loop {
+            self.restart_loop(new_img);
+            return;
+        }
+        if let Target::Headless { canvas, .. } = &mut self.target {
+            for (pix, new) in canvas.chunks_exact_mut(4).zip(new_img.chunks_exact(3)) {
+                pix[0..3].copy_from_slice(new);
+            }
+            self.present_last_frame();
+            return;
+        }
+        self.step = 4 + self.step / 4;
+        self.simple(new_img)
+    }
+
+    /// Ends every loop but the last: we show the new image for a frame, and then go back to the old
+    /// one, so that the next loop starts from scratch
+    fn restart_loop(&mut self, new_img: &[u8]) {
+        self.draw(|_, canvas| {
+            for (pix, new) in canvas.chunks_exact_mut(4).zip(new_img.chunks_exact(3)) {
+                pix[0..3].copy_from_slice(new);
+            }
+        });
+        self.present_last_frame();
+        if let Target::Wallpapers { .. } = self.target {
+            spin_sleep::sleep(self.fps);
+        }
+
+        let old_canvases = std::mem::take(&mut self.old_canvases);
+        self.draw(|id, canvas| {
+            if let Some((_, old)) = old_canvases.iter().find(|(old_id, _)| *old_id == id) {
+                canvas.copy_from_slice(old);
+            }
+        });
+        self.old_canvases = old_canvases;
+        if let Target::Headless {
+            frames,
+            first_frame,
+            ..
+        } = &mut self.target
+        {
+            *first_frame = frames.len();
+        }
+    }
+
+    /// Presents what we've drawn without waiting for the next frame. Headless transitions replace
+    /// their last frame with it, instead of adding a new one
+    fn present_last_frame(&mut self) {
+        match &mut self.target {
+            Target::Wallpapers { .. } => crate::wake_poll(),
+            Target::Headless { canvas, frames, .. } => match frames.last_mut() {
+                Some(last) => last.clone_from(canvas),
+                None => frames.push(canvas.clone()),
+            },
+        }
+    }
+
+    /// Where transitions like 'grow' start from, in pixels
+    fn origin(&self) -> (f32, f32) {
+        self.cursor.unwrap_or_else(|| {
+            // `pos` is on the output as we see it, with y growing upwards
+            let (width, height) = self.transform.buffer_dim(self.dimensions);
+            let (x, y) = self.pos.to_pixel((width, height), self.invert_y);
+            let (x, y) = self
+                .transform
+                .to_buffer((x, height as f32 - y), (width as f32, height as f32));
+            (x, self.dimensions.1 as f32 - y)
+        })
+    }
+
     fn bezier_seq(&self, start: f32, end: f32) -> (AnimationSequence<f32>, Instant) {
         (
             keyframes![(start, 0.0, self.bezier), (end, self.duration, self.bezier)],
@@ -130,50 +417,60 @@ impl Transition {
         )
     }
 
+    /// Like `bezier_seq`, but for transitions that sweep across the screen, which can be reversed.
+    /// When reversed, the sweep runs from `end` to `start`, and the pixels that are changed are the
+    /// ones the sweep has *not* reached yet.
+    fn sweep_seq(&self, start: f32, end: f32) -> (AnimationSequence<f32>, Instant) {
+        if self.reversed {
+            self.bezier_seq(end, start)
+        } else {
+            self.bezier_seq(start, end)
+        }
+    }
+
     fn simple(&mut self, new_img: &[u8]) {
         let step = self.step;
         let mut now = Instant::now();
         let mut done = false;
         while !done {
             done = true;
-            for wallpaper in self.wallpapers.iter_mut() {
-                let (_, buffer) = wallpaper.canvas_change(|canvas| {
-                    for (old, new) in canvas.chunks_exact_mut(4).zip(new_img.chunks_exact(3)) {
-                        change_cols!(step, old, new, done);
-                    }
-                });
-                wallpaper.draw(&buffer);
-            }
+            self.draw(|_, canvas| {
+                for (old, new) in canvas.chunks_exact_mut(4).zip(new_img.chunks_exact(3)) {
+                    change_cols!(step, old, new, done);
+                }
+            });
             self.send_frame(&mut now);
         }
     }
 
     fn fade(&mut self, new_img: &[u8]) {
-        let mut step = 0.0;
+        let mut step: f64 = 0.0;
         let (mut seq, start) = self.bezier_seq(0.0, 1.0);
 
         let mut now = Instant::now();
-        while start.elapsed().as_secs_f64() < seq.duration() {
-            for wallpaper in self.wallpapers.iter_mut() {
-                let (_, buffer) = wallpaper.canvas_change(|canvas| {
-                    canvas
-                        .par_chunks_exact_mut(4)
-                        .zip(new_img.par_chunks_exact(3))
-                        .for_each(|(old_pix, new_pix)| {
-                            for (old_col, new_col) in old_pix.iter_mut().zip(new_pix) {
-                                *old_col =
-                                    (*old_col as f64 * (1.0 - step) + *new_col as f64 * step) as u8;
-                            }
-                        });
-                });
-                wallpaper.draw(&buffer);
-            }
+        let gamma_correct = self.gamma_correct;
+        let (to_linear, to_srgb) = (gamma::to_linear_table(), gamma::to_srgb_table());
+        while self.elapsed(start) < seq.duration() {
+            let weight = (step.min(1.0) * 256.0) as u32;
+            self.draw(|_, canvas| {
+                canvas
+                    .par_chunks_exact_mut(4)
+                    .zip(new_img.par_chunks_exact(3))
+                    .for_each(|(old_pix, new_pix)| {
+                        for (old_col, &new_col) in old_pix.iter_mut().zip(new_pix) {
+                            *old_col = if gamma_correct {
+                                gamma::mix(*old_col, new_col, weight, to_linear, to_srgb)
+                            } else {
+                                (*old_col as f64 * (1.0 - step) + new_col as f64 * step) as u8
+                            };
+                        }
+                    });
+            });
             self.send_frame(&mut now);
             step = seq.now() as f64;
-            seq.advance_to(start.elapsed().as_secs_f64());
+            seq.advance_to(self.elapsed(start));
         }
-        self.step = 4 + self.step / 4;
-        self.simple(new_img)
+        self.finish(new_img)
     }
 
     fn wave(&mut self, new_img: &[u8]) {
@@ -184,68 +481,40 @@ impl Transition {
         let screen_diag = ((width.pow(2) + height.pow(2)) as f64).sqrt();
 
         let angle = self.angle.to_radians();
-        let (scale_x, scale_y) = (self.wave.0 as f64, self.wave.1 as f64);
+        let wave = (self.wave.0 as f64, self.wave.1 as f64);
 
         let circle_radius = screen_diag / 2.0;
-
-        let f = |x: f64| (x / scale_x).sin() * scale_y;
-
-        // graph: https://www.desmos.com/calculator/wunde042es
-        //
-        // checks if a pixel is to the left or right of the line
-        let is_low = |x: f64, y: f64, offset: f64| {
-            let x = x - center.0 as f64;
-            let y = y - center.1 as f64;
-
-            let lhs = y * angle.cos() - x * angle.sin();
-            let rhs = f(x * angle.cos() + y * angle.sin()) + circle_radius - offset;
-            lhs >= rhs
-        };
-
-        // find the offset to start the transition at
-        let mut offset = {
-            let mut offset = 0.0;
-            for x in 0..width {
-                for y in 0..height {
-                    if is_low(x as f64, y as f64, offset) {
-                        offset += 1.0;
-                        break;
-                    }
-                }
-            }
-            offset
-        };
-        let max_offset = 2.0 * circle_radius - offset;
+        let (min_offset, max_offset) = wave_offsets(self.dimensions, angle, wave, circle_radius);
         let (width, height) = (width as usize, height as usize);
 
-        let (mut seq, start) = self.bezier_seq(offset as f32, max_offset as f32);
+        let (mut seq, start) = self.sweep_seq(min_offset as f32, max_offset as f32);
+        let mut offset = seq.now() as f64;
 
         let step = self.step;
+        let reversed = self.reversed;
 
-        while start.elapsed().as_secs_f64() < seq.duration() {
-            for wallpaper in self.wallpapers.iter_mut() {
-                let (_, buffer) = wallpaper.canvas_change(|canvas| {
-                    canvas
-                        .par_chunks_exact_mut(4)
-                        .zip(new_img.par_chunks_exact(3))
-                        .enumerate()
-                        .for_each(|(i, (old, new))| {
-                            let pix_x = i % width;
-                            let pix_y = height - i / width;
-                            if is_low(pix_x as f64, pix_y as f64, offset) {
-                                change_cols!(step, old, new);
-                            }
-                        });
-                });
-                wallpaper.draw(&buffer);
-            }
+        while self.elapsed(start) < seq.duration() {
+            self.draw(|_, canvas| {
+                canvas
+                    .par_chunks_exact_mut(4)
+                    .zip(new_img.par_chunks_exact(3))
+                    .enumerate()
+                    .for_each(|(i, (old, new))| {
+                        let pix_x = i % width;
+                        let pix_y = height - i / width;
+                        let pix = (pix_x as f64, pix_y as f64);
+                        if wave_is_low(pix, center, angle, wave, offset, circle_radius) != reversed
+                        {
+                            change_cols!(step, old, new);
+                        }
+                    });
+            });
             self.send_frame(&mut now);
 
             offset = seq.now() as f64;
-            seq.advance_to(start.elapsed().as_secs_f64());
+            seq.advance_to(self.elapsed(start));
         }
-        self.step = 4 + self.step / 4;
-        self.simple(new_img)
+        self.finish(new_img)
     }
 
     fn wipe(&mut self, new_img: &[u8]) {
@@ -256,63 +525,43 @@ impl Transition {
         let screen_diag = ((width.pow(2) + height.pow(2)) as f64).sqrt();
 
         let circle_radius = screen_diag / 2.0;
-        let max_offset = circle_radius.pow(2) * 2.0;
 
         let angle = self.angle.to_radians();
-
-        let mut offset = {
-            let (x, y) = angle.sin_cos();
-            (x.abs() * width as f64 / 2.0 + y.abs() * height as f64 / 2.0).abs()
-        };
-
-        // line formula: (x-h)*a + (y-k)*b + C = r^2
-        // https://www.desmos.com/calculator/vpvzk12yar
-        //
-        // checks if a pixel is to the left or right of the line
-        let is_low = |pix_x: f64, pix_y: f64, offset: f64, radius: f64| {
-            let a = radius * angle.cos();
-            let b = radius * angle.sin();
-            let x = pix_x - center.0 as f64;
-            let y = pix_y - center.1 as f64;
-            let res = x * a + y * b + offset;
-            res >= radius.pow(2)
-        };
+        let (min_offset, max_offset) = wipe_offsets(self.dimensions, angle, circle_radius);
 
         let (width, height) = (width as usize, height as usize);
-        let (mut seq, start) = self.bezier_seq(0.0, max_offset as f32);
+        let (mut seq, start) = self.sweep_seq(min_offset as f32, max_offset as f32);
+        let mut offset = seq.now() as f64;
 
         let step = self.step;
+        let reversed = self.reversed;
 
-        while start.elapsed().as_secs_f64() < seq.duration() {
-            for wallpaper in self.wallpapers.iter_mut() {
-                let (_, buffer) = wallpaper.canvas_change(|canvas| {
-                    canvas
-                        .par_chunks_exact_mut(4)
-                        .zip(new_img.par_chunks_exact(3))
-                        .enumerate()
-                        .for_each(|(i, (old, new))| {
-                            let pix_x = i % width;
-                            let pix_y = height - i / width;
-                            if is_low(pix_x as f64, pix_y as f64, offset, circle_radius) {
-                                change_cols!(step, old, new);
-                            }
-                        });
-                });
-                wallpaper.draw(&buffer);
-            }
+        while self.elapsed(start) < seq.duration() {
+            self.draw(|_, canvas| {
+                canvas
+                    .par_chunks_exact_mut(4)
+                    .zip(new_img.par_chunks_exact(3))
+                    .enumerate()
+                    .for_each(|(i, (old, new))| {
+                        let pix_x = i % width;
+                        let pix_y = height - i / width;
+                        let pix = (pix_x as f64, pix_y as f64);
+                        if wipe_is_low(pix, center, angle, offset, circle_radius) != reversed {
+                            change_cols!(step, old, new);
+                        }
+                    });
+            });
             self.send_frame(&mut now);
 
             offset = seq.now() as f64;
-            seq.advance_to(start.elapsed().as_secs_f64());
+            seq.advance_to(self.elapsed(start));
         }
-        self.step = 4 + self.step / 4;
-        self.simple(new_img)
+        self.finish(new_img)
     }
 
     fn grow(&mut self, new_img: &[u8]) {
         let (width, height) = (self.dimensions.0 as f32, self.dimensions.1 as f32);
-        let (center_x, center_y) = self.pos.to_pixel(self.dimensions, self.invert_y);
-        let mut dist_center: f32 = 0.0;
+        let (center_x, center_y) = self.origin();
         let dist_end: f32 = {
             let mut x = center_x;
             let mut y = center_y;
@@ -328,43 +577,41 @@ impl Transition {
         let (width, height) = (width as usize, height as usize);
         let (center_x, center_y) = (center_x as usize, center_y as usize);
 
-        let (mut seq, start) = self.bezier_seq(0.0, dist_end);
+        let (mut seq, start) = self.sweep_seq(0.0, dist_end);
+        let mut dist_center = seq.now();
+        let step = self.step;
+        let reversed = self.reversed;
         let mut now = Instant::now();
-        while start.elapsed().as_secs_f64() < seq.duration() {
-            for wallpaper in self.wallpapers.iter_mut() {
-                let (_, buffer) = wallpaper.canvas_change(|canvas| {
-                    canvas
-                        .par_chunks_exact_mut(4)
-                        .zip(new_img.par_chunks_exact(3))
-                        .enumerate()
-                        .for_each(|(i, (old, new))| {
-                            let pix_x = i % width;
-                            let pix_y = height - i / width;
-                            let diff_x = pix_x.abs_diff(center_x);
-                            let diff_y = pix_y.abs_diff(center_y);
-                            let pix_center_dist = f32::sqrt((diff_x.pow(2) + diff_y.pow(2)) as f32);
-                            if pix_center_dist <= dist_center {
-                                let step = self
-                                    .step
-                                    .saturating_add((dist_center - pix_center_dist).log2() as u8);
-                                change_cols!(step, old, new);
-                            }
-                        });
-                });
-                wallpaper.draw(&buffer);
-            }
+        while self.elapsed(start) < seq.duration() {
+            self.draw(|_, canvas| {
+                canvas
+                    .par_chunks_exact_mut(4)
+                    .zip(new_img.par_chunks_exact(3))
+                    .enumerate()
+                    .for_each(|(i, (old, new))| {
+                        let pix_x = i % width;
+                        let pix_y = height - i / width;
+                        let diff_x = pix_x.abs_diff(center_x);
+                        let diff_y = pix_y.abs_diff(center_y);
+                        let pix_center_dist = f32::sqrt((diff_x.pow(2) + diff_y.pow(2)) as f32);
+                        if (pix_center_dist <= dist_center) != reversed {
+                            let step = step
+                                .saturating_add((dist_center - pix_center_dist).abs().log2() as u8);
+                            change_cols!(step, old, new);
+                        }
+                    });
+            });
             self.send_frame(&mut now);
 
             dist_center = seq.now();
-            seq.advance_to(start.elapsed().as_secs_f64());
+            seq.advance_to(self.elapsed(start));
         }
-        self.step = 4 + self.step / 4;
-        self.simple(new_img)
+        self.finish(new_img)
     }
 
     fn outer(&mut self, new_img: &[u8]) {
         let (width, height) = (self.dimensions.0 as f32, self.dimensions.1 as f32);
-        let (center_x, center_y) = self.pos.to_pixel(self.dimensions, self.invert_y);
+        let (center_x, center_y) = self.origin();
         let mut dist_center = {
             let mut x = center_x;
             let mut y = center_y;
@@ -379,37 +626,757 @@ impl Transition {
         let (width, height) = (width as usize, height as usize);
         let (center_x, center_y) = (center_x as usize, center_y as usize);
 
-        let (mut seq, start) = self.bezier_seq(dist_center, 0.0);
+        let (mut seq, start) = self.sweep_seq(dist_center, 0.0);
+        dist_center = seq.now();
+        let step = self.step;
+        let reversed = self.reversed;
         let mut now = Instant::now();
-        while start.elapsed().as_secs_f64() < seq.duration() {
-            for wallpaper in self.wallpapers.iter_mut() {
-                let (_, buffer) = wallpaper.canvas_change(|canvas| {
-                    canvas
-                        .par_chunks_exact_mut(4)
-                        .zip(new_img.par_chunks_exact(3))
-                        .enumerate()
-                        .for_each(|(i, (old, new))| {
-                            let pix_x = i % width;
-                            let pix_y = height - i / width;
-                            let diff_x = pix_x.abs_diff(center_x);
-                            let diff_y = pix_y.abs_diff(center_y);
-                            let pix_center_dist = f32::sqrt((diff_x.pow(2) + diff_y.pow(2)) as f32);
-                            if pix_center_dist >= dist_center {
-                                let step = self
-                                    .step
-                                    .saturating_add((pix_center_dist - dist_center).log2() as u8);
-                                change_cols!(step, old, new);
-                            }
-                        });
+        while self.elapsed(start) < seq.duration() {
+            self.draw(|_, canvas| {
+                canvas
+                    .par_chunks_exact_mut(4)
+                    .zip(new_img.par_chunks_exact(3))
+                    .enumerate()
+                    .for_each(|(i, (old, new))| {
+                        let pix_x = i % width;
+                        let pix_y = height - i / width;
+                        let diff_x = pix_x.abs_diff(center_x);
+                        let diff_y = pix_y.abs_diff(center_y);
+                        let pix_center_dist = f32::sqrt((diff_x.pow(2) + diff_y.pow(2)) as f32);
+                        if (pix_center_dist >= dist_center) != reversed {
+                            let step = step
+                                .saturating_add((pix_center_dist - dist_center).abs().log2() as u8);
+                            change_cols!(step, old, new);
+                        }
+                    });
+            });
+            self.send_frame(&mut now);
+
+            dist_center = seq.now();
+            seq.advance_to(self.elapsed(start));
+        }
+        self.finish(new_img)
+    }
+
+    fn slide(&mut self, new_img: &[u8]) {
+        self.full_frames(new_img, FullFrame::Slide(self.direction));
+    }
+
+    fn zoom_out(&mut self, new_img: &[u8]) {
+        self.full_frames(new_img, FullFrame::ZoomOut(self.bg_color));
+    }
+
+    fn burn(&mut self, new_img: &[u8]) {
+        self.full_frames(new_img, FullFrame::Burn(self.origin()));
+    }
+
+    /// Runs a transition that redraws the whole frame every time, instead of approaching the new
+    /// image incrementally, on the GPU if `--renderer gpu` and we can, and on the CPU otherwise
+    fn full_frames(&mut self, new_img: &[u8], frame: FullFrame) {
+        let dimensions = self.dimensions;
+        // since we overwrite the whole canvas every frame, we need to remember what the old image
+        // looked like
+        let old_imgs = self.snapshot();
+        #[cfg(feature = "gpu")]
+        let textures = crate::gpu::with(|renderer| {
+            Ok(gpu::Textures::new(renderer, dimensions, &old_imgs, new_img))
+        });
+        let redraw = |id: usize, canvas: &mut [u8], progress: f32| {
+            #[cfg(feature = "gpu")]
+            if let Some(textures) = &textures {
+                let drawn = crate::gpu::with(|renderer| {
+                    textures.draw(renderer, id, frame, progress, canvas)
                 });
-                wallpaper.draw(&buffer);
+                if drawn.is_some() {
+                    return;
+                }
             }
+            if let Some((_, old_img)) = old_imgs.iter().find(|(old_id, _)| *old_id == id) {
+                frame.draw(canvas, old_img, new_img, dimensions, progress);
+            }
+        };
+
+        let (mut seq, start) = self.bezier_seq(0.0, 1.0);
+        let mut progress = 0.0;
+        let mut now = Instant::now();
+        while self.elapsed(start) < seq.duration() {
+            self.draw(|id, canvas| redraw(id, canvas, progress));
             self.send_frame(&mut now);
 
-            dist_center = seq.now();
-            seq.advance_to(start.elapsed().as_secs_f64());
+            progress = seq.now();
+            seq.advance_to(self.elapsed(start));
         }
-        self.step = 4 + self.step / 4;
-        self.simple(new_img)
+        self.draw(|id, canvas| redraw(id, canvas, 1.0));
+        #[cfg(feature = "gpu")]
+        if let Some(textures) = textures {
+            crate::gpu::with(|renderer| {
+                textures.delete(renderer);
+                Ok(())
+            });
+        }
+        self.present_last_frame();
+    }
+}
+
+/// The transitions that redraw the whole frame, see [`Transition::full_frames`]
+#[derive(Clone, Copy)]
+enum FullFrame {
+    Slide(ArchivedDirection),
+    ZoomOut([u8; 3]),
+    /// From the origin of the transition
+    Burn((f32, f32)),
+}
+
+impl FullFrame {
+    /// Draws the frame at `progress` on the CPU, see [`slide_frame`]
+    fn draw(&self, canvas: &mut [u8], old: &[u8], new: &[u8], dim: (u32, u32), progress: f32) {
+        match *self {
+            Self::Slide(direction) => slide_frame(canvas, old, new, dim, direction, progress),
+            Self::ZoomOut(bg_color) => zoom_out_frame(canvas, old, new, dim, bg_color, progress),
+            Self::Burn(origin) => burn_frame(canvas, old, new, dim, origin, progress),
+        }
+    }
+}
+
+/// How far the screen goes from its center, along `angle`, with half a pixel more on each side so
+/// that sweeping lines never start or end right on top of a pixel
+fn screen_extent((width, height): (u32, u32), angle: f64) -> f64 {
+    angle.cos().abs() * (width as f64 + 1.0) / 2.0 + angle.sin().abs() * (height as f64 + 1.0) / 2.0
+}
+
+/// The offsets at which the wipe line enters and leaves the screen, so that the wipe spends its
+/// whole duration sweeping across it, whatever its angle
+fn wipe_offsets(dimensions: (u32, u32), angle: f64, radius: f64) -> (f64, f64) {
+    let extent = screen_extent(dimensions, angle);
+    (
+        radius * radius - radius * extent,
+        radius * radius + radius * extent,
+    )
+}
+
+/// Like [`wipe_offsets`], for the wave, whose crests and troughs stick out `wave.1` pixels from
+/// its line. The line moves perpendicular to `angle`
+fn wave_offsets(dimensions: (u32, u32), angle: f64, wave: (f64, f64), radius: f64) -> (f64, f64) {
+    let extent = screen_extent(dimensions, angle + std::f64::consts::FRAC_PI_2) + wave.1.abs();
+    (radius - extent, radius + extent)
+}
+
+/// Checks if a pixel is to the left or right of the line of the wave
+///
+/// graph: https://www.desmos.com/calculator/wunde042es
+fn wave_is_low(
+    (pix_x, pix_y): (f64, f64),
+    center: (u32, u32),
+    angle: f64,
+    (scale_x, scale_y): (f64, f64),
+    offset: f64,
+    radius: f64,
+) -> bool {
+    let x = pix_x - center.0 as f64;
+    let y = pix_y - center.1 as f64;
+
+    let lhs = y * angle.cos() - x * angle.sin();
+    let rhs = ((x * angle.cos() + y * angle.sin()) / scale_x).sin() * scale_y + radius - offset;
+    lhs >= rhs
+}
+
+/// Checks if a pixel is to the left or right of the wipe line
+///
+/// line formula: (x-h)*a + (y-k)*b + C = r^2
+/// https://www.desmos.com/calculator/vpvzk12yar
+fn wipe_is_low(
+    (pix_x, pix_y): (f64, f64),
+    center: (u32, u32),
+    angle: f64,
+    offset: f64,
+    radius: f64,
+) -> bool {
+    let a = radius * angle.cos();
+    let b = radius * angle.sin();
+    let x = pix_x - center.0 as f64;
+    let y = pix_y - center.1 as f64;
+    let res = x * a + y * b + offset;
+    res >= radius.pow(2)
+}
+
+/// The edge of our buffers the edge `direction` of the output, as we see it, is on
+fn buffer_direction(direction: ArchivedDirection, transform: Transform) -> ArchivedDirection {
+    // the angle of the edge, from the center of the output
+    let angle = match direction {
+        ArchivedDirection::Right => 0.0,
+        ArchivedDirection::Up => 90.0,
+        ArchivedDirection::Left => 180.0,
+        ArchivedDirection::Down => 270.0,
+    };
+    match (transform.to_buffer_angle(angle) / 90.0).round() as u32 % 4 {
+        0 => ArchivedDirection::Right,
+        1 => ArchivedDirection::Up,
+        2 => ArchivedDirection::Left,
+        _ => ArchivedDirection::Down,
+    }
+}
+
+/// Draws a single frame of the 'slide' transition into `canvas`.
+///
+/// `old` must have the same layout as the canvas (4 bytes per pixel), while `new` has 3 bytes per
+/// pixel, like all images we receive from the client. `progress` goes from 0.0 (only the old image
+/// is visible) to 1.0 (only the new image is visible).
+fn slide_frame(
+    canvas: &mut [u8],
+    old: &[u8],
+    new: &[u8],
+    dimensions: (u32, u32),
+    direction: ArchivedDirection,
+    progress: f32,
+) {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    let (shift_x, shift_y) = slide_shift(dimensions, progress);
+
+    canvas
+        .par_chunks_exact_mut(width * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pix) in row.chunks_exact_mut(4).enumerate() {
+                // whether the pixel comes from the new image, and its coordinates on that image
+                let (from_new, src_x, src_y) = match direction {
+                    ArchivedDirection::Left if x < shift_x => (true, x + width - shift_x, y),
+                    ArchivedDirection::Left => (false, x - shift_x, y),
+                    ArchivedDirection::Right if x + shift_x >= width => {
+                        (true, x + shift_x - width, y)
+                    }
+                    ArchivedDirection::Right => (false, x + shift_x, y),
+                    ArchivedDirection::Up if y < shift_y => (true, x, y + height - shift_y),
+                    ArchivedDirection::Up => (false, x, y - shift_y),
+                    ArchivedDirection::Down if y + shift_y >= height => {
+                        (true, x, y + shift_y - height)
+                    }
+                    ArchivedDirection::Down => (false, x, y + shift_y),
+                };
+                let src_idx = src_y * width + src_x;
+                if from_new {
+                    pix[0..3].copy_from_slice(&new[src_idx * 3..src_idx * 3 + 3]);
+                } else {
+                    pix[0..3].copy_from_slice(&old[src_idx * 4..src_idx * 4 + 3]);
+                }
+            }
+        });
+}
+
+/// How many pixels the images of the 'slide' transition moved, across and down, by `progress`
+fn slide_shift((width, height): (u32, u32), progress: f32) -> (usize, usize) {
+    let (width, height) = (width as usize, height as usize);
+    let progress = progress.clamp(0.0, 1.0);
+    let shift_x = ((progress * width as f32).round() as usize).min(width);
+    let shift_y = ((progress * height as f32).round() as usize).min(height);
+    (shift_x, shift_y)
+}
+
+/// Draws a single frame of the 'zoom-out' transition into `canvas`.
+///
+/// During the first half, the old image shrinks towards the center of the screen, uncovering
+/// `bg_color`. During the second half, the new image grows from the center back to its full size.
+/// Image layouts and `progress` work like in [`slide_frame`].
+fn zoom_out_frame(
+    canvas: &mut [u8],
+    old: &[u8],
+    new: &[u8],
+    dimensions: (u32, u32),
+    bg_color: [u8; 3],
+    progress: f32,
+) {
+    let (shows_new, scale) = zoom_out_scale(progress);
+    let (img, bytes_per_pixel) = if shows_new { (new, 3) } else { (old, 4) };
+
+    let width = dimensions.0 as usize;
+    let (w, h) = (dimensions.0 as f32, dimensions.1 as f32);
+    let (scaled_w, scaled_h) = (w * scale, h * scale);
+    let (start_x, start_y) = ((w - scaled_w) / 2.0, (h - scaled_h) / 2.0);
+
+    canvas
+        .par_chunks_exact_mut(width * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pix) in row.chunks_exact_mut(4).enumerate() {
+                let img_x = x as f32 + 0.5 - start_x;
+                let img_y = y as f32 + 0.5 - start_y;
+                if scale <= 0.0
+                    || img_x < 0.0
+                    || img_y < 0.0
+                    || img_x >= scaled_w
+                    || img_y >= scaled_h
+                {
+                    pix[0] = bg_color[2];
+                    pix[1] = bg_color[1];
+                    pix[2] = bg_color[0];
+                } else {
+                    let color = bilinear_sample(
+                        img,
+                        bytes_per_pixel,
+                        dimensions,
+                        img_x / scale - 0.5,
+                        img_y / scale - 0.5,
+                    );
+                    pix[0..3].copy_from_slice(&color);
+                }
+            }
+        });
+}
+
+/// Whether the 'zoom-out' transition shows the new image by `progress`, instead of the old one,
+/// and how big it is, from 0.0 to 1.0, its full size
+fn zoom_out_scale(progress: f32) -> (bool, f32) {
+    let progress = progress.clamp(0.0, 1.0);
+    if progress < 0.5 {
+        (false, 1.0 - 2.0 * progress)
+    } else {
+        (true, 2.0 * progress - 1.0)
+    }
+}
+
+/// Width, in pixels, of the charred edge right in front of the new image, in the 'burn' transition
+const CHAR_BAND: f32 = 5.0;
+/// Width, in pixels, of the fire in front of the charred edge, in the 'burn' transition
+const FIRE_BAND: f32 = 10.0;
+
+/// Draws a single frame of the 'burn' transition into `canvas`.
+///
+/// The new image is revealed in a circle growing from `origin`. Right outside of it there is a
+/// dark, charred band, and then a band of fire, going from red to orange, over the old image.
+/// Image layouts and `progress` work like in [`slide_frame`].
+fn burn_frame(
+    canvas: &mut [u8],
+    old: &[u8],
+    new: &[u8],
+    dimensions: (u32, u32),
+    origin: (f32, f32),
+    progress: f32,
+) {
+    let radius = burn_radius(dimensions, origin, progress);
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    canvas
+        .par_chunks_exact_mut(width * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            // like `Position::to_pixel`, y grows from the bottom of the screen
+            let pix_y = (height - y) as f32;
+            for (x, pix) in row.chunks_exact_mut(4).enumerate() {
+                let i = y * width + x;
+                let (dx, dy) = (x as f32 - origin.0, pix_y - origin.1);
+                let band = (dx * dx + dy * dy).sqrt() - radius;
+                if band < 0.0 {
+                    pix[0..3].copy_from_slice(&new[i * 3..i * 3 + 3]);
+                } else if band < CHAR_BAND {
+                    for (col, old_col) in pix[0..3].iter_mut().zip(&old[i * 4..i * 4 + 3]) {
+                        *col = old_col / 8;
+                    }
+                } else if band < CHAR_BAND + FIRE_BAND {
+                    // from red, next to the charred edge, to orange, where the fire is spreading
+                    let heat = (band - CHAR_BAND) / FIRE_BAND;
+                    let fire = [0.0, 40.0 + 120.0 * heat, 255.0]; // BGR
+                    for ((col, old_col), fire_col) in
+                        pix[0..3].iter_mut().zip(&old[i * 4..i * 4 + 3]).zip(fire)
+                    {
+                        *col = (*old_col as f32 * 0.25 + fire_col * 0.75) as u8;
+                    }
+                } else {
+                    pix[0..3].copy_from_slice(&old[i * 4..i * 4 + 3]);
+                }
+            }
+        });
+}
+
+/// How far from `origin` the new image of the 'burn' transition goes by `progress`
+fn burn_radius((width, height): (u32, u32), origin: (f32, f32), progress: f32) -> f32 {
+    let progress = progress.clamp(0.0, 1.0);
+    let (w, h) = (width as f32, height as f32);
+    let dist_x = origin.0.max(w - origin.0);
+    let dist_y = origin.1.max(h - origin.1);
+    // the bands must also be gone by the end of the transition
+    let max_radius = (dist_x * dist_x + dist_y * dist_y).sqrt() + CHAR_BAND + FIRE_BAND;
+    max_radius * progress
+}
+
+/// Samples `img` at the (possibly fractional) coordinates `x` and `y`, interpolating between the
+/// four nearest pixels
+fn bilinear_sample(
+    img: &[u8],
+    bytes_per_pixel: usize,
+    dimensions: (u32, u32),
+    x: f32,
+    y: f32,
+) -> [u8; 3] {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    let x = x.clamp(0.0, (width - 1) as f32);
+    let y = y.clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let pixel = |x: usize, y: usize| {
+        let i = (y * width + x) * bytes_per_pixel;
+        &img[i..i + 3]
+    };
+    let (p00, p10, p01, p11) = (pixel(x0, y0), pixel(x1, y0), pixel(x0, y1), pixel(x1, y1));
+
+    let mut color = [0; 3];
+    for (i, col) in color.iter_mut().enumerate() {
+        let top = p00[i] as f32 * (1.0 - fx) + p10[i] as f32 * fx;
+        let bottom = p01[i] as f32 * (1.0 - fx) + p11[i] as f32 * fx;
+        *col = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::ipc::{Coord, Direction, Position, TransitionType};
+
+    const DIM: (u32, u32) = (10, 4);
+    const OLD: u8 = 0;
+    const NEW: u8 = 255;
+
+    fn slide(direction: ArchivedDirection, progress: f32) -> Vec<u8> {
+        let len = (DIM.0 * DIM.1) as usize;
+        let old = vec![OLD; len * 4];
+        let new = vec![NEW; len * 3];
+        let mut canvas = old.clone();
+        slide_frame(&mut canvas, &old, &new, DIM, direction, progress);
+        canvas
+    }
+
+    fn new_pixels(canvas: &[u8]) -> Vec<(usize, usize)> {
+        canvas
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(_, pix)| pix[0] == NEW)
+            .map(|(i, _)| (i % DIM.0 as usize, i / DIM.0 as usize))
+            .collect()
+    }
+
+    #[test]
+    fn slide_should_only_show_old_image_at_start() {
+        for direction in [
+            ArchivedDirection::Left,
+            ArchivedDirection::Right,
+            ArchivedDirection::Up,
+            ArchivedDirection::Down,
+        ] {
+            assert!(new_pixels(&slide(direction, 0.0)).is_empty());
+        }
+    }
+
+    #[test]
+    fn slide_should_only_show_new_image_at_end() {
+        let total = (DIM.0 * DIM.1) as usize;
+        for direction in [
+            ArchivedDirection::Left,
+            ArchivedDirection::Right,
+            ArchivedDirection::Up,
+            ArchivedDirection::Down,
+        ] {
+            assert_eq!(new_pixels(&slide(direction, 1.0)).len(), total);
+        }
+    }
+
+    #[test]
+    fn slide_should_split_screen_in_half_at_midpoint() {
+        let half = (DIM.0 * DIM.1 / 2) as usize;
+        let (w, h) = (DIM.0 as usize, DIM.1 as usize);
+
+        let left = new_pixels(&slide(ArchivedDirection::Left, 0.5));
+        assert_eq!(left.len(), half);
+        assert!(left.iter().all(|(x, _)| *x < w / 2));
+
+        let right = new_pixels(&slide(ArchivedDirection::Right, 0.5));
+        assert_eq!(right.len(), half);
+        assert!(right.iter().all(|(x, _)| *x >= w / 2));
+
+        let up = new_pixels(&slide(ArchivedDirection::Up, 0.5));
+        assert_eq!(up.len(), half);
+        assert!(up.iter().all(|(_, y)| *y < h / 2));
+
+        let down = new_pixels(&slide(ArchivedDirection::Down, 0.5));
+        assert_eq!(down.len(), half);
+        assert!(down.iter().all(|(_, y)| *y >= h / 2));
+    }
+
+    fn zoom_out(progress: f32) -> Vec<u8> {
+        let len = (DIM.0 * DIM.1) as usize;
+        let old = (0..len * 4).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let new = (0..len * 3)
+            .map(|i| 100 + (i % 11) as u8)
+            .collect::<Vec<_>>();
+        let mut canvas = old.clone();
+        zoom_out_frame(&mut canvas, &old, &new, DIM, [10, 20, 30], progress);
+        canvas
+    }
+
+    #[test]
+    fn zoom_out_should_only_show_background_at_midpoint() {
+        for pix in zoom_out(0.5).chunks_exact(4) {
+            assert_eq!(pix[0..3], [30, 20, 10]);
+        }
+    }
+
+    #[test]
+    fn zoom_out_should_show_full_images_at_start_and_end() {
+        let len = (DIM.0 * DIM.1) as usize;
+        let start = zoom_out(0.0);
+        for (i, pix) in start.chunks_exact(4).enumerate() {
+            for (j, col) in pix[0..3].iter().enumerate() {
+                assert_eq!(*col, ((i * 4 + j) % 7) as u8);
+            }
+        }
+
+        let end = zoom_out(1.0);
+        for (i, pix) in end.chunks_exact(4).take(len).enumerate() {
+            for (j, col) in pix[0..3].iter().enumerate() {
+                assert_eq!(*col, 100 + ((i * 3 + j) % 11) as u8);
+            }
+        }
+    }
+
+    fn burn(progress: f32) -> Vec<u8> {
+        // the bands are 15 pixels wide, so we need a wider screen to see all of them at once
+        let dim = (80, 4);
+        let len = (dim.0 * dim.1) as usize;
+        let old = vec![100; len * 4];
+        let new = vec![NEW; len * 3];
+        let mut canvas = old.clone();
+        // from the middle of the left edge, so the fire sweeps to the right
+        burn_frame(&mut canvas, &old, &new, dim, (0.0, 2.0), progress);
+        canvas
+    }
+
+    #[test]
+    fn burn_should_have_fire_in_front_of_the_new_image() {
+        let canvas = burn(0.5);
+        let fire = canvas
+            .chunks_exact(4)
+            .filter(|pix| pix[2] > 180 && pix[0] < 100)
+            .count();
+        assert!(fire > 0, "no fire in {canvas:?}");
+        assert!(canvas.chunks_exact(4).any(|pix| pix[0..3] == [NEW; 3]));
+        assert!(canvas.chunks_exact(4).any(|pix| pix[0..3] == [100; 3]));
+
+        assert!(burn(0.0).chunks_exact(4).all(|pix| pix[0..3] != [NEW; 3]));
+        assert!(burn(1.0).chunks_exact(4).all(|pix| pix[0..3] == [NEW; 3]));
+    }
+
+    fn headless_transition(
+        transition_type: TransitionType,
+        duration: f32,
+        fps: u8,
+        loops: u8,
+    ) -> Transition {
+        headless(&ipc_transition(transition_type, duration, fps, loops))
+    }
+
+    fn ipc_transition(
+        transition_type: TransitionType,
+        duration: f32,
+        fps: u8,
+        loops: u8,
+    ) -> utils::ipc::Transition {
+        utils::ipc::Transition {
+            transition_type,
+            duration,
+            step: 90,
+            fps,
+            fps_adaptive: false,
+            angle: 45.0,
+            pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+            bezier: (0.54, 0.0, 0.34, 0.99),
+            wave: (20.0, 20.0),
+            invert_y: false,
+            direction: Direction::Left,
+            bg_color: [0, 0, 0],
+            reversed: false,
+            gamma_correct: false,
+            loops,
+            first_frame: true,
+            startup: utils::ipc::StartupTransition {
+                transition_type: TransitionType::Fade,
+                step: 90,
+                angle: 45.0,
+                pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+            },
+            queue: false,
+            scene_change_threshold: None,
+        }
+    }
+
+    /// `transition` from the old image to the new one, rendered frame by frame
+    fn headless(transition: &utils::ipc::Transition) -> Transition {
+        let bytes = rkyv::to_bytes::<_, 256>(transition).unwrap();
+        let archived = unsafe { rkyv::archived_root::<utils::ipc::Transition>(&bytes) };
+        let old = vec![OLD; (DIM.0 * DIM.1 * 3) as usize];
+        Transition::headless(&old, DIM, archived.clone())
+    }
+
+    #[test]
+    fn headless_transitions_should_have_one_frame_per_fps_interval() {
+        let new = vec![NEW; (DIM.0 * DIM.1 * 3) as usize];
+        for (transition_type, fps) in [
+            (TransitionType::Fade, 30),
+            (TransitionType::Fade, 60),
+            (TransitionType::Wipe, 30),
+            (TransitionType::Slide, 144),
+        ] {
+            let frames = headless_transition(transition_type, 0.2, fps, 1).render(&new);
+            let expected = (200.0 * fps as f32 / 1000.0).ceil() as usize;
+            assert_eq!(frames.len(), expected, "fps: {fps}");
+
+            // the last frame should always be the new image
+            let last = frames.last().unwrap();
+            assert!(last.chunks_exact(4).all(|pix| pix[0..3] == [NEW; 3]));
+        }
+    }
+
+    #[test]
+    fn looped_wipe_should_wipe_once_per_loop() {
+        let new = vec![NEW; (DIM.0 * DIM.1 * 3) as usize];
+        let frames = headless_transition(TransitionType::Wipe, 0.3, 30, 3).render(&new);
+        assert_eq!(frames.len(), 9);
+
+        let is_new = |frame: &[u8]| frame.chunks_exact(4).all(|pix| pix[0..3] == [NEW; 3]);
+        let is_partly_old = |frame: &[u8]| frame.chunks_exact(4).any(|pix| pix[0..3] == [OLD; 3]);
+        for wipe in frames.chunks_exact(3) {
+            assert!(
+                is_partly_old(&wipe[0]),
+                "every wipe should start from the old image"
+            );
+            assert!(is_new(&wipe[2]), "every wipe should end on the new image");
+        }
+    }
+
+    /// Which pixels have been reached by a wipe at `progress` (from 0.0 to 1.0), following the
+    /// same steps as `Transition::wipe`
+    fn wipe_mask(dim: (u32, u32), angle: f64, progress: f64, reversed: bool) -> Vec<bool> {
+        let (width, height) = dim;
+        let center = (width / 2, height / 2);
+        let radius = (((width.pow(2) + height.pow(2)) as f64).sqrt()) / 2.0;
+        let (min_offset, max_offset) = wipe_offsets(dim, angle.to_radians(), radius);
+        let progress = if reversed { 1.0 - progress } else { progress };
+        let offset = min_offset + (max_offset - min_offset) * progress;
+        (0..width * height)
+            .map(|i| {
+                let pix = ((i % width) as f64, (height - i / width) as f64);
+                wipe_is_low(pix, center, angle.to_radians(), offset, radius) != reversed
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wipe_should_sweep_the_screen_for_its_whole_duration() {
+        let reached = |angle, progress| {
+            let mask = wipe_mask((160, 90), angle, progress, false);
+            mask.iter().filter(|reached| **reached).count() as f64 / mask.len() as f64
+        };
+        for angle in [0.0, 30.0, 90.0, 135.0, 200.0, 270.0, 333.0] {
+            assert!(reached(angle, 0.0) < 0.01, "angle: {angle}");
+            assert!(reached(angle, 0.05) > 0.0, "angle: {angle}");
+            assert!(reached(angle, 0.95) < 1.0, "angle: {angle}");
+            assert_eq!(reached(angle, 1.0), 1.0, "angle: {angle}");
+        }
+    }
+
+    #[test]
+    fn wave_should_sweep_the_screen_for_its_whole_duration() {
+        let dim: (u32, u32) = (160, 90);
+        let center = (dim.0 / 2, dim.1 / 2);
+        let radius = (((dim.0.pow(2) + dim.1.pow(2)) as f64).sqrt()) / 2.0;
+        for angle in [0.0, 30.0, 90.0, 200.0, 333.0] {
+            for wave in [(20.0, 20.0), (5.0, 0.0), (50.0, 100.0)] {
+                let angle: f64 = angle;
+                let (min, max) = wave_offsets(dim, angle.to_radians(), wave, radius);
+                let reached = |progress: f64| {
+                    let offset = min + (max - min) * progress;
+                    let reached = (0..dim.0 * dim.1)
+                        .filter(|i| {
+                            let pix = ((i % dim.0) as f64, (dim.1 - i / dim.0) as f64);
+                            wave_is_low(pix, center, angle.to_radians(), wave, offset, radius)
+                        })
+                        .count();
+                    reached as f64 / (dim.0 * dim.1) as f64
+                };
+                let msg = format!("angle: {angle}, wave: {wave:?}");
+                assert_eq!(reached(0.0), 0.0, "{msg}");
+                assert!(reached(0.5) > 0.0, "{msg}");
+                assert!(reached(0.5) < 1.0, "{msg}");
+                assert_eq!(reached(1.0), 1.0, "{msg}");
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_transitions_should_keep_pace_with_the_fastest_output() {
+        use crate::headless::Headless;
+        use smithay_client_toolkit::output::Mode;
+
+        let mode = |refresh_rate, current| Mode {
+            dimensions: (2560, 1440),
+            refresh_rate,
+            current,
+            preferred: false,
+        };
+        let (slow, _) = Headless::wallpaper("HEADLESS-1", (2, 2));
+        slow.set_refresh_rate(&[mode(60_000, true)]);
+        let (fast, _) = Headless::wallpaper("HEADLESS-2", (2, 2));
+        // only the mode the output is in counts, rounded to the nearest Hz
+        fast.set_refresh_rate(&[mode(240_000, false), mode(143_856, true)]);
+        let (unknown, _) = Headless::wallpaper("HEADLESS-3", (2, 2));
+        unknown.set_refresh_rate(&[mode(0, true)]);
+
+        let pace = |wallpapers: &[&Arc<Wallpaper>], fps_adaptive| {
+            let mut transition = ipc_transition(TransitionType::Fade, 0.2, 30, 1);
+            transition.fps_adaptive = fps_adaptive;
+            let bytes = rkyv::to_bytes::<_, 256>(&transition).unwrap();
+            let archived = unsafe { rkyv::archived_root::<utils::ipc::Transition>(&bytes) };
+            let wallpapers = wallpapers.iter().map(|w| Arc::clone(w)).collect();
+            Transition::new(wallpapers, (2, 2), archived.clone()).fps
+        };
+        let hz = |hz: u64| Duration::from_nanos(1_000_000_000 / hz);
+        assert_eq!(pace(&[&slow, &fast, &unknown], true), hz(144));
+        assert_eq!(pace(&[&slow, &unknown], true), hz(60));
+        // without a refresh rate, or without asking for it, we keep to --transition-fps
+        assert_eq!(pace(&[&unknown], true), hz(30));
+        assert_eq!(pace(&[&slow, &fast], false), hz(30));
+    }
+
+    #[test]
+    fn reversed_wipe_should_be_a_wipe_from_the_opposite_side() {
+        let new = vec![NEW; (DIM.0 * DIM.1 * 3) as usize];
+        let wipe = |angle, reversed| {
+            let mut transition = ipc_transition(TransitionType::Wipe, 0.3, 30, 1);
+            transition.angle = angle;
+            transition.reversed = reversed;
+            headless(&transition).render(&new)
+        };
+        // 180 is 'left', so reversing it should give us 'right', which is 0
+        let reversed = wipe(180.0, true);
+        assert_eq!(reversed, wipe(0.0, false));
+        assert_ne!(reversed, wipe(180.0, false));
+        let is_partly_old = |frame: &[u8]| frame.chunks_exact(4).any(|pix| pix[0..3] == [OLD; 3]);
+        assert!(is_partly_old(&reversed[0]));
+        assert!(reversed
+            .last()
+            .unwrap()
+            .chunks_exact(4)
+            .all(|pix| pix[0..3] == [NEW; 3]));
+    }
+
+    #[test]
+    fn slides_should_enter_from_the_same_edge_of_rotated_outputs() {
+        use ArchivedDirection::*;
+        for direction in [Left, Right, Up, Down] {
+            assert!(buffer_direction(direction, Transform::Normal) == direction);
+        }
+        // the left edge of an output rotated counter-clockwise is at the bottom of its buffers
+        assert!(buffer_direction(Left, Transform::Rotated90) == Down);
+        assert!(buffer_direction(Up, Transform::Rotated270) == Right);
+        assert!(buffer_direction(Left, Transform::Flipped) == Right);
+        assert!(buffer_direction(Up, Transform::Flipped) == Up);
     }
 }
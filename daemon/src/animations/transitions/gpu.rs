@@ -0,0 +1,268 @@
+//! The transitions that redraw the whole frame, on the GPU, see [`crate::gpu`].
+//!
+//! The shaders do what [`slide_frame`](super::slide_frame),
+//! [`zoom_out_frame`](super::zoom_out_frame) and [`burn_frame`](super::burn_frame) do, with the
+//! same parameters, so that a transition looks the same wherever it is drawn. Textures hold the
+//! images in the byte order the canvas has, and we copy the fragments back as they are, so that
+//! nothing needs swapping channels.
+
+use utils::ipc::ArchivedDirection;
+
+use super::{burn_radius, slide_shift, zoom_out_scale, FullFrame, CHAR_BAND, FIRE_BAND};
+use crate::{
+    gl::{Texture, Uniform},
+    gpu::Renderer,
+};
+
+const SLIDE: &str = "#version 300 es
+precision highp float;
+precision highp int;
+uniform sampler2D old_img;
+uniform sampler2D new_img;
+uniform ivec2 size;
+uniform ivec2 shift;
+// 0 is left, 1 right, 2 up, and 3 down
+uniform int direction;
+out vec4 color;
+void main() {
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    bool from_new;
+    ivec2 src = p;
+    if (direction == 0) {
+        from_new = p.x < shift.x;
+        src.x = from_new ? p.x + size.x - shift.x : p.x - shift.x;
+    } else if (direction == 1) {
+        from_new = p.x + shift.x >= size.x;
+        src.x = from_new ? p.x + shift.x - size.x : p.x + shift.x;
+    } else if (direction == 2) {
+        from_new = p.y < shift.y;
+        src.y = from_new ? p.y + size.y - shift.y : p.y - shift.y;
+    } else {
+        from_new = p.y + shift.y >= size.y;
+        src.y = from_new ? p.y + shift.y - size.y : p.y + shift.y;
+    }
+    vec4 old_color = texelFetch(old_img, src, 0);
+    vec4 new_color = texelFetch(new_img, src, 0);
+    color = vec4(from_new ? new_color.rgb : old_color.rgb, 1.0);
+}
+";
+
+const ZOOM_OUT: &str = "#version 300 es
+precision highp float;
+uniform sampler2D img;
+uniform vec2 size;
+uniform float scale;
+uniform vec2 start;
+uniform vec3 bg_color;
+out vec4 color;
+void main() {
+    vec2 p = gl_FragCoord.xy - start;
+    if (scale <= 0.0 || any(lessThan(p, vec2(0.0))) || any(greaterThanEqual(p, size * scale))) {
+        color = vec4(bg_color, 1.0);
+    } else {
+        color = vec4(texture(img, p / scale / size).rgb, 1.0);
+    }
+}
+";
+
+const BURN: &str = "#version 300 es
+precision highp float;
+uniform sampler2D old_img;
+uniform sampler2D new_img;
+uniform float height;
+uniform vec2 origin;
+uniform float radius;
+uniform float char_band;
+uniform float fire_band;
+out vec4 color;
+void main() {
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    // like `Position::to_pixel`, y grows from the bottom of the screen
+    vec2 d = vec2(float(p.x), height - float(p.y)) - origin;
+    float band = length(d) - radius;
+    vec3 old_color = round(texelFetch(old_img, p, 0).rgb * 255.0);
+    vec3 c;
+    if (band < 0.0) {
+        c = round(texelFetch(new_img, p, 0).rgb * 255.0);
+    } else if (band < char_band) {
+        c = floor(old_color / 8.0);
+    } else if (band < char_band + fire_band) {
+        float heat = (band - char_band) / fire_band;
+        c = floor(old_color * 0.25 + vec3(0.0, 40.0 + 120.0 * heat, 255.0) * 0.75);
+    } else {
+        c = old_color;
+    }
+    color = vec4(c / 255.0, 1.0);
+}
+";
+
+/// The images of a transition, uploaded once for all of its frames. They are textures of this
+/// thread's renderer, which [`Textures::delete`] must get back
+pub struct Textures {
+    dimensions: (u32, u32),
+    /// By the ids [`super::Transition::draw`] gives
+    old: Vec<(usize, Texture)>,
+    new: Texture,
+}
+
+impl Textures {
+    pub fn new(
+        renderer: &Renderer,
+        dimensions: (u32, u32),
+        old_imgs: &[(usize, Box<[u8]>)],
+        new_img: &[u8],
+    ) -> Self {
+        let context = renderer.context();
+        Self {
+            dimensions,
+            old: old_imgs
+                .iter()
+                .map(|(id, img)| (*id, context.texture(dimensions, img, 4)))
+                .collect(),
+            new: context.texture(dimensions, new_img, 3),
+        }
+    }
+
+    /// Draws the frame at `progress` on the canvas of the wallpaper `id`, which we must have the
+    /// old image of
+    pub fn draw(
+        &self,
+        renderer: &mut Renderer,
+        id: usize,
+        frame: FullFrame,
+        progress: f32,
+        canvas: &mut [u8],
+    ) -> Result<(), String> {
+        let Some((_, old)) = self.old.iter().find(|(old_id, _)| *old_id == id) else {
+            return Ok(());
+        };
+        let dim = self.dimensions;
+        let (w, h) = (dim.0 as f32, dim.1 as f32);
+        let program = match frame {
+            FullFrame::Slide(direction) => {
+                let program = renderer.program(SLIDE)?;
+                let context = renderer.context();
+                let (shift_x, shift_y) = slide_shift(dim, progress);
+                let direction = match direction {
+                    ArchivedDirection::Left => 0,
+                    ArchivedDirection::Right => 1,
+                    ArchivedDirection::Up => 2,
+                    ArchivedDirection::Down => 3,
+                };
+                let size = [dim.0 as i32, dim.1 as i32];
+                context.set_uniform(&program, "size", Uniform::IVec2(size));
+                let shift = [shift_x as i32, shift_y as i32];
+                context.set_uniform(&program, "shift", Uniform::IVec2(shift));
+                context.set_uniform(&program, "direction", Uniform::Int(direction));
+                context.bind_texture(&program, "old_img", 0, old);
+                context.bind_texture(&program, "new_img", 1, &self.new);
+                program
+            }
+            FullFrame::ZoomOut(bg_color) => {
+                let program = renderer.program(ZOOM_OUT)?;
+                let context = renderer.context();
+                let (shows_new, scale) = zoom_out_scale(progress);
+                let start = [(w - w * scale) / 2.0, (h - h * scale) / 2.0];
+                context.set_uniform(&program, "size", Uniform::Vec2([w, h]));
+                context.set_uniform(&program, "scale", Uniform::Float(scale));
+                context.set_uniform(&program, "start", Uniform::Vec2(start));
+                // in the canvas' order, like the textures
+                let [r, g, b] = bg_color.map(|col| col as f32 / 255.0);
+                context.set_uniform(&program, "bg_color", Uniform::Vec3([b, g, r]));
+                let img = if shows_new { &self.new } else { old };
+                context.bind_texture(&program, "img", 0, img);
+                program
+            }
+            FullFrame::Burn(origin) => {
+                let program = renderer.program(BURN)?;
+                let context = renderer.context();
+                let radius = burn_radius(dim, origin, progress);
+                context.set_uniform(&program, "height", Uniform::Float(h));
+                context.set_uniform(&program, "origin", Uniform::Vec2([origin.0, origin.1]));
+                context.set_uniform(&program, "radius", Uniform::Float(radius));
+                context.set_uniform(&program, "char_band", Uniform::Float(CHAR_BAND));
+                context.set_uniform(&program, "fire_band", Uniform::Float(FIRE_BAND));
+                context.bind_texture(&program, "old_img", 0, old);
+                context.bind_texture(&program, "new_img", 1, &self.new);
+                program
+            }
+        };
+        renderer.draw(program, dim, canvas, 3)
+    }
+
+    pub fn delete(self, renderer: &Renderer) {
+        let context = renderer.context();
+        for (_, texture) in self.old {
+            context.delete_texture(texture);
+        }
+        context.delete_texture(self.new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIM: (u32, u32) = (23, 17);
+
+    /// Gradients, so that sampling between pixels only gets colors close to theirs
+    fn images() -> (Vec<u8>, Vec<u8>) {
+        let pixels = (0..DIM.1).flat_map(|y| (0..DIM.0).map(move |x| (x * 10, y * 14)));
+        let old = pixels
+            .clone()
+            .flat_map(|(x, y)| [x as u8, y as u8, 200, 255])
+            .collect();
+        let new = pixels.flat_map(|(x, y)| [y as u8, 50, x as u8]).collect();
+        (old, new)
+    }
+
+    /// How far apart the colors of `a` and `b` are, at most
+    fn max_diff(a: &[u8], b: &[u8]) -> u8 {
+        a.chunks_exact(4)
+            .zip(b.chunks_exact(4))
+            .flat_map(|(a, b)| (0..3).map(move |i| a[i].abs_diff(b[i])))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_draw_what_the_cpu_draws() {
+        let Ok(mut renderer) = Renderer::new() else {
+            eprintln!("no OpenGL ES here, skipping");
+            return;
+        };
+        let (old, new) = images();
+        let textures = Textures::new(&renderer, DIM, &[(7, old.clone().into())], &new);
+        let frames = [
+            (FullFrame::Slide(ArchivedDirection::Left), 0),
+            (FullFrame::Slide(ArchivedDirection::Right), 0),
+            (FullFrame::Slide(ArchivedDirection::Up), 0),
+            (FullFrame::Slide(ArchivedDirection::Down), 0),
+            // the GPU interpolates with less precision than we do
+            (FullFrame::ZoomOut([10, 20, 30]), 2),
+            (FullFrame::Burn((3.0, 5.0)), 1),
+        ];
+        for (frame, tolerance) in frames {
+            for progress in [0.0, 0.1, 0.3, 0.5, 0.62, 0.9, 1.0] {
+                let mut cpu = old.clone();
+                frame.draw(&mut cpu, &old, &new, DIM, progress);
+                let mut gpu = old.clone();
+                textures
+                    .draw(&mut renderer, 7, frame, progress, &mut gpu)
+                    .unwrap();
+                let diff = max_diff(&cpu, &gpu);
+                assert!(diff <= tolerance, "{progress}: off by {diff}");
+                // the alpha channel is left alone
+                assert!(gpu.chunks_exact(4).all(|pixel| pixel[3] == 255));
+            }
+        }
+
+        // nothing to draw on a canvas we have no old image of
+        let mut canvas = vec![1; old.len()];
+        textures
+            .draw(&mut renderer, 8, frames[0].0, 0.5, &mut canvas)
+            .unwrap();
+        assert!(canvas.iter().all(|col| *col == 1));
+        textures.delete(&renderer);
+    }
+}
@@ -0,0 +1,148 @@
+//! Optional authentication for IPC connections, and checking the client's protocol version.
+//!
+//! Every connection starts with a `Handshake`, carrying the protocol version of the client and
+//! the token it found in `$SWWW_AUTH_TOKEN` (which may be empty). We answer `VersionMismatch` to
+//! clients whose version we don't understand. If the daemon was started with `--ipc-auth-token`,
+//! the tokens must match, otherwise we answer `AuthFailed`. Either way, we close the connection
+//! after that. Tokens are never logged.
+//!
+//! The `swww` clients we spawn ourselves, to restore outputs or run slideshows, are given our
+//! token in their `$SWWW_AUTH_TOKEN`, so that we don't refuse them.
+
+use std::{os::unix::net::UnixStream, process::Command, sync::Mutex};
+
+use utils::ipc::{
+    is_compatible, read_handshake, version_str, Handshake, HandshakeAnswer, PROTOCOL_VERSION,
+};
+
+/// Our token, if we were started with `--ipc-auth-token`
+static TOKEN: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set_token(token: Option<String>) {
+    *TOKEN.lock().unwrap() = token;
+}
+
+pub fn token() -> Option<String> {
+    TOKEN.lock().unwrap().clone()
+}
+
+/// A command running `swww`, which authenticates with our token
+pub fn swww_command() -> Command {
+    command("swww", token().as_deref())
+}
+
+fn command(program: &str, token: Option<&str>) -> Command {
+    let mut command = Command::new(program);
+    if let Some(token) = token {
+        command.env("SWWW_AUTH_TOKEN", token);
+    }
+    command
+}
+
+/// Reads the client's handshake, and tells it whether it may go on
+pub fn authenticate(stream: &UnixStream, expected: Option<&str>) -> Result<(), String> {
+    let bytes = read_handshake(stream)?;
+    let handshake = match Handshake::receive(&bytes) {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            HandshakeAnswer::VersionMismatch.send(stream)?;
+            return Err(e);
+        }
+    };
+    if !is_compatible(PROTOCOL_VERSION, handshake.version) {
+        HandshakeAnswer::VersionMismatch.send(stream)?;
+        return Err(format!(
+            "client speaks protocol {}, but we speak {}",
+            version_str(handshake.version),
+            version_str(PROTOCOL_VERSION)
+        ));
+    }
+    let accepted = match expected {
+        Some(token) => tokens_match(handshake.token.as_bytes(), token.as_bytes()),
+        None => true,
+    };
+    if accepted {
+        HandshakeAnswer::Ok.send(stream)
+    } else {
+        HandshakeAnswer::AuthFailed.send(stream)?;
+        Err("client sent the wrong auth token".to_string())
+    }
+}
+
+/// Compares in constant time, so that we don't leak how much of the token a client got right
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns whether the daemon accepted the client, and what the client got as an answer
+    fn handshake(handshake: Handshake, daemon_token: Option<&str>) -> (bool, HandshakeAnswer) {
+        let (client, daemon) = UnixStream::pair().unwrap();
+        handshake.send(&client).unwrap();
+        let accepted = authenticate(&daemon, daemon_token).is_ok();
+        drop(daemon);
+        let bytes = read_handshake(&client).unwrap();
+        let (answer, version) = HandshakeAnswer::receive(&bytes).unwrap();
+        assert_eq!(version, PROTOCOL_VERSION);
+        (accepted, answer)
+    }
+
+    #[test]
+    fn should_reject_the_wrong_token() {
+        let rejected = (false, HandshakeAnswer::AuthFailed);
+        assert_eq!(handshake(Handshake::new("guess"), Some("secret")), rejected);
+        assert_eq!(handshake(Handshake::new(""), Some("secret")), rejected);
+        assert_eq!(
+            handshake(Handshake::new("secret2"), Some("secret")),
+            rejected
+        );
+    }
+
+    #[test]
+    fn should_accept_the_right_token() {
+        let accepted = (true, HandshakeAnswer::Ok);
+        assert_eq!(
+            handshake(Handshake::new("secret"), Some("secret")),
+            accepted
+        );
+        assert_eq!(handshake(Handshake::new(""), None), accepted);
+        assert_eq!(handshake(Handshake::new("anything"), None), accepted);
+    }
+
+    #[test]
+    fn should_reject_versions_we_dont_understand() {
+        let (major, minor) = PROTOCOL_VERSION;
+        let version = |version| Handshake {
+            version,
+            token: String::new(),
+        };
+        // like those from before handshakes had versions
+        assert!(Handshake::receive(b"\x05\0\0\0token").is_err());
+        let rejected = (false, HandshakeAnswer::VersionMismatch);
+        assert_eq!(handshake(version((major + 1, 0)), None), rejected);
+        assert_eq!(handshake(version((major, minor + 1)), None), rejected);
+        if minor > 0 {
+            assert_eq!(
+                handshake(version((major, minor - 1)), None),
+                (true, HandshakeAnswer::Ok)
+            );
+        }
+    }
+
+    #[test]
+    fn spawned_clients_should_authenticate_with_our_token() {
+        let (client, daemon) = UnixStream::pair().unwrap();
+        // the child finds the token where `swww` looks for it, and we send the handshake `swww`
+        // would with it
+        let output = command("sh", Some("secret"))
+            .args(["-c", "printf %s \"$SWWW_AUTH_TOKEN\""])
+            .output()
+            .unwrap();
+        let token = String::from_utf8(output.stdout).unwrap();
+        Handshake::new(&token).send(&client).unwrap();
+        assert!(authenticate(&daemon, Some("secret")).is_ok());
+    }
+}
@@ -0,0 +1,192 @@
+//! Saves power on battery, with `--battery-policy`.
+//!
+//! Every so often, we read the batteries in `/sys/class/power_supply`. While one of them is
+//! discharging, and their average charge is below `--battery-threshold`, the animations follow the
+//! policy: showing fewer frames, pausing, or going back to their first frame and pausing there.
+//! Once the system charges again (or is above the threshold), they play as usual.
+
+use log::{debug, error, info};
+
+use crate::Generation;
+
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatteryPolicy {
+    /// Show at most this many frames per second
+    ReduceFps(u32),
+    Pause,
+    /// Go back to the first frame, and pause there
+    FirstFrame,
+}
+
+static POLICY: RwLock<Option<BatteryPolicy>> = RwLock::new(None);
+static LOW_POWER: AtomicBool = AtomicBool::new(false);
+static THREADS: Generation = Generation::new();
+
+/// The policy the animations must follow right now, if any
+pub fn low_power_policy() -> Option<BatteryPolicy> {
+    if LOW_POWER.load(Ordering::Acquire) {
+        *POLICY.read().unwrap()
+    } else {
+        None
+    }
+}
+
+/// Starts following the batteries, replacing the policy we followed before, if any
+pub fn spawn(policy: BatteryPolicy, threshold: u8) {
+    let generation = {
+        let mut current = POLICY.write().unwrap();
+        *current = Some(policy);
+        THREADS.next()
+    };
+    if let Err(e) = thread::Builder::new()
+        .name("battery".to_string())
+        .stack_size(1 << 15)
+        .spawn(move || {
+            while THREADS.is_current(generation) {
+                let low = read_state(Path::new(POWER_SUPPLY_DIR))
+                    .is_some_and(|state| state.is_low(threshold));
+                let changed = {
+                    // a policy that replaced ours while we read must not see what we did
+                    let _current = POLICY.write().unwrap();
+                    if !THREADS.is_current(generation) {
+                        break;
+                    }
+                    LOW_POWER.swap(low, Ordering::AcqRel) != low
+                };
+                if changed {
+                    if low {
+                        info!("the battery is low, animations follow {policy:?}");
+                    } else {
+                        info!("the battery is no longer low, animations play as usual");
+                    }
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        })
+    {
+        error!("failed to spawn 'battery' thread: {e}");
+    }
+}
+
+/// Stops following the batteries, so that animations play as usual whatever their charge
+pub fn stop() {
+    let mut current = POLICY.write().unwrap();
+    THREADS.next();
+    *current = None;
+    LOW_POWER.store(false, Ordering::Release);
+}
+
+#[derive(Debug, PartialEq)]
+struct BatteryState {
+    discharging: bool,
+    /// The average charge of the batteries, in percent
+    capacity: u8,
+}
+
+impl BatteryState {
+    fn is_low(&self, threshold: u8) -> bool {
+        self.discharging && self.capacity < threshold
+    }
+}
+
+/// Reads the batteries in `dir`. Returns `None` if there are none
+fn read_state(dir: &Path) -> Option<BatteryState> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("failed to read {dir:?}: {e}");
+            return None;
+        }
+    };
+    let read = |supply: &Path, file| {
+        std::fs::read_to_string(supply.join(file))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    let (mut discharging, mut capacities) = (false, Vec::new());
+    for entry in entries.flatten() {
+        let supply = entry.path();
+        if read(&supply, "type") != "Battery" {
+            continue;
+        }
+        discharging |= read(&supply, "status") == "Discharging";
+        if let Ok(capacity) = read(&supply, "capacity").parse::<u32>() {
+            capacities.push(capacity.min(100));
+        }
+    }
+    if capacities.is_empty() {
+        return None;
+    }
+    let capacity = capacities.iter().sum::<u32>() / capacities.len() as u32;
+    Some(BatteryState {
+        discharging,
+        capacity: capacity as u8,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(dir: &Path, name: &str, files: &[(&str, &str)]) {
+        let supply = dir.join(name);
+        std::fs::create_dir_all(&supply).unwrap();
+        for (file, contents) in files {
+            std::fs::write(supply.join(file), format!("{contents}\n")).unwrap();
+        }
+    }
+
+    #[test]
+    fn should_read_the_average_charge_of_the_batteries() {
+        let dir = std::env::temp_dir().join(format!("swww-battery-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(read_state(&dir), None);
+
+        supply(&dir, "AC", &[("type", "Mains"), ("online", "0")]);
+        assert_eq!(read_state(&dir), None);
+
+        let battery = |status, capacity| {
+            [
+                ("type", "Battery"),
+                ("status", status),
+                ("capacity", capacity),
+            ]
+        };
+        supply(&dir, "BAT0", &battery("Discharging", "10"));
+        supply(&dir, "BAT1", &battery("Full", "30"));
+        let state = read_state(&dir).unwrap();
+        assert_eq!(
+            state,
+            BatteryState {
+                discharging: true,
+                capacity: 20
+            }
+        );
+        assert!(state.is_low(25));
+        assert!(!state.is_low(20));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn charging_should_never_be_low() {
+        let state = BatteryState {
+            discharging: false,
+            capacity: 5,
+        };
+        assert!(!state.is_low(100));
+    }
+}
@@ -0,0 +1,239 @@
+//! The buffers every wallpaper draws to, see [`BufferPool`].
+//!
+//! Every wallpaper has a pool of shared memory of its own, with up to [`MAX_BUFFERS`] buffers of
+//! the same size in it, which we take turns drawing to: the compositor reads from the one we last
+//! committed, while we draw the next frame to another. Once the compositor releases a buffer, we
+//! draw to it again, instead of creating new ones, so that animating doesn't keep mapping, and
+//! faulting in, memory the kernel only just gave us. The pool only ever grows, when the wallpaper
+//! does, and is only freed along with the wallpaper.
+//!
+//! Animations only send us what changed from one frame to the next, and we unpack that straight
+//! into the buffer we are about to commit. So every buffer we hand out starts with what we drew
+//! to the last one, copied over from it when it isn't the same buffer.
+
+use log::debug;
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use smithay_client_toolkit::shm::{raw::RawPool, CreatePoolError, Shm};
+
+use wayland_client::{
+    protocol::{wl_buffer::WlBuffer, wl_shm},
+    Connection, Dispatch, QueueHandle,
+};
+
+use crate::{
+    surface::{Buffer, Pool},
+    Daemon,
+};
+
+/// How many buffers we keep around. Compositors rarely hold on to more than two at once, the one
+/// they are showing and the one we just committed, so a third one lets us draw without waiting
+pub const MAX_BUFFERS: usize = 3;
+
+/// What every buffer in a pool looks like. Changing it throws all of them away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    width: i32,
+    height: i32,
+    stride: i32,
+    format: wl_shm::Format,
+}
+
+impl Layout {
+    fn len(&self) -> usize {
+        self.stride as usize * self.height as usize
+    }
+}
+
+struct Slot {
+    buffer: WlBuffer,
+    /// Whether the compositor is done reading from the buffer. Cleared when we commit it, and set
+    /// again when the compositor releases it
+    released: Arc<AtomicBool>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+/// Which slot [`BufferPool::get`] hands out
+#[derive(Debug, PartialEq, Eq)]
+enum Pick {
+    /// It already has what we drew last
+    Last(usize),
+    /// It must get a copy of what we drew last first
+    Copy(usize),
+    /// Every slot is still in use, so we need a new one
+    New,
+}
+
+/// Prefers the slot we drew to last, then any other slot the compositor released
+fn pick(released: impl Iterator<Item = bool>, last: Option<usize>) -> Pick {
+    let mut free = None;
+    for (i, released) in released.enumerate() {
+        if !released {
+            continue;
+        }
+        if Some(i) == last {
+            return Pick::Last(i);
+        }
+        free = free.or(Some(i));
+    }
+    free.map_or(Pick::New, Pick::Copy)
+}
+
+pub struct BufferPool {
+    pool: RawPool,
+    layout: Option<Layout>,
+    slots: Vec<Slot>,
+    /// The slot with what we drew last, if we drew anything since the layout last changed
+    last: Option<usize>,
+    qh: QueueHandle<Daemon>,
+}
+
+impl BufferPool {
+    pub fn new(shm: &Shm, qh: QueueHandle<Daemon>) -> Result<Self, CreatePoolError> {
+        Ok(Self {
+            pool: RawPool::new(4096, shm)?,
+            layout: None,
+            slots: Vec::new(),
+            last: None,
+            qh,
+        })
+    }
+}
+
+impl Pool for BufferPool {
+    fn get(
+        &mut self,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: wl_shm::Format,
+    ) -> Result<(Buffer, &mut [u8]), String> {
+        let layout = Layout {
+            width,
+            height,
+            stride,
+            format,
+        };
+        if self.layout != Some(layout) {
+            // the compositor only reads the ones it still holds until our next commit, which
+            // will be of a new one
+            self.clear();
+            self.layout = Some(layout);
+        }
+        // we only make more than `MAX_BUFFERS` while the compositor holds on to all of them, so
+        // we get rid of them again once it lets go
+        while self.slots.len() > MAX_BUFFERS
+            && self.last != Some(self.slots.len() - 1)
+            && self.slots.last().unwrap().released.load(Ordering::Acquire)
+        {
+            self.slots.pop();
+        }
+
+        let len = layout.len();
+        let i = match pick(
+            self.slots
+                .iter()
+                .map(|s| s.released.load(Ordering::Acquire)),
+            self.last,
+        ) {
+            Pick::Last(i) | Pick::Copy(i) => i,
+            Pick::New => {
+                let i = self.slots.len();
+                if i >= MAX_BUFFERS {
+                    debug!("the compositor holds on to all of our {i} buffers, making another");
+                }
+                self.pool
+                    .resize((i + 1) * len)
+                    .map_err(|e| format!("failed to grow the buffer pool: {e}"))?;
+                let released = Arc::new(AtomicBool::new(true));
+                let buffer = self.pool.create_buffer(
+                    (i * len) as i32,
+                    width,
+                    height,
+                    stride,
+                    format,
+                    Arc::clone(&released),
+                    &self.qh,
+                );
+                self.slots.push(Slot { buffer, released });
+                i
+            }
+        };
+        match self.last {
+            Some(last) if last != i => {
+                self.pool
+                    .mmap()
+                    .copy_within(last * len..(last + 1) * len, i * len);
+            }
+            _ => (),
+        }
+        self.last = Some(i);
+        let buffer = Buffer::Wayland(self.slots[i].buffer.clone());
+        Ok((buffer, &mut self.pool.mmap()[i * len..][..len]))
+    }
+
+    /// The compositor releases the buffer once it is done reading from it
+    fn commit(&self, buffer: &Buffer) {
+        let Buffer::Wayland(buffer) = buffer else {
+            return;
+        };
+        if let Some(slot) = self.slots.iter().find(|s| s.buffer == *buffer) {
+            slot.released.store(false, Ordering::Release);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.last = None;
+        self.layout = None;
+    }
+
+    /// Of shared memory
+    fn mapped_bytes(&self) -> usize {
+        self.pool.len()
+    }
+
+    fn last_drawn(&mut self) -> Option<&[u8]> {
+        let len = self.layout?.len();
+        let last = self.last?;
+        Some(&self.pool.mmap()[last * len..][..len])
+    }
+}
+
+impl Dispatch<WlBuffer, Arc<AtomicBool>> for Daemon {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlBuffer,
+        _event: <WlBuffer as wayland_client::Proxy>::Event,
+        released: &Arc<AtomicBool>,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wl_buffer only has the release event
+        released.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_prefer_the_buffer_we_drew_to_last() {
+        let released = [true, true, false];
+        assert_eq!(pick(released.into_iter(), Some(1)), Pick::Last(1));
+        assert_eq!(pick(released.into_iter(), Some(2)), Pick::Copy(0));
+        assert_eq!(pick(released.into_iter(), None), Pick::Copy(0));
+        assert_eq!(pick([false, false].into_iter(), Some(0)), Pick::New);
+        assert_eq!(pick([].into_iter(), None), Pick::New);
+    }
+}
@@ -0,0 +1,925 @@
+//! Command line arguments for the daemon.
+//!
+//! We don't use clap here because the daemon has very few options, and we want to keep its
+//! dependencies (and binary size) small.
+
+use std::{os::unix::net::UnixStream, path::PathBuf, time::Duration};
+
+use nix::sys::signal::Signal;
+use smithay_client_toolkit::shell::wlr_layer::Layer;
+use utils::outputs::OutputPattern;
+
+use crate::{
+    animations::{FrameDropRecovery, InterruptPolicy},
+    battery::BatteryPolicy,
+    logging,
+    placement::Placement,
+    schedule::Schedule,
+    session_lock,
+    shm_format::ShmFormat,
+};
+
+const USAGE: &str = "swww-daemon
+
+Options:
+
+    --anchor <top-left|top|top-right|left|center|right|bottom-left|bottom|bottom-right>
+        Where on the output wallpapers with a --size go. Defaults to 'center'.
+
+    --animate-occluded
+        Keep animating outputs that a fullscreen window covers. By default, if the compositor
+        supports the wlr_foreign_toplevel_management_v1 protocol, we stop until they show again.
+
+    --battery-policy <reduce-fps|pause|first-frame>
+        What animations do while the battery is discharging, and below --battery-threshold.
+        'reduce-fps' shows at most --battery-fps frames per second, 'pause' freezes them, and
+        'first-frame' takes them back to their first frame, and freezes them there. We read the
+        batteries from /sys/class/power_supply every 30 seconds.
+
+    --battery-threshold <percent>
+        The charge below which --battery-policy applies. Defaults to 30.
+
+    --battery-fps <fps>
+        How many frames per second animations show with '--battery-policy reduce-fps'. Defaults
+        to 10.
+
+    --heartbeat-socket <path>
+        Every second, send a JSON line with the daemon's status to the unix datagram socket at
+        <path>. Useful for external monitoring tools.
+
+    --frame-drop-recovery <warp|hold>
+        What to do when an animation frame takes longer than its delay to show. 'warp' (the
+        default) skips the frames we missed, to keep the animation in sync with the clock. 'hold'
+        shows every frame anyway, letting the animation fall behind.
+
+    --gc-interval <seconds>
+        Every <seconds>, free the buffers of outputs that no longer exist. Outputs are only freed
+        on the second pass after they go away, so that we never free anything the compositor might
+        still be reading from. `swww query --verbose` reports how much memory was freed so far.
+
+    --journald
+        Send the logs to the systemd journal too.
+
+    --idle-priority
+        Only process frames when nothing else wants the CPU, with the SCHED_IDLE scheduling
+        policy. `swww img` follows it too.
+
+    --ipc-auth-token <token>
+        Only accept requests from clients that send this token. Clients read it from
+        $SWWW_AUTH_TOKEN, which the clients we run ourselves are given. Note other users may see
+        the command lines of your processes.
+
+    --layer <background|bottom|top|overlay>
+        The layer-shell layer to put the wallpapers on. Defaults to 'background'. On 'top' and
+        'overlay', images are drawn over windows, which is useful to display them as an overlay.
+
+    --layer-namespace <namespace>
+        The namespace of the wallpapers' layer surfaces, which compositors may use to give them
+        rules of their own. '{output}' is replaced with the name of the output, to tell them apart.
+        Defaults to 'swww'.
+
+    --lock-file <path>
+        Hold an exclusive lock on the file at <path> while running. If another daemon already
+        holds it, exit immediately with an error.
+
+    --lock-signals <lock>,<unlock>
+        Take the session as locked when we get the signal <lock>, like USR1, and as unlocked
+        when we get <unlock>, like USR2. Animations pause while it is locked.
+
+    --log-file <path>
+        Append the logs to the file at <path> too.
+
+    --log-level <filter>
+        Which logs to keep, like 'info,animations=debug': a level for every module, and levels
+        for single modules. The levels are 'off', 'error', 'warn', 'info', 'debug' and 'trace'.
+        Defaults to 'info' ('debug' in debug builds). `swww log --level` changes it while we run.
+
+    --margin <pixels|top,right,bottom,left>
+        Keep wallpapers this many logical pixels away from the edges of the output. Wallpapers
+        without a --size cover the rest of it.
+
+    --max-fps <fps|output=fps,...>
+        Show at most <fps> frames per second, on every output, or, before an '=', on the outputs
+        that match a pattern, like `--outputs` takes, such as 'eDP-1=30,DP-*=60'. An <fps> without
+        a pattern is for the other outputs. Animations skip the frames over it, and transitions
+        play at it. `swww img` drops those frames before sending them.
+
+    --memory-limit <MiB>
+        Keep the frames of all running animations under <MiB> mebibytes. The frames that don't
+        fit in what the others leave are written to the cache directory and read back from there
+        as they play, so that they only take the memory the system can spare. Animations that
+        play are never stopped to make room.
+
+    --metrics-backend statsd --statsd-host <host:port>
+        Every second, push frame timing metrics to the StatsD server at <host:port>, over UDP.
+        StatsD is the only backend we support for now.
+
+    --namespace <name>
+        Run in the namespace <name>, with a socket and a cache of its own, so that several daemons
+        can run at once, like one per wayland session. Clients talk to the daemon of the namespace
+        in $SWWW_NAMESPACE, or the one given with `swww --namespace`. Defaults to
+        $SWWW_NAMESPACE, if set. The processes we spawn, like `swww img`, run in it too.
+
+    --nice <0-19>
+        Process frames with threads of this niceness, so that the rest of the desktop gets the CPU
+        first. `swww img` follows it too.
+
+    --no-restore
+        Don't display the last image each output had when the daemon starts, or when an output
+        comes back. Restoring needs the `swww` client to be in $PATH.
+
+    --parallax <pixels>
+        Shift the wallpapers up to this many pixels away from the pointer as it moves over the
+        desktop, for a subtle sense of depth. Images are made this many logical pixels larger on
+        every side, for the shift to show. Implies --track-cursor, and so needs what it needs.
+
+    --prefer-compositor-scaling
+        On outputs with a scale factor, draw wallpapers at the output's logical size, and let the
+        compositor scale them up. This uses less memory, and makes transitions cheaper, at the
+        cost of a blurrier image.
+
+    --renderer <cpu|gpu>
+        Where to draw the transitions that redraw every pixel every frame ('slide', 'zoom-out' and
+        'burn'), and rescale what mirrors copy. 'cpu' (the default) does it with our threads.
+        'gpu' uploads the images as textures and does it with OpenGL ES, through EGL, falling back
+        to the CPU wherever that fails. The other transitions only change a few pixels every
+        frame, and stay on the CPU. Needs the daemon to be built with the `gpu` feature.
+
+    --script <path>
+        Spawn the executable at <path>, send it events on its stdin, and run the commands it
+        writes to its stdout. See swww-daemon(1) for the format of both.
+
+    --shm-format <xrgb8888|xrgb2101010|rgb565>
+        The pixel format to send opaque buffers to the compositor in. 'xrgb8888' (the default)
+        is what images are processed in. 'xrgb2101010' is for compositors that would rather get
+        10 bits per channel, but only holds the same 8, so gradients band just as much. 'rgb565'
+        halves the memory the compositor needs for our buffers. Both cost a conversion every time
+        we draw. If the compositor doesn't support the format,
+        we fall back to 'xrgb8888'.
+
+    --size <width>x<height>
+        Make wallpapers this many logical pixels wide and high, instead of covering the whole
+        output, so that they only take part of it, like an overlay image. Images are made for
+        this size, instead of the output's.
+
+    --threads <n>
+        Process frames with at most <n> threads. Defaults to one per core. `swww img` follows it
+        too.
+
+    --track-cursor
+        Keep track of where the pointer is, for transitions started with `--transition-pos cursor`.
+        This needs the compositor to support the cursor_shape_v1 protocol, and makes the wallpapers
+        receive the clicks on the desktop. We only see the pointer while it is over the desktop, so
+        the transitions start from where it was last seen there.
+
+    --transition-interrupt-policy <keep|restart|skip>
+        What to do when a new image arrives while a transition is still playing. 'keep' lets it
+        finish, and plays the new transition after it. 'restart' (the default) transitions to the
+        new image from wherever the old transition was. 'skip' cuts straight to the new image.
+        With 'keep', every image waits like with `swww img --queue`.
+
+    --wayland-backend <auto|manual>
+        How to find the wayland server. 'auto' (the default) looks at $WAYLAND_DISPLAY and
+        $WAYLAND_SOCKET, like every other wayland client, and, with the x11 feature, draws on
+        the X root window if neither is set but $DISPLAY is. 'manual' connects to the socket
+        given with --wayland-socket, ignoring the environment.
+
+    --wayland-socket <path>
+        The wayland socket to connect to. Requires --wayland-backend manual.
+
+    --render-transition
+        Don't connect to the wayland server. Instead, read a serialized transition preview from
+        stdin, and write every frame of it to stdout. This is used by `swww transition-preview`.
+
+    -h, --help
+        Print this help message and exit
+
+Every option may also be set in the [daemon] section of $XDG_CONFIG_HOME/swww/config.toml, like
+`gc-interval = 60`, or `track-cursor = true`. The command line overrides the file, and
+`swww reload-config` applies the file again while we run.
+";
+
+/// `--max-fps`: at most how many frames per second outputs show
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaxFps {
+    /// For the outputs none of `outputs` match
+    pub default: Option<u32>,
+    /// Patterns, like `--outputs` takes, and the ceiling of the outputs they match. The first
+    /// one that matches wins
+    pub outputs: Vec<(OutputPattern, u32)>,
+}
+
+impl MaxFps {
+    fn parse(s: &str) -> Result<Self, String> {
+        let fps = |fps: &str| match fps.trim().parse::<u32>() {
+            Ok(fps) if fps > 0 => Ok(fps),
+            _ => Err(format!("must be a positive number of frames, got: {fps}")),
+        };
+        let mut max_fps = Self::default();
+        for entry in s.split(',') {
+            match entry.rsplit_once('=') {
+                Some((pattern, value)) => {
+                    let pattern = OutputPattern::new(pattern)?;
+                    max_fps.outputs.push((pattern, fps(value)?));
+                }
+                None => max_fps.default = Some(fps(entry)?),
+            }
+        }
+        Ok(max_fps)
+    }
+
+    /// The ceiling of the output called `name`
+    pub fn of(&self, name: &str) -> Option<u32> {
+        self.outputs
+            .iter()
+            .find(|(pattern, _)| pattern.matches(name))
+            .map_or(self.default, |(_, fps)| Some(*fps))
+    }
+}
+
+/// How we find the wayland server to connect to
+#[derive(Debug, Default, PartialEq)]
+pub enum WaylandBackend {
+    /// Through the environment
+    #[default]
+    Auto,
+    /// Through a socket given by the user
+    Manual(PathBuf),
+}
+
+impl WaylandBackend {
+    /// Connects to the socket given by the user. `None` means we must connect through the
+    /// environment instead
+    pub fn connect(&self) -> Result<Option<UnixStream>, String> {
+        match self {
+            Self::Auto => Ok(None),
+            Self::Manual(path) => match UnixStream::connect(path) {
+                Ok(stream) => Ok(Some(stream)),
+                Err(e) => Err(format!("failed to connect to wayland socket {path:?}: {e}")),
+            },
+        }
+    }
+}
+
+/// `--renderer`: what draws the frames that are worth drawing on the GPU
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Renderer {
+    #[default]
+    Cpu,
+    Gpu,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Cli {
+    pub animate_occluded: bool,
+    pub placement: Placement,
+    /// And the battery charge, in percent, below which it applies
+    pub battery_policy: Option<(BatteryPolicy, u8)>,
+    pub heartbeat_socket: Option<PathBuf>,
+    pub idle_priority: bool,
+    pub frame_drop_recovery: FrameDropRecovery,
+    pub gc_interval: Option<Duration>,
+    pub ipc_auth_token: Option<String>,
+    pub journald: bool,
+    /// `None` is the background
+    pub layer: Option<Layer>,
+    pub layer_namespace: Option<String>,
+    pub lock_file: Option<PathBuf>,
+    /// The signal that locks the session, and the one that unlocks it
+    pub lock_signals: Option<(Signal, Signal)>,
+    pub log_file: Option<PathBuf>,
+    pub log_filter: logging::Filter,
+    pub max_fps: MaxFps,
+    /// In bytes
+    pub memory_limit: Option<usize>,
+    pub namespace: Option<String>,
+    /// From 0 to `utils::cpu::MAX_NICE`
+    pub nice: Option<u8>,
+    pub statsd_host: Option<String>,
+    pub no_restore: bool,
+    /// How many logical pixels past every edge of the outputs `--parallax` shifts the wallpapers by
+    pub parallax: Option<u32>,
+    pub prefer_compositor_scaling: bool,
+    pub renderer: Renderer,
+    pub shm_format: ShmFormat,
+    pub script: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub track_cursor: bool,
+    pub transition_interrupt_policy: InterruptPolicy,
+    pub wayland_backend: WaylandBackend,
+    pub render_transition: bool,
+    /// From the `[schedule]` section of the configuration file, not from the arguments
+    pub schedule: Option<Schedule>,
+}
+
+impl Cli {
+    /// Parses the `[daemon]` section of the configuration file, and then the arguments, so that
+    /// the arguments override the file. Also reads the `[schedule]`
+    pub fn with_config(args: &[String]) -> Result<Self, String> {
+        let config = utils::config::Config::read()?;
+        let mut cli = Self::new(
+            config
+                .args("daemon")
+                .into_iter()
+                .chain(args.iter().cloned()),
+        )?;
+        let path = utils::config::path();
+        let dir = path.as_deref().and_then(std::path::Path::parent);
+        cli.schedule = Schedule::parse(&config.entries("schedule"), dir)
+            .map_err(|e| format!("in [schedule]: {e}"))?;
+        Ok(cli)
+    }
+
+    /// Parses the arguments, *excluding* the program name
+    pub fn new(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut cli = Self::default();
+        let mut manual_backend = false;
+        let mut wayland_socket = None;
+        let mut statsd_backend = false;
+        let mut anchored = false;
+        let (mut battery_policy, mut battery_threshold, mut battery_fps) = (None, None, None);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--anchor" => {
+                    cli.placement.position = value_of(&arg, args.next())?
+                        .parse()
+                        .map_err(|e| format!("{arg} {e}"))?;
+                    anchored = true;
+                }
+                "--battery-policy" => {
+                    battery_policy = Some(match value_of(&arg, args.next())?.as_str() {
+                        "reduce-fps" => BatteryPolicy::ReduceFps(DEFAULT_BATTERY_FPS),
+                        "pause" => BatteryPolicy::Pause,
+                        "first-frame" => BatteryPolicy::FirstFrame,
+                        other => {
+                            return Err(format!(
+                                "{arg} must be 'reduce-fps', 'pause' or 'first-frame', got: {other}"
+                            ))
+                        }
+                    })
+                }
+                "--battery-threshold" => {
+                    let value = value_of(&arg, args.next())?;
+                    match value.parse::<u8>() {
+                        Ok(percent) if (1..=100).contains(&percent) => {
+                            battery_threshold = Some(percent)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "{arg} must be a percentage from 1 to 100, got: {value}"
+                            ))
+                        }
+                    }
+                }
+                "--battery-fps" => {
+                    let value = value_of(&arg, args.next())?;
+                    match value.parse::<u32>() {
+                        Ok(fps) if fps > 0 => battery_fps = Some(fps),
+                        _ => {
+                            return Err(format!(
+                                "{arg} must be a positive number of frames, got: {value}"
+                            ))
+                        }
+                    }
+                }
+                "--heartbeat-socket" => {
+                    cli.heartbeat_socket = Some(value_of(&arg, args.next())?.into());
+                }
+                "--frame-drop-recovery" => {
+                    cli.frame_drop_recovery = match value_of(&arg, args.next())?.as_str() {
+                        "warp" => FrameDropRecovery::Warp,
+                        "hold" => FrameDropRecovery::Hold,
+                        other => {
+                            return Err(format!(
+                                "{arg} must be either 'warp' or 'hold', got: {other}"
+                            ))
+                        }
+                    }
+                }
+                "--gc-interval" => {
+                    let value = value_of(&arg, args.next())?;
+                    match value.parse::<u64>() {
+                        Ok(secs) if secs > 0 => cli.gc_interval = Some(Duration::from_secs(secs)),
+                        _ => {
+                            return Err(format!(
+                                "{arg} must be a positive number of seconds, got: {value}"
+                            ))
+                        }
+                    }
+                }
+                "--ipc-auth-token" => {
+                    let token = value_of(&arg, args.next())?;
+                    if token.is_empty() {
+                        return Err(format!("{arg} must not be empty"));
+                    }
+                    cli.ipc_auth_token = Some(token);
+                }
+                "--idle-priority" => cli.idle_priority = true,
+                "--journald" => cli.journald = true,
+                "--layer" => {
+                    cli.layer = Some(match value_of(&arg, args.next())?.as_str() {
+                        "background" => Layer::Background,
+                        "bottom" => Layer::Bottom,
+                        "top" => Layer::Top,
+                        "overlay" => Layer::Overlay,
+                        other => {
+                            return Err(format!(
+                                "{arg} must be 'background', 'bottom', 'top' or 'overlay', got: \
+                                 {other}"
+                            ))
+                        }
+                    })
+                }
+                "--layer-namespace" => {
+                    let namespace = value_of(&arg, args.next())?;
+                    if namespace.is_empty() {
+                        return Err(format!("{arg} must not be empty"));
+                    }
+                    cli.layer_namespace = Some(namespace);
+                }
+                "--lock-file" => cli.lock_file = Some(value_of(&arg, args.next())?.into()),
+                "--lock-signals" => {
+                    let signals = value_of(&arg, args.next())?;
+                    cli.lock_signals = Some(
+                        session_lock::parse_signals(&signals).map_err(|e| format!("{arg}: {e}"))?,
+                    );
+                }
+                "--log-file" => cli.log_file = Some(value_of(&arg, args.next())?.into()),
+                "--log-level" => {
+                    cli.log_filter = value_of(&arg, args.next())?
+                        .parse()
+                        .map_err(|e| format!("{arg}: {e}"))?;
+                }
+                "--margin" => {
+                    cli.placement.margin =
+                        utils::placement::parse_margin(&value_of(&arg, args.next())?)
+                            .map_err(|e| format!("{arg} {e}"))?;
+                }
+                "--max-fps" => {
+                    cli.max_fps = MaxFps::parse(&value_of(&arg, args.next())?)
+                        .map_err(|e| format!("{arg} {e}"))?;
+                }
+                "--memory-limit" => {
+                    let value = value_of(&arg, args.next())?;
+                    match value.parse::<usize>() {
+                        Ok(mib) if mib > 0 => cli.memory_limit = Some(mib.saturating_mul(1 << 20)),
+                        _ => {
+                            return Err(format!(
+                                "{arg} must be a positive number of MiB, got: {value}"
+                            ))
+                        }
+                    }
+                }
+                "--namespace" => {
+                    let namespace = value_of(&arg, args.next())?;
+                    utils::ipc::check_namespace(&namespace).map_err(|e| format!("{arg}: {e}"))?;
+                    cli.namespace = Some(namespace);
+                }
+                "--metrics-backend" => match value_of(&arg, args.next())?.as_str() {
+                    "statsd" => statsd_backend = true,
+                    other => return Err(format!("{arg} must be 'statsd', got: {other}")),
+                },
+                "--statsd-host" => cli.statsd_host = Some(value_of(&arg, args.next())?),
+                "--nice" => {
+                    let value = value_of(&arg, args.next())?;
+                    match value.parse::<u8>() {
+                        Ok(nice) if nice <= utils::cpu::MAX_NICE => cli.nice = Some(nice),
+                        _ => {
+                            return Err(format!(
+                                "{arg} must be a niceness from 0 to {}, got: {value}",
+                                utils::cpu::MAX_NICE
+                            ))
+                        }
+                    }
+                }
+                "--no-restore" => cli.no_restore = true,
+                "--animate-occluded" => cli.animate_occluded = true,
+                "--parallax" => {
+                    let value = value_of(&arg, args.next())?;
+                    match value.parse::<u32>() {
+                        Ok(pixels) if pixels > 0 => {
+                            cli.parallax = Some(pixels);
+                            cli.track_cursor = true;
+                        }
+                        _ => {
+                            return Err(format!(
+                                "{arg} must be a positive number of pixels, got: {value}"
+                            ))
+                        }
+                    }
+                }
+                "--prefer-compositor-scaling" => cli.prefer_compositor_scaling = true,
+                "--renderer" => {
+                    cli.renderer = match value_of(&arg, args.next())?.as_str() {
+                        "cpu" => Renderer::Cpu,
+                        "gpu" if cfg!(feature = "gpu") => Renderer::Gpu,
+                        "gpu" => {
+                            return Err(format!(
+                                "{arg} gpu needs swww-daemon to be built with the `gpu` feature"
+                            ))
+                        }
+                        other => return Err(format!("{arg} must be 'cpu' or 'gpu', got: {other}")),
+                    }
+                }
+                "--script" => cli.script = Some(value_of(&arg, args.next())?.into()),
+                "--shm-format" => {
+                    cli.shm_format = value_of(&arg, args.next())?
+                        .parse()
+                        .map_err(|e| format!("{arg} {e}"))?;
+                }
+                "--size" => {
+                    cli.placement.size = Some(
+                        utils::placement::parse_size(&value_of(&arg, args.next())?)
+                            .map_err(|e| format!("{arg} {e}"))?,
+                    );
+                }
+                "--threads" => {
+                    let value = value_of(&arg, args.next())?;
+                    match value.parse::<usize>() {
+                        Ok(threads) if threads > 0 => cli.threads = Some(threads),
+                        _ => {
+                            return Err(format!(
+                                "{arg} must be a positive number of threads, got: {value}"
+                            ))
+                        }
+                    }
+                }
+                "--track-cursor" => cli.track_cursor = true,
+                "--transition-interrupt-policy" => {
+                    cli.transition_interrupt_policy = match value_of(&arg, args.next())?.as_str() {
+                        "keep" => InterruptPolicy::Keep,
+                        "restart" => InterruptPolicy::Restart,
+                        "skip" => InterruptPolicy::Skip,
+                        other => {
+                            return Err(format!(
+                                "{arg} must be 'keep', 'restart' or 'skip', got: {other}"
+                            ))
+                        }
+                    }
+                }
+                "--wayland-backend" => {
+                    manual_backend = match value_of(&arg, args.next())?.as_str() {
+                        "auto" => false,
+                        "manual" => true,
+                        other => {
+                            return Err(format!(
+                                "{arg} must be either 'auto' or 'manual', got: {other}"
+                            ))
+                        }
+                    }
+                }
+                "--wayland-socket" => wayland_socket = Some(value_of(&arg, args.next())?.into()),
+                "--render-transition" => cli.render_transition = true,
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => {
+                    return Err(format!(
+                        "unrecognized command line argument: {arg}\n\
+                         Run -h|--help to know what arguments are recognized!"
+                    ))
+                }
+            }
+        }
+        cli.wayland_backend = match (manual_backend, wayland_socket) {
+            (false, None) => WaylandBackend::Auto,
+            (true, Some(path)) => WaylandBackend::Manual(path),
+            (true, None) => return Err("--wayland-backend manual requires --wayland-socket".into()),
+            (false, Some(_)) => {
+                return Err("--wayland-socket requires --wayland-backend manual".into())
+            }
+        };
+        cli.battery_policy = match (battery_policy, battery_fps) {
+            (None, _) if battery_threshold.is_some() || battery_fps.is_some() => {
+                return Err("--battery-threshold and --battery-fps require --battery-policy".into())
+            }
+            (None, _) => None,
+            (Some(BatteryPolicy::ReduceFps(_)), Some(fps)) => Some(BatteryPolicy::ReduceFps(fps)),
+            (Some(_), Some(_)) => {
+                return Err("--battery-fps requires --battery-policy reduce-fps".into())
+            }
+            (Some(policy), None) => Some(policy),
+        }
+        .map(|policy| {
+            (
+                policy,
+                battery_threshold.unwrap_or(DEFAULT_BATTERY_THRESHOLD),
+            )
+        });
+        if anchored && cli.placement.size.is_none() {
+            return Err("--anchor requires --size".into());
+        }
+        if statsd_backend != cli.statsd_host.is_some() {
+            return Err("--metrics-backend statsd and --statsd-host require each other".into());
+        }
+        Ok(cli)
+    }
+}
+
+const DEFAULT_BATTERY_THRESHOLD: u8 = 30;
+const DEFAULT_BATTERY_FPS: u32 = 10;
+
+fn value_of(arg: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("{arg} requires a value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::new(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn should_parse_placement() {
+        let cli = parse(&[
+            "--anchor",
+            "top-right",
+            "--margin",
+            "20",
+            "--size",
+            "800x600",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.placement,
+            Placement {
+                position: crate::placement::Position::TopRight,
+                margin: [20; 4],
+                size: Some((800, 600)),
+            }
+        );
+        assert!(parse(&["--anchor", "top-right"]).is_err());
+        assert!(parse(&["--anchor", "middle", "--size", "800x600"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_layer() {
+        let cli = parse(&["--layer", "overlay", "--layer-namespace", "swww-{output}"]).unwrap();
+        assert_eq!(cli.layer, Some(Layer::Overlay));
+        assert_eq!(cli.layer_namespace.as_deref(), Some("swww-{output}"));
+        assert!(parse(&["--layer", "front"]).is_err());
+        assert!(parse(&["--layer-namespace", ""]).is_err());
+    }
+
+    #[test]
+    fn should_parse_namespace() {
+        let cli = parse(&["--namespace", "nested"]).unwrap();
+        assert_eq!(cli.namespace.as_deref(), Some("nested"));
+        assert!(parse(&["--namespace", "a/b"]).is_err());
+        assert!(parse(&["--namespace"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_cpu_limits() {
+        let cli = parse(&[
+            "--threads",
+            "2",
+            "--nice",
+            "10",
+            "--idle-priority",
+            "--max-fps",
+            "eDP-1=30,DP-*=60,75",
+        ])
+        .unwrap();
+        assert_eq!(
+            (cli.threads, cli.nice, cli.idle_priority),
+            (Some(2), Some(10), true)
+        );
+        assert_eq!(cli.max_fps.of("eDP-1"), Some(30));
+        assert_eq!(cli.max_fps.of("DP-2"), Some(60));
+        assert_eq!(cli.max_fps.of("HDMI-A-1"), Some(75));
+        assert_eq!(
+            parse(&["--max-fps", "DP-*=60"])
+                .unwrap()
+                .max_fps
+                .of("eDP-1"),
+            None
+        );
+        assert!(parse(&["--threads", "0"]).is_err());
+        assert!(parse(&["--nice", "20"]).is_err());
+        assert!(parse(&["--max-fps", "DP-1=0"]).is_err());
+        assert!(parse(&["--max-fps", "re:(=30"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_logging() {
+        let cli = parse(&[
+            "--log-level",
+            "warn,animations=trace",
+            "--log-file",
+            "/tmp/swww.log",
+            "--journald",
+        ])
+        .unwrap();
+        assert_eq!(cli.log_filter, "warn,animations=trace".parse().unwrap());
+        assert_eq!(cli.log_file, Some(PathBuf::from("/tmp/swww.log")));
+        assert!(cli.journald);
+        assert!(parse(&["--log-level", "loud"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_lock_signals() {
+        let cli = parse(&["--lock-signals", "USR1,USR2"]).unwrap();
+        assert_eq!(cli.lock_signals, Some((Signal::SIGUSR1, Signal::SIGUSR2)));
+        assert!(parse(&["--lock-signals", "USR1"]).is_err());
+        assert!(parse(&["--lock-signals"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_heartbeat_socket() {
+        let cli = parse(&["--heartbeat-socket", "/tmp/heartbeat"]).unwrap();
+        assert_eq!(cli.heartbeat_socket, Some(PathBuf::from("/tmp/heartbeat")));
+    }
+
+    #[test]
+    fn should_parse_script() {
+        let cli = parse(&["--script", "/tmp/wallpapers.sh"]).unwrap();
+        assert_eq!(cli.script, Some(PathBuf::from("/tmp/wallpapers.sh")));
+    }
+
+    #[test]
+    fn should_parse_gc_interval() {
+        let cli = parse(&["--gc-interval", "30"]).unwrap();
+        assert_eq!(cli.gc_interval, Some(Duration::from_secs(30)));
+        assert!(parse(&["--gc-interval", "0"]).is_err());
+        assert!(parse(&["--gc-interval", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parallax_should_track_the_cursor() {
+        let cli = parse(&["--parallax", "12"]).unwrap();
+        assert_eq!(cli.parallax, Some(12));
+        assert!(cli.track_cursor);
+        assert!(parse(&["--parallax", "0"]).is_err());
+        assert_eq!(parse(&[]).unwrap().parallax, None);
+    }
+
+    #[test]
+    fn should_parse_shm_format() {
+        assert_eq!(parse(&[]).unwrap().shm_format, ShmFormat::Xrgb8888);
+        let cli = parse(&["--shm-format", "rgb565"]).unwrap();
+        assert_eq!(cli.shm_format, ShmFormat::Rgb565);
+        assert!(parse(&["--shm-format", "rgb888"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_memory_limit() {
+        let cli = parse(&["--memory-limit", "12"]).unwrap();
+        assert_eq!(cli.memory_limit, Some(12 * 1024 * 1024));
+        assert!(parse(&["--memory-limit", "0"]).is_err());
+        assert!(parse(&["--memory-limit", "lots"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_battery_policy() {
+        assert_eq!(parse(&[]).unwrap().battery_policy, None);
+        assert_eq!(
+            parse(&["--battery-policy", "pause"])
+                .unwrap()
+                .battery_policy,
+            Some((BatteryPolicy::Pause, 30))
+        );
+        let cli = parse(&[
+            "--battery-policy",
+            "reduce-fps",
+            "--battery-fps",
+            "5",
+            "--battery-threshold",
+            "50",
+        ])
+        .unwrap();
+        assert_eq!(cli.battery_policy, Some((BatteryPolicy::ReduceFps(5), 50)));
+        assert_eq!(
+            parse(&["--battery-policy", "reduce-fps"])
+                .unwrap()
+                .battery_policy,
+            Some((BatteryPolicy::ReduceFps(10), 30))
+        );
+        assert!(parse(&["--battery-policy", "sleep"]).is_err());
+        assert!(parse(&["--battery-threshold", "50"]).is_err());
+        assert!(parse(&["--battery-policy", "pause", "--battery-fps", "5"]).is_err());
+        assert!(parse(&["--battery-policy", "pause", "--battery-threshold", "0"]).is_err());
+        assert!(parse(&["--battery-policy", "pause", "--battery-threshold", "101"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_flags() {
+        assert!(parse(&["--render-transition"]).unwrap().render_transition);
+        assert!(!parse(&[]).unwrap().render_transition);
+        let cli = parse(&["--prefer-compositor-scaling"]).unwrap();
+        assert!(cli.prefer_compositor_scaling);
+        assert!(!parse(&[]).unwrap().prefer_compositor_scaling);
+        assert!(parse(&["--track-cursor"]).unwrap().track_cursor);
+        assert!(!parse(&[]).unwrap().track_cursor);
+        assert!(parse(&["--no-restore"]).unwrap().no_restore);
+        assert!(!parse(&[]).unwrap().no_restore);
+        assert!(parse(&["--animate-occluded"]).unwrap().animate_occluded);
+        assert!(!parse(&[]).unwrap().animate_occluded);
+    }
+
+    #[test]
+    fn should_parse_renderer() {
+        assert_eq!(parse(&[]).unwrap().renderer, Renderer::Cpu);
+        assert_eq!(
+            parse(&["--renderer", "cpu"]).unwrap().renderer,
+            Renderer::Cpu
+        );
+        let gpu = parse(&["--renderer", "gpu"]);
+        if cfg!(feature = "gpu") {
+            assert_eq!(gpu.unwrap().renderer, Renderer::Gpu);
+        } else {
+            assert!(gpu.unwrap_err().contains("`gpu` feature"));
+        }
+        assert!(parse(&["--renderer", "vulkan"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_wayland_backend() {
+        assert_eq!(parse(&[]).unwrap().wayland_backend, WaylandBackend::Auto);
+        let cli = parse(&["--wayland-backend", "manual", "--wayland-socket", "/tmp/wl"]).unwrap();
+        assert_eq!(
+            cli.wayland_backend,
+            WaylandBackend::Manual("/tmp/wl".into())
+        );
+        assert!(parse(&["--wayland-backend", "manual"]).is_err());
+        assert!(parse(&["--wayland-socket", "/tmp/wl"]).is_err());
+        assert!(parse(&["--wayland-backend", "x11"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_transition_interrupt_policy() {
+        let policy = |value| parse(&["--transition-interrupt-policy", value]);
+        assert_eq!(
+            parse(&[]).unwrap().transition_interrupt_policy,
+            InterruptPolicy::Restart
+        );
+        let cli = policy("keep").unwrap();
+        assert_eq!(cli.transition_interrupt_policy, InterruptPolicy::Keep);
+        let cli = policy("skip").unwrap();
+        assert_eq!(cli.transition_interrupt_policy, InterruptPolicy::Skip);
+        assert!(policy("queue").is_err());
+    }
+
+    #[test]
+    fn should_parse_frame_drop_recovery() {
+        assert_eq!(
+            parse(&[]).unwrap().frame_drop_recovery,
+            FrameDropRecovery::Warp
+        );
+        let cli = parse(&["--frame-drop-recovery", "hold"]).unwrap();
+        assert_eq!(cli.frame_drop_recovery, FrameDropRecovery::Hold);
+        assert!(parse(&["--frame-drop-recovery", "skip"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_statsd_backend() {
+        let args = [
+            "--metrics-backend",
+            "statsd",
+            "--statsd-host",
+            "localhost:8125",
+        ];
+        let cli = parse(&args).unwrap();
+        assert_eq!(cli.statsd_host.as_deref(), Some("localhost:8125"));
+        assert!(parse(&["--metrics-backend", "statsd"]).is_err());
+        assert!(parse(&["--statsd-host", "localhost:8125"]).is_err());
+        assert!(parse(&["--metrics-backend", "prometheus"]).is_err());
+    }
+
+    #[test]
+    fn manual_wayland_backend_should_connect_to_the_given_socket() {
+        use std::os::unix::net::UnixListener;
+        let dir = std::env::temp_dir();
+        let pid = std::process::id();
+        let paths = [0, 1].map(|i| dir.join(format!("swww-wayland-{pid}-{i}")));
+        let listeners = paths.clone().map(|path| {
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(path).unwrap();
+            listener.set_nonblocking(true).unwrap();
+            listener
+        });
+
+        let stream = WaylandBackend::Manual(paths[1].clone()).connect().unwrap();
+        let accepted: Vec<_> = listeners.iter().map(|l| l.accept().is_ok()).collect();
+        paths
+            .iter()
+            .for_each(|path| drop(std::fs::remove_file(path)));
+
+        assert!(stream.is_some());
+        assert_eq!(accepted, vec![false, true]);
+        assert!(WaylandBackend::Auto.connect().unwrap().is_none());
+    }
+
+    #[test]
+    fn should_reject_unknown_and_incomplete_args() {
+        assert!(parse(&["--ahoy"]).is_err());
+        assert!(parse(&["--heartbeat-socket"]).is_err());
+        assert!(parse(&["--script"]).is_err());
+        assert!(parse(&["--lock-file"]).is_err());
+        assert!(parse(&["--ipc-auth-token"]).is_err());
+        assert!(parse(&["--ipc-auth-token", ""]).is_err());
+    }
+}
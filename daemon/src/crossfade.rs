@@ -0,0 +1,120 @@
+//! Blends the animation an output was playing into the next image, see the 'crossfade'
+//! transition.
+//!
+//! Frames only hold their differences to the previous one, so the outgoing animation can't share
+//! the canvas with the incoming image. Instead, it keeps unpacking its frames into a canvas of its
+//! own, the one we keep here, and like with [`crate::tint`], every time the output draws, we blend
+//! that canvas with what was drawn before copying it to the buffer. Once the transition's duration
+//! is over, the outgoing animation stops, and the output draws as usual again.
+
+use std::time::{Duration, Instant};
+
+use utils::gamma;
+
+pub struct Crossfade {
+    /// What the outgoing animation shows, in the same format as the canvas
+    pub outgoing: Vec<u8>,
+    /// The id of the outgoing animation, see [`crate::wallpaper::AnimationToken`]
+    pub from: usize,
+    /// What we blended last, kept so that we don't allocate a canvas for every frame
+    blended: Vec<u8>,
+    start: Instant,
+    duration: Duration,
+    /// Whether to blend in linear light, see [`utils::gamma`]
+    gamma_correct: bool,
+}
+
+impl Crossfade {
+    pub fn new(outgoing: Vec<u8>, from: usize, duration: Duration, gamma_correct: bool) -> Self {
+        Self {
+            outgoing,
+            from,
+            blended: Vec::new(),
+            start: Instant::now(),
+            duration,
+            gamma_correct,
+        }
+    }
+
+    /// How much of the incoming image we show, from 0 to 1
+    fn progress(&self) -> f32 {
+        if self.duration.is_zero() {
+            return 1.0;
+        }
+        (self.start.elapsed().as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.progress() >= 1.0
+    }
+
+    /// Blends `incoming`, the canvas the output drew, with the outgoing animation
+    pub fn blend(&mut self, incoming: &[u8]) -> &[u8] {
+        let weight = (self.progress() * 256.0) as u32;
+        self.blended.resize(incoming.len(), 0);
+        if self.gamma_correct {
+            mix_linear(&self.outgoing, incoming, weight, &mut self.blended);
+        } else {
+            mix(&self.outgoing, incoming, weight, &mut self.blended);
+        }
+        &self.blended
+    }
+}
+
+/// Every byte of `from` and `to`, `weight` 256ths of the way from one to the other, into `mixed`
+fn mix(from: &[u8], to: &[u8], weight: u32, mixed: &mut [u8]) {
+    for ((&from, &to), mixed) in from.iter().zip(to).zip(mixed) {
+        *mixed = ((from as u32 * (256 - weight) + to as u32 * weight) >> 8) as u8;
+    }
+}
+
+/// Like [`mix`], but in linear light. Outgoing animations never have an alpha channel, so every
+/// byte is a color, or padding that is the same in both
+fn mix_linear(from: &[u8], to: &[u8], weight: u32, mixed: &mut [u8]) {
+    let (to_linear, to_srgb) = (gamma::to_linear_table(), gamma::to_srgb_table());
+    for ((&from, &to), mixed) in from.iter().zip(to).zip(mixed) {
+        *mixed = gamma::mix(from, to, weight, to_linear, to_srgb);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mixed(
+        mix: fn(&[u8], &[u8], u32, &mut [u8]),
+        from: &[u8],
+        to: &[u8],
+        weight: u32,
+    ) -> [u8; 4] {
+        let mut mixed = [0; 4];
+        mix(from, to, weight, &mut mixed);
+        mixed
+    }
+
+    #[test]
+    fn should_mix_from_the_outgoing_to_the_incoming_image() {
+        let (from, to) = ([0, 100, 255, 0xff], [200, 100, 55, 0xff]);
+        assert_eq!(mixed(mix, &from, &to, 0), from);
+        assert_eq!(mixed(mix, &from, &to, 128), [100, 100, 155, 0xff]);
+        assert_eq!(mixed(mix, &from, &to, 256), to);
+    }
+
+    #[test]
+    fn linear_mixes_should_not_dip_in_brightness() {
+        let (from, to) = ([0, 100, 255, 0xff], [255, 100, 0, 0xff]);
+        assert_eq!(mixed(mix_linear, &from, &to, 0), from);
+        assert_eq!(mixed(mix_linear, &from, &to, 256), to);
+        let half = mixed(mix_linear, &from, &to, 128);
+        assert!(half[0] > mixed(mix, &from, &to, 128)[0]);
+        assert_eq!(half[1..], [100, half[0], 0xff][..]);
+    }
+
+    #[test]
+    fn crossfades_without_a_duration_should_be_over_right_away() {
+        let mut crossfade = Crossfade::new(vec![0; 4], 1, Duration::ZERO, false);
+        assert!(crossfade.is_over());
+        assert_eq!(crossfade.blend(&[9; 4]), [9; 4]);
+        assert!(!Crossfade::new(vec![0; 4], 1, Duration::from_secs(60), false).is_over());
+    }
+}
@@ -0,0 +1,199 @@
+//! Tracks where the pointer is, for transitions that start from it (`--transition-pos cursor`),
+//! and for `--parallax`.
+//!
+//! Wayland only tells us about the pointer while it is over one of our surfaces, so we have to
+//! accept pointer input on the wallpapers. Normally we don't, so that the compositor draws its own
+//! cursor over the desktop. Accepting it means *we* must set the cursor, which we only do through
+//! the `cursor_shape_v1` protocol. If the compositor doesn't support it, we don't track the
+//! pointer at all. Either way, we only know where the pointer was when it last left the desktop
+//! for a window.
+
+use log::warn;
+
+use smithay_client_toolkit::{
+    delegate_pointer, delegate_seat,
+    reexports::protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::{
+        Shape, WpCursorShapeDeviceV1,
+    },
+    seat::{
+        pointer::{
+            cursor_shape::CursorShapeManager, PointerEvent, PointerEventKind, PointerHandler,
+        },
+        Capability, SeatHandler, SeatState,
+    },
+};
+
+use wayland_client::{
+    globals::GlobalList,
+    protocol::{wl_pointer::WlPointer, wl_seat::WlSeat},
+    Connection, Proxy, QueueHandle,
+};
+
+use crate::Daemon;
+
+pub struct Cursor {
+    shape_manager: CursorShapeManager,
+    pointers: Vec<(WlSeat, WlPointer, WpCursorShapeDeviceV1)>,
+}
+
+impl Cursor {
+    /// Returns `None` if the compositor doesn't support `cursor_shape_v1`
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Daemon>) -> Option<Self> {
+        match CursorShapeManager::bind(globals, qh) {
+            Ok(shape_manager) => Some(Self {
+                shape_manager,
+                pointers: Vec::new(),
+            }),
+            Err(e) => {
+                warn!("cursor_shape_v1 is not available, so we can't track the cursor: {e}");
+                None
+            }
+        }
+    }
+
+    fn shape_device(&self, pointer: &WlPointer) -> Option<&WpCursorShapeDeviceV1> {
+        self.pointers
+            .iter()
+            .find(|(_, p, _)| p == pointer)
+            .map(|(_, _, device)| device)
+    }
+}
+
+impl SeatHandler for Daemon {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.seat_state
+    }
+
+    fn new_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {}
+
+    fn new_capability(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        seat: WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Pointer {
+            self.add_pointer(qh, seat);
+        }
+    }
+
+    fn remove_capability(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        seat: WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Pointer {
+            self.remove_pointers(&seat);
+        }
+    }
+
+    fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, seat: WlSeat) {
+        self.remove_pointers(&seat);
+    }
+}
+
+impl Daemon {
+    /// Starts or stops tracking the pointer. The wallpapers only take the input it needs, or give
+    /// it back, once they are made again
+    pub fn track_cursor(&mut self, track: bool, qh: &QueueHandle<Self>) {
+        if track == self.cursor.is_some() {
+            return;
+        }
+        let seats: Vec<_> = self.seat_state.seats().collect();
+        if !track {
+            for seat in &seats {
+                self.remove_pointers(seat);
+            }
+            self.cursor = None;
+            for wallpaper in &self.wallpapers {
+                wallpaper.set_cursor(None);
+            }
+            return;
+        }
+        self.cursor = Cursor::new(&self.globals, qh);
+        // the seats we already know won't tell us about their pointers again
+        for seat in seats {
+            if self
+                .seat_state
+                .info(&seat)
+                .is_some_and(|info| info.has_pointer)
+            {
+                self.add_pointer(qh, seat);
+            }
+        }
+    }
+
+    fn add_pointer(&mut self, qh: &QueueHandle<Self>, seat: WlSeat) {
+        let Some(cursor) = &mut self.cursor else {
+            return;
+        };
+        match self.seat_state.get_pointer(qh, &seat) {
+            Ok(pointer) => {
+                let device = cursor.shape_manager.get_shape_device(&pointer, qh);
+                cursor.pointers.push((seat, pointer, device));
+            }
+            Err(e) => warn!("failed to get the pointer of seat {}: {e}", seat.id()),
+        }
+    }
+
+    fn remove_pointers(&mut self, seat: &WlSeat) {
+        if let Some(cursor) = &mut self.cursor {
+            cursor.pointers.retain(|(s, pointer, device)| {
+                if s != seat {
+                    return true;
+                }
+                device.destroy();
+                if pointer.version() >= 3 {
+                    pointer.release();
+                }
+                false
+            });
+        }
+    }
+}
+
+impl PointerHandler for Daemon {
+    fn pointer_frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        pointer: &WlPointer,
+        events: &[PointerEvent],
+    ) {
+        for event in events {
+            match event.kind {
+                PointerEventKind::Enter { serial } => {
+                    if let Some(device) = self.cursor.as_ref().and_then(|c| c.shape_device(pointer))
+                    {
+                        device.set_shape(serial, Shape::Default);
+                    }
+                    // only the output the pointer is on knows where it is
+                    for wallpaper in &self.wallpapers {
+                        if !wallpaper.has_surface(&event.surface) {
+                            wallpaper.set_cursor(None);
+                        }
+                    }
+                }
+                PointerEventKind::Motion { .. } => (),
+                _ => continue,
+            }
+            if let Some(wallpaper) = self
+                .wallpapers
+                .iter()
+                .find(|w| w.has_surface(&event.surface))
+            {
+                wallpaper.set_cursor(Some(event.position));
+                if wallpaper.move_parallax() {
+                    let (_, buffer) = wallpaper.canvas_change(|_| ());
+                    wallpaper.draw(&buffer);
+                }
+            }
+        }
+    }
+}
+
+delegate_seat!(Daemon);
+delegate_pointer!(Daemon);
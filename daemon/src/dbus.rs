@@ -0,0 +1,272 @@
+//! Exposes the daemon's commands on the session bus, as `org.swww.Daemon`, for programs that would
+//! rather use D-Bus than spawn `swww`. Requires the `dbus` feature.
+//!
+//! The object `/org/swww/Daemon` has the interface `org.swww.Daemon`, with the methods:
+//! * `SetImage(s token, s path, as outputs)`, like `swww img --outputs <outputs> <path>`;
+//! * `Clear(s token, s color, as outputs)`, like `swww clear --outputs <outputs> <color>`;
+//! * `Query(s token) -> a(suuis)`, the name, width, height, scale and what every output is
+//!   displaying;
+//! * `Pause(s token, as outputs)` and `Resume(s token, as outputs)`, like `swww pause` and
+//!   `swww resume`.
+//!
+//! An empty `outputs` means every output. `token` is what a client would have in
+//! `$SWWW_AUTH_TOKEN`, and may be empty if the daemon wasn't started with `--ipc-auth-token`.
+//!
+//! On the system bus, we also watch the `LockedHint` of our systemd-logind session, so that
+//! animations pause while it is locked, see `crate::session_lock`.
+//!
+//! We are just another client of our own socket: every call becomes a `Request`, sent with the
+//! caller's token, which the main loop authenticates and handles like any other. Images still have
+//! to be decoded by `swww img`, which we spawn, like when we restore them from the cache.
+
+use log::{debug, error, info, warn};
+
+use utils::ipc::{self, Answer, ArchivedAnswer, Clear, ErrorKind, Request};
+use zbus::{
+    blocking::{Connection, ConnectionBuilder, Proxy},
+    dbus_interface,
+    fdo::{RequestNameFlags, RequestNameReply},
+    zvariant::OwnedObjectPath,
+};
+
+const NAME: &str = "org.swww.Daemon";
+const LOGIND: &str = "org.freedesktop.login1";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const PATH: &str = "/org/swww/Daemon";
+
+/// What our methods answer with when they fail, as `org.swww.Daemon.Error.<variant>`
+#[derive(Debug, zbus::DBusError)]
+#[dbus_error(prefix = "org.swww.Daemon.Error")]
+enum Error {
+    #[dbus_error(zbus_error)]
+    ZBus(zbus::Error),
+    Failed(String),
+    InvalidArgs(String),
+    /// The token didn't match the daemon's `--ipc-auth-token`
+    AuthFailed(String),
+}
+
+impl From<ipc::Error> for Error {
+    fn from(e: ipc::Error) -> Self {
+        match e.kind {
+            ErrorKind::PermissionDenied => Self::AuthFailed(e.msg),
+            ErrorKind::InvalidArgument => Self::InvalidArgs(e.msg),
+            _ => Self::Failed(e.msg),
+        }
+    }
+}
+
+pub fn spawn() {
+    if let Err(e) = std::thread::Builder::new()
+        .name("dbus".to_string())
+        .stack_size(1 << 16)
+        .spawn(|| {
+            if let Err(e) = serve() {
+                warn!("D-Bus interface is not available: {e}");
+            }
+        })
+    {
+        error!("failed to spawn 'dbus' thread: {e}");
+    }
+}
+
+fn serve() -> Result<(), String> {
+    let bus = ConnectionBuilder::session()
+        .and_then(|builder| builder.serve_at(PATH, Daemon)?.build())
+        .map_err(|e| format!("failed to connect to the session bus: {e}"))?;
+
+    let name = bus_name(utils::ipc::namespace().as_deref());
+    // if another daemon has the name, we don't want it after it
+    let reply = bus
+        .request_name_with_flags(name.as_str(), RequestNameFlags::DoNotQueue.into())
+        .map_err(|e| format!("failed to request {name}: {e}"))?;
+    if reply != RequestNameReply::PrimaryOwner {
+        return Err(format!("{name} is already taken on the session bus"));
+    }
+    info!("serving {name} on the session bus");
+
+    // zbus answers the calls on threads of its own, for as long as the connection lives
+    loop {
+        std::thread::park();
+    }
+}
+
+/// Follows whether our logind session is locked, see `crate::session_lock`. Without logind, or if
+/// we aren't in a session, we just never take it as locked
+pub fn watch_session_lock() {
+    if let Err(e) = std::thread::Builder::new()
+        .name("logind".to_string())
+        .stack_size(1 << 16)
+        .spawn(|| {
+            if let Err(e) = watch_locked_hint() {
+                info!("not pausing animations while the session is locked: {e}");
+            }
+        })
+    {
+        error!("failed to spawn 'logind' thread: {e}");
+    }
+}
+
+fn watch_locked_hint() -> zbus::Result<()> {
+    let bus = Connection::system()?;
+
+    // "auto" is the session we are in, or else the one of our user that is on a display, which
+    // is where the compositor runs when systemd starts us as a user service
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+    let manager = Proxy::new(
+        &bus,
+        LOGIND,
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )?;
+    let path: OwnedObjectPath = manager.call("GetSession", &(session,))?;
+    debug!("watching the LockedHint of {}", path.as_str());
+
+    let session = Proxy::new(&bus, LOGIND, path, SESSION_INTERFACE)?;
+    crate::session_lock::set_hint(session.get_property("LockedHint")?);
+    for changed in session.receive_property_changed::<bool>("LockedHint") {
+        crate::session_lock::set_hint(changed.get()?);
+    }
+    Err(zbus::Error::Failure("logind went away".to_string()))
+}
+
+/// The name we take on the bus. Daemons in other namespaces get names of their own. Since the
+/// elements of bus names can't have '-' in them, nor start with a digit, we replace the first with
+/// '_', and prefix the second with it
+fn bus_name(namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) => {
+            let prefix = if namespace.starts_with(|c: char| c.is_ascii_digit()) {
+                "_"
+            } else {
+                ""
+            };
+            format!("{NAME}.{prefix}{}", namespace.replace('-', "_"))
+        }
+        None => NAME.to_string(),
+    }
+}
+
+/// The name, width, height, scale and what an output is displaying, as `(suuis)`
+type OutputInfo = (String, u32, u32, i32, String);
+
+struct Daemon;
+
+#[dbus_interface(name = "org.swww.Daemon")]
+impl Daemon {
+    fn set_image(&self, token: &str, path: &str, outputs: Vec<String>) -> Result<(), Error> {
+        debug!("D-Bus call: SetImage");
+        // so that a wrong token fails with AuthFailed, rather than in `swww img`
+        request(&Request::Query, token)?;
+        set_image(path, &outputs, token).map_err(Error::Failed)
+    }
+
+    fn clear(&self, token: &str, color: &str, outputs: Vec<String>) -> Result<(), Error> {
+        debug!("D-Bus call: Clear");
+        let color = parse_color(color).map_err(Error::InvalidArgs)?;
+        let clear = Request::Clear(Clear {
+            color,
+            gradient: None,
+            transition: None,
+            outputs: outputs.into_boxed_slice(),
+        });
+        request_ok(&clear, token)
+    }
+
+    fn query(&self, token: &str) -> Result<Vec<OutputInfo>, Error> {
+        debug!("D-Bus call: Query");
+        let bytes = request(&Request::Query, token)?;
+        let Ok(ArchivedAnswer::Info(infos)) = Answer::receive(&bytes) else {
+            return Err(Error::Failed(
+                "daemon did not answer with its outputs".to_string(),
+            ));
+        };
+        Ok(infos
+            .iter()
+            .map(|info| {
+                (
+                    info.name.to_string(),
+                    info.dim.0,
+                    info.dim.1,
+                    info.scale_factor,
+                    info.img.to_string(),
+                )
+            })
+            .collect())
+    }
+
+    fn pause(&self, token: &str, outputs: Vec<String>) -> Result<(), Error> {
+        debug!("D-Bus call: Pause");
+        request_ok(&Request::Pause(outputs.into_boxed_slice()), token)
+    }
+
+    fn resume(&self, token: &str, outputs: Vec<String>) -> Result<(), Error> {
+        debug!("D-Bus call: Resume");
+        request_ok(&Request::Resume(outputs.into_boxed_slice()), token)
+    }
+}
+
+/// Sends `request` to our own socket, with the caller's `token`, and returns the answer
+fn request(request: &Request, token: &str) -> Result<Vec<u8>, Error> {
+    let socket = ipc::connect_with_token(5, 100, token)?;
+    request.send(&socket).map_err(Error::Failed)?;
+    ipc::read_socket(&socket).map_err(Error::Failed)
+}
+
+/// For the requests that are only answered with `Ok`, or an error
+fn request_ok(req: &Request, token: &str) -> Result<(), Error> {
+    let bytes = request(req, token)?;
+    match Answer::receive(&bytes) {
+        Ok(ArchivedAnswer::Err(e)) => Err(Error::Failed(e.to_string())),
+        Err(e) => Err(e.into()),
+        Ok(_) => Ok(()),
+    }
+}
+
+fn set_image(path: &str, outputs: &[String], token: &str) -> Result<(), String> {
+    let mut command = crate::auth::swww_command();
+    command.arg("img");
+    if !outputs.is_empty() {
+        command.arg(format!("--outputs={}", outputs.join(",")));
+    }
+    // the caller's token, not whatever we were started with
+    command.env("SWWW_AUTH_TOKEN", token);
+    let output = command
+        .arg("--")
+        .arg(path)
+        .output()
+        .map_err(|e| format!("failed to spawn `swww img`: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("expected a color like rrggbb, got: {color}"));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok([channel(0), channel(2), channel(4)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_should_get_bus_names_of_their_own() {
+        assert_eq!(bus_name(None), "org.swww.Daemon");
+        assert_eq!(bus_name(Some("nested-sway")), "org.swww.Daemon.nested_sway");
+        assert_eq!(bus_name(Some("2")), "org.swww.Daemon._2");
+    }
+
+    #[test]
+    fn should_parse_colors() {
+        assert_eq!(parse_color("#ff0010").unwrap(), [255, 0, 16]);
+        assert_eq!(parse_color("00ff00").unwrap(), [0, 255, 0]);
+        assert!(parse_color("red").is_err());
+    }
+}
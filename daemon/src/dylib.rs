@@ -0,0 +1,28 @@
+//! Tables of the functions of C libraries we load when we need them, rather than link, so that the
+//! daemon still runs where they aren't installed, see [`functions`].
+
+/// Declares a struct `$table` of the functions listed, by their C names, with a `load` that finds
+/// them all in a [`Library`](libloading::Library). Functions may have attributes, like `cfg`
+macro_rules! functions {
+    ($table:ident { $($(#[$meta:meta])* $name:ident: fn($($arg:ty),*) $(-> $ret:ty)?;)* }) => {
+        #[allow(non_snake_case)]
+        struct $table {
+            $($(#[$meta])* $name: unsafe extern "C" fn($($arg),*) $(-> $ret)?,)*
+        }
+
+        impl $table {
+            /// # Safety
+            ///
+            /// The function pointers are only valid for as long as `lib` is loaded
+            unsafe fn load(lib: &libloading::Library) -> Result<Self, String> {
+                Ok(Self {
+                    $($(#[$meta])* $name: *lib
+                        .get(concat!(stringify!($name), "\0").as_bytes())
+                        .map_err(|e| e.to_string())?,)*
+                })
+            }
+        }
+    };
+}
+
+pub(crate) use functions;
@@ -0,0 +1,255 @@
+//! Sends events to the clients that ran `swww subscribe`.
+//!
+//! After answering their `Request::Subscribe`, we keep their sockets, and write every event to
+//! them as one JSON object per line:
+//!
+//! * `{"event":"output_added","output":"DP-1"}`, and `output_removed`;
+//! * `{"event":"wallpaper_changed","output":"DP-1","displaying":{"type":"image","path":"/a.png"}}`,
+//!   where `displaying` is like in `swww query --format json`;
+//! * `{"event":"transition_started","output":"DP-1"}`, and `transition_finished`;
+//! * `{"event":"animation_looped","output":"DP-1"}`.
+//!
+//! The sockets are non blocking, so that a subscriber that doesn't read its events can't stall the
+//! daemon. What doesn't fit in one waits for the next event, so that lines are only ever written
+//! whole. Once writing to one fails, or more than [`MAX_PENDING`] bytes wait for it, we drop it.
+
+use log::debug;
+
+use utils::ipc::BgImg;
+
+use std::{
+    io::{ErrorKind, Write},
+    os::unix::net::UnixStream,
+    sync::Mutex,
+};
+
+/// How many bytes of events may wait for a subscriber to read the ones before
+const MAX_PENDING: usize = 1 << 16;
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+/// A non blocking socket we write lines to, like the log followers of `crate::logging`
+pub struct Subscriber {
+    stream: UnixStream,
+    /// What we couldn't write yet, starting at the rest of the line we were writing
+    pending: Vec<u8>,
+}
+
+impl Subscriber {
+    /// `stream` must already be non blocking
+    pub fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Writes `event`, and whatever waited before it, as far as the socket lets us
+    pub fn send(&mut self, event: &str) -> Result<(), String> {
+        self.pending.extend_from_slice(event.as_bytes());
+        let mut written = 0;
+        while written < self.pending.len() {
+            match self.stream.write(&self.pending[written..]) {
+                Ok(0) => return Err("it hung up".to_string()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        self.pending.drain(..written);
+        if self.pending.len() > MAX_PENDING {
+            return Err(format!("{} bytes wait for it", self.pending.len()));
+        }
+        Ok(())
+    }
+}
+
+pub enum Event<'a> {
+    OutputAdded { output: &'a str },
+    OutputRemoved { output: &'a str },
+    WallpaperChanged { output: &'a str, img: &'a BgImg },
+    TransitionStarted { output: &'a str },
+    TransitionFinished { output: &'a str },
+    AnimationLooped { output: &'a str },
+}
+
+impl Event<'_> {
+    fn to_json(&self) -> String {
+        let (event, output) = match self {
+            Self::OutputAdded { output } => ("output_added", output),
+            Self::OutputRemoved { output } => ("output_removed", output),
+            Self::WallpaperChanged { output, img } => {
+                let displaying = match img {
+                    BgImg::Color([r, g, b]) => {
+                        format!("{{\"type\":\"color\",\"color\":\"{r:02x}{g:02x}{b:02x}\"}}")
+                    }
+                    BgImg::Img(path) => {
+                        format!("{{\"type\":\"image\",\"path\":{}}}", json_string(path))
+                    }
+                    BgImg::Shader { path, fps } => {
+                        format!(
+                            "{{\"type\":\"shader\",\"path\":{},\"fps\":{fps}}}",
+                            json_string(path)
+                        )
+                    }
+                    BgImg::Gradient(gradient) => {
+                        let ([r1, g1, b1], [r2, g2, b2]) = (gradient.from, gradient.to);
+                        format!(
+                            "{{\"type\":\"gradient\",\"from\":\"{r1:02x}{g1:02x}{b1:02x}\",\
+                             \"to\":\"{r2:02x}{g2:02x}{b2:02x}\",\"angle\":{}}}",
+                            gradient.angle
+                        )
+                    }
+                };
+                return format!(
+                    "{{\"event\":\"wallpaper_changed\",\"output\":{},\"displaying\":{displaying}}}\n",
+                    json_string(output),
+                );
+            }
+            Self::TransitionStarted { output } => ("transition_started", output),
+            Self::TransitionFinished { output } => ("transition_finished", output),
+            Self::AnimationLooped { output } => ("animation_looped", output),
+        };
+        format!(
+            "{{\"event\":\"{event}\",\"output\":{}}}\n",
+            json_string(output)
+        )
+    }
+}
+
+pub fn subscribe(stream: UnixStream) {
+    if let Err(e) = stream.set_nonblocking(true) {
+        debug!("refusing subscriber, we can't make its socket non blocking: {e}");
+        return;
+    }
+    SUBSCRIBERS.lock().unwrap().push(Subscriber::new(stream));
+}
+
+pub fn send(event: Event) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if subscribers.is_empty() {
+        return;
+    }
+    let json = event.to_json();
+    subscribers.retain_mut(|subscriber| match subscriber.send(&json) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("dropping subscriber: {e}");
+            false
+        }
+    });
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn should_format_events_as_json_lines() {
+        assert_eq!(
+            Event::OutputAdded { output: "DP-1" }.to_json(),
+            "{\"event\":\"output_added\",\"output\":\"DP-1\"}\n"
+        );
+        let img = BgImg::Img("/a \"b\"\\c.png".to_string());
+        assert_eq!(
+            Event::WallpaperChanged {
+                output: "DP-1",
+                img: &img
+            }
+            .to_json(),
+            "{\"event\":\"wallpaper_changed\",\"output\":\"DP-1\",\
+             \"displaying\":{\"type\":\"image\",\"path\":\"/a \\\"b\\\"\\\\c.png\"}}\n"
+        );
+        let color = BgImg::Color([255, 0, 16]);
+        assert!(Event::WallpaperChanged {
+            output: "DP-1",
+            img: &color
+        }
+        .to_json()
+        .contains("{\"type\":\"color\",\"color\":\"ff0010\"}"));
+        let gradient = BgImg::Gradient(utils::ipc::Gradient {
+            from: [0, 0, 0],
+            to: [255, 255, 255],
+            angle: 45.0,
+        });
+        assert!(Event::WallpaperChanged {
+            output: "DP-1",
+            img: &gradient
+        }
+        .to_json()
+        .contains("{\"type\":\"gradient\",\"from\":\"000000\",\"to\":\"ffffff\",\"angle\":45}"));
+        assert_eq!(json_string("a\u{1}b"), "\"a\\u0001b\"");
+    }
+
+    #[test]
+    fn should_send_events_to_subscribers_until_they_leave() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        subscribe(ours);
+        send(Event::AnimationLooped { output: "HDMI-A-1" });
+        let mut line = String::new();
+        BufReader::new(&theirs).read_line(&mut line).unwrap();
+        assert_eq!(
+            line,
+            "{\"event\":\"animation_looped\",\"output\":\"HDMI-A-1\"}\n"
+        );
+
+        drop(theirs);
+        send(Event::OutputRemoved { output: "HDMI-A-1" });
+        assert!(SUBSCRIBERS.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn slow_subscribers_should_only_get_whole_lines() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        ours.set_nonblocking(true).unwrap();
+        let mut subscriber = Subscriber {
+            stream: ours,
+            pending: Vec::new(),
+        };
+        // more than the socket takes at once, so that lines get cut where it is full
+        let event = Event::OutputAdded {
+            output: &"DP-1".repeat(100),
+        }
+        .to_json();
+        let mut sent = 0;
+        while subscriber.pending.is_empty() {
+            subscriber.send(&event).unwrap();
+            sent += 1;
+        }
+
+        // once they read, they get the rest of that line, and the lines after it
+        let reader = std::thread::spawn(move || {
+            BufReader::new(theirs)
+                .lines()
+                .take(sent + 1)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        });
+        while !subscriber.pending.is_empty() {
+            subscriber.send("").unwrap();
+            std::thread::yield_now();
+        }
+        subscriber.send(&event).unwrap();
+        let lines = reader.join().unwrap();
+        assert_eq!(lines.len(), sent + 1);
+        assert!(lines.iter().all(|line| *line == event.trim_end()));
+    }
+}
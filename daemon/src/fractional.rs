@@ -0,0 +1,141 @@
+//! Draws outputs with fractional scales, like 1.5, at their exact size in pixels.
+//!
+//! Through `wl_surface.set_buffer_scale`, we can only ask for integer scales, so the compositor
+//! would have to resample what we draw to the output's real size. Instead, the
+//! `wp_fractional_scale_v1` protocol tells us the scale the compositor prefers for every surface,
+//! we draw buffers that much bigger than the surface, and `wp_viewporter` shows them at the
+//! surface's size, pixel for pixel. If the compositor doesn't support both, we keep using integer
+//! scales, as before.
+
+use std::num::NonZeroU32;
+
+use log::debug;
+
+use smithay_client_toolkit::reexports::protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+};
+
+use wayland_client::{
+    globals::GlobalList, protocol::wl_surface::WlSurface, Connection, Dispatch, QueueHandle,
+};
+
+use crate::Daemon;
+
+pub struct FractionalScaling {
+    manager: WpFractionalScaleManagerV1,
+    viewporter: WpViewporter,
+    /// The output id every fractional scale object reports about
+    scales: Vec<(u32, WpFractionalScaleV1)>,
+}
+
+impl FractionalScaling {
+    /// Returns `None` if the compositor doesn't support both `wp_fractional_scale_v1` and
+    /// `wp_viewporter`
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Daemon>) -> Option<Self> {
+        let manager = globals.bind::<WpFractionalScaleManagerV1, _, _>(qh, 1..=1, ());
+        let viewporter = globals.bind::<WpViewporter, _, _>(qh, 1..=1, ());
+        match (manager, viewporter) {
+            (Ok(manager), Ok(viewporter)) => Some(Self {
+                manager,
+                viewporter,
+                scales: Vec::new(),
+            }),
+            (Err(e), _) | (_, Err(e)) => {
+                debug!("fractional scaling is not available: {e}");
+                None
+            }
+        }
+    }
+
+    /// Starts listening to the scale the compositor prefers for `surface`, which is on the output
+    /// whose id is `output_id`. Returns the viewport the surface must be shown through
+    pub fn watch(
+        &mut self,
+        surface: &WlSurface,
+        output_id: u32,
+        qh: &QueueHandle<Daemon>,
+    ) -> WpViewport {
+        let scale = self.manager.get_fractional_scale(surface, qh, output_id);
+        self.scales.push((output_id, scale));
+        self.viewporter.get_viewport(surface, qh, ())
+    }
+
+    pub fn forget(&mut self, output_id: u32) {
+        self.scales.retain(|(id, scale)| {
+            if *id == output_id {
+                scale.destroy();
+            }
+            *id != output_id
+        });
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for Daemon {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: <WpFractionalScaleManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_fractional_scale_manager_v1 has no events
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for Daemon {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewporter,
+        _event: <WpViewporter as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewporter has no events
+    }
+}
+
+impl Dispatch<WpViewport, ()> for Daemon {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewport has no events
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, u32> for Daemon {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        output_id: &u32,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        let Some(scale) = NonZeroU32::new(scale) else {
+            return;
+        };
+        if let Some(wallpaper) = state.wallpapers.iter().find(|w| w.has_id(*output_id)) {
+            debug!(
+                "output {} prefers a scale of {}",
+                wallpaper.name(),
+                scale.get() as f64 / 120.0
+            );
+            wallpaper.set_fractional_scale(scale);
+            crate::hotplug::redisplay(wallpaper);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Periodically frees memory that belonged to outputs that no longer exist.
+//!
+//! Every tracked item holds a weak reference to its owner (for example, a `Wallpaper`). Once the
+//! owner is gone, nothing will read the item anymore, so we can free it. We do that in two passes:
+//! the first pass only marks the item, and the second one frees it. That way, we never free
+//! something the compositor might still be reading from right after the output went away.
+
+use log::{debug, error};
+
+use std::{
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+use crate::Generation;
+
+static THREADS: Generation = Generation::new();
+
+/// Something that can be freed by the garbage collector
+pub trait Collect {
+    /// Frees the item, returning how many bytes were freed
+    fn collect(self) -> usize;
+}
+
+struct Entry<O, T> {
+    owner: Weak<O>,
+    item: T,
+    marked: bool,
+}
+
+pub struct Gc<O, T> {
+    entries: Vec<Entry<O, T>>,
+    freed_bytes: u64,
+}
+
+impl<O, T: Collect> Gc<O, T> {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            freed_bytes: 0,
+        }
+    }
+
+    /// Starts tracking `item`, which will be freed once `owner` is dropped
+    pub fn track(&mut self, owner: &Arc<O>, item: T) {
+        self.entries.push(Entry {
+            owner: Arc::downgrade(owner),
+            item,
+            marked: false,
+        });
+    }
+
+    /// Runs a single garbage collection pass, returning how many bytes were freed
+    pub fn collect(&mut self) -> usize {
+        let mut freed = 0;
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &mut self.entries[i];
+            if entry.owner.strong_count() > 0 {
+                i += 1;
+            } else if entry.marked {
+                freed += self.entries.swap_remove(i).item.collect();
+            } else {
+                entry.marked = true;
+                i += 1;
+            }
+        }
+        self.freed_bytes += freed as u64;
+        freed
+    }
+
+    /// How many bytes we have freed since the daemon started
+    pub fn freed_bytes(&self) -> u64 {
+        self.freed_bytes
+    }
+}
+
+impl<O, T: Collect> Default for Gc<O, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects every `interval`, replacing the thread that collected before, if any
+pub fn spawn<O, T>(gc: Arc<Mutex<Gc<O, T>>>, interval: Duration)
+where
+    O: Send + Sync + 'static,
+    T: Collect + Send + 'static,
+{
+    let generation = THREADS.next();
+    if let Err(e) = thread::Builder::new()
+        .name("gc".to_string())
+        .stack_size(1 << 15)
+        .spawn(move || {
+            while THREADS.is_current(generation) {
+                thread::sleep(interval);
+                if !THREADS.is_current(generation) {
+                    break;
+                }
+                let freed = gc.lock().unwrap().collect();
+                if freed > 0 {
+                    debug!("gc: freed {freed} bytes");
+                }
+            }
+        })
+    {
+        error!("failed to spawn 'gc' thread: {e}");
+    }
+}
+
+/// Stops collecting
+pub fn stop() {
+    THREADS.next();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    struct Buffer(Arc<AtomicU64>);
+
+    impl Collect for Buffer {
+        fn collect(self) -> usize {
+            self.0.fetch_add(1, Ordering::AcqRel);
+            100
+        }
+    }
+
+    #[test]
+    fn should_free_unused_entries_after_two_intervals() {
+        let collected = Arc::new(AtomicU64::new(0));
+        let alive = Arc::new(());
+        let removed = Arc::new(());
+
+        let mut gc = Gc::new();
+        gc.track(&alive, Buffer(Arc::clone(&collected)));
+        gc.track(&removed, Buffer(Arc::clone(&collected)));
+        assert_eq!(gc.collect(), 0);
+
+        drop(removed);
+        assert_eq!(gc.collect(), 0, "entries must survive the first interval");
+        assert_eq!(collected.load(Ordering::Acquire), 0);
+        assert_eq!(gc.collect(), 100);
+        assert_eq!(collected.load(Ordering::Acquire), 1);
+
+        assert_eq!(gc.collect(), 0, "entries in use must never be freed");
+        assert_eq!(gc.freed_bytes(), 100);
+        drop(alive);
+    }
+}
@@ -0,0 +1,521 @@
+//! An OpenGL ES 3 context that renders off-screen, for `swww shader` and `--renderer gpu`.
+//!
+//! We load `libEGL` and `libGLESv2` when a request needs them, rather than linking them, so that
+//! the daemon still runs where there are none, and only those requests fail. Our buffers are shm
+//! ones, so we render into a framebuffer of our own and read the pixels back into the canvas. We
+//! prefer Mesa's surfaceless platform, which needs no window system at all, and fall back to the
+//! default display of whatever EGL there is otherwise.
+//!
+//! A context is current on the thread that made it, and must stay on it, which is why [`Context`]
+//! is neither `Send` nor `Sync`.
+
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    ptr::null_mut,
+};
+
+use libloading::Library;
+
+use crate::dylib::functions;
+
+type Ptr = *mut c_void;
+
+functions!(Egl {
+    eglGetPlatformDisplay: fn(u32, Ptr, *const isize) -> Ptr;
+    eglGetDisplay: fn(Ptr) -> Ptr;
+    eglInitialize: fn(Ptr, *mut i32, *mut i32) -> u32;
+    eglQueryString: fn(Ptr, i32) -> *const c_char;
+    eglBindAPI: fn(u32) -> u32;
+    eglChooseConfig: fn(Ptr, *const i32, *mut Ptr, i32, *mut i32) -> u32;
+    eglCreateContext: fn(Ptr, Ptr, Ptr, *const i32) -> Ptr;
+    eglDestroyContext: fn(Ptr, Ptr) -> u32;
+    eglMakeCurrent: fn(Ptr, Ptr, Ptr, Ptr) -> u32;
+    eglGetError: fn() -> i32;
+});
+
+functions!(Gl {
+    glGetString: fn(u32) -> *const c_char;
+    glGetError: fn() -> u32;
+    glCreateShader: fn(u32) -> u32;
+    glShaderSource: fn(u32, i32, *const *const c_char, *const i32);
+    glCompileShader: fn(u32);
+    glGetShaderiv: fn(u32, u32, *mut i32);
+    glGetShaderInfoLog: fn(u32, i32, *mut i32, *mut c_char);
+    glDeleteShader: fn(u32);
+    glCreateProgram: fn() -> u32;
+    glAttachShader: fn(u32, u32);
+    glLinkProgram: fn(u32);
+    glGetProgramiv: fn(u32, u32, *mut i32);
+    glGetProgramInfoLog: fn(u32, i32, *mut i32, *mut c_char);
+    glDeleteProgram: fn(u32);
+    glUseProgram: fn(u32);
+    glGetUniformLocation: fn(u32, *const c_char) -> i32;
+    glUniform1f: fn(i32, f32);
+    glUniform1i: fn(i32, i32);
+    #[cfg(feature = "gpu")]
+    glUniform2f: fn(i32, f32, f32);
+    #[cfg(feature = "gpu")]
+    glUniform2i: fn(i32, i32, i32);
+    glUniform3f: fn(i32, f32, f32, f32);
+    glUniform4f: fn(i32, f32, f32, f32, f32);
+    glGenFramebuffers: fn(i32, *mut u32);
+    glBindFramebuffer: fn(u32, u32);
+    glDeleteFramebuffers: fn(i32, *const u32);
+    glGenRenderbuffers: fn(i32, *mut u32);
+    glBindRenderbuffer: fn(u32, u32);
+    glRenderbufferStorage: fn(u32, u32, i32, i32);
+    glDeleteRenderbuffers: fn(i32, *const u32);
+    glFramebufferRenderbuffer: fn(u32, u32, u32, u32);
+    glCheckFramebufferStatus: fn(u32) -> u32;
+    glViewport: fn(i32, i32, i32, i32);
+    glDrawArrays: fn(u32, i32, i32);
+    glPixelStorei: fn(u32, i32);
+    glReadPixels: fn(i32, i32, i32, i32, u32, u32, Ptr);
+    #[cfg(feature = "gpu")]
+    glGenTextures: fn(i32, *mut u32);
+    #[cfg(feature = "gpu")]
+    glDeleteTextures: fn(i32, *const u32);
+    #[cfg(feature = "gpu")]
+    glBindTexture: fn(u32, u32);
+    #[cfg(feature = "gpu")]
+    glActiveTexture: fn(u32);
+    #[cfg(feature = "gpu")]
+    glTexParameteri: fn(u32, u32, i32);
+    #[cfg(feature = "gpu")]
+    glTexImage2D: fn(u32, i32, i32, i32, i32, i32, u32, u32, *const c_void);
+});
+
+const EGL_NONE: i32 = 0x3038;
+const EGL_EXTENSIONS: i32 = 0x3055;
+const EGL_RENDERABLE_TYPE: i32 = 0x3040;
+const EGL_OPENGL_ES3_BIT: i32 = 0x40;
+const EGL_OPENGL_ES_API: u32 = 0x30A0;
+const EGL_CONTEXT_MAJOR_VERSION: i32 = 0x3098;
+const EGL_PLATFORM_SURFACELESS_MESA: u32 = 0x31DD;
+
+const GL_RENDERER: u32 = 0x1F01;
+const GL_VERSION: u32 = 0x1F02;
+const GL_FRAGMENT_SHADER: u32 = 0x8B30;
+const GL_VERTEX_SHADER: u32 = 0x8B31;
+const GL_COMPILE_STATUS: u32 = 0x8B81;
+const GL_LINK_STATUS: u32 = 0x8B82;
+const GL_INFO_LOG_LENGTH: u32 = 0x8B84;
+const GL_FRAMEBUFFER: u32 = 0x8D40;
+const GL_RENDERBUFFER: u32 = 0x8D41;
+const GL_FRAMEBUFFER_COMPLETE: u32 = 0x8CD5;
+const GL_COLOR_ATTACHMENT0: u32 = 0x8CE0;
+const GL_RGBA8: u32 = 0x8058;
+const GL_RGBA: u32 = 0x1908;
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_TRIANGLES: u32 = 0x0004;
+const GL_PACK_ALIGNMENT: u32 = 0x0D05;
+#[cfg(feature = "gpu")]
+const GL_UNPACK_ALIGNMENT: u32 = 0x0CF5;
+#[cfg(feature = "gpu")]
+const GL_RGB: u32 = 0x1907;
+#[cfg(feature = "gpu")]
+const GL_RGB8: u32 = 0x8051;
+#[cfg(feature = "gpu")]
+const GL_TEXTURE_2D: u32 = 0x0DE1;
+#[cfg(feature = "gpu")]
+const GL_TEXTURE0: u32 = 0x84C0;
+#[cfg(feature = "gpu")]
+const GL_TEXTURE_MAG_FILTER: u32 = 0x2800;
+#[cfg(feature = "gpu")]
+const GL_TEXTURE_MIN_FILTER: u32 = 0x2801;
+#[cfg(feature = "gpu")]
+const GL_TEXTURE_WRAP_S: u32 = 0x2802;
+#[cfg(feature = "gpu")]
+const GL_TEXTURE_WRAP_T: u32 = 0x2803;
+#[cfg(feature = "gpu")]
+const GL_LINEAR: i32 = 0x2601;
+#[cfg(feature = "gpu")]
+const GL_CLAMP_TO_EDGE: i32 = 0x812F;
+
+/// A vertex shader for a triangle covering the whole framebuffer, which needs no vertex buffers
+pub const FULLSCREEN_VERTEX_SHADER: &str = "#version 300 es
+void main() {
+    vec2 corner = vec2(float((gl_VertexID & 1) << 2), float((gl_VertexID & 2) << 1));
+    gl_Position = vec4(corner - 1.0, 0.0, 1.0);
+}
+";
+
+pub enum Uniform {
+    Float(f32),
+    Int(i32),
+    #[cfg(feature = "gpu")]
+    Vec2([f32; 2]),
+    #[cfg(feature = "gpu")]
+    IVec2([i32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+}
+
+/// A linked program, only valid in the [`Context`] that made it, which deletes it when dropped
+#[derive(Clone, Copy)]
+pub struct Program(u32);
+
+/// A texture, only valid in the [`Context`] that made it, which must delete it with
+/// [`Context::delete_texture`]. Its first row is the first one of the pixels it was made from, so
+/// the top of a canvas, and it filters linearly, and clamps to its edges
+#[cfg(feature = "gpu")]
+pub struct Texture(u32);
+
+pub struct Context {
+    egl: Egl,
+    gl: Gl,
+    display: Ptr,
+    context: Ptr,
+    /// The framebuffer we render to, and the size of its renderbuffer, once we rendered
+    target: Option<(u32, u32, (u32, u32))>,
+    programs: Vec<u32>,
+    // the tables point into them, so they must outlive them
+    _libs: (Library, Library),
+}
+
+impl Context {
+    /// Makes a context, and makes it current on this thread
+    pub fn new() -> Result<Self, String> {
+        // SAFETY: these are the system's EGL and GLES libraries, whose initializers are sound
+        let egl_lib = unsafe { Library::new("libEGL.so.1") }
+            .map_err(|e| format!("failed to load libEGL: {e}"))?;
+        let gl_lib = unsafe { Library::new("libGLESv2.so.2") }
+            .map_err(|e| format!("failed to load libGLESv2: {e}"))?;
+        // SAFETY: the signatures are those of the EGL 1.5 and GLES 3.0 specifications, and we
+        // keep the libraries with the tables
+        let egl = unsafe { Egl::load(&egl_lib) }.map_err(|e| format!("bad libEGL: {e}"))?;
+        let gl = unsafe { Gl::load(&gl_lib) }.map_err(|e| format!("bad libGLESv2: {e}"))?;
+
+        // SAFETY: every pointer we pass is either null, where EGL allows it, or to live memory
+        unsafe {
+            let extensions = (egl.eglQueryString)(null_mut(), EGL_EXTENSIONS);
+            let surfaceless = !extensions.is_null()
+                && CStr::from_ptr(extensions)
+                    .to_string_lossy()
+                    .split(' ')
+                    .any(|ext| ext == "EGL_MESA_platform_surfaceless");
+            let display = if surfaceless {
+                (egl.eglGetPlatformDisplay)(
+                    EGL_PLATFORM_SURFACELESS_MESA,
+                    null_mut(),
+                    [EGL_NONE as isize].as_ptr(),
+                )
+            } else {
+                (egl.eglGetDisplay)(null_mut())
+            };
+            if display.is_null() {
+                return Err(egl_error(&egl, "failed to get an EGL display"));
+            }
+            let (mut major, mut minor) = (0, 0);
+            if (egl.eglInitialize)(display, &mut major, &mut minor) == 0 {
+                return Err(egl_error(&egl, "failed to initialize EGL"));
+            }
+            if (egl.eglBindAPI)(EGL_OPENGL_ES_API) == 0 {
+                return Err(egl_error(&egl, "EGL doesn't do OpenGL ES"));
+            }
+            // we never draw to a surface of EGL's, so any config will do, and, with
+            // `EGL_KHR_no_config_context`, none too
+            let attributes = [EGL_RENDERABLE_TYPE, EGL_OPENGL_ES3_BIT, EGL_NONE];
+            let (mut config, mut configs) = (null_mut(), 0);
+            (egl.eglChooseConfig)(display, attributes.as_ptr(), &mut config, 1, &mut configs);
+            if configs == 0 {
+                config = null_mut();
+            }
+            let attributes = [EGL_CONTEXT_MAJOR_VERSION, 3, EGL_NONE];
+            let context = (egl.eglCreateContext)(display, config, null_mut(), attributes.as_ptr());
+            if context.is_null() {
+                return Err(egl_error(&egl, "failed to make an OpenGL ES 3 context"));
+            }
+            if (egl.eglMakeCurrent)(display, null_mut(), null_mut(), context) == 0 {
+                (egl.eglDestroyContext)(display, context);
+                return Err(egl_error(
+                    &egl,
+                    "failed to make the OpenGL ES context current",
+                ));
+            }
+            let context = Self {
+                egl,
+                gl,
+                display,
+                context,
+                target: None,
+                programs: Vec::new(),
+                _libs: (egl_lib, gl_lib),
+            };
+            log::debug!(
+                "EGL {major}.{minor}, {}, on {}",
+                context.string(GL_VERSION),
+                context.string(GL_RENDERER)
+            );
+            Ok(context)
+        }
+    }
+
+    fn string(&self, name: u32) -> String {
+        // SAFETY: our context is current, and GL returns either null or a static string
+        unsafe {
+            let string = (self.gl.glGetString)(name);
+            if string.is_null() {
+                return String::new();
+            }
+            CStr::from_ptr(string).to_string_lossy().into_owned()
+        }
+    }
+
+    /// Compiles and links a program, or returns the log of whatever failed, with GLSL's own line
+    /// numbers
+    pub fn program(&mut self, vertex: &str, fragment: &str) -> Result<Program, String> {
+        let vertex = self.shader(GL_VERTEX_SHADER, vertex)?;
+        let fragment = match self.shader(GL_FRAGMENT_SHADER, fragment) {
+            Ok(fragment) => fragment,
+            Err(e) => {
+                // SAFETY: `vertex` is a shader of our current context
+                unsafe { (self.gl.glDeleteShader)(vertex) };
+                return Err(e);
+            }
+        };
+        // SAFETY: the shaders and program are of our current context, and the log buffer is as
+        // long as we say
+        unsafe {
+            let program = (self.gl.glCreateProgram)();
+            (self.gl.glAttachShader)(program, vertex);
+            (self.gl.glAttachShader)(program, fragment);
+            (self.gl.glLinkProgram)(program);
+            // they live on in the program
+            (self.gl.glDeleteShader)(vertex);
+            (self.gl.glDeleteShader)(fragment);
+            let mut linked = 0;
+            (self.gl.glGetProgramiv)(program, GL_LINK_STATUS, &mut linked);
+            if linked == 0 {
+                let mut len = 0;
+                (self.gl.glGetProgramiv)(program, GL_INFO_LOG_LENGTH, &mut len);
+                let mut log = vec![0u8; len.max(1) as usize];
+                (self.gl.glGetProgramInfoLog)(program, len, null_mut(), log.as_mut_ptr().cast());
+                (self.gl.glDeleteProgram)(program);
+                return Err(format!("failed to link shader: {}", info_log(&log)));
+            }
+            self.programs.push(program);
+            Ok(Program(program))
+        }
+    }
+
+    fn shader(&self, kind: u32, source: &str) -> Result<u32, String> {
+        let len = i32::try_from(source.len()).map_err(|_| "shader is too long".to_string())?;
+        // SAFETY: the source is `len` bytes long, and the log buffer is as long as we say
+        unsafe {
+            let shader = (self.gl.glCreateShader)(kind);
+            let source = source.as_ptr().cast::<c_char>();
+            (self.gl.glShaderSource)(shader, 1, &source, &len);
+            (self.gl.glCompileShader)(shader);
+            let mut compiled = 0;
+            (self.gl.glGetShaderiv)(shader, GL_COMPILE_STATUS, &mut compiled);
+            if compiled == 0 {
+                let mut len = 0;
+                (self.gl.glGetShaderiv)(shader, GL_INFO_LOG_LENGTH, &mut len);
+                let mut log = vec![0u8; len.max(1) as usize];
+                (self.gl.glGetShaderInfoLog)(shader, len, null_mut(), log.as_mut_ptr().cast());
+                (self.gl.glDeleteShader)(shader);
+                return Err(format!("failed to compile shader: {}", info_log(&log)));
+            }
+            Ok(shader)
+        }
+    }
+
+    /// Sets the uniform `name` of `program`, if the program uses it. Compilers remove the
+    /// uniforms that are never read, so it not being there is no error
+    pub fn set_uniform(&self, program: &Program, name: &str, value: Uniform) {
+        let Ok(name) = CString::new(name) else {
+            return;
+        };
+        // SAFETY: the program is of our current context, and `name` is nul terminated
+        unsafe {
+            (self.gl.glUseProgram)(program.0);
+            let location = (self.gl.glGetUniformLocation)(program.0, name.as_ptr());
+            if location < 0 {
+                return;
+            }
+            match value {
+                Uniform::Float(x) => (self.gl.glUniform1f)(location, x),
+                Uniform::Int(x) => (self.gl.glUniform1i)(location, x),
+                #[cfg(feature = "gpu")]
+                Uniform::Vec2([x, y]) => (self.gl.glUniform2f)(location, x, y),
+                #[cfg(feature = "gpu")]
+                Uniform::IVec2([x, y]) => (self.gl.glUniform2i)(location, x, y),
+                Uniform::Vec3([x, y, z]) => (self.gl.glUniform3f)(location, x, y, z),
+                Uniform::Vec4([x, y, z, w]) => (self.gl.glUniform4f)(location, x, y, z, w),
+            }
+        }
+    }
+
+    /// Makes a texture of `dim` from `pixels`, which have `channels` bytes each, either 3 or 4.
+    /// Shaders see them in the order they are in, whatever the channels are
+    #[cfg(feature = "gpu")]
+    pub fn texture(&self, dim: (u32, u32), pixels: &[u8], channels: usize) -> Texture {
+        let mut texture = 0;
+        // SAFETY: the texture is of our current context
+        unsafe {
+            (self.gl.glGenTextures)(1, &mut texture);
+            (self.gl.glBindTexture)(GL_TEXTURE_2D, texture);
+            for (parameter, value) in [
+                (GL_TEXTURE_MIN_FILTER, GL_LINEAR),
+                (GL_TEXTURE_MAG_FILTER, GL_LINEAR),
+                (GL_TEXTURE_WRAP_S, GL_CLAMP_TO_EDGE),
+                (GL_TEXTURE_WRAP_T, GL_CLAMP_TO_EDGE),
+            ] {
+                (self.gl.glTexParameteri)(GL_TEXTURE_2D, parameter, value);
+            }
+        }
+        let texture = Texture(texture);
+        self.upload(&texture, dim, pixels, channels);
+        texture
+    }
+
+    /// Replaces what `texture` holds with `pixels`, like [`Context::texture`] takes them
+    #[cfg(feature = "gpu")]
+    pub fn upload(&self, texture: &Texture, dim: (u32, u32), pixels: &[u8], channels: usize) {
+        let (internal, format) = match channels {
+            3 => (GL_RGB8, GL_RGB),
+            _ => (GL_RGBA8, GL_RGBA),
+        };
+        // GL would read past the end of a smaller slice
+        assert!(pixels.len() >= dim.0 as usize * dim.1 as usize * channels);
+        // SAFETY: `pixels` holds the whole texture, in rows without padding
+        unsafe {
+            (self.gl.glBindTexture)(GL_TEXTURE_2D, texture.0);
+            (self.gl.glPixelStorei)(GL_UNPACK_ALIGNMENT, 1);
+            (self.gl.glTexImage2D)(
+                GL_TEXTURE_2D,
+                0,
+                internal as i32,
+                dim.0 as i32,
+                dim.1 as i32,
+                0,
+                format,
+                GL_UNSIGNED_BYTE,
+                pixels.as_ptr().cast(),
+            );
+        }
+    }
+
+    #[cfg(feature = "gpu")]
+    pub fn delete_texture(&self, texture: Texture) {
+        // SAFETY: the texture is of our current context, and nothing uses it after this
+        unsafe { (self.gl.glDeleteTextures)(1, &texture.0) };
+    }
+
+    /// Has the `sampler2D` uniform `name` of `program` sample `texture`, through texture unit
+    /// `unit`
+    #[cfg(feature = "gpu")]
+    pub fn bind_texture(&self, program: &Program, name: &str, unit: u32, texture: &Texture) {
+        // SAFETY: the texture is of our current context, and every GLES 3 has 16 units at least
+        unsafe {
+            (self.gl.glActiveTexture)(GL_TEXTURE0 + unit);
+            (self.gl.glBindTexture)(GL_TEXTURE_2D, texture.0);
+        }
+        self.set_uniform(program, name, Uniform::Int(unit as i32));
+    }
+
+    /// Renders `program` over a whole framebuffer of `dim`, and reads it into `pixels`, as RGBA,
+    /// from the bottom row to the top one, like GL counts them
+    pub fn render(
+        &mut self,
+        program: &Program,
+        dim: (u32, u32),
+        pixels: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        self.bind_target(dim)?;
+        pixels.resize(dim.0 as usize * dim.1 as usize * 4, 0);
+        // SAFETY: `pixels` holds the whole framebuffer, whose rows we pack without padding
+        unsafe {
+            (self.gl.glUseProgram)(program.0);
+            (self.gl.glViewport)(0, 0, dim.0 as i32, dim.1 as i32);
+            (self.gl.glDrawArrays)(GL_TRIANGLES, 0, 3);
+            (self.gl.glPixelStorei)(GL_PACK_ALIGNMENT, 1);
+            (self.gl.glReadPixels)(
+                0,
+                0,
+                dim.0 as i32,
+                dim.1 as i32,
+                GL_RGBA,
+                GL_UNSIGNED_BYTE,
+                pixels.as_mut_ptr().cast(),
+            );
+            match (self.gl.glGetError)() {
+                0 => Ok(()),
+                e => Err(format!("failed to render shader: GL error {e:#x}")),
+            }
+        }
+    }
+
+    /// Binds a framebuffer of `dim` to render to, making a new one if the size changed
+    fn bind_target(&mut self, dim: (u32, u32)) -> Result<(), String> {
+        if self.target.is_some_and(|(_, _, size)| size == dim) {
+            return Ok(());
+        }
+        self.delete_target();
+        // SAFETY: the objects are of our current context, and we pass as many as we ask for
+        unsafe {
+            let (mut framebuffer, mut renderbuffer) = (0, 0);
+            (self.gl.glGenFramebuffers)(1, &mut framebuffer);
+            (self.gl.glGenRenderbuffers)(1, &mut renderbuffer);
+            self.target = Some((framebuffer, renderbuffer, dim));
+            (self.gl.glBindRenderbuffer)(GL_RENDERBUFFER, renderbuffer);
+            (self.gl.glRenderbufferStorage)(GL_RENDERBUFFER, GL_RGBA8, dim.0 as i32, dim.1 as i32);
+            (self.gl.glBindFramebuffer)(GL_FRAMEBUFFER, framebuffer);
+            (self.gl.glFramebufferRenderbuffer)(
+                GL_FRAMEBUFFER,
+                GL_COLOR_ATTACHMENT0,
+                GL_RENDERBUFFER,
+                renderbuffer,
+            );
+            match (self.gl.glCheckFramebufferStatus)(GL_FRAMEBUFFER) {
+                GL_FRAMEBUFFER_COMPLETE => Ok(()),
+                status => {
+                    self.delete_target();
+                    Err(format!(
+                        "failed to make a {}x{} framebuffer: status {status:#x}",
+                        dim.0, dim.1
+                    ))
+                }
+            }
+        }
+    }
+
+    fn delete_target(&mut self) {
+        if let Some((framebuffer, renderbuffer, _)) = self.target.take() {
+            // SAFETY: they are objects of our current context
+            unsafe {
+                (self.gl.glBindFramebuffer)(GL_FRAMEBUFFER, 0);
+                (self.gl.glDeleteFramebuffers)(1, &framebuffer);
+                (self.gl.glDeleteRenderbuffers)(1, &renderbuffer);
+            }
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        self.delete_target();
+        // SAFETY: the programs are of our context, which is current, and which nothing uses after
+        // us. We leave the display be, since other threads' contexts may be on it
+        unsafe {
+            for program in self.programs.drain(..) {
+                (self.gl.glDeleteProgram)(program);
+            }
+            (self.egl.eglMakeCurrent)(self.display, null_mut(), null_mut(), null_mut());
+            (self.egl.eglDestroyContext)(self.display, self.context);
+        }
+    }
+}
+
+fn egl_error(egl: &Egl, msg: &str) -> String {
+    // SAFETY: it only reads this thread's last error
+    let code = unsafe { (egl.eglGetError)() };
+    format!("{msg}: EGL error {code:#x}")
+}
+
+/// An info log, without its nul terminator and trailing newlines
+fn info_log(log: &[u8]) -> String {
+    let end = log.iter().position(|b| *b == 0).unwrap_or(log.len());
+    String::from_utf8_lossy(&log[..end]).trim_end().to_string()
+}
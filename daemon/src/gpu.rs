@@ -0,0 +1,162 @@
+//! Draws frames with OpenGL ES, instead of the CPU, with `--renderer gpu`.
+//!
+//! We only do so where every pixel changes every frame: the transitions that redraw the whole
+//! frame (see `animations::transitions::gpu`), and mirrors that rescale what they copy (see
+//! [`crate::mirror`]). Every other transition only changes a few pixels a frame, which the CPU
+//! does faster than we could upload and read them back. Our buffers are shm ones, so the frames
+//! are read back into the canvas either way, and everything drawn over them afterwards works the
+//! same.
+//!
+//! Contexts are current on the thread that made them, so every thread that draws gets a
+//! [`Renderer`] of its own, the first time it does. Should that, or anything it renders, fail, we
+//! say so once, and the thread goes back to the CPU, which is the caller's to fall back to.
+
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::{debug, warn};
+
+use crate::gl::{self, Program};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static RENDERER: RefCell<State> = const { RefCell::new(State::Untried) };
+}
+
+enum State {
+    Untried,
+    /// Boxed, since a context is far bigger than the other variants
+    Ready(Box<Renderer>),
+    Failed,
+}
+
+/// Whether we draw on the GPU from now on, as `--renderer` says
+pub fn enable(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Runs `f` with this thread's renderer. `None` means the CPU has to draw instead, either because
+/// of `--renderer cpu`, or because we have no GPU to draw with, `f` failing now included
+pub fn with<T>(f: impl FnOnce(&mut Renderer) -> Result<T, String>) -> Option<T> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    RENDERER.with(|state| {
+        let mut state = state.borrow_mut();
+        if let State::Untried = *state {
+            *state = match Renderer::new() {
+                Ok(renderer) => {
+                    debug!("drawing on the GPU");
+                    State::Ready(Box::new(renderer))
+                }
+                Err(e) => {
+                    warn!("failed to start the GPU renderer, drawing on the CPU instead: {e}");
+                    State::Failed
+                }
+            };
+        }
+        let State::Ready(renderer) = &mut *state else {
+            return None;
+        };
+        match f(renderer) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!("failed to draw on the GPU, drawing on the CPU from now on: {e}");
+                *state = State::Failed;
+                None
+            }
+        }
+    })
+}
+
+/// A context, with the programs it compiled so far
+pub struct Renderer {
+    context: gl::Context,
+    /// By the address of their fragment shader's source
+    programs: Vec<(usize, Program)>,
+    /// What we read the frames back into
+    pixels: Vec<u8>,
+}
+
+impl Renderer {
+    /// Makes a context, current on this thread. Only [`with`] and tests make renderers
+    pub fn new() -> Result<Self, String> {
+        Ok(Self {
+            context: gl::Context::new()?,
+            programs: Vec::new(),
+            pixels: Vec::new(),
+        })
+    }
+
+    pub fn context(&self) -> &gl::Context {
+        &self.context
+    }
+
+    /// The program of `fragment`, over [`gl::FULLSCREEN_VERTEX_SHADER`], which we compile the
+    /// first time we need it
+    pub fn program(&mut self, fragment: &'static str) -> Result<Program, String> {
+        let key = fragment.as_ptr() as usize;
+        if let Some((_, program)) = self.programs.iter().find(|(k, _)| *k == key) {
+            return Ok(*program);
+        }
+        let program = self
+            .context
+            .program(gl::FULLSCREEN_VERTEX_SHADER, fragment)?;
+        self.programs.push((key, program));
+        Ok(program)
+    }
+
+    /// Renders `program` at the size of `canvas`, `dim`, and copies the first `channels` bytes of
+    /// every pixel to it. Fragments are at the pixels they draw, so `gl_FragCoord.y` grows
+    /// downwards, from the first row of the canvas, like that of the textures
+    pub fn draw(
+        &mut self,
+        program: Program,
+        dim: (u32, u32),
+        canvas: &mut [u8],
+        channels: usize,
+    ) -> Result<(), String> {
+        self.context.render(&program, dim, &mut self.pixels)?;
+        for (pixel, rgba) in canvas.chunks_exact_mut(4).zip(self.pixels.chunks_exact(4)) {
+            pixel[..channels].copy_from_slice(&rgba[..channels]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: &str = "#version 300 es
+precision highp float;
+out vec4 color;
+void main() {
+    color = vec4(1.0, 0.0, 0.0, 1.0);
+}
+";
+
+    #[test]
+    fn should_draw_on_the_cpu_unless_enabled() {
+        assert!(with(|_| Ok(())).is_none());
+    }
+
+    #[test]
+    fn should_copy_the_channels_it_is_asked_for() {
+        let Ok(mut renderer) = Renderer::new() else {
+            eprintln!("no OpenGL ES here, skipping");
+            return;
+        };
+        let program = renderer.program(RED).unwrap();
+        renderer.program(RED).unwrap();
+        assert_eq!(renderer.programs.len(), 1);
+        let mut canvas = [0, 0, 0, 7, 0, 0, 0, 7];
+        renderer.draw(program, (2, 1), &mut canvas, 3).unwrap();
+        assert_eq!(canvas, [255, 0, 0, 7, 255, 0, 0, 7]);
+        renderer.draw(program, (2, 1), &mut canvas, 4).unwrap();
+        assert_eq!(canvas, [255, 0, 0, 255, 255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,468 @@
+//! A [`Surface`] nothing shows, for testing the daemon without a compositor.
+//!
+//! Each one keeps a copy of every buffer presented to it, in the order it got them, so that tests
+//! can play a request on wallpapers drawing to them, just like the daemon would, and check what
+//! every output displayed. There are never any frame callbacks, so nothing ever waits to draw.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc, Mutex,
+};
+
+use wayland_client::protocol::{wl_output, wl_shm, wl_surface::WlSurface};
+
+use utils::ipc::{Coord, Direction, Position, StartupTransition, Transition, TransitionType};
+
+use crate::{
+    placement::Placement,
+    shm_format::ShmFormat,
+    surface::{Buffer, Pool, Surface},
+    wallpaper::{OutputDescription, Wallpaper},
+};
+
+/// Every frame presented to a [`Headless`] surface, oldest first
+pub type Frames = Arc<Mutex<Vec<Vec<u8>>>>;
+
+#[derive(Default)]
+pub struct Headless {
+    frames: Frames,
+    pools: AtomicU32,
+    /// Makes our pools fail to get buffers while set
+    fail: Arc<AtomicBool>,
+}
+
+impl Headless {
+    /// A wallpaper drawing to a headless surface, on an output `size` logical pixels big, with
+    /// what it presents
+    pub fn wallpaper(name: &str, size: (i32, i32)) -> (Arc<Wallpaper>, Frames) {
+        Self::default().into_wallpaper(0, name, size)
+    }
+
+    /// Like [`Headless::wallpaper`], on the output the compositor advertised as global `id`
+    pub fn output(id: u32, name: &str, size: (i32, i32)) -> (Arc<Wallpaper>, Frames) {
+        Self::default().into_wallpaper(id, name, size)
+    }
+
+    /// Like [`Headless::wallpaper`], with a switch that makes its pools fail to get buffers, like
+    /// they do once we run out of memory
+    pub fn failing_wallpaper(
+        name: &str,
+        size: (i32, i32),
+    ) -> (Arc<Wallpaper>, Frames, Arc<AtomicBool>) {
+        let surface = Self::default();
+        let fail = Arc::clone(&surface.fail);
+        let (wallpaper, frames) = surface.into_wallpaper(0, name, size);
+        (wallpaper, frames, fail)
+    }
+
+    fn into_wallpaper(self, id: u32, name: &str, size: (i32, i32)) -> (Arc<Wallpaper>, Frames) {
+        let surface = self;
+        let frames = Arc::clone(&surface.frames);
+        let output = OutputDescription {
+            id,
+            name: name.to_string(),
+            logical_size: Some(size),
+            scale_factor: 1,
+            transform: wl_output::Transform::Normal,
+            refresh_rate: None,
+        };
+        let wallpaper = Wallpaper::new(
+            output,
+            Box::new(surface),
+            false,
+            ShmFormat::default(),
+            &Placement::default(),
+            None,
+        );
+        (Arc::new(wallpaper), frames)
+    }
+}
+
+impl Surface for Headless {
+    fn is(&self, _surface: &WlSurface) -> bool {
+        false
+    }
+
+    fn place(&self, _placement: &Placement) {}
+
+    fn set_size(&self, _width: u32, _height: u32) {}
+
+    fn set_buffer_scale(&self, _scale: i32) {}
+
+    fn set_buffer_transform(&self, _transform: wl_output::Transform) {}
+
+    fn has_viewport(&self) -> bool {
+        false
+    }
+
+    fn set_destination(&self, _width: i32, _height: i32) {}
+
+    fn commit(&self) {}
+
+    /// Our pools keep the frame when it is committed, right before this
+    fn present(&self, _buffer: &Buffer, _damage: &[(usize, usize, usize, usize)]) -> bool {
+        false
+    }
+
+    fn new_pool(&self) -> Box<dyn Pool> {
+        Box::new(HeadlessPool {
+            id: self.pools.fetch_add(1, Ordering::AcqRel),
+            layout: None,
+            memory: Vec::new(),
+            frames: Arc::clone(&self.frames),
+            fail: Arc::clone(&self.fail),
+        })
+    }
+}
+
+/// Nothing ever reads from our buffer while we draw to it, so one is all we need
+struct HeadlessPool {
+    id: u32,
+    layout: Option<(i32, i32, i32, wl_shm::Format)>,
+    memory: Vec<u8>,
+    frames: Frames,
+    fail: Arc<AtomicBool>,
+}
+
+impl Pool for HeadlessPool {
+    fn get(
+        &mut self,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: wl_shm::Format,
+    ) -> Result<(Buffer, &mut [u8]), String> {
+        if self.fail.load(Ordering::Acquire) {
+            return Err("out of memory".to_string());
+        }
+        let layout = (width, height, stride, format);
+        if self.layout != Some(layout) {
+            self.layout = Some(layout);
+            self.memory = vec![0; stride as usize * height as usize];
+        }
+        Ok((Buffer::Headless(self.id), &mut self.memory))
+    }
+
+    fn commit(&self, buffer: &Buffer) {
+        if *buffer == Buffer::Headless(self.id) {
+            self.frames.lock().unwrap().push(self.memory.clone());
+        }
+    }
+
+    fn clear(&mut self) {
+        self.layout = None;
+        self.memory = Vec::new();
+    }
+
+    fn mapped_bytes(&self) -> usize {
+        self.memory.len()
+    }
+
+    fn last_drawn(&mut self) -> Option<&[u8]> {
+        self.layout.map(|_| &self.memory[..])
+    }
+}
+
+/// A transition that shows the image right away
+pub fn simple_transition() -> Transition {
+    Transition {
+        transition_type: TransitionType::Simple,
+        duration: 0.0,
+        step: u8::MAX,
+        fps: 30,
+        fps_adaptive: false,
+        angle: 0.0,
+        pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+        bezier: (0.54, 0.0, 0.34, 0.99),
+        wave: (20.0, 20.0),
+        invert_y: false,
+        direction: Direction::Left,
+        bg_color: [0, 0, 0],
+        reversed: false,
+        gamma_correct: false,
+        loops: 1,
+        first_frame: false,
+        startup: StartupTransition {
+            transition_type: TransitionType::Simple,
+            step: u8::MAX,
+            angle: 0.0,
+            pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+        },
+        queue: false,
+        scene_change_threshold: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::net::UnixStream,
+        time::{Duration, Instant},
+    };
+
+    use utils::ipc::{
+        comp_decomp::{BitPack, CompressionBackend, Damage},
+        read_socket, Adjustments, BgImg, Img, Remake, Request, Tone,
+    };
+
+    use super::*;
+    use crate::animations::{Animator, Start};
+
+    #[test]
+    fn headless_wallpapers_should_display_the_images_they_are_sent() {
+        let (wallpaper, frames) = Headless::wallpaper("HEADLESS-1", (4, 3));
+        // like the images clients send, 3 bytes per pixel, in the order of the canvas
+        let img: Vec<u8> = (0..4 * 3).flat_map(|i| [i, 2 * i, 3 * i]).collect();
+        let request = Request::Img((
+            simple_transition(),
+            Box::new([(
+                Img {
+                    path: "/test.png".to_string(),
+                    img: img.clone().into_boxed_slice(),
+                    jpeg: false,
+                    remake: Remake::default(),
+                    adjustments: Adjustments::default(),
+                    dither: None,
+                    request: None,
+                },
+                Box::new(["HEADLESS-1".to_string()]) as Box<[String]>,
+            )]),
+        ));
+        // through a socket, like it would come from a client
+        let (client, daemon) = UnixStream::pair().unwrap();
+        request.send(&client).unwrap();
+        let bytes = read_socket(&daemon).unwrap();
+
+        let mut animator = Animator::new();
+        animator.transition(bytes, vec![vec![(Arc::clone(&wallpaper), Start::Now)]]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let displayed = |frames: &Frames| {
+            frames.lock().unwrap().last().is_some_and(|frame| {
+                frame
+                    .chunks_exact(4)
+                    .map(|p| &p[..3])
+                    .eq(img.chunks_exact(3))
+            })
+        };
+        while !displayed(&frames) {
+            assert!(Instant::now() < deadline, "the image was never displayed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(wallpaper.get_img_info().to_string(), "image: /test.png");
+    }
+
+    #[test]
+    fn batches_should_display_every_image_on_its_own_output() {
+        let (left, left_frames) = Headless::wallpaper("HEADLESS-1", (2, 1));
+        let (right, right_frames) = Headless::wallpaper("HEADLESS-2", (2, 1));
+        let img = |path: &str, blue: u8| {
+            let output = path.trim_start_matches('/').trim_end_matches(".png");
+            (
+                Img {
+                    path: path.to_string(),
+                    img: Box::new([blue, 0, 0, blue, 0, 0]),
+                    jpeg: false,
+                    remake: Remake::default(),
+                    adjustments: Adjustments::default(),
+                    dither: None,
+                    request: None,
+                },
+                Box::new([output.to_string()]) as Box<[String]>,
+            )
+        };
+        let request = Request::Batch(Box::new([
+            (simple_transition(), Box::new([img("/HEADLESS-1.png", 10)])),
+            (simple_transition(), Box::new([img("/HEADLESS-2.png", 20)])),
+        ]));
+        let (client, daemon) = UnixStream::pair().unwrap();
+        request.send(&client).unwrap();
+        let bytes = read_socket(&daemon).unwrap();
+
+        // one group of wallpapers per image in the batch, like the daemon makes them
+        let mut animator = Animator::new();
+        animator.transition(
+            bytes,
+            vec![
+                vec![(Arc::clone(&left), Start::Now)],
+                vec![(Arc::clone(&right), Start::Now)],
+            ],
+        );
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let shows = |frames: &Frames, blue: u8| {
+            frames
+                .lock()
+                .unwrap()
+                .last()
+                .is_some_and(|frame| frame.chunks_exact(4).all(|p| p[0] == blue))
+        };
+        while !(shows(&left_frames, 10) && shows(&right_frames, 20)) {
+            assert!(Instant::now() < deadline, "the batch was never displayed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(left.get_img_info().to_string(), "image: /HEADLESS-1.png");
+        assert_eq!(right.get_img_info().to_string(), "image: /HEADLESS-2.png");
+    }
+
+    #[test]
+    fn adjustments_should_go_with_the_image_they_were_sent_with() {
+        let (wallpaper, frames) = Headless::wallpaper("HEADLESS-1", (2, 1));
+        let invert = Adjustments {
+            tone: Some(Tone::Invert),
+            ..Default::default()
+        };
+        let request = Request::Img((
+            simple_transition(),
+            Box::new([(
+                Img {
+                    path: "/test.png".to_string(),
+                    img: Box::new([10, 20, 30, 10, 20, 30]),
+                    jpeg: false,
+                    remake: Remake::default(),
+                    adjustments: invert,
+                    dither: None,
+                    request: None,
+                },
+                Box::new(["HEADLESS-1".to_string()]) as Box<[String]>,
+            )]),
+        ));
+        let (client, daemon) = UnixStream::pair().unwrap();
+        request.send(&client).unwrap();
+        let bytes = read_socket(&daemon).unwrap();
+        let mut animator = Animator::new();
+        animator.transition(bytes, vec![vec![(Arc::clone(&wallpaper), Start::Now)]]);
+
+        let shown = |frames: &Frames| -> Option<Vec<u8>> {
+            let frames = frames.lock().unwrap();
+            let frame = frames.last()?;
+            Some(
+                frame
+                    .chunks_exact(4)
+                    .flat_map(|p| &p[..3])
+                    .copied()
+                    .collect(),
+            )
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while shown(&frames) != Some([245, 235, 225].repeat(2)) {
+            assert!(Instant::now() < deadline, "the image was never adjusted");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // like `swww adjust`, on what we display, without sending it again
+        wallpaper.set_adjustments(&Adjustments {
+            brightness: 10,
+            ..Default::default()
+        });
+        let (_, buffer) = wallpaper.canvas_change(|_| ());
+        wallpaper.draw(&buffer);
+        assert_eq!(shown(&frames).unwrap(), [36, 46, 56].repeat(2));
+
+        // the next image doesn't keep them
+        wallpaper.set_adjustments(&invert);
+        wallpaper.set_img_info(BgImg::Color([40, 0, 0]));
+        let buffer = wallpaper.clear([40, 0, 0]);
+        wallpaper.draw(&buffer);
+        assert_eq!(shown(&frames).unwrap(), [0, 0, 40].repeat(2));
+    }
+
+    /// What unpacking a frame that changes nothing damages
+    fn no_damage() -> Damage {
+        let frame = BitPack::pack(&[0; 12], &[0; 12], CompressionBackend::None, 1).unwrap();
+        frame.unpack_with_damage(&mut [0; 16], 2).unwrap()
+    }
+
+    /// The blue (first) byte of every pixel of what was presented last
+    fn last_blues(frames: &Frames) -> Vec<u8> {
+        let frames = frames.lock().unwrap();
+        frames
+            .last()
+            .unwrap()
+            .chunks_exact(4)
+            .map(|p| p[0])
+            .collect()
+    }
+
+    #[test]
+    fn pools_should_make_buffers_of_the_size_we_draw_at() {
+        let (wallpaper, frames) = Headless::wallpaper("HEADLESS-1", (4, 3));
+        let buffer = wallpaper.clear([0, 0, 10]);
+        wallpaper.draw(&buffer);
+        assert_eq!(last_blues(&frames), [10; 12]);
+
+        wallpaper.resize(2.try_into().ok(), 2.try_into().ok(), None);
+        let buffer = wallpaper.clear([0, 0, 20]);
+        wallpaper.draw(&buffer);
+        assert_eq!(last_blues(&frames), [20; 4]);
+        // twice the size, at twice the scale
+        wallpaper.resize(None, None, 2.try_into().ok());
+        let buffer = wallpaper.clear([0, 0, 30]);
+        wallpaper.draw(&buffer);
+        assert_eq!(last_blues(&frames), [30; 16]);
+    }
+
+    #[test]
+    fn only_presented_buffers_should_be_shown() {
+        let (wallpaper, frames) = Headless::wallpaper("HEADLESS-1", (2, 2));
+        // changing the canvas doesn't show it
+        let buffer = wallpaper.clear([0, 0, 10]);
+        assert!(frames.lock().unwrap().is_empty());
+        // nor does presenting nothing that changed
+        wallpaper.draw_damaged(&buffer, &no_damage());
+        assert!(frames.lock().unwrap().is_empty());
+        wallpaper.draw(&buffer);
+        assert_eq!(frames.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn alpha_should_be_drawn_to_a_pool_of_its_own() {
+        let (wallpaper, frames) = Headless::wallpaper("HEADLESS-1", (2, 2));
+        let opaque = wallpaper.clear([0, 0, 10]);
+        wallpaper.draw(&opaque);
+        wallpaper.set_alpha(true);
+        let alpha = wallpaper.clear([0, 0, 20]);
+        assert_ne!(alpha, opaque);
+        wallpaper.draw(&alpha);
+        assert_eq!(last_blues(&frames), [20; 4]);
+
+        // the opaque pool still has what it drew
+        wallpaper.set_alpha(false);
+        let (_, buffer) = wallpaper.canvas_change(|_| ());
+        assert_eq!(buffer, opaque);
+        wallpaper.draw(&buffer);
+        assert_eq!(last_blues(&frames), [10; 4]);
+    }
+
+    #[test]
+    fn frames_we_get_no_buffer_for_should_be_skipped_not_lost() {
+        let (wallpaper, frames, fail) = Headless::failing_wallpaper("HEADLESS-1", (2, 2));
+        let buffer = wallpaper.clear([0, 0, 10]);
+        wallpaper.draw(&buffer);
+
+        fail.store(true, Ordering::Release);
+        let buffer = wallpaper.clear([0, 0, 20]);
+        assert_eq!(buffer, Buffer::Skipped);
+        wallpaper.draw(&buffer);
+        assert_eq!(frames.lock().unwrap().len(), 1);
+
+        // the next buffer gets what we drew meanwhile, whole
+        fail.store(false, Ordering::Release);
+        let (_, buffer) = wallpaper.canvas_change(|_| ());
+        wallpaper.draw_damaged(&buffer, &no_damage());
+        assert_eq!(frames.lock().unwrap().len(), 2);
+        assert_eq!(last_blues(&frames), [20; 4]);
+    }
+
+    #[test]
+    fn palettes_should_come_from_what_was_drawn_without_drawing_again() {
+        let (wallpaper, frames) = Headless::wallpaper("HEADLESS-1", (4, 4));
+        assert_eq!(wallpaper.with_drawn(|_| ()), None);
+        let buffer = wallpaper.clear([10, 20, 30]);
+        wallpaper.draw(&buffer);
+
+        let colors = wallpaper.with_drawn(|drawn| crate::palette::dominant_colors(drawn, 4));
+        assert_eq!(colors, Some(vec![[10, 20, 30]]));
+        assert_eq!(frames.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,133 @@
+//! Periodically reports that the daemon is alive, for external monitoring tools.
+//!
+//! Every second, we send a JSON line to a unix datagram socket, like so:
+//!
+//! `{"ts":<unix time in ms>,"outputs":<number of outputs>,"fps_actual":<frames drawn per second>}`
+//!
+//! We send it whether or not someone is listening on the other end.
+
+use log::{error, warn};
+
+use std::{
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{wallpaper::frames_drawn, Generation};
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+static THREADS: Generation = Generation::new();
+
+/// Starts sending heartbeats to `path`, instead of wherever we sent them before
+pub fn spawn(path: PathBuf, outputs: Arc<AtomicUsize>) {
+    let generation = THREADS.next();
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("failed to create heartbeat socket: {e}");
+            return;
+        }
+    };
+
+    if let Err(e) = thread::Builder::new()
+        .name("heartbeat".to_string())
+        .stack_size(1 << 15)
+        .spawn(move || {
+            let mut warned = false;
+            let mut last_frames = frames_drawn();
+            let mut last_beat = Instant::now();
+            let mut next_beat = last_beat + INTERVAL;
+            while THREADS.is_current(generation) {
+                thread::sleep(next_beat.saturating_duration_since(Instant::now()));
+                next_beat += INTERVAL;
+                if !THREADS.is_current(generation) {
+                    break;
+                }
+
+                let frames = frames_drawn();
+                let fps = (frames - last_frames) as f32 / last_beat.elapsed().as_secs_f32();
+                last_frames = frames;
+                last_beat = Instant::now();
+
+                let msg = heartbeat_msg(unix_millis(), outputs.load(Ordering::Acquire), fps);
+                match socket.send_to(msg.as_bytes(), &path) {
+                    Ok(_) => warned = false,
+                    Err(e) => {
+                        // Not having anyone listening is normal, so we only warn once
+                        if !warned {
+                            warn!("failed to send heartbeat to {path:?}: {e}");
+                            warned = true;
+                        }
+                    }
+                }
+            }
+        })
+    {
+        error!("failed to spawn 'heartbeat' thread: {e}");
+    }
+}
+
+/// Stops sending heartbeats
+pub fn stop() {
+    THREADS.next();
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+fn heartbeat_msg(ts: u128, outputs: usize, fps: f32) -> String {
+    format!("{{\"ts\":{ts},\"outputs\":{outputs},\"fps_actual\":{fps:.1}}}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_ts(socket: &UnixDatagram) -> u128 {
+        let mut buf = [0; 256];
+        let len = socket.recv(&mut buf).unwrap();
+        let msg = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(msg.starts_with("{\"ts\":") && msg.ends_with("}\n"), "{msg}");
+        assert!(msg.contains("\"outputs\":3"), "{msg}");
+        assert!(msg.contains("\"fps_actual\":"), "{msg}");
+        msg["{\"ts\":".len()..msg.find(',').unwrap()]
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn should_send_heartbeats_every_second() {
+        let _generations = crate::GENERATIONS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let path = std::env::temp_dir().join(format!("swww-heartbeat-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        spawn(path.clone(), Arc::new(AtomicUsize::new(3)));
+        std::thread::sleep(Duration::from_millis(2500));
+
+        let first = read_ts(&listener);
+        let second = read_ts(&listener);
+        let _ = std::fs::remove_file(&path);
+        assert!(
+            (800..1200).contains(&(second - first)),
+            "heartbeats were {}ms apart",
+            second - first
+        );
+    }
+}
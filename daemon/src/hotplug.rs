@@ -0,0 +1,290 @@
+//! Displays something on outputs as soon as they appear, like when docking a laptop.
+//!
+//! We remember what we last displayed on every output, by name, and what we last displayed on all
+//! of them at once. An output that comes back gets what it last displayed, and one we never saw
+//! before gets what all the others display. Only if we know neither do we fall back to the image
+//! cache. The daemon can't resize images itself, so, like restoring from the cache, we spawn
+//! `swww` to do it for us.
+//!
+//! Outputs that change size, scale or transform lose what they displayed the same way, so we
+//! display it again, remade for their new size, resuming animations on the frame they were on.
+//! Either way, images are made again with the same options the client made them with (see
+//! [`Remake`]), and only once the output is configured. Mirrors (see [`crate::mirror`]) have
+//! nothing of their own to lose, so their source draws for them again.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use utils::ipc::{BgImg, Remake};
+
+use crate::wallpaper::Wallpaper;
+
+/// How long we give an output to be configured, before displaying anything on it anyway
+const CONFIGURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What an output displays, and how the client made it, if it is an image
+pub type Shown = (BgImg, Remake);
+
+#[derive(Default)]
+pub struct Hotplug {
+    /// What we last displayed on every output, by name
+    by_output: HashMap<String, Shown>,
+    /// What we last displayed on every output at once
+    default: Option<Shown>,
+}
+
+impl Hotplug {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that every image in `imgs` is now displayed on the outputs, by name, alongside it.
+    /// `all` are the names of every output we have. An image displayed on all of them becomes
+    /// the default for outputs we never saw
+    pub fn displayed(&mut self, imgs: &[(Shown, Vec<&str>)], all: &[&str]) {
+        // The client splits a request to display an image everywhere by output size, so we have to
+        // join the names back together to find out whether it covers every output
+        let mut covered: Vec<(&Shown, Vec<&str>)> = Vec::new();
+        for (shown, names) in imgs {
+            for name in names {
+                self.by_output.insert(name.to_string(), shown.clone());
+            }
+            match covered.iter_mut().find(|(s, _)| s.0 == shown.0) {
+                Some((_, covered_names)) => covered_names.extend_from_slice(names),
+                None => covered.push((shown, names.clone())),
+            }
+        }
+        if let Some((shown, _)) = covered
+            .into_iter()
+            .find(|(_, names)| !all.is_empty() && all.iter().all(|n| names.contains(n)))
+        {
+            self.default = Some(shown.clone());
+        }
+    }
+
+    /// What an output called `name` should display when it appears, if we know
+    pub fn for_output(&self, name: &str) -> Option<&Shown> {
+        self.by_output.get(name).or(self.default.as_ref())
+    }
+}
+
+/// Once `wallpaper` is configured, displays `shown` on it, or, if we don't know what it should
+/// display, what it displayed the last time, from the cache. At startup, `on_start` says whether
+/// anything else was displayed since, in which case we leave it
+pub fn restore(wallpaper: Arc<Wallpaper>, shown: Option<Shown>, on_start: Option<Arc<AtomicBool>>) {
+    if let Err(e) = std::thread::Builder::new()
+        .name("cache loader".to_string())
+        .stack_size(1 << 14)
+        .spawn(move || {
+            wait_until_configured(&wallpaper);
+            if on_start.is_some_and(|restore| !restore.load(Ordering::Acquire)) {
+                return;
+            }
+            let name = wallpaper.name();
+            let shown = match shown {
+                Some(shown) => Some(shown),
+                None => match utils::cache::previous_image(name) {
+                    Ok(previous) => previous.map(|(path, remake)| (BgImg::Img(path), remake)),
+                    Err(e) => {
+                        warn!("failed to read the cache of output {name}: {e}");
+                        None
+                    }
+                },
+            };
+            if let Some((img, remake)) = shown {
+                if let Err(e) = apply(name, &img, &remake, None) {
+                    warn!("failed to restore output {name}: {e}");
+                }
+            }
+        })
+    {
+        warn!("failed to spawn `cache loader` thread: {e}");
+    }
+}
+
+/// Waits for the compositor to configure the surface of `wallpaper`, for at most
+/// [`CONFIGURE_TIMEOUT`]. Displaying anything before that would only be for the wrong size
+fn wait_until_configured(wallpaper: &Wallpaper) {
+    let start = Instant::now();
+    while !wallpaper.configured.load(Ordering::Acquire)
+        && start.elapsed() < CONFIGURE_TIMEOUT
+        && !crate::should_daemon_exit()
+    {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Once `wallpaper` is configured at its new size, displays again what it displayed before, if it
+/// must
+pub fn redisplay(wallpaper: &Arc<Wallpaper>) {
+    if !wallpaper.needs_redisplay() {
+        return;
+    }
+    let wallpaper = Arc::clone(wallpaper);
+    if let Err(e) = std::thread::Builder::new()
+        .name("redisplay".to_string())
+        .stack_size(1 << 14)
+        .spawn(move || {
+            wait_until_configured(&wallpaper);
+            // mirrors only need their source to draw for their new size
+            if let Some(source) = wallpaper.mirror_source() {
+                if wallpaper.take_redisplay().is_none() {
+                    source.redraw_mirrors();
+                }
+                return;
+            }
+            // another change may have come while we waited, and another thread may have taken it
+            let Some((img, remake, frame)) = wallpaper.take_redisplay() else {
+                return;
+            };
+            debug!(
+                "output {} changed size, displaying {img} again",
+                wallpaper.name()
+            );
+            if let Err(e) = apply(wallpaper.name(), &img, &remake, frame) {
+                warn!("failed to redisplay output {}: {e}", wallpaper.name());
+            }
+        })
+    {
+        warn!("failed to spawn `redisplay` thread: {e}");
+    }
+}
+
+/// Spawns the client to display `img` on the output called `name`, making it like `remake` says,
+/// and starting animations on `start_frame`. Images read from stdin can't be read again, so we
+/// can't display them
+pub fn apply(
+    name: &str,
+    img: &BgImg,
+    remake: &Remake,
+    start_frame: Option<usize>,
+) -> Result<(), String> {
+    let mut command = crate::auth::swww_command();
+    match img {
+        BgImg::Color(color) => command.args(["clear", &hex(*color), &format!("--outputs={name}")]),
+        BgImg::Gradient(gradient) => command.args([
+            "clear",
+            "--gradient",
+            &hex(gradient.from),
+            &hex(gradient.to),
+            &format!("--angle={}", gradient.angle),
+            &format!("--outputs={name}"),
+        ]),
+        BgImg::Shader { path, fps } => command.args([
+            "shader",
+            &format!("--fps={fps}"),
+            &format!("--outputs={name}"),
+            "--",
+            path,
+        ]),
+        BgImg::Img(path) if path == "STDIN" => {
+            debug!("not displaying an image read from stdin on output {name}");
+            return Ok(());
+        }
+        BgImg::Img(path) => {
+            // it may be gone, and the path of the image is absolute anyway
+            if Path::new(&remake.cwd).is_dir() {
+                command.current_dir(&remake.cwd);
+            }
+            // after the options it was made with, which ours override
+            command.arg("img").args(remake.args.iter()).args([
+                &format!("--outputs={name}"),
+                "--transition-type=none",
+                &format!("--start-frame={}", start_frame.unwrap_or(0)),
+                "--",
+                path,
+            ])
+        }
+    };
+    match command.spawn() {
+        Ok(mut child) => {
+            // reap the child, so it doesn't linger as a zombie
+            if let Err(e) = child.wait() {
+                warn!("failed to wait for the client displaying on output {name}: {e}");
+            }
+            Ok(())
+        }
+        Err(e) => Err(format!("failed to spawn child process: {e}")),
+    }
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("{r:02x}{g:02x}{b:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn img(path: &str) -> Shown {
+        (BgImg::Img(path.to_string()), Remake::default())
+    }
+
+    #[test]
+    fn remembers_every_output() {
+        let mut hotplug = Hotplug::new();
+        hotplug.displayed(&[(img("a"), vec!["DP-1"])], &["DP-1", "DP-2"]);
+        hotplug.displayed(&[(img("b"), vec!["DP-2"])], &["DP-1", "DP-2"]);
+        assert_eq!(hotplug.for_output("DP-1"), Some(&img("a")));
+        assert_eq!(hotplug.for_output("DP-2"), Some(&img("b")));
+        assert_eq!(hotplug.for_output("HDMI-A-1"), None);
+    }
+
+    #[test]
+    fn image_on_every_output_is_the_default() {
+        let mut hotplug = Hotplug::new();
+        // split by output size, like the client does
+        hotplug.displayed(
+            &[(img("a"), vec!["DP-1"]), (img("a"), vec!["DP-2"])],
+            &["DP-1", "DP-2"],
+        );
+        assert_eq!(hotplug.for_output("HDMI-A-1"), Some(&img("a")));
+
+        // an output that came back gets what it last displayed, even over a newer default
+        hotplug.displayed(&[(img("b"), vec!["DP-1"])], &["DP-1"]);
+        let black = (BgImg::Color([0; 3]), Remake::default());
+        hotplug.displayed(&[(black.clone(), vec!["DP-2"])], &["DP-2"]);
+        assert_eq!(hotplug.for_output("DP-1"), Some(&img("b")));
+        assert_eq!(hotplug.for_output("HDMI-A-1"), Some(&black));
+    }
+
+    #[test]
+    fn different_images_are_no_default() {
+        let mut hotplug = Hotplug::new();
+        hotplug.displayed(
+            &[(img("a"), vec!["DP-1"]), (img("b"), vec!["DP-2"])],
+            &["DP-1", "DP-2"],
+        );
+        assert_eq!(hotplug.for_output("HDMI-A-1"), None);
+    }
+
+    #[test]
+    fn images_keep_how_they_were_made_on_every_output() {
+        let made = |args: &[&str]| {
+            let (img, _) = img("a");
+            let remake = Remake {
+                cwd: "/".to_string(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+            };
+            (img, remake)
+        };
+        let (fit, nearest) = (made(&["--resize=fit"]), made(&["--filter=Nearest"]));
+        let mut hotplug = Hotplug::new();
+        hotplug.displayed(
+            &[(fit.clone(), vec!["DP-1"]), (nearest.clone(), vec!["DP-2"])],
+            &["DP-1", "DP-2"],
+        );
+        assert_eq!(hotplug.for_output("DP-1"), Some(&fit));
+        assert_eq!(hotplug.for_output("DP-2"), Some(&nearest));
+        // it is still the same image everywhere
+        assert_eq!(hotplug.for_output("HDMI-A-1"), Some(&fit));
+    }
+}
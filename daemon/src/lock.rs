@@ -0,0 +1,47 @@
+//! A lock file, so that only one daemon can run at a time, even if a previous one left a stale
+//! socket behind.
+
+use nix::fcntl::{flock, FlockArg};
+
+use std::{fs::File, os::fd::AsRawFd, path::Path};
+
+/// The lock is held for as long as this lives. It is also released if the daemon dies, since the
+/// kernel closes the file then
+pub struct LockFile {
+    _file: File,
+}
+
+pub fn acquire(path: &Path) -> Result<LockFile, String> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("failed to open lock file {path:?}: {e}"))?;
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => Ok(LockFile { _file: file }),
+        Err(nix::errno::Errno::EWOULDBLOCK) => Err(format!(
+            "lock file {path:?} is held by another swww-daemon, which must still be running"
+        )),
+        Err(e) => Err(format!("failed to lock {path:?}: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_daemon_should_hold_the_lock() {
+        let path = std::env::temp_dir().join(format!("swww-lock-{}", std::process::id()));
+        let first = acquire(&path);
+        let second = acquire(&path);
+        assert!(first.is_ok());
+        assert!(second.is_err());
+
+        // once the first daemon exits, another one may start
+        drop(first);
+        assert!(acquire(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+}
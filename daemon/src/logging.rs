@@ -0,0 +1,354 @@
+//! The daemon's logger.
+//!
+//! Every record goes to stderr, and, with `--log-file` and `--journald`, to a file and to the
+//! systemd journal. Which records we keep is up to a [`Filter`], like `info,animations=debug`: a
+//! level for every module, and levels of their own for the modules (and their submodules) that are
+//! named. `--log-level` picks the first one, and `swww log --level` replaces it while we run, so
+//! that users can see what a misbehaving animation does without restarting us.
+//!
+//! After `swww log --follow`, we also keep the client's socket, and write every record the filter
+//! lets through to it, as a line of JSON: `{"time_ms":...,"level":"debug","target":"...",
+//! "message":"..."}`. Like with `swww subscribe`, lines are only ever written whole, and clients
+//! that don't read them get dropped.
+
+use std::{
+    fmt,
+    fs::File,
+    os::unix::net::{UnixDatagram, UnixStream},
+    path::Path,
+    str::FromStr,
+    sync::{Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simplelog::{
+    ColorChoice, Config, ConfigBuilder, TermLogger, TerminalMode, ThreadLogMode, WriteLogger,
+};
+
+use crate::events::{json_string, Subscriber};
+
+static FILTER: RwLock<Filter> = RwLock::new(Filter {
+    default: LevelFilter::Info,
+    modules: Vec::new(),
+});
+
+static FOLLOWERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+static SINKS: RwLock<Sinks> = RwLock::new(Sinks {
+    file: None,
+    journal: None,
+});
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Which records to keep, by the level of the module they come from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    /// Longest first, so that the first one a record's module is in is the most specific
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            default: if cfg!(debug_assertions) {
+                LevelFilter::Debug
+            } else {
+                LevelFilter::Info
+            },
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Comma separated levels, with `module=` before those for a single module. Our own modules
+    /// may go without the `swww_daemon::` in front of them
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = |level: &str| {
+            level.trim().parse::<LevelFilter>().map_err(|_| {
+                format!(
+                    "invalid log level {level:?}, expected off, error, warn, info, debug or trace"
+                )
+            })
+        };
+        let mut filter = Self::default();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, _)) if module.trim().is_empty() => {
+                    return Err(format!("missing the module in log filter {directive:?}"))
+                }
+                Some((module, lvl)) => {
+                    let module = module.trim();
+                    let lvl = level(lvl)?;
+                    filter.modules.retain(|(m, _)| m != module);
+                    filter.modules.push((module.to_string(), lvl));
+                }
+                None => filter.default = level(directive)?,
+            }
+        }
+        filter
+            .modules
+            .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (module, level) in &self.modules {
+            write!(f, ",{module}={}", level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+impl Filter {
+    /// The most verbose level records from `target` are kept at
+    fn level(&self, target: &str) -> LevelFilter {
+        let ours = target.strip_prefix("swww_daemon::");
+        let within = |target: &str, module: &str| {
+            target
+                .strip_prefix(module)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        };
+        self.modules
+            .iter()
+            .find(|(module, _)| within(target, module) || ours.is_some_and(|t| within(t, module)))
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// The most verbose level any module is kept at
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// Replaces the filter, see the module docs
+pub fn set_filter(filter: Filter) {
+    log::set_max_level(filter.max());
+    *FILTER.write().unwrap() = filter;
+}
+
+pub fn filter() -> Filter {
+    FILTER.read().unwrap().clone()
+}
+
+/// Sends every record from now on to `stream` too, see the module docs
+pub fn follow(stream: UnixStream) {
+    if let Err(e) = stream.set_nonblocking(true) {
+        log::debug!("refusing log follower, we can't make its socket non blocking: {e}");
+        return;
+    }
+    FOLLOWERS.lock().unwrap().push(Subscriber::new(stream));
+}
+
+/// Starts logging, with `filter`. Failing to open the log file is fatal, but not reaching the
+/// journal, since we may just not be running under systemd
+pub fn init(filter: Filter, log_file: Option<&Path>, journald: bool) -> Result<(), String> {
+    let logger = Logger {
+        // the filter already picked what to keep
+        term: TermLogger::new(
+            LevelFilter::Trace,
+            config(),
+            TerminalMode::Stderr,
+            ColorChoice::AlwaysAnsi,
+        ),
+    };
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| format!("failed to set logger: {e}"))?;
+    set_filter(filter);
+    set_sinks(log_file, journald)
+}
+
+/// Sends the records to the file at `log_file`, and with `journald`, to the journal, besides
+/// stderr, instead of wherever they went before, see [`init`]
+pub fn set_sinks(log_file: Option<&Path>, journald: bool) -> Result<(), String> {
+    let file = match log_file {
+        Some(path) => Some(file_logger(path, config())?),
+        None => None,
+    };
+    let journal = journald.then(|| {
+        let journal = UnixDatagram::unbound().and_then(|socket| {
+            socket.connect(JOURNAL_SOCKET)?;
+            Ok(socket)
+        });
+        journal.map_err(|e| format!("failed to connect to the journal at {JOURNAL_SOCKET}: {e}"))
+    });
+    let (journal, journal_error) = match journal {
+        Some(Ok(journal)) => (Some(journal), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    let old = std::mem::replace(&mut *SINKS.write().unwrap(), Sinks { file, journal });
+    // what the old file got, it must keep
+    if let Some(file) = old.file {
+        file.flush();
+    }
+    if let Some(e) = journal_error {
+        log::warn!("{e}");
+    }
+    Ok(())
+}
+
+fn config() -> Config {
+    ConfigBuilder::new()
+        .set_thread_level(LevelFilter::Error) // let me see where the processing is happening
+        .set_thread_mode(ThreadLogMode::Both)
+        .build()
+}
+
+fn file_logger(path: &Path, config: Config) -> Result<Box<WriteLogger<File>>, String> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("failed to open log file {path:?}: {e}"))?;
+    Ok(WriteLogger::new(LevelFilter::Trace, config, file))
+}
+
+struct Logger {
+    term: Box<TermLogger>,
+}
+
+/// Where the records go besides stderr
+struct Sinks {
+    file: Option<Box<WriteLogger<File>>>,
+    journal: Option<UnixDatagram>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= FILTER.read().unwrap().level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.term.log(record);
+        // nothing here may log, or we would deadlock
+        let sinks = SINKS.read().unwrap();
+        if let Some(file) = &sinks.file {
+            file.log(record);
+        }
+        if let Some(journal) = &sinks.journal {
+            let _ = journal.send(&journal_entry(record));
+        }
+        drop(sinks);
+        let mut followers = FOLLOWERS.lock().unwrap();
+        if !followers.is_empty() {
+            let json = record_json(record);
+            followers.retain_mut(|follower| follower.send(&json).is_ok());
+        }
+    }
+
+    fn flush(&self) {
+        self.term.flush();
+        if let Some(file) = &SINKS.read().unwrap().file {
+            file.flush();
+        }
+    }
+}
+
+fn record_json(record: &Record) -> String {
+    let time_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    format!(
+        "{{\"time_ms\":{time_ms},\"level\":\"{}\",\"target\":{},\"message\":{}}}\n",
+        record.level().as_str().to_lowercase(),
+        json_string(record.target()),
+        json_string(&record.args().to_string())
+    )
+}
+
+/// A record in the journal's native protocol: one `FIELD=value` per line, except for values with
+/// newlines, which go after their length instead. Records too long for a datagram are lost
+fn journal_entry(record: &Record) -> Vec<u8> {
+    let priority = match record.level() {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+    let mut entry = Vec::new();
+    let mut field = |name: &str, value: &[u8]| {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains(&b'\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value);
+        entry.push(b'\n');
+    };
+    field("PRIORITY", priority.as_bytes());
+    field("SYSLOG_IDENTIFIER", b"swww-daemon");
+    field("CODE_MODULE", record.target().as_bytes());
+    if let Some(file) = record.file() {
+        field("CODE_FILE", file.as_bytes());
+    }
+    if let Some(line) = record.line() {
+        field("CODE_LINE", line.to_string().as_bytes());
+    }
+    field("MESSAGE", record.args().to_string().as_bytes());
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_should_pick_the_most_specific_module() {
+        let filter: Filter = "warn, animations=debug,animations::stream=trace,wayland_client=off"
+            .parse()
+            .unwrap();
+        assert_eq!(filter.level("swww_daemon"), LevelFilter::Warn);
+        assert_eq!(filter.level("swww_daemon::wallpaper"), LevelFilter::Warn);
+        assert_eq!(filter.level("swww_daemon::animations"), LevelFilter::Debug);
+        assert_eq!(
+            filter.level("swww_daemon::animations::stream"),
+            LevelFilter::Trace
+        );
+        assert_eq!(filter.level("swww_daemon::animationsx"), LevelFilter::Warn);
+        assert_eq!(filter.level("wayland_client::conn"), LevelFilter::Off);
+        assert_eq!(filter.max(), LevelFilter::Trace);
+        assert_eq!(
+            filter.to_string(),
+            "warn,animations::stream=trace,wayland_client=off,animations=debug"
+        );
+        assert_eq!(filter.to_string().parse::<Filter>(), Ok(filter));
+
+        assert_eq!("".parse::<Filter>(), Ok(Filter::default()));
+        assert!("loud".parse::<Filter>().is_err());
+        assert!("animations=".parse::<Filter>().is_err());
+        assert!("=debug".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn journal_entries_should_frame_multiline_messages() {
+        let entry = journal_entry(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("swww_daemon::wallpaper")
+                .args(format_args!("a\nb"))
+                .build(),
+        );
+        let mut expected = b"PRIORITY=4\nSYSLOG_IDENTIFIER=swww-daemon\n\
+                             CODE_MODULE=swww_daemon::wallpaper\nMESSAGE\n"
+            .to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(entry, expected);
+    }
+}
@@ -2,26 +2,74 @@
 //! them fail there is no point in continuing. All of the initialization code, for example, is full
 //! of `expects`, **on purpose**, because we **want** to unwind and exit when they happen
 
+#[cfg(feature = "xdg-activation")]
+mod activation;
+mod adjust;
 mod animations;
+mod auth;
+mod battery;
+mod buffers;
+mod cli;
+mod crossfade;
+mod cursor;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod dylib;
+mod events;
+mod fractional;
+mod gc;
+mod gl;
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(test)]
+mod headless;
+mod heartbeat;
+mod hotplug;
+mod lock;
+mod logging;
+mod metrics;
+mod mirror;
+#[cfg(any(test, feature = "x11"))]
+mod nullserver;
+mod occlusion;
+mod overlay;
+mod palette;
+mod parallax;
+mod placement;
+mod power;
+mod query;
+mod schedule;
+mod script;
+mod session_lock;
+mod shader;
+mod shm_format;
+mod slideshow;
+mod stats;
+mod surface;
+mod tint;
 mod wallpaper;
-use log::{debug, error, info, warn, LevelFilter};
+mod watch;
+#[cfg(feature = "x11")]
+mod x11;
+use log::{debug, error, info, warn};
 use nix::{
     poll::{poll, PollFd, PollFlags},
     sys::signal::{self, SigHandler, Signal},
 };
-use rkyv::{boxed::ArchivedBox, string::ArchivedString};
-use simplelog::{ColorChoice, TermLogger, TerminalMode, ThreadLogMode};
-use wallpaper::Wallpaper;
+use rkyv::{boxed::ArchivedBox, string::ArchivedString, Deserialize};
+use surface::LayerBackend;
+use wallpaper::{PoolBuffers, Wallpaper};
 
 use std::{
     fs,
+    io::{BufWriter, Read, Write},
     num::NonZeroI32,
     os::{
-        fd::{BorrowedFd, RawFd},
+        fd::{BorrowedFd, FromRawFd, RawFd},
         unix::net::{UnixListener, UnixStream},
     },
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc, Mutex, OnceLock,
     },
 };
@@ -32,11 +80,12 @@ use smithay_client_toolkit::{
     output::{OutputHandler, OutputState},
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
+    seat::SeatState,
     shell::{
         wlr_layer::{Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure},
         WaylandSurface,
     },
-    shm::{multi::MultiPool, Shm, ShmHandler},
+    shm::{Shm, ShmHandler},
 };
 
 use wayland_client::{
@@ -45,9 +94,13 @@ use wayland_client::{
     Connection, QueueHandle,
 };
 
-use utils::ipc::{get_socket_path, Answer, ArchivedRequest, BgInfo, Request};
+use utils::ipc::{
+    get_socket_path, Adjustments, Answer, ArchivedAdjustments, ArchivedImageRequest,
+    ArchivedRequest, BgInfo, CpuLimits, Error, ErrorKind, Remake, Request, Stats,
+    TransitionPreview,
+};
 
-use animations::Animator;
+use animations::{Animator, InterruptPolicy, Start};
 
 // We need this because this might be set by signals, so we can't keep it in the daemon
 static EXIT: AtomicBool = AtomicBool::new(false);
@@ -62,10 +115,41 @@ fn should_daemon_exit() -> bool {
     EXIT.load(Ordering::Acquire)
 }
 
+/// Stops the threads of a feature that was turned off, or set up again, when the configuration
+/// file is reloaded: every thread keeps the generation it was spawned in, and only runs while it
+/// is the current one
+#[derive(Default)]
+pub struct Generation(AtomicU64);
+
+impl Generation {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Starts a new generation, and returns it. The threads of those before stop
+    pub fn next(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Whether a thread spawned in `generation` should keep running
+    pub fn is_current(&self, generation: u64) -> bool {
+        !should_daemon_exit() && self.0.load(Ordering::Acquire) == generation
+    }
+}
+
+/// Taken by the tests that start or stop the threads of a [`Generation`]: only one of its
+/// generations runs at a time, so they would stop each other's threads otherwise
+#[cfg(test)]
+static GENERATIONS: Mutex<()> = Mutex::new(());
+
 static POLL_WAKER: OnceLock<RawFd> = OnceLock::new();
 
 pub fn wake_poll() {
-    if let Err(e) = nix::unistd::write(*unsafe { POLL_WAKER.get().unwrap_unchecked() }, &[0]) {
+    // headless wallpapers draw without a main loop to wake
+    let Some(&waker) = POLL_WAKER.get() else {
+        return;
+    };
+    if let Err(e) = nix::unistd::write(waker, &[0]) {
         error!("failed to write to pipe file descriptor: {e}");
     }
 }
@@ -75,30 +159,94 @@ extern "C" fn signal_handler(_s: i32) {
 }
 
 fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli = cli::Cli::with_config(&args)?;
+    // our socket and cache, and the clients we spawn, all find the namespace in the environment
+    if let Some(namespace) = &cli.namespace {
+        std::env::set_var(utils::ipc::NAMESPACE_VAR, namespace);
+    }
+    if let Some(namespace) = utils::ipc::namespace() {
+        utils::ipc::check_namespace(&namespace)?;
+    }
+    let _lock = cli.lock_file.as_deref().map(lock::acquire).transpose()?;
+    logging::init(
+        cli.log_filter.clone(),
+        cli.log_file.as_deref(),
+        cli.journald,
+    )?;
+    let (nice, idle) = (cli.nice, cli.idle_priority);
     rayon::ThreadPoolBuilder::default()
         .thread_name(|i| format!("rayon thread {i}"))
+        .num_threads(cli.threads.unwrap_or(0))
+        .start_handler(move |_| {
+            if let Err(e) = utils::cpu::lower_priority(nice, idle) {
+                warn!("{e}");
+            }
+        })
         .build_global()
         .expect("failed to configure rayon global thread pool");
-    make_logger();
-    let listener = SocketWrapper::new()?;
+    if cli.render_transition {
+        #[cfg(feature = "gpu")]
+        gpu::enable(cli.renderer == cli::Renderer::Gpu);
+        return render_transition_preview();
+    }
+    let mut listener = SocketWrapper::new()?;
     let wake = setup_signals_and_pipe();
 
-    let conn = Connection::connect_to_env().expect("failed to connect to the wayland server");
+    let stream = cli.wayland_backend.connect()?;
+    // with no wayland server, we run on one of our own, whose outputs are the X monitors
+    #[cfg(feature = "x11")]
+    let (stream, screen) = match stream {
+        None if x11::is_session(|name| std::env::var_os(name)) => {
+            let screen = x11::Screen::open()?;
+            info!("no wayland server, drawing on the X11 root window");
+            let names: Vec<_> = screen.monitors().iter().map(|m| m.name.as_str()).collect();
+            (Some(nullserver::serve(&names)?), Some(Arc::new(screen)))
+        }
+        stream => (stream, None),
+    };
+    let conn = match stream {
+        Some(stream) => Connection::from_socket(stream),
+        None => Connection::connect_to_env(),
+    }
+    .expect("failed to connect to the wayland server");
     // Enumerate the list of globals to get the protocols the server implements.
     let (globals, mut event_queue) =
         registry_queue_init(&conn).expect("failed to initialize the event queue");
     let qh = event_queue.handle();
 
-    let mut daemon = Daemon::new(&globals, &qh);
-
-    if let Ok(true) = sd_notify::booted() {
-        if let Err(e) = sd_notify::notify(true, &[sd_notify::NotifyState::Ready]) {
-            error!("Error sending status update to systemd: {}", e.to_string());
-        }
+    let mut daemon = Daemon::new(globals, &qh);
+    #[cfg(feature = "x11")]
+    {
+        daemon.x11 = screen;
     }
+    daemon.args = args;
+    daemon.cpu_limits = CpuLimits {
+        threads: cli.threads.map(|threads| threads as u32),
+        nice,
+        idle,
+    };
+    #[cfg(feature = "dbus")]
+    dbus::spawn();
+    #[cfg(feature = "dbus")]
+    dbus::watch_session_lock();
+    daemon.configure(cli)?;
+
+    // find the outputs, so that we know which surfaces must be configured before we are ready
+    event_queue
+        .roundtrip(&mut daemon)
+        .expect("failed to roundtrip the event queue");
+    let mut notified_ready = false;
     info!("Initialization succeeded! Starting main loop...");
     let mut buf = [0; 16];
     while !should_daemon_exit() {
+        session_lock::report();
+        if !notified_ready && daemon.is_configured() {
+            notify_ready();
+            notified_ready = true;
+            daemon.scheduler.ready();
+        }
+
         // Process wayland events
         event_queue
             .flush()
@@ -111,7 +259,7 @@ fn main() -> Result<(), String> {
             let connection_fd = read_guard.connection_fd();
             let waker = unsafe { BorrowedFd::borrow_raw(wake) };
             let mut fds = [
-                PollFd::new(&listener.0, PollFlags::POLLIN),
+                PollFd::new(&listener.listener, PollFlags::POLLIN),
                 PollFd::new(&connection_fd, PollFlags::POLLIN | PollFlags::POLLRDBAND),
                 PollFd::new(&waker, PollFlags::POLLIN),
             ];
@@ -138,7 +286,7 @@ fn main() -> Result<(), String> {
 
         if let Some(flags) = events[0] {
             if !flags.is_empty() {
-                match listener.0.accept() {
+                match listener.listener.accept() {
                     Ok((stream, _adr)) => daemon.recv_socket_msg(stream),
                     Err(e) => match e.kind() {
                         std::io::ErrorKind::WouldBlock => (),
@@ -157,6 +305,21 @@ fn main() -> Result<(), String> {
         }
     }
 
+    daemon.shutdown();
+    if let Err(e) = event_queue.flush() {
+        error!("failed to flush the event queue: {e}");
+    }
+    let removed = listener.remove();
+    if let Some(stream) = daemon.kill_stream.take() {
+        let answer = match removed {
+            Ok(()) => Answer::Ok,
+            Err(e) => Answer::Err(e.into()),
+        };
+        if let Err(e) = answer.send(&stream) {
+            error!("error sending answer to client: {e}");
+        }
+    }
+
     if let Err(e) = nix::unistd::close(*POLL_WAKER.get().unwrap()) {
         error!("error closing write pipe file descriptor: {e}");
     }
@@ -168,6 +331,16 @@ fn main() -> Result<(), String> {
     Ok(())
 }
 
+/// Tells systemd we are ready, if it started us with `Type=notify`. Clients that connected before
+/// were just waiting in the socket's backlog
+fn notify_ready() {
+    if let Ok(true) = sd_notify::booted() {
+        if let Err(e) = sd_notify::notify(true, &[sd_notify::NotifyState::Ready]) {
+            error!("Error sending status update to systemd: {e}");
+        }
+    }
+}
+
 /// Returns the file descriptor we should install in the poll handler
 fn setup_signals_and_pipe() -> RawFd {
     let handler = SigHandler::Handler(signal_handler);
@@ -181,9 +354,28 @@ fn setup_signals_and_pipe() -> RawFd {
 
 /// This is a wrapper that makes sure to delete the socket when it is dropped
 /// It also makes sure to set the listener to nonblocking mode
-struct SocketWrapper(UnixListener);
+///
+/// With systemd socket activation, we use the socket we inherited instead, and leave it for
+/// systemd to delete
+struct SocketWrapper {
+    listener: UnixListener,
+    /// `false` if systemd passed us the socket, or we already deleted it
+    delete: bool,
+}
+
 impl SocketWrapper {
     fn new() -> Result<Self, String> {
+        if let Some(listener) = inherited_listener()? {
+            info!("Using the socket systemd passed us");
+            if let Err(e) = listener.set_nonblocking(true) {
+                return Err(format!("failed to set socket to nonblocking mode: {e}"));
+            }
+            return Ok(Self {
+                listener,
+                delete: false,
+            });
+        }
+
         let socket_addr = get_socket_path();
         let runtime_dir = match socket_addr.parent() {
             Some(path) => path,
@@ -212,64 +404,398 @@ impl SocketWrapper {
             return Err(format!("failed to set socket to nonblocking mode: {e}"));
         }
 
-        Ok(Self(listener))
+        Ok(Self {
+            listener,
+            delete: true,
+        })
+    }
+
+    /// Deletes the socket now, rather than when dropped, so we can tell `swww kill` whether it
+    /// worked
+    fn remove(&mut self) -> Result<(), String> {
+        if !self.delete {
+            return Ok(());
+        }
+        self.delete = false;
+        let socket_addr = get_socket_path();
+        fs::remove_file(&socket_addr)
+            .map_err(|e| format!("failed to remove socket at {socket_addr:?}: {e}"))?;
+        info!("Removed socket at {:?}", socket_addr);
+        Ok(())
     }
 }
 
 impl Drop for SocketWrapper {
     fn drop(&mut self) {
-        let socket_addr = get_socket_path();
-        if let Err(e) = fs::remove_file(&socket_addr) {
-            error!("Failed to remove socket at {socket_addr:?}: {e}");
+        if let Err(e) = self.remove() {
+            error!("{e}");
         }
-        info!("Removed socket at {:?}", socket_addr);
     }
 }
 
+/// The listening socket systemd passed us in `$LISTEN_FDS`, if any. We only take the first
+fn inherited_listener() -> Result<Option<UnixListener>, String> {
+    let mut fds = sd_notify::listen_fds()
+        .map_err(|e| format!("failed to read the sockets systemd passed us: {e}"))?;
+    let Some(fd) = fds.next() else {
+        return Ok(None);
+    };
+    if fds.next().is_some() {
+        warn!("systemd passed us more than one socket, we only listen on the first");
+    }
+    // SAFETY: systemd made this fd for us, and nothing else in the daemon uses it
+    Ok(Some(unsafe { UnixListener::from_raw_fd(fd) }))
+}
+
+/// Reads a `TransitionPreview` from stdin, and writes all of its frames to stdout, one after the
+/// other
+fn render_transition_preview() -> Result<(), String> {
+    let mut bytes = Vec::new();
+    if let Err(e) = std::io::stdin().read_to_end(&mut bytes) {
+        return Err(format!("failed to read transition preview from stdin: {e}"));
+    }
+    let frames = animations::render_preview(TransitionPreview::receive(&bytes)?)?;
+
+    let mut writer = BufWriter::new(std::io::stdout().lock());
+    for frame in frames {
+        if let Err(e) = writer.write_all(&frame) {
+            return Err(format!("failed to write frame to stdout: {e}"));
+        }
+    }
+    if let Err(e) = writer.flush() {
+        return Err(format!("failed to write frame to stdout: {e}"));
+    }
+    Ok(())
+}
+
 struct Daemon {
     // Wayland stuff
     layer_shell: LayerShell,
     compositor_state: CompositorState,
     registry_state: RegistryState,
     output_state: OutputState,
+    seat_state: SeatState,
     shm: Shm,
-    pool: wallpaper::MtShmPool,
+    /// Kept to bind what the settings need when they change
+    globals: GlobalList,
+    qh: QueueHandle<Self>,
 
     // swww stuff
     wallpapers: Vec<Arc<Wallpaper>>,
+    /// Always equal to `wallpapers.len()`, but shared with the heartbeat thread
+    output_count: Arc<AtomicUsize>,
+    /// Only present if the user passed `--gc-interval`
+    gc: Option<Arc<Mutex<gc::Gc<Wallpaper, PoolBuffers>>>>,
+    /// Whether to draw at the logical size and let the compositor scale it to the output
+    compositor_scaling: bool,
+    /// The format the user asked for opaque buffers to be in
+    shm_format: shm_format::ShmFormat,
+    /// Only present if the user passed `--track-cursor`, and the compositor lets us set the cursor
+    cursor: Option<cursor::Cursor>,
+    /// The most pixels `--parallax` shifts the wallpapers by, if set
+    parallax: Option<u32>,
+    /// Only present if the compositor tells us when outputs are turned off
+    power: Option<power::OutputPower>,
+    /// Only present if the user didn't pass `--prefer-compositor-scaling`, and the compositor
+    /// supports fractional scaling
+    fractional: Option<fractional::FractionalScaling>,
+    /// Only present if the user didn't pass `--animate-occluded`, and the compositor tells us
+    /// about its windows
+    occlusion: Option<occlusion::Occlusion>,
+    #[cfg(feature = "xdg-activation")]
+    activation: Option<activation::Activation>,
     animator: Animator,
     initializing: bool,
+    /// `false` if the user passed `--no-restore`
+    restore: bool,
+    /// The layer of the wallpapers' surfaces, and their namespace, with `{output}` in it replaced
+    /// by the name of the output
+    layer: Layer,
+    layer_namespace: String,
+    /// Where on their outputs the wallpapers go
+    placement: placement::Placement,
+    /// What to display on outputs when they appear
+    hotplug: hotplug::Hotplug,
+    /// The slideshows running on the outputs, see `swww slideshow`
+    slideshows: slideshow::Slideshows,
+    /// The images we display again when their files change, see `swww img --watch`
+    watches: watch::Watches,
+    /// The images the `[schedule]` of the configuration file shows through the day
+    scheduler: schedule::Scheduler,
+    /// Whether we should still restore the outputs we found at startup. Any request that displays
+    /// something, or comes from `swww init` (which restores them itself), makes it `false`
+    restore_on_start: Arc<AtomicBool>,
+    /// What the user passed to `--threads`, `--nice` and `--idle-priority`
+    cpu_limits: CpuLimits,
+    /// The frame rates the user capped the outputs at, with `--max-fps`
+    max_fps: cli::MaxFps,
+    /// The command line arguments, to apply over the configuration file when we reload it
+    args: Vec<String>,
+    /// The settings we run with, see [`Self::configure`]
+    cli: Option<cli::Cli>,
+    /// The client that asked us to exit with `swww kill`. We only answer it once we shut down
+    kill_stream: Option<UnixStream>,
+    /// Only present on X11 sessions, where the wallpapers draw on the root window instead of on
+    /// the outputs of our own wayland server
+    #[cfg(feature = "x11")]
+    x11: Option<Arc<x11::Screen>>,
 }
 
 impl Daemon {
-    fn new(globals: &GlobalList, qh: &QueueHandle<Self>) -> Self {
+    fn new(globals: GlobalList, qh: &QueueHandle<Self>) -> Self {
         // The compositor (not to be confused with the server which is commonly called the compositor) allows
         // configuring surfaces to be presented.
         let compositor_state =
-            CompositorState::bind(globals, qh).expect("wl_compositor is not available");
+            CompositorState::bind(&globals, qh).expect("wl_compositor is not available");
 
-        let layer_shell = LayerShell::bind(globals, qh).expect("layer shell is not available");
+        let layer_shell = LayerShell::bind(&globals, qh).expect("layer shell is not available");
 
-        let shm = Shm::bind(globals, qh).expect("wl_shm is not available");
-        let pool = MultiPool::new(&shm).expect("failed to create MultiPool");
+        let shm = Shm::bind(&globals, qh).expect("wl_shm is not available");
 
         Self {
             // Outputs may be hotplugged at runtime, therefore we need to setup a registry state to
             // listen for Outputs.
-            registry_state: RegistryState::new(globals),
-            output_state: OutputState::new(globals, qh),
+            registry_state: RegistryState::new(&globals),
+            output_state: OutputState::new(&globals, qh),
+            seat_state: SeatState::new(&globals, qh),
             compositor_state,
             shm,
-            pool: Arc::new(Mutex::new(pool)),
             layer_shell,
+            power: power::OutputPower::new(&globals, qh),
+            #[cfg(feature = "xdg-activation")]
+            activation: activation::Activation::new(&globals, qh),
+            globals,
+            qh: qh.clone(),
 
             wallpapers: Vec::new(),
+            output_count: Arc::new(AtomicUsize::new(0)),
+            gc: None,
+            compositor_scaling: false,
+            shm_format: shm_format::ShmFormat::Xrgb8888,
+            cursor: None,
+            parallax: None,
+            fractional: None,
+            occlusion: None,
             animator: Animator::new(),
             initializing: true,
+            restore: true,
+            restore_on_start: Arc::new(AtomicBool::new(true)),
+            layer: Layer::Background,
+            layer_namespace: "swww".to_string(),
+            placement: placement::Placement::default(),
+            hotplug: hotplug::Hotplug::new(),
+            slideshows: slideshow::Slideshows::new(),
+            watches: watch::Watches::new(),
+            scheduler: schedule::Scheduler::new(),
+            args: Vec::new(),
+            cli: None,
+            kill_stream: None,
+            cpu_limits: CpuLimits::default(),
+            max_fps: cli::MaxFps::default(),
+            #[cfg(feature = "x11")]
+            x11: None,
+        }
+    }
+
+    /// Applies `cli`, the settings we start with, or, when the configuration file is reloaded, its
+    /// settings with the command line's over them again. Only the options that make us what we
+    /// are, like our namespace, can't change while we run: if they did, we fail, after applying
+    /// everything else. The settings of the wallpapers' surfaces make us make them again
+    fn configure(&mut self, mut cli: cli::Cli) -> Result<(), String> {
+        let old = self.cli.take();
+        let reloading = old.is_some();
+        // whether any of the fields changed. They all do as we start
+        macro_rules! changed {
+            ($($field:ident),+) => {
+                old.as_ref().map_or(true, |old| $(old.$field != cli.$field)||+)
+            };
+        }
+        let qh = self.qh.clone();
+        let mut errors = Vec::new();
+
+        if let Some(old) = &old {
+            let startup_only = [
+                ("namespace", old.namespace != cli.namespace),
+                ("lock-file", old.lock_file != cli.lock_file),
+                ("threads", old.threads != cli.threads),
+                ("nice", old.nice != cli.nice),
+                ("idle-priority", old.idle_priority != cli.idle_priority),
+                (
+                    "wayland backend",
+                    old.wayland_backend != cli.wayland_backend,
+                ),
+            ];
+            let names: Vec<_> = startup_only
+                .iter()
+                .filter(|(_, changed)| *changed)
+                .map(|(name, _)| *name)
+                .collect();
+            if !names.is_empty() {
+                errors.push(format!(
+                    "{} only change when swww-daemon restarts",
+                    names.join(", ")
+                ));
+            }
+        }
+
+        self.restore = !cli.no_restore;
+        self.animator.interrupt_policy = cli.transition_interrupt_policy;
+        self.animator.frame_drop_recovery = cli.frame_drop_recovery;
+        self.animator.set_memory_limit(cli.memory_limit);
+        self.scheduler.configure(cli.schedule.clone());
+        auth::set_token(cli.ipc_auth_token.clone());
+        if changed!(max_fps) {
+            self.max_fps = cli.max_fps.clone();
+            for wallpaper in &self.wallpapers {
+                wallpaper.set_max_fps(self.max_fps.of(wallpaper.name()));
+            }
+        }
+
+        // `logging::init` already set them up as we started
+        if reloading {
+            logging::set_filter(cli.log_filter.clone());
+            if changed!(log_file, journald) {
+                if let Err(e) = logging::set_sinks(cli.log_file.as_deref(), cli.journald) {
+                    errors.push(e);
+                }
+            }
+        }
+        if changed!(lock_signals) {
+            if let Err(e) = session_lock::listen_to_signals(cli.lock_signals) {
+                errors.push(e);
+            }
+        }
+        if changed!(battery_policy) {
+            match cli.battery_policy {
+                Some((policy, threshold)) => battery::spawn(policy, threshold),
+                None => battery::stop(),
+            }
+        }
+        if changed!(heartbeat_socket) {
+            match &cli.heartbeat_socket {
+                Some(path) => heartbeat::spawn(path.clone(), Arc::clone(&self.output_count)),
+                None => heartbeat::stop(),
+            }
+        }
+        if changed!(statsd_host) {
+            match &cli.statsd_host {
+                Some(host) => metrics::spawn_statsd(host.clone()),
+                None => metrics::stop_statsd(),
+            }
+        }
+        if changed!(script) {
+            match &cli.script {
+                Some(path) => script::spawn(path.clone()),
+                None => script::stop(),
+            }
+        }
+        if changed!(gc_interval) {
+            match cli.gc_interval {
+                Some(interval) => {
+                    let gc = self.gc.get_or_insert_with(|| {
+                        let mut gc = gc::Gc::new();
+                        for wallpaper in &self.wallpapers {
+                            gc.track(wallpaper, wallpaper.pool_buffers());
+                        }
+                        Arc::new(Mutex::new(gc))
+                    });
+                    gc::spawn(Arc::clone(gc), interval);
+                }
+                None => {
+                    gc::stop();
+                    self.gc = None;
+                }
+            }
+        }
+        #[cfg(feature = "gpu")]
+        if changed!(renderer) {
+            gpu::enable(cli.renderer == cli::Renderer::Gpu);
+        }
+        if changed!(animate_occluded) {
+            self.watch_occlusion(!cli.animate_occluded, &qh);
+        }
+
+        // the surfaces of the wallpapers are made with these, so we make them again, and they
+        // restore what they displayed
+        let remake = reloading
+            && changed!(
+                prefer_compositor_scaling,
+                shm_format,
+                placement,
+                layer,
+                layer_namespace,
+                parallax,
+                track_cursor
+            );
+        let outputs = if remake {
+            self.remove_wallpapers()
+        } else {
+            Vec::new()
+        };
+        if changed!(prefer_compositor_scaling) {
+            self.compositor_scaling = cli.prefer_compositor_scaling;
+            self.fractional = if self.compositor_scaling {
+                None
+            } else {
+                fractional::FractionalScaling::new(&self.globals, &qh)
+            };
+        }
+        self.shm_format = cli.shm_format;
+        self.placement = cli.placement;
+        self.layer = cli.layer.unwrap_or(Layer::Background);
+        self.layer_namespace = cli
+            .layer_namespace
+            .clone()
+            .unwrap_or_else(|| "swww".to_string());
+        self.parallax = cli.parallax;
+        self.track_cursor(cli.track_cursor, &qh);
+        for output in outputs {
+            self.add_wallpaper(&qh, &output, true);
+        }
+        if remake {
+            self.update_occlusion();
+            info!("made the wallpapers again, with their new settings");
+        }
+
+        // we keep running with the settings we started with
+        if let Some(old) = old {
+            cli.namespace = old.namespace;
+            cli.lock_file = old.lock_file;
+            cli.threads = old.threads;
+            cli.nice = old.nice;
+            cli.idle_priority = old.idle_priority;
+            cli.wayland_backend = old.wayland_backend;
+        }
+        self.cli = Some(cli);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Removes the wallpapers of every output, and returns the outputs, to make them again with
+    /// [`Self::add_wallpaper`]
+    fn remove_wallpapers(&mut self) -> Vec<wl_output::WlOutput> {
+        let outputs: Vec<_> = self.output_state.outputs().collect();
+        for output in &outputs {
+            if let Some(info) = self.output_state.info(output) {
+                if let Some(wallpaper) = self.wallpapers.iter().find(|w| w.has_id(info.id)) {
+                    // its animations stop rather than draw on a surface that's gone
+                    wallpaper.inc_animation_id();
+                }
+                self.remove_wallpaper(info.id);
+            }
         }
+        outputs
     }
 
     fn recv_socket_msg(&mut self, stream: UnixStream) {
+        // a client failing to authenticate is no reason to exit, unlike failing to read a request
+        if let Err(e) = auth::authenticate(&stream, auth::token().as_deref()) {
+            warn!("refused connection: {e}");
+            return;
+        }
         let bytes = match utils::ipc::read_socket(&stream) {
             Ok(bytes) => bytes,
             Err(e) => {
@@ -278,88 +804,633 @@ impl Daemon {
                 return;
             }
         };
-        let request = Request::receive(&bytes);
+        let request = match Request::receive(&bytes) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("refused request: {e}");
+                if let Err(e) = Answer::Err(e).send(&stream) {
+                    error!("error sending answer to client: {e}");
+                }
+                return;
+            }
+        };
+        let subscribe = matches!(request, ArchivedRequest::Subscribe);
+        let follow_logs = matches!(request, ArchivedRequest::Log(log) if log.follow);
         let answer = match request {
             ArchivedRequest::Animation(animations) => {
                 let mut wallpapers = Vec::new();
-                for (_, names) in animations.iter() {
+                for (_, _, names) in animations.iter() {
                     wallpapers.push(self.find_wallpapers_by_names(names));
                 }
                 self.animator.animate(bytes, wallpapers)
             }
-            ArchivedRequest::Clear(clear) => {
-                self.initializing = false;
-                let wallpapers = self.find_wallpapers_by_names(&clear.outputs);
-                let color = clear.color;
-                match std::thread::Builder::new()
-                    .stack_size(1 << 15)
-                    .name("clear".to_string())
-                    .spawn(move || {
+            ArchivedRequest::AnimationFrames(_) => match self.animator.add_frames(bytes, &stream) {
+                Some(answer) => answer,
+                // the animation answers once it plays the frames
+                None => return,
+            },
+            ArchivedRequest::Clear(clear) => match self.resolve_outputs(&clear.outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => {
+                    self.initializing = false;
+                    self.restore_on_start.store(false, Ordering::Release);
+                    let fill = clear.fill();
+                    let names = wallpapers.iter().map(|w| w.name()).collect();
+                    self.record_displayed(&[((fill.clone(), Remake::default()), names)]);
+                    if clear.transition.is_some() {
+                        let groups = self.start_clear_request(wallpapers);
+                        self.animator.transition(bytes, groups)
+                    } else {
                         for wallpaper in &wallpapers {
-                            wallpaper.inc_animation_id();
+                            wallpaper.cancel_animations();
                         }
-                        for wallpaper in wallpapers {
-                            wallpaper.set_img_info(utils::ipc::BgImg::Color(color));
-                            let buffer = wallpaper.clear(color);
-                            wallpaper.draw(&buffer);
+                        match std::thread::Builder::new()
+                            .stack_size(1 << 15)
+                            .name("clear".to_string())
+                            .spawn(move || {
+                                for wallpaper in &wallpapers {
+                                    wallpaper.inc_animation_id();
+                                }
+                                for wallpaper in wallpapers {
+                                    wallpaper.set_img_info(fill.clone());
+                                    let buffer = match &fill {
+                                        utils::ipc::BgImg::Color(color) => wallpaper.clear(*color),
+                                        _ => wallpaper.fill(&animations::render_fill(
+                                            &fill,
+                                            wallpaper.get_dimensions(),
+                                            wallpaper.transform(),
+                                        )),
+                                    };
+                                    wallpaper.draw(&buffer);
+                                }
+                                wake_poll();
+                            }) {
+                            Ok(_) => Answer::Ok,
+                            Err(e) => {
+                                Answer::Err(format!("failed to spawn `clear` thread: {e}").into())
+                            }
                         }
-                        wake_poll();
-                    }) {
-                    Ok(_) => Answer::Ok,
-                    Err(e) => Answer::Err(format!("failed to spawn `clear` thread: {e}")),
+                    }
                 }
+            },
+            ArchivedRequest::Init => {
+                self.restore_on_start.store(false, Ordering::Release);
+                Answer::Init(self.is_configured())
             }
-            ArchivedRequest::Init => Answer::Init(
-                self.wallpapers
-                    .iter()
-                    .all(|w| w.configured.load(std::sync::atomic::Ordering::Acquire)),
-            ),
             ArchivedRequest::Kill => {
                 exit_daemon();
-                Answer::Ok
+                self.kill_stream = Some(stream);
+                return;
+            }
+            ArchivedRequest::PlaybackSpeed(speed, outputs) => {
+                if !speed.is_finite() || *speed <= 0.0 {
+                    Answer::Err(Error::new(
+                        ErrorKind::InvalidArgument,
+                        format!("playback speed must be positive, got: {speed}"),
+                    ))
+                } else {
+                    match self.resolve_outputs(outputs) {
+                        Ok(wallpapers) => {
+                            for wallpaper in wallpapers {
+                                wallpaper.set_playback_speed(*speed);
+                            }
+                            Answer::Ok
+                        }
+                        Err(e) => Answer::Err(e),
+                    }
+                }
             }
+            ArchivedRequest::Overlay(overlays) => {
+                let overlays = overlays
+                    .iter()
+                    .map(|(overlay, outputs)| {
+                        let overlay = overlay
+                            .as_ref()
+                            .map(overlay::Overlay::new)
+                            .transpose()
+                            .map_err(|e| Error::new(ErrorKind::InvalidArgument, e))?;
+                        Ok((overlay, self.resolve_outputs(outputs)?))
+                    })
+                    .collect::<Result<Vec<_>, Error>>();
+                match overlays {
+                    Err(e) => Answer::Err(e),
+                    Ok(overlays) => match std::thread::Builder::new()
+                        .stack_size(1 << 15)
+                        .name("overlay".to_string())
+                        .spawn(move || {
+                            for (overlay, wallpapers) in overlays {
+                                let overlay = overlay.map(Arc::new);
+                                for wallpaper in wallpapers {
+                                    wallpaper.set_overlay(overlay.clone());
+                                    // like for tints, animations will draw it soon enough, but
+                                    // still images must be drawn again
+                                    let (_, buffer) = wallpaper.canvas_change(|_| ());
+                                    if !wallpaper.is_hidden() {
+                                        wallpaper.draw(&buffer);
+                                    }
+                                }
+                            }
+                            wake_poll();
+                        }) {
+                        Ok(_) => Answer::Ok,
+                        Err(e) => {
+                            Answer::Err(format!("failed to spawn `overlay` thread: {e}").into())
+                        }
+                    },
+                }
+            }
+            ArchivedRequest::Palette(count, outputs) => match self.resolve_outputs(outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => Answer::Palette(
+                    wallpapers
+                        .into_iter()
+                        .map(|wallpaper| {
+                            let colors = wallpaper
+                                .with_drawn(|drawn| {
+                                    palette::dominant_colors(drawn, *count as usize)
+                                })
+                                .unwrap_or_default();
+                            (wallpaper.name().to_string(), colors.into_boxed_slice())
+                        })
+                        .collect(),
+                ),
+            },
+            ArchivedRequest::Pause(outputs) => match self.resolve_outputs(outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => {
+                    for wallpaper in wallpapers {
+                        wallpaper.set_paused(true);
+                    }
+                    Answer::Ok
+                }
+            },
             ArchivedRequest::Query => Answer::Info(self.wallpapers_info()),
-            ArchivedRequest::Img((_, imgs)) => {
-                self.initializing = false;
-                let mut used_wallpapers = Vec::new();
-                for img in imgs.iter() {
-                    let mut wallpapers = self.find_wallpapers_by_names(&img.1);
-                    for wallpaper in wallpapers.iter_mut() {
-                        wallpaper.inc_animation_id();
+            ArchivedRequest::QueryJson(verbose) => {
+                let gc_freed_bytes =
+                    verbose.then(|| self.gc.as_ref().map(|gc| gc.lock().unwrap().freed_bytes()));
+                let outputs: Vec<_> = self
+                    .outputs_info()
+                    .map(|(info, wallpaper)| {
+                        let animation =
+                            wallpaper
+                                .animation_frame()
+                                .map(|frame| query::AnimationState {
+                                    frame,
+                                    paused: wallpaper.is_paused(),
+                                    speed: wallpaper.playback_speed(),
+                                });
+                        (info, animation)
+                    })
+                    .collect();
+                Answer::Json(query::json(&outputs, gc_freed_bytes))
+            }
+            ArchivedRequest::ReloadConfig => {
+                match cli::Cli::with_config(&self.args).and_then(|cli| self.configure(cli)) {
+                    Ok(()) => {
+                        info!("reloaded the configuration file");
+                        Answer::Ok
+                    }
+                    Err(e) => Answer::Err(Error::new(
+                        ErrorKind::InvalidArgument,
+                        format!("failed to reload the configuration file: {e}"),
+                    )),
+                }
+            }
+            ArchivedRequest::Step(frames, outputs) => match self.resolve_outputs(outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => match wallpapers.iter().find(|w| !w.is_paused()) {
+                    Some(wallpaper) => Answer::Err(Error::new(
+                        ErrorKind::InvalidArgument,
+                        format!(
+                            "output {} is not paused, run `swww pause` first",
+                            wallpaper.name()
+                        ),
+                    )),
+                    None => {
+                        for wallpaper in wallpapers {
+                            wallpaper.add_steps(*frames);
+                        }
+                        Answer::Ok
+                    }
+                },
+            },
+            ArchivedRequest::Resume(outputs) => match self.resolve_outputs(outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => {
+                    for wallpaper in wallpapers {
+                        wallpaper.set_paused(false);
                     }
-                    used_wallpapers.push(wallpapers);
+                    Answer::Ok
                 }
+            },
+            ArchivedRequest::Slideshow(slideshow) => match self.resolve_outputs(&slideshow.outputs)
+            {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => match self.slideshows.start(slideshow, &wallpapers) {
+                    Ok(()) => Answer::Ok,
+                    Err(e) => Answer::Err(e.into()),
+                },
+            },
+            ArchivedRequest::Stats => Answer::Stats(Stats {
+                gc_freed_bytes: self.gc.as_ref().map(|gc| gc.lock().unwrap().freed_bytes()),
+                memory_limit: self.animator.memory_limit().map(|limit| limit as u64),
+                resident_bytes: self.animator.resident_bytes() as u64,
+                cpu_limits: self.cpu_limits,
+                outputs: self.wallpapers.iter().map(|w| w.stats()).collect(),
+            }),
+            ArchivedRequest::StopSlideshow(outputs) => {
+                let names: Vec<_> = outputs.iter().map(|n| n.to_string()).collect();
+                self.slideshows.stop(&names);
+                Answer::Ok
+            }
+            ArchivedRequest::Subscribe => Answer::Ok,
+            ArchivedRequest::Adjust(adjustments, _) if !valid_adjustments(adjustments) => {
+                Answer::Err(Error::new(
+                    ErrorKind::InvalidArgument,
+                    format!("invalid adjustments: {adjustments:?}"),
+                ))
+            }
+            ArchivedRequest::Adjust(adjustments, outputs) => match self.resolve_outputs(outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => {
+                    let adjustments: Adjustments =
+                        adjustments.deserialize(&mut rkyv::Infallible).unwrap();
+                    match std::thread::Builder::new()
+                        .stack_size(1 << 15)
+                        .name("adjust".to_string())
+                        .spawn(move || {
+                            for wallpaper in wallpapers {
+                                wallpaper.set_adjustments(&adjustments);
+                                // like for the tint, only still images must be drawn again
+                                let (_, buffer) = wallpaper.canvas_change(|_| ());
+                                if !wallpaper.is_hidden() {
+                                    wallpaper.draw(&buffer);
+                                }
+                            }
+                            wake_poll();
+                        }) {
+                        Ok(_) => Answer::Ok,
+                        Err(e) => {
+                            Answer::Err(format!("failed to spawn `adjust` thread: {e}").into())
+                        }
+                    }
+                }
+            },
+            ArchivedRequest::Tint(gains, _)
+                if gains.iter().any(|gain| !gain.is_finite() || *gain < 0.0) =>
+            {
+                Answer::Err(Error::new(
+                    ErrorKind::InvalidArgument,
+                    format!("tint gains must be non-negative numbers, got: {gains:?}"),
+                ))
+            }
+            ArchivedRequest::Tint(gains, outputs) => match self.resolve_outputs(outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => {
+                    let gains = *gains;
+                    match std::thread::Builder::new()
+                        .stack_size(1 << 15)
+                        .name("tint".to_string())
+                        .spawn(move || {
+                            for wallpaper in wallpapers {
+                                wallpaper.set_tint(gains);
+                                // animations will draw it tinted soon enough, but still images
+                                // must be drawn again
+                                let (_, buffer) = wallpaper.canvas_change(|_| ());
+                                if !wallpaper.is_hidden() {
+                                    wallpaper.draw(&buffer);
+                                }
+                            }
+                            wake_poll();
+                        }) {
+                        Ok(_) => Answer::Ok,
+                        Err(e) => Answer::Err(format!("failed to spawn `tint` thread: {e}").into()),
+                    }
+                }
+            },
+            ArchivedRequest::Watch(watch) => match self.watches.add(watch) {
+                Ok(()) => Answer::Ok,
+                Err(e) => Answer::Err(e.into()),
+            },
+            ArchivedRequest::Img(request) => {
+                self.initializing = false;
+                self.restore_on_start.store(false, Ordering::Release);
+                let used_wallpapers =
+                    self.start_img_requests(std::slice::from_ref(request), &stream);
+                self.record_img_requests(std::slice::from_ref(request), &used_wallpapers);
+                self.animator.transition(bytes, used_wallpapers);
+                Answer::Ok
+            }
+            ArchivedRequest::Log(log) => match log.filter.as_deref().map(str::parse) {
+                Some(Err(e)) => Answer::Err(Error::new(ErrorKind::InvalidArgument, e)),
+                Some(Ok(filter)) => {
+                    logging::set_filter(filter);
+                    info!("log filter is now {}", logging::filter());
+                    Answer::Log(logging::filter().to_string())
+                }
+                None => Answer::Log(logging::filter().to_string()),
+            },
+            ArchivedRequest::Mirror(source, targets) => match self.start_mirror(source, targets) {
+                Ok(()) => Answer::Ok,
+                Err(e) => Answer::Err(e),
+            },
+            ArchivedRequest::Shader(shader) => match self.resolve_outputs(&shader.outputs) {
+                Err(e) => Answer::Err(e),
+                Ok(wallpapers) => match shader::start(shader, wallpapers.clone()) {
+                    Err(e) => Answer::Err(e),
+                    Ok(()) => {
+                        self.initializing = false;
+                        self.restore_on_start.store(false, Ordering::Release);
+                        let img = utils::ipc::BgImg::Shader {
+                            path: shader.path.to_string(),
+                            fps: shader.fps,
+                        };
+                        let names = wallpapers.iter().map(|w| w.name()).collect();
+                        self.record_displayed(&[((img, Remake::default()), names)]);
+                        Answer::Ok
+                    }
+                },
+            },
+            ArchivedRequest::Batch(requests) => {
+                self.initializing = false;
+                self.restore_on_start.store(false, Ordering::Release);
+                let used_wallpapers = self.start_img_requests(requests, &stream);
+                self.record_img_requests(requests, &used_wallpapers);
                 self.animator.transition(bytes, used_wallpapers);
                 Answer::Ok
             }
         };
+        if let Answer::Err(e) = &answer {
+            self.report_error(&e.msg);
+        }
         if let Err(e) = answer.send(&stream) {
             error!("error sending answer to client: {e}");
+        } else if subscribe {
+            events::subscribe(stream);
+        } else if follow_logs {
+            logging::follow(stream);
+        }
+    }
+
+    /// Stops every animation and destroys the wallpapers' layer surfaces, before we exit. Whatever
+    /// we logged gets to the log file too
+    fn shutdown(&mut self) {
+        for wallpaper in self.wallpapers.drain(..) {
+            wallpaper.inc_animation_id();
+        }
+        self.output_count.store(0, Ordering::Release);
+        log::logger().flush();
+    }
+
+    /// Logs an error the user should know about. With the `xdg-activation` feature, we also try to
+    /// show it in a notification
+    fn report_error(&self, msg: &str) {
+        error!("{msg}");
+        #[cfg(feature = "xdg-activation")]
+        if let Some(activation) = &self.activation {
+            activation.notify_error(msg);
+        }
+    }
+
+    /// Stops whatever the wallpapers used by these requests were doing (unless the interrupt
+    /// policy, or `--queue`, makes us wait for it), and returns them, in the same order as the
+    /// images of the requests, along with how their transitions must start. `client` is the
+    /// socket the requests came on, which we keep until the animations that follow them arrive
+    fn start_img_requests(
+        &mut self,
+        requests: &[ArchivedImageRequest],
+        client: &UnixStream,
+    ) -> Vec<Vec<(Arc<Wallpaper>, Start)>> {
+        let policy = self.animator.interrupt_policy;
+        let expecting = requests
+            .iter()
+            .any(|(_, imgs)| imgs.iter().any(|img| img.0.request.is_some()));
+        let client = match client.try_clone() {
+            Ok(client) if expecting => Some(Arc::new(client)),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("can't tell whether the client gives up on its animations: {e}");
+                None
+            }
+        };
+        let mut used_wallpapers = Vec::new();
+        for (transition, imgs) in requests.iter() {
+            for img in imgs.iter() {
+                let mut wallpapers = Vec::new();
+                for wallpaper in self.find_wallpapers_by_names(&img.1) {
+                    let policy = if transition.queue {
+                        InterruptPolicy::Keep
+                    } else {
+                        policy
+                    };
+                    // 'keep' lets what is on its way to the outputs play too, like `--queue`
+                    if policy != InterruptPolicy::Keep {
+                        wallpaper.cancel_animations();
+                    }
+                    let start = policy.start(&wallpaper);
+                    if !matches!(start, Start::Queued(_)) {
+                        animations::stop_for(&wallpaper, transition);
+                    }
+                    if let Some(request) = img.0.request.as_ref() {
+                        wallpaper.expect_animation(*request, client.clone());
+                    }
+                    wallpapers.push((wallpaper, start));
+                }
+                used_wallpapers.push(wallpapers);
+            }
         }
+        used_wallpapers
+    }
+
+    /// Like [`Daemon::start_img_requests`], for a clear request with a transition. The client
+    /// splits images by the size of the outputs, but we draw the fill ourselves, so we group the
+    /// wallpapers by their size and transform here instead
+    fn start_clear_request(
+        &mut self,
+        wallpapers: Vec<Arc<Wallpaper>>,
+    ) -> Vec<Vec<(Arc<Wallpaper>, Start)>> {
+        let policy = self.animator.interrupt_policy;
+        let mut groups: Vec<Vec<(Arc<Wallpaper>, Start)>> = Vec::new();
+        for wallpaper in wallpapers {
+            if policy != InterruptPolicy::Keep {
+                wallpaper.cancel_animations();
+            }
+            let start = policy.start(&wallpaper);
+            if !matches!(start, Start::Queued(_)) {
+                wallpaper.inc_animation_id();
+            }
+            let shape = (wallpaper.get_dimensions(), wallpaper.transform());
+            match groups
+                .iter_mut()
+                .find(|group| (group[0].0.get_dimensions(), group[0].0.transform()) == shape)
+            {
+                Some(group) => group.push((wallpaper, start)),
+                None => groups.push(vec![(wallpaper, start)]),
+            }
+        }
+        groups
+    }
+
+    /// Makes the outputs `targets` matches display whatever the output called `source` draws. An
+    /// output can't mirror itself, nor an output that already displays what it draws
+    fn start_mirror(
+        &mut self,
+        source: &ArchivedString,
+        targets: &ArchivedBox<[ArchivedString]>,
+    ) -> Result<(), Error> {
+        let Some(source) = self.wallpapers.iter().find(|w| w.name() == source.as_str()) else {
+            return Err(Error::new(
+                ErrorKind::NoSuchOutput,
+                format!("there is no output called {source}"),
+            ));
+        };
+        let source = Arc::clone(source);
+        let targets: Vec<_> = self
+            .resolve_outputs(targets)?
+            .into_iter()
+            .filter(|target| !Arc::ptr_eq(target, &source))
+            .collect();
+        if targets.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                format!("output {} can't mirror itself", source.name()),
+            ));
+        }
+        if let Some(target) = targets.iter().find(|t| mirror::leads_to(&source, t)) {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                format!(
+                    "output {} already displays what {} draws",
+                    source.name(),
+                    target.name()
+                ),
+            ));
+        }
+        self.initializing = false;
+        self.restore_on_start.store(false, Ordering::Release);
+        let names = targets.iter().map(|t| t.name()).collect();
+        self.record_displayed(&[((source.get_img_info(), source.remake()), names)]);
+        for target in &targets {
+            target.mirror(&source);
+        }
+        // still images only get to the mirrors if the source draws them again
+        std::thread::Builder::new()
+            .stack_size(1 << 15)
+            .name("mirror".to_string())
+            .spawn(move || {
+                source.redraw_mirrors();
+                wake_poll();
+            })
+            .map(|_| ())
+            .map_err(|e| format!("failed to spawn `mirror` thread: {e}").into())
+    }
+
+    /// Records what these requests display, with the wallpapers `start_img_requests` returned for
+    /// them, for outputs that appear later
+    fn record_img_requests(
+        &mut self,
+        requests: &[ArchivedImageRequest],
+        used_wallpapers: &[Vec<(Arc<Wallpaper>, Start)>],
+    ) {
+        let imgs: Vec<_> = requests
+            .iter()
+            .flat_map(|(_, imgs)| imgs.iter())
+            .zip(used_wallpapers)
+            .map(|(img, wallpapers)| {
+                let names = wallpapers.iter().map(|(w, _)| w.name()).collect();
+                let remake = img.0.remake.deserialize(&mut rkyv::Infallible).unwrap();
+                (
+                    (utils::ipc::BgImg::Img(img.0.path.to_string()), remake),
+                    names,
+                )
+            })
+            .collect();
+        self.record_displayed(&imgs);
+    }
+
+    fn record_displayed(&mut self, imgs: &[(hotplug::Shown, Vec<&str>)]) {
+        let all: Vec<_> = self.wallpapers.iter().map(|w| w.name()).collect();
+        self.hotplug.displayed(imgs, &all);
+        self.watches.displayed(imgs);
+    }
+
+    /// Whether the compositor configured the surfaces of every output we know of
+    fn is_configured(&self) -> bool {
+        self.wallpapers
+            .iter()
+            .all(|w| w.configured.load(Ordering::Acquire))
     }
 
     fn wallpapers_info(&self) -> Box<[BgInfo]> {
-        self.output_state
-            .outputs()
-            .filter_map(|output| {
-                if let Some(info) = self.output_state.info(&output) {
-                    if let Some(wallpaper) = self.wallpapers.iter().find(|w| w.has_id(info.id)) {
-                        return Some(BgInfo {
+        self.outputs_info().map(|(info, _)| info).collect()
+    }
+
+    /// What `swww query` tells about each output, along with its wallpaper
+    fn outputs_info(&self) -> impl Iterator<Item = (BgInfo, &Arc<Wallpaper>)> {
+        self.output_state.outputs().filter_map(|output| {
+            if let Some(info) = self.output_state.info(&output) {
+                if let Some(wallpaper) = self.wallpapers.iter().find(|w| w.has_id(info.id)) {
+                    // clients make images for the part of the output we cover, and as far past
+                    // it as `--parallax` shifts
+                    let margin = wallpaper.parallax_margin() as i32;
+                    let region = info.logical_size.map(|size| {
+                        let ((x, y), (width, height)) = self.placement.region(size);
+                        (
+                            (x - margin, y - margin),
+                            (width + 2 * margin, height + 2 * margin),
+                        )
+                    });
+                    return Some((
+                        BgInfo {
                             name: info.name.unwrap_or("?".to_string()),
-                            dim: info
-                                .logical_size
-                                .map(|(width, height)| (width as u32, height as u32))
-                                .unwrap_or((0, 0)),
-                            scale_factor: info.scale_factor,
+                            dim: region.map_or((0, 0), |(_, (width, height))| {
+                                (width as u32, height as u32)
+                            }),
+                            // with compositor scaling, clients must send images at the logical size
+                            scale_factor: if self.compositor_scaling {
+                                1
+                            } else {
+                                info.scale_factor
+                            },
+                            fractional_scale: wallpaper.fractional_scale().map(|s| s.get()),
+                            transform: wallpaper.transform(),
+                            position: match (info.logical_position, region) {
+                                (Some((x, y)), Some(((dx, dy), _))) => (x + dx, y + dy),
+                                (position, _) => position.unwrap_or((0, 0)),
+                            },
                             img: wallpaper.get_img_info(),
-                        });
-                    }
+                            transitioning: wallpaper.is_transitioning(),
+                            animation_frame: wallpaper.animation_frame().map(|f| f as u32),
+                            set_at_ms: wallpaper.img_set_at().map(|at| {
+                                at.duration_since(std::time::UNIX_EPOCH)
+                                    .unwrap_or_default()
+                                    .as_millis() as u64
+                            }),
+                        },
+                        wallpaper,
+                    ));
                 }
-                None
-            })
-            .collect()
+            }
+            None
+        })
+    }
+
+    /// The wallpapers of the outputs a user asked for, see [`utils::outputs`]
+    fn resolve_outputs(
+        &self,
+        patterns: &ArchivedBox<[ArchivedString]>,
+    ) -> Result<Vec<Arc<Wallpaper>>, Error> {
+        let patterns: Vec<_> = patterns.iter().map(|n| n.as_str()).collect();
+        let names = utils::outputs::resolve(&patterns, self.wallpapers.iter().map(|w| w.name()))?;
+        Ok(self
+            .wallpapers
+            .iter()
+            .filter(|w| names.contains(&w.name()))
+            .cloned()
+            .collect())
     }
 
+    /// Like [`Daemon::resolve_outputs`], for the names of outputs the client already resolved
     fn find_wallpapers_by_names(
         &self,
         names: &ArchivedBox<[ArchivedString]>,
@@ -409,7 +1480,11 @@ impl CompositorHandler for Daemon {
     ) {
         for wallpaper in self.wallpapers.iter_mut() {
             if wallpaper.has_surface(surface) {
-                wallpaper.draw(&wallpaper.canvas_change(|_| {}).1);
+                wallpaper.frame_done();
+                if wallpaper.take_parallax_undrawn() {
+                    let (_, buffer) = wallpaper.canvas_change(|_| ());
+                    wallpaper.draw(&buffer);
+                }
                 return;
             }
         }
@@ -426,6 +1501,28 @@ impl CompositorHandler for Daemon {
     }
 }
 
+/// Whether `adjustments` are some `swww adjust` could have sent: percents from -100 to 100, and a
+/// blur that is a positive number
+fn valid_adjustments(adjustments: &ArchivedAdjustments) -> bool {
+    let percent = |p: i8| (-100..=100).contains(&p);
+    adjustments
+        .blur
+        .as_ref()
+        .map_or(true, |sigma| sigma.is_finite() && *sigma > 0.0)
+        && [
+            adjustments.brightness,
+            adjustments.contrast,
+            adjustments.saturation,
+        ]
+        .into_iter()
+        .all(percent)
+}
+
+/// The namespace of the layer surface of the output called `name`, from `--layer-namespace`
+fn layer_namespace(template: &str, name: Option<&str>) -> String {
+    template.replace("{output}", name.unwrap_or_default())
+}
+
 impl OutputHandler for Daemon {
     fn output_state(&mut self) -> &mut OutputState {
         &mut self.output_state
@@ -437,50 +1534,8 @@ impl OutputHandler for Daemon {
         qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
-        if let Some(output_info) = self.output_state.info(&output) {
-            let surface = self.compositor_state.create_surface(qh);
-
-            // Wayland clients are expected to render the cursor on their input region.
-            // By setting the input region to an empty region, the compositor renders the
-            // default cursor. Without this, an empty desktop won't render a cursor.
-            if let Ok(region) = Region::new(&self.compositor_state) {
-                surface.set_input_region(Some(region.wl_region()));
-            }
-            let layer_surface = self.layer_shell.create_layer_surface(
-                qh,
-                surface,
-                Layer::Background,
-                Some("swww"),
-                Some(&output),
-            );
-
-            if !self.initializing {
-                if let Some(name) = &output_info.name {
-                    let name = name.to_owned();
-                    if let Err(e) = std::thread::Builder::new()
-                        .name("cache loader".to_string())
-                        .stack_size(1 << 14)
-                        .spawn(move || {
-                            // Wait for a bit for the output to be properly configured and stuff
-                            // this is obviously not ideal, but it solves the vast majority of problems
-                            std::thread::sleep(std::time::Duration::from_millis(100));
-                            if let Err(e) = utils::cache::load(&name) {
-                                warn!("failed to load cache: {e}");
-                            }
-                        })
-                    {
-                        warn!("failed to spawn `cache loader` thread: {e}");
-                    }
-                }
-            }
-
-            debug!("New output: {output_info:?}");
-            self.wallpapers.push(Arc::new(Wallpaper::new(
-                output_info,
-                layer_surface,
-                Arc::clone(&self.pool),
-            )));
-            debug!("Output count: {}", self.wallpapers.len());
+        if let Some(name) = self.add_wallpaper(qh, &output, self.restore) {
+            events::send(events::Event::OutputAdded { output: &name });
         }
     }
 
@@ -501,12 +1556,16 @@ impl OutputHandler for Daemon {
                 }
                 for wallpaper in self.wallpapers.iter_mut() {
                     if wallpaper.has_id(output_info.id) {
+                        let (_, size) = self.placement.region(output_size);
                         let (width, height) = (
-                            Some(NonZeroI32::new(output_size.0).unwrap()),
-                            Some(NonZeroI32::new(output_size.1).unwrap()),
+                            Some(NonZeroI32::new(size.0).unwrap()),
+                            Some(NonZeroI32::new(size.1).unwrap()),
                         );
                         let scale_factor = Some(NonZeroI32::new(output_info.scale_factor).unwrap());
+                        wallpaper.set_refresh_rate(&output_info.modes);
+                        wallpaper.set_transform(output_info.transform);
                         wallpaper.resize(width, height, scale_factor);
+                        hotplug::redisplay(wallpaper);
                         return;
                     }
                 }
@@ -521,9 +1580,147 @@ impl OutputHandler for Daemon {
         output: wl_output::WlOutput,
     ) {
         if let Some(output_info) = self.output_state.info(&output) {
-            self.wallpapers.retain(|w| !w.has_id(output_info.id));
+            self.remove_wallpaper(output_info.id);
             debug!("Destroyed output: {output_info:?}");
+            if let Some(name) = &output_info.name {
+                events::send(events::Event::OutputRemoved { output: name });
+            }
+        }
+    }
+}
+
+impl Daemon {
+    /// Makes the wallpaper of `output`, which displays what it did before if `restore`. Returns
+    /// the name of the output
+    fn add_wallpaper(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        output: &wl_output::WlOutput,
+        restore: bool,
+    ) -> Option<String> {
+        #[cfg(feature = "x11")]
+        if let Some(screen) = &self.x11 {
+            return self.add_x11_wallpaper(&Arc::clone(screen), output, restore);
+        }
+        if let Some(output_info) = self.output_state.info(output) {
+            let surface = self.compositor_state.create_surface(qh);
+
+            // Wayland clients are expected to render the cursor on their input region.
+            // By setting the input region to an empty region, the compositor renders the
+            // default cursor. Without this, an empty desktop won't render a cursor. When we track
+            // the cursor, we need the input, and set the cursor ourselves
+            if self.cursor.is_none() {
+                if let Ok(region) = Region::new(&self.compositor_state) {
+                    surface.set_input_region(Some(region.wl_region()));
+                }
+            }
+            let layer_surface = self.layer_shell.create_layer_surface(
+                qh,
+                surface,
+                self.layer,
+                Some(layer_namespace(
+                    &self.layer_namespace,
+                    output_info.name.as_deref(),
+                )),
+                Some(output),
+            );
+
+            debug!("New output: {output_info:?}");
+            let name = output_info.name.clone();
+            let output_id = output_info.id;
+            let viewport = self
+                .fractional
+                .as_mut()
+                .map(|f| f.watch(layer_surface.wl_surface(), output_id, qh));
+            // wl_shm was bound before wl_output, so its formats arrived before the output did
+            let shm_format = self.shm_format.negotiate(&self.shm);
+            let surface = LayerBackend::new(layer_surface, viewport, &self.shm, qh.clone());
+            let wallpaper = Arc::new(Wallpaper::new(
+                output_info.into(),
+                Box::new(surface),
+                self.compositor_scaling,
+                shm_format,
+                &self.placement,
+                self.parallax,
+            ));
+            self.track_wallpaper(wallpaper, restore && name.is_some());
+            if let Some(power) = &mut self.power {
+                power.watch(output, output_id, qh);
+            }
+            return name;
+        }
+        None
+    }
+
+    /// Like [`Self::add_wallpaper`], on the X monitor `output` stands for. X never configures
+    /// anything, nor scales it, so the wallpaper is at the monitor's size, and ready right away
+    #[cfg(feature = "x11")]
+    fn add_x11_wallpaper(
+        &mut self,
+        screen: &Arc<x11::Screen>,
+        output: &wl_output::WlOutput,
+        restore: bool,
+    ) -> Option<String> {
+        let output_info = self.output_state.info(output)?;
+        let name = output_info.name.clone()?;
+        let Some(surface) = screen.surface(&name) else {
+            error!("no X11 monitor is called {name}");
+            return None;
+        };
+        debug!("New X11 monitor: {name}");
+        let description = wallpaper::OutputDescription {
+            logical_size: Some(surface.size()),
+            scale_factor: 1,
+            transform: wl_output::Transform::Normal,
+            ..output_info.into()
+        };
+        // the root window has no alpha channel, nor more than 8 bits a channel
+        let wallpaper = Arc::new(Wallpaper::new(
+            description,
+            Box::new(surface),
+            false,
+            shm_format::ShmFormat::Xrgb8888,
+            &self.placement,
+            self.parallax,
+        ));
+        wallpaper.configured.store(true, Ordering::Release);
+        self.track_wallpaper(wallpaper, restore);
+        Some(name)
+    }
+
+    /// Keeps `wallpaper`, made for a new output, which displays what it did before if `restore`
+    fn track_wallpaper(&mut self, wallpaper: Arc<Wallpaper>, restore: bool) {
+        wallpaper.set_max_fps(self.max_fps.of(wallpaper.name()));
+        if let Some(gc) = &self.gc {
+            gc.lock()
+                .unwrap()
+                .track(&wallpaper, wallpaper.pool_buffers());
+        }
+        if restore {
+            let shown = self.hotplug.for_output(wallpaper.name()).cloned();
+            // at startup, `swww init` or the user may display something else first
+            let on_start = self
+                .initializing
+                .then(|| Arc::clone(&self.restore_on_start));
+            hotplug::restore(Arc::clone(&wallpaper), shown, on_start);
         }
+        self.slideshows.output_added(&wallpaper);
+        self.wallpapers.push(wallpaper);
+        self.output_count
+            .store(self.wallpapers.len(), Ordering::Release);
+        debug!("Output count: {}", self.wallpapers.len());
+    }
+
+    fn remove_wallpaper(&mut self, output_id: u32) {
+        self.wallpapers.retain(|w| !w.has_id(output_id));
+        if let Some(power) = &mut self.power {
+            power.forget(output_id);
+        }
+        if let Some(fractional) = &mut self.fractional {
+            fractional.forget(output_id);
+        }
+        self.output_count
+            .store(self.wallpapers.len(), Ordering::Release);
     }
 }
 
@@ -571,20 +1768,244 @@ impl ProvidesRegistryState for Daemon {
     fn registry(&mut self) -> &mut RegistryState {
         &mut self.registry_state
     }
-    registry_handlers![OutputState];
+    registry_handlers![OutputState, SeatState];
 }
 
-fn make_logger() {
-    let config = simplelog::ConfigBuilder::new()
-        .set_thread_level(LevelFilter::Error) // let me see where the processing is happening
-        .set_thread_mode(ThreadLogMode::Both)
-        .build();
-
-    TermLogger::init(
-        LevelFilter::Debug,
-        config,
-        TerminalMode::Stderr,
-        ColorChoice::AlwaysAnsi,
-    )
-    .expect("Failed to initialize logger. Cancelling...");
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::UdpSocket,
+        os::unix::net::UnixDatagram,
+        path::Path,
+        sync::{MutexGuard, PoisonError},
+        time::{Duration, Instant},
+    };
+
+    use utils::ipc::{
+        read_handshake, read_socket, ArchivedAnswer, Handshake, HandshakeAnswer, Img, Shader,
+    };
+    use wayland_client::EventQueue;
+
+    use super::*;
+    use crate::headless::{self, Frames, Headless};
+
+    /// A daemon connected to [`nullserver`], with one output, which tests send requests to like
+    /// clients do
+    struct TestDaemon {
+        daemon: Daemon,
+        /// What the output displayed
+        frames: Frames,
+        _queue: EventQueue<Daemon>,
+        _generations: MutexGuard<'static, ()>,
+    }
+
+    impl TestDaemon {
+        const OUTPUT: &'static str = "HEADLESS-1";
+
+        fn new(args: &[&str]) -> Self {
+            // configuring the daemon starts and stops the threads of every feature
+            let generations = GENERATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+            let conn =
+                Connection::from_socket(nullserver::serve(&[Self::OUTPUT]).unwrap()).unwrap();
+            let (globals, mut queue) = registry_queue_init(&conn).unwrap();
+            let mut daemon = Daemon::new(globals, &queue.handle());
+            // we don't want to run `swww` to restore the output
+            let args = args.iter().chain(&["--no-restore"]);
+            let cli = cli::Cli::new(args.map(|arg| arg.to_string())).unwrap();
+            daemon.configure(cli).unwrap();
+            queue.roundtrip(&mut daemon).unwrap();
+
+            // the compositor displays nothing, so the wallpaper the daemon made for the output
+            // goes headless
+            let output = daemon.output_state.outputs().next().unwrap();
+            let id = daemon.output_state.info(&output).unwrap().id;
+            assert!(
+                daemon.wallpapers.pop().is_some(),
+                "the output was never added"
+            );
+            let (headless, frames) = Headless::output(id, Self::OUTPUT, (2, 1));
+            daemon.wallpapers.push(headless);
+            Self {
+                daemon,
+                frames,
+                _queue: queue,
+                _generations: generations,
+            }
+        }
+
+        /// Sends `request` through a socket, the way `swww` does, and returns the error the
+        /// daemon answered, if any
+        fn send(&mut self, request: Request) -> Result<(), String> {
+            let (client, stream) = UnixStream::pair().unwrap();
+            Handshake::new("").send(&client).unwrap();
+            request.send(&client).unwrap();
+            self.daemon.recv_socket_msg(stream);
+
+            let bytes = read_handshake(&client).unwrap();
+            assert_eq!(
+                HandshakeAnswer::receive(&bytes).unwrap().0,
+                HandshakeAnswer::Ok
+            );
+            let bytes = read_socket(&client).unwrap();
+            match Answer::receive(&bytes).unwrap() {
+                ArchivedAnswer::Err(e) => Err(e.msg.to_string()),
+                _ => Ok(()),
+            }
+        }
+
+        /// Sends an image of two pixels to the output, and waits for it to be displayed
+        fn display(&mut self, pixels: [u8; 6]) {
+            assert_eq!(self.send(img(Self::OUTPUT, &pixels)), Ok(()));
+            wait_for(&self.frames, &pixels);
+        }
+    }
+
+    /// A request to show `pixels`, in the 3 bytes per pixel clients send, on `output` at once
+    fn img(output: &str, pixels: &[u8]) -> Request {
+        Request::Img((
+            headless::simple_transition(),
+            Box::new([(
+                Img {
+                    path: "/test.png".to_string(),
+                    img: pixels.into(),
+                    jpeg: false,
+                    remake: Remake::default(),
+                    adjustments: Adjustments::default(),
+                    dither: None,
+                    request: None,
+                },
+                Box::new([output.to_string()]) as Box<[String]>,
+            )]),
+        ))
+    }
+
+    /// Waits for the last frame of `frames` to show `pixels`
+    fn wait_for(frames: &Frames, pixels: &[u8]) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let displayed = |frames: &Frames| {
+            frames.lock().unwrap().last().is_some_and(|frame| {
+                frame
+                    .chunks_exact(4)
+                    .map(|p| &p[..3])
+                    .eq(pixels.chunks_exact(3))
+            })
+        };
+        while !displayed(frames) {
+            assert!(Instant::now() < deadline, "the image was never displayed");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn heartbeats_should_count_the_outputs_and_the_frames_they_display() {
+        let path = temp_path("swww-daemon-heartbeat");
+        let listener = UnixDatagram::bind(&path).unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut daemon = TestDaemon::new(&["--heartbeat-socket", path.to_str().unwrap()]);
+        let beat = || {
+            let mut buf = [0; 256];
+            let len = listener.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+
+        let before = beat();
+        assert!(before.contains("\"outputs\":1"), "{before}");
+        // the next beat counts the frame we draw now
+        daemon.display([10, 20, 30, 10, 20, 30]);
+        let after = beat();
+        assert!(after.contains("\"outputs\":1"), "{after}");
+        assert!(!after.contains("\"fps_actual\":0.0"), "{after}");
+
+        heartbeat::stop();
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reloading_should_keep_the_lock_file_we_started_with() {
+        let path = temp_path("swww-daemon-lock");
+        let _lock = lock::acquire(&path).unwrap();
+        let mut daemon = TestDaemon::new(&["--lock-file", path.to_str().unwrap()]);
+
+        let other = temp_path("swww-daemon-other-lock");
+        daemon.daemon.args = vec!["--lock-file".to_string(), other.display().to_string()];
+        let e = daemon.send(Request::ReloadConfig).unwrap_err();
+        assert!(e.contains("lock-file only change"), "{e}");
+        let cli = daemon.daemon.cli.as_ref().unwrap();
+        assert_eq!(cli.lock_file.as_deref(), Some(Path::new(&path)));
+        assert!(!other.exists(), "the daemon should not take another lock");
+
+        // still holding the lock, the daemon goes on displaying what it is sent
+        assert!(lock::acquire(&path).is_err());
+        daemon.display([10, 20, 30, 10, 20, 30]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn frames_drawn_for_requests_should_be_timed_for_statsd() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let host = server.local_addr().unwrap().to_string();
+        let mut daemon = TestDaemon::new(&["--metrics-backend", "statsd", "--statsd-host", &host]);
+        daemon.display([10, 20, 30, 10, 20, 30]);
+        // the timing goes in the first packets after the frame is drawn
+        let mut packets = Vec::new();
+        while !packets
+            .iter()
+            .any(|p: &String| p.starts_with("swww.frame_time_us:"))
+        {
+            assert!(packets.len() < 4, "the frame was never timed: {packets:?}");
+            let mut buf = [0; 256];
+            let len = server.recv(&mut buf).unwrap();
+            packets.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+        }
+        metrics::stop_statsd();
+    }
+
+    #[test]
+    fn shaders_should_render_until_the_output_displays_something_else() {
+        if gl::Context::new().is_err() {
+            eprintln!("no OpenGL ES here, skipping");
+            return;
+        }
+        let mut daemon = TestDaemon::new(&[]);
+        let shader = |source: &str| {
+            Request::Shader(Shader {
+                source: source.to_string(),
+                path: "/test.glsl".to_string(),
+                fps: 60,
+                outputs: Box::new([TestDaemon::OUTPUT.to_string()]),
+            })
+        };
+        let e = daemon
+            .send(shader(
+                "void mainImage(out vec4 c, in vec2 p) { c = nothing; }",
+            ))
+            .unwrap_err();
+        assert!(e.contains("failed to compile shader"), "{e}");
+
+        // red on the left, and blue on the right, in the order of our canvas
+        let source = "void mainImage(out vec4 c, in vec2 p) {
+            c = p.x < 1.0 ? vec4(1.0, 0.0, 0.0, 1.0) : vec4(0.0, 0.0, 1.0, 1.0);
+        }";
+        assert_eq!(daemon.send(shader(source)), Ok(()));
+        wait_for(&daemon.frames, &[0, 0, 255, 255, 0, 0]);
+        let rendered = daemon.frames.lock().unwrap().len();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(daemon.frames.lock().unwrap().len() > rendered);
+
+        daemon.display([10, 20, 30, 10, 20, 30]);
+        let displayed = daemon.frames.lock().unwrap().len();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(daemon.frames.lock().unwrap().len(), displayed);
+    }
 }
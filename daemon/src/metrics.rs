@@ -0,0 +1,152 @@
+//! Pushes frame timing metrics to a StatsD server.
+//!
+//! Every second, we send these over UDP:
+//!
+//! * `swww.frame_time_us:<value>|ms`: how long drawing a frame took, on average, in microseconds,
+//!   if we drew any;
+//! * `swww.frames_dropped:<n>|c`: how many frames missed their deadline.
+
+use log::{error, warn};
+
+use std::{
+    net::UdpSocket,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::Generation;
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+static FRAME_TIME_US: AtomicU64 = AtomicU64::new(0);
+static FRAMES_TIMED: AtomicU64 = AtomicU64::new(0);
+static FRAMES_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+static THREADS: Generation = Generation::new();
+
+/// Records that drawing a frame took `time`
+pub fn frame_drawn(time: Duration) {
+    FRAME_TIME_US.fetch_add(time.as_micros() as u64, Ordering::AcqRel);
+    FRAMES_TIMED.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Records that a frame was shown later than it should have been
+pub fn frame_dropped() {
+    FRAMES_DROPPED.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Starts pushing the metrics to the StatsD server at `host`, instead of the one we pushed them to
+/// before, if any
+pub fn spawn_statsd(host: String) {
+    let generation = THREADS.next();
+    let socket = match UdpSocket::bind(("0.0.0.0", 0)).and_then(|socket| {
+        socket.connect(&host)?;
+        Ok(socket)
+    }) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("failed to create statsd socket for {host}: {e}");
+            return;
+        }
+    };
+
+    if let Err(e) = thread::Builder::new()
+        .name("statsd".to_string())
+        .stack_size(1 << 15)
+        .spawn(move || {
+            let mut warned = false;
+            let mut next = Instant::now() + INTERVAL;
+            while THREADS.is_current(generation) {
+                thread::sleep(next.saturating_duration_since(Instant::now()));
+                next += INTERVAL;
+                if !THREADS.is_current(generation) {
+                    break;
+                }
+
+                let total_us = FRAME_TIME_US.swap(0, Ordering::AcqRel);
+                let frames = FRAMES_TIMED.swap(0, Ordering::AcqRel);
+                let dropped = FRAMES_DROPPED.swap(0, Ordering::AcqRel);
+                for msg in statsd_msgs(frame_time_us(total_us, frames), dropped) {
+                    match socket.send(msg.as_bytes()) {
+                        Ok(_) => warned = false,
+                        Err(e) => {
+                            // the server not being up yet is normal, so we only warn once
+                            if !warned {
+                                warn!("failed to send metrics to {host}: {e}");
+                                warned = true;
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    {
+        error!("failed to spawn 'statsd' thread: {e}");
+    }
+}
+
+/// Stops pushing the metrics
+pub fn stop_statsd() {
+    THREADS.next();
+}
+
+/// How long drawing one of `frames` took, on average, if we drew any. Nothing was drawn while
+/// outputs showed still images, or animations without a frame, and a timing of 0 would then say
+/// drawing got faster
+fn frame_time_us(total_us: u64, frames: u64) -> Option<u64> {
+    total_us.checked_div(frames)
+}
+
+fn statsd_msgs(frame_time_us: Option<u64>, frames_dropped: u64) -> Vec<String> {
+    let mut msgs = Vec::with_capacity(2);
+    if let Some(frame_time_us) = frame_time_us {
+        msgs.push(format!("swww.frame_time_us:{frame_time_us}|ms"));
+    }
+    msgs.push(format!("swww.frames_dropped:{frames_dropped}|c"));
+    msgs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_time_frames_if_we_drew_any() {
+        assert_eq!(frame_time_us(0, 0), None);
+        assert_eq!(frame_time_us(3000, 2), Some(1500));
+        assert_eq!(statsd_msgs(None, 0), ["swww.frames_dropped:0|c"]);
+        assert_eq!(
+            statsd_msgs(Some(1500), 1),
+            ["swww.frame_time_us:1500|ms", "swww.frames_dropped:1|c"]
+        );
+    }
+
+    #[test]
+    fn should_push_metrics_to_statsd() {
+        let _generations = crate::GENERATIONS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // so that there is a timing to send
+        frame_drawn(Duration::from_micros(1500));
+        spawn_statsd(server.local_addr().unwrap().to_string());
+
+        let mut buf = [0; 256];
+        let mut packets = Vec::new();
+        for _ in 0..2 {
+            let len = server.recv(&mut buf).unwrap();
+            packets.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+        }
+        assert!(packets[0].starts_with("swww.frame_time_us:"), "{packets:?}");
+        assert!(packets[0].ends_with("|ms"), "{packets:?}");
+        assert!(
+            packets[1].starts_with("swww.frames_dropped:"),
+            "{packets:?}"
+        );
+        assert!(packets[1].ends_with("|c"), "{packets:?}");
+    }
+}
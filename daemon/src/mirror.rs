@@ -0,0 +1,268 @@
+//! Makes outputs display what another output draws, see `swww mirror`.
+//!
+//! A mirror doesn't get images of its own: every time its source draws, we copy what it drew to
+//! the mirror's canvas, and the mirror presents it right after the source does. Animations are
+//! thus only unpacked once, however many outputs show them, and every mirror stays on the source's
+//! frame. The copy is a plain one if both outputs have the same size and transform, and is
+//! rescaled, bilinearly, otherwise, on the GPU with `--renderer gpu`.
+//!
+//! What the source draws over its images (its tint, overlay and parallax shift) is its own, and
+//! isn't copied: mirrors draw theirs over what they get instead. A mirror stops once it displays
+//! something of its own, like with `swww img` or `swww clear`, or once its source goes away.
+
+use std::sync::Arc;
+
+use rayon::prelude::*;
+use utils::ipc::Transform;
+
+use crate::wallpaper::Wallpaper;
+
+/// The size of a canvas, in pixels, and the transform it is drawn with
+pub type Geometry = ((u32, u32), Transform);
+
+/// Whether `source` displays what `target` draws, through any number of mirrors. Making `target`
+/// mirror it then would have them copy each other forever
+pub fn leads_to(source: &Arc<Wallpaper>, target: &Arc<Wallpaper>) -> bool {
+    let mut at = source.mirror_source();
+    while let Some(wallpaper) = at {
+        if Arc::ptr_eq(&wallpaper, target) {
+            return true;
+        }
+        at = wallpaper.mirror_source();
+    }
+    false
+}
+
+/// Copies `src`, `xrgb8888` (or premultiplied `argb8888`) pixels, to `dst`, so that both show the
+/// same on their outputs. Does nothing if either doesn't have the size its geometry says, since
+/// that means an output changed size while we copied
+pub fn copy(
+    src: &[u8],
+    (src_dim, src_transform): Geometry,
+    dst: &mut [u8],
+    dst_geometry: Geometry,
+) {
+    let (dst_dim, _) = dst_geometry;
+    let len = |(width, height): (u32, u32)| width as usize * height as usize * 4;
+    if src.len() != len(src_dim) || dst.len() != len(dst_dim) || dst.is_empty() {
+        return;
+    }
+    if (src_dim, src_transform) == dst_geometry {
+        dst.copy_from_slice(src);
+        return;
+    }
+    let to_src = source_map((src_dim, src_transform), dst_geometry);
+    #[cfg(feature = "gpu")]
+    if crate::gpu::with(|renderer| {
+        rescale_on_gpu(renderer, (src, src_dim), (&mut *dst, dst_dim), &to_src)
+    })
+    .is_some()
+    {
+        return;
+    }
+    let row = dst_dim.0 as usize * 4;
+    dst.par_chunks_exact_mut(row)
+        .enumerate()
+        .for_each(|(y, line)| {
+            for (x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                // the centers of the pixels
+                let (x, y) = to_src((x as f32 + 0.5, y as f32 + 0.5));
+                pixel.copy_from_slice(&bilinear_sample(src, src_dim, x - 0.5, y - 0.5));
+            }
+        });
+}
+
+/// Takes points of the canvas of `dst` to where they show the same on that of `src`: from the
+/// buffer to the output, to the other output, and to its buffer
+fn source_map(
+    (src_dim, src_transform): Geometry,
+    (dst_dim, dst_transform): Geometry,
+) -> impl Fn((f32, f32)) -> (f32, f32) + Sync {
+    // the sizes of what the outputs show, before their transforms
+    let (src_width, src_height) = src_transform.buffer_dim((src_dim.0 as f32, src_dim.1 as f32));
+    let (dst_width, dst_height) = dst_transform.buffer_dim((dst_dim.0 as f32, dst_dim.1 as f32));
+    let untransform = inverse(dst_transform);
+    move |point| {
+        let (x, y) = untransform.to_buffer(point, (dst_dim.0 as f32, dst_dim.1 as f32));
+        src_transform.to_buffer(
+            (x * src_width / dst_width, y * src_height / dst_height),
+            (src_width, src_height),
+        )
+    }
+}
+
+/// Samples `src` where `to_src` takes every pixel center of `dst`, like [`copy`] does
+#[cfg(feature = "gpu")]
+const RESCALE: &str = "#version 300 es
+precision highp float;
+uniform sampler2D src;
+uniform vec2 size;
+uniform vec3 map_x;
+uniform vec3 map_y;
+out vec4 color;
+void main() {
+    vec3 p = vec3(gl_FragCoord.xy, 1.0);
+    color = texture(src, vec2(dot(map_x, p), dot(map_y, p)) / size);
+}
+";
+
+/// [`copy`]'s rescaling, on the GPU. `to_src` is affine, so three points of it are all of it
+#[cfg(feature = "gpu")]
+fn rescale_on_gpu(
+    renderer: &mut crate::gpu::Renderer,
+    (src, src_dim): (&[u8], (u32, u32)),
+    (dst, dst_dim): (&mut [u8], (u32, u32)),
+    to_src: impl Fn((f32, f32)) -> (f32, f32),
+) -> Result<(), String> {
+    use crate::gl::Uniform;
+
+    let origin = to_src((0.0, 0.0));
+    let (across, down) = (to_src((1.0, 0.0)), to_src((0.0, 1.0)));
+    let map_x = [across.0 - origin.0, down.0 - origin.0, origin.0];
+    let map_y = [across.1 - origin.1, down.1 - origin.1, origin.1];
+    let program = renderer.program(RESCALE)?;
+    let context = renderer.context();
+    let texture = context.texture(src_dim, src, 4);
+    let size = [src_dim.0 as f32, src_dim.1 as f32];
+    context.set_uniform(&program, "size", Uniform::Vec2(size));
+    context.set_uniform(&program, "map_x", Uniform::Vec3(map_x));
+    context.set_uniform(&program, "map_y", Uniform::Vec3(map_y));
+    context.bind_texture(&program, "src", 0, &texture);
+    let drawn = renderer.draw(program, dst_dim, dst, 4);
+    renderer.context().delete_texture(texture);
+    drawn
+}
+
+/// The transform that takes a buffer drawn with `transform` back to how the output shows it
+fn inverse(transform: Transform) -> Transform {
+    match transform {
+        Transform::Rotated90 => Transform::Rotated270,
+        Transform::Rotated270 => Transform::Rotated90,
+        // every other one is its own inverse
+        transform => transform,
+    }
+}
+
+/// Samples every channel of `img` at `(x, y)`, interpolating between the four nearest pixels
+fn bilinear_sample(img: &[u8], (width, height): (u32, u32), x: f32, y: f32) -> [u8; 4] {
+    let (width, height) = (width as usize, height as usize);
+    let x = x.clamp(0.0, (width - 1) as f32);
+    let y = y.clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let pixel = |x: usize, y: usize| {
+        let i = (y * width + x) * 4;
+        &img[i..i + 4]
+    };
+    let (p00, p10, p01, p11) = (pixel(x0, y0), pixel(x1, y0), pixel(x0, y1), pixel(x1, y1));
+
+    let mut color = [0; 4];
+    for (i, col) in color.iter_mut().enumerate() {
+        let top = p00[i] as f32 * (1.0 - fx) + p10[i] as f32 * fx;
+        let bottom = p01[i] as f32 * (1.0 - fx) + p11[i] as f32 * fx;
+        *col = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::headless::{Frames, Headless};
+
+    /// A 2x2 canvas, one color per pixel
+    const SRC: [u8; 16] = [
+        10, 0, 0, 0xff, 20, 0, 0, 0xff, //
+        30, 0, 0, 0xff, 40, 0, 0, 0xff,
+    ];
+
+    fn blues(canvas: &[u8]) -> Vec<u8> {
+        canvas.chunks_exact(4).map(|pixel| pixel[0]).collect()
+    }
+
+    #[test]
+    fn should_copy_what_the_other_output_shows() {
+        let same = ((2, 2), Transform::Normal);
+        let mut dst = [0; 16];
+        copy(&SRC, same, &mut dst, same);
+        assert_eq!(dst, SRC);
+
+        // twice as big, the corners keep their colors
+        let mut dst = [0; 64];
+        copy(&SRC, same, &mut dst, ((4, 4), Transform::Normal));
+        let dst = blues(&dst);
+        assert_eq!([dst[0], dst[3], dst[12], dst[15]], [10, 20, 30, 40]);
+
+        // the same output, rotated, so its buffer is the source's turned a quarter counter-clockwise
+        let mut dst = [0; 16];
+        copy(&SRC, same, &mut dst, ((2, 2), Transform::Rotated90));
+        assert_eq!(blues(&dst), [20, 40, 10, 30]);
+        // and back
+        let mut back = [0; 16];
+        copy(&dst, ((2, 2), Transform::Rotated90), &mut back, same);
+        assert_eq!(back, SRC);
+
+        // an output that changed size while we copied gets nothing
+        let mut dst = [0; 12];
+        copy(&SRC, same, &mut dst, ((2, 2), Transform::Normal));
+        assert_eq!(dst, [0; 12]);
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn should_rescale_on_the_gpu_like_on_the_cpu() {
+        let Ok(mut renderer) = crate::gpu::Renderer::new() else {
+            eprintln!("no OpenGL ES here, skipping");
+            return;
+        };
+        let src_dim = (13, 7);
+        let src: Vec<u8> = (0..src_dim.1)
+            .flat_map(|y| (0..src_dim.0).flat_map(move |x| [x * 19, y * 36, 90, 255]))
+            .map(|col| col as u8)
+            .collect();
+        for dst in [
+            ((29, 11), Transform::Normal),
+            ((5, 4), Transform::Rotated90),
+            ((8, 26), Transform::Flipped270),
+        ] {
+            let src_geometry = (src_dim, Transform::Normal);
+            let mut cpu = vec![0; dst.0 .0 as usize * dst.0 .1 as usize * 4];
+            copy(&src, src_geometry, &mut cpu, dst);
+            let mut gpu = vec![0; cpu.len()];
+            let to_src = source_map(src_geometry, dst);
+            rescale_on_gpu(&mut renderer, (&src, src_dim), (&mut gpu, dst.0), to_src).unwrap();
+            // the GPU interpolates with less precision than we do
+            for (cpu, gpu) in cpu.iter().zip(&gpu) {
+                assert!(cpu.abs_diff(*gpu) <= 2, "{}: {cpu} and {gpu}", dst.1);
+            }
+        }
+    }
+
+    #[test]
+    fn mirrors_should_present_every_frame_their_source_draws() {
+        let (source, _) = Headless::wallpaper("HEADLESS-1", (2, 2));
+        let (target, frames) = Headless::wallpaper("HEADLESS-2", (4, 4));
+        assert!(!leads_to(&target, &source));
+        target.mirror(&source);
+        assert!(leads_to(&target, &source));
+        assert!(!leads_to(&source, &target));
+
+        let drawn = |frames: &Frames| blues(frames.lock().unwrap().last().unwrap());
+        for blue in [10, 20] {
+            let buffer = source.clear([0, 0, blue]);
+            source.draw(&buffer);
+            assert_eq!(drawn(&frames), [blue; 16]);
+        }
+
+        // something of its own stops it
+        target.set_img_info(utils::ipc::BgImg::Color([0; 3]));
+        assert!(!leads_to(&target, &source));
+        let shown = frames.lock().unwrap().len();
+        let buffer = source.clear([0, 0, 30]);
+        source.draw(&buffer);
+        assert_eq!(frames.lock().unwrap().len(), shown);
+    }
+}
@@ -0,0 +1,136 @@
+//! A wayland server of our own, which displays nothing, for running the daemon without a
+//! compositor.
+//!
+//! It has the globals the daemon can't do without, and outputs that only tell their names, and it
+//! ignores every request. Tests give the daemon `headless` wallpapers on its outputs, and the X11
+//! backend gives it wallpapers on the monitors of the X screen (see `x11`), so that requests play
+//! just like they do on a compositor, whatever the wallpapers draw to.
+
+use std::{
+    os::{fd::OwnedFd, unix::net::UnixStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+use wayland_backend::{
+    protocol::{Argument, Message},
+    server::{
+        Backend, ClientData, ClientId, DisconnectReason, GlobalHandler, GlobalId, Handle,
+        ObjectData, ObjectId,
+    },
+};
+use wayland_client::{
+    protocol::{wl_compositor::WlCompositor, wl_output, wl_shm},
+    Proxy,
+};
+
+/// Starts a server with only the globals [`Daemon::new`](crate::Daemon::new) needs, and one
+/// `wl_output` for each of `outputs`, on a thread of its own, and returns the socket of its only
+/// client. It stops once the client disconnects
+pub fn serve(outputs: &[&str]) -> Result<UnixStream, String> {
+    let (client, server) =
+        UnixStream::pair().map_err(|e| format!("failed to make a wayland socket: {e}"))?;
+    let mut backend =
+        Backend::<()>::new().map_err(|e| format!("failed to start our wayland server: {e}"))?;
+    let handle = backend.handle();
+    handle.create_global::<()>(WlCompositor::interface(), 4, Arc::new(Ignore));
+    handle.create_global::<()>(wl_shm::WlShm::interface(), 1, Arc::new(Ignore));
+    handle.create_global::<()>(ZwlrLayerShellV1::interface(), 4, Arc::new(Ignore));
+    for name in outputs {
+        let output = Arc::new(Output(name.to_string()));
+        handle.create_global::<()>(wl_output::WlOutput::interface(), 4, output);
+    }
+    let connected = Arc::new(AtomicBool::new(true));
+    backend
+        .handle()
+        .insert_client(server, Arc::new(Connected(Arc::clone(&connected))))
+        .map_err(|e| format!("failed to connect to our wayland server: {e}"))?;
+
+    std::thread::spawn(move || {
+        while connected.load(Ordering::Acquire) {
+            let fd = backend.poll_fd();
+            let mut fds = [nix::poll::PollFd::new(&fd, nix::poll::PollFlags::POLLIN)];
+            let _ = nix::poll::poll(&mut fds, 100);
+            if backend.dispatch_all_clients(&mut ()).is_err() || backend.flush(None).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(client)
+}
+
+/// The data of every global, object and client of [`serve`], which does nothing
+struct Ignore;
+
+impl GlobalHandler<()> for Ignore {
+    fn bind(
+        self: Arc<Self>,
+        _: &Handle,
+        _: &mut (),
+        _: ClientId,
+        _: GlobalId,
+        _: ObjectId,
+    ) -> Arc<dyn ObjectData<()>> {
+        self
+    }
+}
+
+impl ObjectData<()> for Ignore {
+    fn request(
+        self: Arc<Self>,
+        _: &Handle,
+        _: &mut (),
+        _: ClientId,
+        msg: Message<ObjectId, OwnedFd>,
+    ) -> Option<Arc<dyn ObjectData<()>>> {
+        // the objects the requests make must have data too
+        msg.args
+            .iter()
+            .any(|arg| matches!(arg, Argument::NewId(_)))
+            .then_some(self as Arc<dyn ObjectData<()>>)
+    }
+
+    fn destroyed(self: Arc<Self>, _: &Handle, _: &mut (), _: ClientId, _: ObjectId) {}
+}
+
+/// An output of [`serve`], which tells its name to whoever binds it
+struct Output(String);
+
+impl GlobalHandler<()> for Output {
+    fn bind(
+        self: Arc<Self>,
+        handle: &Handle,
+        _: &mut (),
+        _: ClientId,
+        _: GlobalId,
+        output: ObjectId,
+    ) -> Arc<dyn ObjectData<()>> {
+        let name = std::ffi::CString::new(self.0.as_str()).unwrap();
+        // the scale, name and done events of wl_output
+        for (opcode, args) in [
+            (3, vec![Argument::Int(1)]),
+            (4, vec![Argument::Str(Some(Box::new(name)))]),
+            (2, vec![]),
+        ] {
+            let msg = Message {
+                sender_id: output.clone(),
+                opcode,
+                args: args.into(),
+            };
+            handle.send_event(msg).unwrap();
+        }
+        Arc::new(Ignore)
+    }
+}
+
+/// Whether the client of [`serve`] is still connected
+struct Connected(Arc<AtomicBool>);
+
+impl ClientData for Connected {
+    fn disconnected(&self, _: ClientId, _: DisconnectReason) {
+        self.0.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,173 @@
+//! Stops animating outputs that a fullscreen window covers, since nobody sees them anyway.
+//!
+//! We follow the windows through the `wlr_foreign_toplevel_management_v1` protocol, and consider
+//! an output covered while a fullscreen window that isn't minimized is on it. This is a heuristic:
+//! the protocol tells us nothing about workspaces, so some compositors might report a fullscreen
+//! window on a workspace that isn't showing. `--animate-occluded` turns it off. If the compositor
+//! doesn't support the protocol, we keep animating every output, as before.
+
+use log::debug;
+
+use smithay_client_toolkit::reexports::protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, State, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+use wayland_client::{event_created_child, globals::GlobalList, Connection, Dispatch, QueueHandle};
+
+use crate::Daemon;
+
+pub struct Occlusion {
+    manager: ZwlrForeignToplevelManagerV1,
+    toplevels: Vec<Toplevel>,
+}
+
+struct Toplevel {
+    handle: ZwlrForeignToplevelHandleV1,
+    /// The ids of the outputs the window is on
+    outputs: Vec<u32>,
+    fullscreen: bool,
+    minimized: bool,
+}
+
+impl Toplevel {
+    fn covers(&self, output_id: u32) -> bool {
+        self.fullscreen && !self.minimized && self.outputs.contains(&output_id)
+    }
+}
+
+impl Occlusion {
+    /// Returns `None` if the compositor doesn't support `wlr_foreign_toplevel_management_v1`
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Daemon>) -> Option<Self> {
+        // the fullscreen state only exists since version 2
+        match globals.bind::<ZwlrForeignToplevelManagerV1, _, _>(qh, 2..=3, ()) {
+            Ok(manager) => Some(Self {
+                manager,
+                toplevels: Vec::new(),
+            }),
+            Err(e) => {
+                debug!("wlr_foreign_toplevel_management_v1 is not available: {e}");
+                None
+            }
+        }
+    }
+
+    fn toplevel(&mut self, handle: &ZwlrForeignToplevelHandleV1) -> Option<&mut Toplevel> {
+        self.toplevels.iter_mut().find(|t| &t.handle == handle)
+    }
+}
+
+impl Daemon {
+    /// Starts or stops following the windows. Outputs we consider covered are animated again once
+    /// we stop
+    pub fn watch_occlusion(&mut self, watch: bool, qh: &QueueHandle<Self>) {
+        if let Some(occlusion) = self.occlusion.take() {
+            occlusion.manager.stop();
+        }
+        if watch {
+            self.occlusion = Occlusion::new(&self.globals, qh);
+        }
+        self.update_occlusion();
+    }
+
+    pub fn update_occlusion(&self) {
+        let toplevels = self.occlusion.as_ref().map_or(&[][..], |o| &o.toplevels);
+        for wallpaper in &self.wallpapers {
+            let occluded = toplevels.iter().any(|t| t.covers(wallpaper.output_id()));
+            if occluded != wallpaper.is_occluded() {
+                debug!("output {} occluded: {occluded}", wallpaper.name());
+                wallpaper.set_occluded(occluded);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for Daemon {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                if let Some(occlusion) = state.occlusion.as_mut().filter(|o| &o.manager == proxy) {
+                    occlusion.toplevels.push(Toplevel {
+                        handle: toplevel,
+                        outputs: Vec::new(),
+                        fullscreen: false,
+                        minimized: false,
+                    });
+                }
+            }
+            // the compositor won't tell us about windows anymore
+            zwlr_foreign_toplevel_manager_v1::Event::Finished => {
+                debug!("wlr_foreign_toplevel_management_v1 finished");
+                // unless it's one we stopped, and replaced since
+                if state
+                    .occlusion
+                    .as_ref()
+                    .is_some_and(|o| &o.manager == proxy)
+                {
+                    state.occlusion = None;
+                    state.update_occlusion();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    event_created_child!(Daemon, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for Daemon {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let output_id = |output| state.output_state.info(output).map(|info| info.id);
+        let Some(occlusion) = &mut state.occlusion else {
+            return;
+        };
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::OutputEnter { output } => {
+                if let (Some(id), Some(toplevel)) = (output_id(&output), occlusion.toplevel(proxy))
+                {
+                    toplevel.outputs.push(id);
+                }
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::OutputLeave { output } => {
+                if let (Some(id), Some(toplevel)) = (output_id(&output), occlusion.toplevel(proxy))
+                {
+                    toplevel.outputs.retain(|o| *o != id);
+                }
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::State { state: states } => {
+                if let Some(toplevel) = occlusion.toplevel(proxy) {
+                    let states: Vec<u32> = states
+                        .chunks_exact(4)
+                        .map(|s| u32::from_ne_bytes([s[0], s[1], s[2], s[3]]))
+                        .collect();
+                    toplevel.fullscreen = states.contains(&(State::Fullscreen as u32));
+                    toplevel.minimized = states.contains(&(State::Minimized as u32));
+                }
+            }
+            // everything before it applies at once
+            zwlr_foreign_toplevel_handle_v1::Event::Done => state.update_occlusion(),
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                occlusion.toplevels.retain(|t| &t.handle != proxy);
+                proxy.destroy();
+                state.update_occlusion();
+            }
+            _ => (),
+        }
+    }
+}
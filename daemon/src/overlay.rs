@@ -0,0 +1,90 @@
+//! Draws an image, like a logo or a vignette, over everything an output displays.
+//!
+//! Like with [`crate::tint`], an output with an overlay draws to a canvas of its own, without it,
+//! and we draw the overlay over a copy of it every time we copy it to the buffer. That way, every
+//! frame of an animation, which only unpacks what changed since the last one, gets it too.
+
+use utils::ipc::ArchivedOverlay;
+
+pub struct Overlay {
+    /// Premultiplied `[b, g, r, a]` pixels
+    img: Box<[u8]>,
+    dim: (usize, usize),
+    anchor: (f32, f32),
+}
+
+impl Overlay {
+    pub fn new(overlay: &ArchivedOverlay) -> Result<Self, String> {
+        let dim = (overlay.dim.0 as usize, overlay.dim.1 as usize);
+        if overlay.img.len() != dim.0 * dim.1 * 4 {
+            return Err(format!(
+                "overlay is {}x{}, but has {} bytes",
+                dim.0,
+                dim.1,
+                overlay.img.len()
+            ));
+        }
+        let anchor = (overlay.anchor.0, overlay.anchor.1);
+        if !(0.0..=1.0).contains(&anchor.0) || !(0.0..=1.0).contains(&anchor.1) {
+            return Err(format!(
+                "overlay anchors must be between 0.0 and 1.0, got: {anchor:?}"
+            ));
+        }
+        Ok(Self {
+            img: overlay.img.as_ref().into(),
+            dim,
+            anchor,
+        })
+    }
+
+    /// Draws the overlay over `canvas`, `xrgb8888` pixels `dim` in size. What doesn't fit is left
+    /// out
+    pub fn apply(&self, canvas: &mut [u8], dim: (usize, usize)) {
+        let (width, height) = dim;
+        let x = ((width.saturating_sub(self.dim.0)) as f32 * self.anchor.0).round() as usize;
+        let y = ((height.saturating_sub(self.dim.1)) as f32 * self.anchor.1).round() as usize;
+        let row_len = self.dim.0.min(width - x) * 4;
+        for (row, src) in self
+            .img
+            .chunks_exact(self.dim.0 * 4)
+            .take(height - y)
+            .enumerate()
+        {
+            let start = ((y + row) * width + x) * 4;
+            let dst = &mut canvas[start..start + row_len];
+            for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+                let alpha = src[3] as u32;
+                if alpha == 0 {
+                    continue;
+                }
+                for c in 0..3 {
+                    dst[c] = (src[c] as u32 + (dst[c] as u32 * (255 - alpha) + 127) / 255) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlays_should_blend_at_their_anchor() {
+        // 2x1: opaque red, then half transparent white, premultiplied
+        let overlay = Overlay {
+            img: [0, 0, 255, 255, 128, 128, 128, 128].into(),
+            dim: (2, 1),
+            anchor: (1.0, 1.0),
+        };
+        let mut canvas = [0; 3 * 2 * 4];
+        overlay.apply(&mut canvas, (3, 2));
+        assert_eq!(&canvas[..16], [0; 16]);
+        assert_eq!(&canvas[16..], [0, 0, 255, 0, 128, 128, 128, 0]);
+
+        // too big for the canvas, so we only draw its left side
+        let mut canvas = [100; 4];
+        overlay.apply(&mut canvas, (1, 1));
+        assert_eq!(canvas, [0, 0, 255, 100]);
+    }
+}
@@ -0,0 +1,98 @@
+//! Finds the dominant colors of what an output displays, for theming tools, with a variant of the
+//! median cut.
+//!
+//! We start with every color in a single box, and keep splitting the box that spans the widest
+//! range of any channel, times how many pixels it has. Unlike the median cut, which splits it at
+//! the median of that channel, we split it in the middle of the range, so that a small patch of a
+//! color doesn't get averaged with half of a big one. Every box then gives the average of its
+//! colors.
+
+/// More than enough pixels to find the dominant colors of any picture, and little enough to do it
+/// right away
+const MAX_SAMPLES: usize = 1 << 14;
+
+/// Up to `count` dominant colors of `canvas`, `xrgb8888` pixels (`[b, g, r, x]` in memory), from
+/// most to least common. There are fewer if the canvas has fewer colors
+pub fn dominant_colors(canvas: &[u8], count: usize) -> Vec<[u8; 3]> {
+    let pixels = canvas.chunks_exact(4);
+    let step = (pixels.len() / MAX_SAMPLES).max(1);
+    let samples: Vec<_> = pixels.step_by(step).map(|p| [p[2], p[1], p[0]]).collect();
+    if samples.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    let mut boxes = vec![samples];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, colors)| {
+                let (channel, min, max) = widest_channel(colors);
+                (i, channel, min, max, (max - min) as usize * colors.len())
+            })
+            .filter(|(.., weight)| *weight > 0)
+            .max_by_key(|(.., weight)| *weight);
+        let Some((i, channel, min, max, _)) = widest else {
+            // every box has a single color
+            break;
+        };
+        let colors = &mut boxes[i];
+        colors.sort_unstable_by_key(|color| color[channel]);
+        // above the minimum, so neither half is empty
+        let middle = min + (max - min) / 2 + 1;
+        let upper = colors.split_off(colors.partition_point(|color| color[channel] < middle));
+        boxes.push(upper);
+    }
+
+    boxes.sort_by_key(|colors| std::cmp::Reverse(colors.len()));
+    boxes.iter().map(|colors| average(colors)).collect()
+}
+
+/// The channel whose values span the widest range in `colors`, and its minimum and maximum
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8, u8) {
+    (0..3)
+        .map(|channel| {
+            let (min, max) = colors.iter().fold((u8::MAX, u8::MIN), |(min, max), color| {
+                (min.min(color[channel]), max.max(color[channel]))
+            });
+            (channel, min, max)
+        })
+        .max_by_key(|(_, min, max)| max - min)
+        .unwrap()
+}
+
+fn average(colors: &[[u8; 3]]) -> [u8; 3] {
+    let mut sums = [0u64; 3];
+    for color in colors {
+        for (sum, col) in sums.iter_mut().zip(color) {
+            *sum += *col as u64;
+        }
+    }
+    sums.map(|sum| (sum as f64 / colors.len() as f64).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canvas(colors: &[([u8; 3], usize)]) -> Vec<u8> {
+        colors
+            .iter()
+            .flat_map(|&([r, g, b], n)| std::iter::repeat([b, g, r, 0xff]).take(n).flatten())
+            .collect()
+    }
+
+    #[test]
+    fn most_common_color_comes_first() {
+        let canvas = canvas(&[([0, 0, 255], 100), ([255, 0, 0], 300)]);
+        assert_eq!(dominant_colors(&canvas, 2), [[255, 0, 0], [0, 0, 255]]);
+    }
+
+    #[test]
+    fn cannot_find_more_colors_than_there_are() {
+        let canvas = canvas(&[([10, 20, 30], 50), ([200, 100, 0], 50)]);
+        assert_eq!(dominant_colors(&canvas, 8).len(), 2);
+        assert_eq!(dominant_colors(&canvas, 1), [[105, 60, 15]]);
+        assert!(dominant_colors(&[], 8).is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+//! Shifts what an output draws a few pixels away from the pointer, for `--parallax`.
+//!
+//! A shifted output draws to a canvas of its own, larger than the output by the most it shifts by
+//! on every side, and the clients make their images that much larger too. Every time we copy it to
+//! the buffer, we copy the part of it the shift shows, so the shift never uncovers anything, and
+//! animations keep drawing what they always do. The pointer only moves the wallpaper while it is
+//! over the desktop, since that is the only time we hear from it. We redraw still images when the
+//! shift changes by a whole pixel, at most once per frame callback, and leave animations to pick
+//! it up with their next frame.
+
+/// How far a canvas `canvas_dim` in size reaches past every edge of an output `dim` in size,
+/// which is as far as we may shift it
+pub fn margin(canvas_dim: (usize, usize), dim: (usize, usize)) -> (u32, u32) {
+    (
+        (canvas_dim.0.saturating_sub(dim.0) / 2) as u32,
+        (canvas_dim.1.saturating_sub(dim.1) / 2) as u32,
+    )
+}
+
+/// How many pixels to shift an output `dim` in size by, with the pointer at `cursor`, away from
+/// it, and by `max` at most. Both are in pixels of our buffers, with y growing downwards
+pub fn shift_for(cursor: (f32, f32), dim: (u32, u32), max: (u32, u32)) -> (i32, i32) {
+    let away = |position: f32, len: u32, max: u32| {
+        let from_center = (position / len as f32 - 0.5).clamp(-0.5, 0.5);
+        (-from_center * 2.0 * max as f32).round() as i32
+    };
+    (away(cursor.0, dim.0, max.0), away(cursor.1, dim.1, max.1))
+}
+
+/// Where the part of a canvas `canvas_dim` in size that an output `dim` in size shows with `shift`
+/// starts
+fn origin(canvas_dim: (usize, usize), dim: (usize, usize), shift: (i32, i32)) -> (usize, usize) {
+    let (margin_x, margin_y) = margin(canvas_dim, dim);
+    let at = |margin: u32, shift: i32, extra: usize| {
+        (margin as i64 - shift as i64).clamp(0, extra as i64) as usize
+    };
+    (
+        at(margin_x, shift.0, canvas_dim.0.saturating_sub(dim.0)),
+        at(margin_y, shift.1, canvas_dim.1.saturating_sub(dim.1)),
+    )
+}
+
+/// Copies the part of `src`, 4 byte pixels `canvas_dim` in size, that an output `dim` in size
+/// shows with `shift`, into `dst`
+pub fn shift(
+    src: &[u8],
+    canvas_dim: (usize, usize),
+    dst: &mut [u8],
+    dim: (usize, usize),
+    shift: (i32, i32),
+) {
+    let (x, y) = origin(canvas_dim, dim, shift);
+    let row_len = dim.0 * 4;
+    for (row, dst_row) in dst.chunks_exact_mut(row_len).enumerate() {
+        let start = ((y + row) * canvas_dim.0 + x) * 4;
+        dst_row.copy_from_slice(&src[start..start + row_len]);
+    }
+}
+
+/// The parts of an output `dim` in size, shifted by `shift`, that show `damage`, the parts of the
+/// canvas `canvas_dim` in size that changed, as `(x, y, width, height)`
+pub fn shift_damage(
+    damage: &[(usize, usize, usize, usize)],
+    canvas_dim: (usize, usize),
+    dim: (usize, usize),
+    shift: (i32, i32),
+) -> Vec<(usize, usize, usize, usize)> {
+    let (origin_x, origin_y) = origin(canvas_dim, dim, shift);
+    damage
+        .iter()
+        .filter_map(|&(x, y, width, height)| {
+            let left = x.max(origin_x);
+            let top = y.max(origin_y);
+            let right = (x + width).min(origin_x + dim.0);
+            let bottom = (y + height).min(origin_y + dim.1);
+            (left < right && top < bottom)
+                .then(|| (left - origin_x, top - origin_y, right - left, bottom - top))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_should_go_away_from_the_pointer() {
+        assert_eq!(shift_for((50.0, 25.0), (100, 50), (10, 10)), (0, 0));
+        assert_eq!(shift_for((100.0, 0.0), (100, 50), (10, 10)), (-10, 10));
+        assert_eq!(shift_for((25.0, 50.0), (100, 50), (10, 4)), (5, -4));
+        // pointers just past an edge shift as far as the edge
+        assert_eq!(shift_for((-5.0, 60.0), (100, 50), (10, 10)), (10, -10));
+    }
+
+    #[test]
+    fn shifting_should_show_more_of_the_canvas() {
+        // 4x3, with a margin of 1 pixel around a 2x1 output, one byte per pixel, repeated 4 times
+        let src: Vec<u8> = (1..=12).flat_map(|p| [p; 4]).collect();
+        let shifted = |by| {
+            let mut dst = vec![0; 2 * 4];
+            shift(&src, (4, 3), &mut dst, (2, 1), by);
+            dst.chunks_exact(4).map(|p| p[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(margin((4, 3), (2, 1)), (1, 1));
+        assert_eq!(shifted((0, 0)), [6, 7]);
+        assert_eq!(shifted((1, 0)), [5, 6]);
+        assert_eq!(shifted((-1, 1)), [3, 4]);
+        // never past the canvas
+        assert_eq!(shifted((5, -5)), [9, 10]);
+    }
+
+    #[test]
+    fn damage_should_follow_the_shift() {
+        let canvas_dim = (12, 12);
+        let dim = (8, 8);
+        // the whole canvas, a corner the output doesn't show, and one it shows part of
+        let damage = [(0, 0, 12, 12), (0, 0, 2, 2), (9, 9, 3, 3)];
+        assert_eq!(
+            shift_damage(&damage, canvas_dim, dim, (0, 0)),
+            [(0, 0, 8, 8), (7, 7, 1, 1)]
+        );
+        assert_eq!(
+            shift_damage(&damage, canvas_dim, dim, (2, 2)),
+            [(0, 0, 8, 8), (0, 0, 2, 2)]
+        );
+        assert_eq!(
+            shift_damage(&damage, canvas_dim, dim, (-2, -2)),
+            [(0, 0, 8, 8), (5, 5, 3, 3)]
+        );
+    }
+}
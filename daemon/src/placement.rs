@@ -0,0 +1,132 @@
+//! Where on its output a wallpaper goes, see `--anchor`, `--margin` and `--size`.
+//!
+//! By default, wallpapers cover the whole output. Otherwise, their layer surfaces only cover a
+//! region of it, and we tell clients that region is the size of the output, so that they make the
+//! images for it, like they would for a smaller output.
+
+use smithay_client_toolkit::shell::wlr_layer::Anchor;
+
+/// Which corner or edge of the output a wallpaper with a `--size` sticks to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl std::str::FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Self::TopLeft),
+            "top" => Ok(Self::Top),
+            "top-right" => Ok(Self::TopRight),
+            "left" => Ok(Self::Left),
+            "center" => Ok(Self::Center),
+            "right" => Ok(Self::Right),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom" => Ok(Self::Bottom),
+            "bottom-right" => Ok(Self::BottomRight),
+            other => Err(format!(
+                "must be 'top-left', 'top', 'top-right', 'left', 'center', 'right', \
+                 'bottom-left', 'bottom' or 'bottom-right', got: {other}"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub position: Position,
+    /// Top, right, bottom and left, in logical pixels
+    pub margin: [i32; 4],
+    /// In logical pixels. `None` covers the whole output, but the margins
+    pub size: Option<(i32, i32)>,
+}
+
+impl Placement {
+    /// The edges of the output the layer surface sticks to
+    pub fn anchor(&self) -> Anchor {
+        if self.size.is_none() {
+            return Anchor::all();
+        }
+        match self.position {
+            Position::TopLeft => Anchor::TOP | Anchor::LEFT,
+            Position::Top => Anchor::TOP,
+            Position::TopRight => Anchor::TOP | Anchor::RIGHT,
+            Position::Left => Anchor::LEFT,
+            Position::Center => Anchor::empty(),
+            Position::Right => Anchor::RIGHT,
+            Position::BottomLeft => Anchor::BOTTOM | Anchor::LEFT,
+            Position::Bottom => Anchor::BOTTOM,
+            Position::BottomRight => Anchor::BOTTOM | Anchor::RIGHT,
+        }
+    }
+
+    /// Where the wallpaper goes on an output of this logical size: its offset from the top left
+    /// corner of the output, and its size, which is never empty
+    pub fn region(&self, output: (i32, i32)) -> ((i32, i32), (i32, i32)) {
+        let [top, right, bottom, left] = self.margin;
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => (output.0 - left - right, output.1 - top - bottom),
+        };
+        let (width, height) = (width.max(1), height.max(1));
+        let anchor = self.anchor();
+        // like the compositor does: anchored to one edge, we keep to it, to both, or to none, we
+        // go in the middle
+        let offset =
+            |start: Anchor, end: Anchor, before: i32, after: i32, len: i32, out: i32| match (
+                anchor.contains(start),
+                anchor.contains(end),
+            ) {
+                (true, false) => before,
+                (false, true) => out - len - after,
+                _ => before + (out - before - after - len) / 2,
+            };
+        let x = offset(Anchor::LEFT, Anchor::RIGHT, left, right, width, output.0);
+        let y = offset(Anchor::TOP, Anchor::BOTTOM, top, bottom, height, output.1);
+        ((x, y), (width, height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_cover_the_output_but_the_margins() {
+        let placement = Placement {
+            margin: [10, 20, 30, 40],
+            ..Default::default()
+        };
+        assert_eq!(placement.anchor(), Anchor::all());
+        assert_eq!(placement.region((1920, 1080)), ((40, 10), (1860, 1040)));
+        assert_eq!(
+            Placement::default().region((1920, 1080)),
+            ((0, 0), (1920, 1080))
+        );
+    }
+
+    #[test]
+    fn should_keep_to_the_anchored_edges() {
+        let mut placement = Placement {
+            position: Position::TopRight,
+            margin: [20; 4],
+            size: Some((800, 600)),
+        };
+        assert_eq!(placement.region((1920, 1080)), ((1100, 20), (800, 600)));
+        placement.position = Position::Center;
+        assert_eq!(placement.region((1920, 1080)), ((560, 240), (800, 600)));
+        placement.position = Position::Bottom;
+        assert_eq!(placement.region((1920, 1080)), ((560, 460), (800, 600)));
+    }
+}
@@ -0,0 +1,98 @@
+//! Stops animating outputs that are turned off, like when the compositor blanks them while idle.
+//!
+//! The compositor tells us whether every output is on through the
+//! `wlr_output_power_management_v1` protocol, which we only ever listen to: we never turn outputs
+//! on or off ourselves. If the compositor doesn't support it, we keep animating every output, as
+//! before.
+
+use log::debug;
+
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::{
+    zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1,
+    zwlr_output_power_v1::{self, Mode, ZwlrOutputPowerV1},
+};
+
+use wayland_client::{
+    globals::GlobalList, protocol::wl_output::WlOutput, Connection, Dispatch, QueueHandle, WEnum,
+};
+
+use crate::Daemon;
+
+pub struct OutputPower {
+    manager: ZwlrOutputPowerManagerV1,
+    /// The output id every power object reports about
+    controls: Vec<(u32, ZwlrOutputPowerV1)>,
+}
+
+impl OutputPower {
+    /// Returns `None` if the compositor doesn't support `wlr_output_power_management_v1`
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Daemon>) -> Option<Self> {
+        match globals.bind::<ZwlrOutputPowerManagerV1, _, _>(qh, 1..=1, ()) {
+            Ok(manager) => Some(Self {
+                manager,
+                controls: Vec::new(),
+            }),
+            Err(e) => {
+                debug!("wlr_output_power_management_v1 is not available: {e}");
+                None
+            }
+        }
+    }
+
+    /// Starts listening to the power mode of `output`, whose id is `output_id`. The compositor
+    /// tells us the current mode right away
+    pub fn watch(&mut self, output: &WlOutput, output_id: u32, qh: &QueueHandle<Daemon>) {
+        let control = self.manager.get_output_power(output, qh, output_id);
+        self.controls.push((output_id, control));
+    }
+
+    pub fn forget(&mut self, output_id: u32) {
+        self.controls.retain(|(id, control)| {
+            if *id == output_id {
+                control.destroy();
+            }
+            *id != output_id
+        });
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerManagerV1, ()> for Daemon {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrOutputPowerManagerV1,
+        _event: <ZwlrOutputPowerManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwlr_output_power_manager_v1 has no events
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerV1, u32> for Daemon {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrOutputPowerV1,
+        event: zwlr_output_power_v1::Event,
+        output_id: &u32,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let powered_off = match event {
+            zwlr_output_power_v1::Event::Mode { mode } => mode == WEnum::Value(Mode::Off),
+            // we can no longer know, so we'd rather keep animating it
+            zwlr_output_power_v1::Event::Failed => {
+                debug!("lost track of the power mode of output {output_id}");
+                if let Some(power) = &mut state.power {
+                    power.forget(*output_id);
+                }
+                false
+            }
+            _ => return,
+        };
+        if let Some(wallpaper) = state.wallpapers.iter().find(|w| w.has_id(*output_id)) {
+            debug!("output {} powered off: {powered_off}", wallpaper.name());
+            wallpaper.set_powered_off(powered_off);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! The `swww query --format json` output. We make it ourselves, rather than the client, since only
+//! we know what the animations on our outputs are doing.
+
+use serde_json::{json, Value};
+use utils::ipc::{BgImg, BgInfo};
+
+/// What the animation on an output is doing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationState {
+    /// The index of the frame on the screen
+    pub frame: usize,
+    pub paused: bool,
+    /// How many times faster than its frame delays say it plays
+    pub speed: f32,
+}
+
+/// All of `outputs`, with `gc_freed_bytes` if `swww query --verbose` wants it. This is `None`
+/// inside if the garbage collector is disabled
+pub fn json(
+    outputs: &[(BgInfo, Option<AnimationState>)],
+    gc_freed_bytes: Option<Option<u64>>,
+) -> String {
+    let outputs: Vec<_> = outputs
+        .iter()
+        .map(|(info, animation)| {
+            json!({
+                "name": info.name,
+                "width": info.dim.0,
+                "height": info.dim.1,
+                "scale": info.scale_factor,
+                "fractional_scale": info.fractional_scale.map(|s| s as f64 / 120.0),
+                "transform": info.transform.to_string(),
+                "x": info.position.0,
+                "y": info.position.1,
+                "displaying": displaying(&info.img),
+                "transitioning": info.transitioning,
+                "animated": animation.is_some(),
+                "animation_frame": animation.map(|a| a.frame),
+                "animation": animation.map(|a| json!({
+                    "frame": a.frame,
+                    "paused": a.paused,
+                    "speed": a.speed,
+                })),
+                "set_at_ms": info.set_at_ms,
+            })
+        })
+        .collect();
+    let mut json = json!({ "outputs": outputs });
+    if let Some(gc_freed_bytes) = gc_freed_bytes {
+        json["gc_freed_bytes"] = json!(gc_freed_bytes);
+    }
+    json.to_string()
+}
+
+/// `{"type":"image","path":PATH}`, `{"type":"color","color":"rrggbb"}`, a gradient between two
+/// colors, or `{"type":"shader","path":PATH,"fps":FPS}`
+fn displaying(img: &BgImg) -> Value {
+    let hex = |[r, g, b]: [u8; 3]| format!("{r:02x}{g:02x}{b:02x}");
+    match img {
+        BgImg::Color(color) => json!({ "type": "color", "color": hex(*color) }),
+        BgImg::Img(path) => json!({ "type": "image", "path": path }),
+        BgImg::Shader { path, fps } => json!({ "type": "shader", "path": path, "fps": fps }),
+        BgImg::Gradient(gradient) => json!({
+            "type": "gradient",
+            "from": hex(gradient.from),
+            "to": hex(gradient.to),
+            "angle": gradient.angle,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::ipc::Transform;
+
+    #[test]
+    fn should_tell_what_every_output_and_its_animation_do() {
+        let info = BgInfo {
+            name: "DP-1".to_string(),
+            dim: (1920, 1080),
+            scale_factor: 2,
+            fractional_scale: None,
+            transform: Transform::Rotated90,
+            position: (-1920, 0),
+            img: BgImg::Img("/a \"quoted\" path.png".to_string()),
+            transitioning: true,
+            animation_frame: Some(3),
+            set_at_ms: Some(1_700_000_000_000),
+        };
+        let animation = AnimationState {
+            frame: 3,
+            paused: true,
+            speed: 0.5,
+        };
+        let mut still = info.clone();
+        still.name = "DP-2".to_string();
+        still.fractional_scale = Some(180);
+        still.img = BgImg::Color([255, 0, 16]);
+        let outputs = [(info, Some(animation)), (still, None)];
+
+        let json: Value = serde_json::from_str(&super::json(&outputs, None)).unwrap();
+        assert_eq!(
+            json["outputs"][0],
+            json!({
+                "name": "DP-1",
+                "width": 1920,
+                "height": 1080,
+                "scale": 2,
+                "fractional_scale": null,
+                "transform": "90",
+                "x": -1920,
+                "y": 0,
+                "displaying": {"type": "image", "path": "/a \"quoted\" path.png"},
+                "transitioning": true,
+                "animated": true,
+                "animation_frame": 3,
+                "animation": {"frame": 3, "paused": true, "speed": 0.5},
+                "set_at_ms": 1_700_000_000_000u64,
+            })
+        );
+        let still = &json["outputs"][1];
+        assert_eq!(still["fractional_scale"], 1.5);
+        assert_eq!(
+            still["displaying"],
+            json!({"type": "color", "color": "ff0010"})
+        );
+        assert_eq!(still["animated"], false);
+        assert_eq!(still["animation"], Value::Null);
+        assert!(json.get("gc_freed_bytes").is_none());
+
+        let json: Value = serde_json::from_str(&super::json(&outputs, Some(None))).unwrap();
+        assert_eq!(json["gc_freed_bytes"], Value::Null);
+    }
+}
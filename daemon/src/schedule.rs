@@ -0,0 +1,454 @@
+//! Shows images at set times of the day, from the `[schedule]` section of the configuration file.
+//!
+//! Every key of the section, other than `location`, `outputs` and `transition-type`, is when an
+//! image starts: a time, like `"07:00"`, or `sunrise` or `sunset`, maybe with an offset, like
+//! `"sunset-30m"`. An image lasts until the next one starts, unless its key is a range, like
+//! `"22:00..sunrise"`, in which case it shows over the others while the range lasts, and they come
+//! back once it is over. Sunrise and sunset need the `location` as `"<latitude>,<longitude>"`.
+//!
+//! Like slideshows, the schedule runs on a thread of its own, and spawns `swww img` to show the
+//! images, so that they get the transition of the `[img]` section, unless `transition-type` says
+//! otherwise. Every few seconds, it works out which image should be showing from the wall clock,
+//! and only shows it when that changes. So it catches up right after a resume from suspend, and
+//! follows daylight saving time, while leaving alone whatever the user showed in between.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, error, info};
+
+/// How often we look at the clock
+const TICK: Duration = Duration::from_secs(5);
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// The keys of the section that aren't times
+const SETTINGS: &[&str] = &["location", "outputs", "transition-type"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    entries: Vec<Entry>,
+    /// Latitude and longitude, in degrees, north and east positive
+    location: Option<(f64, f64)>,
+    /// What to pass to `swww img --outputs`, or every output, if `None`
+    outputs: Option<String>,
+    transition_type: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    start: Time,
+    end: Option<Time>,
+    image: PathBuf,
+}
+
+/// A time of the day, as seconds after midnight or after the sun's event, which may be negative
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Time {
+    Clock(i64),
+    Sunrise(i64),
+    Sunset(i64),
+}
+
+impl Schedule {
+    /// Reads the schedule from the `key = value` pairs of its section, or `None` if it has no
+    /// images. Relative paths are relative to `dir`, the configuration file's directory
+    pub fn parse(section: &[(String, String)], dir: Option<&Path>) -> Result<Option<Self>, String> {
+        let mut schedule = Self {
+            entries: Vec::new(),
+            location: None,
+            outputs: None,
+            transition_type: None,
+        };
+        for (key, value) in section {
+            match key.as_str() {
+                "location" => schedule.location = Some(parse_location(value)?),
+                "outputs" => schedule.outputs = Some(value.clone()),
+                "transition-type" => schedule.transition_type = Some(value.clone()),
+                key => {
+                    let (start, end) = match key.split_once("..") {
+                        Some((start, end)) => (parse_time(start)?, Some(parse_time(end)?)),
+                        None => (
+                            parse_time(key)
+                                .map_err(|e| format!("{e}, or any of {}", SETTINGS.join(", ")))?,
+                            None,
+                        ),
+                    };
+                    schedule.entries.push(Entry {
+                        start,
+                        end,
+                        image: expand(value, dir),
+                    });
+                }
+            }
+        }
+        let uses_sun = schedule.entries.iter().any(|entry| {
+            std::iter::once(entry.start)
+                .chain(entry.end)
+                .any(|time| !matches!(time, Time::Clock(_)))
+        });
+        if uses_sun && schedule.location.is_none() {
+            return Err("sunrise and sunset need a location, like location = \"52.5,13.4\"".into());
+        }
+        Ok((!schedule.entries.is_empty()).then_some(schedule))
+    }
+
+    /// Which entry should be showing `now`, and the midnight of the day it started on, to tell
+    /// one day's from the next, all in seconds of local time, where `offset` is how far ahead of
+    /// UTC that is right now. `None` if no entry started yet, or if only ranges did, and they are
+    /// over
+    fn active(&self, now: i64, offset: i64) -> Option<(usize, i64)> {
+        let today = now.div_euclid(DAY) * DAY;
+        let mut active: Option<(i64, usize, i64)> = None;
+        // offsets can move events of one day into the next
+        for midnight in [today - 2 * DAY, today - DAY, today] {
+            for (i, entry) in self.entries.iter().enumerate() {
+                let Some(start) = self.at(entry.start, midnight, offset) else {
+                    continue;
+                };
+                if start > now || active.is_some_and(|(latest, ..)| start < latest) {
+                    continue;
+                }
+                if let Some(end) = entry.end {
+                    let Some(mut end) = self.at(end, midnight, offset) else {
+                        continue;
+                    };
+                    if end <= start {
+                        end += DAY;
+                    }
+                    if now >= end {
+                        continue;
+                    }
+                }
+                active = Some((start, i, midnight));
+            }
+        }
+        active.map(|(_, i, midnight)| (i, midnight))
+    }
+
+    /// When `time` is on the day starting at `midnight`, or `None` if the sun doesn't rise or set
+    /// that day
+    fn at(&self, time: Time, midnight: i64, offset: i64) -> Option<i64> {
+        let sun = |event: fn(&(i64, i64)) -> i64| {
+            let (latitude, longitude) = self.location?;
+            // the sun is computed in UTC
+            sun_events(midnight - offset + DAY / 2, latitude, longitude)
+                .map(|events| event(&events) + offset)
+        };
+        match time {
+            Time::Clock(secs) => Some(midnight + secs),
+            Time::Sunrise(secs) => sun(|(sunrise, _)| *sunrise).map(|t| t + secs),
+            Time::Sunset(secs) => sun(|(_, sunset)| *sunset).map(|t| t + secs),
+        }
+    }
+}
+
+fn parse_location(s: &str) -> Result<(f64, f64), String> {
+    let invalid = || format!("location must be '<latitude>,<longitude>' in degrees, got: {s}");
+    let (latitude, longitude) = s.split_once(',').ok_or_else(invalid)?;
+    let latitude: f64 = latitude.trim().parse().map_err(|_| invalid())?;
+    let longitude: f64 = longitude.trim().parse().map_err(|_| invalid())?;
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(invalid());
+    }
+    Ok((latitude, longitude))
+}
+
+/// `HH:MM`, `HH:MM:SS`, `sunrise` or `sunset`, then maybe `+` or `-` and an offset, like `1h30m`
+fn parse_time(s: &str) -> Result<Time, String> {
+    let invalid = || format!("expected a time, like '07:00', 'sunrise' or 'sunset-30m', got: {s}");
+    let s = s.trim();
+    let (base, offset) = match s.find(['+', '-']) {
+        Some(i) => (&s[..i], parse_offset(&s[i..]).ok_or_else(invalid)?),
+        None => (s, 0),
+    };
+    let time = match base.trim() {
+        "sunrise" => Time::Sunrise(offset),
+        "sunset" => Time::Sunset(offset),
+        clock => {
+            let field = |field: &str, max: i64| {
+                let value: i64 = field.parse().ok()?;
+                (field.len() == 2 && (0..max).contains(&value)).then_some(value)
+            };
+            let secs = match clock.split(':').collect::<Vec<_>>()[..] {
+                [h, m] => field(h, 24)
+                    .zip(field(m, 60))
+                    .map(|(h, m)| h * 3600 + m * 60),
+                [h, m, s] => field(h, 24)
+                    .zip(field(m, 60))
+                    .zip(field(s, 60))
+                    .map(|((h, m), s)| h * 3600 + m * 60 + s),
+                _ => None,
+            };
+            Time::Clock(secs.ok_or_else(invalid)? + offset)
+        }
+    };
+    Ok(time)
+}
+
+/// `+1h30m`, `-45m`, `+90s`..., in seconds
+fn parse_offset(s: &str) -> Option<i64> {
+    let (sign, mut rest) = match s.split_at(1) {
+        ("+", rest) => (1, rest.trim()),
+        ("-", rest) => (-1, rest.trim()),
+        _ => return None,
+    };
+    let mut secs = 0;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: i64 = rest[..digits].parse().ok()?;
+        let unit = match rest[digits..].chars().next() {
+            Some('h') => 3600,
+            Some('m') => 60,
+            Some('s') => 1,
+            _ => return None,
+        };
+        secs += value * unit;
+        rest = &rest[digits + 1..];
+    }
+    (secs > 0 && secs < DAY).then_some(sign * secs)
+}
+
+fn expand(path: &str, dir: Option<&Path>) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = std::env::var_os("HOME") {
+            return Path::new(&home).join(rest);
+        }
+    }
+    match dir {
+        Some(dir) if Path::new(path).is_relative() => dir.join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// When the sun rises and sets, as unix timestamps, on the day around `noon`, at `latitude` and
+/// `longitude`, or `None` during polar days and nights. This is the sunrise equation NOAA uses,
+/// which is good to about a minute
+fn sun_events(noon: i64, latitude: f64, longitude: f64) -> Option<(i64, i64)> {
+    const J2000: f64 = 2_451_545.0;
+    const UNIX_EPOCH: f64 = 2_440_587.5;
+    let julian = |unix: i64| unix as f64 / DAY as f64 + UNIX_EPOCH;
+    let (sin, cos) = (
+        |deg: f64| deg.to_radians().sin(),
+        |deg: f64| deg.to_radians().cos(),
+    );
+
+    // the day, counted from J2000, whose solar noon is closest to `noon` where we are
+    let day = (julian(noon) - J2000 + longitude / 360.0).round();
+    let mean_noon = day - longitude / 360.0;
+    let anomaly = (357.5291 + 0.985_600_28 * mean_noon).rem_euclid(360.0);
+    let center = 1.9148 * sin(anomaly) + 0.02 * sin(2.0 * anomaly) + 0.0003 * sin(3.0 * anomaly);
+    let ecliptic = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let transit = J2000 + mean_noon + 0.0053 * sin(anomaly) - 0.0069 * sin(2.0 * ecliptic);
+    let declination = (sin(ecliptic) * sin(23.4397)).asin().to_degrees();
+    let hour_angle =
+        (sin(-0.833) - sin(latitude) * sin(declination)) / (cos(latitude) * cos(declination));
+    if !(-1.0..=1.0).contains(&hour_angle) {
+        return None;
+    }
+    let half_day = hour_angle.acos().to_degrees() / 360.0;
+    let unix = |julian: f64| ((julian - UNIX_EPOCH) * DAY as f64).round() as i64;
+    Some((unix(transit - half_day), unix(transit + half_day)))
+}
+
+extern "C" {
+    // POSIX, but missing from the libc crate
+    fn tzset();
+}
+
+/// The unix time, and how far ahead of UTC local time is, in seconds
+fn now() -> (i64, i64) {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64);
+    let offset = unsafe {
+        // picks up changes to the time zone, like when travelling
+        tzset();
+        let mut tm = std::mem::zeroed();
+        if nix::libc::localtime_r(&(now as nix::libc::time_t), &mut tm).is_null() {
+            0
+        } else {
+            tm.tm_gmtoff as i64
+        }
+    };
+    (now, offset)
+}
+
+/// Runs the schedule, once the daemon is ready to show its images
+#[derive(Default)]
+pub struct Scheduler {
+    schedule: Option<Schedule>,
+    ready: bool,
+    stopped: Option<Arc<AtomicBool>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the schedule, which starts over if it changed
+    pub fn configure(&mut self, schedule: Option<Schedule>) {
+        if self.schedule != schedule {
+            self.schedule = schedule;
+            self.restart();
+        }
+    }
+
+    /// Starts the schedule, now that the outputs are configured
+    pub fn ready(&mut self) {
+        if !self.ready {
+            self.ready = true;
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        if let Some(stopped) = self.stopped.take() {
+            stopped.store(true, Ordering::Release);
+        }
+        let Some(schedule) = self.schedule.clone().filter(|_| self.ready) else {
+            return;
+        };
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = Arc::clone(&stopped);
+        let spawned = std::thread::Builder::new()
+            .name("schedule".to_string())
+            .stack_size(1 << 15)
+            .spawn(move || run(&schedule, &thread_stopped));
+        match spawned {
+            Ok(_) => self.stopped = Some(stopped),
+            Err(e) => error!("failed to spawn `schedule` thread: {e}"),
+        }
+    }
+}
+
+fn run(schedule: &Schedule, stopped: &AtomicBool) {
+    let mut shown = None;
+    loop {
+        if stopped.load(Ordering::Acquire) || crate::should_daemon_exit() {
+            debug!("stopped the schedule");
+            return;
+        }
+        let (now, offset) = now();
+        // in the gaps between ranges, we leave the last image
+        if let Some(active) = schedule.active(now + offset, offset) {
+            if shown != Some(active) {
+                let image = &schedule.entries[active.0].image;
+                info!("the schedule shows {image:?}");
+                show(schedule, image);
+                shown = Some(active);
+            }
+        }
+        std::thread::sleep(TICK);
+    }
+}
+
+fn show(schedule: &Schedule, path: &Path) {
+    let mut command = crate::auth::swww_command();
+    command.arg("img");
+    if let Some(outputs) = &schedule.outputs {
+        command.arg(format!("--outputs={outputs}"));
+    }
+    if let Some(transition_type) = &schedule.transition_type {
+        command.arg(format!("--transition-type={transition_type}"));
+    }
+    command.arg(path);
+    match command.status() {
+        Ok(status) if !status.success() => error!("schedule failed to show {path:?}: {status}"),
+        Ok(_) => (),
+        Err(e) => error!("schedule failed to spawn child process: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(section: &[(&str, &str)]) -> Result<Option<Schedule>, String> {
+        let section: Vec<_> = section
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Schedule::parse(&section, None)
+    }
+
+    #[test]
+    fn should_parse_times() {
+        assert_eq!(parse_time("07:30"), Ok(Time::Clock(7 * 3600 + 30 * 60)));
+        assert_eq!(parse_time("23:59:59"), Ok(Time::Clock(DAY - 1)));
+        assert_eq!(parse_time("sunset-30m"), Ok(Time::Sunset(-30 * 60)));
+        assert_eq!(parse_time("sunrise + 1h30m"), Ok(Time::Sunrise(5400)));
+        for invalid in [
+            "7:00",
+            "24:00",
+            "07:60",
+            "07:00:00:00",
+            "noon",
+            "sunset+",
+            "sunset+1d",
+        ] {
+            assert!(parse_time(invalid).is_err(), "{invalid}");
+        }
+        assert!(schedule(&[("sunset", "night.png")]).is_err());
+        assert!(schedule(&[("location", "91,0")]).is_err());
+        assert!(schedule(&[("fps", "30")]).is_err());
+        assert_eq!(schedule(&[("outputs", "DP-1")]), Ok(None));
+    }
+
+    #[test]
+    fn the_latest_entry_to_start_should_show_while_ranges_cover_the_others() {
+        let day_and_night = schedule(&[
+            ("07:00", "day.png"),
+            ("19:00", "evening.png"),
+            ("12:00..13:00", "lunch.png"),
+        ])
+        .unwrap()
+        .unwrap();
+        let hour = 3600;
+        let day = 100 * DAY;
+        // the evening started the day before
+        assert_eq!(
+            day_and_night.active(day + 3 * hour, 0),
+            Some((1, day - DAY))
+        );
+        assert_eq!(day_and_night.active(day + 7 * hour, 0), Some((0, day)));
+        assert_eq!(day_and_night.active(day + 12 * hour + 1, 0), Some((2, day)));
+        assert_eq!(day_and_night.active(day + 13 * hour, 0), Some((0, day)));
+        assert_eq!(day_and_night.active(day + 20 * hour, 0), Some((1, day)));
+
+        let night = schedule(&[("22:00..06:00", "night.png")]).unwrap().unwrap();
+        assert_eq!(night.active(day + 2 * hour, 0), Some((0, day - DAY)));
+        assert_eq!(night.active(day + 12 * hour, 0), None);
+    }
+
+    #[test]
+    fn sunrise_and_sunset_should_be_where_the_sun_is() {
+        // Berlin, on the 21st of June 2024, at 04:43 and 21:33 CEST, so 02:43 and 19:33 UTC
+        let midnight = 1_718_928_000;
+        let (sunrise, sunset) = sun_events(midnight + DAY / 2, 52.52, 13.405).unwrap();
+        assert!((sunrise - (midnight + 2 * 3600 + 43 * 60)).abs() < 120);
+        assert!((sunset - (midnight + 19 * 3600 + 33 * 60)).abs() < 120);
+        // the sun doesn't set in Svalbard in June
+        assert_eq!(sun_events(midnight + DAY / 2, 78.2, 15.6), None);
+
+        let sun = schedule(&[
+            ("location", "52.52,13.405"),
+            ("sunrise", "day.png"),
+            ("sunset+1h", "night.png"),
+        ])
+        .unwrap()
+        .unwrap();
+        // CEST is 2 hours ahead of UTC
+        let (local, offset) = (midnight, 2 * 3600);
+        assert_eq!(sun.active(local + 5 * 3600, offset), Some((0, local)));
+        assert_eq!(sun.active(local + 22 * 3600, offset), Some((0, local)));
+        assert_eq!(sun.active(local + 23 * 3600, offset), Some((1, local)));
+    }
+}
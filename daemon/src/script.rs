@@ -0,0 +1,175 @@
+//! Lets an external script decide what to display.
+//!
+//! We spawn the script at startup and send it events on its stdin, one JSON object per line, until
+//! the configuration file is reloaded with another script, or none, which closes its stdin:
+//!
+//! * `{"event":"ready"}`, once, when the daemon starts;
+//! * `{"event":"timer","elapsed_ms":<ms since the daemon started>}`, every second.
+//!
+//! Every line the script writes to its stdout is a command, in the same format as the files
+//! `swww img --batch-file` takes, like `[{"output":"DP-1","path":"/some/img.png"}]`. We run them
+//! through `swww img`, since the daemon cannot decode images on its own.
+
+use log::{debug, error, warn};
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::Generation;
+
+const TIMER_INTERVAL: Duration = Duration::from_secs(1);
+
+static THREADS: Generation = Generation::new();
+
+enum Event {
+    Ready,
+    Timer { elapsed_ms: u128 },
+}
+
+impl Event {
+    fn to_json(&self) -> String {
+        match self {
+            Self::Ready => "{\"event\":\"ready\"}\n".to_string(),
+            Self::Timer { elapsed_ms } => {
+                format!("{{\"event\":\"timer\",\"elapsed_ms\":{elapsed_ms}}}\n")
+            }
+        }
+    }
+}
+
+/// Spawns the script at `path`, instead of the one we ran before, if any
+pub fn spawn(path: PathBuf) {
+    if let Err(e) = start(&path, THREADS.next(), run_command) {
+        error!("failed to start script {path:?}: {e}");
+    }
+}
+
+/// Stops sending events to the script we ran
+pub fn stop() {
+    THREADS.next();
+}
+
+/// Spawns the script, sending it events for as long as `generation` lasts, and calling
+/// `on_command` with every line it outputs
+fn start<F>(path: &Path, generation: u64, mut on_command: F) -> Result<(), String>
+where
+    F: FnMut(&str) + Send + 'static,
+{
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to spawn: {e}"))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+
+    thread::Builder::new()
+        .name("script events".to_string())
+        .stack_size(1 << 15)
+        .spawn(move || {
+            let start = Instant::now();
+            let mut event = Event::Ready;
+            while THREADS.is_current(generation) {
+                if let Err(e) = stdin.write_all(event.to_json().as_bytes()) {
+                    // this happens when the script exits, which it is free to do
+                    debug!("stopped sending events to script: {e}");
+                    break;
+                }
+                thread::sleep(TIMER_INTERVAL);
+                event = Event::Timer {
+                    elapsed_ms: start.elapsed().as_millis(),
+                };
+            }
+        })
+        .map_err(|e| format!("failed to spawn 'script events' thread: {e}"))?;
+
+    thread::Builder::new()
+        .name("script commands".to_string())
+        .stack_size(1 << 15)
+        .spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) if line.trim().is_empty() => (),
+                    Ok(line) => on_command(&line),
+                    Err(e) => {
+                        warn!("failed to read command from script: {e}");
+                        break;
+                    }
+                }
+            }
+            if let Err(e) = child.wait() {
+                warn!("failed to wait for script: {e}");
+            }
+        })
+        .map_err(|e| format!("failed to spawn 'script commands' thread: {e}"))?;
+    Ok(())
+}
+
+fn run_command(command: &str) {
+    debug!("script command: {command}");
+    let child = crate::auth::swww_command()
+        .args(["img", "--batch-file", "/dev/stdin"])
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("failed to spawn swww to run script command: {e}");
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(e) = stdin.write_all(command.as_bytes()) {
+            warn!("failed to send script command to swww: {e}");
+        }
+    }
+    match child.wait() {
+        Ok(status) if status.success() => (),
+        Ok(status) => warn!("script command {command} failed: {status}"),
+        Err(e) => warn!("failed to wait for swww to run script command: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::PermissionsExt, sync::mpsc};
+
+    #[test]
+    fn should_format_events_as_json_lines() {
+        assert_eq!(Event::Ready.to_json(), "{\"event\":\"ready\"}\n");
+        assert_eq!(
+            Event::Timer { elapsed_ms: 1500 }.to_json(),
+            "{\"event\":\"timer\",\"elapsed_ms\":1500}\n"
+        );
+    }
+
+    #[test]
+    fn should_send_events_and_read_commands() {
+        let path = std::env::temp_dir().join(format!("swww-script-{}.sh", std::process::id()));
+        // echoes the first two events back, as if they were commands
+        std::fs::write(&path, "#!/bin/sh\nhead -n 2\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        start(&path, THREADS.next(), move |command| {
+            tx.send(command.to_string()).unwrap()
+        })
+        .unwrap();
+        let timeout = Duration::from_secs(5);
+        let ready = rx.recv_timeout(timeout).unwrap();
+        let timer = rx.recv_timeout(timeout).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(ready, "{\"event\":\"ready\"}");
+        assert!(
+            timer.starts_with("{\"event\":\"timer\",\"elapsed_ms\":"),
+            "{timer}"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Stops animating while the session is locked, since nobody sees the wallpapers behind the locker.
+//!
+//! Only the locker itself hears about `ext_session_lock_v1`, so we learn that the session is locked
+//! from elsewhere:
+//! * with the `dbus` feature, from the `LockedHint` of our systemd-logind session, which lockers
+//!   and idle daemons set (see `dbus::watch_session_lock`);
+//! * with `--lock-signals`, from the Unix signals the user picked, which a script can send us
+//!   around the locker, like `pkill -USR1 swww-daemon; swaylock; pkill -USR2 swww-daemon`.
+//!
+//! The session is locked if either says so. Until it is unlocked, every wallpaper is hidden (see
+//! [`crate::wallpaper::Wallpaper::is_hidden`]), like when its output is off: animations stay on
+//! their frame, without unpacking the next ones, and carry on from there once it is unlocked.
+//!
+//! We don't draw the wallpapers on the lock screen either. Its surfaces are the locker's: only the
+//! client holding the `ext_session_lock_v1` may make them, and only one client may hold it, so
+//! drawing there would mean locking the session ourselves, and authenticating whoever unlocks it.
+
+use log::info;
+
+use nix::sys::signal::{self, SigHandler, Signal};
+
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+
+/// What logind says
+static HINTED: AtomicBool = AtomicBool::new(false);
+/// What the last of the `--lock-signals` we got says
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+/// The signal that locks the session, and the one that unlocks it, or 0 without `--lock-signals`
+static SIGNALS: [AtomicI32; 2] = [AtomicI32::new(0), AtomicI32::new(0)];
+/// Whether the session was locked when we last logged about it
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_locked() -> bool {
+    HINTED.load(Ordering::Acquire) || SIGNALED.load(Ordering::Acquire)
+}
+
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub fn set_hint(locked: bool) {
+    HINTED.store(locked, Ordering::Release);
+    crate::wake_poll();
+}
+
+/// Takes the session as locked when we get `lock`, and as unlocked when we get `unlock`. With
+/// `None`, or other signals than before, the signals we listened to before get their default
+/// behaviour back, and the session is no longer locked by them
+pub fn listen_to_signals(signals: Option<(Signal, Signal)>) -> Result<(), String> {
+    let [lock, unlock] = signals.map_or([0; 2], |(lock, unlock)| [lock as i32, unlock as i32]);
+    for old in &SIGNALS {
+        let old = old.swap(0, Ordering::AcqRel);
+        if let Ok(old) = Signal::try_from(old) {
+            unsafe { signal::signal(old, SigHandler::SigDfl) }
+                .map_err(|e| format!("failed to restore the handler of {old}: {e}"))?;
+        }
+    }
+    SIGNALED.store(false, Ordering::Release);
+    SIGNALS[0].store(lock, Ordering::Release);
+    SIGNALS[1].store(unlock, Ordering::Release);
+    let handler = SigHandler::Handler(signal_handler);
+    for signal in signals
+        .into_iter()
+        .flat_map(|(lock, unlock)| [lock, unlock])
+    {
+        unsafe { signal::signal(signal, handler) }
+            .map_err(|e| format!("failed to install a handler for {signal}: {e}"))?;
+    }
+    Ok(())
+}
+
+extern "C" fn signal_handler(signal: i32) {
+    // nothing here may allocate or lock, and the main loop wakes up for every signal anyway
+    if signal == SIGNALS[0].load(Ordering::Acquire) {
+        SIGNALED.store(true, Ordering::Release);
+    } else if signal == SIGNALS[1].load(Ordering::Acquire) {
+        SIGNALED.store(false, Ordering::Release);
+    }
+}
+
+/// Logs whether the session got locked or unlocked since we were last called
+pub fn report() {
+    let locked = is_locked();
+    if REPORTED.swap(locked, Ordering::AcqRel) != locked {
+        if locked {
+            info!("the session is locked, animations pause until it is unlocked");
+        } else {
+            info!("the session is unlocked, animations carry on");
+        }
+    }
+}
+
+/// Parses `--lock-signals`: two signals, like `USR1,USR2`, with or without their `SIG`. They can't
+/// be those that make us exit, nor those we can't catch
+pub fn parse_signals(s: &str) -> Result<(Signal, Signal), String> {
+    let signal = |name: &str| {
+        let name = name.trim().to_uppercase();
+        let name = if name.starts_with("SIG") {
+            name
+        } else {
+            format!("SIG{name}")
+        };
+        match Signal::from_str(&name) {
+            Ok(
+                Signal::SIGINT
+                | Signal::SIGQUIT
+                | Signal::SIGTERM
+                | Signal::SIGKILL
+                | Signal::SIGSTOP,
+            ) => Err(format!("{name} can't be a lock signal")),
+            Ok(signal) => Ok(signal),
+            Err(_) => Err(format!("unknown signal {name}")),
+        }
+    };
+    let (lock, unlock) = s
+        .split_once(',')
+        .ok_or("expected two signals, like 'USR1,USR2'")?;
+    let (lock, unlock) = (signal(lock)?, signal(unlock)?);
+    if lock == unlock {
+        return Err("the lock and unlock signals must be different".to_string());
+    }
+    Ok((lock, unlock))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_signals_should_be_two_different_catchable_signals() {
+        assert_eq!(
+            parse_signals("USR1, sigusr2"),
+            Ok((Signal::SIGUSR1, Signal::SIGUSR2))
+        );
+        assert_eq!(
+            parse_signals("SIGRTMIN,HUP").unwrap_err(),
+            "unknown signal SIGRTMIN"
+        );
+        assert!(parse_signals("USR1").is_err());
+        assert!(parse_signals("USR1,USR1").is_err());
+        assert!(parse_signals("USR1,TERM").is_err());
+        assert!(parse_signals("KILL,USR2").is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Renders GLSL fragment shaders on outputs, every frame, see `swww shader`.
+//!
+//! Shaders are written like those of shadertoy: a `mainImage` function, which we call from a
+//! `main` of our own, with the uniforms we declare before it. We render them off-screen, with
+//! [`gl::Context`], at the size of every output's buffer, and copy the pixels to its canvas, so
+//! everything else we draw over images (tints, overlays, mirrors) works the same over shaders.
+//!
+//! Shadertoy's `fragCoord` is where the pixel is on the output, with y growing upwards, whereas
+//! the framebuffer is laid out like the buffer, which the output's transform may have rotated or
+//! flipped. We thus hand the shader a map from one to the other, see [`frag_coord_map`]. Like
+//! animations, a shader stops once its outputs display anything else, pauses with `swww pause`,
+//! and isn't rendered at all while nobody can see it.
+
+use std::{
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error};
+
+use utils::ipc::{ArchivedShader, BgImg, Error, ErrorKind, Transform};
+
+use crate::{
+    battery::{self, BatteryPolicy},
+    gl::{self, Uniform},
+    wallpaper::{AnimationToken, Wallpaper},
+};
+
+/// Shader compilers recurse deeply, far more than the rest of our threads
+const STACK_SIZE: usize = 1 << 23;
+/// How often a paused shader checks whether it was resumed
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Compiles `shader` and starts rendering it on `wallpapers`, on a thread of its own. We only
+/// answer once it compiled, so that its errors get to the client
+pub fn start(shader: &ArchivedShader, wallpapers: Vec<Arc<Wallpaper>>) -> Result<(), Error> {
+    if shader.fps == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidArgument,
+            "shaders must render at least 1 frame per second",
+        ));
+    }
+    let source = wrap(&shader.source);
+    let img = BgImg::Shader {
+        path: shader.path.to_string(),
+        fps: shader.fps,
+    };
+    let interval = Duration::from_secs(1) / shader.fps as u32;
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::Builder::new()
+        .name("shader".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let compiled = gl::Context::new()
+                .map_err(|e| Error::new(ErrorKind::Other, e))
+                .and_then(|mut context| {
+                    let program = context
+                        .program(gl::FULLSCREEN_VERTEX_SHADER, &source)
+                        .map_err(|e| Error::new(ErrorKind::InvalidArgument, e))?;
+                    Ok((context, program))
+                });
+            let (mut context, program) = match compiled {
+                Ok(compiled) => compiled,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            };
+            for wallpaper in &wallpapers {
+                wallpaper.cancel_animations();
+                wallpaper.inc_animation_id();
+            }
+            let tokens: Vec<_> = wallpapers
+                .iter()
+                .map(|w| w.create_animation_token())
+                .collect();
+            for wallpaper in &wallpapers {
+                wallpaper.set_img_info(img.clone());
+            }
+            let _ = sender.send(Ok(()));
+            debug!("rendering {img}");
+            render(&mut context, &program, &wallpapers, &tokens, interval);
+        })
+        .map_err(|e| format!("failed to spawn `shader` thread: {e}"))?;
+    receiver
+        .recv()
+        .map_err(|_| Error::from("the `shader` thread died before compiling".to_string()))?
+}
+
+/// Renders `program` on every wallpaper we still hold the token of, every `interval`, until there
+/// are none
+fn render(
+    context: &mut gl::Context,
+    program: &gl::Program,
+    wallpapers: &[Arc<Wallpaper>],
+    tokens: &[AnimationToken],
+    interval: Duration,
+) {
+    let mut pixels = Vec::new();
+    // the time the shader played for, which stands still while it is paused
+    let mut time = Duration::ZERO;
+    let mut last = Instant::now();
+    let mut frame = 0usize;
+    loop {
+        let ours = || {
+            wallpapers
+                .iter()
+                .zip(tokens)
+                .filter(|(w, token)| w.has_animation_id(token))
+        };
+        if ours().next().is_none() || crate::should_daemon_exit() {
+            return;
+        }
+        if ours().any(|(w, _)| w.is_paused())
+            || ours().all(|(w, _)| w.is_unseen())
+            || matches!(
+                battery::low_power_policy(),
+                Some(BatteryPolicy::Pause | BatteryPolicy::FirstFrame)
+            )
+        {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+            last = Instant::now();
+            continue;
+        }
+        let now = Instant::now();
+        let delta = now - last;
+        time += delta;
+        last = now;
+        let interval = match battery::low_power_policy() {
+            Some(BatteryPolicy::ReduceFps(fps)) => interval.max(Duration::from_secs(1) / fps),
+            _ => interval,
+        };
+
+        let deadline = now + interval;
+        for (wallpaper, token) in ours() {
+            if wallpaper.is_unseen() {
+                wallpaper.mark_undrawn();
+                continue;
+            }
+            let dim = wallpaper.get_dimensions();
+            let transform = wallpaper.transform();
+            let output_dim = transform.buffer_dim((dim.0 as f32, dim.1 as f32));
+            let [x, y] = frag_coord_map(transform, output_dim);
+            context.set_uniform(program, "swww_FragCoordX", Uniform::Vec3(x));
+            context.set_uniform(program, "swww_FragCoordY", Uniform::Vec3(y));
+            let resolution = [output_dim.0, output_dim.1, 1.0];
+            context.set_uniform(program, "iResolution", Uniform::Vec3(resolution));
+            context.set_uniform(program, "iTime", Uniform::Float(time.as_secs_f32()));
+            context.set_uniform(program, "iTimeDelta", Uniform::Float(delta.as_secs_f32()));
+            context.set_uniform(program, "iFrame", Uniform::Int(frame as i32));
+            let mouse = match wallpaper.cursor_position() {
+                // it is where the pointer is on the buffer, but with y growing upwards
+                Some((u, v)) => {
+                    let v = dim.1 as f32 - v;
+                    let dot = |[a, b, c]: [f32; 3]| a * u + b * v + c;
+                    [dot(x), dot(y), 0.0, 0.0]
+                }
+                None => [0.0; 4],
+            };
+            context.set_uniform(program, "iMouse", Uniform::Vec4(mouse));
+            if let Err(e) = context.render(program, dim, &mut pixels) {
+                error!("stopping shader on {}: {e}", wallpaper.name());
+                return;
+            }
+
+            // our size may have changed since we rendered, and maybe someone else draws now
+            let (copied, buffer) = wallpaper.canvas_change(|canvas| {
+                if canvas.len() != pixels.len() || !wallpaper.has_animation_id(token) {
+                    return false;
+                }
+                for (pixel, rgba) in canvas.chunks_exact_mut(4).zip(pixels.chunks_exact(4)) {
+                    pixel[0] = rgba[2];
+                    pixel[1] = rgba[1];
+                    pixel[2] = rgba[0];
+                }
+                true
+            });
+            if !copied {
+                continue;
+            }
+            if !wallpaper.is_hidden()
+                && !wallpaper.over_max_fps(interval)
+                && wallpaper.wait_frame(deadline)
+            {
+                wallpaper.draw(&buffer);
+            } else {
+                wallpaper.mark_undrawn();
+            }
+            wallpaper.set_animation_frame(frame);
+        }
+        crate::wake_poll();
+        frame = frame.wrapping_add(1);
+
+        let elapsed = now.elapsed();
+        if elapsed > interval {
+            crate::metrics::frame_dropped();
+        }
+        spin_sleep::sleep(interval.saturating_sub(elapsed));
+    }
+}
+
+/// `source`, with the uniforms shadertoy shaders expect declared before it, and a `main` that
+/// calls its `mainImage` after it. Errors in it are still on the lines the user wrote them on
+fn wrap(source: &str) -> String {
+    format!(
+        "#version 300 es
+precision highp float;
+precision highp int;
+uniform vec3 iResolution;
+uniform float iTime;
+uniform float iTimeDelta;
+uniform int iFrame;
+uniform vec4 iMouse;
+uniform vec3 swww_FragCoordX;
+uniform vec3 swww_FragCoordY;
+out vec4 swww_FragColor;
+#line 1
+{source}
+void main() {{
+    vec3 pixel = vec3(gl_FragCoord.xy, 1.0);
+    vec4 color = vec4(0.0, 0.0, 0.0, 1.0);
+    mainImage(color, vec2(dot(swww_FragCoordX, pixel), dot(swww_FragCoordY, pixel)));
+    swww_FragColor = color;
+}}
+"
+    )
+}
+
+/// The rows of the affine map from where a pixel is in the framebuffer (the first row we read back
+/// being the top of the buffer) to shadertoy's `fragCoord`, on an output `dim` in size, drawn with
+/// `transform`. It undoes [`Transform::to_buffer`], and then flips y
+fn frag_coord_map(transform: Transform, (width, height): (f32, f32)) -> [[f32; 3]; 2] {
+    let (x, y) = match transform {
+        Transform::Normal => ([1.0, 0.0, 0.0], [0.0, -1.0, height]),
+        Transform::Rotated90 => ([0.0, -1.0, width], [-1.0, 0.0, height]),
+        Transform::Rotated180 => ([-1.0, 0.0, width], [0.0, 1.0, 0.0]),
+        Transform::Rotated270 => ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+        Transform::Flipped => ([-1.0, 0.0, width], [0.0, -1.0, height]),
+        Transform::Flipped90 => ([0.0, 1.0, 0.0], [-1.0, 0.0, height]),
+        Transform::Flipped180 => ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        Transform::Flipped270 => ([0.0, -1.0, width], [1.0, 0.0, 0.0]),
+    };
+    [x, y]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frag_coords_should_be_where_pixels_are_on_the_output() {
+        let dim = (4.0, 3.0);
+        for transform in [
+            Transform::Normal,
+            Transform::Rotated90,
+            Transform::Rotated180,
+            Transform::Rotated270,
+            Transform::Flipped,
+            Transform::Flipped90,
+            Transform::Flipped180,
+            Transform::Flipped270,
+        ] {
+            let [x, y] = frag_coord_map(transform, dim);
+            for point in [(0.0, 0.0), (1.0, 2.0), (4.0, 0.5)] {
+                let (u, v) = transform.to_buffer(point, dim);
+                let dot = |[a, b, c]: [f32; 3]| a * u + b * v + c;
+                assert_eq!((dot(x), dot(y)), (point.0, dim.1 - point.1), "{transform}");
+            }
+        }
+    }
+
+    #[test]
+    fn errors_should_be_on_the_lines_of_the_source() {
+        let Ok(mut context) = gl::Context::new() else {
+            eprintln!("no OpenGL ES here, skipping");
+            return;
+        };
+        let source = "void mainImage(out vec4 fragColor, in vec2 fragCoord) {\n\
+                      fragColor = vec4(1.0);\n\
+                      undeclared = 1.0;\n\
+                      }\n";
+        let e = context
+            .program(gl::FULLSCREEN_VERTEX_SHADER, &wrap(source))
+            .err()
+            .unwrap();
+        assert!(e.contains(":3"), "{e}");
+
+        let source = "void mainImage(out vec4 fragColor, in vec2 fragCoord) {\n\
+                      fragColor = vec4(fragCoord / iResolution.xy, 0.0, 1.0);\n\
+                      }\n";
+        let program = context
+            .program(gl::FULLSCREEN_VERTEX_SHADER, &wrap(source))
+            .unwrap();
+        let [x, y] = frag_coord_map(Transform::Normal, (2.0, 2.0));
+        context.set_uniform(&program, "swww_FragCoordX", Uniform::Vec3(x));
+        context.set_uniform(&program, "swww_FragCoordY", Uniform::Vec3(y));
+        context.set_uniform(&program, "iResolution", Uniform::Vec3([2.0, 2.0, 1.0]));
+        let mut pixels = Vec::new();
+        context.render(&program, (2, 2), &mut pixels).unwrap();
+        // the first row is the top one, where `fragCoord.y` is the highest
+        assert_eq!(&pixels[..4], &[64, 191, 0, 255]);
+        assert_eq!(&pixels[12..], &[191, 64, 0, 255]);
+    }
+}
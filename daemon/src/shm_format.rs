@@ -0,0 +1,269 @@
+//! The formats we can send opaque buffers to the compositor in.
+//!
+//! Everything we draw, from the client's images to the animation frames and transitions, is
+//! `xrgb8888`, and that is what we send by default. Some compositors may prefer `xrgb2101010`
+//! buffers, and low memory devices `rgb565`. For those, we draw to a canvas of our own, in
+//! `xrgb8888`, and convert it to the buffer's format every time we draw. `xrgb2101010` thus only
+//! ever holds 8 bits of every channel, widened to 10: it shows nothing `xrgb8888` doesn't. Buffers with an alpha
+//! channel are always `argb8888`.
+//!
+//! Converting is the last thing we do to what we draw, so that is where we dither it, see
+//! `swww img --dither`: nothing after it undoes the dither, and, since animations are diffed before
+//! it, a dithered animation is no bigger than any other. Only `rgb565` has fewer bits per channel
+//! than what we draw, so it is the only format we dither to.
+
+use log::warn;
+use smithay_client_toolkit::shm::Shm;
+use utils::ipc::Dither;
+use wayland_client::protocol::wl_shm;
+
+/// The thresholds of the ordered dither, from 0 to 63: neighbors are as far apart as they can be
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ShmFormat {
+    #[default]
+    Xrgb8888,
+    /// 10 bits per channel, of which we only fill 8
+    Xrgb2101010,
+    /// 5 bits for red and blue, and 6 for green
+    Rgb565,
+}
+
+impl ShmFormat {
+    /// The format the user asked for, if the compositor supports it, and `xrgb8888` otherwise
+    pub fn negotiate(self, shm: &Shm) -> Self {
+        if self == Self::Xrgb8888 || shm.formats().contains(&self.wl_format()) {
+            self
+        } else {
+            warn!("the compositor doesn't support {self:?} buffers, falling back to Xrgb8888");
+            Self::Xrgb8888
+        }
+    }
+
+    pub fn wl_format(self) -> wl_shm::Format {
+        match self {
+            Self::Xrgb8888 => wl_shm::Format::Xrgb8888,
+            Self::Xrgb2101010 => wl_shm::Format::Xrgb2101010,
+            Self::Rgb565 => wl_shm::Format::Rgb565,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Xrgb8888 | Self::Xrgb2101010 => 4,
+            Self::Rgb565 => 2,
+        }
+    }
+
+    /// Whether we draw straight to the buffers, with no canvas of our own in between
+    pub fn is_native(self) -> bool {
+        self == Self::Xrgb8888
+    }
+
+    /// Converts `src`, `xrgb8888` pixels (`[b, g, r, x]` in memory) `width` wide, to this format,
+    /// into `dst`. Channels that get fewer bits are dithered with `dither`, or else truncated
+    pub fn convert(self, src: &[u8], dst: &mut [u8], width: usize, dither: Option<Dither>) {
+        match self {
+            Self::Xrgb8888 => dst.copy_from_slice(src),
+            Self::Xrgb2101010 => {
+                for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+                    // repeating the high bits in the low ones maps 0xff to 0x3ff, so white stays
+                    // white
+                    let widen = |c: u8| (c as u32) << 2 | (c as u32) >> 6;
+                    let pixel = widen(src[2]) << 20 | widen(src[1]) << 10 | widen(src[0]);
+                    dst.copy_from_slice(&pixel.to_le_bytes());
+                }
+            }
+            Self::Rgb565 => {
+                let pixel = |[b, g, r]: [u16; 3]| (r << 11 | g << 5 | b).to_le_bytes();
+                match dither {
+                    Some(dither) => quantize(src, width, [31, 63, 31], dither, |i, levels| {
+                        dst[i * 2..i * 2 + 2].copy_from_slice(&pixel(levels))
+                    }),
+                    None => {
+                        for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(2)) {
+                            let levels =
+                                [src[0] as u16 >> 3, src[1] as u16 >> 2, src[2] as u16 >> 3];
+                            dst.copy_from_slice(&pixel(levels));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Quantizes `src`, `xrgb8888` pixels `width` wide, to `max` + 1 levels of blue, green and red,
+/// dithering them with `dither`. `put` gets the levels of every pixel, along with where it is
+fn quantize(
+    src: &[u8],
+    width: usize,
+    max: [u16; 3],
+    dither: Dither,
+    mut put: impl FnMut(usize, [u16; 3]),
+) {
+    if width == 0 {
+        return;
+    }
+    let scale = max.map(|max| max as f32 / 255.0);
+    match dither {
+        Dither::Bayer => {
+            for (i, pixel) in src.chunks_exact(4).enumerate() {
+                let threshold = (BAYER[i / width % 8][i % width % 8] as f32 + 0.5) / 64.0;
+                put(
+                    i,
+                    [0, 1, 2].map(|c| {
+                        (pixel[c] as f32 * scale[c] + threshold)
+                            .floor()
+                            .min(max[c] as f32) as u16
+                    }),
+                );
+            }
+        }
+        Dither::FloydSteinberg => {
+            // the errors carried over to the current row, and to the next one
+            let mut errors = vec![0f32; width * 3];
+            let mut next = vec![0f32; width * 3];
+            for (y, row) in src.chunks_exact(width * 4).enumerate() {
+                for x in 0..width {
+                    let mut levels = [0; 3];
+                    for (c, level) in levels.iter_mut().enumerate() {
+                        let i = x * 3 + c;
+                        let value = row[x * 4 + c] as f32 + errors[i];
+                        let quantized = (value * scale[c]).round().clamp(0.0, max[c] as f32);
+                        *level = quantized as u16;
+                        let error = value - quantized / scale[c];
+                        if x + 1 < width {
+                            errors[i + 3] += error * 7.0 / 16.0;
+                            next[i + 3] += error / 16.0;
+                        }
+                        if x > 0 {
+                            next[i - 3] += error * 3.0 / 16.0;
+                        }
+                        next[i] += error * 5.0 / 16.0;
+                    }
+                    put(y * width + x, levels);
+                }
+                std::mem::swap(&mut errors, &mut next);
+                next.fill(0.0);
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ShmFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xrgb8888" => Ok(Self::Xrgb8888),
+            "xrgb2101010" => Ok(Self::Xrgb2101010),
+            "rgb565" => Ok(Self::Rgb565),
+            other => Err(format!(
+                "must be 'xrgb8888', 'xrgb2101010' or 'rgb565', got: {other}"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(format: ShmFormat, src: &[u8]) -> Vec<u8> {
+        dithered(format, src, src.len() / 4, None)
+    }
+
+    fn dithered(format: ShmFormat, src: &[u8], width: usize, dither: Option<Dither>) -> Vec<u8> {
+        let mut dst = vec![0; src.len() / 4 * format.bytes_per_pixel()];
+        format.convert(src, &mut dst, width, dither);
+        dst
+    }
+
+    /// From black, on the left, to white, on the right
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let c = (i % width * 255 / (width - 1)) as u8;
+                [c, c, c, 0xff]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_convert_to_xrgb2101010() {
+        let white = convert(ShmFormat::Xrgb2101010, &[0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(u32::from_le_bytes(white.try_into().unwrap()), 0x3fff_ffff);
+        let pixel = convert(ShmFormat::Xrgb2101010, &[0x00, 0x80, 0x40, 0xff]);
+        assert_eq!(
+            u32::from_le_bytes(pixel.try_into().unwrap()),
+            0x101 << 20 | 0x202 << 10
+        );
+    }
+
+    #[test]
+    fn should_convert_to_rgb565() {
+        let pixels = convert(
+            ShmFormat::Rgb565,
+            &[0xff, 0xff, 0xff, 0, 0xff, 0x00, 0x00, 0],
+        );
+        assert_eq!(u16::from_le_bytes([pixels[0], pixels[1]]), 0xffff);
+        // blue is the low bits
+        assert_eq!(u16::from_le_bytes([pixels[2], pixels[3]]), 0x001f);
+    }
+
+    #[test]
+    fn dithering_should_keep_the_average_color() {
+        let (width, height) = (64, 16);
+        let src = gradient(width, height);
+        for dither in [Dither::Bayer, Dither::FloydSteinberg] {
+            let pixels = dithered(ShmFormat::Rgb565, &src, width, Some(dither));
+            let blues: Vec<f32> = pixels
+                .chunks_exact(2)
+                .map(|pixel| {
+                    (u16::from_le_bytes([pixel[0], pixel[1]]) & 0x1f) as f32 * 255.0 / 31.0
+                })
+                .collect();
+            // every column is a single shade, which dithering must approximate on average
+            for x in 0..width {
+                let average =
+                    (0..height).map(|y| blues[y * width + x]).sum::<f32>() / height as f32;
+                let expected = src[x * 4] as f32;
+                assert!(
+                    (average - expected).abs() < 4.0,
+                    "{dither:?}: column {x} averages {average}, instead of {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dithering_should_leave_formats_with_8_bits_or_more_alone() {
+        let src = gradient(64, 8);
+        for format in [ShmFormat::Xrgb8888, ShmFormat::Xrgb2101010] {
+            for dither in [Dither::Bayer, Dither::FloydSteinberg] {
+                assert_eq!(
+                    dithered(format, &src, 64, Some(dither)),
+                    convert(format, &src)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn should_parse_formats() {
+        assert_eq!("rgb565".parse(), Ok(ShmFormat::Rgb565));
+        assert_eq!("xrgb2101010".parse(), Ok(ShmFormat::Xrgb2101010));
+        assert!("argb8888".parse::<ShmFormat>().is_err());
+    }
+}
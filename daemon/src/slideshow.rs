@@ -0,0 +1,340 @@
+//! Shows the images of a directory one after another, see `swww slideshow`.
+//!
+//! Every slideshow runs on its own thread, and, like the hotplug code, spawns `swww img` to show
+//! every image, since the daemon can't resize them itself, with the options the slideshow was
+//! started with, see `ipc::Remake`. The interval only counts the time at
+//! least one of the slideshow's outputs is visible, so a slideshow on outputs that are powered off
+//! or covered stays on the same image until they come back.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, error, warn};
+use rkyv::Deserialize;
+use utils::{
+    ipc::{ArchivedSlideshow, Remake},
+    outputs::OutputPattern,
+};
+
+use crate::wallpaper::Wallpaper;
+
+/// How often we check whether the outputs are visible, and whether we were stopped
+const TICK: Duration = Duration::from_secs(1);
+
+/// What a slideshow shares with its thread
+struct Shared {
+    /// The outputs it is on, or all of them, if empty
+    names: Vec<String>,
+    /// `names`, parsed
+    patterns: Vec<OutputPattern>,
+    /// Their wallpapers, to know whether any of them is visible
+    wallpapers: Mutex<Vec<Weak<Wallpaper>>>,
+    stopped: AtomicBool,
+}
+
+impl Shared {
+    fn covers(&self, name: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(name))
+    }
+
+    fn overlaps(&self, names: &[String]) -> bool {
+        self.names.is_empty() || names.is_empty() || names.iter().any(|n| self.covers(n))
+    }
+
+    fn is_visible(&self) -> bool {
+        let mut wallpapers = self.wallpapers.lock().unwrap();
+        wallpapers.retain(|w| w.strong_count() > 0);
+        wallpapers
+            .iter()
+            .filter_map(Weak::upgrade)
+            .any(|w| !w.is_unseen())
+    }
+}
+
+#[derive(Default)]
+pub struct Slideshows(Vec<Arc<Shared>>);
+
+impl Slideshows {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts `slideshow` on `wallpapers`, the ones it asked for, replacing the slideshows that
+    /// were on any of them
+    pub fn start(
+        &mut self,
+        slideshow: &ArchivedSlideshow,
+        wallpapers: &[Arc<Wallpaper>],
+    ) -> Result<(), String> {
+        let names: Vec<_> = slideshow.outputs.iter().map(|n| n.to_string()).collect();
+        let patterns = OutputPattern::parse_all(&names)?;
+        self.stop(&names);
+        let shared = Arc::new(Shared {
+            names,
+            patterns,
+            wallpapers: Mutex::new(wallpapers.iter().map(Arc::downgrade).collect()),
+            stopped: AtomicBool::new(false),
+        });
+        let dir = PathBuf::from(slideshow.dir.as_str());
+        let interval = Duration::from_secs(slideshow.interval_secs as u64);
+        let shuffle = slideshow.shuffle;
+        let transition_type = slideshow.transition_type.as_ref().map(|t| t.to_string());
+        let remake: Remake = slideshow.remake.deserialize(&mut rkyv::Infallible).unwrap();
+        let thread_shared = Arc::clone(&shared);
+        std::thread::Builder::new()
+            .name("slideshow".to_string())
+            .stack_size(1 << 15)
+            .spawn(move || {
+                run(
+                    &thread_shared,
+                    &dir,
+                    interval,
+                    shuffle,
+                    transition_type,
+                    &remake,
+                )
+            })
+            .map_err(|e| format!("failed to spawn `slideshow` thread: {e}"))?;
+        self.0.push(shared);
+        Ok(())
+    }
+
+    /// Stops the slideshows on any of these outputs, or all of them, if empty
+    pub fn stop(&mut self, names: &[String]) {
+        self.0.retain(|shared| {
+            let stop = shared.overlaps(names);
+            if stop {
+                shared.stopped.store(true, Ordering::Release);
+            }
+            !stop
+        });
+    }
+
+    /// Makes the slideshows that cover a new output know whether it is visible
+    pub fn output_added(&self, wallpaper: &Arc<Wallpaper>) {
+        for shared in self.0.iter().filter(|s| s.covers(wallpaper.name())) {
+            shared
+                .wallpapers
+                .lock()
+                .unwrap()
+                .push(Arc::downgrade(wallpaper));
+        }
+    }
+}
+
+fn run(
+    shared: &Shared,
+    dir: &Path,
+    interval: Duration,
+    shuffle: bool,
+    transition_type: Option<String>,
+    remake: &Remake,
+) {
+    let mut order = Order::new(shuffle);
+    loop {
+        match order.next(dir) {
+            Some(path) => show(&shared.names, &path, transition_type.as_deref(), remake),
+            None => warn!("slideshow found no images in {dir:?}"),
+        }
+        let mut shown = Duration::ZERO;
+        while shown < interval {
+            std::thread::sleep(TICK);
+            if shared.stopped.load(Ordering::Acquire) || crate::should_daemon_exit() {
+                debug!("stopped slideshow of {dir:?}");
+                return;
+            }
+            if shared.is_visible() {
+                shown += TICK;
+            }
+        }
+    }
+}
+
+/// Spawns the client to show `path` on the outputs called `names`, or on all of them, if empty,
+/// making it like `remake` says
+fn show(names: &[String], path: &Path, transition_type: Option<&str>, remake: &Remake) {
+    let mut command = crate::auth::swww_command();
+    if Path::new(&remake.cwd).is_dir() {
+        command.current_dir(&remake.cwd);
+    }
+    // ours come after the options it was started with, so that they win
+    command.arg("img").args(remake.args.iter());
+    if !names.is_empty() {
+        command.arg(format!("--outputs={}", names.join(",")));
+    }
+    if let Some(transition_type) = transition_type {
+        command.arg(format!("--transition-type={transition_type}"));
+    }
+    command.arg("--").arg(path);
+    match command.status() {
+        Ok(status) if !status.success() => error!("slideshow failed to show {path:?}: {status}"),
+        Ok(_) => (),
+        Err(e) => error!("slideshow failed to spawn child process: {e}"),
+    }
+}
+
+/// The images in `dir`, sorted by name. We read it again for every image, so that the slideshow
+/// picks up images added to it, and skips removed ones
+fn images(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("slideshow failed to read {dir:?}: {e}");
+            return Vec::new();
+        }
+    };
+    let mut images: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    utils::IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+                })
+        })
+        .collect();
+    images.sort();
+    images
+}
+
+/// Which image comes next
+struct Order {
+    /// The images we still have to show in this round, in the order we pop them, when shuffling
+    queue: Option<Vec<PathBuf>>,
+    last: Option<PathBuf>,
+    rng: XorShift,
+}
+
+impl Order {
+    fn new(shuffle: bool) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self {
+            queue: shuffle.then(Vec::new),
+            last: None,
+            rng: XorShift::new(seed),
+        }
+    }
+
+    fn next(&mut self, dir: &Path) -> Option<PathBuf> {
+        let next = match &mut self.queue {
+            None => next_in_order(&images(dir), self.last.as_deref()).cloned(),
+            Some(queue) => {
+                // skip the images removed since we shuffled them
+                queue.retain(|path| path.exists());
+                if queue.is_empty() {
+                    *queue = images(dir);
+                    self.rng.shuffle(queue);
+                    // don't show the same image twice in a row, across rounds
+                    let len = queue.len();
+                    if len > 1 && queue.last() == self.last.as_ref() {
+                        queue.swap(0, len - 1);
+                    }
+                }
+                queue.pop()
+            }
+        };
+        self.last.clone_from(&next);
+        next
+    }
+}
+
+/// The first image after `last`, by name, going back to the first one after the last one
+fn next_in_order<'a>(images: &'a [PathBuf], last: Option<&Path>) -> Option<&'a PathBuf> {
+    match last {
+        Some(last) => images
+            .iter()
+            .find(|path| path.as_path() > last)
+            .or(images.first()),
+        None => images.first(),
+    }
+}
+
+/// Good enough randomness to shuffle images, without depending on a crate for it
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // the state must never be 0
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Fisher-Yates
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn should_go_through_the_images_by_name() {
+        let images = paths(&["/a.png", "/b.png", "/c.png"]);
+        assert_eq!(next_in_order(&images, None), Some(&images[0]));
+        assert_eq!(
+            next_in_order(&images, Some(Path::new("/a.png"))),
+            Some(&images[1])
+        );
+        // the last one was removed, so we go on from where it was
+        assert_eq!(
+            next_in_order(&images, Some(Path::new("/bb.png"))),
+            Some(&images[2])
+        );
+        assert_eq!(
+            next_in_order(&images, Some(Path::new("/c.png"))),
+            Some(&images[0])
+        );
+        assert_eq!(next_in_order(&[], None), None);
+    }
+
+    #[test]
+    fn shuffling_should_keep_every_image() {
+        let mut images = paths(&["/a.png", "/b.png", "/c.png", "/d.png", "/e.png"]);
+        XorShift::new(42).shuffle(&mut images);
+        images.sort();
+        assert_eq!(
+            images,
+            paths(&["/a.png", "/b.png", "/c.png", "/d.png", "/e.png"])
+        );
+    }
+
+    #[test]
+    fn slideshows_on_the_same_outputs_should_overlap() {
+        let shared = |names: &[&str]| Shared {
+            names: names.iter().map(|n| n.to_string()).collect(),
+            patterns: OutputPattern::parse_all(names).unwrap(),
+            wallpapers: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+        };
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(shared(&["DP-1", "DP-2"]).overlaps(&names(&["DP-2"])));
+        assert!(!shared(&["DP-1"]).overlaps(&names(&["DP-2"])));
+        // no names are every output
+        assert!(shared(&[]).overlaps(&names(&["DP-2"])));
+        assert!(shared(&["DP-1"]).overlaps(&[]));
+    }
+}
@@ -0,0 +1,121 @@
+//! Counters about what every output costs us, for `swww stats`.
+//!
+//! They count from when the output appeared, except for the frame rate, which is over the last
+//! second, and the bytes of frames, which are those the animation the output plays keeps in memory
+//! right now.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// What we measure the frame rate over
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct Counters {
+    shown: AtomicU64,
+    skipped: AtomicU64,
+    animations: AtomicU64,
+    /// How many of `animations` the client found in its cache, instead of making their frames
+    cached_animations: AtomicU64,
+    /// When we showed the frames of the last [`FPS_WINDOW`], oldest first
+    recent: Mutex<VecDeque<Instant>>,
+    /// The id of the animation we last played, and how many bytes its frames take
+    frames: Mutex<Option<(usize, Arc<AtomicUsize>)>>,
+}
+
+impl Counters {
+    pub fn frame_shown(&self) {
+        self.shown.fetch_add(1, Ordering::AcqRel);
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent.push_back(now);
+        prune(&mut recent, now);
+    }
+
+    /// An animation frame we unpacked, but didn't show, to catch up with the clock
+    pub fn frame_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// An animation started playing, whose frames take `bytes`, see [`Self::resident_bytes`]
+    pub fn animation_started(&self, animation_id: usize, cached: bool, bytes: Arc<AtomicUsize>) {
+        self.animations.fetch_add(1, Ordering::AcqRel);
+        if cached {
+            self.cached_animations.fetch_add(1, Ordering::AcqRel);
+        }
+        *self.frames.lock().unwrap() = Some((animation_id, bytes));
+    }
+
+    pub fn shown(&self) -> u64 {
+        self.shown.load(Ordering::Acquire)
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Acquire)
+    }
+
+    /// How many animations started, and how many of them came from the client's cache
+    pub fn animations(&self) -> (u64, u64) {
+        (
+            self.animations.load(Ordering::Acquire),
+            self.cached_animations.load(Ordering::Acquire),
+        )
+    }
+
+    /// Frames shown per second, over the last second
+    pub fn fps(&self) -> f32 {
+        let mut recent = self.recent.lock().unwrap();
+        prune(&mut recent, Instant::now());
+        recent.len() as f32 / FPS_WINDOW.as_secs_f32()
+    }
+
+    /// The bytes the frames of the animation with `animation_id` take, if it is the one we last
+    /// played. They are 0 once it stops
+    pub fn resident_bytes(&self, animation_id: usize) -> usize {
+        match &*self.frames.lock().unwrap() {
+            Some((id, bytes)) if *id == animation_id => bytes.load(Ordering::Acquire),
+            _ => 0,
+        }
+    }
+}
+
+fn prune(recent: &mut VecDeque<Instant>, now: Instant) {
+    while recent
+        .front()
+        .is_some_and(|shown| now.duration_since(*shown) > FPS_WINDOW)
+    {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_should_only_count_frames_of_the_animation_playing() {
+        let counters = Counters::default();
+        for _ in 0..3 {
+            counters.frame_shown();
+        }
+        counters.frame_skipped();
+        assert_eq!((counters.shown(), counters.skipped()), (3, 1));
+        assert_eq!(counters.fps(), 3.0);
+
+        let bytes = Arc::new(AtomicUsize::new(1024));
+        counters.animation_started(7, true, Arc::clone(&bytes));
+        counters.animation_started(8, false, Arc::clone(&bytes));
+        assert_eq!(counters.animations(), (2, 1));
+        assert_eq!(counters.resident_bytes(8), 1024);
+        // something else replaced the animation
+        assert_eq!(counters.resident_bytes(9), 0);
+        bytes.store(0, Ordering::Release);
+        assert_eq!(counters.resident_bytes(8), 0);
+    }
+}
@@ -0,0 +1,185 @@
+//! What wallpapers draw to, see [`Surface`] and [`Pool`].
+//!
+//! Wallpapers normally draw to a layer surface, with buffers in shared memory that the compositor
+//! reads from (see [`crate::buffers`]). Everything a wallpaper does with them goes through these
+//! traits, so that it can just as well draw to a surface nothing shows: the tests use
+//! `headless` ones, which keep every frame in memory, to play requests from start to
+//! display without a compositor.
+
+use smithay_client_toolkit::{
+    reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport,
+    shell::{
+        wlr_layer::{KeyboardInteractivity, LayerSurface},
+        WaylandSurface,
+    },
+    shm::Shm,
+};
+
+use wayland_client::{
+    protocol::{wl_buffer::WlBuffer, wl_output, wl_shm, wl_surface::WlSurface},
+    QueueHandle,
+};
+
+use crate::{buffers::BufferPool, placement::Placement, Daemon};
+
+/// A buffer handed out by a [`Pool`], for its [`Surface`] to show
+#[derive(Clone, Debug, PartialEq)]
+pub enum Buffer {
+    Wayland(WlBuffer),
+    /// Headless pools only have one buffer, so this is the pool's id
+    #[cfg_attr(not(test), allow(dead_code))]
+    Headless(u32),
+    /// X11 pools only have one buffer, which they draw to the root window when committed, so
+    /// this is the pool's id, like that of headless ones
+    #[cfg(feature = "x11")]
+    X11(u32),
+    /// The pool couldn't get us a buffer, so we keep what we drew for the next one, and skip this
+    /// frame
+    Skipped,
+}
+
+pub trait Surface: Send + Sync {
+    /// Whether the compositor's events about `surface` are about us
+    fn is(&self, surface: &WlSurface) -> bool;
+
+    /// Puts us where `placement` says on the output, under windows, taking no input
+    fn place(&self, placement: &Placement);
+
+    /// Our size, in logical pixels
+    fn set_size(&self, width: u32, height: u32);
+
+    fn set_buffer_scale(&self, scale: i32);
+
+    fn set_buffer_transform(&self, transform: wl_output::Transform);
+
+    /// Whether the compositor can show our buffers at fractional scales, through
+    /// [`Surface::set_destination`]
+    fn has_viewport(&self) -> bool;
+
+    /// Has the compositor show our buffers this many logical pixels wide and high, whatever their
+    /// size. Does nothing without a viewport
+    fn set_destination(&self, width: i32, height: i32);
+
+    fn commit(&self);
+
+    /// Shows `buffer`, where only the `damage` rectangles, as `(x, y, width, height)` in pixels,
+    /// changed. Returns whether we will be told when to draw the next frame
+    fn present(&self, buffer: &Buffer, damage: &[(usize, usize, usize, usize)]) -> bool;
+
+    /// A pool of buffers to draw to, for [`Surface::present`]
+    fn new_pool(&self) -> Box<dyn Pool>;
+}
+
+/// See [`BufferPool`], whose docs hold for every pool
+pub trait Pool: Send {
+    /// A buffer to draw to, and its pixels, which start with what we drew last, unless the buffers
+    /// have a different size or format than they had then. Fails if we ran out of memory for it
+    fn get(
+        &mut self,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: wl_shm::Format,
+    ) -> Result<(Buffer, &mut [u8]), String>;
+
+    /// Marks `buffer` as in use, if it is ours, until its surface is done with it. Must be called
+    /// right before presenting it
+    fn commit(&self, buffer: &Buffer);
+
+    /// Throws away every buffer, and what we drew to them. Keeps the memory for the next ones
+    fn clear(&mut self);
+
+    /// How many bytes of memory the pool has
+    fn mapped_bytes(&self) -> usize;
+
+    /// The pixels of the buffer we drew to last, unless the buffers changed since then
+    fn last_drawn(&mut self) -> Option<&[u8]>;
+}
+
+/// A layer surface on one of the compositor's outputs
+pub struct LayerBackend {
+    layer_surface: LayerSurface,
+    /// Only present if the compositor supports fractional scaling
+    viewport: Option<WpViewport>,
+    shm: Shm,
+    qh: QueueHandle<Daemon>,
+}
+
+impl LayerBackend {
+    pub fn new(
+        layer_surface: LayerSurface,
+        viewport: Option<WpViewport>,
+        shm: &Shm,
+        qh: QueueHandle<Daemon>,
+    ) -> Self {
+        Self {
+            layer_surface,
+            viewport,
+            // pools only need the global, not the formats the compositor supports
+            shm: Shm::from(shm.wl_shm().clone()),
+            qh,
+        }
+    }
+}
+
+impl Surface for LayerBackend {
+    fn is(&self, surface: &WlSurface) -> bool {
+        self.layer_surface.wl_surface() == surface
+    }
+
+    fn place(&self, placement: &Placement) {
+        self.layer_surface.set_anchor(placement.anchor());
+        self.layer_surface.set_exclusive_zone(-1);
+        let [top, right, bottom, left] = placement.margin;
+        self.layer_surface.set_margin(top, right, bottom, left);
+        self.layer_surface
+            .set_keyboard_interactivity(KeyboardInteractivity::None);
+    }
+
+    fn set_size(&self, width: u32, height: u32) {
+        self.layer_surface.set_size(width, height);
+    }
+
+    fn set_buffer_scale(&self, scale: i32) {
+        self.layer_surface.set_buffer_scale(scale as u32).unwrap();
+    }
+
+    fn set_buffer_transform(&self, transform: wl_output::Transform) {
+        self.layer_surface.set_buffer_transform(transform).unwrap();
+    }
+
+    fn has_viewport(&self) -> bool {
+        self.viewport.is_some()
+    }
+
+    fn set_destination(&self, width: i32, height: i32) {
+        if let Some(viewport) = &self.viewport {
+            viewport.set_destination(width, height);
+        }
+    }
+
+    fn commit(&self) {
+        self.layer_surface.commit();
+    }
+
+    fn present(&self, buffer: &Buffer, damage: &[(usize, usize, usize, usize)]) -> bool {
+        let Buffer::Wayland(buffer) = buffer else {
+            return false;
+        };
+        let surface = self.layer_surface.wl_surface();
+        surface.attach(Some(buffer), 0, 0);
+        for &(x, y, width, height) in damage {
+            surface.damage_buffer(x as i32, y as i32, width as i32, height as i32);
+        }
+        // asks the compositor to tell us when it is a good time to draw again, with this commit
+        surface.frame(&self.qh, surface.clone());
+        surface.commit();
+        true
+    }
+
+    fn new_pool(&self) -> Box<dyn Pool> {
+        Box::new(
+            BufferPool::new(&self.shm, self.qh.clone()).expect("failed to create a buffer pool"),
+        )
+    }
+}
@@ -0,0 +1,61 @@
+//! Tints everything an output draws, without the client sending it again.
+//!
+//! Like for the buffer formats of [`crate::shm_format`], a tinted output draws to a canvas of its
+//! own, untinted, and we tint it every time we copy it to the buffer. That way, animations and
+//! transitions, which only draw what changed since the last frame, keep working, and removing the
+//! tint gets us back exactly what we drew.
+
+/// Per channel lookup tables, since every channel only has 256 values to tint
+#[derive(Clone)]
+pub struct Tint {
+    red: [u8; 256],
+    green: [u8; 256],
+    blue: [u8; 256],
+}
+
+impl Tint {
+    /// Multiplies the red, green and blue channels by `gains`. Returns `None` if that changes
+    /// nothing
+    pub fn new(gains: [f32; 3]) -> Option<Self> {
+        if gains == [1.0; 3] {
+            return None;
+        }
+        let table = |gain: f32| {
+            std::array::from_fn(|value| (value as f32 * gain).round().clamp(0.0, 255.0) as u8)
+        };
+        Some(Self {
+            red: table(gains[0]),
+            green: table(gains[1]),
+            blue: table(gains[2]),
+        })
+    }
+
+    /// Tints `src`, `xrgb8888` pixels (`[b, g, r, x]` in memory), into `dst`
+    pub fn apply(&self, src: &[u8], dst: &mut [u8]) {
+        for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+            dst[0] = self.blue[src[0] as usize];
+            dst[1] = self.green[src[1] as usize];
+            dst[2] = self.red[src[2] as usize];
+            dst[3] = src[3];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_tint_every_channel() {
+        let tint = Tint::new([1.0, 0.5, 2.0]).unwrap();
+        let mut dst = [0; 8];
+        tint.apply(&[100, 100, 100, 0xff, 200, 10, 255, 0], &mut dst);
+        assert_eq!(dst, [200, 50, 100, 0xff, 255, 5, 255, 0]);
+    }
+
+    #[test]
+    fn neutral_gains_are_no_tint() {
+        assert!(Tint::new([1.0; 3]).is_none());
+        assert!(Tint::new([1.0, 0.0, 1.0]).is_some());
+    }
+}
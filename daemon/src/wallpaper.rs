@@ -1,33 +1,92 @@
-use utils::ipc::BgImg;
+use utils::{
+    comp_decomp::Damage,
+    ipc::{Adjustments, BgImg, Dither, OutputStats, Remake, Transform},
+};
+
+use crate::{
+    adjust::Adjust,
+    crossfade::Crossfade,
+    events::{self, Event},
+    gc::Collect,
+    mirror,
+    overlay::Overlay,
+    parallax,
+    placement::Placement,
+    shm_format::ShmFormat,
+    stats::Counters,
+    surface::{Buffer, Pool, Surface},
+    tint::Tint,
+};
 
 use std::{
-    num::NonZeroI32,
+    num::{NonZeroI32, NonZeroU32},
+    os::unix::net::UnixStream,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
-        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
     },
+    time::{Duration, Instant, SystemTime},
 };
 
-use smithay_client_toolkit::{
-    output::OutputInfo,
-    shell::{
-        wlr_layer::{Anchor, KeyboardInteractivity, LayerSurface},
-        WaylandSurface,
-    },
-    shm,
-};
+use nix::poll::{poll, PollFd, PollFlags};
+use smithay_client_toolkit::output::{Mode, OutputInfo};
+
+use wayland_client::protocol::{wl_output, wl_shm, wl_surface::WlSurface};
 
-use wayland_client::protocol::{wl_buffer::WlBuffer, wl_shm, wl_surface::WlSurface};
+/// A buffer's format can't change once it is created, so buffers with an alpha channel are kept
+/// in a pool of their own, apart from the opaque ones, which keep what we drew to them
+struct Pools {
+    opaque: Box<dyn Pool>,
+    alpha: Box<dyn Pool>,
+}
+
+impl Pools {
+    fn clear(&mut self) {
+        self.opaque.clear();
+        self.alpha.clear();
+    }
+}
+
+/// How many frames we have committed so far, across all outputs
+static FRAMES_DRAWN: AtomicUsize = AtomicUsize::new(0);
 
-/// The memory pool wallpapers use
-pub type ShmPool = shm::multi::MultiPool<(WlSurface, u32)>;
-/// The memory pool, multithreaded
-pub type MtShmPool = Arc<Mutex<ShmPool>>;
+pub fn frames_drawn() -> usize {
+    FRAMES_DRAWN.load(Ordering::Acquire)
+}
+
+/// A frame callback the compositor hasn't answered in this long was most likely dropped, so we stop
+/// waiting for it
+const STALE_FRAME_CALLBACK: Duration = Duration::from_secs(1);
+
+/// Value of `AnimationState::transition_id` when no transition is playing
+const NO_TRANSITION: usize = usize::MAX;
+
+/// Value of `AnimationState::frame` when no animation is playing
+const NOT_ANIMATED: usize = usize::MAX;
 
 #[derive(Debug)]
 struct AnimationState {
     id: AtomicUsize,
     transition_finished: Arc<AtomicBool>,
+    /// Animation id of the transition playing on the wallpaper, or `NO_TRANSITION`
+    transition_id: AtomicUsize,
+    /// Transitions waiting for the one playing to end take a ticket from `next_ticket`, and start
+    /// once `serving_ticket` reaches it
+    next_ticket: AtomicUsize,
+    serving_ticket: AtomicUsize,
+    /// The animation frame on the screen, or `NOT_ANIMATED`
+    frame: AtomicUsize,
+    /// Set by `swww pause`. Unlike the rest, a new image doesn't reset it
+    paused: AtomicBool,
+    /// The bits of the `f32` the animation's frame delays are divided by
+    playback_speed: AtomicU32,
+    /// How many frames `swww step` still wants the paused animation to advance
+    steps: AtomicU32,
+    /// Whether the compositor turned the output off. Unlike `paused`, this only stops animations
+    /// that are off on every output they play on
+    powered_off: AtomicBool,
+    /// Whether a fullscreen window covers the output. Works like `powered_off`
+    occluded: AtomicBool,
 }
 
 #[derive(Debug)]
@@ -48,71 +107,289 @@ impl Drop for AnimationToken {
     }
 }
 
+/// An animation a client is still making, for an image it already sent us. See
+/// [`utils::ipc::Img::request`]
+#[derive(Debug)]
+struct Expected {
+    request: u64,
+    /// Where the transition queue ended after the image was queued, so that its animation waits
+    /// for the transitions up to the image's own
+    queue_end: usize,
+    /// The socket the client sent the image on. Dropping it tells the client to give up, and the
+    /// client hangs up if it fails to make the animation
+    client: Option<Arc<UnixStream>>,
+    /// Whether the animation got to us, and only waits for its turn. Its client may exit then,
+    /// without giving up on it
+    arrived: bool,
+}
+
+impl Expected {
+    fn client_gave_up(&self) -> bool {
+        !self.arrived
+            && self.client.as_ref().is_some_and(|client| {
+                // the client never writes to this socket again, so anything to read is a hang up
+                let mut fds = [PollFd::new(client.as_ref(), PollFlags::POLLIN)];
+                matches!(poll(&mut fds, 0), Ok(n) if n > 0)
+            })
+    }
+}
+
+/// What a wallpaper needs to know about its output when it is made
+pub struct OutputDescription {
+    pub id: u32,
+    pub name: String,
+    /// In logical pixels, if the compositor told us
+    pub logical_size: Option<(i32, i32)>,
+    pub scale_factor: i32,
+    pub transform: wl_output::Transform,
+    /// In Hz, of the current mode
+    pub refresh_rate: Option<u32>,
+}
+
+impl From<OutputInfo> for OutputDescription {
+    fn from(info: OutputInfo) -> Self {
+        Self {
+            id: info.id,
+            name: info.name.unwrap_or_else(|| "?".to_string()),
+            logical_size: info.logical_size,
+            scale_factor: info.scale_factor,
+            transform: info.transform,
+            refresh_rate: refresh_rate(&info.modes),
+        }
+    }
+}
+
 /// Owns all the necessary information for drawing.
 #[derive(Debug)]
 struct WallpaperInner {
     width: NonZeroI32,
     height: NonZeroI32,
     scale_factor: NonZeroI32,
+    /// Whether we draw at the logical size and let the compositor scale it to the output
+    compositor_scaling: bool,
+    /// The scale the compositor prefers, in 120ths, if it told us through
+    /// `wp_fractional_scale_v1`. It overrides `scale_factor`
+    fractional_scale: Option<NonZeroU32>,
+    /// How the output is rotated or flipped. We draw with it applied, so that the compositor
+    /// needn't apply it itself
+    transform: Transform,
 
     img: BgImg,
+    /// How the client made `img`, if it is an image
+    remake: Remake,
+    /// How we dither `img` down to our buffers, see `ShmFormat::convert`
+    dither: Option<Dither>,
+    /// Whether we were asked to display anything yet
+    shown_img: bool,
+    /// When we were asked to display `img`
+    img_set_at: Option<SystemTime>,
+    /// Whether we draw to buffers with an alpha channel
+    alpha: bool,
+    /// Where the pointer was last seen on this output, in surface coordinates
+    cursor: Option<(f64, f64)>,
+}
+
+impl WallpaperInner {
+    fn buffer_dimensions(&self) -> (i32, i32) {
+        self.canvas_dimensions(0)
+    }
+
+    /// The size of what we draw: that of our buffers, and `margin` more logical pixels past every
+    /// edge, for `--parallax` to shift by
+    fn canvas_dimensions(&self, margin: u32) -> (i32, i32) {
+        let grow = |len: NonZeroI32| {
+            NonZeroI32::new(len.get().saturating_add(2 * margin as i32)).unwrap_or(len)
+        };
+        self.transform.buffer_dim(buffer_dimensions(
+            grow(self.width),
+            grow(self.height),
+            self.scale_factor,
+            self.fractional_scale,
+            self.compositor_scaling,
+        ))
+    }
 }
 
 pub struct Wallpaper {
     output_id: u32,
+    /// The name of the output, for the events we send to `swww subscribe` and our errors
+    name: String,
     inner: RwLock<WallpaperInner>,
-    layer_surface: LayerSurface,
+    surface: Box<dyn Surface>,
 
     animation_state: AnimationState,
-    pool: MtShmPool,
+    pools: Arc<Mutex<Pools>>,
+    /// The format of our opaque buffers
+    shm_format: ShmFormat,
+    /// What we draw, in `xrgb8888`, when our opaque buffers are in another format, or are
+    /// adjusted, tinted or overlaid. We convert (and adjust, and so on) it every time we draw it
+    canvas: Mutex<Vec<u8>>,
+    /// How we adjust what we display, until we display something else, except on buffers with an
+    /// alpha channel
+    adjust: Mutex<Option<Adjust>>,
+    /// What we tint everything we draw with, except for buffers with an alpha channel
+    tint: Mutex<Option<Tint>>,
+    /// What we draw over everything we draw, under the tint, except on buffers with an alpha
+    /// channel
+    overlay: Mutex<Option<Arc<Overlay>>>,
+    /// How many logical pixels past every edge of the output we draw, for `--parallax` to shift
+    /// by, if set
+    parallax: Option<u32>,
+    /// How far `--parallax` shifts what we draw now. Like tinted outputs, shifted ones draw to
+    /// `canvas`, which is larger than our buffers then
+    parallax_shift: Mutex<(i32, i32)>,
+    /// The part of `canvas` the shift shows, with the overlay drawn over it, kept so that we don't
+    /// allocate either for every frame
+    composed: Mutex<Vec<u8>>,
+    /// Whether the shift changed while we waited for a frame callback, so that we must draw it
+    /// once it arrives
+    parallax_undrawn: AtomicBool,
+    /// The animation we are blending into what we draw, while a 'crossfade' transition plays.
+    /// Like tinted outputs, crossfading ones draw to `canvas`
+    crossfade: Mutex<Option<Crossfade>>,
+    /// The animations still on their way to us, oldest first
+    expected: Mutex<Vec<Expected>>,
+    /// In Hz, or 0 if the output didn't tell us
+    refresh_rate: AtomicU32,
+    /// Our `--max-fps`, or 0 if we have none
+    max_fps: AtomicU32,
+    /// How long it has been, in animation time, since the last animation frame we showed under
+    /// `max_fps` appeared
+    since_shown: Mutex<Option<Duration>>,
+    /// Whether the canvas changed since we last sent it to the compositor, so that the next
+    /// [`Wallpaper::draw_damaged`] must damage all of it
+    undrawn: AtomicBool,
+    /// When we asked for the frame callback the compositor hasn't answered yet, if there is one
+    frame_requested: Mutex<Option<Instant>>,
+    frame_done: Condvar,
+    /// What we displayed before our size changed, and the animation frame we were on, until we
+    /// are given something else to display
+    lost: Mutex<Option<(BgImg, Remake, Option<usize>)>>,
+    /// Whether `lost` must be displayed again at our new size
+    redisplay: AtomicBool,
+    /// The outputs mirroring us, see `swww mirror`, with the buffer we last copied what we drew
+    /// to, until we present it
+    mirrors: Mutex<Vec<(Weak<Wallpaper>, Option<Buffer>)>>,
+    /// A copy of what we last drew, for our mirrors, so that we resample it for them without
+    /// holding our canvas. Kept so that we don't allocate it for every frame
+    mirrored: Mutex<Vec<u8>>,
+    /// The output we mirror, until we display something of our own
+    mirror_of: Mutex<Option<Weak<Wallpaper>>>,
+    /// For `swww stats`
+    counters: Counters,
     pub configured: AtomicBool,
 }
 
+/// All the buffers a wallpaper has, and their memory. Once the wallpaper is gone, nobody else
+/// will ever use them, so the garbage collector can free them
+pub struct PoolBuffers {
+    pools: Arc<Mutex<Pools>>,
+}
+
+impl Collect for PoolBuffers {
+    fn collect(self) -> usize {
+        let mut pools = self.pools.lock().unwrap();
+        pools.clear();
+        // the memory itself goes once we drop the last reference to it, which is ours
+        pools.opaque.mapped_bytes() + pools.alpha.mapped_bytes()
+    }
+}
+
 impl Wallpaper {
-    pub fn new(output_info: OutputInfo, layer_surface: LayerSurface, pool: MtShmPool) -> Self {
-        let (width, height): (NonZeroI32, NonZeroI32) = if let Some(size) = output_info.logical_size
-        {
-            if size.0 == 0 || size.1 == 0 {
-                (256.try_into().unwrap(), 256.try_into().unwrap())
-            } else {
-                (size.0.try_into().unwrap(), size.1.try_into().unwrap())
-            }
-        } else {
-            (256.try_into().unwrap(), 256.try_into().unwrap())
+    pub fn new(
+        output: OutputDescription,
+        surface: Box<dyn Surface>,
+        compositor_scaling: bool,
+        shm_format: ShmFormat,
+        placement: &Placement,
+        parallax: Option<u32>,
+    ) -> Self {
+        let output_size = match output.logical_size {
+            Some(size) if size.0 != 0 && size.1 != 0 => size,
+            _ => (256, 256),
         };
+        let (_, size) = placement.region(output_size);
+        let (width, height): (NonZeroI32, NonZeroI32) =
+            (size.0.try_into().unwrap(), size.1.try_into().unwrap());
+
+        let scale_factor = NonZeroI32::new(output.scale_factor).unwrap();
+        let transform = ipc_transform(output.transform);
 
-        let scale_factor = NonZeroI32::new(output_info.scale_factor).unwrap();
-
-        // Configure the layer surface
-        layer_surface.set_anchor(Anchor::all());
-        layer_surface.set_exclusive_zone(-1);
-        layer_surface.set_margin(0, 0, 0, 0);
-        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::None);
-        layer_surface.set_size(width.get() as u32, height.get() as u32);
-        layer_surface
-            .set_buffer_scale(scale_factor.get() as u32)
-            .unwrap();
+        surface.place(placement);
+        surface.set_size(width.get() as u32, height.get() as u32);
+        surface.set_buffer_scale(buffer_scale(scale_factor, compositor_scaling));
+        surface.set_buffer_transform(output.transform);
         // commit so that the compositor send the initial configuration
-        layer_surface.commit();
+        surface.commit();
 
         Self {
-            output_id: output_info.id,
-            layer_surface,
-            pool,
+            max_fps: AtomicU32::new(0),
+            since_shown: Mutex::new(None),
+            refresh_rate: AtomicU32::new(output.refresh_rate.unwrap_or(0)),
+            output_id: output.id,
+            name: output.name,
+            pools: Arc::new(Mutex::new(Pools {
+                opaque: surface.new_pool(),
+                alpha: surface.new_pool(),
+            })),
+            surface,
+            shm_format,
+            canvas: Mutex::new(Vec::new()),
+            adjust: Mutex::new(None),
+            tint: Mutex::new(None),
+            overlay: Mutex::new(None),
+            parallax,
+            parallax_shift: Mutex::new((0, 0)),
+            composed: Mutex::new(Vec::new()),
+            parallax_undrawn: AtomicBool::new(false),
+            crossfade: Mutex::new(None),
+            expected: Mutex::new(Vec::new()),
             inner: RwLock::new(WallpaperInner {
                 width,
                 height,
                 scale_factor,
+                compositor_scaling,
+                fractional_scale: None,
+                transform,
                 img: BgImg::Color([0, 0, 0]),
+                remake: Remake::default(),
+                dither: None,
+                shown_img: false,
+                img_set_at: None,
+                alpha: false,
+                cursor: None,
             }),
             animation_state: AnimationState {
                 id: AtomicUsize::new(0),
                 transition_finished: Arc::new(AtomicBool::new(false)),
+                transition_id: AtomicUsize::new(NO_TRANSITION),
+                next_ticket: AtomicUsize::new(0),
+                serving_ticket: AtomicUsize::new(0),
+                frame: AtomicUsize::new(NOT_ANIMATED),
+                paused: AtomicBool::new(false),
+                playback_speed: AtomicU32::new(1.0f32.to_bits()),
+                steps: AtomicU32::new(0),
+                powered_off: AtomicBool::new(false),
+                occluded: AtomicBool::new(false),
             },
+            undrawn: AtomicBool::new(false),
+            frame_requested: Mutex::new(None),
+            frame_done: Condvar::new(),
+            lost: Mutex::new(None),
+            redisplay: AtomicBool::new(false),
+            mirrors: Mutex::new(Vec::new()),
+            mirrored: Mutex::new(Vec::new()),
+            mirror_of: Mutex::new(None),
+            counters: Counters::default(),
             configured: AtomicBool::new(false),
         }
     }
 
+    #[inline]
+    pub fn output_id(&self) -> u32 {
+        self.output_id
+    }
+
     #[inline]
     pub fn has_id(&self, id: u32) -> bool {
         self.output_id == id
@@ -128,65 +405,277 @@ impl Wallpaper {
 
     #[inline]
     pub fn has_surface(&self, surface: &WlSurface) -> bool {
-        self.layer_surface.wl_surface() == surface
+        self.surface.is(surface)
+    }
+
+    pub fn pool_buffers(&self) -> PoolBuffers {
+        PoolBuffers {
+            pools: Arc::clone(&self.pools),
+        }
+    }
+
+    /// The refresh rate of the output, in Hz, if it told us
+    pub fn refresh_rate(&self) -> Option<u32> {
+        match self.refresh_rate.load(Ordering::Acquire) {
+            0 => None,
+            hz => Some(hz),
+        }
+    }
+
+    pub fn set_refresh_rate(&self, modes: &[Mode]) {
+        self.refresh_rate
+            .store(refresh_rate(modes).unwrap_or(0), Ordering::Release);
+    }
+
+    pub fn set_max_fps(&self, max_fps: Option<u32>) {
+        self.max_fps.store(max_fps.unwrap_or(0), Ordering::Release);
     }
 
+    /// At most how many frames per second we show, with `--max-fps`
+    pub fn max_fps(&self) -> Option<u32> {
+        match self.max_fps.load(Ordering::Acquire) {
+            0 => None,
+            fps => Some(fps),
+        }
+    }
+
+    /// Whether we must skip showing the next animation frame, which stays on the screen for
+    /// `delay`, to stay under our `--max-fps`. We only show the frames that come at least one
+    /// interval after the last one we showed. Must be called for every frame we could show
+    pub fn over_max_fps(&self, delay: Duration) -> bool {
+        let Some(max_fps) = self.max_fps() else {
+            return false;
+        };
+        let interval = Duration::from_secs(1) / max_fps;
+        let mut since_shown = self.since_shown.lock().unwrap();
+        match *since_shown {
+            Some(since) if since < interval => {
+                *since_shown = Some(since + delay);
+                true
+            }
+            _ => {
+                *since_shown = Some(delay);
+                false
+            }
+        }
+    }
+
+    /// The size of what we draw, in pixels, which, with `--parallax`, is larger than our output
     pub fn get_dimensions(&self) -> (u32, u32) {
+        let (width, height) = self.canvas_dimensions(&self.lock_inner());
+        (width as u32, height as u32)
+    }
+
+    fn canvas_dimensions(&self, inner: &WallpaperInner) -> (i32, i32) {
+        inner.canvas_dimensions(self.parallax_margin())
+    }
+
+    /// How many logical pixels past every edge of our output clients must make images for, see
+    /// `crate::parallax`
+    pub fn parallax_margin(&self) -> u32 {
+        self.parallax.unwrap_or(0)
+    }
+
+    pub fn set_cursor(&self, cursor: Option<(f64, f64)>) {
+        self.lock_inner_mut().cursor = cursor;
+    }
+
+    /// Where the pointer was last seen on this output, in pixels of our buffers. Like in
+    /// `Position::to_pixel`, y grows from the bottom of the screen
+    pub fn cursor_position(&self) -> Option<(f32, f32)> {
         let inner = self.lock_inner();
-        let width = inner.width.get() as u32;
-        let height = inner.height.get() as u32;
-        let scale_factor = inner.scale_factor.get() as u32;
-        (width * scale_factor, height * scale_factor)
+        let (x, y) = inner.cursor?;
+        let (width, height) = inner.buffer_dimensions();
+        // the size of the output, in pixels, before we transform it to our buffers
+        let (out_width, out_height) = inner.transform.buffer_dim((width as f32, height as f32));
+        let x = x as f32 * out_width / inner.width.get() as f32;
+        let y = y as f32 * out_height / inner.height.get() as f32;
+        let (x, y) = inner.transform.to_buffer((x, y), (out_width, out_height));
+        Some((x, height as f32 - y))
     }
 
     #[inline]
-    fn lock(&self) -> (RwLockReadGuard<WallpaperInner>, MutexGuard<ShmPool>) {
-        (self.lock_inner(), self.pool.lock().unwrap())
+    fn lock(&self) -> (RwLockReadGuard<'_, WallpaperInner>, MutexGuard<'_, Pools>) {
+        (self.lock_inner(), self.pools.lock().unwrap())
     }
 
     #[inline]
-    fn lock_mut(&self) -> (RwLockWriteGuard<WallpaperInner>, MutexGuard<ShmPool>) {
-        (self.lock_inner_mut(), self.pool.lock().unwrap())
+    fn lock_mut(&self) -> (RwLockWriteGuard<'_, WallpaperInner>, MutexGuard<'_, Pools>) {
+        (self.lock_inner_mut(), self.pools.lock().unwrap())
     }
 
     #[inline]
-    fn lock_inner(&self) -> RwLockReadGuard<WallpaperInner> {
+    fn lock_inner(&self) -> RwLockReadGuard<'_, WallpaperInner> {
         self.inner.read().unwrap()
     }
 
     #[inline]
-    fn lock_inner_mut(&self) -> RwLockWriteGuard<WallpaperInner> {
+    fn lock_inner_mut(&self) -> RwLockWriteGuard<'_, WallpaperInner> {
         self.inner.write().unwrap()
     }
 
-    pub fn canvas_change<F, T>(&self, f: F) -> (T, WlBuffer)
+    pub fn canvas_change<F, T>(&self, f: F) -> (T, Buffer)
     where
         F: FnOnce(&mut [u8]) -> T,
     {
-        let (inner, mut pool) = self.lock();
-        let width = inner.width.get() * inner.scale_factor.get();
-        let stride = width * 4;
-        let height = inner.height.get() * inner.scale_factor.get();
+        let (inner, mut pools) = self.lock();
+        let (width, height) = inner.buffer_dimensions();
+        let canvas_dim = self.canvas_dimensions(&inner);
+        let canvas_dim = (canvas_dim.0 as usize, canvas_dim.1 as usize);
+        let alpha = inner.alpha;
+        let dither = inner.dither;
+        let geometry = ((width as u32, height as u32), inner.transform);
+        let (pool, format, native) = if alpha {
+            (&mut pools.alpha, wl_shm::Format::Argb8888, true)
+        } else {
+            (
+                &mut pools.opaque,
+                self.shm_format.wl_format(),
+                self.shm_format.is_native(),
+            )
+        };
+        let stride = if native {
+            width * 4
+        } else {
+            width * self.shm_format.bytes_per_pixel() as i32
+        };
         drop(inner);
-        let mut frame = 0u32;
-        loop {
-            match pool.create_buffer(
-                width,
-                stride,
-                height,
-                &(self.layer_surface.wl_surface().clone(), frame),
-                wl_shm::Format::Xrgb8888,
-            ) {
-                Ok((_offset, buffer, canvas)) => return (f(canvas), buffer.clone()),
-                Err(e) => match e {
-                    smithay_client_toolkit::shm::multi::PoolError::InUse => frame += 1,
-                    smithay_client_toolkit::shm::multi::PoolError::Overlap => {
-                        pool.remove(&(self.layer_surface.wl_surface().clone(), frame));
+        let mut adjust = self.adjust.lock().unwrap();
+        let tint = self.tint.lock().unwrap();
+        let overlay = self.overlay.lock().unwrap();
+        let shift = *self.parallax_shift.lock().unwrap();
+        let shifting = self.parallax.is_some();
+        let crossfading = self.is_crossfading();
+        let start = std::time::Instant::now();
+        let dim = (width as usize, height as usize);
+        let len = canvas_dim.0 * canvas_dim.1 * 4;
+        let mut ours = self.canvas.lock().unwrap();
+        let mirrored;
+        let (buffer, mut canvas) = match pool.get(width, height, stride, format) {
+            Ok((buffer, canvas)) => (buffer, Some(canvas)),
+            Err(e) => {
+                log::warn!("skipping a frame on {}: {e}", self.name);
+                // we draw to our own canvas instead, which starts with what we drew last, so that
+                // the next buffer has it, and animations stay on where they were
+                if ours.len() != len {
+                    ours.clear();
+                    match pool.last_drawn() {
+                        Some(drawn) if native && drawn.len() == len => {
+                            ours.extend_from_slice(drawn)
+                        }
+                        _ => ours.resize(len, 0),
                     }
-                    smithay_client_toolkit::shm::multi::PoolError::NotFound => unreachable!(),
-                },
+                }
+                (Buffer::Skipped, None)
             }
+        };
+        let direct = canvas.is_some()
+            && native
+            && !shifting
+            && (alpha || (adjust.is_none() && tint.is_none() && overlay.is_none() && !crossfading));
+        let ret = if let Some(canvas) = canvas.as_deref_mut().filter(|_| direct) {
+            // we stopped tinting, so the buffer has what we tinted, not what we drew
+            if !alpha && !ours.is_empty() {
+                if ours.len() == canvas.len() {
+                    canvas.copy_from_slice(&ours);
+                }
+                *ours = Vec::new();
+            }
+            let ret = f(canvas);
+            mirrored = self.keep_for_mirrors(canvas);
+            ret
+        } else {
+            if ours.len() != len {
+                ours.clear();
+                match canvas.as_deref() {
+                    // we started tinting, and drew straight to the buffer until now
+                    Some(canvas) if native && canvas.len() == len => ours.extend_from_slice(canvas),
+                    _ => ours.resize(len, 0),
+                }
+            }
+            let ret = f(&mut ours);
+            let mut crossfade = self.crossfade.lock().unwrap();
+            let drawn = match &mut *crossfade {
+                Some(crossfade) if crossfade.outgoing.len() == len => crossfade.blend(&ours),
+                _ => &ours,
+            };
+            // buffers with an alpha channel only ever take what we drew, shifted
+            let drawn = match adjust.as_mut().filter(|_| !alpha) {
+                Some(adjust) => adjust.apply(drawn, canvas_dim),
+                None => drawn,
+            };
+            let mut composed = self.composed.lock().unwrap();
+            if shifting {
+                composed.resize(dim.0 * dim.1 * 4, 0);
+                parallax::shift(drawn, canvas_dim, &mut composed, dim, shift);
+                mirrored = self.keep_for_mirrors(&composed);
+            } else {
+                mirrored = self.keep_for_mirrors(drawn);
+            }
+            let overlay = overlay.as_ref().filter(|_| !alpha);
+            if let Some(overlay) = overlay {
+                if !shifting {
+                    composed.clear();
+                    composed.extend_from_slice(drawn);
+                }
+                overlay.apply(&mut composed, dim);
+            }
+            let drawn = if shifting || overlay.is_some() {
+                &composed
+            } else {
+                drawn
+            };
+            match (tint.as_ref().filter(|_| !alpha), canvas) {
+                (_, None) => (),
+                (Some(tint), Some(canvas)) if native => tint.apply(drawn, canvas),
+                (Some(tint), Some(canvas)) => {
+                    let mut tinted = vec![0; drawn.len()];
+                    tint.apply(drawn, &mut tinted);
+                    self.shm_format
+                        .convert(&tinted, canvas, width as usize, dither);
+                }
+                (None, Some(canvas)) if alpha => canvas.copy_from_slice(drawn),
+                (None, Some(canvas)) => {
+                    self.shm_format
+                        .convert(drawn, canvas, width as usize, dither)
+                }
+            }
+            ret
+        };
+        crate::metrics::frame_drawn(start.elapsed());
+        drop((ours, overlay, tint, adjust, pools));
+        if mirrored {
+            self.copy_to_mirrors(geometry, alpha);
         }
+        (ret, buffer)
+    }
+
+    /// Runs `f` on what we drew last, `xrgb8888` (or premultiplied `argb8888`) pixels, before our
+    /// adjustments, tint, overlay and parallax shift, without drawing anything. `None` if we
+    /// haven't drawn anything since our size last changed
+    pub fn with_drawn<T>(&self, f: impl FnOnce(&[u8]) -> T) -> Option<T> {
+        let (inner, mut pools) = self.lock();
+        let (width, height) = self.canvas_dimensions(&inner);
+        let alpha = inner.alpha;
+        drop(inner);
+        // shifted outputs always draw to their own canvas
+        if alpha && self.parallax.is_none() {
+            return pools.alpha.last_drawn().map(f);
+        }
+        let len = width as usize * height as usize * 4;
+        let ours = self.canvas.lock().unwrap();
+        if ours.len() == len {
+            return Some(f(&ours));
+        }
+        drop(ours);
+        if !self.shm_format.is_native() {
+            return None;
+        }
+        pools
+            .opaque
+            .last_drawn()
+            .filter(|drawn| drawn.len() == len)
+            .map(f)
     }
 
     #[inline]
@@ -194,6 +683,11 @@ impl Wallpaper {
         self.lock_inner().img.clone()
     }
 
+    /// When we were asked to display what we are displaying, or `None`, if we were never asked
+    pub fn img_set_at(&self) -> Option<SystemTime> {
+        self.lock_inner().img_set_at
+    }
+
     #[inline]
     pub fn create_animation_token(&self) -> AnimationToken {
         let id = self.animation_state.id.load(Ordering::Acquire);
@@ -203,16 +697,327 @@ impl Wallpaper {
         }
     }
 
+    /// Whether the animation `token` is for still plays on us. Unlike the token, this can be kept
+    /// elsewhere, see `FrameCache`, and doesn't keep us alive
+    pub fn still_playing(self: &Arc<Self>, token: &AnimationToken) -> impl Fn() -> bool + Send {
+        let (wallpaper, id) = (Arc::downgrade(self), token.id);
+        move || {
+            wallpaper
+                .upgrade()
+                .is_some_and(|w| w.animation_state.id.load(Ordering::Acquire) == id)
+        }
+    }
+
     /// This will stop all animations with the current id
     #[inline]
     pub fn inc_animation_id(&self) {
+        *self.crossfade.lock().unwrap() = None;
         self.animation_state.id.fetch_add(1, Ordering::AcqRel);
         self.animation_state
             .transition_finished
             .store(false, Ordering::Release);
+        self.animation_state
+            .frame
+            .store(NOT_ANIMATED, Ordering::Release);
+    }
+
+    /// Stops the animation we are playing, like [`Wallpaper::inc_animation_id`], but lets it keep
+    /// playing under whatever we draw next, fading out over `duration`, see
+    /// [`Wallpaper::fade_out`]. Returns `false`, doing nothing, if we aren't playing an animation,
+    /// or it is paused, or has an alpha channel. With `gamma_correct`, they blend in linear light
+    pub fn start_crossfade(&self, duration: Duration, gamma_correct: bool) -> bool {
+        if self.animation_frame().is_none() || self.is_paused() || self.lock_inner().alpha {
+            return false;
+        }
+        // the animation only unpacks frames while holding the canvas, so it can't get ahead of
+        // the copy we make
+        let _ = self.canvas_change(|canvas| {
+            let from = self.animation_state.id.load(Ordering::Acquire);
+            self.inc_animation_id();
+            let crossfade = Crossfade::new(canvas.to_vec(), from, duration, gamma_correct);
+            *self.crossfade.lock().unwrap() = Some(crossfade);
+        });
+        true
+    }
+
+    /// Whether a crossfade is still blending an animation into what we draw
+    pub fn is_crossfading(&self) -> bool {
+        self.crossfade
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|crossfade| !crossfade.is_over())
+    }
+
+    /// Calls `f` with the canvas of the animation holding `token`, if a crossfade is fading it out.
+    /// Returns whether it still is
+    pub fn fade_out<F>(&self, token: &AnimationToken, f: F) -> bool
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let mut crossfade = self.crossfade.lock().unwrap();
+        match &mut *crossfade {
+            Some(crossfade) if crossfade.from == token.id => {
+                if crossfade.is_over() {
+                    return false;
+                }
+                f(&mut crossfade.outgoing);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stops blending the animation holding `token`, if we still were
+    pub fn end_crossfade(&self, token: &AnimationToken) {
+        let mut crossfade = self.crossfade.lock().unwrap();
+        if crossfade.as_ref().is_some_and(|c| c.from == token.id) {
+            *crossfade = None;
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Records which frame of its animation the wallpaper is showing
+    pub fn set_animation_frame(&self, frame: usize) {
+        self.animation_state.frame.store(frame, Ordering::Release);
+    }
+
+    pub fn set_playback_speed(&self, speed: f32) {
+        self.animation_state
+            .playback_speed
+            .store(speed.to_bits(), Ordering::Release);
+    }
+
+    pub fn playback_speed(&self) -> f32 {
+        f32::from_bits(self.animation_state.playback_speed.load(Ordering::Acquire))
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.animation_state.paused.store(paused, Ordering::Release);
+        if !paused {
+            self.animation_state.steps.store(0, Ordering::Release);
+        }
+    }
+
+    pub fn set_powered_off(&self, powered_off: bool) {
+        self.animation_state
+            .powered_off
+            .store(powered_off, Ordering::Release);
+    }
+
+    pub fn set_occluded(&self, occluded: bool) {
+        self.animation_state
+            .occluded
+            .store(occluded, Ordering::Release);
+    }
+
+    pub fn is_occluded(&self) -> bool {
+        self.animation_state.occluded.load(Ordering::Acquire)
+    }
+
+    /// Whether nobody can see the output, because it is off or covered, or the session is locked
+    pub fn is_hidden(&self) -> bool {
+        self.animation_state.powered_off.load(Ordering::Acquire)
+            || self.is_occluded()
+            || crate::session_lock::is_locked()
+    }
+
+    /// Whether nobody can see what we draw, neither on our output, nor on those mirroring us
+    pub fn is_unseen(&self) -> bool {
+        self.is_hidden() && self.live_mirrors().iter().all(|mirror| mirror.is_hidden())
+    }
+
+    pub fn add_steps(&self, steps: u32) {
+        let _ = self
+            .animation_state
+            .steps
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                Some(n.saturating_add(steps))
+            });
+    }
+
+    /// Takes one of the steps `add_steps` added, returning whether there was one
+    pub fn take_step(&self) -> bool {
+        self.animation_state
+            .steps
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Whether the animation on the wallpaper should stay on its current frame
+    pub fn is_paused(&self) -> bool {
+        self.animation_state.paused.load(Ordering::Acquire)
+    }
+
+    /// The frame of the animation the wallpaper is showing, or `None`, if it isn't animated
+    pub fn animation_frame(&self) -> Option<usize> {
+        let frame = self.animation_state.frame.load(Ordering::Acquire);
+        (frame != NOT_ANIMATED).then_some(frame)
+    }
+
+    /// Marks that the transition holding `token` is playing
+    pub fn start_transition(&self, token: &AnimationToken) {
+        self.animation_state
+            .transition_id
+            .store(token.id, Ordering::Release);
+        events::send(Event::TransitionStarted { output: &self.name });
+    }
+
+    /// Marks that the transition holding `token` is done, unless another one already replaced it
+    pub fn end_transition(&self, token: &AnimationToken) {
+        let ended = self.animation_state.transition_id.compare_exchange(
+            token.id,
+            NO_TRANSITION,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        if ended.is_ok() {
+            events::send(Event::TransitionFinished { output: &self.name });
+        }
+    }
+
+    /// The animation `token` is for started playing on us, see [`Counters::animation_started`]
+    pub fn animation_started(&self, token: &AnimationToken, cached: bool, bytes: Arc<AtomicUsize>) {
+        self.counters.animation_started(token.id, cached, bytes);
+    }
+
+    /// Our animation skipped a frame, to catch up with the clock
+    pub fn frame_skipped(&self) {
+        self.counters.frame_skipped();
+    }
+
+    pub fn stats(&self) -> OutputStats {
+        let counters = &self.counters;
+        let (animations, cached_animations) = counters.animations();
+        let animation_id = self.animation_state.id.load(Ordering::Acquire);
+        OutputStats {
+            name: self.name.clone(),
+            fps: counters.fps(),
+            frames_shown: counters.shown(),
+            frames_skipped: counters.skipped(),
+            resident_bytes: counters.resident_bytes(animation_id) as u64,
+            animations,
+            cached_animations,
+            max_fps: self.max_fps(),
+        }
+    }
+
+    /// Tells the subscribers the animation on this wallpaper finished a loop
+    pub fn animation_looped(&self) {
+        events::send(Event::AnimationLooped { output: &self.name });
+    }
+
+    fn is_playing_transition(&self) -> bool {
+        let state = &self.animation_state;
+        state.transition_id.load(Ordering::Acquire) == state.id.load(Ordering::Acquire)
+    }
+
+    /// Whether a transition is playing, or waiting for its turn to play
+    pub fn is_transitioning(&self) -> bool {
+        let state = &self.animation_state;
+        self.is_playing_transition()
+            || state.next_ticket.load(Ordering::Acquire)
+                != state.serving_ticket.load(Ordering::Acquire)
+    }
+
+    /// Queues a transition to play after the current one, and every other one queued before it.
+    /// Returns its ticket, to give to [`Wallpaper::wait_turn`]
+    pub fn queue_transition(&self) -> usize {
+        self.animation_state
+            .next_ticket
+            .fetch_add(1, Ordering::AcqRel)
+    }
+
+    /// The ticket the next queued transition will get. Once `serving_ticket` reaches it, every
+    /// transition queued so far has started
+    pub fn queue_end(&self) -> usize {
+        self.animation_state.next_ticket.load(Ordering::Acquire)
+    }
+
+    /// Blocks until it is the turn of the transition with `ticket`. Once it starts, it must call
+    /// [`Wallpaper::end_turn`], so that the next one can wait for it. With `swww img --queue`,
+    /// queued transitions also wait for the animations of the images before them to arrive
+    pub fn wait_turn(&self, ticket: usize) {
+        while !crate::should_daemon_exit() {
+            let serving = self.animation_state.serving_ticket.load(Ordering::Acquire);
+            if serving == ticket && !self.is_playing_transition() && !self.expects_before(ticket) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    pub fn end_turn(&self) {
+        self.animation_state
+            .serving_ticket
+            .fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Blocks until the transitions queued before `queue_end` have all started
+    pub fn wait_queue(&self, queue_end: usize) {
+        while self.animation_state.serving_ticket.load(Ordering::Acquire) < queue_end
+            && !crate::should_daemon_exit()
+        {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Marks that the client that sent us the image of `request` on `client` will send its
+    /// animation next. If the image's transition was queued, call it after queueing it
+    pub fn expect_animation(&self, request: u64, client: Option<Arc<UnixStream>>) {
+        let queue_end = self.queue_end();
+        self.expected.lock().unwrap().push(Expected {
+            request,
+            queue_end,
+            client,
+            arrived: false,
+        });
+    }
+
+    /// Whether animations are still on their way to us
+    pub fn expects_animation(&self) -> bool {
+        self.expects_before(usize::MAX)
+    }
+
+    /// Whether the animation of an image queued before `ticket` is still on its way to us.
+    /// Forgets those whose clients gave up on them
+    fn expects_before(&self, ticket: usize) -> bool {
+        let mut expected = self.expected.lock().unwrap();
+        expected.retain(|e| !e.client_gave_up());
+        expected.iter().any(|e| e.queue_end <= ticket)
+    }
+
+    /// Stops waiting for the animations on their way to us, since something else replaces their
+    /// images. Their clients see us hang up, and stop making them
+    pub fn cancel_animations(&self) {
+        self.expected.lock().unwrap().clear();
+    }
+
+    /// Marks that the animation of `request` got to us, so that we keep expecting it until it
+    /// is claimed, whatever its client does. Returns where the transition queue ended after its
+    /// image was queued, or `None` if we don't expect it anymore
+    pub fn animation_arrived(&self, request: u64) -> Option<usize> {
+        let mut expected = self.expected.lock().unwrap();
+        let expected = expected.iter_mut().find(|e| e.request == request)?;
+        expected.arrived = true;
+        Some(expected.queue_end)
     }
 
-    pub fn clear(&self, color: [u8; 3]) -> WlBuffer {
+    /// Takes the token the animation of `request` plays with, unless something replaced its
+    /// image in the meantime. In both cases, we stop expecting it
+    pub fn claim_animation(&self, request: u64) -> Option<AnimationToken> {
+        let mut expected = self.expected.lock().unwrap();
+        let i = expected.iter().position(|e| e.request == request)?;
+        // the token must be taken before the next transition in the queue can stop it
+        let token = self.create_animation_token();
+        expected.remove(i);
+        Some(token)
+    }
+
+    pub fn clear(&self, color: [u8; 3]) -> Buffer {
         self.canvas_change(|canvas| {
             for pixel in canvas.chunks_exact_mut(4) {
                 pixel[2] = color[0];
@@ -223,21 +1028,166 @@ impl Wallpaper {
         .1
     }
 
+    /// Draws `pixels`, three bytes each, in the same order as the images clients send us
+    pub fn fill(&self, pixels: &[u8]) -> Buffer {
+        self.canvas_change(|canvas| {
+            for (pixel, new) in canvas.chunks_exact_mut(4).zip(pixels.chunks_exact(3)) {
+                pixel[..3].copy_from_slice(new);
+            }
+        })
+        .1
+    }
+
+    /// Records how the client made the image we display. Must come after [`Self::set_img_info`],
+    /// which forgets it
+    pub fn set_remake(&self, remake: Remake) {
+        self.lock_inner_mut().remake = remake;
+    }
+
+    /// How the client made the image we display, if it sent us one
+    pub fn remake(&self) -> Remake {
+        self.lock_inner().remake.clone()
+    }
+
+    /// Dithers what we display with `dither`, from the next time we draw it. Must come after
+    /// [`Self::set_img_info`], which forgets it
+    pub fn set_dither(&self, dither: Option<Dither>) {
+        self.lock_inner_mut().dither = dither;
+    }
+
     pub fn set_img_info(&self, img_info: BgImg) {
         log::debug!("output {} - drawing: {}", self.output_id, img_info);
-        self.lock_inner_mut().img = img_info;
+        let mut inner = self.lock_inner_mut();
+        inner.img = img_info.clone();
+        inner.remake = Remake::default();
+        inner.dither = None;
+        *self.lost.lock().unwrap() = None;
+        inner.shown_img = true;
+        inner.img_set_at = Some(SystemTime::now());
+        inner.alpha = false;
+        drop(inner);
+        *self.adjust.lock().unwrap() = None;
+        // subscribers may take a while, and mustn't hold up whoever waits for us meanwhile
+        events::send(Event::WallpaperChanged {
+            output: &self.name,
+            img: &img_info,
+        });
+        *self.mirror_of.lock().unwrap() = None;
+        // so that `swww query` tells what our mirrors display
+        for mirror in self.live_mirrors() {
+            mirror.lock_inner_mut().img = img_info.clone();
+        }
     }
 
-    pub fn draw(&self, buf: &WlBuffer) {
-        let inner = self.lock_inner();
-        let width = inner.width.get() * inner.scale_factor.get();
-        let height = inner.height.get() * inner.scale_factor.get();
-        drop(inner);
+    /// Makes us draw to buffers with an alpha channel (premultiplied, as wayland expects), until
+    /// we are given a new image. Note they don't have what we drew to the opaque ones
+    pub fn set_alpha(&self, alpha: bool) {
+        self.lock_inner_mut().alpha = alpha;
+    }
 
-        let surface = self.layer_surface.wl_surface();
-        surface.attach(Some(buf), 0, 0);
-        surface.damage_buffer(0, 0, width, height);
-        surface.commit();
+    /// Whether this output hasn't displayed anything since it showed up, other than the black
+    /// screen we start with
+    #[inline]
+    pub fn is_first_img(&self) -> bool {
+        !self.lock_inner().shown_img
+    }
+
+    pub fn draw(&self, buf: &Buffer) {
+        if *buf == Buffer::Skipped {
+            // so that the next frame damages everything, since this one never got to the screen
+            return self.mark_undrawn();
+        }
+        let (width, height) = self.lock_inner().buffer_dimensions();
+        self.present(buf, &[(0, 0, width as usize, height as usize)]);
+        self.undrawn.store(false, Ordering::Release);
+        FRAMES_DRAWN.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Like [`Wallpaper::draw`], but only damages what `damage` says changed, so that the
+    /// compositor doesn't recomposite the rest. Doesn't commit at all if nothing changed
+    pub fn draw_damaged(&self, buf: &Buffer, damage: &Damage) {
+        if *buf == Buffer::Skipped || self.undrawn.load(Ordering::Acquire) {
+            return self.draw(buf);
+        }
+        if damage.is_empty() {
+            return;
+        }
+        let rects = damage.rects();
+        if self.parallax.is_some() {
+            let (dim, canvas_dim) = {
+                let inner = self.lock_inner();
+                (inner.buffer_dimensions(), self.canvas_dimensions(&inner))
+            };
+            let shift = *self.parallax_shift.lock().unwrap();
+            let rects = parallax::shift_damage(
+                &rects,
+                (canvas_dim.0 as usize, canvas_dim.1 as usize),
+                (dim.0 as usize, dim.1 as usize),
+                shift,
+            );
+            // the frame only changed what the shift doesn't show
+            if rects.is_empty() {
+                return;
+            }
+            self.present(buf, &rects);
+        } else {
+            self.present(buf, &rects);
+        }
+        FRAMES_DRAWN.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Keeps us from drawing to `buf` again until the compositor is done reading from it, and
+    /// shows it, waiting for the frame callback that follows, if there is one
+    fn present(&self, buf: &Buffer, damage: &[(usize, usize, usize, usize)]) {
+        let pools = self.pools.lock().unwrap();
+        pools.opaque.commit(buf);
+        pools.alpha.commit(buf);
+        drop(pools);
+        let mut requested = self.frame_requested.lock().unwrap();
+        if self.surface.present(buf, damage) {
+            *requested = Some(Instant::now());
+        }
+        drop(requested);
+        self.counters.frame_shown();
+        self.present_mirrors();
+    }
+
+    /// The compositor answered our frame callback
+    pub fn frame_done(&self) {
+        *self.frame_requested.lock().unwrap() = None;
+        self.frame_done.notify_all();
+    }
+
+    /// Blocks until the compositor answers our last frame callback, or until `deadline`. Returns
+    /// whether we should draw. Compositors don't answer while they can't show the surface, so
+    /// that is also how we stop drawing to outputs nobody sees
+    pub fn wait_frame(&self, deadline: Instant) -> bool {
+        let mut requested = self.frame_requested.lock().unwrap();
+        while let Some(at) = *requested {
+            if at.elapsed() > STALE_FRAME_CALLBACK {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            requested = self
+                .frame_done
+                .wait_timeout(requested, deadline - now)
+                .unwrap()
+                .0;
+        }
+        true
+    }
+
+    /// Marks that the canvas changed without us drawing it
+    pub fn mark_undrawn(&self) {
+        self.undrawn.store(true, Ordering::Release);
+    }
+
+    /// The width of the canvas, in pixels
+    pub fn buffer_width(&self) -> usize {
+        self.canvas_dimensions(&self.lock_inner()).0 as usize
     }
 
     pub fn resize(
@@ -246,37 +1196,530 @@ impl Wallpaper {
         height: Option<NonZeroI32>,
         scale_factor: Option<NonZeroI32>,
     ) {
-        if let Some(s) = scale_factor {
-            self.layer_surface.set_buffer_scale(s.get() as u32).unwrap();
+        let (mut inner, pools) = self.lock_mut();
+        // with a fractional scale, the viewport scales our buffers instead
+        if let Some(s) = scale_factor.filter(|_| inner.fractional_scale.is_none()) {
+            self.surface
+                .set_buffer_scale(buffer_scale(s, inner.compositor_scaling));
         }
-        let (mut inner, mut pool) = self.lock_mut();
         let width = width.unwrap_or(inner.width);
         let height = height.unwrap_or(inner.height);
         let scale_factor = scale_factor.unwrap_or(inner.scale_factor);
         if (width, height, scale_factor) == (inner.width, inner.height, inner.scale_factor) {
             return;
         }
+        inner.width = width;
+        inner.height = height;
+        inner.scale_factor = scale_factor;
+        self.reallocate(inner, pools);
+    }
+
+    /// Makes us draw at `scale`, in 120ths, and show it through the viewport. Does nothing if the
+    /// compositor doesn't support fractional scaling
+    pub fn set_fractional_scale(&self, scale: NonZeroU32) {
+        if !self.surface.has_viewport() {
+            return;
+        }
+        let (mut inner, pools) = self.lock_mut();
+        if inner.fractional_scale == Some(scale) {
+            return;
+        }
+        if inner.fractional_scale.is_none() {
+            self.surface.set_buffer_scale(1);
+        }
+        inner.fractional_scale = Some(scale);
+        self.reallocate(inner, pools);
+    }
+
+    /// Makes us draw with the output's new transform
+    pub fn set_transform(&self, transform: wl_output::Transform) {
+        let (mut inner, pools) = self.lock_mut();
+        if inner.transform == ipc_transform(transform) {
+            return;
+        }
+        self.surface.set_buffer_transform(transform);
+        inner.transform = ipc_transform(transform);
+        self.reallocate(inner, pools);
+    }
+
+    #[inline]
+    pub fn transform(&self) -> Transform {
+        self.lock_inner().transform
+    }
+
+    /// The scale the compositor prefers, in 120ths, if we draw at it
+    pub fn fractional_scale(&self) -> Option<NonZeroU32> {
+        self.lock_inner().fractional_scale
+    }
+
+    /// Adjusts what we display with `adjustments`, from the next time we draw it. Must come after
+    /// [`Self::set_img_info`], which forgets them
+    pub fn set_adjustments(&self, adjustments: &Adjustments) {
+        *self.adjust.lock().unwrap() = Adjust::new(adjustments);
+    }
+
+    /// Tints everything we draw from now on with `gains` (see [`Tint::new`]). What we display
+    /// stays the same, so the tint stays with the next images too
+    pub fn set_tint(&self, gains: [f32; 3]) {
+        *self.tint.lock().unwrap() = Tint::new(gains);
+    }
+
+    /// Draws `overlay` over everything we draw from now on, or stops drawing one with `None`. Like
+    /// the tint, it stays with the next images
+    pub fn set_overlay(&self, overlay: Option<Arc<Overlay>>) {
+        *self.overlay.lock().unwrap() = overlay;
+    }
+
+    /// Shifts what we draw away from the pointer, if we have a `--parallax`, and it moved far
+    /// enough. Returns whether we must draw again to show it: still images must, and animations
+    /// show it with their next frame. If we are waiting for a frame callback, we wait for it, see
+    /// [`Wallpaper::take_parallax_undrawn`]
+    pub fn move_parallax(&self) -> bool {
+        let (Some(_), Some((x, y))) = (self.parallax, self.cursor_position()) else {
+            return false;
+        };
+        let ((width, height), canvas_dim) = {
+            let inner = self.lock_inner();
+            (inner.buffer_dimensions(), self.canvas_dimensions(&inner))
+        };
+        // as far as the canvas reaches past our edges
+        let max = parallax::margin(
+            (canvas_dim.0 as usize, canvas_dim.1 as usize),
+            (width as usize, height as usize),
+        );
+        // `cursor_position` has y growing upwards, like transitions do
+        let shift = parallax::shift_for((x, height as f32 - y), (width as u32, height as u32), max);
+        let mut current = self.parallax_shift.lock().unwrap();
+        if *current == shift {
+            return false;
+        }
+        *current = shift;
+        drop(current);
+        if self.animation_frame().is_some() && !self.is_paused() {
+            return false;
+        }
+        if self.frame_requested.lock().unwrap().is_some() {
+            self.parallax_undrawn.store(true, Ordering::Release);
+            return false;
+        }
+        true
+    }
+
+    /// Whether the shift of `--parallax` changed while we waited for the frame callback that just
+    /// arrived, so that we must draw it now
+    pub fn take_parallax_undrawn(&self) -> bool {
+        self.parallax_undrawn.swap(false, Ordering::AcqRel)
+    }
+
+    /// What we displayed before our size changed, and the animation frame we were on, if it must
+    /// be displayed again. Only returns it once per change
+    pub fn take_redisplay(&self) -> Option<(BgImg, Remake, Option<usize>)> {
+        if !self.redisplay.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        self.lost.lock().unwrap().clone()
+    }
+
+    /// Whether [`Wallpaper::take_redisplay`] has something for us
+    pub fn needs_redisplay(&self) -> bool {
+        self.redisplay.load(Ordering::Acquire)
+    }
+
+    /// Makes us display whatever `source` draws, from the next time it draws, until we display
+    /// something of our own. Stops what we were playing
+    pub fn mirror(self: &Arc<Self>, source: &Arc<Self>) {
+        self.cancel_animations();
         self.inc_animation_id();
+        self.set_img_info(source.get_img_info());
+        *self.mirror_of.lock().unwrap() = Some(Arc::downgrade(source));
+        let mut mirrors = source.mirrors.lock().unwrap();
+        // we may have been mirroring it already
+        mirrors.retain(|(mirror, _)| !std::ptr::eq(mirror.as_ptr(), Arc::as_ptr(self)));
+        mirrors.push((Arc::downgrade(self), None));
+    }
 
-        // remove all buffers with the previous size
-        let mut frame = 0u32;
-        while pool
-            .remove(&(self.layer_surface.wl_surface().clone(), frame))
-            .is_some()
-        {
-            frame += 1;
+    /// The output we mirror, if we do
+    pub fn mirror_source(&self) -> Option<Arc<Self>> {
+        self.mirror_of.lock().unwrap().as_ref()?.upgrade()
+    }
+
+    fn is_mirroring(&self, source: &Self) -> bool {
+        self.mirror_of
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|of| std::ptr::eq(of.as_ptr(), source))
+    }
+
+    /// The outputs still mirroring us. Those that stopped are forgotten
+    fn live_mirrors(&self) -> Vec<Arc<Self>> {
+        let mut mirrors = self.mirrors.lock().unwrap();
+        mirrors.retain(|(mirror, _)| mirror.upgrade().is_some_and(|m| m.is_mirroring(self)));
+        mirrors
+            .iter()
+            .filter_map(|(mirror, _)| mirror.upgrade())
+            .collect()
+    }
+
+    /// Keeps a copy of `pixels`, what we just drew, for [`Self::copy_to_mirrors`], if anyone
+    /// mirrors us. Returns whether we did
+    fn keep_for_mirrors(&self, pixels: &[u8]) -> bool {
+        if self.mirrors.lock().unwrap().is_empty() {
+            return false;
         }
-        drop(pool);
+        let mut mirrored = self.mirrored.lock().unwrap();
+        mirrored.clear();
+        mirrored.extend_from_slice(pixels);
+        true
+    }
 
-        inner.width = width;
-        inner.height = height;
-        inner.scale_factor = scale_factor;
+    /// Copies what we last drew, of `geometry`, to the canvases of our mirrors, for
+    /// [`Self::present_mirrors`] to present. They draw with an alpha channel if we do. Since our
+    /// own output doesn't present anything while it is hidden, they are presented right away then
+    fn copy_to_mirrors(&self, geometry: mirror::Geometry, alpha: bool) {
+        let pixels = self.mirrored.lock().unwrap();
+        let mut mirrors = self.mirrors.lock().unwrap();
+        mirrors.retain_mut(|(target, buffer)| {
+            let Some(target) = target.upgrade().filter(|t| t.is_mirroring(self)) else {
+                return false;
+            };
+            target.set_alpha(alpha);
+            let to = {
+                let inner = target.lock_inner();
+                let (width, height) = target.canvas_dimensions(&inner);
+                ((width as u32, height as u32), inner.transform)
+            };
+            let (_, copied) =
+                target.canvas_change(|canvas| mirror::copy(&pixels, geometry, canvas, to));
+            *buffer = Some(copied);
+            true
+        });
+        drop((mirrors, pixels));
+        if self.is_hidden() {
+            self.present_mirrors();
+        }
+    }
+
+    /// Presents what we last copied to our mirrors, right after we presented it ourselves, or
+    /// right after we copied it, while our output is hidden
+    pub fn present_mirrors(&self) {
+        let copied: Vec<_> = self
+            .mirrors
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter_map(|(target, buffer)| Some((target.upgrade()?, buffer.take()?)))
+            .collect();
+        for (target, buffer) in copied {
+            if !target.is_hidden() {
+                target.draw(&buffer);
+            }
+        }
+    }
+
+    /// Copies what we display to our mirrors again, like when one starts mirroring us, or changes
+    /// size. Still images would otherwise only get to them once we display something else
+    pub fn redraw_mirrors(&self) {
+        let (_, buffer) = self.canvas_change(|_| ());
+        if !self.is_hidden() {
+            self.draw(&buffer);
+        }
+    }
 
-        self.layer_surface
+    /// Throws away our buffers and what they had, after our size changed. What they had was made
+    /// for the old size, so it can only be displayed again by remaking it for the new one
+    fn reallocate(
+        &self,
+        mut inner: RwLockWriteGuard<'_, WallpaperInner>,
+        mut pools: MutexGuard<'_, Pools>,
+    ) {
+        // if we changed size twice before displaying anything again, what we lost is still what
+        // we had the first time, not the black we were left with
+        // mirrors lose nothing of their own, they only need their source to draw for them again
+        let mirroring = self.mirror_source().is_some();
+        let mut lost = self.lost.lock().unwrap();
+        if lost.is_none() && inner.shown_img && !mirroring {
+            *lost = Some((
+                inner.img.clone(),
+                inner.remake.clone(),
+                self.animation_frame(),
+            ));
+        }
+        self.redisplay
+            .store(lost.is_some() || mirroring, Ordering::Release);
+        drop(lost);
+        self.inc_animation_id();
+        self.mark_undrawn();
+
+        // remove all buffers with the previous size
+        pools.clear();
+        drop(pools);
+
+        // it would otherwise keep pixels laid out for the old size
+        self.canvas.lock().unwrap().clear();
+
+        self.surface
             .set_size(inner.width.get() as u32, inner.height.get() as u32);
-        inner.img = BgImg::Color([0, 0, 0]);
+        if inner.fractional_scale.is_some() {
+            self.surface
+                .set_destination(inner.width.get(), inner.height.get());
+        }
+        if !mirroring {
+            inner.img = BgImg::Color([0, 0, 0]);
+        }
+        inner.alpha = false;
         drop(inner);
-        self.layer_surface.commit();
+        self.surface.commit();
         self.configured.store(false, Ordering::Release);
     }
 }
+
+/// The refresh rate of the current mode, rounded to the nearest Hz
+fn refresh_rate(modes: &[Mode]) -> Option<u32> {
+    modes
+        .iter()
+        .find(|mode| mode.current && mode.refresh_rate > 0)
+        .map(|mode| (mode.refresh_rate as u32 + 500) / 1000)
+}
+
+fn ipc_transform(transform: wl_output::Transform) -> Transform {
+    match transform {
+        wl_output::Transform::_90 => Transform::Rotated90,
+        wl_output::Transform::_180 => Transform::Rotated180,
+        wl_output::Transform::_270 => Transform::Rotated270,
+        wl_output::Transform::Flipped => Transform::Flipped,
+        wl_output::Transform::Flipped90 => Transform::Flipped90,
+        wl_output::Transform::Flipped180 => Transform::Flipped180,
+        wl_output::Transform::Flipped270 => Transform::Flipped270,
+        _ => Transform::Normal,
+    }
+}
+
+/// With compositor scaling, our buffers are at the logical size, so their scale is always 1
+fn buffer_scale(scale_factor: NonZeroI32, compositor_scaling: bool) -> i32 {
+    if compositor_scaling {
+        1
+    } else {
+        scale_factor.get()
+    }
+}
+
+fn buffer_dimensions(
+    width: NonZeroI32,
+    height: NonZeroI32,
+    scale_factor: NonZeroI32,
+    fractional_scale: Option<NonZeroU32>,
+    compositor_scaling: bool,
+) -> (i32, i32) {
+    match fractional_scale {
+        Some(scale) if !compositor_scaling => (
+            utils::ipc::fractional_scaled(width.get() as u32, scale.get()) as i32,
+            utils::ipc::fractional_scaled(height.get() as u32, scale.get()) as i32,
+        ),
+        _ => {
+            let scale = buffer_scale(scale_factor, compositor_scaling);
+            (width.get() * scale, height.get() * scale)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_should_give_up_by_hanging_up() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let expected = Expected {
+            request: 1,
+            queue_end: 0,
+            client: Some(Arc::new(ours)),
+            arrived: false,
+        };
+        assert!(!expected.client_gave_up());
+        drop(theirs);
+        assert!(expected.client_gave_up());
+    }
+
+    #[test]
+    fn queued_animations_should_outlive_their_clients() {
+        let (wallpaper, _) = crate::headless::Headless::wallpaper("HEADLESS-1", (4, 3));
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let ticket = wallpaper.queue_transition();
+        wallpaper.expect_animation(1, Some(Arc::new(ours)));
+        assert_eq!(wallpaper.animation_arrived(1), Some(ticket + 1));
+
+        // `swww img --queue` exits once it sent the animation, which then waits for its turn
+        drop(theirs);
+        assert!(wallpaper.expects_animation());
+        wallpaper.end_turn();
+        assert!(wallpaper.claim_animation(1).is_some());
+        assert!(!wallpaper.expects_animation());
+    }
+
+    /// Waits for the turn of `ticket` on another thread, and sends it once it came
+    fn wait_turn_in_thread(
+        wallpaper: &Arc<Wallpaper>,
+        ticket: usize,
+        turns: &std::sync::mpsc::Sender<usize>,
+    ) -> std::thread::JoinHandle<()> {
+        let (wallpaper, turns) = (Arc::clone(wallpaper), turns.clone());
+        std::thread::spawn(move || {
+            wallpaper.wait_turn(ticket);
+            turns.send(ticket).unwrap();
+        })
+    }
+
+    #[test]
+    fn queued_transitions_should_take_turns_in_order() {
+        let (wallpaper, _) = crate::headless::Headless::wallpaper("HEADLESS-1", (4, 3));
+        let (turns, turn) = std::sync::mpsc::channel();
+        let tickets: Vec<_> = (0..3).map(|_| wallpaper.queue_transition()).collect();
+        assert_eq!(tickets, [0, 1, 2]);
+        assert!(wallpaper.is_transitioning());
+
+        // the last ones start waiting first, and still go last
+        let waiting: Vec<_> = tickets
+            .iter()
+            .rev()
+            .map(|&ticket| wait_turn_in_thread(&wallpaper, ticket, &turns))
+            .collect();
+        for ticket in tickets {
+            assert_eq!(turn.recv_timeout(Duration::from_secs(5)), Ok(ticket));
+            // the next one waits for this one to end its turn
+            assert!(turn.recv_timeout(Duration::from_millis(50)).is_err());
+            wallpaper.end_turn();
+        }
+        waiting.into_iter().for_each(|t| t.join().unwrap());
+        assert!(!wallpaper.is_transitioning());
+        wallpaper.wait_queue(wallpaper.queue_end());
+    }
+
+    #[test]
+    fn turns_should_wait_for_the_transition_playing() {
+        let (wallpaper, _) = crate::headless::Headless::wallpaper("HEADLESS-1", (4, 3));
+        let (turns, turn) = std::sync::mpsc::channel();
+        let token = wallpaper.create_animation_token();
+        wallpaper.start_transition(&token);
+
+        let ticket = wallpaper.queue_transition();
+        let waiting = wait_turn_in_thread(&wallpaper, ticket, &turns);
+        assert!(turn.recv_timeout(Duration::from_millis(50)).is_err());
+        wallpaper.end_transition(&token);
+        assert_eq!(turn.recv_timeout(Duration::from_secs(5)), Ok(ticket));
+        waiting.join().unwrap();
+    }
+
+    #[test]
+    fn queued_transitions_should_wait_for_the_animations_before_them() {
+        let (wallpaper, _) = crate::headless::Headless::wallpaper("HEADLESS-1", (4, 3));
+        let (turns, turn) = std::sync::mpsc::channel();
+
+        // an image with an animation, and one after it
+        let first = wallpaper.queue_transition();
+        wallpaper.expect_animation(1, None);
+        let second = wallpaper.queue_transition();
+        assert_eq!(wallpaper.animation_arrived(1), Some(second));
+
+        let waiting = wait_turn_in_thread(&wallpaper, first, &turns);
+        assert_eq!(turn.recv_timeout(Duration::from_secs(5)), Ok(first));
+        waiting.join().unwrap();
+        wallpaper.end_turn();
+
+        let waiting = wait_turn_in_thread(&wallpaper, second, &turns);
+        assert!(turn.recv_timeout(Duration::from_millis(50)).is_err());
+        assert!(wallpaper.claim_animation(1).is_some());
+        assert_eq!(turn.recv_timeout(Duration::from_secs(5)), Ok(second));
+        waiting.join().unwrap();
+        wallpaper.end_turn();
+        assert!(!wallpaper.expects_animation());
+    }
+
+    #[test]
+    fn cancelled_animations_should_stop_holding_up_the_queue() {
+        let (wallpaper, _) = crate::headless::Headless::wallpaper("HEADLESS-1", (4, 3));
+        let (turns, turn) = std::sync::mpsc::channel();
+
+        wallpaper.expect_animation(1, None);
+        let ticket = wallpaper.queue_transition();
+        let waiting = wait_turn_in_thread(&wallpaper, ticket, &turns);
+        assert!(turn.recv_timeout(Duration::from_millis(50)).is_err());
+
+        // something replaced the image, so its animation can't come anymore
+        wallpaper.cancel_animations();
+        assert_eq!(turn.recv_timeout(Duration::from_secs(5)), Ok(ticket));
+        waiting.join().unwrap();
+        assert_eq!(wallpaper.animation_arrived(1), None);
+        assert!(wallpaper.claim_animation(1).is_none());
+    }
+
+    #[test]
+    fn mirrors_should_get_what_hidden_outputs_draw() {
+        let (source, source_frames) = crate::headless::Headless::wallpaper("HEADLESS-1", (4, 3));
+        let (mirror, mirror_frames) = crate::headless::Headless::wallpaper("HEADLESS-2", (2, 2));
+        mirror.mirror(&source);
+        source.set_powered_off(true);
+        assert!(!source.is_unseen());
+
+        let (_, buffer) = source.canvas_change(|canvas| canvas.fill(0xff));
+        assert!(source_frames.lock().unwrap().is_empty());
+        let mirrored = mirror_frames.lock().unwrap().last().cloned().unwrap();
+        assert!(mirrored
+            .chunks_exact(4)
+            .all(|pixel| pixel[..3] == [0xff; 3]));
+
+        mirror.set_powered_off(true);
+        assert!(source.is_unseen());
+        // what the mirror got before is presented once, not again with the source
+        source.set_powered_off(false);
+        source.draw(&buffer);
+        assert_eq!(source_frames.lock().unwrap().len(), 1);
+        assert_eq!(mirror_frames.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn compositor_scaling_should_draw_at_the_logical_size() {
+        let (width, height) = (1920.try_into().unwrap(), 1080.try_into().unwrap());
+        let scale = 2.try_into().unwrap();
+        let physical = buffer_dimensions(width, height, scale, None, false);
+        assert_eq!(physical, (3840, 2160));
+        assert_eq!(
+            buffer_dimensions(width, height, scale, None, true),
+            (physical.0 / 2, physical.1 / 2)
+        );
+        assert_eq!(buffer_scale(scale, true), 1);
+        assert_eq!(buffer_scale(scale, false), 2);
+    }
+
+    #[test]
+    fn fractional_scales_should_draw_at_the_exact_size() {
+        let (width, height) = (1707.try_into().unwrap(), 960.try_into().unwrap());
+        let scale = 2.try_into().unwrap();
+        // 1.5, in 120ths
+        let fractional = NonZeroU32::new(180);
+        assert_eq!(
+            buffer_dimensions(width, height, scale, fractional, false),
+            (2561, 1440)
+        );
+        assert_eq!(
+            buffer_dimensions(width, height, scale, fractional, true),
+            (1707, 960)
+        );
+    }
+
+    #[test]
+    fn max_fps_should_skip_frames_until_an_interval_passed() {
+        let (wallpaper, _) = crate::headless::Headless::wallpaper("HEADLESS-1", (4, 3));
+        let ms = Duration::from_millis;
+        assert!(!wallpaper.over_max_fps(ms(10)));
+        // at most one frame every 40ms
+        wallpaper.set_max_fps(Some(25));
+        let shown: Vec<_> = [10, 20, 30, 40, 50]
+            .into_iter()
+            .map(|delay| !wallpaper.over_max_fps(ms(delay)))
+            .collect();
+        assert_eq!(shown, [true, false, false, true, true]);
+        wallpaper.set_max_fps(None);
+        assert_eq!(wallpaper.max_fps(), None);
+    }
+}
@@ -0,0 +1,221 @@
+//! Displays images again when their files change, see `swww img --watch`.
+//!
+//! We watch the directory the image is in, rather than the image itself, since most programs
+//! that generate images write a new file and rename it over the old one, which replaces the file
+//! we would be watching. Like slideshows, we spawn the client to display the image again, with
+//! the same arguments it was first displayed with.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, error, warn};
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+};
+use utils::{
+    ipc::{ArchivedWatch, BgImg},
+    outputs::OutputPattern,
+};
+
+use crate::hotplug::Shown;
+
+/// How often we check whether the daemon should exit, in milliseconds
+const POLL_TIMEOUT: i32 = 1000;
+
+/// How long we wait after a change for the program writing the image to be done with it
+const SETTLE: Duration = Duration::from_millis(200);
+
+struct Watched {
+    /// Absolute, like the paths of the images we display
+    path: PathBuf,
+    dir: WatchDescriptor,
+    /// Where, and with which arguments, the client was run, so we can run it the same way
+    cwd: PathBuf,
+    args: Vec<String>,
+    /// The outputs it is on, or all of them, if empty
+    patterns: Vec<OutputPattern>,
+}
+
+/// Whether a watch on the outputs `watched` matches is on any of `names`. No names are every output
+fn overlaps<S: AsRef<str>>(watched: &[OutputPattern], names: &[S]) -> bool {
+    watched.is_empty()
+        || names.is_empty()
+        || names
+            .iter()
+            .any(|name| watched.iter().any(|p| p.matches(name.as_ref())))
+}
+
+/// Whether an event about the file called `name` is about the image at `path`
+fn is_file(path: &Path, name: Option<&OsStr>) -> bool {
+    name.is_some_and(|name| path.file_name() == Some(name))
+}
+
+#[derive(Default)]
+pub struct Watches {
+    /// Only created once something is watched
+    inotify: Option<Arc<Inotify>>,
+    watched: Arc<Mutex<Vec<Watched>>>,
+}
+
+impl Watches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts watching an image, replacing the watches on any of its outputs
+    pub fn add(&mut self, watch: &ArchivedWatch) -> Result<(), String> {
+        let path = PathBuf::from(watch.path.as_str());
+        let dir = path
+            .parent()
+            .ok_or_else(|| format!("{path:?} is not in a directory"))?;
+        let names: Vec<_> = watch.outputs.iter().map(|n| n.to_string()).collect();
+        let patterns = OutputPattern::parse_all(&names)?;
+        self.remove(|watched| overlaps(&watched.patterns, &names));
+
+        let inotify = match &self.inotify {
+            Some(inotify) => inotify,
+            None => self.inotify.insert(self.spawn()?),
+        };
+        let dir = inotify
+            .add_watch(
+                dir,
+                AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+            )
+            .map_err(|e| format!("failed to watch {dir:?}: {e}"))?;
+        debug!("watching {path:?}");
+        self.watched.lock().unwrap().push(Watched {
+            path,
+            dir,
+            cwd: PathBuf::from(watch.cwd.as_str()),
+            args: watch.args.iter().map(|a| a.to_string()).collect(),
+            patterns,
+        });
+        Ok(())
+    }
+
+    /// Stops watching the images of the outputs that display something else now
+    pub fn displayed(&mut self, imgs: &[(Shown, Vec<&str>)]) {
+        self.remove(|watched| {
+            imgs.iter().any(|((img, _), names)| {
+                !names.is_empty()
+                    && overlaps(&watched.patterns, names)
+                    && !matches!(img, BgImg::Img(path) if Path::new(path) == watched.path)
+            })
+        });
+    }
+
+    fn remove(&mut self, mut f: impl FnMut(&Watched) -> bool) {
+        let mut watched = self.watched.lock().unwrap();
+        let mut removed = Vec::new();
+        watched.retain(|w| {
+            let remove = f(w);
+            if remove {
+                debug!("stopped watching {:?}", w.path);
+                removed.push(w.dir);
+            }
+            !remove
+        });
+        // several images may be in the same directory
+        removed.retain(|dir| watched.iter().all(|w| w.dir != *dir));
+        removed.sort();
+        removed.dedup();
+        if let Some(inotify) = &self.inotify {
+            for dir in removed {
+                if let Err(e) = inotify.rm_watch(dir) {
+                    warn!("failed to stop watching a directory: {e}");
+                }
+            }
+        }
+    }
+
+    fn spawn(&self) -> Result<Arc<Inotify>, String> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .map_err(|e| format!("failed to initialize inotify: {e}"))?;
+        let inotify = Arc::new(inotify);
+        let thread_inotify = Arc::clone(&inotify);
+        let watched = Arc::clone(&self.watched);
+        std::thread::Builder::new()
+            .name("watch".to_string())
+            .stack_size(1 << 15)
+            .spawn(move || run(&thread_inotify, &watched))
+            .map_err(|e| format!("failed to spawn `watch` thread: {e}"))?;
+        Ok(inotify)
+    }
+}
+
+fn run(inotify: &Inotify, watched: &Mutex<Vec<Watched>>) {
+    while !crate::should_daemon_exit() {
+        let mut fds = [PollFd::new(inotify, PollFlags::POLLIN)];
+        match poll(&mut fds, POLL_TIMEOUT) {
+            Ok(0) => continue,
+            Ok(_) => (),
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => {
+                error!("stopped watching images, failed to poll inotify: {e}");
+                return;
+            }
+        }
+
+        // programs may write an image in several steps, so we wait for them to be done, and
+        // only display it again once
+        std::thread::sleep(SETTLE);
+        let mut events = Vec::new();
+        while let Ok(new) = inotify.read_events() {
+            events.extend(new);
+        }
+        let commands: Vec<_> = watched
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|w| {
+                events
+                    .iter()
+                    .any(|e| e.wd == w.dir && is_file(&w.path, e.name.as_deref()))
+            })
+            .map(|w| (w.path.clone(), w.cwd.clone(), w.args.clone()))
+            .collect();
+        for (path, cwd, args) in commands {
+            debug!("{path:?} changed, displaying it again");
+            match crate::auth::swww_command()
+                .args(&args)
+                .current_dir(&cwd)
+                .status()
+            {
+                Ok(status) if !status.success() => {
+                    error!("failed to display {path:?} again: {status}")
+                }
+                Ok(_) => (),
+                Err(e) => error!("failed to spawn child process: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_match_the_watched_file() {
+        let path = Path::new("/tmp/weather.png");
+        assert!(is_file(path, Some(OsStr::new("weather.png"))));
+        assert!(!is_file(path, Some(OsStr::new("weather.png.tmp"))));
+        assert!(!is_file(path, None));
+    }
+
+    #[test]
+    fn watches_on_the_same_outputs_should_overlap() {
+        let names = |names: &[&str]| OutputPattern::parse_all(names).unwrap();
+        assert!(overlaps(&names(&["DP-1", "DP-2"]), &["DP-2"]));
+        assert!(!overlaps(&names(&["DP-1"]), &["DP-2"]));
+        assert!(overlaps(&names(&["re:DP-[0-9]"]), &["DP-2"]));
+        // no names are every output
+        assert!(overlaps(&[], &["DP-2"]));
+        assert!(overlaps::<&str>(&names(&["DP-1"]), &[]));
+    }
+}
@@ -0,0 +1,534 @@
+//! Draws the wallpapers on the root window of X11 sessions, with the `x11` feature.
+//!
+//! When there is no wayland server to connect to, but there is an X server (`$DISPLAY`, without
+//! `$WAYLAND_DISPLAY` nor `$WAYLAND_SOCKET`), we set a pixmap the size of the X screen as the
+//! root window's background, and every monitor XRandR tells about gets a [`X11Surface`] drawing
+//! to its part of it, or the whole screen one without XRandR. We also point `_XROOTPMAP_ID` and
+//! `ESETROOT_PMAP_ID` at it, for the programs that paint the background themselves, like X11
+//! compositors and pseudo-transparent terminals.
+//!
+//! Everything else runs like it does on wayland: the daemon connects to a wayland server of its
+//! own (see [`crate::nullserver`]), with an output for every monitor, whose wallpapers draw with
+//! our surfaces, so that requests, animations and transitions all go through the same code, and
+//! only the frames end up elsewhere. The X server never tells us when it showed a frame, so
+//! animations keep to their own pace, and the monitors are those of when we started.
+//!
+//! We load `libX11` and `libXrandr` when we need them, like [`crate::gl`] does with EGL. Xlib
+//! isn't thread safe, and wallpapers draw from any thread, so every call goes through a lock.
+
+use std::{
+    ffi::{c_char, c_int, c_uint, c_ulong, c_void, CStr, OsString},
+    ptr::{null, null_mut},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use libloading::Library;
+use log::{debug, error};
+use wayland_client::protocol::{wl_output, wl_shm, wl_surface::WlSurface};
+
+use crate::{
+    dylib::functions,
+    placement::Placement,
+    surface::{Buffer, Pool, Surface},
+};
+
+type Ptr = *mut c_void;
+/// The id of a window, pixmap or atom
+type Xid = c_ulong;
+type ErrorHandler = unsafe extern "C" fn(Ptr, *mut ErrorEvent) -> c_int;
+
+functions!(Xlib {
+    XOpenDisplay: fn(*const c_char) -> Ptr;
+    XCloseDisplay: fn(Ptr) -> c_int;
+    XSetErrorHandler: fn(Option<ErrorHandler>) -> Option<ErrorHandler>;
+    XDefaultScreen: fn(Ptr) -> c_int;
+    XRootWindow: fn(Ptr, c_int) -> Xid;
+    XDisplayWidth: fn(Ptr, c_int) -> c_int;
+    XDisplayHeight: fn(Ptr, c_int) -> c_int;
+    XDefaultDepth: fn(Ptr, c_int) -> c_int;
+    XCreatePixmap: fn(Ptr, Xid, c_uint, c_uint, c_uint) -> Xid;
+    XFreePixmap: fn(Ptr, Xid) -> c_int;
+    XCreateGC: fn(Ptr, Xid, c_ulong, Ptr) -> Ptr;
+    XFreeGC: fn(Ptr, Ptr) -> c_int;
+    XFillRectangle: fn(Ptr, Xid, Ptr, c_int, c_int, c_uint, c_uint) -> c_int;
+    XInitImage: fn(*mut Image) -> c_int;
+    XPutImage: fn(Ptr, Xid, Ptr, *mut Image, c_int, c_int, c_int, c_int, c_uint, c_uint) -> c_int;
+    XSetWindowBackgroundPixmap: fn(Ptr, Xid, Xid) -> c_int;
+    XClearArea: fn(Ptr, Xid, c_int, c_int, c_uint, c_uint, c_int) -> c_int;
+    XInternAtom: fn(Ptr, *const c_char, c_int) -> Xid;
+    XGetAtomName: fn(Ptr, Xid) -> *mut c_char;
+    XChangeProperty: fn(Ptr, Xid, Xid, Xid, c_int, c_int, *const u8, c_int) -> c_int;
+    XDeleteProperty: fn(Ptr, Xid, Xid) -> c_int;
+    XFlush: fn(Ptr) -> c_int;
+    XFree: fn(Ptr) -> c_int;
+});
+
+functions!(Xrandr {
+    XRRGetMonitors: fn(Ptr, Xid, c_int, *mut c_int) -> *mut MonitorInfo;
+    XRRFreeMonitors: fn(*mut MonitorInfo);
+});
+
+const Z_PIXMAP: c_int = 2;
+const LSB_FIRST: c_int = 0;
+const XA_PIXMAP: Xid = 20;
+const PROP_MODE_REPLACE: c_int = 0;
+
+/// Xlib's `XImage`, which we fill in ourselves, over pixels of ours
+#[repr(C)]
+struct Image {
+    width: c_int,
+    height: c_int,
+    xoffset: c_int,
+    format: c_int,
+    data: *mut c_char,
+    byte_order: c_int,
+    bitmap_unit: c_int,
+    bitmap_bit_order: c_int,
+    bitmap_pad: c_int,
+    depth: c_int,
+    bytes_per_line: c_int,
+    bits_per_pixel: c_int,
+    red_mask: c_ulong,
+    green_mask: c_ulong,
+    blue_mask: c_ulong,
+    obdata: Ptr,
+    /// The functions `XInitImage` fills in
+    funcs: [Ptr; 6],
+}
+
+/// Xlib's `XErrorEvent`
+#[repr(C)]
+struct ErrorEvent {
+    kind: c_int,
+    display: Ptr,
+    resource_id: Xid,
+    serial: c_ulong,
+    error_code: u8,
+    request_code: u8,
+    minor_code: u8,
+}
+
+/// XRandR's `XRRMonitorInfo`
+#[repr(C)]
+struct MonitorInfo {
+    name: Xid,
+    primary: c_int,
+    automatic: c_int,
+    noutput: c_int,
+    x: c_int,
+    y: c_int,
+    width: c_int,
+    height: c_int,
+    mwidth: c_int,
+    mheight: c_int,
+    outputs: *mut Xid,
+}
+
+/// Whether we are in an X11 session, and not a wayland one, by the environment variables `var`
+/// reads
+pub fn is_session(var: impl Fn(&str) -> Option<OsString>) -> bool {
+    let set = |name| var(name).is_some_and(|value| !value.is_empty());
+    !set("WAYLAND_DISPLAY") && !set("WAYLAND_SOCKET") && set("DISPLAY")
+}
+
+/// Where a monitor is on the X screen, in pixels, and what XRandR calls it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Monitor {
+    pub name: String,
+    pub position: (i32, i32),
+    pub size: (i32, i32),
+}
+
+/// Our connection to the X server in `$DISPLAY`, and the pixmap of its root window
+pub struct Screen {
+    display: Mutex<Display>,
+    monitors: Vec<Monitor>,
+}
+
+struct Display {
+    xlib: Xlib,
+    ptr: Ptr,
+    root: Xid,
+    pixmap: Xid,
+    gc: Ptr,
+    depth: c_int,
+    /// `_XROOTPMAP_ID` and `ESETROOT_PMAP_ID`
+    atoms: [Xid; 2],
+    // the tables point into them, and XRandR hooks into the display until it is closed, so they
+    // must outlive it
+    _libs: (Library, Option<Library>),
+}
+
+// SAFETY: Xlib isn't thread safe, but we only ever call it with the display locked
+unsafe impl Send for Display {}
+
+impl Screen {
+    /// Connects to the X server, and sets a black pixmap as the root window's background
+    pub fn open() -> Result<Self, String> {
+        // SAFETY: these are the system's Xlib libraries, whose initializers are sound
+        let lib = unsafe { Library::new("libX11.so.6") }
+            .map_err(|e| format!("failed to load libX11: {e}"))?;
+        // SAFETY: the signatures are those of Xlib.h, and we keep the library with the table
+        let xlib = unsafe { Xlib::load(&lib) }.map_err(|e| format!("bad libX11: {e}"))?;
+
+        // SAFETY: every pointer we pass is either null, where Xlib allows it, or to live memory
+        unsafe {
+            // the default handler exits the process
+            (xlib.XSetErrorHandler)(Some(log_error));
+            let ptr = (xlib.XOpenDisplay)(null());
+            if ptr.is_null() {
+                return Err("failed to connect to the X server in $DISPLAY".to_string());
+            }
+            let screen = (xlib.XDefaultScreen)(ptr);
+            let root = (xlib.XRootWindow)(ptr, screen);
+            let width = (xlib.XDisplayWidth)(ptr, screen);
+            let height = (xlib.XDisplayHeight)(ptr, screen);
+            let depth = (xlib.XDefaultDepth)(ptr, screen);
+            if depth != 24 && depth != 32 {
+                (xlib.XCloseDisplay)(ptr);
+                return Err(format!(
+                    "X screens {depth} bits deep aren't supported, only 24 and 32 bits deep ones"
+                ));
+            }
+            let (monitors, xrandr) = match monitors(&xlib, ptr, root) {
+                Ok((monitors, xrandr)) if !monitors.is_empty() => (monitors, Some(xrandr)),
+                result => {
+                    if let Err(e) = &result {
+                        debug!("{e}, drawing on the whole X screen");
+                    }
+                    let monitor = Monitor {
+                        name: format!("screen-{screen}"),
+                        position: (0, 0),
+                        size: (width, height),
+                    };
+                    (vec![monitor], result.ok().map(|(_, xrandr)| xrandr))
+                }
+            };
+            debug!("X11 monitors: {monitors:?}");
+
+            let pixmap = (xlib.XCreatePixmap)(ptr, root, width as u32, height as u32, depth as u32);
+            let gc = (xlib.XCreateGC)(ptr, pixmap, 0, null_mut());
+            // what a new GC draws with is black
+            (xlib.XFillRectangle)(ptr, pixmap, gc, 0, 0, width as u32, height as u32);
+            (xlib.XSetWindowBackgroundPixmap)(ptr, root, pixmap);
+            let atoms = [&b"_XROOTPMAP_ID\0"[..], &b"ESETROOT_PMAP_ID\0"[..]]
+                .map(|name| (xlib.XInternAtom)(ptr, name.as_ptr().cast(), 0));
+            for atom in atoms {
+                let data = (&pixmap as *const Xid).cast();
+                (xlib.XChangeProperty)(ptr, root, atom, XA_PIXMAP, 32, PROP_MODE_REPLACE, data, 1);
+            }
+            // a width and height of 0 are the whole window's
+            (xlib.XClearArea)(ptr, root, 0, 0, 0, 0, 0);
+            (xlib.XFlush)(ptr);
+
+            let display = Display {
+                xlib,
+                ptr,
+                root,
+                pixmap,
+                gc,
+                depth,
+                atoms,
+                _libs: (lib, xrandr),
+            };
+            Ok(Self {
+                display: Mutex::new(display),
+                monitors,
+            })
+        }
+    }
+
+    pub fn monitors(&self) -> &[Monitor] {
+        &self.monitors
+    }
+
+    /// A surface drawing to the monitor called `name`
+    pub fn surface(self: &Arc<Self>, name: &str) -> Option<X11Surface> {
+        let monitor = self.monitors.iter().find(|m| m.name == name)?;
+        Some(X11Surface {
+            screen: Arc::clone(self),
+            monitor: monitor.clone(),
+            offset: Arc::new(Mutex::new((0, 0))),
+            pools: AtomicU32::new(0),
+        })
+    }
+
+    /// Draws `pixels`, `xrgb8888` ones with `stride` bytes per row, to the root window, at
+    /// `position`
+    fn put(&self, position: (i32, i32), (width, height): (i32, i32), stride: i32, pixels: &[u8]) {
+        let display = self.display.lock().unwrap();
+        let xlib = &display.xlib;
+        let mut image = Image {
+            width,
+            height,
+            xoffset: 0,
+            format: Z_PIXMAP,
+            // Xlib only reads from it
+            data: pixels.as_ptr() as *mut c_char,
+            byte_order: LSB_FIRST,
+            bitmap_unit: 32,
+            bitmap_bit_order: LSB_FIRST,
+            bitmap_pad: 32,
+            depth: display.depth,
+            bytes_per_line: stride,
+            bits_per_pixel: 32,
+            red_mask: 0xff0000,
+            green_mask: 0xff00,
+            blue_mask: 0xff,
+            obdata: null_mut(),
+            funcs: [null_mut(); 6],
+        };
+        // SAFETY: the image describes `pixels`, which are `stride * height` bytes, and the
+        // display is locked
+        unsafe {
+            if (xlib.XInitImage)(&mut image) == 0 {
+                error!("failed to make a {width}x{height} X11 image");
+                return;
+            }
+            let (x, y) = position;
+            let (w, h) = (width as u32, height as u32);
+            (xlib.XPutImage)(
+                display.ptr,
+                display.pixmap,
+                display.gc,
+                &mut image,
+                0,
+                0,
+                x,
+                y,
+                w,
+                h,
+            );
+            (xlib.XClearArea)(display.ptr, display.root, x, y, w, h, 0);
+            (xlib.XFlush)(display.ptr);
+        }
+    }
+}
+
+impl Drop for Display {
+    fn drop(&mut self) {
+        // SAFETY: these are our resources, which nothing uses after us. The root window keeps its
+        // background, but nobody else should take it for ours
+        unsafe {
+            for atom in self.atoms {
+                (self.xlib.XDeleteProperty)(self.ptr, self.root, atom);
+            }
+            (self.xlib.XFreeGC)(self.ptr, self.gc);
+            (self.xlib.XFreePixmap)(self.ptr, self.pixmap);
+            (self.xlib.XCloseDisplay)(self.ptr);
+        }
+    }
+}
+
+/// The active monitors of the screen of `root`, and the XRandR library, which must stay loaded
+/// while the display is open
+///
+/// # Safety
+///
+/// `display` must be open
+unsafe fn monitors(
+    xlib: &Xlib,
+    display: Ptr,
+    root: Xid,
+) -> Result<(Vec<Monitor>, Library), String> {
+    let lib =
+        Library::new("libXrandr.so.2").map_err(|e| format!("failed to load libXrandr: {e}"))?;
+    let xrandr = Xrandr::load(&lib).map_err(|e| format!("bad libXrandr: {e}"))?;
+    let mut count = 0;
+    let infos = (xrandr.XRRGetMonitors)(display, root, 1, &mut count);
+    if infos.is_null() {
+        return Err("XRandR doesn't tell about monitors".to_string());
+    }
+    let monitors = std::slice::from_raw_parts(infos, count.max(0) as usize)
+        .iter()
+        .filter(|info| info.width > 0 && info.height > 0)
+        .enumerate()
+        .map(|(i, info)| {
+            let name = (xlib.XGetAtomName)(display, info.name);
+            let name = if name.is_null() {
+                format!("monitor-{i}")
+            } else {
+                let owned = CStr::from_ptr(name).to_string_lossy().into_owned();
+                (xlib.XFree)(name.cast());
+                owned
+            };
+            Monitor {
+                name,
+                position: (info.x, info.y),
+                size: (info.width, info.height),
+            }
+        })
+        .collect();
+    (xrandr.XRRFreeMonitors)(infos);
+    Ok((monitors, lib))
+}
+
+unsafe extern "C" fn log_error(_: Ptr, event: *mut ErrorEvent) -> c_int {
+    let event = &*event;
+    error!(
+        "X11 error {} on request {}.{}",
+        event.error_code, event.request_code, event.minor_code
+    );
+    0
+}
+
+/// A monitor's part of the root window's pixmap
+pub struct X11Surface {
+    screen: Arc<Screen>,
+    monitor: Monitor,
+    /// Where on the monitor the wallpaper goes, see [`Surface::place`]
+    offset: Arc<Mutex<(i32, i32)>>,
+    /// How many pools we made, which buffers are committed to all of
+    pools: AtomicU32,
+}
+
+impl X11Surface {
+    /// The size of the monitor, in pixels, which X never scales
+    pub fn size(&self) -> (i32, i32) {
+        self.monitor.size
+    }
+}
+
+impl Surface for X11Surface {
+    fn is(&self, _surface: &WlSurface) -> bool {
+        false
+    }
+
+    fn place(&self, placement: &Placement) {
+        let (offset, _) = placement.region(self.monitor.size);
+        *self.offset.lock().unwrap() = offset;
+    }
+
+    fn set_size(&self, _width: u32, _height: u32) {}
+
+    fn set_buffer_scale(&self, _scale: i32) {}
+
+    fn set_buffer_transform(&self, _transform: wl_output::Transform) {}
+
+    fn has_viewport(&self) -> bool {
+        false
+    }
+
+    fn set_destination(&self, _width: i32, _height: i32) {}
+
+    fn commit(&self) {}
+
+    /// Our pools draw the frame when it is committed, right before this
+    fn present(&self, _buffer: &Buffer, _damage: &[(usize, usize, usize, usize)]) -> bool {
+        false
+    }
+
+    fn new_pool(&self) -> Box<dyn Pool> {
+        Box::new(X11Pool {
+            id: self.pools.fetch_add(1, Ordering::AcqRel),
+            screen: Arc::clone(&self.screen),
+            position: self.monitor.position,
+            offset: Arc::clone(&self.offset),
+            layout: None,
+            memory: Vec::new(),
+        })
+    }
+}
+
+/// The X server copies what we draw, so one buffer is all we need
+struct X11Pool {
+    id: u32,
+    screen: Arc<Screen>,
+    /// Of the monitor on the screen
+    position: (i32, i32),
+    offset: Arc<Mutex<(i32, i32)>>,
+    layout: Option<(i32, i32, i32)>,
+    memory: Vec<u8>,
+}
+
+impl Pool for X11Pool {
+    fn get(
+        &mut self,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: wl_shm::Format,
+    ) -> Result<(Buffer, &mut [u8]), String> {
+        // the root window has no alpha channel, so premultiplied pixels show over black
+        if !matches!(format, wl_shm::Format::Xrgb8888 | wl_shm::Format::Argb8888) {
+            return Err(format!("X11 can't show {format:?} buffers"));
+        }
+        let layout = (width, height, stride);
+        if self.layout != Some(layout) {
+            self.layout = Some(layout);
+            self.memory = vec![0; stride as usize * height as usize];
+        }
+        Ok((Buffer::X11(self.id), &mut self.memory))
+    }
+
+    fn commit(&self, buffer: &Buffer) {
+        // both of a wallpaper's pools get its buffers, but only ours are for us to draw
+        if *buffer != Buffer::X11(self.id) {
+            return;
+        }
+        if let Some((width, height, stride)) = self.layout {
+            let offset = *self.offset.lock().unwrap();
+            let position = (self.position.0 + offset.0, self.position.1 + offset.1);
+            self.screen
+                .put(position, (width, height), stride, &self.memory);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.layout = None;
+        self.memory = Vec::new();
+    }
+
+    fn mapped_bytes(&self) -> usize {
+        self.memory.len()
+    }
+
+    fn last_drawn(&mut self) -> Option<&[u8]> {
+        self.layout.map(|_| &self.memory[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_use_x11_without_wayland() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        assert!(is_session(env(&[("DISPLAY", ":0")])));
+        assert!(!is_session(env(&[
+            ("DISPLAY", ":0"),
+            ("WAYLAND_DISPLAY", "wayland-1")
+        ])));
+        assert!(!is_session(env(&[
+            ("DISPLAY", ":0"),
+            ("WAYLAND_SOCKET", "3")
+        ])));
+        // set but empty is unset
+        assert!(is_session(env(&[
+            ("DISPLAY", ":0"),
+            ("WAYLAND_DISPLAY", "")
+        ])));
+        assert!(!is_session(env(&[("DISPLAY", "")])));
+        assert!(!is_session(env(&[])));
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn structs_should_be_laid_out_like_xlibs() {
+        use std::mem::size_of;
+
+        assert_eq!(size_of::<Image>(), 136);
+        assert_eq!(size_of::<ErrorEvent>(), 40);
+        assert_eq!(size_of::<MonitorInfo>(), 56);
+    }
+}
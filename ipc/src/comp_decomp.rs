@@ -0,0 +1,1100 @@
+//! # Compression Strategy
+//!
+//! For every pixel, we drop the alpha part; I don't think anyone will use transparency for a
+//! background (nor if it even makes sense). Those who do can pack their frames with
+//! [`BitPack::pack_with_alpha`] instead, which keeps all 4 bytes of every pixel.
+//!
+//! For what's left, we store only the difference from the last frame to this one. We do that as
+//! follows:
+//! * First, we count how many pixels didn't change. We store that value as a u8.
+//!   Every time the u8 hits the max (i.e. 255, or 0xFF), we push in onto the vector
+//!   and restart the counting.
+//! * Once we find a pixel that has changed, we count, starting from that one, how many changed,
+//!   the same way we counted above (i.e. store as u8, every time it hits the max push and restart
+//!   the counting)
+//! * Then, we store all the new bytes.
+//! * Start from the top until we are done with the image
+//!
+//! Finally, we compress all of that with the [`CompressionBackend`] the caller picked, and store
+//! the [`checksum`] of the result along with it. Frames go through the socket and the cache, and a
+//! corrupt one would otherwise decompress to garbage, or not at all, so whoever receives or loads
+//! frames checks them once, with [`BitPack::is_intact`], and refuses those that don't match. We
+//! don't check them again every time we unpack them, since animations unpack every frame of every
+//! loop.
+//!
+
+use lzzzz::lz4f;
+use rkyv::{Archive, Deserialize, Serialize};
+
+lazy_static::lazy_static! {
+    static ref COMPRESSION_PREFERENCES: lz4f::Preferences = lz4f::PreferencesBuilder::new()
+            .block_size(lz4f::BlockSize::Max256KB)
+            .compression_level(9)
+            .build();
+}
+
+/// zstd's levels go up to 22, but the last ones need a lot of memory. Whoever picks zstd wants
+/// small frames, and pays for them with preprocessing time, so we pick the highest of the others
+const ZSTD_LEVEL: i32 = 19;
+
+/// How we compress the packed frames. Every [`BitPack`] remembers its own, so that we know how to
+/// decompress it
+#[derive(Archive, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[archive(check_bytes)]
+pub enum CompressionBackend {
+    /// Fast to compress and decompress
+    #[default]
+    Lz4,
+    /// Slower to compress, but the frames are a lot smaller
+    Zstd,
+    /// Keeps the packed frames as they are
+    None,
+}
+
+impl CompressionBackend {
+    fn compress(self, bytes: &[u8]) -> Result<Box<[u8]>, String> {
+        match self {
+            Self::Lz4 => {
+                let mut v = Vec::with_capacity(bytes.len() / 2);
+                lz4f::compress_to_vec(bytes, &mut v, &COMPRESSION_PREFERENCES)
+                    .map_err(|e| e.to_string())?;
+                Ok(v.into_boxed_slice())
+            }
+            Self::Zstd => zstd::bulk::compress(bytes, ZSTD_LEVEL)
+                .map(Vec::into_boxed_slice)
+                .map_err(|e| e.to_string()),
+            Self::None => Ok(bytes.into()),
+        }
+    }
+
+    /// Returns whether `inner` decompressed. The checksum makes that all but certain, but we would
+    /// rather skip a frame than panic in the middle of an animation
+    fn decompress(self, inner: &[u8], unpack: impl FnOnce(&[u8])) -> bool {
+        match self {
+            Self::Lz4 => {
+                let mut v = Vec::with_capacity(inner.len() * 3);
+                if lz4f::decompress_to_vec(inner, &mut v).is_err() {
+                    return false;
+                }
+                unpack(&v);
+            }
+            Self::Zstd => match zstd::stream::decode_all(inner) {
+                Ok(v) => unpack(&v),
+                Err(_) => return false,
+            },
+            Self::None => unpack(inner),
+        }
+        true
+    }
+}
+
+/// This calculates the difference between the current(cur) frame and the next(goal).
+/// The closure you pass is run at every difference. It dictates the update logic of the current
+/// frame. With that, you can control whether all different pixels changed are updated, or only the
+/// ones at a certain position. It is meant to be used primarily when writing transitions
+fn pack_bytes<const N: usize>(cur: &[u8], goal: &[u8], block_size: usize) -> Box<[u8]> {
+    if cur.len() != goal.len() {
+        unreachable!(
+            "Frames to pack have different sizes: {}, {}",
+            cur.len(),
+            goal.len()
+        );
+    }
+    let mut v = Vec::with_capacity(goal.len());
+
+    let (cur_pixels, goal_pixels) = (pixels::<N>(cur), pixels::<N>(goal));
+    let len = goal_pixels.len();
+    let mut to_add = Vec::with_capacity(100 * N + 33); // 100 pixels
+                                                       // always at the start of a block
+    let mut i = 0;
+    while i < len {
+        let equal_bytes = equal_prefix(&cur[i * N..], &goal[i * N..]);
+        if i * N + equal_bytes == goal.len() {
+            // unpacking always expects a trailing byte after the last pixel
+            if !v.is_empty() {
+                v.push(0);
+            }
+            return v.into_boxed_slice();
+        }
+        let equals = equal_bytes / (N * block_size);
+        i += equals * block_size;
+
+        let mut diffs = 0;
+        while i < len {
+            let end = (i + block_size).min(len);
+            if cur_pixels[i..end] == goal_pixels[i..end] {
+                break;
+            }
+            to_add.extend_from_slice(&goal[i * N..end * N]);
+            diffs += 1;
+            i = end;
+        }
+        // the equal block that ends the run is implied, unpacking skips it
+        i += block_size;
+
+        let j = v.len() + equals / 255;
+        v.resize(1 + v.len() + equals / 255 + diffs / 255, 255);
+        v[j] = (equals % 255) as u8;
+        v.push((diffs % 255) as u8);
+        v.append(&mut to_add);
+    }
+    v.push(0);
+    v.into_boxed_slice()
+}
+
+/// How many bytes at the start of `a` and `b` are the same. Most pixels don't change from one
+/// frame to the next, so this is where packing spends most of its time, and why we compare 16 or
+/// 32 bytes at a time when the CPU lets us
+fn equal_prefix(a: &[u8], b: &[u8]) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { equal_prefix_avx2(a, b) };
+        }
+        // every x86_64 cpu has sse2
+        return unsafe { equal_prefix_sse2(a, b) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // every aarch64 cpu has neon
+        return unsafe { equal_prefix_neon(a, b) };
+    }
+    #[allow(unreachable_code)]
+    equal_prefix_scalar(a, b)
+}
+
+fn equal_prefix_scalar(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn equal_prefix_sse2(a: &[u8], b: &[u8]) -> usize {
+    use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+    let len = a.len().min(b.len());
+    let mut i = 0;
+    while i + 16 <= len {
+        let x = _mm_loadu_si128(a.as_ptr().add(i).cast::<__m128i>());
+        let y = _mm_loadu_si128(b.as_ptr().add(i).cast::<__m128i>());
+        let equal = _mm_movemask_epi8(_mm_cmpeq_epi8(x, y)) as u32;
+        if equal != 0xFFFF {
+            return i + (!equal).trailing_zeros() as usize;
+        }
+        i += 16;
+    }
+    i + equal_prefix_scalar(&a[i..len], &b[i..len])
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn equal_prefix_avx2(a: &[u8], b: &[u8]) -> usize {
+    use std::arch::x86_64::{__m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_movemask_epi8};
+    let len = a.len().min(b.len());
+    let mut i = 0;
+    while i + 32 <= len {
+        let x = _mm256_loadu_si256(a.as_ptr().add(i).cast::<__m256i>());
+        let y = _mm256_loadu_si256(b.as_ptr().add(i).cast::<__m256i>());
+        let equal = _mm256_movemask_epi8(_mm256_cmpeq_epi8(x, y)) as u32;
+        if equal != u32::MAX {
+            return i + (!equal).trailing_zeros() as usize;
+        }
+        i += 32;
+    }
+    i + equal_prefix_sse2(&a[i..len], &b[i..len])
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn equal_prefix_neon(a: &[u8], b: &[u8]) -> usize {
+    use std::arch::aarch64::{vceqq_u8, vld1q_u8, vminvq_u8};
+    let len = a.len().min(b.len());
+    let mut i = 0;
+    while i + 16 <= len {
+        let equal = vceqq_u8(vld1q_u8(a.as_ptr().add(i)), vld1q_u8(b.as_ptr().add(i)));
+        if vminvq_u8(equal) != u8::MAX {
+            // neon has no movemask, so we find the exact byte the slow way
+            return i + equal_prefix_scalar(&a[i..i + 16], &b[i..i + 16]);
+        }
+        i += 16;
+    }
+    i + equal_prefix_scalar(&a[i..len], &b[i..len])
+}
+
+/// Packs every pixel of `goal`, as if all of them had changed
+fn pack_all_bytes<const N: usize>(goal: &[u8]) -> Box<[u8]> {
+    let diffs = pixels::<N>(goal).len();
+    let mut v = Vec::with_capacity(goal.len() + diffs / 255 + 3);
+    v.push(0);
+    v.resize(1 + diffs / 255, 255);
+    v.push((diffs % 255) as u8);
+    v.extend_from_slice(goal);
+    v.push(0);
+    v.into_boxed_slice()
+}
+
+/// `N` is how many bytes every pixel in `diff` has. We always copy 4 bytes at a time, which, when
+/// `N` is 3, is why `diff` must have a trailing byte after the last pixel. `changed` is called with
+/// the first pixel and the length of every run of pixels we copy
+fn unpack_bytes<const N: usize>(
+    buf: &mut [u8],
+    diff: &[u8],
+    block_size: usize,
+    mut changed: impl FnMut(usize, usize),
+) {
+    let buf_chunks = pixels_mut(buf);
+    let len = buf_chunks.len();
+    let mut diff_idx = 0;
+    let mut pix_idx = 0;
+    while diff_idx < diff.len() - 1 {
+        let mut equals = 0;
+        while diff[diff_idx] == u8::MAX {
+            equals += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        equals += diff[diff_idx] as usize;
+        pix_idx += equals * block_size;
+        diff_idx += 1;
+
+        let mut to_cpy = 0;
+        while diff[diff_idx] == u8::MAX {
+            to_cpy += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        to_cpy += diff[diff_idx] as usize;
+        diff_idx += 1;
+
+        // the last block may be cut short by the end of the frame
+        let to_cpy = (to_cpy * block_size).min(len.saturating_sub(pix_idx));
+        if to_cpy > 0 {
+            changed(pix_idx, to_cpy);
+        }
+        for _ in 0..to_cpy {
+            unsafe {
+                buf_chunks
+                    .get_unchecked_mut(pix_idx)
+                    .clone_from_slice(diff.get_unchecked(diff_idx..diff_idx + 4));
+            }
+            diff_idx += N;
+            pix_idx += 1;
+        }
+        pix_idx += block_size;
+    }
+}
+
+/// This struct represents the cached difference between the previous frame and the next
+#[derive(Archive, Serialize, Deserialize, Clone)]
+#[archive(check_bytes)]
+pub struct BitPack {
+    inner: Box<[u8]>,
+    /// This field will ensure we won't ever try to unpack the images on a buffer of the wrong size,
+    /// which ultimately is what allows us to use unsafe in the unpack_bytes function
+    expected_buf_size: usize,
+    /// Whether we kept the alpha channel, see [`BitPack::pack_with_alpha`]
+    alpha: bool,
+    backend: CompressionBackend,
+    /// How many pixels we compare at a time, see [`BitPack::pack`]
+    block_size: u8,
+    /// The [`checksum`] of `inner`
+    checksum: u64,
+}
+
+impl BitPack {
+    /// Compresses a frame of animation by getting the difference between the previous and the
+    /// current frame.
+    /// IMPORTANT: this will change `prev` into `cur`, that's why it needs to be 'mut'
+    ///
+    /// We compare `block_size` pixels at a time, storing all of them if any changed. Bigger blocks
+    /// need fewer headers in frames where most pixels change, like in noisy, video-like
+    /// animations, while 1 works best in mostly static ones. See [`BitPack::best_block_size`]
+    pub fn pack(
+        prev: &[u8],
+        cur: &[u8],
+        backend: CompressionBackend,
+        block_size: u8,
+    ) -> Result<Self, String> {
+        Self::compress(
+            pack_bytes::<3>(prev, cur, valid_block_size(block_size)?),
+            (cur.len() / 3) * 4,
+            false,
+            backend,
+            block_size,
+        )
+    }
+
+    /// Like [`BitPack::pack`], but for frames with an alpha channel, that is, with 4 bytes per
+    /// pixel, which are unpacked as they are. Without `prev`, every pixel is packed, so that we
+    /// can unpack the frame over anything
+    pub fn pack_with_alpha(
+        prev: Option<&[u8]>,
+        cur: &[u8],
+        backend: CompressionBackend,
+        block_size: u8,
+    ) -> Result<Self, String> {
+        let (bit_pack, block_size) = match prev {
+            Some(prev) => (
+                pack_bytes::<4>(prev, cur, valid_block_size(block_size)?),
+                block_size,
+            ),
+            None => (pack_all_bytes::<4>(cur), 1),
+        };
+        Self::compress(bit_pack, cur.len(), true, backend, block_size)
+    }
+
+    /// Of [`BLOCK_SIZES`], the one that makes the smallest frame going from `prev` to `cur`. We
+    /// compress the frame with every one of them, so this is only meant to be run once per
+    /// animation
+    pub fn best_block_size(
+        prev: &[u8],
+        cur: &[u8],
+        alpha: bool,
+        backend: CompressionBackend,
+    ) -> Result<u8, String> {
+        let mut best = (usize::MAX, 1);
+        for block_size in BLOCK_SIZES {
+            let bit_pack = if alpha {
+                Self::pack_with_alpha(Some(prev), cur, backend, block_size)?
+            } else {
+                Self::pack(prev, cur, backend, block_size)?
+            };
+            if bit_pack.inner.len() < best.0 {
+                best = (bit_pack.inner.len(), block_size);
+            }
+        }
+        Ok(best.1)
+    }
+
+    fn compress(
+        bit_pack: Box<[u8]>,
+        expected_buf_size: usize,
+        alpha: bool,
+        backend: CompressionBackend,
+        block_size: u8,
+    ) -> Result<Self, String> {
+        let inner = if bit_pack.is_empty() {
+            Box::new([])
+        } else {
+            backend.compress(&bit_pack)?
+        };
+        Ok(BitPack {
+            checksum: checksum(&inner),
+            inner,
+            expected_buf_size,
+            alpha,
+            backend,
+            block_size,
+        })
+    }
+
+    /// How many bytes the compressed frame takes in memory
+    pub fn compressed_len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Whether the frame still matches its checksum
+    pub fn is_intact(&self) -> bool {
+        checksum(&self.inner) == self.checksum
+    }
+
+    ///return whether unpacking was successful. Note it can only fail if `buf.len() !=
+    ///expected_buf_size`, in which case `buf` is left as it was, or if the frame didn't decompress.
+    ///The frame must have been checked with [`BitPack::is_intact`] when it arrived
+    #[must_use]
+    pub fn unpack(&self, buf: &mut [u8]) -> bool {
+        buf.len() == self.expected_buf_size
+            && decompress(
+                &self.inner,
+                buf,
+                self.alpha,
+                self.backend,
+                self.block_size,
+                |_, _| (),
+            )
+    }
+
+    /// Like [`BitPack::unpack`], but also returns which parts of `buf`, a frame `width` pixels
+    /// wide, changed. Returns `None` if unpacking failed
+    #[must_use]
+    pub fn unpack_with_damage(&self, buf: &mut [u8], width: usize) -> Option<Damage> {
+        if buf.len() != self.expected_buf_size {
+            return None;
+        }
+        let mut damage = Damage::new(width);
+        decompress(
+            &self.inner,
+            buf,
+            self.alpha,
+            self.backend,
+            self.block_size,
+            |start, len| damage.add(start, len),
+        )
+        .then_some(damage)
+    }
+}
+
+/// More rectangles than this cost the compositor more than they save it, so we merge them all
+/// into one
+const MAX_DAMAGE_RECTS: usize = 32;
+
+/// The parts of a frame that unpacking changed, as rectangles. Runs of changed pixels that touch
+/// vertically are merged into one rectangle, as wide as all of them together, so that ordinary
+/// changes, like a moving object, only need one or two
+#[derive(Debug, PartialEq)]
+pub struct Damage {
+    width: usize,
+    /// As `(x, y, width, height)`, in pixels, from top to bottom
+    rects: Vec<(usize, usize, usize, usize)>,
+}
+
+impl Damage {
+    fn new(width: usize) -> Self {
+        Self {
+            width: width.max(1),
+            rects: Vec::new(),
+        }
+    }
+
+    /// Adds the `len` pixels starting at pixel `start`. Runs must be added in order
+    fn add(&mut self, start: usize, len: usize) {
+        let (first_row, last_row) = (start / self.width, (start + len - 1) / self.width);
+        let (x, width) = if first_row == last_row {
+            (start % self.width, len)
+        } else {
+            (0, self.width)
+        };
+        if let Some(rect) = self.rects.last_mut() {
+            // it touches the rectangle above it
+            if first_row <= rect.1 + rect.3 {
+                let right = (rect.0 + rect.2).max(x + width);
+                rect.0 = rect.0.min(x);
+                rect.2 = right - rect.0;
+                rect.3 = last_row + 1 - rect.1;
+                return;
+            }
+        }
+        self.rects
+            .push((x, first_row, width, last_row + 1 - first_row));
+    }
+
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// The rectangles, as `(x, y, width, height)`, in pixels
+    pub fn rects(&self) -> Vec<(usize, usize, usize, usize)> {
+        if self.rects.len() <= MAX_DAMAGE_RECTS {
+            return self.rects.clone();
+        }
+        let (left, right) = self.rects.iter().fold((usize::MAX, 0), |(left, right), r| {
+            (left.min(r.0), right.max(r.0 + r.2))
+        });
+        let (top, last) = (&self.rects[0], &self.rects[self.rects.len() - 1]);
+        vec![(left, top.1, right - left, last.1 + last.3 - top.1)]
+    }
+}
+
+/// The block sizes [`BitPack::best_block_size`] picks from
+pub const BLOCK_SIZES: [u8; 4] = [1, 2, 4, 8];
+
+fn valid_block_size(block_size: u8) -> Result<usize, String> {
+    match block_size {
+        0 => Err("the block size must be at least 1 pixel".to_string()),
+        block_size => Ok(block_size as usize),
+    }
+}
+
+/// The XXH64 of `bytes`, to tell whether a frame arrived as it was sent, or was stored. It is fast
+/// enough to check the frames of a whole animation as they arrive
+pub fn checksum(bytes: &[u8]) -> u64 {
+    xxh64(bytes, 0)
+}
+
+/// XXH64, see <https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md>
+fn xxh64(bytes: &[u8], seed: u64) -> u64 {
+    const PRIME_1: u64 = 0x9e37_79b1_85eb_ca87;
+    const PRIME_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+    const PRIME_3: u64 = 0x1656_67b1_9e37_79f9;
+    const PRIME_4: u64 = 0x85eb_ca77_c2b2_ae63;
+    const PRIME_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+    let round = |acc: u64, lane: u64| {
+        acc.wrapping_add(lane.wrapping_mul(PRIME_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME_1)
+    };
+    let merge = |acc: u64, lane: u64| {
+        (acc ^ round(0, lane))
+            .wrapping_mul(PRIME_1)
+            .wrapping_add(PRIME_4)
+    };
+    let u64_at = |chunk: &[u8]| u64::from_le_bytes(chunk[..8].try_into().unwrap());
+
+    let stripes = bytes.chunks_exact(32);
+    let mut rest = stripes.remainder();
+    let mut hash = if bytes.len() >= 32 {
+        let mut acc = [
+            seed.wrapping_add(PRIME_1).wrapping_add(PRIME_2),
+            seed.wrapping_add(PRIME_2),
+            seed,
+            seed.wrapping_sub(PRIME_1),
+        ];
+        for stripe in stripes {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, u64_at(&stripe[i * 8..]));
+            }
+        }
+        let hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+        acc.into_iter().fold(hash, merge)
+    } else {
+        seed.wrapping_add(PRIME_5)
+    };
+    hash = hash.wrapping_add(bytes.len() as u64);
+
+    while rest.len() >= 8 {
+        hash = (hash ^ round(0, u64_at(rest)))
+            .rotate_left(27)
+            .wrapping_mul(PRIME_1)
+            .wrapping_add(PRIME_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let lane = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+        hash = (hash ^ lane.wrapping_mul(PRIME_1))
+            .rotate_left(23)
+            .wrapping_mul(PRIME_2)
+            .wrapping_add(PRIME_3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash = (hash ^ (byte as u64).wrapping_mul(PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME_3);
+    hash ^ (hash >> 32)
+}
+
+fn decompress(
+    inner: &[u8],
+    buf: &mut [u8],
+    alpha: bool,
+    backend: CompressionBackend,
+    block_size: u8,
+    changed: impl FnMut(usize, usize),
+) -> bool {
+    inner.is_empty()
+        || backend.decompress(inner, |v| {
+            if alpha {
+                unpack_bytes::<4>(buf, v, block_size as usize, changed);
+            } else {
+                unpack_bytes::<3>(buf, v, block_size as usize, changed);
+            }
+        })
+}
+
+impl ArchivedBitPack {
+    /// How many bytes the compressed frame takes in memory
+    pub fn compressed_len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Whether the frame must be shown on a surface with an alpha channel
+    pub fn has_alpha(&self) -> bool {
+        self.alpha
+    }
+
+    /// See [`BitPack::is_intact`]
+    pub fn is_intact(&self) -> bool {
+        checksum(&self.inner) == self.checksum
+    }
+
+    /// See [`BitPack::unpack`]
+    #[must_use]
+    pub fn unpack(&self, buf: &mut [u8]) -> bool {
+        let expected_buf_size: usize = self
+            .expected_buf_size
+            .deserialize(&mut rkyv::Infallible)
+            .unwrap();
+        if buf.len() != expected_buf_size {
+            return false;
+        }
+        let backend = self.backend.deserialize(&mut rkyv::Infallible).unwrap();
+        decompress(
+            &self.inner,
+            buf,
+            self.alpha,
+            backend,
+            self.block_size,
+            |_, _| (),
+        )
+    }
+
+    /// See [`BitPack::unpack_with_damage`]
+    #[must_use]
+    pub fn unpack_with_damage(&self, buf: &mut [u8], width: usize) -> Option<Damage> {
+        let expected_buf_size: usize = self
+            .expected_buf_size
+            .deserialize(&mut rkyv::Infallible)
+            .unwrap();
+        if buf.len() != expected_buf_size {
+            return None;
+        }
+        let backend = self.backend.deserialize(&mut rkyv::Infallible).unwrap();
+        let mut damage = Damage::new(width);
+        decompress(
+            &self.inner,
+            buf,
+            self.alpha,
+            backend,
+            self.block_size,
+            |start, len| damage.add(start, len),
+        )
+        .then_some(damage)
+    }
+}
+
+// The functions below were copy pasted and adapted from the bytemuck crate:
+
+#[inline]
+fn pixels<const N: usize>(img: &[u8]) -> &[[u8; N]] {
+    if img.len() % N != 0 {
+        unreachable!("Calling pixels with a wrongly formatted image");
+    }
+    unsafe { core::slice::from_raw_parts(img.as_ptr().cast::<[u8; N]>(), img.len() / N) }
+}
+
+#[inline]
+fn pixels_mut(img: &mut [u8]) -> &mut [[u8; 4]] {
+    if img.len() % 4 != 0 {
+        unreachable!("Calling pixels_mut with a wrongly formatted image");
+    }
+    unsafe { core::slice::from_raw_parts_mut(img.as_ptr() as *mut [u8; 4], img.len() / 4) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        equal_prefix, equal_prefix_scalar, pack_bytes, pixels, BitPack, CompressionBackend,
+    };
+    use rand::prelude::random;
+
+    /// How we packed frames before [`equal_prefix`], one pixel at a time
+    fn scalar_pack_bytes<const N: usize>(cur: &[u8], goal: &[u8]) -> Box<[u8]> {
+        let mut v = Vec::new();
+        let mut iter = pixels::<N>(cur).iter().zip(pixels::<N>(goal));
+        let mut to_add = Vec::new();
+        while let Some((mut cur, mut goal)) = iter.next() {
+            let mut equals = 0;
+            while cur == goal {
+                equals += 1;
+                match iter.next() {
+                    None => {
+                        if !v.is_empty() {
+                            v.push(0);
+                        }
+                        return v.into_boxed_slice();
+                    }
+                    Some((c, g)) => (cur, goal) = (c, g),
+                }
+            }
+
+            let mut diffs = 0;
+            while cur != goal {
+                to_add.extend_from_slice(goal);
+                diffs += 1;
+                match iter.next() {
+                    None => break,
+                    Some((c, g)) => (cur, goal) = (c, g),
+                }
+            }
+            let j = v.len() + equals / 255;
+            v.resize(1 + v.len() + equals / 255 + diffs / 255, 255);
+            v[j] = (equals % 255) as u8;
+            v.push((diffs % 255) as u8);
+            v.append(&mut to_add);
+        }
+        v.push(0);
+        v.into_boxed_slice()
+    }
+
+    /// A frame that only differs from `prev` in a few runs of pixels
+    fn changed_frame(prev: &[u8]) -> Vec<u8> {
+        let mut cur = prev.to_vec();
+        for _ in 0..random::<usize>() % 8 {
+            let start = random::<usize>() % cur.len();
+            let end = (start + random::<usize>() % 1000).min(cur.len());
+            cur[start..end].iter_mut().for_each(|b| *b = random());
+        }
+        cur
+    }
+
+    type EqualPrefix = fn(&[u8], &[u8]) -> usize;
+
+    #[test]
+    fn simd_comparisons_should_match_the_scalar_loop() {
+        let mut implementations: Vec<(&str, EqualPrefix)> = vec![("dispatch", equal_prefix)];
+        #[cfg(target_arch = "x86_64")]
+        {
+            implementations.push(("sse2", |a, b| unsafe { super::equal_prefix_sse2(a, b) }));
+            if is_x86_feature_detected!("avx2") {
+                implementations.push(("avx2", |a, b| unsafe { super::equal_prefix_avx2(a, b) }));
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        implementations.push(("neon", |a, b| unsafe { super::equal_prefix_neon(a, b) }));
+
+        let a: Vec<u8> = (0..200).map(|_| random()).collect();
+        for len in 0..a.len() {
+            for diff in 0..=len {
+                let mut b = a[..len].to_vec();
+                if let Some(byte) = b.get_mut(diff) {
+                    *byte = byte.wrapping_add(1);
+                }
+                for (name, equal_prefix) in &implementations {
+                    assert_eq!(
+                        equal_prefix(&a[..len], &b),
+                        equal_prefix_scalar(&a[..len], &b),
+                        "{name} failed with len {len} and a difference at {diff}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn simd_packing_should_match_the_scalar_packing() {
+        for _ in 0..50 {
+            let prev: Vec<u8> = (0..12000).map(|_| random()).collect();
+            let cur = changed_frame(&prev);
+            assert_eq!(
+                pack_bytes::<3>(&prev, &cur, 1),
+                scalar_pack_bytes::<3>(&prev, &cur)
+            );
+            assert_eq!(
+                pack_bytes::<4>(&prev, &cur, 1),
+                scalar_pack_bytes::<4>(&prev, &cur)
+            );
+        }
+        let same = vec![1; 3000];
+        assert_eq!(
+            pack_bytes::<3>(&same, &same, 1),
+            scalar_pack_bytes::<3>(&same, &same)
+        );
+    }
+
+    fn buf_from(slice: &[u8]) -> Vec<u8> {
+        let mut v = Vec::new();
+        for pix in slice.chunks_exact(3) {
+            v.extend_from_slice(pix);
+            v.push(255);
+        }
+        v
+    }
+
+    #[test]
+    //Use this when annoying problems show up
+    fn should_compress_and_decompress_to_same_info_small() {
+        let frame1 = [1, 2, 3, 4, 5, 6];
+        let frame2 = [1, 2, 3, 6, 5, 4];
+        let compressed = BitPack::pack(&frame1, &frame2, CompressionBackend::Lz4, 1).unwrap();
+
+        let mut buf = buf_from(&frame1);
+        assert!(compressed.unpack(&mut buf));
+        for i in 0..2 {
+            for j in 0..3 {
+                assert_eq!(
+                    frame2[i * 3 + j],
+                    buf[i * 4 + j],
+                    "\nframe2: {frame2:?}, buf: {buf:?}\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn should_keep_the_alpha_channel_when_asked_to() {
+        let mut original = Vec::with_capacity(20);
+        for _ in 0..20 {
+            let mut v: Vec<u8> = (0..4000).map(|_| random::<u8>()).collect();
+            // some pixels only change their alpha, and some don't change at all
+            v[..400].copy_from_slice(&[7; 400]);
+            v[403] = random::<u8>();
+            original.push(v);
+        }
+
+        let mut compressed = Vec::with_capacity(20);
+        compressed.push(
+            BitPack::pack_with_alpha(None, &original[0], CompressionBackend::Lz4, 1).unwrap(),
+        );
+        for i in 1..20 {
+            compressed.push(
+                BitPack::pack_with_alpha(
+                    Some(&original[i - 1]),
+                    &original[i],
+                    CompressionBackend::Lz4,
+                    1,
+                )
+                .unwrap(),
+            );
+        }
+
+        // the first frame must unpack over anything
+        let mut buf = vec![123; 4000];
+        for i in 0..20 {
+            assert!(compressed[i].unpack(&mut buf));
+            assert_eq!(buf, original[i], "Failed at frame: {i}");
+        }
+        assert!(!compressed[0].unpack(&mut [0; 3000]));
+    }
+
+    #[test]
+    fn should_know_which_parts_of_the_frame_changed() {
+        const WIDTH: usize = 10;
+        let prev = vec![0; WIDTH * 10 * 3];
+        let changed = |pixels: &[(usize, usize)]| {
+            let mut cur = prev.clone();
+            for (x, y) in pixels {
+                cur[(y * WIDTH + x) * 3] = 1;
+            }
+            let bit_pack = BitPack::pack(&prev, &cur, CompressionBackend::Lz4, 1).unwrap();
+            let mut buf = buf_from(&prev);
+            let damage = bit_pack.unpack_with_damage(&mut buf, WIDTH).unwrap();
+            assert!(buf.chunks_exact(4).map(|p| &p[..3]).eq(cur.chunks_exact(3)));
+            damage.rects()
+        };
+        assert_eq!(changed(&[]), []);
+        // touching runs make a single rectangle, as wide as all of them
+        assert_eq!(
+            changed(&[(2, 1), (3, 1), (2, 2), (8, 7)]),
+            [(2, 1, 2, 2), (8, 7, 1, 1)]
+        );
+        // a run that goes on to the next row damages both rows whole
+        assert_eq!(changed(&[(9, 4), (0, 5)]), [(0, 4, WIDTH, 2)]);
+
+        let mut buf = vec![0; WIDTH * 10 * 4];
+        assert!(BitPack::pack(&prev, &prev, CompressionBackend::Lz4, 1)
+            .unwrap()
+            .unpack_with_damage(&mut buf[4..], WIDTH)
+            .is_none());
+    }
+
+    #[test]
+    fn too_many_damaged_rectangles_should_become_one() {
+        let mut damage = super::Damage::new(4);
+        for row in (0..80).step_by(2) {
+            damage.add(row * 4 + 1, 1);
+        }
+        assert_eq!(damage.rects(), [(1, 0, 1, 79)]);
+        assert!(!damage.is_empty());
+    }
+
+    #[test]
+    fn every_block_size_should_decompress_to_the_same_frame() {
+        // not a multiple of any block size, so that the last block is cut short
+        let prev: Vec<u8> = (0..1001 * 3).map(|_| random()).collect();
+        for _ in 0..20 {
+            let cur = changed_frame(&prev);
+            for block_size in super::BLOCK_SIZES {
+                let backend = CompressionBackend::Lz4;
+                let bit_pack = BitPack::pack(&prev, &cur, backend, block_size).unwrap();
+                let mut buf = buf_from(&prev);
+                assert!(bit_pack.unpack(&mut buf));
+                let colors = buf.chunks_exact(4).map(|pixel| &pixel[..3]);
+                assert!(colors.eq(cur.chunks_exact(3)), "block size {block_size}");
+
+                let prev = buf_from(&prev);
+                let cur = buf_from(&cur);
+                let bit_pack =
+                    BitPack::pack_with_alpha(Some(&prev), &cur, backend, block_size).unwrap();
+                let mut buf = prev.clone();
+                assert!(bit_pack.unpack(&mut buf));
+                assert_eq!(buf, cur, "block size {block_size}, with alpha");
+            }
+        }
+        assert!(BitPack::pack(&prev, &prev, CompressionBackend::Lz4, 0).is_err());
+    }
+
+    #[test]
+    fn best_block_size_should_make_the_smallest_frame() {
+        let prev: Vec<u8> = (0..30000).map(|_| random()).collect();
+        let mut cur = prev.clone();
+        cur[1500] = cur[1500].wrapping_add(1);
+        let backend = CompressionBackend::None;
+        assert_eq!(
+            BitPack::best_block_size(&prev, &cur, false, backend).unwrap(),
+            1
+        );
+
+        let cur = changed_frame(&prev);
+        let best = BitPack::best_block_size(&prev, &cur, false, backend).unwrap();
+        let len = |block_size| {
+            BitPack::pack(&prev, &cur, backend, block_size)
+                .unwrap()
+                .inner
+                .len()
+        };
+        for block_size in super::BLOCK_SIZES {
+            assert!(len(best) <= len(block_size));
+        }
+    }
+
+    #[test]
+    fn every_backend_should_decompress_what_it_compressed() {
+        let prev: Vec<u8> = (0..30000).map(|i| (i % 251) as u8).collect();
+        let cur = changed_frame(&prev);
+        let backends = [
+            CompressionBackend::Lz4,
+            CompressionBackend::Zstd,
+            CompressionBackend::None,
+        ];
+        for backend in backends {
+            let bytes = rkyv::to_bytes::<_, 1024>(&BitPack::pack(&prev, &cur, backend, 1).unwrap())
+                .unwrap();
+            // the backend must come along with the frame through ipc
+            let archived = unsafe { rkyv::archived_root::<BitPack>(&bytes) };
+            let mut buf = buf_from(&prev);
+            assert!(archived.unpack(&mut buf), "{backend:?}");
+            let colors = buf.chunks_exact(4).map(|pixel| &pixel[..3]);
+            assert!(colors.eq(cur.chunks_exact(3)), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn checksums_should_match_the_reference_xxh64() {
+        assert_eq!(super::xxh64(b"", 0), 0xef46_db37_51d8_e999);
+        assert_eq!(super::xxh64(b"abc", 0), 0x44bc_2cf5_ad77_0999);
+        let spam = b"Nobody inspects the spammish repetition";
+        assert_eq!(super::xxh64(spam, 0), 0xfbce_a83c_8a37_8bf1);
+    }
+
+    #[test]
+    fn corrupt_frames_should_not_match_their_checksum() {
+        let prev: Vec<u8> = (0..30000).map(|i| (i % 251) as u8).collect();
+        let mut cur = prev.clone();
+        cur[1000..2000].iter_mut().for_each(|b| *b = random());
+        for backend in [CompressionBackend::Lz4, CompressionBackend::Zstd] {
+            let mut frame = BitPack::pack(&prev, &cur, backend, 1).unwrap();
+            assert!(frame.is_intact());
+            let mut inner = frame.inner.to_vec();
+            let middle = inner.len() / 2;
+            inner[middle] ^= 0x10;
+            frame.inner = inner.into_boxed_slice();
+            assert!(!frame.is_intact());
+
+            let bytes = rkyv::to_bytes::<_, 1024>(&frame).unwrap();
+            let archived = unsafe { rkyv::archived_root::<BitPack>(&bytes) };
+            assert!(!archived.is_intact(), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn should_compress_and_decompress_to_same_info() {
+        for _ in 0..10 {
+            let mut original = Vec::with_capacity(20);
+            for _ in 0..20 {
+                let mut v = Vec::with_capacity(3000);
+                for _ in 0..3000 {
+                    v.push(random::<u8>());
+                }
+                original.push(v);
+            }
+
+            let mut compressed = Vec::with_capacity(20);
+            compressed.push(
+                BitPack::pack(
+                    original.last().unwrap(),
+                    &original[0],
+                    CompressionBackend::Lz4,
+                    1,
+                )
+                .unwrap(),
+            );
+            for i in 1..20 {
+                compressed.push(
+                    BitPack::pack(&original[i - 1], &original[i], CompressionBackend::Lz4, 1)
+                        .unwrap(),
+                );
+            }
+
+            let mut buf = buf_from(original.last().unwrap());
+            for i in 0..20 {
+                assert!(compressed[i].unpack(&mut buf));
+                let mut j = 0;
+                let mut l = 0;
+                while j < 3000 {
+                    for k in 0..3 {
+                        assert_eq!(
+                            buf[j + l + k],
+                            original[i][j + k],
+                            "Failed at index: {}",
+                            j + k
+                        );
+                    }
+                    j += 3;
+                    l += 1;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn should_compress_and_decompress_to_same_info_with_equal_data() {
+        for _ in 0..10 {
+            let mut original = Vec::with_capacity(20);
+            for _ in 0..20 {
+                let mut v = Vec::with_capacity(3000);
+                for _ in 0..2000 {
+                    v.push(random::<u8>());
+                }
+                for i in 0..1000 {
+                    v.push((i % 255) as u8);
+                }
+                original.push(v);
+            }
+
+            let mut compressed = Vec::with_capacity(20);
+            compressed.push(
+                BitPack::pack(
+                    original.last().unwrap(),
+                    &original[0],
+                    CompressionBackend::Lz4,
+                    1,
+                )
+                .unwrap(),
+            );
+            for i in 1..20 {
+                compressed.push(
+                    BitPack::pack(&original[i - 1], &original[i], CompressionBackend::Lz4, 1)
+                        .unwrap(),
+                );
+            }
+
+            let mut buf = buf_from(original.last().unwrap());
+            for i in 0..20 {
+                assert!(compressed[i].unpack(&mut buf));
+                let mut j = 0;
+                let mut l = 0;
+                while j < 3000 {
+                    for k in 0..3 {
+                        assert_eq!(
+                            buf[j + l + k],
+                            original[i][j + k],
+                            "Failed at index: {}",
+                            j + k
+                        );
+                    }
+                    j += 3;
+                    l += 1;
+                }
+            }
+        }
+    }
+}
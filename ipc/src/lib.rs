@@ -0,0 +1,1522 @@
+//! The protocol `swww` uses to talk to `swww-daemon`, for anyone who wants to write their own
+//! frontend in Rust.
+//!
+//! Every connection goes like this:
+//! 1. the client connects to the socket at [`get_socket_path`], and sends a [`Handshake`], with the
+//!    [`PROTOCOL_VERSION`] it speaks. The daemon sends back a [`HandshakeAnswer`], with its own: a
+//!    refusal if it wanted another token, or doesn't speak the client's version. [`connect`] does
+//!    all of this;
+//! 2. the client sends one [`Request`], and the daemon sends back one [`Answer`];
+//! 3. the daemon hangs up. The only exceptions are [`Request::Subscribe`], after which it keeps
+//!    sending events, as lines of JSON, and [`Request::Log`] with `follow`, after which it does
+//!    the same with its logs.
+//!
+//! Every message is serialized with `rkyv`, and sent after its length, as a native endian
+//! `usize`. [`read_socket`] reads a message of the other side, which [`Answer::receive`] (or
+//! [`Request::receive`]) then checks and reads without copying it.
+//!
+//! The archived messages change along with the types, so the daemon only goes past the handshake
+//! with clients whose [`PROTOCOL_VERSION`] it understands, see [`is_compatible`].
+
+use rkyv::{Archive, Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{BufWriter, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub mod comp_decomp;
+use comp_decomp::{BitPack, CompressionBackend};
+
+#[derive(Clone, PartialEq, Archive, Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone))]
+pub enum Coord {
+    Pixel(f32),
+    Percent(f32),
+}
+
+#[derive(Clone, PartialEq, Archive, Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone))]
+pub struct Position {
+    pub x: Coord,
+    pub y: Coord,
+    /// Start from the pointer instead, on the output it is on. The others, and that one too if the
+    /// daemon doesn't know where the pointer is, use `x` and `y`
+    pub follow_cursor: bool,
+}
+
+impl Position {
+    #[must_use]
+    pub fn new(x: Coord, y: Coord) -> Self {
+        Self {
+            x,
+            y,
+            follow_cursor: false,
+        }
+    }
+
+    #[must_use]
+    pub fn to_pixel(&self, dim: (u32, u32), invert_y: bool) -> (f32, f32) {
+        let x = match self.x {
+            Coord::Pixel(x) => x,
+            Coord::Percent(x) => x * dim.0 as f32,
+        };
+
+        let y = match self.y {
+            Coord::Pixel(y) => {
+                if invert_y {
+                    dim.1 as f32 - y
+                } else {
+                    y
+                }
+            }
+            Coord::Percent(y) => {
+                if invert_y {
+                    (1.0 - y) * dim.1 as f32
+                } else {
+                    y * dim.1 as f32
+                }
+            }
+        };
+
+        (x, y)
+    }
+
+    #[must_use]
+    pub fn to_percent(&self, dim: (u32, u32)) -> (f32, f32) {
+        let x = match self.x {
+            Coord::Pixel(x) => x / dim.0 as f32,
+            Coord::Percent(x) => x,
+        };
+
+        let y = match self.y {
+            Coord::Pixel(y) => y / dim.1 as f32,
+            Coord::Percent(y) => y,
+        };
+
+        (x, y)
+    }
+}
+
+impl ArchivedPosition {
+    #[must_use]
+    pub fn to_pixel(&self, dim: (u32, u32), invert_y: bool) -> (f32, f32) {
+        let x = match self.x {
+            ArchivedCoord::Pixel(x) => x,
+            ArchivedCoord::Percent(x) => x * dim.0 as f32,
+        };
+
+        let y = match self.y {
+            ArchivedCoord::Pixel(y) => {
+                if invert_y {
+                    dim.1 as f32 - y
+                } else {
+                    y
+                }
+            }
+            ArchivedCoord::Percent(y) => {
+                if invert_y {
+                    (1.0 - y) * dim.1 as f32
+                } else {
+                    y * dim.1 as f32
+                }
+            }
+        };
+
+        (x, y)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(PartialEq))]
+pub enum BgImg {
+    Color([u8; 3]),
+    Img(String),
+    Gradient(Gradient),
+    /// A GLSL fragment shader the daemon renders `fps` times a second, see [`Shader`]
+    Shader {
+        path: String,
+        fps: u16,
+    },
+}
+
+impl fmt::Display for BgImg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BgImg::Color(color) => {
+                write!(f, "color: {:02X}{:02X}{:02X}", color[0], color[1], color[2])
+            }
+            BgImg::Img(p) => write!(f, "image: {p}",),
+            BgImg::Gradient(gradient) => write!(f, "{gradient}"),
+            BgImg::Shader { path, fps } => write!(f, "shader: {path}, {fps} fps"),
+        }
+    }
+}
+
+/// A linear gradient, from `from` on one side of the output to `to` on the other
+#[derive(Debug, PartialEq, Clone, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(PartialEq))]
+pub struct Gradient {
+    pub from: [u8; 3],
+    pub to: [u8; 3],
+    /// Which way it goes, in degrees, counter-clockwise: 0 goes from left to right, and 90 from
+    /// bottom to top
+    pub angle: f32,
+}
+
+impl Gradient {
+    /// The gradient as pixels of `dim`, in the same order as [`Img::img`] has them, on a buffer
+    /// drawn with `transform`
+    #[must_use]
+    pub fn render(&self, (width, height): (u32, u32), transform: Transform) -> Vec<u8> {
+        let angle = transform.to_buffer_angle(self.angle as f64).to_radians();
+        // the buffer's y grows downwards
+        let (dx, dy) = (angle.cos(), -angle.sin());
+        // so that the centers of the farthest pixels get exactly `from` and `to`
+        let extent = dx.abs() * (width as f64 - 1.0) / 2.0 + dy.abs() * (height as f64 - 1.0) / 2.0;
+        let (cx, cy) = ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let along = (x as f64 - cx) * dx + (y as f64 - cy) * dy;
+                let t = if extent > 0.0 {
+                    (along / extent + 1.0) / 2.0
+                } else {
+                    0.5
+                };
+                let [r, g, b] = std::array::from_fn(|i| {
+                    let (from, to) = (self.from[i] as f64, self.to[i] as f64);
+                    (from + (to - from) * t).round().clamp(0.0, 255.0) as u8
+                });
+                pixels.extend_from_slice(&[b, g, r]);
+            }
+        }
+        pixels
+    }
+}
+
+impl fmt::Display for Gradient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ([r1, g1, b1], [r2, g2, b2]) = (self.from, self.to);
+        write!(
+            f,
+            "gradient: {r1:02X}{g1:02X}{b1:02X} to {r2:02X}{g2:02X}{b2:02X}, {} degrees",
+            self.angle
+        )
+    }
+}
+
+impl ArchivedBgImg {
+    /// Deserialized the archived bg img
+    #[must_use]
+    pub fn de(&self) -> BgImg {
+        self.deserialize(&mut rkyv::Infallible).unwrap()
+    }
+}
+
+impl fmt::Display for ArchivedBgImg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchivedBgImg::Color(color) => {
+                write!(f, "color: {:02X}{:02X}{:02X}", color[0], color[1], color[2])
+            }
+            ArchivedBgImg::Img(p) => write!(f, "image: {p}",),
+            ArchivedBgImg::Gradient(gradient) => {
+                let gradient: Gradient = gradient.deserialize(&mut rkyv::Infallible).unwrap();
+                write!(f, "{gradient}")
+            }
+            ArchivedBgImg::Shader { path, fps } => write!(f, "shader: {path}, {fps} fps"),
+        }
+    }
+}
+
+#[derive(Clone, Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct BgInfo {
+    pub name: String,
+    pub dim: (u32, u32),
+    pub scale_factor: i32,
+    /// The scale the daemon draws the output at, in 120ths, when the compositor gave it a
+    /// fractional one. It overrides `scale_factor`
+    pub fractional_scale: Option<u32>,
+    /// How the output is rotated or flipped. The daemon's buffers are drawn with it already
+    /// applied, so images must be sent that way (see [`Transform::to_buffer`])
+    pub transform: Transform,
+    /// Where the output is in the compositor's logical space, according to xdg-output
+    pub position: (i32, i32),
+    pub img: BgImg,
+    /// Whether a transition is playing, or waiting to play, on the output
+    pub transitioning: bool,
+    /// The frame on the screen, if `img` is animated
+    pub animation_frame: Option<u32>,
+    /// When `img` was sent, in milliseconds since the unix epoch. `None` if nothing was sent yet
+    pub set_at_ms: Option<u64>,
+}
+
+impl BgInfo {
+    /// The size, in pixels, of the output as we see it. Images are made at this size, and then
+    /// transformed with `transform` to the size of the daemon's buffers
+    #[must_use]
+    pub fn real_dim(&self) -> (u32, u32) {
+        match self.fractional_scale {
+            Some(scale) => (
+                fractional_scaled(self.dim.0, scale),
+                fractional_scaled(self.dim.1, scale),
+            ),
+            None => (
+                self.dim.0 * self.scale_factor as u32,
+                self.dim.1 * self.scale_factor as u32,
+            ),
+        }
+    }
+}
+
+/// How an output is rotated or flipped, like `wl_output.transform`: the flipped ones are flipped
+/// around the vertical axis, and then rotated counter-clockwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone, Copy, PartialEq))]
+pub enum Transform {
+    #[default]
+    Normal,
+    Rotated90,
+    Rotated180,
+    Rotated270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+impl Transform {
+    /// Whether buffers drawn with this transform have the output's width as their height
+    #[must_use]
+    pub fn swaps_dimensions(self) -> bool {
+        matches!(
+            self,
+            Self::Rotated90 | Self::Rotated270 | Self::Flipped90 | Self::Flipped270
+        )
+    }
+
+    /// The size of the buffer for something `dim` in size on the output
+    #[must_use]
+    pub fn buffer_dim<T>(self, (width, height): (T, T)) -> (T, T) {
+        if self.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Where the point `(x, y)` of something `dim` in size, as seen on the output, is on a buffer
+    /// drawn with this transform. For both, y grows downwards
+    #[must_use]
+    pub fn to_buffer(self, (x, y): (f32, f32), (width, height): (f32, f32)) -> (f32, f32) {
+        match self {
+            Self::Normal => (x, y),
+            Self::Rotated90 => (y, width - x),
+            Self::Rotated180 => (width - x, height - y),
+            Self::Rotated270 => (height - y, x),
+            Self::Flipped => (width - x, y),
+            Self::Flipped90 => (y, x),
+            Self::Flipped180 => (x, height - y),
+            Self::Flipped270 => (height - y, width - x),
+        }
+    }
+
+    /// An angle on the output, in degrees, counter-clockwise, as it is on a buffer drawn with this
+    /// transform
+    #[must_use]
+    pub fn to_buffer_angle(self, degrees: f64) -> f64 {
+        let (flipped, quarter_turns) = match self {
+            Self::Normal => (false, 0),
+            Self::Rotated90 => (false, 1),
+            Self::Rotated180 => (false, 2),
+            Self::Rotated270 => (false, 3),
+            Self::Flipped => (true, 0),
+            Self::Flipped90 => (true, 1),
+            Self::Flipped180 => (true, 2),
+            Self::Flipped270 => (true, 3),
+        };
+        let degrees = if flipped { 180.0 - degrees } else { degrees };
+        (degrees + 90.0 * quarter_turns as f64).rem_euclid(360.0)
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Normal => "normal",
+            Self::Rotated90 => "90",
+            Self::Rotated180 => "180",
+            Self::Rotated270 => "270",
+            Self::Flipped => "flipped",
+            Self::Flipped90 => "flipped-90",
+            Self::Flipped180 => "flipped-180",
+            Self::Flipped270 => "flipped-270",
+        };
+        f.write_str(name)
+    }
+}
+
+impl ArchivedTransform {
+    #[must_use]
+    pub fn de(&self) -> Transform {
+        self.deserialize(&mut rkyv::Infallible).unwrap()
+    }
+}
+
+/// Scales a logical `len` by `scale`, in 120ths, rounding halfway away from zero, like
+/// `wp_fractional_scale_v1` says compositors do
+#[must_use]
+pub fn fractional_scaled(len: u32, scale: u32) -> u32 {
+    ((len as u64 * scale as u64 + 60) / 120) as u32
+}
+
+impl fmt::Display for ArchivedBgInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = match self.fractional_scale.as_ref() {
+            Some(scale) => format!("{}", *scale as f64 / 120.0),
+            None => self.scale_factor.to_string(),
+        };
+        write!(
+            f,
+            "{}: {}x{}, scale: {}, position: {},{}, currently displaying: {}",
+            self.name, self.dim.0, self.dim.1, scale, self.position.0, self.position.1, self.img
+        )
+    }
+}
+
+/// Statistics about the daemon itself, rather than about its outputs
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Stats {
+    /// `None` if the daemon is not running the garbage collector
+    pub gc_freed_bytes: Option<u64>,
+    /// The daemon's `--memory-limit`, in bytes
+    pub memory_limit: Option<u64>,
+    /// How many bytes the compressed frames of every running animation take
+    pub resident_bytes: u64,
+    pub outputs: Box<[OutputStats]>,
+    pub cpu_limits: CpuLimits,
+}
+
+/// How much of the CPU the daemon processes frames with, which clients processing images for it
+/// follow too, so that neither takes it over. See `utils::cpu`
+#[derive(Clone, Copy, Default, Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct CpuLimits {
+    /// At most how many threads to process frames with, or `None` for one per core
+    pub threads: Option<u32>,
+    /// The niceness of those threads
+    pub nice: Option<u8>,
+    /// Whether those threads only run when nothing else wants to
+    pub idle: bool,
+}
+
+/// What an output cost the daemon, since it appeared
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct OutputStats {
+    pub name: String,
+    /// Frames shown over the last second
+    pub fps: f32,
+    pub frames_shown: u64,
+    /// Animation frames the daemon didn't show, to catch up with the clock after falling behind
+    pub frames_skipped: u64,
+    /// How many bytes the compressed frames of the animation the output plays take. Outputs
+    /// playing the same animation share them
+    pub resident_bytes: u64,
+    pub animations: u64,
+    /// How many of `animations` the client found in its cache, see [`Playback::from_cache`]
+    pub cached_animations: u64,
+    /// At most how many frames per second the output shows. Clients drop the frames of
+    /// animations for outputs that would skip them anyway, see [`shared_max_fps`]
+    pub max_fps: Option<u32>,
+}
+
+/// At most how many frames per second to make for outputs with `max_fps` that share frames, or a
+/// transition: the lowest of them, so that none of them goes over its own. Outputs without one
+/// don't limit the others
+pub fn shared_max_fps(max_fps: impl IntoIterator<Item = Option<u32>>) -> Option<u32> {
+    max_fps.into_iter().flatten().min()
+}
+
+impl ArchivedOutputStats {
+    /// Which part of the animations came from the client's cache, or `None` if there were none
+    #[must_use]
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        (self.animations > 0).then(|| self.cached_animations as f64 / self.animations as f64)
+    }
+}
+
+impl fmt::Display for ArchivedOutputStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:.1} fps{}, {} frames shown, {} skipped, {:.2} MiB of frames, ",
+            self.name,
+            self.fps,
+            match self.max_fps.as_ref() {
+                Some(max) => format!(" (at most {max})"),
+                None => String::new(),
+            },
+            self.frames_shown,
+            self.frames_skipped,
+            self.resident_bytes as f64 / (1024.0 * 1024.0)
+        )?;
+        match self.cache_hit_rate() {
+            Some(rate) => write!(
+                f,
+                "{} of {} animations from the cache ({:.0}%)",
+                self.cached_animations,
+                self.animations,
+                rate * 100.0
+            ),
+            None => write!(f, "no animations"),
+        }
+    }
+}
+
+impl fmt::Display for ArchivedCpuLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.threads.as_ref() {
+            Some(threads) => write!(f, "cpu: {threads} threads")?,
+            None => write!(f, "cpu: one thread per core")?,
+        }
+        if let Some(nice) = self.nice.as_ref() {
+            write!(f, ", nice {nice}")?;
+        }
+        if self.idle {
+            write!(f, ", idle priority")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ArchivedStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.gc_freed_bytes.as_ref() {
+            Some(bytes) => write!(f, "gc: freed {:.2} MiB", *bytes as f64 / (1024.0 * 1024.0)),
+            None => write!(f, "gc: disabled (see swww-daemon --gc-interval)"),
+        }
+    }
+}
+
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone))]
+pub enum TransitionType {
+    Simple,
+    Fade,
+    Outer,
+    Wipe,
+    Grow,
+    Wave,
+    Slide,
+    ZoomOut,
+    Burn,
+    /// Like `Fade`, but an animation that was playing keeps playing while it fades out
+    Crossfade,
+}
+
+/// Edge of the screen the new image enters from, for the 'slide' transition
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone, Copy, PartialEq))]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone))]
+pub struct Transition {
+    pub transition_type: TransitionType,
+    pub duration: f32,
+    pub step: u8,
+    pub fps: u8,
+    /// Use the refresh rate of the outputs instead of `fps`, when they report one
+    pub fps_adaptive: bool,
+    pub angle: f64,
+    pub pos: Position,
+    pub bezier: (f32, f32, f32, f32),
+    pub wave: (f32, f32),
+    pub invert_y: bool,
+    pub direction: Direction,
+    pub bg_color: [u8; 3],
+    pub reversed: bool,
+    /// Blend the images in linear light rather than their sRGB bytes, see `swww img
+    /// --gamma-correct`. Only fades and crossfades blend
+    pub gamma_correct: bool,
+    /// How many times to play the transition, each taking an equal part of `duration`
+    pub loops: u8,
+    /// Whether to play a transition on outputs showing their first image. If not, they simply cut
+    /// to it
+    pub first_frame: bool,
+    /// What to play on those outputs, instead of `transition_type`
+    pub startup: StartupTransition,
+    /// Whether to play after whatever is playing on the outputs, or still on its way to them,
+    /// rather than replace it. See `swww img --queue`
+    pub queue: bool,
+    /// Cut straight to the new image on the outputs it is another scene for, rather than play
+    /// the transition. See `swww img --detect-scene-change`
+    pub scene_change_threshold: Option<f32>,
+}
+
+/// The parts of a [`Transition`] that change when it is played on an output's first image. The
+/// rest, like the duration, stays the same
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone))]
+pub struct StartupTransition {
+    pub transition_type: TransitionType,
+    pub step: u8,
+    pub angle: f64,
+    pub pos: Position,
+}
+
+/// A transition for the daemon to render off-screen, used by `swww transition-preview`. Both
+/// images have 3 bytes per pixel, like in [`Img`]
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct TransitionPreview {
+    pub transition: Transition,
+    pub dimensions: (u32, u32),
+    pub old_img: Box<[u8]>,
+    pub new_img: Box<[u8]>,
+}
+
+impl TransitionPreview {
+    pub fn serialize(&self) -> Result<Vec<u8>, String> {
+        match rkyv::to_bytes::<_, 1024>(self) {
+            Ok(bytes) => Ok(bytes.into_vec()),
+            Err(e) => Err(format!("Failed to serialize transition preview: {e}")),
+        }
+    }
+
+    /// Checks that `bytes` hold a valid transition preview before reading it, since they come from a
+    /// process we don't trust
+    pub fn receive(bytes: &[u8]) -> Result<&ArchivedTransitionPreview, Error> {
+        rkyv::check_archived_root::<Self>(bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::ProtocolMismatch,
+                format!("received an invalid transition preview: {e}"),
+            )
+        })
+    }
+}
+
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Clear {
+    pub color: [u8; 3],
+    /// Fill the outputs with this gradient, instead of `color`
+    pub gradient: Option<Gradient>,
+    /// How to get from what the outputs displayed to the fill. Without one, we replace it right
+    /// away
+    pub transition: Option<Transition>,
+    pub outputs: Box<[String]>,
+}
+
+impl ArchivedClear {
+    /// What the outputs display once this is done
+    #[must_use]
+    pub fn fill(&self) -> BgImg {
+        match self.gradient.as_ref() {
+            Some(gradient) => BgImg::Gradient(gradient.deserialize(&mut rkyv::Infallible).unwrap()),
+            None => BgImg::Color(self.color),
+        }
+    }
+}
+
+/// A GLSL fragment shader, like those of shadertoy, for the daemon to render on the outputs every
+/// frame. The source only has the `mainImage(out vec4 fragColor, in vec2 fragCoord)` function, and
+/// the uniforms `iResolution`, `iTime`, `iTimeDelta`, `iFrame` and `iMouse`, which the daemon
+/// declares
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Shader {
+    pub source: String,
+    /// Where the client read `source` from, for `swww query`, and to render it again on outputs
+    /// that change size or appear
+    pub path: String,
+    pub fps: u16,
+    pub outputs: Box<[String]>,
+}
+
+/// How the client made an [`Img`], so that it can be made again, with the same options, for an
+/// output that changed size or appeared, or when the daemon restores it from the cache
+#[derive(Debug, Clone, PartialEq, Eq, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct Remake {
+    /// Where the client ran, since the arguments may hold relative paths
+    pub cwd: String,
+    /// The arguments after `swww img`, without the image, and without the options that only make
+    /// sense once, like `--watch`. Whoever makes the image again passes the image and the output
+    pub args: Box<[String]>,
+}
+
+/// What the daemon does to the colors and sharpness of an image every time it draws it, see `swww
+/// img --blur` and `swww adjust`. The default leaves it as it is
+#[derive(Debug, Clone, Copy, PartialEq, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone, Copy, Debug))]
+pub struct Adjustments {
+    /// The standard deviation of a gaussian blur, in pixels of the output's buffers
+    pub blur: Option<f32>,
+    /// From -100 to 100 percent, like the rest
+    pub brightness: i8,
+    pub contrast: i8,
+    pub saturation: i8,
+    /// Applied after the others
+    pub tone: Option<Tone>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone, Copy, Debug))]
+pub enum Tone {
+    Grayscale,
+    Invert,
+    Sepia,
+}
+
+/// How the daemon dithers what it draws down to buffers with fewer bits per channel than the 8 it
+/// draws with, like `rgb565` ones, see `swww img --dither`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone, Copy, Debug))]
+pub enum Dither {
+    Bayer,
+    FloydSteinberg,
+}
+
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Img {
+    pub path: String,
+    pub img: Box<[u8]>,
+    /// Whether `img` is a JPEG of the image, rather than its raw pixels. See `utils::jpeg`
+    pub jpeg: bool,
+    /// How the image was made, so that the daemon has it made the same way once the output it is
+    /// on changes size, or appears again
+    pub remake: Remake,
+    /// How the daemon adjusts the image, and the animation that may follow it, when it draws them.
+    /// They go away with the image
+    pub adjustments: Adjustments,
+    /// Like the adjustments, but only for buffers that hold fewer bits per channel than the image
+    pub dither: Option<Dither>,
+    /// Only set when an [`Animation`] follows the image, to the `request` of its [`Playback`].
+    /// Until it arrives, the client keeps the socket it sent the image on open, and the daemon
+    /// closes it if something replaces the image in the meantime, to tell it to give up
+    pub request: Option<u64>,
+}
+
+#[derive(Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct Animation {
+    /// `animation[i]` goes from frame `i` to frame `i + 1`, and the last one back to the first
+    pub animation: Box<[(BitPack, Duration)]>,
+    /// Only for boomerang animations, which play forward and then backward: `reverse[i]` goes
+    /// from frame `i + 1` back to frame `i`. Empty otherwise
+    pub reverse: Box<[(BitPack, Duration)]>,
+    pub path: String,
+    pub dimensions: (u32, u32),
+    /// Only set when these frames were made with the options the cache assumes, and so can be
+    /// stored in it. See `utils::cache::animation_key`
+    pub cache_key: Option<u64>,
+    /// How every frame was compressed. We cache animations compressed differently apart
+    pub compression: CompressionBackend,
+}
+
+/// How the daemon should play an [`Animation`]. Unlike the animation, this is never cached
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Playback {
+    /// How many times faster than its frame delays say to play the animation
+    pub speed: f32,
+    /// How many times to play the animation, or `None`, to loop it forever
+    pub loops: Option<u32>,
+    /// What to show once the animation played `loops` times
+    pub at_end: AtEnd,
+    /// The frame to start playing from, like the one an output was on before its size changed
+    pub start_frame: u32,
+    /// The `request` of the [`Img`] showing the animation's first frame. The daemon only plays
+    /// the animation on outputs nothing else replaced that image on
+    pub request: u64,
+    /// Whether the animation only has its first frames, and the rest follow, in
+    /// [`Request::AnimationFrames`], so that it starts playing before they are all made. Then
+    /// the last of [`Animation::animation`] does not go back to the first frame
+    pub more_frames: bool,
+    /// Whether, on top of [`Self::more_frames`], the client sends the frames over again every
+    /// loop, instead of the daemon keeping them, see `swww img --stream`. The daemon drops the
+    /// frames once it played them, and only answers the [`Request::AnimationFrames`] once it
+    /// starts playing their frames, so that the client never gets far ahead of it
+    pub stream: bool,
+    /// Whether the client read the frames from its cache, instead of making them. Only for the
+    /// daemon's [`Stats`]
+    pub from_cache: bool,
+}
+
+/// The next frames of an animation the daemon may already be playing, see
+/// [`Playback::more_frames`]
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct AnimationFrames {
+    /// The `request` of the animation's [`Playback`]
+    pub request: u64,
+    /// Where the animation was in its [`AnimationRequest`]
+    pub animation: u32,
+    /// They follow the frames the daemon already has, like they would in [`Animation::animation`]
+    pub frames: Box<[(BitPack, Duration)]>,
+    /// Whether these are the last frames, the last of which goes back to the first frame. With
+    /// [`Playback::stream`], the last frames of a loop, with those of the next one following
+    pub last: bool,
+}
+
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone, Copy))]
+pub enum AtEnd {
+    /// Keep showing the last frame
+    HoldLast,
+    /// Go back to the first frame, and keep showing it
+    FirstFrame,
+    /// Clear the output to this color
+    Clear([u8; 3]),
+}
+
+/// Show the images of a directory one after another, see `swww slideshow`
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Slideshow {
+    /// Absolute, since the daemon doesn't run where the client does
+    pub dir: String,
+    /// How long to show every image, only counting the time the outputs are visible
+    pub interval_secs: u32,
+    pub shuffle: bool,
+    /// The `--transition-type` to show every image with, or `None`, for the default one
+    pub transition_type: Option<String>,
+    /// The rest of the options to show every image with
+    pub remake: Remake,
+    pub outputs: Box<[String]>,
+}
+
+/// Display an image again whenever its file changes, see `swww img --watch`
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Watch {
+    /// Absolute, like [`Img::path`]
+    pub path: String,
+    /// Where the client ran, and the arguments it got, after `swww`, to run it the same way
+    /// again
+    pub cwd: String,
+    pub args: Box<[String]>,
+    pub outputs: Box<[String]>,
+}
+
+/// An image the daemon draws over everything an output displays, see `swww overlay`
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct Overlay {
+    /// `[b, g, r, a]` pixels, with the alpha premultiplied, already transformed like the output's
+    /// buffers are
+    pub img: Box<[u8]>,
+    pub dim: (u32, u32),
+    /// Where it goes on the buffer, from `(0.0, 0.0)`, its top left corner, to `(1.0, 1.0)`, its
+    /// bottom right one
+    pub anchor: (f32, f32),
+}
+
+/// See `swww log`
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub struct LogRequest {
+    /// Which logs the daemon keeps from now on, like `info,animations=debug`. `None` keeps the
+    /// filter it has
+    pub filter: Option<String>,
+    /// Keep the connection open after answering, and send every log the filter lets through, as a
+    /// line of JSON
+    pub follow: bool,
+}
+
+/// The dominant colors of an output, by name, from most to least common
+pub type Palette = (String, Box<[[u8; 3]]>);
+
+pub type AnimationRequest = Box<[(Animation, Playback, Box<[String]>)]>;
+pub type OverlayRequest = Box<[(Option<Overlay>, Box<[String]>)]>;
+pub type ImageRequest = (Transition, Box<[(Img, Box<[String]>)]>);
+pub type ArchivedImageRequest = rkyv::Archived<ImageRequest>;
+
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub enum Request {
+    /// Change the [`Adjustments`] of the images these outputs (or all of them, if empty) display,
+    /// without sending them again
+    Adjust(Adjustments, Box<[String]>),
+    Animation(AnimationRequest),
+    /// The daemon answers with an error once nothing plays the animation anymore, so that the
+    /// client stops making its frames
+    AnimationFrames(AnimationFrames),
+    Clear(Clear),
+    Init,
+    /// Exit. The daemon answers once it destroyed its surfaces and deleted its socket, with an
+    /// error if it failed to delete it
+    Kill,
+    /// Change the speed of the animations on these outputs (or all of them, if empty), see
+    /// [`Playback::speed`]
+    PlaybackSpeed(f32, Box<[String]>),
+    /// Draw these overlays over everything their outputs display, until they are replaced. `None`
+    /// removes the overlays of its outputs (or of all of them, if empty)
+    Overlay(OverlayRequest),
+    /// Find up to this many dominant colors of what these outputs (or all of them, if empty)
+    /// display
+    Palette(u8, Box<[String]>),
+    /// Freeze the animations on these outputs (or all of them, if empty) on their current frame
+    Pause(Box<[String]>),
+    Query,
+    /// Read the configuration file again, see `utils::config`
+    ReloadConfig,
+    /// Continue the animations `Pause` froze
+    Resume(Box<[String]>),
+    /// Advance the paused animations on these outputs by this many frames
+    Step(u32, Box<[String]>),
+    /// Replace the slideshows on any of these outputs (or all of them, if empty) with this one
+    Slideshow(Slideshow),
+    Stats,
+    /// Stop the slideshows on any of these outputs (or all of them, if empty)
+    StopSlideshow(Box<[String]>),
+    /// Keep the connection open after answering, and send an event, as a line of JSON, whenever
+    /// something changes. See `swww subscribe`
+    Subscribe,
+    /// Multiply the red, green and blue of everything these outputs draw by these gains, without
+    /// changing what they display. `[1.0; 3]` removes the tint
+    Tint([f32; 3], Box<[String]>),
+    /// Replace the watches on any of these outputs (or all of them, if empty) with this one. It
+    /// stops once they display something else
+    Watch(Watch),
+    Img(ImageRequest),
+    /// Several image requests, possibly with different transitions, that must all start at the
+    /// same time
+    Batch(Box<[ImageRequest]>),
+    /// The daemon answers with [`Answer::Log`]
+    Log(LogRequest),
+    /// Make the outputs of the second field display whatever the output named by the first draws,
+    /// until they display something of their own, see `swww mirror`
+    Mirror(String, Box<[String]>),
+    /// Like [`Request::Query`], but the daemon answers with [`Answer::Json`], what `swww query
+    /// --format json` prints. With `true`, that has the daemon's statistics too
+    QueryJson(bool),
+    Shader(Shader),
+}
+
+impl Request {
+    pub fn send(&self, stream: &UnixStream) -> Result<(), String> {
+        let bytes = match rkyv::to_bytes::<_, 1024>(self) {
+            Ok(bytes) => bytes,
+            Err(e) => return Err(format!("Failed to serialize request: {e}")),
+        };
+        let mut writer = BufWriter::new(stream);
+        if let Err(e) = writer.write_all(&bytes.len().to_ne_bytes()) {
+            return Err(format!("failed to write serialized request's length: {e}"));
+        }
+        if let Err(e) = writer.write_all(&bytes) {
+            Err(format!("failed to write serialized request: {e}"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that `bytes` hold a valid request before reading it, since they come from a
+    /// process we don't trust
+    pub fn receive(bytes: &[u8]) -> Result<&ArchivedRequest, Error> {
+        rkyv::check_archived_root::<Self>(bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::ProtocolMismatch,
+                format!("received an invalid request: {e}"),
+            )
+        })
+    }
+}
+
+/// The version of the protocol, as `(major, minor)`. Majors change whenever a message the other
+/// side already knows does, since `rkyv` gives it a new layout, which the other side would read as
+/// garbage. Minors only change when we add messages, so a daemon also understands every client of
+/// the same major and an older minor
+pub const PROTOCOL_VERSION: (u16, u16) = (8, 0);
+
+/// The first bytes of both handshake messages, so that we can tell a handshake from before they had
+/// versions
+const HANDSHAKE_MAGIC: &[u8; 4] = b"swww";
+
+/// Formats a protocol version like we show it to users
+pub fn version_str((major, minor): (u16, u16)) -> String {
+    format!("v{major}.{minor}")
+}
+
+/// The first message of every connection. Unlike every other message, we write it by hand, so that
+/// it never changes: whatever versions the two sides are, they can always tell each other which
+/// [`PROTOCOL_VERSION`] they speak. If the daemon was started with `--ipc-auth-token`, `token`
+/// must match it, or the daemon answers with [`HandshakeAnswer::AuthFailed`] and hangs up
+#[derive(Debug, PartialEq)]
+pub struct Handshake {
+    pub version: (u16, u16),
+    pub token: String,
+}
+
+impl Handshake {
+    pub fn new(token: &str) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            token: token.to_string(),
+        }
+    }
+
+    pub fn send(&self, stream: &UnixStream) -> Result<(), String> {
+        let mut bytes = handshake_header(self.version);
+        bytes.extend_from_slice(self.token.as_bytes());
+        if bytes.len() > MAX_HANDSHAKE_LEN {
+            return Err("the auth token is too long".to_string());
+        }
+        write_message(stream, &bytes, "handshake")
+    }
+
+    pub fn receive(bytes: &[u8]) -> Result<Self, String> {
+        let (version, rest) = read_handshake_header(bytes)
+            .ok_or("the client's handshake has no version, so it must be older than v1.0")?;
+        let token = String::from_utf8(rest.to_vec())
+            .map_err(|_| "the client's auth token is not valid UTF-8".to_string())?;
+        Ok(Self { version, token })
+    }
+}
+
+/// How the daemon answers a [`Handshake`], along with the [`PROTOCOL_VERSION`] it speaks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeAnswer {
+    Ok,
+    AuthFailed,
+    /// The daemon doesn't understand the client's version
+    VersionMismatch,
+}
+
+impl HandshakeAnswer {
+    pub fn send(self, stream: &UnixStream) -> Result<(), String> {
+        let mut bytes = handshake_header(PROTOCOL_VERSION);
+        bytes.push(self as u8);
+        write_message(stream, &bytes, "handshake answer")
+    }
+
+    /// The answer, and the version of the daemon
+    pub fn receive(bytes: &[u8]) -> Result<(Self, (u16, u16)), String> {
+        let (version, rest) = read_handshake_header(bytes)
+            .ok_or("swww-daemon did not answer our handshake with a protocol version, so it must be older than v1.0; please restart it with a newer version")?;
+        let answer = match rest {
+            [0] => Self::Ok,
+            [1] => Self::AuthFailed,
+            [2] => Self::VersionMismatch,
+            _ => return Err("the daemon answered our handshake with garbage".to_string()),
+        };
+        Ok((answer, version))
+    }
+}
+
+/// Whether a daemon speaking the version `daemon` understands a client speaking `client`
+pub fn is_compatible(daemon: (u16, u16), client: (u16, u16)) -> bool {
+    daemon.0 == client.0 && daemon.1 >= client.1
+}
+
+fn handshake_header((major, minor): (u16, u16)) -> Vec<u8> {
+    let mut bytes = HANDSHAKE_MAGIC.to_vec();
+    bytes.extend_from_slice(&major.to_le_bytes());
+    bytes.extend_from_slice(&minor.to_le_bytes());
+    bytes
+}
+
+fn read_handshake_header(bytes: &[u8]) -> Option<((u16, u16), &[u8])> {
+    let rest = bytes.strip_prefix(HANDSHAKE_MAGIC)?;
+    if rest.len() < 4 {
+        return None;
+    }
+    let major = u16::from_le_bytes([rest[0], rest[1]]);
+    let minor = u16::from_le_bytes([rest[2], rest[3]]);
+    Some(((major, minor), &rest[4..]))
+}
+
+/// Writes `bytes` after their length, like every other message
+fn write_message(stream: &UnixStream, bytes: &[u8], what: &str) -> Result<(), String> {
+    let mut writer = BufWriter::new(stream);
+    if let Err(e) = writer.write_all(&bytes.len().to_ne_bytes()) {
+        return Err(format!("failed to write serialized {what}'s length: {e}"));
+    }
+    if let Err(e) = writer.write_all(bytes) {
+        Err(format!("failed to write serialized {what}: {e}"))
+    } else {
+        Ok(())
+    }
+}
+
+/// What kind of failure an [`Error`] is, so that scripts can tell them apart. Each has an exit
+/// code of its own, from `sysexits.h`
+#[derive(Archive, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Clone, Copy, PartialEq))]
+pub enum ErrorKind {
+    Other,
+    /// A value out of range, a pattern that doesn't parse, or a request that makes no sense in
+    /// the state the daemon is in
+    InvalidArgument,
+    FileNotFound,
+    UnsupportedFormat,
+    NoSuchOutput,
+    /// Something else holds what we need, like another daemon running
+    Busy,
+    /// The other side answered with something we did not expect
+    ProtocolMismatch,
+    /// We can't reach the daemon
+    Unavailable,
+    /// We aren't allowed to, like when the daemon refuses our auth token
+    PermissionDenied,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::InvalidArgument => 64,
+            Self::UnsupportedFormat => 65,
+            Self::FileNotFound => 66,
+            Self::NoSuchOutput => 68,
+            Self::Unavailable => 69,
+            Self::Busy => 75,
+            Self::ProtocolMismatch => 76,
+            Self::PermissionDenied => 77,
+        }
+    }
+
+    /// How we name it in JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::InvalidArgument => "invalid-argument",
+            Self::FileNotFound => "file-not-found",
+            Self::UnsupportedFormat => "unsupported-format",
+            Self::NoSuchOutput => "no-such-output",
+            Self::Busy => "busy",
+            Self::ProtocolMismatch => "protocol-mismatch",
+            Self::Unavailable => "unavailable",
+            Self::PermissionDenied => "permission-denied",
+        }
+    }
+}
+
+/// A failure, of either side. Most of our functions fail with plain strings, which become
+/// [`ErrorKind::Other`] errors, and the other way around, so `?` works across them
+#[derive(Archive, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[archive(check_bytes)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub msg: String,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, msg: impl Into<String>) -> Self {
+        Self {
+            kind,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Self::new(ErrorKind::Other, msg)
+    }
+}
+
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.msg
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl ArchivedError {
+    #[must_use]
+    pub fn de(&self) -> Error {
+        self.deserialize(&mut rkyv::Infallible).unwrap()
+    }
+}
+
+impl fmt::Display for ArchivedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+#[derive(Archive, Serialize)]
+#[archive(check_bytes)]
+pub enum Answer {
+    Ok,
+    Err(Error),
+    Info(Box<[BgInfo]>),
+    Init(bool),
+    Palette(Box<[Palette]>),
+    Stats(Stats),
+    /// The log filter in effect, after a [`Request::Log`]
+    Log(String),
+    /// After a [`Request::QueryJson`]
+    Json(String),
+}
+
+impl Answer {
+    pub fn send(&self, stream: &UnixStream) -> Result<(), String> {
+        let bytes = match rkyv::to_bytes::<_, 256>(self) {
+            Ok(bytes) => bytes,
+            Err(e) => return Err(format!("Failed to serialize answer: {e}")),
+        };
+        let mut writer = BufWriter::new(stream);
+        if let Err(e) = writer.write_all(&bytes.len().to_ne_bytes()) {
+            return Err(format!("failed to write serialized answer's length: {e}"));
+        }
+        if let Err(e) = writer.write_all(&bytes) {
+            Err(format!("Failed to write serialized answer: {e}"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that `bytes` hold a valid answer before reading it, since they come from a
+    /// process we don't trust
+    pub fn receive(bytes: &[u8]) -> Result<&ArchivedAnswer, Error> {
+        rkyv::check_archived_root::<Self>(bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::ProtocolMismatch,
+                format!("received an invalid answer: {e}"),
+            )
+        })
+    }
+}
+
+/// The longest [`Handshake`] we read, token included. Anyone can send us one, so we don't want
+/// them to make us allocate whatever length they like
+pub const MAX_HANDSHAKE_LEN: usize = 4096;
+
+/// Reads one message. We don't buffer our reads, so that whatever comes after it, like the events
+/// after the answer to [`Request::Subscribe`], is still in the socket
+pub fn read_socket(stream: &UnixStream) -> Result<Vec<u8>, String> {
+    read_at_most(stream, usize::MAX)
+}
+
+/// Like [`read_socket`], for a [`Handshake`] or its answer, which are never longer than
+/// [`MAX_HANDSHAKE_LEN`]
+pub fn read_handshake(stream: &UnixStream) -> Result<Vec<u8>, String> {
+    read_at_most(stream, MAX_HANDSHAKE_LEN)
+}
+
+fn read_at_most(stream: &UnixStream, max_len: usize) -> Result<Vec<u8>, String> {
+    let mut reader = stream;
+    let mut buf = vec![0; 8];
+
+    let mut tries = 0;
+    loop {
+        match reader.read_exact(&mut buf[0..std::mem::size_of::<usize>()]) {
+            Ok(()) => break,
+            Err(e) => {
+                if e.kind() == std::io::ErrorKind::WouldBlock && tries < 5 {
+                    std::thread::sleep(Duration::from_millis(1));
+                } else {
+                    return Err(format!("failed to read serialized length: {e}"));
+                }
+            }
+        }
+        tries += 1;
+    }
+    let len = usize::from_ne_bytes(buf[0..std::mem::size_of::<usize>()].try_into().unwrap());
+    if len > max_len {
+        return Err(format!(
+            "message is {len} bytes long, but we only read up to {max_len}"
+        ));
+    }
+    // the buffer only grows as the bytes actually arrive, rather than to whatever length we got
+    buf.clear();
+    buf.reserve(len.min(1 << 20));
+    match reader.take(len as u64).read_to_end(&mut buf) {
+        Ok(read) if read == len => Ok(buf),
+        Ok(read) => Err(format!(
+            "Failed to read request: got {read} of its {len} bytes"
+        )),
+        Err(e) => Err(format!("Failed to read request: {e}")),
+    }
+}
+
+/// The environment variable with the namespace of the daemon to talk to, see [`namespace`]
+pub const NAMESPACE_VAR: &str = "SWWW_NAMESPACE";
+
+/// The namespace of the daemon we are, or talk to, from `$SWWW_NAMESPACE`, or `None`, for the
+/// default one. Daemons in different namespaces have sockets and caches of their own, so that
+/// several of them can run at once, like one per wayland session
+#[must_use]
+pub fn namespace() -> Option<String> {
+    std::env::var(NAMESPACE_VAR)
+        .ok()
+        .filter(|namespace| !namespace.is_empty())
+}
+
+/// Checks that `namespace` can go in the names of our files
+pub fn check_namespace(namespace: &str) -> Result<(), String> {
+    if namespace.is_empty()
+        || !namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "namespace must only have letters, digits, '-' and '_', got: {namespace:?}"
+        ));
+    }
+    Ok(())
+}
+
+/// `name`, followed by the [`namespace`], if any, to tell the files of different daemons apart
+#[must_use]
+pub fn namespaced(name: &str) -> String {
+    match namespace() {
+        Some(namespace) => format!("{name}-{namespace}"),
+        None => name.to_string(),
+    }
+}
+
+#[must_use]
+pub fn get_socket_path() -> PathBuf {
+    let runtime_dir = if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+        dir
+    } else {
+        "/tmp/swww".to_string()
+    };
+    let runtime_dir = Path::new(&runtime_dir);
+    runtime_dir.join(format!("{}.socket", namespaced("swww")))
+}
+
+/// Connects to the daemon and does the [`Handshake`], with the token in `$SWWW_AUTH_TOKEN`, if
+/// any. We make sure the Stream is always set to blocking mode
+///
+/// * `tries` -  how make times to attempt the connection
+/// * `interval` - how long to wait between attempts, in milliseconds
+pub fn connect(tries: u8, interval: u64) -> Result<UnixStream, Error> {
+    let token = std::env::var("SWWW_AUTH_TOKEN").unwrap_or_default();
+    connect_with_token(tries, interval, &token)
+}
+
+/// Like [`connect`], but with a token of our own. An empty one is the same as none
+pub fn connect_with_token(tries: u8, interval: u64, token: &str) -> Result<UnixStream, Error> {
+    //Make sure we try at least once
+    let tries = if tries == 0 { 1 } else { tries };
+    let path = get_socket_path();
+    let mut error = None;
+    for _ in 0..tries {
+        match UnixStream::connect(&path) {
+            Ok(socket) => {
+                if let Err(e) = socket.set_nonblocking(false) {
+                    return Err(format!("Failed to set blocking connection: {e}").into());
+                }
+                #[cfg(debug_assertions)]
+                let timeout = Duration::from_secs(30); //Some operations take a while to respond in debug mode
+                #[cfg(not(debug_assertions))]
+                let timeout = Duration::from_secs(5);
+
+                if let Err(e) = socket.set_read_timeout(Some(timeout)) {
+                    return Err(format!("failed to set read timeout for socket: {e}").into());
+                }
+
+                handshake(&socket, token)?;
+                return Ok(socket);
+            }
+            Err(e) => error = Some(e),
+        }
+        std::thread::sleep(Duration::from_millis(interval));
+    }
+    let error = error.unwrap();
+    if error.kind() == std::io::ErrorKind::NotFound {
+        return Err(Error::new(
+            ErrorKind::Unavailable,
+            "Socket file not found. Are you sure swww-daemon is running?",
+        ));
+    }
+
+    Err(Error::new(
+        ErrorKind::Unavailable,
+        format!("Failed to connect to socket: {error}"),
+    ))
+}
+
+fn handshake(socket: &UnixStream, token: &str) -> Result<(), Error> {
+    Handshake::new(token).send(socket)?;
+    let bytes = read_handshake(socket)?;
+    let (answer, version) =
+        HandshakeAnswer::receive(&bytes).map_err(|e| Error::new(ErrorKind::ProtocolMismatch, e))?;
+    let mismatch = |advice: &str| {
+        Error::new(
+            ErrorKind::ProtocolMismatch,
+            format!(
+                "swww-daemon speaks protocol {}, but we speak {}; {advice}",
+                version_str(version),
+                version_str(PROTOCOL_VERSION)
+            ),
+        )
+    };
+    match answer {
+        HandshakeAnswer::Ok if is_compatible(version, PROTOCOL_VERSION) => Ok(()),
+        HandshakeAnswer::AuthFailed => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "swww-daemon refused our auth token. Is $SWWW_AUTH_TOKEN set correctly?",
+        )),
+        // an older daemon, or one that is already running while swww got upgraded
+        _ if version < PROTOCOL_VERSION => Err(mismatch("please restart it with a newer version")),
+        _ => Err(mismatch("please upgrade swww")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_frames_should_follow_the_lowest_max_fps() {
+        assert_eq!(shared_max_fps([Some(60), None, Some(30)]), Some(30));
+        assert_eq!(shared_max_fps([None, None]), None);
+        assert_eq!(shared_max_fps([]), None);
+    }
+
+    #[test]
+    fn namespaces_should_be_file_name_safe() {
+        assert!(check_namespace("nested-sway_2").is_ok());
+        assert!(check_namespace("").is_err());
+        assert!(check_namespace("../escape").is_err());
+        assert!(check_namespace("with space").is_err());
+    }
+
+    #[test]
+    fn should_read_one_message_at_a_time() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        Answer::Init(true).send(&sender).unwrap();
+        Answer::Err(Error::new(ErrorKind::NoSuchOutput, "oops"))
+            .send(&sender)
+            .unwrap();
+
+        let first = read_socket(&receiver).unwrap();
+        assert!(matches!(
+            Answer::receive(&first),
+            Ok(ArchivedAnswer::Init(true))
+        ));
+        let second = read_socket(&receiver).unwrap();
+        match Answer::receive(&second) {
+            Ok(ArchivedAnswer::Err(e)) => {
+                assert_eq!(e.de(), Error::new(ErrorKind::NoSuchOutput, "oops"))
+            }
+            _ => panic!("expected the second answer"),
+        }
+    }
+
+    #[test]
+    fn should_refuse_what_is_not_a_message() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let mut writer = &sender;
+        // a handshake claiming to be longer than any
+        writer
+            .write_all(&(MAX_HANDSHAKE_LEN + 1).to_ne_bytes())
+            .unwrap();
+        assert!(read_handshake(&receiver).is_err());
+
+        // a message shorter than its length says
+        writer.write_all(&usize::MAX.to_ne_bytes()).unwrap();
+        writer.write_all(b"short").unwrap();
+        drop(sender);
+        assert!(read_socket(&receiver).is_err());
+
+        let mut garbage = rkyv::AlignedVec::new();
+        garbage.extend_from_slice(&[0xff; 64]);
+        let Err(e) = Request::receive(&garbage) else {
+            panic!("garbage passed for a request");
+        };
+        assert_eq!(e.kind, ErrorKind::ProtocolMismatch);
+        assert!(Answer::receive(&garbage).is_err());
+    }
+
+    #[test]
+    fn fractional_scales_should_override_the_integer_one() {
+        let mut info = BgInfo {
+            name: "DP-1".to_string(),
+            dim: (1707, 960),
+            scale_factor: 2,
+            fractional_scale: None,
+            transform: Transform::Normal,
+            position: (0, 0),
+            img: BgImg::Color([0, 0, 0]),
+            transitioning: false,
+            animation_frame: None,
+            set_at_ms: None,
+        };
+        assert_eq!(info.real_dim(), (3414, 1920));
+        // 1.5, in 120ths
+        info.fractional_scale = Some(180);
+        assert_eq!(info.real_dim(), (2561, 1440));
+    }
+
+    #[test]
+    fn gradients_should_go_from_one_color_to_the_other() {
+        let gradient = Gradient {
+            from: [0, 0, 0],
+            to: [255, 0, 100],
+            angle: 0.0,
+        };
+        // left to right, as blue, green and red
+        let pixels = gradient.render((3, 1), Transform::Normal);
+        assert_eq!(pixels, [0, 0, 0, 50, 0, 128, 100, 0, 255]);
+        // bottom to top
+        let vertical = Gradient {
+            angle: 90.0,
+            ..gradient.clone()
+        };
+        let pixels = vertical.render((1, 2), Transform::Normal);
+        assert_eq!(pixels, [100, 0, 255, 0, 0, 0]);
+        // an output rotated a quarter turn shows the buffer's bottom to top as left to right
+        let pixels = gradient.render((1, 2), Transform::Rotated90);
+        assert_eq!(pixels, [100, 0, 255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn transforms_should_map_the_output_to_their_buffers() {
+        // the top right corner of a portrait output
+        let (corner, dim) = ((1080.0, 0.0), (1080.0, 1920.0));
+        assert_eq!(Transform::Normal.to_buffer(corner, dim), corner);
+        // rotating it counter-clockwise takes that corner to the top left
+        assert_eq!(Transform::Rotated90.to_buffer(corner, dim), (0.0, 0.0));
+        assert_eq!(
+            Transform::Rotated270.to_buffer(corner, dim),
+            (1920.0, 1080.0)
+        );
+        assert_eq!(Transform::Flipped.to_buffer(corner, dim), (0.0, 0.0));
+        assert_eq!(Transform::Flipped90.to_buffer(corner, dim), (0.0, 1080.0));
+        assert_eq!(Transform::Rotated90.buffer_dim((1080, 1920)), (1920, 1080));
+        assert_eq!(Transform::Flipped180.buffer_dim((1080, 1920)), (1080, 1920));
+
+        assert_eq!(Transform::Rotated90.to_buffer_angle(0.0), 90.0);
+        assert_eq!(Transform::Rotated270.to_buffer_angle(180.0), 90.0);
+        assert_eq!(Transform::Flipped.to_buffer_angle(45.0), 135.0);
+        assert_eq!(Transform::Flipped90.to_buffer_angle(0.0), 270.0);
+    }
+}
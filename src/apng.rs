@@ -0,0 +1,222 @@
+//! Decodes the frames of animated PNGs.
+//!
+//! `image`'s own APNG decoder refuses 16-bit images, so we decode them with the `png` crate
+//! directly, asking it to strip every channel down to 8 bits, and then compose the frames on a
+//! canvas ourselves.
+
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    DynamicImage, Frame, Frames, GrayAlphaImage, GrayImage, ImageError, ImageFormat, Pixel,
+    RgbImage, RgbaImage,
+};
+use png::{BlendOp, ColorType, DisposeOp, Transformations};
+
+use std::io::Read;
+
+pub fn into_frames<'a>(reader: impl Read + 'a) -> Result<Frames<'a>, String> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let reader = decoder
+        .read_info()
+        .map_err(|e| format!("failed to decode png during animation: {e}"))?;
+
+    let info = reader.info();
+    let remaining = info.animation_control().map_or(0, |actl| actl.num_frames);
+    // if the default image has no fcTL, it is not part of the animation. It's only a thumbnail
+    let has_thumbnail = info.frame_control().is_none();
+    let canvas = RgbaImage::new(info.width, info.height);
+    Ok(Frames::new(Box::new(ApngFrames {
+        buf: vec![0; reader.output_buffer_size()],
+        reader,
+        previous: canvas.clone(),
+        canvas,
+        dispose: None,
+        remaining,
+        has_thumbnail,
+    })))
+}
+
+struct ApngFrames<R: Read> {
+    reader: png::Reader<R>,
+    buf: Vec<u8>,
+    canvas: RgbaImage,
+    /// The canvas before the last frame was drawn, for `DisposeOp::Previous`
+    previous: RgbaImage,
+    /// How to dispose of the last frame's region, as `(op, x, y, width, height)`
+    dispose: Option<(DisposeOp, u32, u32, u32, u32)>,
+    remaining: u32,
+    has_thumbnail: bool,
+}
+
+impl<R: Read> ApngFrames<R> {
+    fn next_frame(&mut self) -> Result<Frame, ImageError> {
+        if self.has_thumbnail {
+            self.has_thumbnail = false;
+            self.reader
+                .next_frame(&mut self.buf)
+                .map_err(decoding_error)?;
+        }
+
+        match self.dispose {
+            Some((DisposeOp::Background, x, y, width, height)) => {
+                for y in y..y + height {
+                    for x in x..x + width {
+                        self.canvas.put_pixel(x, y, image::Rgba([0; 4]));
+                    }
+                }
+            }
+            Some((DisposeOp::Previous, ..)) => self.canvas.clone_from(&self.previous),
+            Some((DisposeOp::None, ..)) | None => (),
+        }
+
+        let output = self
+            .reader
+            .next_frame(&mut self.buf)
+            .map_err(decoding_error)?;
+        let fc = *self
+            .reader
+            .info()
+            .frame_control()
+            .ok_or_else(|| decoding_error("missing frame control chunk"))?;
+        let source = to_rgba(&self.buf, &output).ok_or_else(|| decoding_error("bad frame size"))?;
+        if fc.x_offset + fc.width > self.canvas.width()
+            || fc.y_offset + fc.height > self.canvas.height()
+        {
+            return Err(decoding_error("frame goes out of the image's bounds"));
+        }
+
+        if fc.dispose_op == DisposeOp::Previous {
+            self.previous.clone_from(&self.canvas);
+        }
+        for (x, y, pixel) in source.enumerate_pixels() {
+            let dst = self.canvas.get_pixel_mut(x + fc.x_offset, y + fc.y_offset);
+            match fc.blend_op {
+                BlendOp::Source => *dst = *pixel,
+                BlendOp::Over => dst.blend(pixel),
+            }
+        }
+        self.dispose = Some((
+            // the first frame has no canvas to go back to
+            match (self.dispose, fc.dispose_op) {
+                (None, DisposeOp::Previous) => DisposeOp::Background,
+                (_, op) => op,
+            },
+            fc.x_offset,
+            fc.y_offset,
+            fc.width,
+            fc.height,
+        ));
+
+        // PNG delays are fractions of seconds, and a 0 denominator means 100
+        let denom = match fc.delay_den {
+            0 => 100,
+            d => d.into(),
+        };
+        let delay = image::Delay::from_numer_denom_ms(u32::from(fc.delay_num) * 1000, denom);
+        Ok(Frame::from_parts(self.canvas.clone(), 0, 0, delay))
+    }
+}
+
+impl<R: Read> Iterator for ApngFrames<R> {
+    type Item = Result<Frame, ImageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        let frame = self.next_frame();
+        if frame.is_err() {
+            self.remaining = 0;
+        }
+        Some(frame)
+    }
+}
+
+/// The frames are 8-bit already, thanks to [`Transformations::normalize_to_color8`]
+fn to_rgba(buf: &[u8], output: &png::OutputInfo) -> Option<RgbaImage> {
+    let channels = match output.color_type {
+        ColorType::Grayscale => 1,
+        ColorType::GrayscaleAlpha => 2,
+        ColorType::Rgb => 3,
+        ColorType::Rgba => 4,
+        // palettes are expanded to RGB(A)
+        ColorType::Indexed => return None,
+    };
+    let row_len = output.width as usize * channels;
+    let data: Vec<u8> = buf
+        .chunks(output.line_size)
+        .take(output.height as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+    let (width, height) = (output.width, output.height);
+    Some(match output.color_type {
+        ColorType::Grayscale => {
+            DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, data)?).into_rgba8()
+        }
+        ColorType::GrayscaleAlpha => {
+            DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(width, height, data)?).into_rgba8()
+        }
+        ColorType::Rgb => {
+            DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, data)?).into_rgba8()
+        }
+        _ => RgbaImage::from_raw(width, height, data)?,
+    })
+}
+
+fn decoding_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        e,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x4 animation: a red frame, and then a green 2x2 square over it
+    fn apng(depth: png::BitDepth) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, 4, 4);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(depth);
+        encoder.set_animated(2, 0).unwrap();
+        encoder.set_frame_delay(1, 10).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+
+        let pixel = |rgba: [u8; 4]| match depth {
+            png::BitDepth::Sixteen => rgba.iter().flat_map(|c| [*c, *c]).collect(),
+            _ => rgba.to_vec(),
+        };
+        writer
+            .write_image_data(&pixel([255, 0, 0, 255]).repeat(16))
+            .unwrap();
+        writer.set_frame_dimension(2, 2).unwrap();
+        writer.set_frame_position(1, 1).unwrap();
+        writer.set_frame_delay(1, 0).unwrap();
+        writer
+            .write_image_data(&pixel([0, 255, 0, 255]).repeat(4))
+            .unwrap();
+        writer.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn should_compose_the_frames_of_8_and_16_bit_images() {
+        for depth in [png::BitDepth::Eight, png::BitDepth::Sixteen] {
+            let frames: Vec<_> = into_frames(apng(depth).as_slice())
+                .unwrap()
+                .map(|frame| frame.unwrap())
+                .collect();
+            assert_eq!(frames.len(), 2, "{depth:?}");
+            let delay = |i: usize| std::time::Duration::from(frames[i].delay());
+            assert_eq!(delay(0), std::time::Duration::from_millis(100));
+            assert_eq!(delay(1), std::time::Duration::from_millis(10));
+
+            let last = frames[1].buffer();
+            assert_eq!(last.get_pixel(0, 0).0, [255, 0, 0, 255], "{depth:?}");
+            assert_eq!(last.get_pixel(1, 1).0, [0, 255, 0, 255], "{depth:?}");
+            assert_eq!(last.get_pixel(2, 2).0, [0, 255, 0, 255], "{depth:?}");
+            assert_eq!(last.get_pixel(3, 3).0, [255, 0, 0, 255], "{depth:?}");
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Batch files, for changing what several outputs display at once with `swww img --batch-file`,
+//! or with `swww img --outputs DP-1:a.png,HDMI-A-1:b.png`
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::cli;
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchEntry {
+    pub output: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub transition: Option<String>,
+    #[serde(default)]
+    pub duration_ms: Option<f32>,
+}
+
+impl BatchEntry {
+    /// Makes the `swww img` options for this entry, starting from the ones in the command line
+    pub fn apply(&self, img: &cli::Img) -> Result<cli::Img, String> {
+        let mut img = img.clone();
+        img.path = self.path.clone();
+        img.outputs = self.output.clone();
+        img.batch_file = None;
+        if let Some(transition) = &self.transition {
+            apply_transition(&mut img, transition)?;
+        }
+        if let Some(duration_ms) = self.duration_ms {
+            img.transition_duration = duration_ms / 1000.0;
+        }
+        // the command line overrides the file
+        for (output, transition) in &img.output_transition.clone() {
+            if *output == self.output {
+                apply_transition(&mut img, transition)?;
+            }
+        }
+        Ok(img)
+    }
+}
+
+/// Parses a transition like `wipe:angle=30,duration=2000`, the way `swww transition-preview` does,
+/// into the options of a `swww img` command that has nothing else set
+fn parse_transition(spec: &str) -> Result<cli::Img, String> {
+    let args = crate::transition_spec_to_args(spec, Path::new("."))?;
+    cli::Img::try_parse_from(args).map_err(|e| format!("invalid transition {spec}: {e}"))
+}
+
+/// Sets the transition options `spec` gives in `img`, leaving the rest as they are
+pub fn apply_transition(img: &mut cli::Img, spec: &str) -> Result<(), String> {
+    let parsed = parse_transition(spec)?;
+    img.transition_type = parsed.transition_type;
+    let options = spec.split_once(':').map_or("", |(_, options)| options);
+    for option in options.split(',').filter(|o| !o.is_empty()) {
+        let key = option.split_once('=').map_or(option, |(key, _)| key);
+        match key {
+            "step" => img.transition_step = parsed.transition_step,
+            "duration" => img.transition_duration = parsed.transition_duration,
+            "fps" => img.transition_fps = parsed.transition_fps,
+            "angle" => img.transition_angle = parsed.transition_angle,
+            "pos" => img.transition_pos = parsed.transition_pos.clone(),
+            "bezier" => img.transition_bezier = parsed.transition_bezier,
+            "wave" => img.transition_wave = parsed.transition_wave,
+            "reverse" => img.transition_reverse = parsed.transition_reverse,
+            "loop" => img.transition_loop = parsed.transition_loop,
+            "direction" => img.transition_direction = parsed.transition_direction,
+            _ => return Err(format!("transition option '{key}' can't be set per output")),
+        }
+    }
+    Ok(())
+}
+
+/// Parses and validates a batch file. We do not check whether the outputs exist, since for that we
+/// need to ask the daemon
+pub fn parse(json: &str) -> Result<Vec<BatchEntry>, String> {
+    let entries: Vec<BatchEntry> =
+        serde_json::from_str(json).map_err(|e| format!("invalid batch file: {e}"))?;
+    if entries.is_empty() {
+        return Err("batch file has no entries".to_string());
+    }
+    validate(&entries)?;
+    Ok(entries)
+}
+
+/// Parses `--outputs` when it maps outputs to images, like `DP-1:a.png,HDMI-A-1:b.gif`. Returns
+/// `None` when it is a plain list of outputs instead
+pub fn parse_output_map(outputs: &str) -> Result<Option<Vec<BatchEntry>>, String> {
+    if !outputs.contains(':') {
+        return Ok(None);
+    }
+    let entries = outputs
+        .split(',')
+        .map(|pair| match pair.split_once(':') {
+            Some((output, path)) if !path.is_empty() => Ok(BatchEntry {
+                output: output.to_string(),
+                path: path.into(),
+                transition: None,
+                duration_ms: None,
+            }),
+            _ => Err(format!(
+                "{pair} is missing its image: either give every output one, like \
+                 `DP-1:a.png,HDMI-A-1:b.png`, or none of them"
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    validate(&entries)?;
+    Ok(Some(entries))
+}
+
+fn validate(entries: &[BatchEntry]) -> Result<(), String> {
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.output.is_empty() {
+            return Err(format!("batch entry {i} has an empty output"));
+        }
+        if entries[..i].iter().any(|e| e.output == entry.output) {
+            return Err(format!("output {} appears more than once", entry.output));
+        }
+        if let Some(transition) = &entry.transition {
+            // transitions apply the same way over any options, so we check them over the defaults
+            let mut defaults = parse_transition("none")?;
+            if let Err(e) = apply_transition(&mut defaults, transition) {
+                return Err(format!("batch entry for {}: {e}", entry.output));
+            }
+        }
+        if let Some(duration_ms) = entry.duration_ms {
+            if !duration_ms.is_finite() || duration_ms < 0.0 {
+                return Err(format!(
+                    "batch entry for {}: invalid duration_ms {duration_ms}",
+                    entry.output
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_OUTPUTS: &str = r#"[
+        {"output": "DP-1", "path": "a.png", "transition": "fade", "duration_ms": 800},
+        {"output": "HDMI-A-1", "path": "b.png"}
+    ]"#;
+
+    #[test]
+    fn entries_should_only_change_the_command_line_where_they_say() {
+        let entries = parse(TWO_OUTPUTS).unwrap();
+        let base =
+            cli::Img::try_parse_from(["img", "--batch-file", "batch.json", "--resize", "fit"])
+                .unwrap();
+        let first = entries[0].apply(&base).unwrap();
+        assert_eq!(first.path, PathBuf::from("a.png"));
+        assert_eq!(first.outputs, "DP-1");
+        assert!(matches!(first.transition_type, cli::TransitionType::Fade));
+        assert_eq!(first.transition_duration, 0.8);
+        assert_eq!(first.resize, cli::ResizeStrategy::Fit);
+
+        // unset options come from the command line
+        let second = entries[1].apply(&base).unwrap();
+        assert_eq!(second.outputs, "HDMI-A-1");
+        assert_eq!(second.transition_duration, base.transition_duration);
+        assert_eq!(second.resize, cli::ResizeStrategy::Fit);
+    }
+
+    #[test]
+    fn transitions_should_only_override_the_options_they_give() {
+        let base = cli::Img::try_parse_from([
+            "img",
+            "--batch-file",
+            "batch.json",
+            "--transition-fps",
+            "144",
+            "--output-transition",
+            "DP-1=fade:duration=100",
+        ])
+        .unwrap();
+        let entry = |output: &str| BatchEntry {
+            output: output.to_string(),
+            path: PathBuf::from("a.png"),
+            transition: Some("wipe:angle=30,duration=2000".to_string()),
+            duration_ms: None,
+        };
+
+        let other = entry("HDMI-A-1").apply(&base).unwrap();
+        assert!(matches!(other.transition_type, cli::TransitionType::Wipe));
+        assert_eq!(other.transition_angle, 30.0);
+        assert_eq!(other.transition_duration, 2.0);
+        assert_eq!(other.transition_fps, 144);
+
+        // the command line overrides the file
+        let overridden = entry("DP-1").apply(&base).unwrap();
+        assert!(matches!(
+            overridden.transition_type,
+            cli::TransitionType::Fade
+        ));
+        assert_eq!(overridden.transition_duration, 0.1);
+        assert_eq!(overridden.transition_angle, 30.0);
+
+        assert!(cli::Img::try_parse_from(["img", "a.png", "--output-transition", "DP-1"]).is_err());
+    }
+
+    #[test]
+    fn should_reject_batch_if_any_entry_is_invalid() {
+        let valid = r#"{"output": "DP-1", "path": "a.png"}"#;
+        for invalid in [
+            r#"{"output": "DP-2", "path": "b.png", "transition": "explode"}"#,
+            r#"{"output": "DP-2", "path": "b.png", "transition": "wipe:angle=north"}"#,
+            r#"{"output": "DP-2", "path": "b.png", "transition": "wipe:preview-file=a"}"#,
+            r#"{"output": "DP-2", "path": "b.png", "duration_ms": -1}"#,
+            r#"{"output": "DP-2", "path": "b.png", "colour": "red"}"#,
+            r#"{"output": "DP-1", "path": "b.png"}"#,
+            r#"{"output": "", "path": "b.png"}"#,
+            r#"{"path": "b.png"}"#,
+        ] {
+            let json = format!("[{valid}, {invalid}]");
+            assert!(parse(&json).is_err(), "{json}");
+        }
+        assert!(parse("[]").is_err());
+        assert!(parse(&format!("[{valid}]")).is_ok());
+    }
+
+    #[test]
+    fn should_parse_outputs_mapped_to_images() {
+        assert_eq!(parse_output_map("DP-1,HDMI-A-1").unwrap(), None);
+        assert_eq!(parse_output_map("").unwrap(), None);
+
+        let entries = parse_output_map("DP-1:a.png,HDMI-A-1:/wallpapers/b.gif")
+            .unwrap()
+            .unwrap();
+        let pairs: Vec<_> = entries
+            .iter()
+            .map(|e| (e.output.as_str(), e.path.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [("DP-1", "a.png"), ("HDMI-A-1", "/wallpapers/b.gif")]
+        );
+
+        for invalid in [
+            "DP-1:a.png,HDMI-A-1",
+            "DP-1:",
+            ":a.png",
+            "DP-1:a.png,DP-1:b.png",
+        ] {
+            assert!(parse_output_map(invalid).is_err(), "{invalid}");
+        }
+    }
+}
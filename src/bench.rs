@@ -0,0 +1,217 @@
+//! Times the stages of preprocessing an image, see `swww bench`.
+//!
+//! We run the same stages `swww img` runs on animations, but one after the other, on a single
+//! thread, rather than in parallel chunks, so that each of them can be timed on its own, and the
+//! timings don't depend on the machine's load as much. Diffing and compressing a frame are a
+//! single call to [`BitPack::pack`], so we time diffing alone by packing without compression.
+
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use utils::comp_decomp::{BitPack, CompressionBackend};
+
+use crate::{cli, imgproc::*};
+
+#[derive(Debug, Default)]
+struct Stages {
+    decode: Duration,
+    scale: Duration,
+    /// The rest are only measured for animations
+    diff: Duration,
+    compress: Duration,
+    unpack: Duration,
+}
+
+#[derive(Debug)]
+struct Report {
+    dim: (u32, u32),
+    frames: usize,
+    compression: CompressionBackend,
+    block_size: Option<u8>,
+    stages: Stages,
+    /// How many bytes the scaled frames take, the diffed ones, and the compressed ones
+    raw_bytes: usize,
+    diffed_bytes: usize,
+    compressed_bytes: usize,
+}
+
+pub fn run(bench: &cli::Bench) -> Result<(), String> {
+    let report = measure(
+        &bench.path,
+        (bench.width, bench.height),
+        make_scaling(&bench.filter, bench.gamma_correct),
+        make_compression(bench.compression),
+        bench.diff_block_size,
+    )?;
+    print!("{report}");
+    Ok(())
+}
+
+fn measure(
+    path: &Path,
+    dim: (u32, u32),
+    scaling: Scaling,
+    compression: CompressionBackend,
+    block_size: Option<u8>,
+) -> Result<Report, String> {
+    let mut stages = Stages::default();
+    let imgbuf = ImgBuf::new(path)?;
+
+    let start = Instant::now();
+    let imgs = if imgbuf.is_animated() {
+        imgbuf
+            .into_frames()?
+            .map_while(Result::ok)
+            .map(|frame| frame_to_rgb(frame, &[0, 0, 0], 0))
+            .collect()
+    } else {
+        vec![imgbuf.decode()?]
+    };
+    stages.decode = start.elapsed();
+
+    let start = Instant::now();
+    let frames = imgs
+        .into_iter()
+        .map(|img| img_resize_crop(img, dim, scaling, CENTER))
+        .collect::<Result<Vec<_>, String>>()?;
+    stages.scale = start.elapsed();
+
+    let frame_len = frames.first().map_or(0, Vec::len);
+    let mut report = Report {
+        dim,
+        frames: frames.len(),
+        compression,
+        block_size: None,
+        stages,
+        raw_bytes: frame_len * frames.len(),
+        diffed_bytes: 0,
+        compressed_bytes: 0,
+    };
+    if frames.len() < 2 {
+        return Ok(report);
+    }
+
+    let block_size = match block_size {
+        Some(block_size) => block_size,
+        None => BitPack::best_block_size(&frames[0], &frames[1], false, compression)?,
+    };
+    report.block_size = Some(block_size);
+    // like the animations we send the daemon, the last frame goes back to the first
+    let pairs = || (0..frames.len()).map(|i| (&frames[i], &frames[(i + 1) % frames.len()]));
+
+    let start = Instant::now();
+    for (prev, cur) in pairs() {
+        let diffed = BitPack::pack(prev, cur, CompressionBackend::None, block_size)?;
+        report.diffed_bytes += diffed.compressed_len();
+    }
+    report.stages.diff = start.elapsed();
+
+    let start = Instant::now();
+    let packed = pairs()
+        .map(|(prev, cur)| BitPack::pack(prev, cur, compression, block_size))
+        .collect::<Result<Vec<_>, String>>()?;
+    report.stages.compress = start.elapsed().saturating_sub(report.stages.diff);
+    report.compressed_bytes = packed.iter().map(BitPack::compressed_len).sum();
+
+    // the daemon unpacks into buffers with 4 bytes per pixel
+    let mut canvas = vec![0; frame_len / 3 * 4];
+    let start = Instant::now();
+    for frame in &packed {
+        if !frame.unpack(&mut canvas) {
+            return Err("failed to unpack a frame".to_string());
+        }
+    }
+    report.stages.unpack = start.elapsed();
+    Ok(report)
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frames = self.frames as u32;
+        let stage = |f: &mut fmt::Formatter<'_>, name: &str, time: Duration| {
+            writeln!(
+                f,
+                "{name:<9}{:>10.1?} ({:.1?} per frame)",
+                time,
+                time / frames.max(1)
+            )
+        };
+        let plural = if self.frames == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{}x{}, {} frame{plural}",
+            self.dim.0, self.dim.1, self.frames
+        )?;
+        match self.block_size {
+            Some(block_size) => writeln!(f, ", diff block size {block_size}")?,
+            None => writeln!(f)?,
+        }
+        stage(f, "decode", self.stages.decode)?;
+        stage(f, "scale", self.stages.scale)?;
+        let buffer = mib(self.raw_bytes / self.frames.max(1) / 3 * 4);
+        if self.block_size.is_none() {
+            // the daemon gets still images whole
+            writeln!(f, "raw image {}", mib(self.raw_bytes))?;
+            return writeln!(f, "daemon memory: {buffer} for each buffer it draws to");
+        }
+        stage(f, "diff", self.stages.diff)?;
+        stage(f, "compress", self.stages.compress)?;
+        stage(f, "unpack", self.stages.unpack)?;
+        writeln!(
+            f,
+            "raw frames {}, diffed {}, compressed ({}) {}, {:.1}x smaller",
+            mib(self.raw_bytes),
+            mib(self.diffed_bytes),
+            format!("{:?}", self.compression).to_lowercase(),
+            mib(self.compressed_bytes),
+            self.raw_bytes as f64 / self.compressed_bytes.max(1) as f64
+        )?;
+        writeln!(
+            f,
+            "daemon memory: {} of frames, plus {buffer} for each buffer it draws to",
+            mib(self.compressed_bytes)
+        )
+    }
+}
+
+fn mib(bytes: usize) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn should_measure_every_stage_of_animations() {
+        let path = std::env::temp_dir().join(format!("swww-bench-{}.gif", std::process::id()));
+        let frames = (0..4u8).map(|i| {
+            let img = RgbaImage::from_pixel(16, 16, image::Rgba([i * 60, 0, 0, 255]));
+            image::Frame::from_parts(img, 0, 0, image::Delay::from_numer_denom_ms(20, 1))
+        });
+        let mut gif = Vec::new();
+        image::codecs::gif::GifEncoder::new(&mut gif)
+            .encode_frames(frames)
+            .unwrap();
+        std::fs::write(&path, gif).unwrap();
+
+        let report = measure(
+            &path,
+            (8, 8),
+            make_scaling(&cli::Filter::Nearest, false),
+            CompressionBackend::Lz4,
+            Some(1),
+        );
+        std::fs::remove_file(&path).unwrap();
+        let report = report.unwrap();
+        assert_eq!(report.frames, 4);
+        assert_eq!(report.raw_bytes, 4 * 8 * 8 * 3);
+        assert!(report.compressed_bytes > 0);
+        assert!(report.compressed_bytes < report.diffed_bytes);
+        assert!(report.to_string().contains("diff block size 1"));
+    }
+}
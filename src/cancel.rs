@@ -0,0 +1,69 @@
+//! Stops making an animation once the daemon no longer wants it.
+//!
+//! We send the first frame of an animated image before making the rest of its frames, which can
+//! take a while, and then keep the socket we sent it on open. If something replaces the image on
+//! every output in the meantime, the daemon hangs up that socket, and we give up on the animation.
+//! If instead we fail to make it, or exit, our end closes, and the daemon stops waiting for it.
+//!
+//! Long animations start playing before we made all of their frames, see
+//! `ipc::Playback::more_frames`. The daemon then hangs up because it started playing them, and
+//! instead tells us it no longer wants the rest by answering the next frames with an error.
+
+use std::{
+    io::Read,
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static PLAYING: AtomicBool = AtomicBool::new(false);
+
+/// The id of our request, for [`utils::ipc::Img::request`] and [`utils::ipc::Playback::request`]
+pub fn request_id() -> u64 {
+    static ID: OnceLock<u64> = OnceLock::new();
+    *ID.get_or_init(rand::random)
+}
+
+/// Whether the daemon no longer wants the animation we are making
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::Acquire) && !PLAYING.load(Ordering::Acquire)
+}
+
+/// The daemon started playing the first frames of our animation, so it hanging up no longer
+/// cancels it
+pub fn playing() {
+    PLAYING.store(true, Ordering::Release);
+}
+
+/// The daemon no longer wants the rest of the animation it was playing
+pub fn cancel() {
+    PLAYING.store(false, Ordering::Release);
+    CANCELLED.store(true, Ordering::Release);
+}
+
+/// Keeps the socket we sent the first frame on open, watching for the daemon to hang up
+pub fn watch(socket: UnixStream) -> Result<(), String> {
+    socket
+        .set_read_timeout(None)
+        .map_err(|e| format!("failed to clear read timeout for socket: {e}"))?;
+    std::thread::Builder::new()
+        .name("cancel".to_string())
+        .stack_size(1 << 14)
+        .spawn(move || {
+            let mut byte = [0];
+            // the daemon never writes to it, so reading returns once it hangs up
+            loop {
+                match (&socket).read(&mut byte) {
+                    Ok(0) => break,
+                    Err(e) if e.kind() != std::io::ErrorKind::Interrupted => break,
+                    _ => (),
+                }
+            }
+            CANCELLED.store(true, Ordering::Release);
+        })
+        .map_err(|e| format!("failed to spawn `cancel` thread: {e}"))?;
+    Ok(())
+}
@@ -1,6 +1,6 @@
 /// Note: this file only has basic declarations and some definitions in order to be possible to
 /// import it in the build script, to automate shell completion
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, ValueEnum};
 use std::path::PathBuf;
 
 fn from_hex(hex: &str) -> Result<[u8; 3], String> {
@@ -36,7 +36,7 @@ fn from_hex(hex: &str) -> Result<[u8; 3], String> {
     Ok(color)
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Filter {
     Nearest,
     Bilinear,
@@ -49,12 +49,12 @@ impl std::str::FromStr for Filter {
     type Err = &'static str;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "Nearest" => Ok(Self::Nearest),
-            "Bilinear" => Ok(Self::Bilinear),
-            "CatmullRom" => Ok(Self::CatmullRom),
-            "Mitchell" => Ok(Self::Mitchell),
-            "Lanczos3" => Ok(Self::Lanczos3),
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Self::Nearest),
+            "bilinear" => Ok(Self::Bilinear),
+            "catmullrom" => Ok(Self::CatmullRom),
+            "mitchell" => Ok(Self::Mitchell),
+            "lanczos3" | "lanczos" => Ok(Self::Lanczos3),
             _ => Err("unrecognized filter. Valid filters are:\
                      Nearest | Bilinear | CatmullRom | Mitchell | Lanczos3\
                      see swww img --help for more details"),
@@ -78,6 +78,10 @@ pub enum TransitionType {
     Wipe,
     Wave,
     Grow,
+    Slide,
+    ZoomOut,
+    Burn,
+    Crossfade,
 }
 
 impl std::str::FromStr for TransitionType {
@@ -99,8 +103,12 @@ impl std::str::FromStr for TransitionType {
             "wave" => Ok(Self::Wave),
             "random" => Ok(Self::Random),
             "fade" => Ok(Self::Fade),
+            "slide" => Ok(Self::Slide),
+            "zoom-out" => Ok(Self::ZoomOut),
+            "burn" => Ok(Self::Burn),
+            "crossfade" => Ok(Self::Crossfade),
             _ => Err("unrecognized transition type.\nValid transitions are:\n\
-                     \tsimple | fade | left | right | top | bottom | wipe | grow | center | outer | random | wave\n\
+                     \tsimple | fade | left | right | top | bottom | wipe | grow | center | outer | random | wave | slide | zoom-out | burn | crossfade\n\
                      see swww img --help for more details"),
         }
     }
@@ -116,12 +124,18 @@ pub enum CliCoord {
 pub struct CliPosition {
     pub x: CliCoord,
     pub y: CliCoord,
+    /// Follow the pointer, if the daemon knows where it is, and use `x` and `y` otherwise
+    pub cursor: bool,
     //Unknown(f32, f32),
 }
 
 impl CliPosition {
     pub fn new(x: CliCoord, y: CliCoord) -> Self {
-        Self { x, y }
+        Self {
+            x,
+            y,
+            cursor: false,
+        }
     }
 }
 
@@ -135,7 +149,42 @@ impl CliPosition {
 ///
 ///Note `swww` will only work in a compositor that implements the layer-shell protocol. Typically,
 ///wlr-roots based compositors.
+pub struct Cli {
+    ///Talk to the daemon of this namespace, instead of the default one
+    ///
+    ///Every namespace has a socket and a cache of its own, so that several daemons can run at
+    ///once, like one per wayland session. `swww init` starts the daemon in it.
+    #[arg(long, global = true, env = "SWWW_NAMESPACE")]
+    pub namespace: Option<String>,
+
+    ///How to print errors. The exit code tells what kind of error it was either way
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub error_format: ErrorFormat,
+
+    #[command(subcommand)]
+    pub command: Swww,
+}
+
+// we only ever make one, so boxing `Img` would save nothing
+#[allow(clippy::large_enum_variant)]
+#[derive(Parser)]
 pub enum Swww {
+    ///Adjusts what the given outputs display, without sending it again
+    ///
+    ///The daemon adjusts what it displays every time it draws it, so adjustments don't add up:
+    ///every call replaces the ones before, including those given to `swww img`. They go away with
+    ///the image.
+    Adjust {
+        #[command(flatten)]
+        adjustments: Adjustments,
+
+        /// Comma separated list of outputs to adjust.
+        ///
+        /// If it isn't set, all outputs are adjusted.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
     ///Fills the specified outputs with the given color.
     ///
     ///Defaults to filling all outputs with black.
@@ -147,6 +196,12 @@ pub enum Swww {
     ///as the animation frames of every gif ever set for a given version of `swww`.
     ClearCache,
 
+    ///Displays several images side by side on every output, in a grid
+    ///
+    ///Every output gets the whole grid, with each image resized to its cell. Useful on ultrawide
+    ///outputs, where a single image never quite fits.
+    Collage(Collage),
+
     /// Sends an image (or animated gif) for the daemon to display.
     ///
     /// Use `-` to read from stdin
@@ -170,16 +225,438 @@ pub enum Swww {
         ///results some reliable: `swww init --no-cache && swww img <some img>`
         #[clap(long)]
         no_cache: bool,
+
+        ///Have the daemon send a JSON status line to this unix datagram socket every second
+        ///
+        ///The line looks like `{"ts":<unix time in ms>,"outputs":<n>,"fps_actual":<fps>}`. This
+        ///is meant for external monitoring tools, to know whether the daemon is still alive.
+        #[clap(long)]
+        heartbeat_socket: Option<PathBuf>,
+
+        ///Every this many seconds, have the daemon free the buffers of outputs that no longer exist
+        ///
+        ///Use `swww query --verbose` to see how much memory was freed so far.
+        #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+        gc_interval: Option<u64>,
+
+        ///On outputs with a scale factor, draw at the logical size and let the compositor scale up
+        ///
+        ///This uses less memory and makes transitions cheaper, at the cost of a blurrier image.
+        #[clap(long)]
+        prefer_compositor_scaling: bool,
+
+        ///Have the daemon hold an exclusive lock on this file while it runs
+        ///
+        ///If another daemon already holds it, the new one exits right away. This is more robust
+        ///than checking whether the socket exists, since a crashed daemon may leave it behind.
+        #[clap(long)]
+        lock_file: Option<PathBuf>,
+
+        ///Have the daemon push frame timing metrics to this StatsD server, over UDP
+        ///
+        ///Every second, it sends `swww.frame_time_us:<us>|ms`, the average time it took to draw a
+        ///frame, if it drew any, and `swww.frames_dropped:<n>|c`, how many frames missed their deadline.
+        #[clap(long, value_name = "HOST:PORT")]
+        statsd_host: Option<String>,
+
+        ///Have the daemon run this executable, and let it decide what to display
+        ///
+        ///The daemon sends it JSON events on its stdin (`{"event":"ready"}` at startup, and
+        ///`{"event":"timer","elapsed_ms":<ms>}` every second). Every line it writes to its stdout
+        ///is run as a `swww img --batch-file`, so it must be a JSON array in that format.
+        #[clap(long)]
+        script: Option<PathBuf>,
+
+        ///Have the daemon keep track of the pointer, for `swww img --transition-pos cursor`
+        ///
+        ///This needs the compositor to support the cursor_shape_v1 protocol, and makes the
+        ///wallpapers receive the clicks on the desktop.
+        #[clap(long)]
+        track_cursor: bool,
+
+        ///Have the daemon connect to this wayland socket, instead of the one in $WAYLAND_DISPLAY
+        ///
+        ///Useful when running several compositors at once, like a nested one for testing.
+        #[clap(long)]
+        wayland_socket: Option<PathBuf>,
+
+        ///Have the daemon put the wallpapers on this layer-shell layer, instead of the background
+        ///
+        ///On `top` and `overlay`, images are drawn over windows, to display them as an overlay.
+        #[clap(long, value_enum)]
+        layer: Option<Layer>,
+
+        ///Have the daemon give the wallpapers' layer surfaces this namespace, instead of `swww`
+        ///
+        ///Compositors may use it to give them rules of their own. `{output}` is replaced with the
+        ///name of the output, to tell them apart.
+        #[clap(long)]
+        layer_namespace: Option<String>,
+
+        ///Have the daemon make the wallpapers this many logical pixels wide and high, like 800x600
+        ///
+        ///They then only take part of the output, like an overlay image, and images are made for
+        ///this size, instead of the output's.
+        #[clap(long, value_name = "WIDTHxHEIGHT")]
+        size: Option<String>,
+
+        ///Where on the output the daemon puts wallpapers with a `--size`
+        #[clap(long, value_enum, requires = "size")]
+        anchor: Option<Anchor>,
+
+        ///Have the daemon keep the wallpapers this many logical pixels away from the output's edges
+        ///
+        ///Either one number, for every edge, or four, for the top, right, bottom and left ones,
+        ///like `10,20,10,20`.
+        #[clap(long)]
+        margin: Option<String>,
     },
 
     ///Kills the daemon
     Kill,
 
+    ///Changes which logs the daemon keeps, or prints them as they happen
+    ///
+    ///Without options, prints the filter the daemon has. Logs go to the daemon's stderr, and to its
+    ///--log-file and the journal, if it was started with them.
+    Log {
+        /// Which logs to keep from now on, like `info,animations=debug`
+        ///
+        /// A level for every module, and levels of their own for single modules, along with the
+        /// modules inside them. The levels are off, error, warn, info, debug and trace. Modules
+        /// that are not named go back to the daemon's default level, unless one is given.
+        #[arg(short, long)]
+        level: Option<String>,
+
+        /// Print every log the daemon keeps as it happens, one JSON object per line, until it
+        /// exits
+        #[arg(short, long)]
+        follow: bool,
+    },
+
+    ///Makes outputs display whatever another output draws, like for video walls or presentations
+    ///
+    ///The daemon copies every frame the source draws to the targets, rescaling it only if their
+    ///sizes or transforms differ, so animations are only processed once and stay in sync. A target
+    ///stops mirroring once it displays something of its own, with `swww img` for example.
+    Mirror {
+        /// The output to copy from
+        source: String,
+
+        /// The outputs to copy to, which can also be comma separated
+        #[arg(required = true, value_delimiter = ',')]
+        targets: Vec<String>,
+    },
+
+    ///Changes how fast the animations on the given outputs play, without sending them again
+    ///
+    ///Outputs that got the same animation in the same `swww img` call share its speed.
+    PlaybackSpeed {
+        /// How many times faster than their frame delays say to play, like 0.5 for half speed
+        #[arg(value_parser = parse_playback_speed)]
+        speed: f32,
+
+        /// Comma separated list of outputs to change the speed of.
+        ///
+        /// If it isn't set, all outputs are changed.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
+    ///Prints the dominant colors of what the given outputs display, for theming tools
+    ///
+    ///Colors are printed from most to least common, as `#rrggbb`.
+    Palette {
+        /// How many colors to find. Images with fewer colors than this give fewer
+        #[arg(short, long, default_value = "8", value_parser = clap::value_parser!(u8).range(1..))]
+        count: u8,
+
+        /// How to print the colors
+        #[clap(long, value_enum, default_value_t)]
+        format: PaletteFormat,
+
+        /// Comma separated list of outputs to find the colors of.
+        ///
+        /// If it isn't set, all outputs are used. `--format sh` needs exactly one.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
+    ///Freezes the animations on the given outputs on their current frame
+    ///
+    ///They stay paused, even if they get a new image, until you call `swww resume`.
+    Pause {
+        /// Comma separated list of outputs to pause.
+        ///
+        /// If it isn't set, all outputs are paused.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
+    ///Makes the daemon read its configuration file again
+    ///
+    ///Only the `transition-interrupt-policy`, `frame-drop-recovery` and `no-restore` keys take
+    ///effect right away. The others need the daemon to restart.
+    ReloadConfig,
+
+    ///Continues the animations `swww pause` froze, from the frame they were on
+    Resume {
+        /// Comma separated list of outputs to resume.
+        ///
+        /// If it isn't set, all outputs are resumed.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
+    ///Has the daemon render a GLSL fragment shader on the given outputs, every frame
+    ///
+    ///The shader is written like those of shadertoy: a `mainImage(out vec4 fragColor, in vec2
+    ///fragCoord)` function, which may use the uniforms `iResolution`, `iTime`, `iTimeDelta`,
+    ///`iFrame` and `iMouse`. The daemon needs OpenGL ES 3 for it, through EGL. It renders until the
+    ///outputs display something else.
+    Shader(Shader),
+
+    ///Has the daemon show the images of a directory one after another, on the given outputs
+    ///
+    ///The interval only counts the time the outputs are visible, so the slideshow waits while they
+    ///are powered off or covered. Displaying something else on them, with `swww img` for example,
+    ///doesn't stop it: use `swww slideshow --stop` for that.
+    Slideshow {
+        /// The directory to take the images from. Images added to it, or removed from it, while
+        /// the slideshow runs are picked up
+        #[arg(required_unless_present = "stop")]
+        dir: Option<PathBuf>,
+
+        /// How long to show every image, as a number of seconds, or followed by s, m or h
+        #[arg(short, long, default_value = "10m", value_parser = parse_interval)]
+        interval: u32,
+
+        /// Show the images in a random order, rather than by name
+        #[clap(short, long)]
+        shuffle: bool,
+
+        /// The transition to show every image with, like for `swww img --transition-type`
+        ///
+        /// If it isn't set, the default one (or $SWWW_TRANSITION) is used.
+        #[arg(short, long, value_parser = parse_transition_type)]
+        transition_type: Option<String>,
+
+        /// Stop the slideshows on the given outputs, instead of starting one
+        #[arg(
+            long,
+            conflicts_with_all = ["dir", "interval", "shuffle", "transition_type", "img_args"]
+        )]
+        stop: bool,
+
+        /// Comma separated list of outputs to show the slideshow on.
+        ///
+        /// If it isn't set, all outputs are used. A new slideshow replaces the ones that were on
+        /// any of them.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+
+        /// Options of `swww img` to show every image with, after `--`, like
+        /// `swww slideshow ~/walls -- --resize fit`
+        #[arg(last = true, value_name = "IMG OPTIONS")]
+        img_args: Vec<String>,
+    },
+
+    ///Advances the paused animations on the given outputs by a number of frames
+    ///
+    ///The outputs must have been paused with `swww pause` first. `swww query` shows which frame
+    ///they are on.
+    Step {
+        /// How many frames to advance
+        #[arg(default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+        frames: u32,
+
+        /// Comma separated list of outputs to advance.
+        ///
+        /// If it isn't set, all outputs are advanced.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
+    ///Draws an image, like a logo, a vignette or a texture, over everything the given outputs draw
+    ///
+    ///The overlay stays on through new images, animations and transitions, until you replace or
+    ///remove it.
+    Overlay {
+        /// Path to the image to draw, or `none`, to remove the overlay
+        path: PathBuf,
+
+        /// Where on the outputs to draw the image
+        #[arg(long, value_enum, default_value = "center")]
+        anchor: OverlayAnchor,
+
+        /// How opaque the image is, from 0.0 (invisible) to 1.0 (as opaque as the image itself)
+        #[arg(long, default_value = "1.0", value_parser = parse_opacity)]
+        opacity: f32,
+
+        /// How to resize the image to the outputs
+        #[arg(long, value_enum, default_value = "no")]
+        resize: OverlayResize,
+
+        /// Comma separated list of outputs to draw the image on.
+        ///
+        /// If it isn't set, it is drawn on all outputs.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
+    ///Prints what the daemon does as it happens, one JSON object per line, until it exits
+    ///
+    ///The events are: `output_added`, `output_removed`, `wallpaper_changed`,
+    ///`transition_started`, `transition_finished` and `animation_looped`.
+    Subscribe,
+
+    ///Tints everything the given outputs draw, without sending their images again
+    ///
+    ///The tint stays on through new images, animations and transitions, until you change it.
+    Tint {
+        /// A color temperature, like 4500K, or red, green and blue multipliers, like 1,0.9,0.8
+        ///
+        /// 6500K is neutral, like `none`, which removes the tint. Lower temperatures are warmer
+        /// (redder), and higher ones cooler (bluer), like with `swww img --color-temp`.
+        #[arg(value_parser = parse_tint)]
+        tint: Tint,
+
+        /// Comma separated list of outputs to tint.
+        ///
+        /// If it isn't set, all outputs are tinted.
+        #[clap(short, long, default_value = "")]
+        outputs: String,
+    },
+
+    /// Times each stage of preprocessing an image, without needing a running daemon
+    ///
+    /// Decodes the image, scales it to the size of an output, and diffs, compresses and unpacks
+    /// its frames like `swww img` and the daemon do for animations, reporting how long each
+    /// stage took, how small the frames got, and how much memory the daemon would need for them.
+    #[command(hide = true)]
+    Bench(Bench),
+
+    /// Renders every frame of a transition between two images to png files, without needing a
+    /// running daemon or compositor.
+    ///
+    /// Frames are written to `<OUTPUT_DIR>/frame_<n>.png`, starting from 0.
+    TransitionPreview(TransitionPreview),
+
+    /// Writes an image to a file the way `swww img` would display it on an output of a given size,
+    /// without needing a running daemon or compositor.
+    ///
+    /// Animations are written as their first frame.
+    Preview(Preview),
+
     ///Asks the daemon to print output information (names and dimensions).
     ///
     ///You may use this to find out valid values for the <swww-img --outputs> option. If you want
     ///more detailed information about your outputs, I would recommend trying wlr-randr.
-    Query,
+    Query {
+        ///Also print statistics about the daemon itself, like how much memory its garbage
+        ///collector has freed
+        #[clap(short, long)]
+        verbose: bool,
+
+        ///How to print the information. `json` is meant for scripts
+        #[clap(long, value_enum, default_value_t)]
+        format: QueryFormat,
+    },
+
+    ///Prints what every output costs the daemon since it appeared
+    ///
+    ///That is, the frames per second it shows, how many frames it showed and skipped, how much
+    ///memory the frames of its animation take, and how many of its animations `swww img` found in
+    ///its cache, instead of making them.
+    Stats {
+        ///How to print the statistics. `json` is meant for scripts
+        #[clap(long, value_enum, default_value_t)]
+        format: QueryFormat,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layer {
+    Background,
+    Bottom,
+    Top,
+    Overlay,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QueryFormat {
+    /// One line per output, meant for humans
+    #[default]
+    Text,
+    /// A single JSON object, like `{"outputs":[{"name":"DP-1","width":1920,...}]}`
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// `Error: "<message>"`, meant for humans
+    #[default]
+    Text,
+    /// A single JSON object, like `{"error":{"kind":"no-such-output","message":"...",...}}`
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PaletteFormat {
+    /// A single JSON object, like `{"outputs":[{"name":"DP-1","colors":["#282828",...]}]}`
+    #[default]
+    Json,
+    /// Shell variables, like `color0='#282828'`, one per line, to `source` from scripts
+    Sh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loops {
+    Infinite,
+    Times(u32),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorProfile {
+    /// Convert from the ICC profile the image carries, if it has one
+    #[default]
+    Embedded,
+    /// Convert from Display P3, whatever profile the image carries
+    DisplayP3,
+    /// Show the image's colors as they are
+    Ignore,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PlaybackDirection {
+    #[default]
+    Forward,
+    Reverse,
+    PingPong,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AtEnd {
+    /// Keep showing the last frame
+    #[default]
+    HoldLast,
+    /// Go back to the first frame, and keep showing it
+    FirstFrame,
+    /// Fill the output with --fill-color
+    Clear,
 }
 
 #[derive(Parser)]
@@ -190,6 +667,30 @@ pub struct Clear {
     #[arg(value_parser = from_hex, default_value = "000000")]
     pub color: [u8; 3],
 
+    /// Fill the screen with a linear gradient between these two colors instead, in rrggbb format
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"], value_parser = from_hex)]
+    pub gradient: Option<Vec<[u8; 3]>>,
+
+    /// Which way the gradient goes, in degrees, counter-clockwise
+    ///
+    /// 0 goes from left to right, and 90 from bottom to top.
+    #[arg(
+        long,
+        default_value = "0",
+        requires = "gradient",
+        allow_negative_numbers = true
+    )]
+    pub angle: f32,
+
+    /// Transition to the new fill, like `fade:duration=500`, instead of replacing what the outputs
+    /// display right away
+    ///
+    /// Options are the same as the `--transition-*` options of `swww img`, without the
+    /// `--transition-` prefix, like for `swww transition-preview`. The ones not given come from the
+    /// `[img]` section of the configuration file.
+    #[arg(long)]
+    pub transition: Option<String>,
+
     /// Comma separated list of outputs to display the image at.
     ///
     /// If it isn't set, the image is displayed on all outputs.
@@ -197,32 +698,440 @@ pub struct Clear {
     pub outputs: String,
 }
 
+#[derive(Parser)]
+pub struct Shader {
+    /// The GLSL file of the fragment shader
+    pub path: PathBuf,
+
+    /// How many frames per second to render it at
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u16).range(1..))]
+    pub fps: u16,
+
+    /// Comma separated list of outputs to render the shader on.
+    ///
+    /// If it isn't set, the shader is rendered on all outputs.
+    #[clap(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Collage {
+    /// The images, from left to right and top to bottom
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// How many columns and rows the grid has, like 2x2
+    ///
+    /// Defaults to as few columns as make a grid that is at least as wide as it is high.
+    #[arg(long, value_name = "COLUMNSxROWS", value_parser = parse_grid)]
+    pub grid: Option<(u32, u32)>,
+
+    /// How many pixels to leave between the images, filled with --fill-color
+    #[arg(long, default_value_t = 0)]
+    pub gap: u32,
+
+    /// How to resize the images to their cells, like for `swww img --resize`
+    ///
+    /// Either one for every image, or one per image, comma separated, like `crop,fit,crop`.
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "crop")]
+    pub resize: Vec<ResizeStrategy>,
+
+    /// The color of the gaps, and of the space around the images that don't fill their cells, in
+    /// rrggbb format
+    ///
+    /// Defaults to the `fill-color` of the `[img]` section of the configuration file.
+    #[arg(long, value_parser = from_hex)]
+    pub fill_color: Option<[u8; 3]>,
+
+    /// Transition to the collage like `fade:duration=500`, like for `swww clear --transition`
+    ///
+    /// Without it, the collage transitions like `swww img` would.
+    #[arg(long)]
+    pub transition: Option<String>,
+
+    /// Comma separated list of outputs to display the collage at.
+    ///
+    /// If it isn't set, the collage is displayed on all outputs.
+    #[clap(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Bench {
+    /// Image to preprocess
+    pub path: PathBuf,
+
+    /// Width of the output to scale the image to, in pixels
+    #[arg(long, default_value = "1920")]
+    pub width: u32,
+
+    /// Height of the output to scale the image to, in pixels
+    #[arg(long, default_value = "1080")]
+    pub height: u32,
+
+    /// Filter to scale the image with, like for `swww img --filter`
+    #[arg(short, long, default_value = "Lanczos3")]
+    pub filter: Filter,
+
+    /// Scale the image in linear light, like for `swww img --gamma-correct`
+    #[arg(long)]
+    pub gamma_correct: bool,
+
+    /// How to compress the frames, like for `swww img --compression`
+    #[arg(long, value_enum, default_value_t)]
+    pub compression: Compression,
+
+    /// How many pixels to compare at a time when diffing the frames, like for `swww img
+    /// --diff-block-size`
+    #[arg(long, value_parser = parse_diff_block_size)]
+    pub diff_block_size: Option<u8>,
+}
+
+#[derive(Parser)]
+pub struct TransitionPreview {
+    /// The transition to preview, followed by its options, like `wipe:angle=30,duration=500`.
+    ///
+    /// Options are the same as the `--transition-*` options of `swww img`, without the
+    /// `--transition-` prefix. The only exception is `duration`, which is given in milliseconds
+    /// here. Options that are not given take their usual defaults.
+    pub transition: String,
+
+    /// Image we transition from
+    pub from: PathBuf,
+
+    /// Image we transition to
+    pub to: PathBuf,
+
+    /// Directory to write the frames to. It is created if it doesn't exist
+    #[arg(short, long)]
+    pub output_dir: PathBuf,
+
+    /// Width of the frames, in pixels
+    #[arg(long, default_value = "1920")]
+    pub width: u32,
+
+    /// Height of the frames, in pixels
+    #[arg(long, default_value = "1080")]
+    pub height: u32,
+}
+
+#[derive(Parser)]
+pub struct Preview {
+    /// Image to preview
+    pub path: PathBuf,
+
+    /// File to write the preview to. Its extension picks the format, like `.png`
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Size of the output to preview the image on, in pixels
+    #[arg(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        value_parser = parse_dimensions,
+        default_value = "1920x1080"
+    )]
+    pub size: (u32, u32),
+
+    /// How to resize the image, like for `swww img --resize`
+    #[arg(long, value_enum, default_value_t)]
+    pub resize: ResizeStrategy,
+
+    /// Filter to scale the image with, like for `swww img --filter`
+    #[arg(short, long, default_value = "Lanczos3")]
+    pub filter: Filter,
+
+    /// Which color to fill the padding with when the image does not fill the output
+    #[arg(value_parser = from_hex, long, default_value = "000000")]
+    pub fill_color: [u8; 3],
+
+    /// Write the first frame of this transition from the image `--from`, instead of the image
+    /// itself. It is written like for `swww transition-preview`
+    #[arg(long, requires = "from")]
+    pub transition: Option<String>,
+
+    /// Image the `--transition` starts from. It is resized like the image itself
+    #[arg(long, requires = "transition")]
+    pub from: Option<PathBuf>,
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
 pub enum ResizeStrategy {
     /// Do not resize the image
     ///
-    /// If this is set, the image won't be resized, and will be centralized in the middle of the
-    /// screen instead. If it is smaller than the screen's size, it will be padded with the value
-    /// of `fill_color`, below.
-    No,
-    #[default]
-    /// Resize the image to fill the whole screen, cropping out parts that don't fit
-    Crop,
-    /// Resize the image to fit inside the screen, preserving the original aspect ratio
-    Fit,
-}
+    /// If this is set, the image won't be resized, and will be centralized in the middle of the
+    /// screen instead. If it is smaller than the screen's size, it will be padded with the value
+    /// of `fill_color`, below.
+    #[value(alias = "center")]
+    No,
+    #[default]
+    /// Resize the image to fill the whole screen, cropping out parts that don't fit
+    Crop,
+    /// Resize the image to fit inside the screen, preserving the original aspect ratio
+    Fit,
+    /// Resize the image to fill the screen's width, adding bars on the top and bottom if needed
+    ///
+    /// If the image is too tall, its top and bottom get cropped out instead.
+    Letterbox,
+    /// Resize the image to fill the screen's height, adding bars on the left and right if needed
+    ///
+    /// If the image is too wide, its sides get cropped out instead.
+    Pillarbox,
+    /// Resize the image to exactly the screen's size, ignoring its aspect ratio
+    Stretch,
+    /// Repeat the image at its original size until it covers the whole screen, starting from the
+    /// top left corner
+    Tile,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Direction {
+    /// The new image enters from the left edge of the screen
+    #[default]
+    Left,
+    /// The new image enters from the right edge of the screen
+    Right,
+    /// The new image enters from the top edge of the screen
+    Up,
+    /// The new image enters from the bottom edge of the screen
+    Down,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Compression {
+    /// Fast to compress and decompress
+    #[default]
+    Lz4,
+    /// A lot slower to compress, but makes much smaller frames
+    Zstd,
+    /// Don't compress the frames at all
+    None,
+}
+
+/// Adjustments to the colors and sharpness of images, shared by `swww img` and `swww adjust`. The
+/// daemon makes them, every time it draws
+#[derive(Debug, Default, Clone, PartialEq, Args)]
+pub struct Adjustments {
+    /// Blur the image with a gaussian of this standard deviation, in pixels of the output
+    ///
+    /// Must be a positive number.
+    #[arg(long, value_parser = parse_blur)]
+    pub blur: Option<f32>,
+
+    /// Make the image brighter, or darker, from -100 to 100 percent
+    #[arg(long, default_value = "0", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i32).range(-100..=100))]
+    pub brightness: i32,
+
+    /// Increase, or decrease, the contrast of the image, from -100 to 100 percent
+    #[arg(long, default_value = "0", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i32).range(-100..=100))]
+    pub contrast: i32,
+
+    /// Make the colors of the image more, or less, saturated, from -100 to 100 percent
+    ///
+    /// -100 makes the image grayscale.
+    #[arg(long, default_value = "0", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i32).range(-100..=100))]
+    pub saturation: i32,
+
+    /// Turn the image into one tone, after the other adjustments
+    #[arg(long, value_enum)]
+    pub tone: Option<Tone>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tint {
+    /// A color temperature, in kelvin
+    Kelvin(u32),
+    /// Red, green and blue multipliers
+    Gains([f32; 3]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OverlayAnchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl OverlayAnchor {
+    /// Where the anchor is, from (0.0, 0.0), the top left corner, to (1.0, 1.0), the bottom right
+    /// one
+    pub fn position(self) -> (f32, f32) {
+        match self {
+            Self::TopLeft => (0.0, 0.0),
+            Self::Top => (0.5, 0.0),
+            Self::TopRight => (1.0, 0.0),
+            Self::Left => (0.0, 0.5),
+            Self::Center => (0.5, 0.5),
+            Self::Right => (1.0, 0.5),
+            Self::BottomLeft => (0.0, 1.0),
+            Self::Bottom => (0.5, 1.0),
+            Self::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OverlayResize {
+    /// Keep the image's own size. If it is bigger than the output, it is shrunk to fit
+    No,
+    /// Resize the image to fit inside the output, preserving its aspect ratio
+    Fit,
+    /// Resize the image to cover the whole output, cropping out parts that don't fit
+    Crop,
+    /// Resize the image to exactly the output's size, ignoring its aspect ratio
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Tone {
+    /// Only keeps the luma of every pixel
+    Grayscale,
+    /// Replaces every color by its opposite, like a photographic negative
+    Invert,
+    /// Tints the grayscale image brown, like an old photograph
+    Sepia,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dither {
+    /// Adds the same fixed pattern to every frame, so pixels that stay the same look the same
+    Bayer,
+    /// Spreads the rounding error of every pixel to its neighbors. Smoother, but a change to one
+    /// pixel may change how every pixel after it looks
+    FloydSteinberg,
+}
+
+#[derive(Clone, Parser)]
+// the `[img]` section of the configuration file comes before the real arguments, which must be able
+// to override it
+#[command(args_override_self = true)]
+pub struct Img {
+    /// Path to the image to display
+    ///
+    /// If it is a directory, a random image in it is displayed instead, and its path printed.
+    // the default is never used: we either get a path, or read them all from `--batch-file` or
+    // `--outputs`
+    #[arg(
+        required_unless_present_any = ["batch_file", "outputs"],
+        default_value = ".",
+        hide_default_value = true
+    )]
+    pub path: PathBuf,
+
+    /// Read a JSON list of images to display from this file, and apply all of them at once
+    ///
+    /// The file should look like `[{"output":"DP-1","path":"a.png","transition":"fade",
+    /// "duration_ms":800}, ...]`, where `transition` and `duration_ms` are optional, and
+    /// `transition` may have options, like `wipe:angle=30`, see `--output-transition`. Every other
+    /// option given on the command line applies to all images. If any entry is invalid, nothing is
+    /// changed. Otherwise, all transitions start at the same time.
+    #[arg(long, conflicts_with_all = ["path", "outputs"])]
+    pub batch_file: Option<PathBuf>,
+
+    /// The format of the image, like `png` or `jpg`, instead of guessing it from its contents
+    ///
+    /// Useful when reading from stdin an image whose format can't be guessed, like a tga.
+    #[arg(long)]
+    pub format: Option<String>,
+
+    /// When the path is a url, keep the downloaded image in swww's cache
+    ///
+    /// Otherwise, it is only kept in memory, and restoring the wallpaper later, like in `swww init`,
+    /// downloads it again.
+    #[arg(long)]
+    pub cache: bool,
+
+    /// At most how many MiB the frames of animations take in swww's cache
+    ///
+    /// Once they take more, those used the longest time ago are deleted, until the rest fit.
+    #[arg(long, default_value_t = 1024, value_name = "MiB")]
+    pub cache_limit: u64,
+
+    /// When the path is a directory, also pick from the images in its subdirectories
+    #[arg(long)]
+    pub recursive: bool,
+
+    /// When the path is a directory, the extensions of the files to pick from, comma separated
+    ///
+    /// Defaults to those of every image format swww can open:
+    /// png,jpg,jpeg,gif,webp,bmp,tif,tiff,tga,pnm,qoi,avif,jxl,svg,ico,hdr,exr,farbfeld.
+    #[arg(long, value_delimiter = ',')]
+    pub extensions: Vec<String>,
+
+    /// When the path is a directory, play the images in it as an animation, at this many frames
+    /// per second, instead of picking one of them
+    ///
+    /// The images are played in the order of their names, with the numbers in them compared as
+    /// numbers, so `frame_2.png` comes before `frame_10.png`. Only files with one of --extensions
+    /// are frames.
+    ///
+    /// With --ken-burns, how many frames per second the zoom has, 10 by default.
+    #[arg(long, value_parser = parse_fps)]
+    pub fps: Option<f32>,
+
+    /// Display the image again whenever its file changes, like when a script generates it
+    ///
+    /// The daemon runs `swww img` again, with the same arguments, until something else is
+    /// displayed on any of the outputs. The path must be an image file, not a url or stdin.
+    #[arg(long, conflicts_with = "batch_file")]
+    pub watch: bool,
+
+    /// Wait until the transition is over, and the image is on screen, before exiting
+    ///
+    /// Useful in scripts that do something with what is on screen next, like taking its colors.
+    /// Exits with an error if the daemon takes longer than `--sync-timeout`.
+    #[arg(long, conflicts_with = "batch_file")]
+    pub sync: bool,
 
-#[derive(Parser)]
-pub struct Img {
-    /// Path to the image to display
-    pub path: PathBuf,
+    /// How many seconds `--sync` waits for the transition, at most
+    #[arg(long, value_name = "SECONDS", default_value = "60")]
+    pub sync_timeout: u64,
+
+    /// Play after what is already on its way to the outputs, instead of replacing it
+    ///
+    /// By default, a new image interrupts the transition playing, and cancels any animation still
+    /// being prepared for the outputs. With this, the transition waits for them, and the
+    /// animations still play. It is what every image does when the daemon's
+    /// `--transition-interrupt-policy` is 'keep'.
+    #[arg(long)]
+    pub queue: bool,
 
     /// Comma separated list of outputs to display the image at.
     ///
-    /// If it isn't set, the image is displayed on all outputs.
+    /// If it isn't set, the image is displayed on all outputs. Besides names, takes globs, like
+    /// `DP-*`, and regular expressions, after `re:`, like `re:eDP.*`, which must match whole names.
+    ///
+    /// Each output may also get its own image, like `DP-1:a.png,HDMI-A-1:b.gif`, in which case
+    /// the path to the image should not be given. Every other option applies to all images, and
+    /// all transitions start at the same time.
     #[arg(short, long, default_value = "")]
     pub outputs: String,
 
+    /// Play a transition of its own on an output, like `DP-1=wipe:angle=30,duration=2000`
+    ///
+    /// The transition is written like for `swww transition-preview`, and its options override the
+    /// `--transition-*` ones on that output only, and those of `--batch-file`. Give it once for
+    /// each output that needs one. All transitions still start at the same time.
+    #[arg(long, value_parser = parse_output_transition)]
+    pub output_transition: Vec<(String, String)>,
+
+    /// Span the image across all outputs, as if they were a single big screen
+    ///
+    /// Every output displays the part of the image that matches where it is in the compositor's
+    /// layout, so a panorama continues seamlessly from one monitor to the next. `--resize` decides
+    /// how the image covers the whole layout. Animated images are not supported.
+    #[arg(long, conflicts_with = "batch_file")]
+    pub span: bool,
+
     /// Do not resize the image. Equivalent to `--resize=no`
     ///
     /// If this is set, the image won't be resized, and will be centralized in the middle of the
@@ -240,10 +1149,183 @@ pub struct Img {
     )]
     pub resize: ResizeStrategy,
 
+    /// Only show this part of the image, given in pixels as WIDTHxHEIGHT+X+Y
+    ///
+    /// swww treats that part like the whole image from then on: --resize resizes it to the
+    /// outputs, and --zoom zooms into it. Every frame of animations is cropped the same way. Has
+    /// no effect on svgs.
+    #[arg(long, value_parser = parse_crop)]
+    pub crop: Option<(u32, u32, u32, u32)>,
+
+    /// Zoom into the image by this factor, like 1.3, before resizing it
+    ///
+    /// 1.0, the default, shows all of it, or 1.1, with --ken-burns. See --offset for which part we
+    /// zoom into.
+    #[arg(
+        long,
+        default_value = "1.0",
+        default_value_if("ken_burns", "true", "1.1"),
+        value_parser = parse_zoom
+    )]
+    pub zoom: f32,
+
+    /// Which part of the image --zoom shows, as X,Y
+    ///
+    /// Both go from -1.0, the left or top edge of the image, to 1.0, its right or bottom edge.
+    /// 0,0, the default, zooms into its middle.
+    #[arg(long, default_value = "0,0", value_parser = parse_offset, allow_hyphen_values = true)]
+    pub offset: (f32, f32),
+
+    /// Turn still images into an animation that slowly zooms into --zoom, at --offset, and back
+    /// out again
+    ///
+    /// Every frame is made before it plays, at --fps frames per second, so longer and smoother
+    /// zooms take more memory. Has no effect on animated images.
+    #[arg(long)]
+    pub ken_burns: bool,
+
+    /// How long --ken-burns takes to zoom in, in seconds. Zooming back out takes as long again
+    #[arg(long, default_value = "60", value_parser = parse_ken_burns_duration)]
+    pub ken_burns_duration: f32,
+
+    /// When cropping, center the crop on the largest face in the image
+    ///
+    /// This keeps people in portrait photos from having their heads cut off when displayed on a
+    /// landscape monitor. If no face is found, we crop around the center of the image, as usual.
+    /// Only works for static images, and requires both `--face-model` and swww to be compiled with
+    /// the `face-detect` feature.
+    #[arg(long)]
+    pub auto_crop_faces: bool,
+
+    /// Path to the face detection model used by `--auto-crop-faces`
+    ///
+    /// This is the SeetaFace frontal face model, `seeta_fd_frontal_v1.0.bin`, distributed with the
+    /// rustface crate.
+    #[arg(long, env = "SWWW_FACE_MODEL")]
+    pub face_model: Option<PathBuf>,
+
     /// Which color to fill the padding with when output image does not fill screen
     #[arg(value_parser = from_hex, long, default_value = "000000")]
     pub fill_color: [u8; 3],
 
+    /// Pixels less opaque than this (from 0 to 255) show the fill color, and all others become
+    /// fully opaque
+    ///
+    /// Useful for images with nearly transparent pixels, that would otherwise show up as faint
+    /// ghosts. With the default of 0, transparency is ignored, and only the colors are used.
+    #[arg(long, default_value = "0")]
+    pub alpha_threshold: u8,
+
+    /// Keep the transparency of animated images, instead of dropping it
+    ///
+    /// The compositor then blends the animation with whatever is below it. Static images, and the
+    /// first frame of animations (until the animation starts), are always shown opaque. Cannot be
+    /// used with --alpha-threshold.
+    #[arg(long, conflicts_with = "alpha_threshold")]
+    pub keep_alpha: bool,
+
+    /// Dither the image down to the daemon's buffers, when they hold fewer bits per channel
+    ///
+    /// The daemon dithers every frame it draws, as it converts it to its buffers, which is the
+    /// last thing it does to it. Images are processed with 8 bits per channel, so this only
+    /// changes anything when the daemon draws with fewer, like with `swww-daemon --shm-format
+    /// rgb565`, where it hides the banding of gradients.
+    #[arg(long, value_enum)]
+    pub dither: Option<Dither>,
+
+    /// Shift the image's color temperature to this many kelvin, from 1000 to 10000
+    ///
+    /// 6500 is neutral. Lower values make the image warmer (redder), while higher values make it
+    /// cooler (bluer).
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1000..=10000))]
+    pub color_temp: Option<u32>,
+
+    /// Blur the busy, high-contrast regions of the image, like foliage, leaving flat ones sharp
+    ///
+    /// The blur is a gaussian of this standard deviation, in pixels, blended in proportionally to
+    /// how much the colors vary around each pixel. Flat regions, like skies, are left untouched.
+    /// Must be a positive number.
+    #[arg(long, value_parser = parse_heatmap_blur)]
+    pub heatmap_blur: Option<f32>,
+
+    #[command(flatten)]
+    pub adjustments: Adjustments,
+
+    /// Play animated images this many times faster than their own frame delays say
+    ///
+    /// For example, 0.5 plays them at half speed, and 2 at double speed. Must be a positive
+    /// number. Has no effect on static images.
+    #[arg(long, value_parser = parse_time_stretch)]
+    pub time_stretch: Option<f32>,
+
+    /// Have the daemon play animated images this many times faster than their frame delays say
+    ///
+    /// Unlike `--time-stretch`, the frames stay the same, so they can still be cached, and
+    /// `swww playback-speed` can change the speed later, without sending the image again. Must be
+    /// a positive number.
+    #[arg(long, value_parser = parse_playback_speed, default_value = "1.0")]
+    pub playback_speed: f32,
+
+    /// Which way to play animated images
+    ///
+    /// `reverse` plays them backward, from the first frame to the last one, and from there down to
+    /// the first again. `ping-pong` plays them forward, then backward, then forward again, and so
+    /// on, instead of jumping from the last frame back to the first one. Both make the animation
+    /// twice as big (or, for `reverse`, as slow to make), since frames only hold what changed from
+    /// the one before, and going backward needs the opposite changes.
+    #[arg(
+        long,
+        value_enum,
+        default_value = "forward",
+        default_value_if("loop_boomerang", "true", "ping-pong")
+    )]
+    pub playback: PlaybackDirection,
+
+    /// Play animated images forward, then backward, then forward again, and so on. Equivalent to
+    /// `--playback=ping-pong`, which overrides it
+    #[arg(long)]
+    pub loop_boomerang: bool,
+
+    /// How many times to play animated images, or `infinite`, to loop them forever
+    ///
+    /// With `--playback ping-pong`, going forward and then backward again counts as one loop.
+    #[arg(long = "loop", value_parser = parse_loops, default_value = "infinite")]
+    pub loops: Loops,
+
+    /// What to show once an animated image played --loop times
+    ///
+    /// `clear` fills the output with --fill-color. Has no effect with `--loop infinite`.
+    #[arg(long, value_enum, default_value_t)]
+    pub at_end: AtEnd,
+
+    /// Start playing animated images from this frame, instead of the first one
+    ///
+    /// Only the daemon uses it, to resume animations on the frame they were on after their output
+    /// changes size. Frames are counted from 0, and wrap around after the last one.
+    #[arg(long, default_value = "0", hide = true)]
+    pub start_frame: u32,
+
+    /// Play videos at this many frames per second, instead of their own frame rate
+    ///
+    /// Videos (mp4, webm, mkv, mov, avi and m4v files) are decoded with `ffmpeg`, which must be
+    /// installed. Lower frame rates make them faster to process and lighter to play.
+    #[arg(long, value_parser = parse_fps)]
+    pub video_fps: Option<f32>,
+
+    /// How many times to play videos, or `infinite`, to loop them forever. Defaults to --loop
+    ///
+    /// So that short clips can keep looping while other animated images play once, or the other
+    /// way around.
+    #[arg(long, value_parser = parse_loops)]
+    pub video_loop: Option<Loops>,
+
+    /// Play the frames of GIFs whose delay is shorter than this many milliseconds for 100ms
+    ///
+    /// Many GIFs leave their delays at 0 or 10ms, which browsers play at 100ms, and which their
+    /// authors expect. Use 0 to play every frame for as long as it says instead.
+    #[arg(long, value_name = "MS", default_value_t = 20)]
+    pub gif_min_delay: u64,
+
     ///Filter to use when scaling images (run swww img --help to see options).
     ///
     ///Available options are:
@@ -261,11 +1343,64 @@ pub struct Img {
     #[arg(short, long, default_value = "Lanczos3")]
     pub filter: Filter,
 
+    /// Which color space to convert images from, to the sRGB outputs show
+    ///
+    /// 'embedded' uses the ICC profile in png, jpeg, webp and tiff images, so that wide gamut
+    /// photos, like Display P3 ones, don't look washed out. 'display-p3' is for images that lost
+    /// their profile. Only still images and matrix/TRC profiles are converted, and only if swww was
+    /// compiled with the `icc` feature. Lookup table (LUT) profiles, like printer ones, are skipped
+    /// with a warning, and the image is shown as it is.
+    #[arg(long, value_enum, default_value_t)]
+    pub color_profile: ColorProfile,
+
+    /// Scale images, and blend them into each other during transitions, in linear light
+    ///
+    /// Averaging the sRGB bytes images are stored as, which is what scaling and blending do, comes
+    /// out darker than it should: fine detail dims when the image is shrunk, and fades dip in
+    /// brightness halfway through. This converts the pixels to linear light for it, and back,
+    /// which costs a little more time and memory while scaling.
+    #[arg(long)]
+    pub gamma_correct: bool,
+
+    /// How to compress the frames of animated images
+    ///
+    /// This also applies to the animations in the cache, which are kept apart for each of these.
+    #[arg(long, value_enum, default_value_t)]
+    pub compression: Compression,
+
+    /// How many pixels to compare at a time when diffing the frames of animated images: 1, 2, 4 or 8
+    ///
+    /// Bigger blocks are faster for the daemon to unpack, but may make the animation bigger.
+    /// By default, we pick whichever packs the first frames of the animation the best.
+    #[arg(long, value_parser = parse_diff_block_size)]
+    pub diff_block_size: Option<u8>,
+
+    /// Make the frames of animated images again every loop, instead of having the daemon keep them
+    ///
+    /// The daemon then only holds the few frames it is about to play, while `swww img` keeps
+    /// running for as long as the animation plays, making (or, if they are in the cache, reading)
+    /// its frames over again, a few at a time. This trades CPU for memory, for animations too big
+    /// to hold whole. Animations for outputs of different sizes, played in reverse or ping-pong, or
+    /// resumed by the daemon after their output changed size, are never streamed.
+    #[arg(long)]
+    pub stream: bool,
+
+    ///Send very large images to the daemon as a JPEG of this quality, from 1 to 100
+    ///
+    ///Only images larger than --quality-threshold once resized are transcoded, since that is
+    ///lossy. Without this, images are always sent losslessly, however large they are.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+
+    ///How large (in MiB of raw pixels) an image must be for --quality to apply
+    #[arg(long, default_value = "32", requires = "quality")]
+    pub quality_threshold: u32,
+
     ///Sets the type of transition. Default is 'simple', that fades into the new image
     ///
     ///Possible transitions are:
     ///
-    ///none | simple | fade | left | right | top | bottom | wipe | wave | grow | center | any | outer | random
+    ///none | simple | fade | left | right | top | bottom | wipe | wave | grow | center | any | outer | slide | zoom-out | burn | crossfade | random
     ///
     ///The 'left', 'right', 'top' and 'bottom' options make the transition happen from that
     ///position to its opposite in the screen.
@@ -288,10 +1423,34 @@ pub struct Img {
     ///
     ///'outer' is the same as grow but the circle shrinks instead of growing.
     ///
+    ///'slide' scrolls the new image in from the edge given with `--transition-direction`, pushing
+    ///the old one out.
+    ///
+    ///'zoom-out' shrinks the old image into the center of the screen, uncovering the
+    ///`--fill-color`, and then grows the new image from there.
+    ///
+    ///'burn' burns the old image away like paper, in a circle growing from `--transition-pos`,
+    ///leaving a band of fire and a charred edge in front of the new image.
+    ///
+    ///'crossfade' is like 'fade', but an animation that was playing keeps playing while it fades
+    ///out, and so does the new one while it fades in, instead of freezing on a frame.
+    ///
     ///Finally, 'random' will select a transition effect at random
     #[arg(short, long, env = "SWWW_TRANSITION", default_value = "simple")]
     pub transition_type: TransitionType,
 
+    ///The transition to play instead on outputs displaying their first image
+    ///
+    ///That is, the first image after the daemon starts, or after an output is plugged in, which
+    ///would otherwise transition from a black screen. Takes the same values as
+    ///--transition-type, and every other --transition-* option still applies.
+    #[arg(long, env = "SWWW_TRANSITION_ON_STARTUP", default_value = "fade")]
+    pub transition_on_startup: TransitionType,
+
+    ///Show the image right away on outputs displaying their first image, without a transition
+    #[arg(long)]
+    pub no_transition_on_startup: bool,
+
     ///How fast the transition approaches the new image.
     ///
     ///The transition logic works by adding or subtracting from the current rgb values until the
@@ -312,7 +1471,7 @@ pub struct Img {
     ///How long the transition takes to complete in seconds.
     ///
     ///Note that this doesn't work with the 'simple' transition
-    #[arg(long, env = "SWWW_TRANSITION_DURATION", default_value = "3")]
+    #[arg(long, env = "SWWW_TRANSITION_DURATION", default_value = "3", value_parser = parse_transition_duration)]
     pub transition_duration: f32,
 
     ///Frame rate for the transition effect.
@@ -321,9 +1480,16 @@ pub struct Img {
     ///
     ///Also note this is **different** from the transition-step. That one controls by how much we
     ///approach the new image every frame.
-    #[arg(long, env = "SWWW_TRANSITION_FPS", default_value = "30")]
+    #[arg(long, env = "SWWW_TRANSITION_FPS", default_value = "30", value_parser = parse_transition_fps)]
     pub transition_fps: u8,
 
+    ///Use the refresh rate of the outputs as the transition's frame rate
+    ///
+    ///For example, transitions play at 144 fps on a 144Hz monitor, and at 60 fps on a 60Hz one.
+    ///Outputs that don't report their refresh rate use --transition-fps instead.
+    #[arg(long)]
+    pub transition_fps_adaptive: bool,
+
     ///This is used for the 'wipe' and 'wave' transitions. It controls the angle of the wipe
     ///
     ///Note that the angle is in degrees, where '0' is right to left and '90' is top to bottom, and '270' bottom to top
@@ -339,6 +1505,9 @@ pub struct Img {
     ///
     ///the value can also be an alias which will set the position accordingly):
     /// 'center' | 'top' | 'left' | 'right' | 'bottom' | 'top-left' | 'top-right' | 'bottom-left' | 'bottom-right'
+    ///
+    ///'cursor' starts from the pointer, on the output it is on, and from the center elsewhere. This
+    ///requires running the daemon with --track-cursor.
     #[arg(long, env = "SWWW_TRANSITION_POS", default_value = "center", value_parser=parse_coords)]
     pub transition_pos: CliPosition,
 
@@ -350,12 +1519,67 @@ pub struct Img {
     ///https://cubic-bezier.com is a good website to get these values from
     ///
     ///eg: 0.0,0.0,1.0,1.0 for linear animation
+    ///
+    ///The css presets are also accepted: 'linear' | 'ease' | 'ease-in' | 'ease-out' | 'ease-in-out'
     #[arg(long, env = "SWWW_TRANSITION_BEZIER", default_value = ".54,0,.34,.99", value_parser = parse_bezier)]
     pub transition_bezier: (f32, f32, f32, f32),
 
     ///currently only used for 'wave' transition to control the width and height of each wave
     #[arg(long, env = "SWWW_TRANSITION_WAVE", default_value = "20,20", value_parser = parse_wave)]
     pub transition_wave: (f32, f32),
+
+    ///Play the transition backwards
+    ///
+    ///Only affects the 'wipe', 'wave', 'grow' and 'outer' transitions (and their aliases). The
+    ///sweep starts where it would normally end, and the new image shows up from where the old one
+    ///would have stayed the longest.
+    #[arg(long)]
+    pub transition_reverse: bool,
+
+    ///How many times to play the transition before settling on the new image
+    ///
+    ///Every time takes an equal part of the transition's duration, and every time but the last
+    ///starts over from the old image. Has no effect on the 'simple' transition.
+    #[arg(long, env = "SWWW_TRANSITION_LOOP", default_value = "1", value_parser = clap::value_parser!(u8).range(1..))]
+    pub transition_loop: u8,
+
+    ///This is only used for the 'slide' transition. It controls which edge of the screen the new
+    ///image enters from
+    #[arg(long, env = "SWWW_TRANSITION_DIRECTION", default_value = "left")]
+    pub transition_direction: Direction,
+
+    ///Save the start, middle and end of the transition as png files, instead of applying it
+    ///
+    ///The frames are written to `<PREFIX>_start.png`, `<PREFIX>_mid.png` and `<PREFIX>_end.png`.
+    ///They are rendered at the size of the first output, transitioning from what it currently
+    ///displays.
+    #[arg(long, value_name = "PREFIX")]
+    pub transition_preview_file: Option<PathBuf>,
+
+    ///Skip the transition when the new image is a different scene from the current one
+    ///
+    ///On every output, we compare the color histograms of what it displays, whichever command
+    ///put it there, and the new image. If they differ by more than `--scene-change-threshold`, we
+    ///cut straight to the new image on that output, as if `--transition-type none` had been
+    ///given. Otherwise, we use the transition set above.
+    ///
+    ///We also compare every frame of videos with the one before it. Frames of the same scene play
+    ///the transition set above between them, over the first frame's delay at most, and the first
+    ///frame of a new scene is shown as it is.
+    #[arg(long)]
+    pub detect_scene_change: bool,
+
+    ///How different two images must be to count as a scene change (see `--detect-scene-change`)
+    ///
+    ///This is the L1 distance between the normalized 64-bin RGB histograms of both images, so it
+    ///goes from 0.0 (identical colors) to 2.0 (no colors in common).
+    #[arg(long, default_value = "0.4")]
+    pub scene_change_threshold: f32,
+
+    // The arguments we were parsed from, for the daemon to make the image again, see
+    // `ipc::Remake`. We set them ourselves
+    #[arg(skip)]
+    pub remake_args: Vec<String>,
 }
 
 fn parse_wave(raw: &str) -> Result<(f32, f32), String> {
@@ -366,11 +1590,250 @@ fn parse_wave(raw: &str) -> Result<(f32, f32), String> {
             .and_then(|s| s.parse::<f32>().map_err(|e| e.to_string()))
     };
 
-    let parsed = (parse()?, parse()?);
-    Ok(parsed)
+    let (width, height) = (parse()?, parse()?);
+    if !width.is_finite() || width <= 0.0 {
+        return Err(format!(
+            "wave width must be a positive number, got: {width}"
+        ));
+    }
+    if !height.is_finite() {
+        return Err(format!("wave height must be a number, got: {height}"));
+    }
+    Ok((width, height))
+}
+
+fn parse_transition_duration(raw: &str) -> Result<f32, String> {
+    let secs = raw.parse::<f32>().map_err(|e| e.to_string())?;
+    if !secs.is_finite() || secs < 0.0 {
+        return Err(format!(
+            "transition duration must be a number of seconds, got: {raw}"
+        ));
+    }
+    Ok(secs)
+}
+
+fn parse_transition_fps(raw: &str) -> Result<u8, String> {
+    match raw.parse::<u8>() {
+        Ok(fps) if fps > 0 => Ok(fps),
+        _ => Err(format!(
+            "transition fps must be between 1 and {}, got: {raw}",
+            u8::MAX
+        )),
+    }
+}
+
+fn parse_time_stretch(raw: &str) -> Result<f32, String> {
+    let factor = raw.parse::<f32>().map_err(|e| e.to_string())?;
+    if !factor.is_finite() || factor <= 0.0 {
+        return Err(format!(
+            "time stretch must be a positive number, got: {raw}"
+        ));
+    }
+    Ok(factor)
+}
+
+fn parse_playback_speed(raw: &str) -> Result<f32, String> {
+    let speed = raw.parse::<f32>().map_err(|e| e.to_string())?;
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(format!(
+            "playback speed must be a positive number, got: {raw}"
+        ));
+    }
+    Ok(speed)
+}
+
+fn parse_dimensions(raw: &str) -> Result<(u32, u32), String> {
+    let positive = |n: &str| n.parse::<u16>().ok().filter(|&n| n > 0);
+    match raw.split_once('x').map(|(w, h)| (positive(w), positive(h))) {
+        Some((Some(width), Some(height))) => Ok((width.into(), height.into())),
+        _ => Err(format!(
+            "size must be a positive width and height, like 800x600, got: {raw}"
+        )),
+    }
+}
+
+fn parse_grid(raw: &str) -> Result<(u32, u32), String> {
+    let positive = |n: &str| n.parse::<u8>().ok().filter(|&n| n > 0);
+    match raw.split_once('x').map(|(c, r)| (positive(c), positive(r))) {
+        Some((Some(columns), Some(rows))) => Ok((columns.into(), rows.into())),
+        _ => Err(format!(
+            "grid must be a positive number of columns and rows, like 2x2, got: {raw}"
+        )),
+    }
+}
+
+fn parse_interval(raw: &str) -> Result<u32, String> {
+    let (number, unit) = match raw.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &raw[number.len()..]),
+        None => (raw, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        _ => 60 * 60,
+    };
+    match number
+        .parse::<u32>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(secs) if secs > 0 => Ok(secs),
+        _ => Err(format!(
+            "interval must be a positive number of seconds, optionally followed by s, m or h, \
+             got: {raw}"
+        )),
+    }
+}
+
+fn parse_transition_type(raw: &str) -> Result<String, String> {
+    raw.parse::<TransitionType>()?;
+    Ok(raw.to_string())
+}
+
+fn parse_loops(raw: &str) -> Result<Loops, String> {
+    if raw == "infinite" {
+        return Ok(Loops::Infinite);
+    }
+    match raw.parse::<u32>() {
+        Ok(0) | Err(_) => Err(format!(
+            "expected a positive number of loops, or `infinite`, got: {raw}"
+        )),
+        Ok(n) => Ok(Loops::Times(n)),
+    }
+}
+
+/// Parses crops like `1920x1080+100+0`
+fn parse_crop(raw: &str) -> Result<(u32, u32, u32, u32), String> {
+    let err = || format!("crop must look like WIDTHxHEIGHT+X+Y, like 1920x1080+100+0, got: {raw}");
+    let (size, position) = raw.split_once('+').ok_or_else(err)?;
+    let (width, height) = size.split_once('x').ok_or_else(err)?;
+    let (x, y) = position.split_once('+').ok_or_else(err)?;
+    let parse = |s: &str| s.parse::<u32>().map_err(|_| err());
+    let (width, height) = (parse(width)?, parse(height)?);
+    if width == 0 || height == 0 {
+        return Err(err());
+    }
+    Ok((parse(x)?, parse(y)?, width, height))
+}
+
+fn parse_zoom(raw: &str) -> Result<f32, String> {
+    match raw.parse::<f32>() {
+        Ok(zoom) if zoom.is_finite() && zoom >= 1.0 => Ok(zoom),
+        _ => Err(format!("zoom must be a number of at least 1.0, got: {raw}")),
+    }
+}
+
+/// Parses offsets like `0.2,-0.5`
+fn parse_offset(raw: &str) -> Result<(f32, f32), String> {
+    let err = || format!("offset must be two numbers between -1.0 and 1.0, like 0.2,0, got: {raw}");
+    let (x, y) = raw.split_once(',').ok_or_else(err)?;
+    let parse = |s: &str| match s.trim().parse::<f32>() {
+        Ok(n) if (-1.0..=1.0).contains(&n) => Ok(n),
+        _ => Err(err()),
+    };
+    Ok((parse(x)?, parse(y)?))
+}
+
+fn parse_ken_burns_duration(raw: &str) -> Result<f32, String> {
+    match raw.parse::<f32>() {
+        Ok(duration) if duration.is_finite() && duration > 0.0 => Ok(duration),
+        _ => Err(format!(
+            "ken burns duration must be a positive number of seconds, got: {raw}"
+        )),
+    }
+}
+
+fn parse_fps(raw: &str) -> Result<f32, String> {
+    let fps = raw.parse::<f32>().map_err(|e| e.to_string())?;
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(format!("fps must be a positive number, got: {raw}"));
+    }
+    Ok(fps)
+}
+
+fn parse_tint(raw: &str) -> Result<Tint, String> {
+    if raw == "none" {
+        return Ok(Tint::Gains([1.0; 3]));
+    }
+    if let Some(kelvin) = raw.strip_suffix(['K', 'k']) {
+        let kelvin = kelvin.parse::<u32>().map_err(|e| e.to_string())?;
+        if !(1000..=10000).contains(&kelvin) {
+            return Err(format!(
+                "temperature must be from 1000K to 10000K, got: {raw}"
+            ));
+        }
+        return Ok(Tint::Kelvin(kelvin));
+    }
+    let gains: Vec<_> = raw
+        .split(',')
+        .map(|gain| gain.trim().parse::<f32>().map_err(|e| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    match gains[..] {
+        [r, g, b] if gains.iter().all(|gain| gain.is_finite() && *gain >= 0.0) => {
+            Ok(Tint::Gains([r, g, b]))
+        }
+        _ => Err(format!(
+            "tint must be a temperature, like 4500K, or three non-negative multipliers, like \
+             1,0.9,0.8, got: {raw}"
+        )),
+    }
+}
+
+fn parse_opacity(raw: &str) -> Result<f32, String> {
+    match raw.parse::<f32>() {
+        Ok(opacity) if (0.0..=1.0).contains(&opacity) => Ok(opacity),
+        _ => Err(format!("opacity must be between 0.0 and 1.0, got: {raw}")),
+    }
+}
+
+fn parse_blur(raw: &str) -> Result<f32, String> {
+    let sigma = raw.parse::<f32>().map_err(|e| e.to_string())?;
+    if !sigma.is_finite() || sigma <= 0.0 {
+        return Err(format!("blur must be a positive number, got: {raw}"));
+    }
+    Ok(sigma)
+}
+
+fn parse_heatmap_blur(raw: &str) -> Result<f32, String> {
+    let sigma = raw.parse::<f32>().map_err(|e| e.to_string())?;
+    if !sigma.is_finite() || sigma <= 0.0 {
+        return Err(format!(
+            "heatmap blur must be a positive number, got: {raw}"
+        ));
+    }
+    Ok(sigma)
+}
+
+fn parse_output_transition(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((output, transition)) if !output.is_empty() && !transition.is_empty() => {
+            Ok((output.to_string(), transition.to_string()))
+        }
+        _ => Err(format!(
+            "expected an output and its transition, like DP-1=fade:duration=500, got: {raw}"
+        )),
+    }
+}
+
+fn parse_diff_block_size(raw: &str) -> Result<u8, String> {
+    match raw.parse::<u8>() {
+        Ok(size @ (1 | 2 | 4 | 8)) => Ok(size),
+        _ => Err(format!(
+            "diff block size must be one of 1, 2, 4 or 8, got: {raw}"
+        )),
+    }
 }
 
 fn parse_bezier(raw: &str) -> Result<(f32, f32, f32, f32), String> {
+    // the same presets as css
+    match raw {
+        "linear" => return Ok((0.0, 0.0, 1.0, 1.0)),
+        "ease" => return Ok((0.25, 0.1, 0.25, 1.0)),
+        "ease-in" => return Ok((0.42, 0.0, 1.0, 1.0)),
+        "ease-out" => return Ok((0.0, 0.0, 0.58, 1.0)),
+        "ease-in-out" => return Ok((0.42, 0.0, 0.58, 1.0)),
+        _ => (),
+    }
     let mut iter = raw.split(',');
     let mut parse = || {
         iter.next()
@@ -382,6 +1845,17 @@ fn parse_bezier(raw: &str) -> Result<(f32, f32, f32, f32), String> {
     if parsed == (0.0, 0.0, 0.0, 0.0) {
         return Err("Invalid bezier curve: 0,0,0,0 (try using 0,0,1,1 instead)".to_string());
     }
+    // the x values are points in time, so the curve would go back in time outside of [0, 1]. The
+    // y values may go past them, to overshoot
+    let (x1, y1, x2, y2) = parsed;
+    if !(0.0..=1.0).contains(&x1) || !(0.0..=1.0).contains(&x2) {
+        return Err(format!(
+            "Invalid bezier curve: {raw} (the 1st and 3rd values must be between 0 and 1)"
+        ));
+    }
+    if !y1.is_finite() || !y2.is_finite() {
+        return Err(format!("Invalid bezier curve: {raw}"));
+    }
     Ok(parsed)
 }
 
@@ -396,6 +1870,12 @@ fn parse_coords(raw: &str) -> Result<CliPosition, String> {
                     CliCoord::Percent(0.5),
                 ));
             }
+            "cursor" => {
+                return Ok(CliPosition {
+                    cursor: true,
+                    ..CliPosition::new(CliCoord::Percent(0.5), CliCoord::Percent(0.5))
+                });
+            }
             "top" => {
                 return Ok(CliPosition::new(
                     CliCoord::Percent(0.5),
@@ -474,6 +1954,237 @@ fn parse_coords(raw: &str) -> Result<CliPosition, String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn should_parse_the_cursor_as_a_position() {
+        let pos = parse_coords("cursor").unwrap();
+        assert!(pos.cursor);
+        assert!(matches!(
+            (pos.x, pos.y),
+            (CliCoord::Percent(x), CliCoord::Percent(y)) if x == 0.5 && y == 0.5
+        ));
+        assert!(!parse_coords("center").unwrap().cursor);
+    }
+
+    #[test]
+    fn help_should_list_the_image_extensions_we_default_to() {
+        let cmd = <Cli as clap::CommandFactory>::command();
+        let img = cmd.find_subcommand("img").unwrap();
+        let arg = img
+            .get_arguments()
+            .find(|arg| arg.get_id() == "extensions")
+            .unwrap();
+        let help = arg.get_long_help().unwrap().to_string();
+        assert!(help.contains(&utils::IMAGE_EXTENSIONS.join(",")), "{help}");
+    }
+
+    #[test]
+    fn should_reject_waves_without_width() {
+        assert_eq!(parse_wave("20,10").unwrap(), (20.0, 10.0));
+        assert_eq!(parse_wave("5,0").unwrap(), (5.0, 0.0));
+        assert!(parse_wave("0,20").is_err());
+        assert!(parse_wave("-5,20").is_err());
+        assert!(parse_wave("NaN,20").is_err());
+        assert!(parse_wave("20,inf").is_err());
+        assert!(parse_wave("20").is_err());
+    }
+
+    #[test]
+    fn should_parse_bezier_curves() {
+        assert_eq!(
+            parse_bezier(".54,0,.34,.99").unwrap(),
+            (0.54, 0.0, 0.34, 0.99)
+        );
+        assert_eq!(parse_bezier("ease-out").unwrap(), (0.0, 0.0, 0.58, 1.0));
+        // overshooting is fine, going back in time is not
+        assert!(parse_bezier("0.5,-0.5,0.5,1.5").is_ok());
+        assert!(parse_bezier("1.5,0,0.5,1").is_err());
+        assert!(parse_bezier("0.5,0,-0.1,1").is_err());
+        assert!(parse_bezier("0.5,NaN,0.5,1").is_err());
+        assert!(parse_bezier("0,0,0,0").is_err());
+        assert!(parse_bezier("0.5,0,0.5").is_err());
+    }
+
+    #[test]
+    fn should_parse_transition_durations_and_fps() {
+        assert_eq!(parse_transition_duration("0.5").unwrap(), 0.5);
+        assert_eq!(parse_transition_duration("0").unwrap(), 0.0);
+        assert!(parse_transition_duration("-1").is_err());
+        assert!(parse_transition_duration("inf").is_err());
+        assert_eq!(parse_transition_fps("144").unwrap(), 144);
+        assert!(parse_transition_fps("0").is_err());
+        assert!(parse_transition_fps("300").is_err());
+    }
+
+    #[test]
+    fn should_parse_crops_zooms_and_offsets() {
+        assert_eq!(parse_crop("1920x1080+100+0").unwrap(), (100, 0, 1920, 1080));
+        assert!(parse_crop("1920x1080").is_err());
+        assert!(parse_crop("0x1080+0+0").is_err());
+        assert!(parse_crop("1920x1080+-1+0").is_err());
+        assert_eq!(parse_zoom("1.3").unwrap(), 1.3);
+        assert!(parse_zoom("0.5").is_err());
+        assert_eq!(parse_offset("0.2,-1").unwrap(), (0.2, -1.0));
+        assert!(parse_offset("0.2").is_err());
+        assert!(parse_offset("2,0").is_err());
+        assert_eq!(parse_ken_burns_duration("60").unwrap(), 60.0);
+        assert!(parse_ken_burns_duration("0").is_err());
+    }
+
+    #[test]
+    fn should_parse_opacities() {
+        assert_eq!(parse_opacity("0.6").unwrap(), 0.6);
+        assert_eq!(parse_opacity("1").unwrap(), 1.0);
+        assert!(parse_opacity("1.5").is_err());
+        assert!(parse_opacity("NaN").is_err());
+    }
+
+    #[test]
+    fn should_parse_loop_counts() {
+        assert_eq!(parse_loops("infinite").unwrap(), Loops::Infinite);
+        assert_eq!(parse_loops("3").unwrap(), Loops::Times(3));
+        assert!(parse_loops("0").is_err());
+        assert!(parse_loops("-1").is_err());
+        assert!(parse_loops("forever").is_err());
+    }
+
+    #[test]
+    fn previews_should_take_a_size_and_the_transition_with_its_start() {
+        let swww = Swww::try_parse_from([
+            "swww",
+            "preview",
+            "a.gif",
+            "--size",
+            "2560x1440",
+            "--resize",
+            "fit",
+            "--filter",
+            "lanczos",
+            "-o",
+            "out.png",
+        ])
+        .unwrap();
+        let Swww::Preview(preview) = swww else {
+            panic!("expected a preview");
+        };
+        assert_eq!(preview.size, (2560, 1440));
+        assert_eq!(preview.resize, ResizeStrategy::Fit);
+        assert!(matches!(preview.filter, Filter::Lanczos3));
+        assert!(parse_dimensions("0x1440").is_err());
+        assert!(Swww::try_parse_from([
+            "swww",
+            "preview",
+            "a.gif",
+            "-o",
+            "b.png",
+            "--transition",
+            "fade"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn namespace_may_come_before_or_after_the_command() {
+        for args in [
+            ["swww", "--namespace", "nested", "query"],
+            ["swww", "query", "--namespace", "nested"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert_eq!(cli.namespace.as_deref(), Some("nested"));
+            assert!(matches!(cli.command, Swww::Query { .. }));
+        }
+    }
+
+    #[test]
+    fn should_parse_slideshow_intervals() {
+        assert_eq!(parse_interval("90").unwrap(), 90);
+        assert_eq!(parse_interval("30s").unwrap(), 30);
+        assert_eq!(parse_interval("10m").unwrap(), 600);
+        assert_eq!(parse_interval("2h").unwrap(), 7200);
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval("1d").is_err());
+    }
+
+    #[test]
+    fn anchor_should_require_a_size() {
+        assert!(Swww::try_parse_from(["swww", "init", "--anchor", "top-right"]).is_err());
+        let Swww::Init { anchor, margin, .. } = Swww::try_parse_from([
+            "swww",
+            "init",
+            "--anchor",
+            "top-right",
+            "--size",
+            "800x600",
+            "--margin",
+            "20",
+        ])
+        .unwrap() else {
+            panic!("expected `swww init`");
+        };
+        assert_eq!(anchor, Some(Anchor::TopRight));
+        assert_eq!(margin.as_deref(), Some("20"));
+    }
+
+    #[test]
+    fn should_only_accept_positive_playback_speeds() {
+        assert_eq!(parse_playback_speed("0.5").unwrap(), 0.5);
+        assert_eq!(parse_playback_speed("2").unwrap(), 2.0);
+        assert!(parse_playback_speed("0").is_err());
+        assert!(parse_playback_speed("-1").is_err());
+        assert!(parse_playback_speed("NaN").is_err());
+    }
+
+    #[test]
+    fn should_parse_adjustments() {
+        let Swww::Adjust {
+            adjustments,
+            outputs,
+        } = Swww::try_parse_from(["swww", "adjust", "--brightness", "-20", "--blur", "2"]).unwrap()
+        else {
+            panic!("expected `swww adjust`");
+        };
+        assert_eq!(adjustments.blur, Some(2.0));
+        assert_eq!(adjustments.brightness, -20);
+        assert_eq!(adjustments.contrast, 0);
+        assert_eq!(adjustments.tone, None);
+        assert!(outputs.is_empty());
+        let Swww::Adjust { adjustments, .. } =
+            Swww::try_parse_from(["swww", "adjust", "--tone", "sepia"]).unwrap()
+        else {
+            panic!("expected `swww adjust`");
+        };
+        assert_eq!(adjustments.tone, Some(Tone::Sepia));
+        assert!(Swww::try_parse_from(["swww", "adjust", "--saturation", "101"]).is_err());
+        assert!(Swww::try_parse_from(["swww", "adjust", "--blur", "0"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_tints() {
+        assert_eq!(parse_tint("4500K").unwrap(), Tint::Kelvin(4500));
+        assert_eq!(parse_tint("none").unwrap(), Tint::Gains([1.0; 3]));
+        assert_eq!(
+            parse_tint("1,0.9,0.8").unwrap(),
+            Tint::Gains([1.0, 0.9, 0.8])
+        );
+        assert!(parse_tint("500K").is_err());
+        assert!(parse_tint("1,0.9").is_err());
+        assert!(parse_tint("1,-0.9,1").is_err());
+        assert!(parse_tint("warm").is_err());
+    }
+
+    #[test]
+    fn should_parse_gradients() {
+        let Swww::Clear(clear) =
+            Swww::try_parse_from(["swww", "clear", "--gradient", "000000", "ffffff"]).unwrap()
+        else {
+            panic!("expected `swww clear`");
+        };
+        assert_eq!(clear.gradient, Some(vec![[0; 3], [255; 3]]));
+        assert_eq!(clear.angle, 0.0);
+        assert!(Swww::try_parse_from(["swww", "clear", "--gradient", "000000"]).is_err());
+        assert!(Swww::try_parse_from(["swww", "clear", "--angle", "45"]).is_err());
+    }
+
     #[test]
     fn should_reject_wrong_colors() {
         assert!(
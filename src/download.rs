@@ -0,0 +1,84 @@
+//! Downloads images for `swww img https://...`.
+//!
+//! We check that what we got is an image before using it, since a server that can't find the
+//! image will happily answer with an html page instead. Downloading requires the `http` feature.
+
+use image::ImageFormat;
+
+use std::path::{Path, PathBuf};
+
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Downloads the image at `url`. If `format` is set, we trust it instead of checking the data
+pub fn fetch(url: &str, format: Option<ImageFormat>) -> Result<Vec<u8>, String> {
+    let data = get(url)?;
+    if format.is_none() && extension(&data).is_none() {
+        return Err(format!("{url} doesn't look like an image we can display"));
+    }
+    Ok(data)
+}
+
+/// Downloads the image at `url` into swww's cache, and returns where we put it
+pub fn fetch_to_cache(url: &str, format: Option<ImageFormat>) -> Result<PathBuf, String> {
+    let data = fetch(url, format)?;
+    let extension = format
+        .and_then(|format| format.extensions_str().first().copied())
+        .or_else(|| extension(&data))
+        .unwrap_or("img");
+    let path = utils::cache::download_path(url, extension)?;
+    std::fs::write(&path, data).map_err(|e| format!("failed to save {url} to {path:?}: {e}"))?;
+    Ok(path)
+}
+
+/// The extension of the image in `data`, or `None`, if we don't recognize it
+fn extension(data: &[u8]) -> Option<&'static str> {
+    if crate::jxl::is_jxl(data) {
+        return Some("jxl");
+    }
+    let format = image::guess_format(data).ok()?;
+    format.extensions_str().first().copied()
+}
+
+#[cfg(feature = "http")]
+fn get(url: &str) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| format!("failed to download {url}: {e}"))?;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut data)
+        .map_err(|e| format!("failed to download {url}: {e}"))?;
+    Ok(data)
+}
+
+#[cfg(not(feature = "http"))]
+fn get(_: &str) -> Result<Vec<u8>, String> {
+    Err("swww was compiled without the `http` feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_recognize_urls() {
+        assert!(is_url(Path::new("https://example.com/a.png")));
+        assert!(is_url(Path::new("http://example.com/a.png")));
+        assert!(!is_url(Path::new("/home/me/a.png")));
+        assert!(!is_url(Path::new("https")));
+        assert!(!is_url(Path::new("-")));
+    }
+
+    #[test]
+    fn should_only_find_extensions_of_images() {
+        assert_eq!(extension(b"GIF89a\x01\0\x01\0"), Some("gif"));
+        assert_eq!(extension(&[0xFF, 0x0A, 0xFA, 0x7F]), Some("jxl"));
+        assert_eq!(extension(b"<!DOCTYPE html><html>Not Found</html>"), None);
+    }
+}
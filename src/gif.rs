@@ -0,0 +1,209 @@
+//! Decodes the frames of GIFs.
+//!
+//! We compose the frames on a canvas ourselves, with the `gif` crate, instead of using `image`'s
+//! decoder, so that every frame is disposed of the way it says, and so that we can clamp the delays
+//! of its frames. Many GIFs leave their delays at 0 or 10ms, which browsers play at 100ms instead,
+//! and which their authors expect.
+
+use gif::{ColorOutput, DecodeOptions, DisposalMethod};
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    Delay, Frame, Frames, ImageError, ImageFormat, RgbaImage,
+};
+
+use std::{io::Read, time::Duration};
+
+/// What frames with delays shorter than the minimum play for, like in browsers
+const CLAMPED_DELAY: Duration = Duration::from_millis(100);
+
+/// Frames with delays shorter than `min_delay` play for 100ms instead
+pub fn into_frames<'a>(reader: impl Read + 'a, min_delay: Duration) -> Result<Frames<'a>, String> {
+    let mut options = DecodeOptions::new();
+    options.set_color_output(ColorOutput::RGBA);
+    let decoder = options
+        .read_info(reader)
+        .map_err(|e| format!("failed to decode gif during animation: {e}"))?;
+    let canvas = RgbaImage::new(decoder.width().into(), decoder.height().into());
+    Ok(Frames::new(Box::new(GifFrames {
+        decoder,
+        previous: canvas.clone(),
+        canvas,
+        dispose: None,
+        min_delay,
+        done: false,
+    })))
+}
+
+struct GifFrames<R: Read> {
+    decoder: gif::Decoder<R>,
+    canvas: RgbaImage,
+    /// The canvas before the last frame was drawn, for `DisposalMethod::Previous`
+    previous: RgbaImage,
+    /// How to dispose of the last frame's region, as `(method, x, y, width, height)`
+    dispose: Option<(DisposalMethod, u32, u32, u32, u32)>,
+    min_delay: Duration,
+    done: bool,
+}
+
+impl<R: Read> GifFrames<R> {
+    fn next_frame(&mut self) -> Result<Option<Frame>, ImageError> {
+        match self.dispose {
+            Some((DisposalMethod::Background, x, y, width, height)) => {
+                // like browsers, we ignore the background color, and show what is under us
+                for y in y..(y + height).min(self.canvas.height()) {
+                    for x in x..(x + width).min(self.canvas.width()) {
+                        self.canvas.put_pixel(x, y, image::Rgba([0; 4]));
+                    }
+                }
+            }
+            Some((DisposalMethod::Previous, ..)) => self.canvas.clone_from(&self.previous),
+            Some((DisposalMethod::Keep | DisposalMethod::Any, ..)) | None => (),
+        }
+
+        let Some(frame) = self.decoder.read_next_frame().map_err(decoding_error)? else {
+            return Ok(None);
+        };
+        let (left, top) = (u32::from(frame.left), u32::from(frame.top));
+        let (width, height) = (u32::from(frame.width), u32::from(frame.height));
+        if frame.buffer.len() != width as usize * height as usize * 4 {
+            return Err(decoding_error("bad frame size"));
+        }
+
+        if frame.dispose == DisposalMethod::Previous {
+            self.previous.clone_from(&self.canvas);
+        }
+        // transparent pixels keep what the canvas had, and frames may go past its borders
+        for (i, pixel) in frame.buffer.chunks_exact(4).enumerate() {
+            let (x, y) = (left + i as u32 % width, top + i as u32 / width);
+            if pixel[3] != 0 && x < self.canvas.width() && y < self.canvas.height() {
+                self.canvas
+                    .put_pixel(x, y, image::Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            }
+        }
+        self.dispose = Some((
+            // the first frame has no canvas to go back to
+            match (self.dispose, frame.dispose) {
+                (None, DisposalMethod::Previous) => DisposalMethod::Background,
+                (_, method) => method,
+            },
+            left,
+            top,
+            width,
+            height,
+        ));
+
+        // GIF delays are in hundredths of a second
+        let delay = match Duration::from_millis(u64::from(frame.delay) * 10) {
+            delay if delay < self.min_delay => CLAMPED_DELAY,
+            delay => delay,
+        };
+        Ok(Some(Frame::from_parts(
+            self.canvas.clone(),
+            0,
+            0,
+            Delay::from_saturating_duration(delay),
+        )))
+    }
+}
+
+impl<R: Read> Iterator for GifFrames<R> {
+    type Item = Result<Frame, ImageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        self.done = !matches!(frame, Some(Ok(_)));
+        frame
+    }
+}
+
+fn decoding_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Gif),
+        e,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::borrow::Cow;
+
+    const RED: u8 = 0;
+    const GREEN: u8 = 1;
+    const CLEAR: u8 = 2;
+
+    /// A 4x4 animation: a red frame, a green 2x2 square over it, that goes away with `dispose`, and
+    /// a 1x1 transparent frame, to see what is left
+    fn gif(dispose: DisposalMethod, delays: [u16; 3]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 0];
+        let mut encoder = gif::Encoder::new(&mut out, 4, 4, &palette).unwrap();
+        let mut write = |left, top, size: usize, color, dispose, delay| {
+            let buffer = vec![color; size * size];
+            encoder
+                .write_frame(&gif::Frame {
+                    left,
+                    top,
+                    width: size as u16,
+                    height: size as u16,
+                    buffer: Cow::Owned(buffer),
+                    transparent: Some(CLEAR),
+                    dispose,
+                    delay,
+                    ..gif::Frame::default()
+                })
+                .unwrap();
+        };
+        write(0, 0, 4, RED, DisposalMethod::Keep, delays[0]);
+        write(1, 1, 2, GREEN, dispose, delays[1]);
+        write(0, 0, 1, CLEAR, DisposalMethod::Keep, delays[2]);
+        drop(encoder);
+        out
+    }
+
+    fn frames(gif: &[u8], min_delay: Duration) -> Vec<Frame> {
+        into_frames(gif, min_delay)
+            .unwrap()
+            .map(|frame| frame.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn should_dispose_of_every_frame_the_way_it_says() {
+        let (red, green, clear) = ([255, 0, 0, 255], [0, 255, 0, 255], [0; 4]);
+        for (dispose, left) in [
+            (DisposalMethod::Keep, green),
+            (DisposalMethod::Background, clear),
+            (DisposalMethod::Previous, red),
+        ] {
+            let frames = frames(&gif(dispose, [10, 10, 10]), Duration::ZERO);
+            assert_eq!(frames.len(), 3, "{dispose:?}");
+            let second = frames[1].buffer();
+            assert_eq!(second.get_pixel(0, 0).0, red, "{dispose:?}");
+            assert_eq!(second.get_pixel(1, 1).0, green, "{dispose:?}");
+            let last = frames[2].buffer();
+            assert_eq!(last.get_pixel(0, 0).0, red, "{dispose:?}");
+            assert_eq!(last.get_pixel(1, 1).0, left, "{dispose:?}");
+            assert_eq!(last.get_pixel(2, 2).0, left, "{dispose:?}");
+            assert_eq!(last.get_pixel(3, 3).0, red, "{dispose:?}");
+        }
+    }
+
+    #[test]
+    fn delays_shorter_than_the_minimum_should_be_clamped() {
+        let gif = gif(DisposalMethod::Keep, [0, 1, 5]);
+        let delays = |min_delay| {
+            frames(&gif, min_delay)
+                .iter()
+                .map(|frame| Duration::from(frame.delay()))
+                .collect::<Vec<_>>()
+        };
+        let ms = Duration::from_millis;
+        assert_eq!(delays(ms(20)), [ms(100), ms(100), ms(50)]);
+        assert_eq!(delays(Duration::ZERO), [ms(0), ms(10), ms(50)]);
+    }
+}
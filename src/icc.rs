@@ -0,0 +1,312 @@
+//! Converts images from the color space their ICC profile describes to sRGB, see `swww img
+//! --color-profile`.
+//!
+//! Compositors show our buffers as sRGB, so images made for wider gamuts, like Display P3 photos,
+//! come out washed out unless we convert them first. We only understand matrix/TRC profiles: a tone
+//! curve for each channel, and the primaries the channels mix into. That is what camera, phone and
+//! monitor profiles are, but not printer ones, which are lookup tables we show as they are.
+//!
+//! Each channel goes through its curve to linear light, with a table per channel, then through a
+//! matrix to linear sRGB, and back to sRGB bytes with [`utils::gamma`]. This module needs the
+//! `icc` feature.
+
+use rayon::prelude::*;
+use utils::gamma;
+
+/// Linear sRGB from the D50 XYZ of the profile connection space, through the Bradford transform
+const SRGB_FROM_XYZ: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+/// The D50 XYZ of Display P3's red, green and blue, like in its ICC profile
+const DISPLAY_P3: [[f32; 3]; 3] = [
+    [0.515_102, 0.241_182, -0.001_050],
+    [0.291_965, 0.692_236, 0.041_882],
+    [0.157_153, 0.066_582, 0.784_378],
+];
+
+/// A matrix/TRC profile, ready to convert pixels to sRGB
+pub struct Profile {
+    /// Every byte of each channel, in linear light
+    to_linear: [[f32; 256]; 3],
+    /// Linear sRGB from the profile's linear red, green and blue
+    matrix: [[f32; 3]; 3],
+}
+
+impl Profile {
+    /// Display P3 uses the sRGB tone curve, only its primaries are wider
+    pub fn display_p3() -> Self {
+        let curve = Curve::Parametric(SRGB_CURVE);
+        Self::new([&curve, &curve, &curve], DISPLAY_P3)
+    }
+
+    /// `primaries` are the D50 XYZ of red, green and blue
+    fn new(curves: [&Curve; 3], primaries: [[f32; 3]; 3]) -> Self {
+        let mut to_linear = [[0.0; 256]; 3];
+        for (table, curve) in to_linear.iter_mut().zip(curves) {
+            for (i, linear) in table.iter_mut().enumerate() {
+                *linear = curve.eval(i as f32 / 255.0);
+            }
+        }
+        let mut matrix = [[0.0; 3]; 3];
+        for (row, srgb) in matrix.iter_mut().zip(SRGB_FROM_XYZ) {
+            for (col, cell) in row.iter_mut().enumerate() {
+                *cell = (0..3).map(|k| srgb[k] * primaries[col][k]).sum();
+            }
+        }
+        Self { to_linear, matrix }
+    }
+
+    /// Whether converting with us would leave every pixel the same, like with the sRGB profiles
+    /// many images carry
+    pub fn is_srgb(&self) -> bool {
+        let identity = self.matrix.iter().enumerate().all(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .all(|(j, &cell)| (cell - if i == j { 1.0 } else { 0.0 }).abs() < 0.002)
+        });
+        let srgb = gamma::to_linear_table();
+        identity
+            && self.to_linear.iter().all(|table| {
+                table
+                    .iter()
+                    .zip(srgb)
+                    .all(|(&ours, &srgb)| (ours * u16::MAX as f32 - srgb as f32).abs() < 64.0)
+            })
+    }
+
+    /// Converts the first 3 bytes of every `channels` bytes of `pixels`, which are RGB, to sRGB
+    pub fn convert(&self, pixels: &mut [u8], channels: usize) {
+        let to_srgb = gamma::to_srgb_table();
+        pixels.par_chunks_exact_mut(channels).for_each(|pixel| {
+            let linear: [f32; 3] = std::array::from_fn(|c| self.to_linear[c][pixel[c] as usize]);
+            for (byte, row) in pixel.iter_mut().zip(&self.matrix) {
+                let v = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                *byte = to_srgb[(v.clamp(0.0, 1.0) * u16::MAX as f32 + 0.5) as usize];
+            }
+        });
+    }
+}
+
+/// The sRGB tone curve, as the parameters of an ICC `para` curve of type 3
+const SRGB_CURVE: [f32; 5] = [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045];
+
+/// How a channel goes to linear light
+enum Curve {
+    Gamma(f32),
+    /// Evenly spaced samples from 0 to 1
+    Table(Vec<f32>),
+    /// g, a, b, c and d, of `(a * x + b)^g` from `d` on, and `c * x` below it. The other types of
+    /// ICC `para` curves are special cases of this one, as long as they have no offsets
+    Parametric([f32; 5]),
+    /// Like `Parametric`, but with e and f, offsets of both sides
+    Offset([f32; 7]),
+}
+
+impl Curve {
+    fn eval(&self, x: f32) -> f32 {
+        let y = match self {
+            Self::Gamma(g) => x.powf(*g),
+            Self::Table(samples) => {
+                let pos = x * (samples.len() - 1) as f32;
+                let i = (pos as usize).min(samples.len() - 2);
+                let t = pos - i as f32;
+                samples[i] * (1.0 - t) + samples[i + 1] * t
+            }
+            Self::Parametric([g, a, b, c, d]) => {
+                if x >= *d {
+                    (a * x + b).max(0.0).powf(*g)
+                } else {
+                    c * x
+                }
+            }
+            Self::Offset([g, a, b, c, d, e, f]) => {
+                if x >= *d {
+                    (a * x + b).max(0.0).powf(*g) + e
+                } else {
+                    c * x + f
+                }
+            }
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+/// Reads a matrix/TRC RGB profile
+pub fn parse(icc: &[u8]) -> Result<Profile, String> {
+    if icc.len() < 132 || &icc[36..40] != b"acsp" {
+        return Err("not an ICC profile".to_string());
+    }
+    if &icc[16..20] != b"RGB " {
+        return Err(format!(
+            "only RGB profiles are supported, not {}",
+            String::from_utf8_lossy(&icc[16..20]).trim()
+        ));
+    }
+    let tag = |signature: &[u8; 4]| {
+        let count = be_u32(icc, 128)? as usize;
+        (0..count).find_map(|i| {
+            let entry = 132 + i * 12;
+            if icc.get(entry..entry + 4)? != signature {
+                return None;
+            }
+            let offset = be_u32(icc, entry + 4)? as usize;
+            let len = be_u32(icc, entry + 8)? as usize;
+            icc.get(offset..offset.checked_add(len)?)
+        })
+    };
+    // lookup table profiles may have primaries and curves too, but only as a fallback
+    let lut = [b"A2B0", b"A2B1", b"A2B2"].iter().any(|s| tag(s).is_some());
+    let missing = |name: &str| {
+        if lut {
+            "it is a lookup table (LUT) profile, only matrix/TRC profiles are supported".to_string()
+        } else {
+            format!("the profile has no {name}, only matrix/TRC profiles are supported")
+        }
+    };
+
+    let xyz = |signature| {
+        let data = tag(signature).ok_or_else(|| missing("primaries"))?;
+        if !data.starts_with(b"XYZ ") {
+            return Err("invalid primaries".to_string());
+        }
+        let v =
+            |i: usize| s15_fixed16(data, 8 + i * 4).ok_or_else(|| "invalid primaries".to_string());
+        Ok([v(0)?, v(1)?, v(2)?])
+    };
+    let primaries = [xyz(b"rXYZ")?, xyz(b"gXYZ")?, xyz(b"bXYZ")?];
+
+    let curve = |signature| {
+        let data = tag(signature).ok_or_else(|| missing("tone curves"))?;
+        parse_curve(data).ok_or_else(|| "invalid tone curve".to_string())
+    };
+    let curves = [curve(b"rTRC")?, curve(b"gTRC")?, curve(b"bTRC")?];
+    Ok(Profile::new(
+        [&curves[0], &curves[1], &curves[2]],
+        primaries,
+    ))
+}
+
+fn parse_curve(data: &[u8]) -> Option<Curve> {
+    match data.get(..4)? {
+        b"curv" => {
+            let count = be_u32(data, 8)? as usize;
+            let entry = |i: usize| {
+                let bytes = data.get(12 + i * 2..14 + i * 2)?;
+                Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+            };
+            match count {
+                0 => Some(Curve::Gamma(1.0)),
+                1 => Some(Curve::Gamma(entry(0)? as f32 / 256.0)),
+                _ => (0..count)
+                    .map(|i| Some(entry(i)? as f32 / u16::MAX as f32))
+                    .collect::<Option<_>>()
+                    .map(Curve::Table),
+            }
+        }
+        b"para" => {
+            let kind = u16::from_be_bytes([*data.get(8)?, *data.get(9)?]);
+            let count = [1, 3, 4, 5, 7].get(kind as usize)?;
+            let params = (0..*count)
+                .map(|i| s15_fixed16(data, 12 + i * 4))
+                .collect::<Option<Vec<_>>>()?;
+            let [g, a, b, c, d] = match (kind, &params[..]) {
+                (0, &[g]) => [g, 1.0, 0.0, 0.0, 0.0],
+                // zero below -b/a, where (a * x + b) would go negative
+                (1, &[g, a, b]) => [g, a, b, 0.0, -b / a],
+                (2, &[g, a, b, c]) => {
+                    return Some(Curve::Offset([g, a, b, 0.0, -b / a, c, c]));
+                }
+                (3, &[g, a, b, c, d]) => [g, a, b, c, d],
+                (4, &[g, a, b, c, d, e, f]) => return Some(Curve::Offset([g, a, b, c, d, e, f])),
+                _ => return None,
+            };
+            Some(Curve::Parametric([g, a, b, c, d]))
+        }
+        _ => None,
+    }
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn s15_fixed16(data: &[u8], at: usize) -> Option<f32> {
+    Some(be_u32(data, at)? as i32 as f32 / 65536.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A profile with `primaries`, and the sRGB tone curve on every channel
+    fn profile(primaries: [[f32; 3]; 3]) -> Vec<u8> {
+        let fixed = |v: f32| ((v * 65536.0).round() as i32).to_be_bytes();
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (signature, xyz) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().zip(primaries) {
+            let mut data = b"XYZ \0\0\0\0".to_vec();
+            xyz.into_iter().for_each(|v| data.extend(fixed(v)));
+            tags.push((signature, data));
+        }
+        let mut curve = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        SRGB_CURVE.into_iter().for_each(|v| curve.extend(fixed(v)));
+        for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((signature, curve.clone()));
+        }
+
+        let mut icc = vec![0; 128];
+        icc[16..20].copy_from_slice(b"RGB ");
+        icc[36..40].copy_from_slice(b"acsp");
+        icc.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + tags.len() * 12;
+        for (signature, data) in &tags {
+            icc.extend(*signature);
+            icc.extend((offset as u32).to_be_bytes());
+            icc.extend((data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        tags.into_iter().for_each(|(_, data)| icc.extend(data));
+        icc
+    }
+
+    /// The D50 XYZ of sRGB's red, green and blue
+    const SRGB: [[f32; 3]; 3] = [
+        [0.436_075, 0.222_504, 0.013_932],
+        [0.385_065, 0.716_879, 0.097_105],
+        [0.143_080, 0.060_617, 0.714_173],
+    ];
+
+    #[test]
+    fn srgb_profiles_should_be_left_alone() {
+        assert!(parse(&profile(SRGB)).unwrap().is_srgb());
+        assert!(!parse(&profile(DISPLAY_P3)).unwrap().is_srgb());
+    }
+
+    #[test]
+    fn display_p3_should_be_more_saturated_in_srgb() {
+        let parsed = parse(&profile(DISPLAY_P3)).unwrap();
+        let mut pixels = [200, 100, 100, 255, 128, 128, 128, 255];
+        parsed.convert(&mut pixels, 4);
+        assert!(pixels[0] > 200 && pixels[1] < 100 && pixels[2] < 100);
+        // grays and alpha stay the same
+        assert_eq!(pixels[3..], [255, 128, 128, 128, 255]);
+
+        let mut builtin = [200, 100, 100];
+        Profile::display_p3().convert(&mut builtin, 3);
+        assert_eq!(builtin, pixels[..3]);
+    }
+
+    #[test]
+    fn should_reject_what_is_not_a_matrix_profile() {
+        assert!(parse(b"not a profile").is_err());
+        let mut gray = profile(SRGB);
+        gray[16..20].copy_from_slice(b"GRAY");
+        assert!(parse(&gray).is_err());
+        let mut lut = profile(SRGB);
+        lut[132..136].copy_from_slice(b"A2B0");
+        let e = parse(&lut).err().unwrap();
+        assert!(e.contains("lookup table"), "{e}");
+    }
+}
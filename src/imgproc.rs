@@ -1,29 +1,51 @@
 use fast_image_resize::{FilterType, PixelType, Resizer};
 use image::{
-    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
-    AnimationDecoder, DynamicImage, Frames, ImageFormat, RgbImage,
+    codecs::{png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, DynamicImage, Frames, ImageFormat, RgbImage, RgbaImage,
 };
+use rayon::prelude::*;
 use std::{
     fs::File,
-    io::Stdin,
-    io::{stdin, BufReader, Read},
+    io::{stdin, BufReader, Cursor, Read, Seek},
     num::NonZeroU32,
-    path::Path,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
+/// A rectangular region of an image, as (x, y, width, height), in pixels
+pub type Region = (u32, u32, u32, u32);
+
+/// Crops centered in the middle of the image
+pub const CENTER: (f32, f32) = (0.5, 0.5);
+
 use utils::{
-    comp_decomp::BitPack,
+    comp_decomp::{BitPack, CompressionBackend},
+    gamma,
     ipc::{self, Coord, Position},
 };
 
-use crate::cli::ResizeStrategy;
+use crate::{cli::ResizeStrategy, svg::Svg};
 
 use super::cli;
 
 enum ImgBufInner {
-    Stdin(BufReader<Stdin>),
-    File(image::io::Reader<BufReader<File>>),
+    /// Stdin isn't seekable, so we read it whole to guess its format
+    Stdin {
+        buffer: Vec<u8>,
+        format: Option<ImageFormat>,
+    },
+    File {
+        reader: image::io::Reader<BufReader<File>>,
+        path: PathBuf,
+    },
+    /// Rendered by `resvg`, see [`crate::svg`]
+    Svg(PathBuf),
+    /// Decoded by `jxl-oxide`, see [`crate::jxl`]
+    Jxl(Vec<u8>),
+    /// Decoded by `ffmpeg`, see [`crate::video`]
+    Video { path: PathBuf, fps: Option<f32> },
+    /// A directory of numbered images, see [`crate::sequence`]
+    Sequence { frames: Vec<PathBuf>, fps: f32 },
 }
 
 impl ImgBufInner {
@@ -31,56 +53,276 @@ impl ImgBufInner {
     #[inline]
     fn format(&self) -> Option<ImageFormat> {
         match &self {
-            ImgBufInner::Stdin(_) => None, // not seekable
-            ImgBufInner::File(reader) => reader.format(),
+            ImgBufInner::Stdin { format, .. } => *format,
+            ImgBufInner::File { reader, .. } => reader.format(),
+            ImgBufInner::Svg(_)
+            | ImgBufInner::Jxl(_)
+            | ImgBufInner::Video { .. }
+            | ImgBufInner::Sequence { .. } => None,
         }
     }
 }
 
 pub struct ImgBuf {
     inner: ImgBufInner,
+    /// Whether the image itself is animated, even without `options.ken_burns`
     is_animated: bool,
+    options: DecodeOptions,
+}
+
+/// How we decode an image, see [`ImgBuf::with_options`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DecodeOptions {
+    /// Still images are converted from it to sRGB, see [`crate::icc`]
+    pub color_profile: cli::ColorProfile,
+    /// Only the part of the image it picks is shown. Has no effect on svgs, which are rendered at
+    /// the size of the outputs instead of decoded
+    pub view: View,
+    /// Makes still images into animations that zoom into their view, and back out. Has no effect
+    /// on animated images
+    pub ken_burns: Option<KenBurns>,
+    /// GIF frames with shorter delays play at 100ms instead, see [`crate::gif::into_frames`]. Has
+    /// no effect on other images
+    pub gif_min_delay: Duration,
+}
+
+/// How `swww img --ken-burns` zooms into the [`View`] of a still image, and back out, see
+/// [`ken_burns_frames`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KenBurns {
+    /// Zooming in takes this long, and zooming back out as long again
+    pub duration: Duration,
+    pub fps: f32,
+}
+
+/// The part of an image `swww img --crop`, `--zoom` and `--offset` show. Everything after decoding
+/// sees only that part, as if it were the whole image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    pub crop: Option<Region>,
+    /// At least 1.0
+    pub zoom: f32,
+    /// From -1.0 to 1.0 on both axes
+    pub offset: (f32, f32),
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            crop: None,
+            zoom: 1.0,
+            offset: (0.0, 0.0),
+        }
+    }
+}
+
+impl View {
+    /// The region of an image `img_dim` in size to show, or `None` if that is all of it. Crops that
+    /// go past the image's borders only keep what is inside them, and always keep a pixel
+    pub fn region(&self, img_dim: (u32, u32)) -> Option<Region> {
+        if *self == Self::default() {
+            return None;
+        }
+        let (x, y, w, h) = match self.crop {
+            Some((x, y, w, h)) => {
+                let (x, y) = (x.min(img_dim.0 - 1), y.min(img_dim.1 - 1));
+                (x, y, w.min(img_dim.0 - x), h.min(img_dim.1 - y))
+            }
+            None => (0, 0, img_dim.0, img_dim.1),
+        };
+        let zoomed = |len: u32| ((len as f32 / self.zoom).round() as u32).max(1);
+        let (zoomed_w, zoomed_h) = (zoomed(w), zoomed(h));
+        // the offset moves the zoomed in part from one edge of the crop to the other
+        let shift = |slack: u32, offset: f32| (slack as f32 * (offset + 1.0) / 2.0).round() as u32;
+        Some((
+            x + shift(w - zoomed_w, self.offset.0),
+            y + shift(h - zoomed_h, self.offset.1),
+            zoomed_w,
+            zoomed_h,
+        ))
+    }
 }
 
 impl ImgBuf {
     /// Create a new ImgBuf from a given path. Use - for Stdin
     pub fn new(path: &Path) -> Result<Self, String> {
-        Ok(if let Some("-") = path.to_str() {
-            let reader = BufReader::new(stdin());
-            Self {
-                inner: ImgBufInner::Stdin(reader),
-                is_animated: false,
-            }
+        Ok(Self::open(path, None)?)
+    }
+
+    /// Like [`ImgBuf::new`], but trusts `format` instead of guessing it, when it is set. Errors
+    /// tell a file we can't open apart from one we can't decode
+    pub fn open(path: &Path, format: Option<ImageFormat>) -> Result<Self, ipc::Error> {
+        let unsupported = |e| ipc::Error::new(ipc::ErrorKind::UnsupportedFormat, e);
+        if let Some("-") = path.to_str() {
+            let mut buffer = Vec::new();
+            stdin()
+                .read_to_end(&mut buffer)
+                .map_err(|e| unsupported(format!("failed to read stdin: {e}")))?;
+            Self::from_memory(buffer, format).map_err(unsupported)
+        } else if crate::download::is_url(path) {
+            let url = path.to_string_lossy();
+            let buffer = crate::download::fetch(&url, format).map_err(unsupported)?;
+            Self::from_memory(buffer, format).map_err(unsupported)
+        } else if crate::svg::is_svg(path) {
+            Ok(Self::from_inner(
+                ImgBufInner::Svg(path.to_path_buf()),
+                false,
+            ))
+        } else if crate::video::is_video(path) {
+            let inner = ImgBufInner::Video {
+                path: path.to_path_buf(),
+                fps: None,
+            };
+            Ok(Self::from_inner(inner, true))
         } else {
-            let reader = image::io::Reader::open(path)
-                .map_err(|e| format!("failed to open image: {e}"))?
-                .with_guessed_format()
-                .map_err(|e| format!("failed to detect the image's format: {e}"))?;
-
-            let is_animated = {
-                match reader.format() {
-                    Some(ImageFormat::Gif) => true,
-                    Some(ImageFormat::WebP) => {
-                        // Note: unwrapping is safe because we already opened the file once before this
-                        WebPDecoder::new(BufReader::new(File::open(path).unwrap()))
-                            .map_err(|e| format!("failed to decode Webp Image: {e}"))?
-                            .has_animation()
-                    }
-                    Some(ImageFormat::Png) => {
-                        PngDecoder::new(BufReader::new(File::open(path).unwrap()))
-                            .map_err(|e| format!("failed to decode Png Image: {e}"))?
-                            .is_apng()
-                    }
-
-                    _ => false,
+            let file = File::open(path).map_err(|e| {
+                let kind = match e.kind() {
+                    std::io::ErrorKind::NotFound => ipc::ErrorKind::FileNotFound,
+                    std::io::ErrorKind::PermissionDenied => ipc::ErrorKind::PermissionDenied,
+                    _ => ipc::ErrorKind::Other,
+                };
+                ipc::Error::new(kind, format!("failed to open {path:?}: {e}"))
+            })?;
+            let mut reader = image::io::Reader::new(BufReader::new(file));
+            match format {
+                Some(format) => reader.set_format(format),
+                None => {
+                    reader = reader.with_guessed_format().map_err(|e| {
+                        unsupported(format!("failed to detect the image's format: {e}"))
+                    })?
                 }
+            }
+            let format = reader.format();
+            let mut file = reader.into_inner();
+            if format.is_none() {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)
+                    .map_err(|e| unsupported(format!("failed to read image: {e}")))?;
+                if crate::jxl::is_jxl(&data) {
+                    return Self::from_jxl(data).map_err(unsupported);
+                }
+            }
+            let is_animated = is_animated(format, || &mut file).map_err(unsupported)?;
+            file.rewind()
+                .map_err(|e| unsupported(format!("failed to read image: {e}")))?;
+            let mut reader = image::io::Reader::new(file);
+            if let Some(format) = format {
+                reader.set_format(format);
+            }
+
+            let inner = ImgBufInner::File {
+                reader,
+                path: path.to_path_buf(),
             };
+            Ok(Self::from_inner(inner, is_animated))
+        }
+    }
 
-            Self {
-                inner: ImgBufInner::File(reader),
-                is_animated,
+    /// The images with one of `extensions` in `dir`, played in order at `fps`
+    pub fn sequence(dir: &Path, extensions: &[String], fps: f32) -> Result<Self, String> {
+        let inner = ImgBufInner::Sequence {
+            frames: crate::sequence::frames(dir, extensions)?,
+            fps,
+        };
+        Ok(Self::from_inner(inner, true))
+    }
+
+    fn from_inner(inner: ImgBufInner, is_animated: bool) -> Self {
+        Self {
+            inner,
+            is_animated,
+            options: DecodeOptions::default(),
+        }
+    }
+
+    fn from_memory(buffer: Vec<u8>, format: Option<ImageFormat>) -> Result<Self, String> {
+        if format.is_none() && crate::jxl::is_jxl(&buffer) {
+            return Self::from_jxl(buffer);
+        }
+        let format = format.or_else(|| image::guess_format(&buffer).ok());
+        let is_animated = is_animated(format, || buffer.as_slice())?;
+        Ok(Self::from_inner(
+            ImgBufInner::Stdin { buffer, format },
+            is_animated,
+        ))
+    }
+
+    fn from_jxl(data: Vec<u8>) -> Result<Self, String> {
+        let is_animated = crate::jxl::is_animated(&data)?;
+        Ok(Self::from_inner(ImgBufInner::Jxl(data), is_animated))
+    }
+
+    /// Another ImgBuf for the same image, which we can decode on its own. Images from stdin are
+    /// copied, since we can't read stdin again
+    pub fn try_clone(&self) -> Result<Self, String> {
+        let clone = match &self.inner {
+            ImgBufInner::Stdin { buffer, format } => Self::from_memory(buffer.clone(), *format),
+            ImgBufInner::File { reader, path } => Ok(Self::open(path, reader.format())?),
+            ImgBufInner::Svg(path) => Self::new(path),
+            ImgBufInner::Jxl(data) => Ok(Self::from_inner(
+                ImgBufInner::Jxl(data.clone()),
+                self.is_animated,
+            )),
+            ImgBufInner::Video { path, fps } => Ok(Self::new(path)?.with_video_fps(*fps)),
+            ImgBufInner::Sequence { frames, fps } => {
+                let inner = ImgBufInner::Sequence {
+                    frames: frames.clone(),
+                    fps: *fps,
+                };
+                Ok(Self::from_inner(inner, true))
             }
-        })
+        }?;
+        Ok(clone.with_options(self.options))
+    }
+
+    /// SVGs are better rendered at the size of each output than decoded once and then resized
+    pub fn svg_path(&self) -> Option<&Path> {
+        match &self.inner {
+            ImgBufInner::Svg(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self.inner, ImgBufInner::Video { .. })
+    }
+
+    /// Plays videos at `fps` instead of their own frame rate. Has no effect on images
+    pub fn with_video_fps(mut self, fps: Option<f32>) -> Self {
+        if let ImgBufInner::Video { fps: video_fps, .. } = &mut self.inner {
+            *video_fps = fps;
+        }
+        self
+    }
+
+    /// Decodes the image with `options`, which its clones keep
+    pub fn with_options(mut self, options: DecodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The profile to convert the image from, if it isn't sRGB already. Images with profiles we
+    /// can't read are shown as they are
+    #[cfg(feature = "icc")]
+    fn icc_profile(&self) -> Option<crate::icc::Profile> {
+        let icc = match (self.options.color_profile, &self.inner) {
+            (cli::ColorProfile::Ignore, _) => return None,
+            (cli::ColorProfile::DisplayP3, _) => return Some(crate::icc::Profile::display_p3()),
+            (cli::ColorProfile::Embedded, ImgBufInner::Stdin { buffer, format }) => {
+                embedded_icc_profile(*format, Cursor::new(buffer))
+            }
+            (cli::ColorProfile::Embedded, ImgBufInner::File { reader, path }) => {
+                embedded_icc_profile(reader.format(), BufReader::new(File::open(path).ok()?))
+            }
+            (cli::ColorProfile::Embedded, _) => None,
+        }?;
+        match crate::icc::parse(&icc) {
+            Ok(profile) => (!profile.is_srgb()).then_some(profile),
+            Err(e) => {
+                eprintln!("Warning: showing the image without converting its color profile: {e}");
+                None
+            }
+        }
     }
 
     /// Guess the format of the ImgBuf
@@ -90,111 +332,672 @@ impl ImgBuf {
 
     #[inline]
     pub fn is_animated(&self) -> bool {
-        self.is_animated
+        self.is_animated || self.ken_burns().is_some()
+    }
+
+    /// How we zoom into the image, if it is still
+    fn ken_burns(&self) -> Option<KenBurns> {
+        self.options.ken_burns.filter(|_| !self.is_animated)
     }
 
     /// Decode the ImgBuf into am RgbImage
     pub fn decode(self) -> Result<RgbImage, String> {
-        Ok(match self.inner {
-            ImgBufInner::Stdin(mut reader) => {
-                let mut buffer = Vec::new();
-                reader
-                    .read_to_end(&mut buffer)
-                    .map_err(|e| format!("failed to read stdin: {e}"))?;
-
-                image::load_from_memory(&buffer)
+        Ok(self.decode_dynamic()?.into_rgb8())
+    }
+
+    /// Like [`ImgBuf::decode`], but keeps the alpha channel
+    pub fn decode_rgba(self) -> Result<RgbaImage, String> {
+        Ok(self.decode_dynamic()?.into_rgba8())
+    }
+
+    /// Like [`ImgBuf::decode`], but applies `alpha_threshold` first, see [`flatten_alpha`]
+    pub fn decode_over(
+        self,
+        fill_color: &[u8; 3],
+        alpha_threshold: u8,
+    ) -> Result<RgbImage, String> {
+        let img = self.decode_dynamic()?;
+        if alpha_threshold == 0 {
+            Ok(img.into_rgb8())
+        } else {
+            Ok(flatten_alpha(img.into_rgba8(), fill_color, alpha_threshold))
+        }
+    }
+
+    fn decode_dynamic(self) -> Result<DynamicImage, String> {
+        if cfg!(not(feature = "avif")) && self.format() == Some(ImageFormat::Avif) {
+            return Err("swww was compiled without the `avif` feature".to_string());
+        }
+        #[cfg(feature = "icc")]
+        let icc_profile = self.icc_profile();
+        #[cfg(not(feature = "icc"))]
+        if self.options.color_profile == cli::ColorProfile::DisplayP3 {
+            return Err("swww was compiled without the `icc` feature".to_string());
+        }
+        let (options, ken_burns) = (self.options, self.ken_burns());
+        let img = match self.inner {
+            ImgBufInner::Stdin {
+                buffer,
+                format: Some(format),
+            } => image::load_from_memory_with_format(&buffer, format),
+            ImgBufInner::Stdin { buffer, .. } => image::load_from_memory(&buffer),
+            ImgBufInner::File { reader, .. } => reader.decode(),
+            ImgBufInner::Svg(path) => {
+                let svg = Svg::open(&path)?;
+                let dim = crate::svg::render_size(ResizeStrategy::No, svg.size(), (0, 0));
+                Ok(DynamicImage::ImageRgba8(svg.render(dim)?))
             }
-            ImgBufInner::File(reader) => reader.decode(),
+            ImgBufInner::Jxl(data) => crate::jxl::into_frames(data)?
+                .next()
+                .ok_or_else(|| "jxl image has no frames".to_string())?
+                .map(|frame| DynamicImage::ImageRgba8(frame.into_buffer())),
+            ImgBufInner::Video { path, fps } => crate::video::into_frames(&path, fps)?
+                .next()
+                .ok_or_else(|| format!("{path:?} has no frames"))?
+                .map(|frame| DynamicImage::ImageRgba8(frame.into_buffer())),
+            ImgBufInner::Sequence { frames, .. } => image::open(&frames[0]),
+        }
+        .map_err(|e| format!("failed to decode image: {e}"))?;
+        #[cfg(feature = "icc")]
+        let img = match icc_profile {
+            Some(profile) => convert_to_srgb(img, &profile),
+            None => img,
+        };
+        // the zoom is where ken burns animations go, but they start from all of the crop
+        let view = match ken_burns {
+            Some(_) => View {
+                crop: options.view.crop,
+                ..View::default()
+            },
+            None => options.view,
+        };
+        match view.region((img.width(), img.height())) {
+            Some((x, y, w, h)) => Ok(img.crop_imm(x, y, w, h)),
+            None => Ok(img),
         }
-        .map_err(|e| format!("failed to decode image: {e}"))?
-        .into_rgb8())
     }
 
     /// Convert this ImgBuf into Frames
     pub fn into_frames<'a>(self) -> Result<Frames<'a>, String> {
-        fn create_decoder<'a>(
-            img_format: Option<ImageFormat>,
-            reader: impl Read + 'a,
-        ) -> Result<Frames<'a>, String> {
-            match img_format {
-                Some(ImageFormat::Gif) => Ok(GifDecoder::new(reader)
-                    .map_err(|e| format!("failed to decode gif during animation: {e}"))?
-                    .into_frames()),
+        if let Some(ken_burns) = self.ken_burns() {
+            let zoom = View {
+                crop: None,
+                ..self.options.view
+            };
+            // decodes only the crop, see `decode_dynamic`
+            let img = self.decode_dynamic()?.into_rgba8();
+            let end = zoom.region(img.dimensions());
+            return Ok(ken_burns_frames(img, end, ken_burns));
+        }
+        let gif_min_delay = self.options.gif_min_delay;
+        let create_decoder =
+            |img_format: Option<ImageFormat>, reader: Box<dyn Read + 'a>| match img_format {
+                Some(ImageFormat::Gif) => crate::gif::into_frames(reader, gif_min_delay),
                 Some(ImageFormat::WebP) => Ok(WebPDecoder::new(reader)
                     .map_err(|e| format!("failed to decode webp during animation: {e}"))?
                     .into_frames()),
-                Some(ImageFormat::Png) => Ok(PngDecoder::new(reader)
-                    .map_err(|e| format!("failed to decode png during animation: {e}"))?
-                    .apng()
-                    .into_frames()),
+                Some(ImageFormat::Png) => crate::apng::into_frames(reader),
                 _ => Err(format!("requested format has no decoder: {img_format:#?}")),
+            };
+
+        let img_format = self.format();
+        let view = self.options.view;
+        let frames = match self.inner {
+            ImgBufInner::Stdin { buffer, .. } => {
+                create_decoder(img_format, Box::new(Cursor::new(buffer)))
+            }
+            ImgBufInner::File { reader, .. } => {
+                create_decoder(img_format, Box::new(reader.into_inner()))
             }
+            ImgBufInner::Svg(_) => Err("svgs have no frames".to_string()),
+            ImgBufInner::Jxl(data) => crate::jxl::into_frames(data),
+            ImgBufInner::Video { path, fps } => crate::video::into_frames(&path, fps),
+            ImgBufInner::Sequence { frames, fps } => Ok(crate::sequence::into_frames(frames, fps)),
+        }?;
+        Ok(crop_frames(frames, view))
+    }
+}
+
+/// Reads the ICC profile of images in the formats `image` can find one in
+#[cfg(feature = "icc")]
+fn embedded_icc_profile(
+    format: Option<ImageFormat>,
+    reader: impl std::io::BufRead + std::io::Seek,
+) -> Option<Vec<u8>> {
+    use image::{
+        codecs::{jpeg::JpegDecoder, tiff::TiffDecoder},
+        ImageDecoder,
+    };
+    match format? {
+        ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Converts `img` from `profile` to sRGB, keeping its alpha channel if it has one
+#[cfg(feature = "icc")]
+fn convert_to_srgb(img: DynamicImage, profile: &crate::icc::Profile) -> DynamicImage {
+    if img.color().has_alpha() {
+        let mut img = img.into_rgba8();
+        profile.convert(&mut img, 4);
+        DynamicImage::ImageRgba8(img)
+    } else {
+        let mut img = img.into_rgb8();
+        profile.convert(&mut img, 3);
+        DynamicImage::ImageRgb8(img)
+    }
+}
+
+/// Parses formats like `png` or `jpg`, for when we can't guess the format of an image
+pub fn parse_format(raw: &str) -> Result<ImageFormat, String> {
+    ImageFormat::from_extension(raw).ok_or_else(|| format!("unknown image format: {raw}"))
+}
+
+/// Whether the image has more than one frame. `reader` must read the image from its start
+fn is_animated<R: Read>(
+    format: Option<ImageFormat>,
+    reader: impl FnOnce() -> R,
+) -> Result<bool, String> {
+    Ok(match format {
+        Some(ImageFormat::Gif) => true,
+        Some(ImageFormat::WebP) => WebPDecoder::new(reader())
+            .map_err(|e| format!("failed to decode Webp Image: {e}"))?
+            .has_animation(),
+        Some(ImageFormat::Png) => PngDecoder::new(reader())
+            .map_err(|e| format!("failed to decode Png Image: {e}"))?
+            .is_apng(),
+        _ => false,
+    })
+}
+
+#[inline]
+pub fn frame_to_rgb(frame: image::Frame, fill_color: &[u8; 3], alpha_threshold: u8) -> RgbImage {
+    rgba_to_rgb(frame.into_buffer(), fill_color, alpha_threshold)
+}
+
+#[inline]
+pub fn rgba_to_rgb(img: RgbaImage, fill_color: &[u8; 3], alpha_threshold: u8) -> RgbImage {
+    if alpha_threshold == 0 {
+        DynamicImage::ImageRgba8(img).into_rgb8()
+    } else {
+        flatten_alpha(img, fill_color, alpha_threshold)
+    }
+}
+
+/// Makes every pixel less opaque than `alpha_threshold` fully transparent, and every other pixel
+/// fully opaque
+pub fn threshold_alpha(img: &mut RgbaImage, alpha_threshold: u8) {
+    for pixel in img.pixels_mut() {
+        if pixel[3] < alpha_threshold {
+            pixel.0 = [0, 0, 0, 0];
+        } else {
+            pixel[3] = u8::MAX;
         }
+    }
+}
 
-        let img_format = self.format();
-        match self.inner {
-            ImgBufInner::Stdin(reader) => create_decoder(img_format, reader),
-            ImgBufInner::File(reader) => create_decoder(img_format, reader.into_inner()),
+/// Thresholds the image's alpha (see [`threshold_alpha`]), and then composites it over
+/// `fill_color`, so that transparent pixels show the fill color
+pub fn flatten_alpha(mut img: RgbaImage, fill_color: &[u8; 3], alpha_threshold: u8) -> RgbImage {
+    threshold_alpha(&mut img, alpha_threshold);
+    let (width, height) = img.dimensions();
+    let mut flat = Vec::with_capacity(width as usize * height as usize * 3);
+    for pixel in img.pixels() {
+        if pixel[3] == 0 {
+            flat.extend_from_slice(fill_color);
+        } else {
+            flat.extend_from_slice(&pixel.0[0..3]);
         }
     }
+    RgbImage::from_raw(width, height, flat).unwrap()
 }
 
-#[inline]
-pub fn frame_to_rgb(frame: image::Frame) -> RgbImage {
-    DynamicImage::ImageRgba8(frame.into_buffer()).into_rgb8()
+/// The frame's alpha channel, as a grayscale image we can resize the same way as the frame itself
+fn alpha_plane(img: &RgbaImage) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        image::Rgb([img.get_pixel(x, y)[3]; 3])
+    })
+}
+
+/// Adds an alpha channel to the `bgr` pixels, taken from the first channel of every pixel in
+/// `alpha`, and premultiplies their colors by it, as wayland expects
+fn premultiply_alpha(bgr: &[u8], alpha: &[u8]) -> Vec<u8> {
+    let mut bgra = Vec::with_capacity(bgr.len() / 3 * 4);
+    for (pixel, alpha) in bgr.chunks_exact(3).zip(alpha.chunks_exact(3)) {
+        let alpha = alpha[0];
+        bgra.extend(
+            pixel
+                .iter()
+                .map(|&col| ((col as u16 * alpha as u16 + 127) / 255) as u8),
+        );
+        bgra.push(alpha);
+    }
+    bgra
+}
+
+fn resize_img(
+    img: RgbImage,
+    dim: (u32, u32),
+    scaling: Scaling,
+    resize: ResizeStrategy,
+    color: &[u8; 3],
+) -> Result<Vec<u8>, String> {
+    match resize {
+        ResizeStrategy::No => img_pad(img, dim, color),
+        ResizeStrategy::Crop => img_resize_crop(img, dim, scaling, CENTER),
+        ResizeStrategy::Fit => img_resize_fit(img, dim, scaling, color),
+        ResizeStrategy::Letterbox => img_resize_letterbox(img, dim, scaling, color),
+        ResizeStrategy::Pillarbox => img_resize_pillarbox(img, dim, scaling, color),
+        ResizeStrategy::Stretch => img_resize_stretch(img, dim, scaling),
+        ResizeStrategy::Tile => Ok(img_tile(&img, dim)),
+    }
 }
 
+/// Also returns the frames to play the animation backward with, for the `PingPong` `direction`.
+/// For `Reverse`, the frames we return first are the ones that play it backward, from the first
+/// frame to the last one, and from there down to the first again, so that the daemon can play them
+/// like any other, and the others are empty. With
+/// `keep_alpha`, the frames keep their alpha channel (see [`BitPack::pack_with_alpha`]). Without a
+/// `block_size`, we pick the one that packs the first frames best.
+///
+/// `on_frames` gets the forward frames as we make them, a few at a time, and whether they are the
+/// last ones, so that they can be sent before we are done (except when played in reverse, which
+/// it only gets once they all are). Its errors stop us. Without
+/// `return_frames`, that is all we do with them, and we return no forward frames, so that we never
+/// hold more than a few of them at once
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn compress_frames(
     mut frames: Frames,
     dim: (u32, u32),
-    filter: FilterType,
+    transform: ipc::Transform,
+    scaling: Scaling,
     resize: ResizeStrategy,
     color: &[u8; 3],
-) -> Result<Vec<(BitPack, Duration)>, String> {
+    color_temp: Option<u32>,
+    heatmap_blur: Option<f32>,
+    alpha_threshold: u8,
+    keep_alpha: bool,
+    direction: cli::PlaybackDirection,
+    compression: CompressionBackend,
+    mut block_size: Option<u8>,
+    return_frames: bool,
+    on_frames: &mut dyn FnMut(&[(BitPack, Duration)], bool) -> Result<(), String>,
+) -> Result<(Vec<(BitPack, Duration)>, Vec<(BitPack, Duration)>), String> {
     let mut compressed_frames = Vec::new();
+    let mut reverse_frames = Vec::new();
+    let frame_to_rgb = |frame| {
+        let mut img = frame_to_rgb(frame, color, alpha_threshold);
+        if let Some(kelvin) = color_temp {
+            apply_color_temp(&mut img, kelvin);
+        }
+        if let Some(sigma) = heatmap_blur {
+            apply_heatmap_blur(&mut img, sigma);
+        }
+        img
+    };
+    let to_pixels = |frame: image::Frame| {
+        let alpha = keep_alpha.then(|| alpha_plane(frame.buffer()));
+        let pixels = resize_img(frame_to_rgb(frame), dim, scaling, resize, color)?;
+        match alpha {
+            // the borders we may add are opaque
+            Some(alpha) => {
+                // alpha doesn't stand for light, so it never needs converting
+                let scaling = Scaling {
+                    linear: false,
+                    ..scaling
+                };
+                let alpha = resize_img(alpha, dim, scaling, resize, &[u8::MAX; 3])?;
+                let pixels = premultiply_alpha(&pixels, &alpha);
+                Ok::<_, String>(transform_pixels::<4>(pixels, dim, transform))
+            }
+            None => Ok(transform_pixels::<3>(pixels, dim, transform)),
+        }
+    };
+    let pack = |prev: &[u8], cur: &[u8], block_size| {
+        if keep_alpha {
+            BitPack::pack_with_alpha(Some(prev), cur, compression, block_size)
+        } else {
+            BitPack::pack(prev, cur, compression, block_size)
+        }
+    };
 
     // The first frame should always exist
     let first = frames.next().unwrap().unwrap();
-    let first_duration = first.delay().numer_denom_ms();
-    let first_duration = Duration::from_millis((first_duration.0 / first_duration.1).into());
-    let first_img = match resize {
-        ResizeStrategy::No => img_pad(frame_to_rgb(first), dim, color)?,
-        ResizeStrategy::Crop => img_resize_crop(frame_to_rgb(first), dim, filter)?,
-        ResizeStrategy::Fit => img_resize_fit(frame_to_rgb(first), dim, filter, color)?,
-    };
+    let first_duration = Duration::from(first.delay());
+    let first_img = to_pixels(first)?;
 
     let mut canvas: Option<Vec<u8>> = None;
-    while let Some(Ok(frame)) = frames.next() {
-        let (dur_num, dur_div) = frame.delay().numer_denom_ms();
-        let duration = Duration::from_millis((dur_num / dur_div).into());
-
-        let img = match resize {
-            ResizeStrategy::No => img_pad(frame_to_rgb(frame), dim, color)?,
-            ResizeStrategy::Crop => img_resize_crop(frame_to_rgb(frame), dim, filter)?,
-            ResizeStrategy::Fit => img_resize_fit(frame_to_rgb(frame), dim, filter, color)?,
+    let mut prev_duration = first_duration;
+    let chunk_len = rayon::current_num_threads() * 2;
+    let mut done = false;
+    // how many of the compressed frames `on_frames` got
+    let mut reported = 0;
+    while !done {
+        if crate::cancel::is_cancelled() {
+            return Err("the daemon no longer wants the animation".to_string());
+        }
+        // decoding is inherently sequential, but processing and packing the decoded frames isn't.
+        // We do it in chunks so that we never hold every uncompressed frame at once
+        let chunk: Vec<_> = frames
+            .by_ref()
+            .map_while(Result::ok)
+            .take(chunk_len)
+            .map(|frame| {
+                let duration = Duration::from(frame.delay());
+                (frame, duration)
+            })
+            .collect();
+        // we stop at the first frame we fail to decode
+        done = chunk.len() < chunk_len;
+        if chunk.is_empty() {
+            break;
+        }
+        let imgs = chunk
+            .into_par_iter()
+            .map(|(frame, duration)| Ok((to_pixels(frame)?, duration)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let prev = canvas.as_ref().unwrap_or(&first_img);
+        let block_size = match block_size {
+            Some(block_size) => block_size,
+            None => *block_size.insert(BitPack::best_block_size(
+                prev,
+                &imgs[0].0,
+                keep_alpha,
+                compression,
+            )?),
         };
+        let is_first_chunk = canvas.is_none();
+        let packed = (0..imgs.len())
+            .into_par_iter()
+            .map(|i| {
+                let img = &imgs[i].0;
+                let prev = if i == 0 { prev } else { &imgs[i - 1].0 };
+                let compressed = if i == 0 && is_first_chunk && keep_alpha {
+                    // the daemon shows the first frame without its alpha channel, so the first
+                    // frame we play has to be packed whole
+                    BitPack::pack_with_alpha(None, img, compression, block_size)?
+                } else {
+                    pack(prev, img, block_size)?
+                };
+                let reverse = match direction {
+                    cli::PlaybackDirection::Forward => None,
+                    _ => Some(pack(img, prev, block_size)?),
+                };
+                Ok((compressed, reverse))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
 
-        compressed_frames.push((
-            BitPack::pack(canvas.as_ref().unwrap_or(&first_img), &img)?,
-            duration,
-        ));
-        canvas = Some(img);
+        for ((compressed, reverse), (_, duration)) in packed.into_iter().zip(&imgs) {
+            compressed_frames.push((compressed, *duration));
+            if let Some(reverse) = reverse {
+                reverse_frames.push((reverse, prev_duration));
+            }
+            prev_duration = *duration;
+        }
+        canvas = imgs.into_iter().last().map(|(img, _)| img);
+        if !done && direction != cli::PlaybackDirection::Reverse {
+            on_frames(&compressed_frames[reported..], false)?;
+            if return_frames {
+                reported = compressed_frames.len();
+            } else {
+                compressed_frames.clear();
+            }
+        }
+    }
+    let last_img = canvas.as_ref().unwrap_or(&first_img);
+    if direction == cli::PlaybackDirection::Reverse {
+        let block_size = block_size.unwrap_or(1);
+        // going back from the first frame to the last one is the first step we play
+        let wrap = if keep_alpha {
+            BitPack::pack_with_alpha(None, last_img, compression, block_size)?
+        } else {
+            pack(&first_img, last_img, block_size)?
+        };
+        let backward: Vec<_> = std::iter::once((wrap, prev_duration))
+            .chain(reverse_frames.into_iter().rev())
+            .collect();
+        on_frames(&backward, true)?;
+        return Ok((backward, Vec::new()));
     }
     //Add the first frame we got earlier:
     compressed_frames.push((
-        BitPack::pack(canvas.as_ref().unwrap_or(&first_img), &first_img)?,
+        pack(last_img, &first_img, block_size.unwrap_or(1))?,
         first_duration,
     ));
-    Ok(compressed_frames)
+    on_frames(&compressed_frames[reported..], true)?;
+    Ok((compressed_frames, reverse_frames))
+}
+
+/// Zooms from all of `img` into `end`, over `ken_burns.duration`, and then back out, easing in
+/// and out of both ends, so that turning around never jerks. Without an end, every frame is all of
+/// `img`. Every frame is as large as `img`, and shows a part of it that starts and ends between
+/// pixels, see [`resample`], since rounding it to whole pixels makes the zoom judder
+fn ken_burns_frames<'a>(img: RgbaImage, end: Option<Region>, ken_burns: KenBurns) -> Frames<'a> {
+    let (width, height) = img.dimensions();
+    let (x, y, w, h) = end.unwrap_or((0, 0, width, height));
+    let steps = ((ken_burns.duration.as_secs_f32() * ken_burns.fps).round() as u32).max(1);
+    let delay =
+        image::Delay::from_saturating_duration(Duration::from_secs_f32(1.0 / ken_burns.fps));
+    let lerp = |from: u32, to: u32, t: f32| from as f32 + (to as f32 - from as f32) * t;
+    Frames::new(Box::new((0..2 * steps).map(move |i| {
+        let t = (1.0 - (std::f32::consts::PI * i as f32 / steps as f32).cos()) / 2.0;
+        let region = (
+            lerp(0, x, t),
+            lerp(0, y, t),
+            lerp(width, w, t),
+            lerp(height, h, t),
+        );
+        Ok(image::Frame::from_parts(
+            resample(&img, region),
+            0,
+            0,
+            delay,
+        ))
+    })))
+}
+
+/// The part of `img` at `x`, `y`, `w` pixels wide and `h` high, none of which need to be whole,
+/// stretched over all of `img`, blending every pixel from the 4 nearest ones
+fn resample(img: &RgbaImage, (x, y, w, h): (f32, f32, f32, f32)) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return img.clone();
+    }
+    let (scale_x, scale_y) = (w / width as f32, h / height as f32);
+    // the centers of the pixels at the edges of the part, so that we don't blend in those outside
+    let (max_x, max_y) = ((x + w - 1.0).max(x), (y + h - 1.0).max(y));
+    let src = img.as_raw();
+    let stride = width as usize * 4;
+    let mut resampled = vec![0; src.len()];
+    resampled
+        .par_chunks_exact_mut(stride)
+        .enumerate()
+        .for_each(|(row, dst)| {
+            let fy = (y + (row as f32 + 0.5) * scale_y - 0.5).clamp(y, max_y);
+            let (y0, wy) = (fy as usize, fy.fract());
+            let y1 = (y0 + 1).min(height as usize - 1);
+            for (col, dst) in dst.chunks_exact_mut(4).enumerate() {
+                let fx = (x + (col as f32 + 0.5) * scale_x - 0.5).clamp(x, max_x);
+                let (x0, wx) = (fx as usize, fx.fract());
+                let x1 = (x0 + 1).min(width as usize - 1);
+                let at = |x: usize, y: usize, c: usize| src[y * stride + x * 4 + c] as f32;
+                for (c, dst) in dst.iter_mut().enumerate() {
+                    let top = at(x0, y0, c) + (at(x1, y0, c) - at(x0, y0, c)) * wx;
+                    let bottom = at(x0, y1, c) + (at(x1, y1, c) - at(x0, y1, c)) * wx;
+                    *dst = (top + (bottom - top) * wy).round() as u8;
+                }
+            }
+        });
+    RgbaImage::from_raw(width, height, resampled).unwrap()
 }
 
-pub fn make_filter(filter: &cli::Filter) -> fast_image_resize::FilterType {
-    match filter {
-        cli::Filter::Nearest => fast_image_resize::FilterType::Box,
-        cli::Filter::Bilinear => fast_image_resize::FilterType::Bilinear,
-        cli::Filter::CatmullRom => fast_image_resize::FilterType::CatmullRom,
-        cli::Filter::Mitchell => fast_image_resize::FilterType::Mitchell,
-        cli::Filter::Lanczos3 => fast_image_resize::FilterType::Lanczos3,
+/// Only keeps the part of every frame `view` picks
+fn crop_frames(frames: Frames<'_>, view: View) -> Frames<'_> {
+    if view == View::default() {
+        return frames;
+    }
+    let mut frames = frames.into_iter();
+    Frames::new(Box::new(std::iter::from_fn(move || {
+        let frame = match frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        let Some((x, y, w, h)) = view.region(frame.buffer().dimensions()) else {
+            return Some(Ok(frame));
+        };
+        let delay = frame.delay();
+        let cropped = image::imageops::crop_imm(frame.buffer(), x, y, w, h).to_image();
+        Some(Ok(image::Frame::from_parts(cropped, 0, 0, delay)))
+    })))
+}
+
+/// Merges frames that come sooner than `max_fps` allows into the one before them, which lasts as
+/// long as they did together. The daemon wouldn't show them anyway
+pub fn limit_fps(frames: Frames<'_>, max_fps: Option<u32>) -> Frames<'_> {
+    let Some(max_fps) = max_fps.filter(|fps| *fps > 0) else {
+        return frames;
+    };
+    let interval = Duration::from_secs(1) / max_fps;
+    let mut frames = frames.into_iter().peekable();
+    Frames::new(Box::new(std::iter::from_fn(move || {
+        let frame = match frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut delay = Duration::from(frame.delay());
+        while delay < interval {
+            match frames.next_if(|next| next.is_ok()) {
+                Some(Ok(merged)) => delay += Duration::from(merged.delay()),
+                _ => break,
+            }
+        }
+        let (left, top) = (frame.left(), frame.top());
+        Some(Ok(image::Frame::from_parts(
+            frame.into_buffer(),
+            left,
+            top,
+            image::Delay::from_saturating_duration(delay),
+        )))
+    })))
+}
+
+/// Plays a transition between every two frames that show the same scene, and cuts between those
+/// that don't, see `swww img --detect-scene-change`. `transition` makes the frames going from one
+/// frame to the next in the time given, the last of which is the next frame. They share its delay
+pub fn transition_scenes<'a>(
+    frames: Frames<'a>,
+    threshold: f32,
+    mut transition: impl FnMut(&RgbaImage, &RgbaImage, Duration) -> Result<Vec<RgbaImage>, String> + 'a,
+) -> Frames<'a> {
+    let mut frames = frames.into_iter();
+    let mut previous: Option<RgbaImage> = None;
+    let mut made = std::collections::VecDeque::new();
+    Frames::new(Box::new(std::iter::from_fn(move || {
+        if let Some(frame) = made.pop_front() {
+            return Some(Ok(frame));
+        }
+        let frame = match frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        let (left, top, delay) = (frame.left(), frame.top(), Duration::from(frame.delay()));
+        let new = frame.into_buffer();
+        let same_scene = |old: &RgbaImage| {
+            old.dimensions() == new.dimensions()
+                && !utils::scene::is_scene_change((old, 4), (&new, 4), threshold)
+        };
+        let transitioned = match previous.replace(new.clone()) {
+            Some(old) if same_scene(&old) => match transition(&old, &new, delay) {
+                Ok(transitioned) => transitioned,
+                Err(e) => {
+                    let e = std::io::Error::new(std::io::ErrorKind::Other, e);
+                    return Some(Err(image::ImageError::IoError(e)));
+                }
+            },
+            _ => Vec::new(),
+        };
+        if transitioned.is_empty() {
+            let delay = image::Delay::from_saturating_duration(delay);
+            return Some(Ok(image::Frame::from_parts(new, left, top, delay)));
+        }
+        let delay = image::Delay::from_saturating_duration(delay / transitioned.len() as u32);
+        made.extend(
+            transitioned
+                .into_iter()
+                .map(|img| image::Frame::from_parts(img, left, top, delay)),
+        );
+        made.pop_front().map(Ok)
+    })))
+}
+
+/// Scales a frame delay so that the animation plays `factor` times faster
+pub fn stretch_delay(delay: Duration, factor: f32) -> Duration {
+    delay.div_f64(factor as f64)
+}
+
+pub fn make_compression(compression: cli::Compression) -> CompressionBackend {
+    match compression {
+        cli::Compression::Lz4 => CompressionBackend::Lz4,
+        cli::Compression::Zstd => CompressionBackend::Zstd,
+        cli::Compression::None => CompressionBackend::None,
+    }
+}
+
+/// How we scale images: with which filter, and whether in linear light, see [`utils::gamma`]
+#[derive(Debug, Clone, Copy)]
+pub struct Scaling {
+    pub filter: FilterType,
+    pub linear: bool,
+}
+
+impl From<FilterType> for Scaling {
+    fn from(filter: FilterType) -> Self {
+        Self {
+            filter,
+            linear: false,
+        }
+    }
+}
+
+pub fn make_scaling(filter: &cli::Filter, gamma_correct: bool) -> Scaling {
+    let filter = match filter {
+        cli::Filter::Nearest => FilterType::Box,
+        cli::Filter::Bilinear => FilterType::Bilinear,
+        cli::Filter::CatmullRom => FilterType::CatmullRom,
+        cli::Filter::Mitchell => FilterType::Mitchell,
+        cli::Filter::Lanczos3 => FilterType::Lanczos3,
+    };
+    Scaling {
+        filter,
+        linear: gamma_correct,
+    }
+}
+
+/// How we tell the daemon to adjust an image, every time it draws it
+pub fn ipc_adjustments(adjustments: &cli::Adjustments) -> ipc::Adjustments {
+    // clap only takes percents from -100 to 100
+    ipc::Adjustments {
+        blur: adjustments.blur,
+        brightness: adjustments.brightness as i8,
+        contrast: adjustments.contrast as i8,
+        saturation: adjustments.saturation as i8,
+        tone: adjustments.tone.map(|tone| match tone {
+            cli::Tone::Grayscale => ipc::Tone::Grayscale,
+            cli::Tone::Invert => ipc::Tone::Invert,
+            cli::Tone::Sepia => ipc::Tone::Sepia,
+        }),
+    }
+}
+
+/// How we tell the daemon to dither an image, every time it draws it
+pub fn ipc_dither(dither: cli::Dither) -> ipc::Dither {
+    match dither {
+        cli::Dither::Bayer => ipc::Dither::Bayer,
+        cli::Dither::FloydSteinberg => ipc::Dither::FloydSteinberg,
     }
 }
 
@@ -207,7 +1010,12 @@ pub fn img_pad(
     let (padded_w, padded_h) = (padded_w as usize, padded_h as usize);
     let mut padded = Vec::with_capacity(padded_h * padded_w * 3);
 
-    let img = image::imageops::crop(&mut img, 0, 0, dimensions.0, dimensions.1).to_image();
+    // if the image is larger than the screen, we keep its middle
+    let (x, y) = (
+        img.width().saturating_sub(dimensions.0) / 2,
+        img.height().saturating_sub(dimensions.1) / 2,
+    );
+    let img = image::imageops::crop(&mut img, x, y, dimensions.0, dimensions.1).to_image();
     let (img_w, img_h) = img.dimensions();
     let (img_w, img_h) = (img_w as usize, img_h as usize);
     let raw_img = img.into_vec();
@@ -218,10 +1026,10 @@ pub fn img_pad(
         padded.push(color[0]);
     }
 
-    // Calculate left and right border widths. `u32::div` rounds toward 0, so, if `img_w` is odd,
-    // add an extra pixel to the right border to ensure the row is the correct width.
+    // Calculate left and right border widths. `u32::div` rounds toward 0, so, if the borders can't
+    // be the same width, the right one gets the extra pixel.
     let left_border_w = (padded_w - img_w) / 2;
-    let right_border_w = left_border_w + (img_w % 2);
+    let right_border_w = padded_w - img_w - left_border_w;
 
     for row in 0..img_h {
         for _ in 0..left_border_w {
@@ -251,20 +1059,70 @@ pub fn img_pad(
     Ok(padded)
 }
 
-/// Convert an RGB &[u8] to BRG in-place by swapping bytes
+/// Convert an RGB &[u8] to BRG in-place by swapping bytes. Doing it twice gets the RGB back
 #[inline]
-fn rgb_to_brg(rgb: &mut [u8]) {
+pub fn rgb_to_brg(rgb: &mut [u8]) {
     for pixel in rgb.chunks_exact_mut(3) {
         pixel.swap(0, 2);
     }
 }
 
+/// Scales `rgb`, an image `src` in size, to `dst`. With `centering`, it first crops it to the aspect
+/// ratio of `dst`, see [`img_resize_crop`]
+fn scale(
+    rgb: Vec<u8>,
+    src: (u32, u32),
+    dst: (u32, u32),
+    scaling: Scaling,
+    centering: Option<(f32, f32)>,
+) -> Result<Vec<u8>, String> {
+    let (buf, pixel_type) = if scaling.linear {
+        let linear = gamma::to_linear(&rgb);
+        let buf = linear.into_iter().flat_map(u16::to_ne_bytes).collect();
+        (buf, PixelType::U16x3)
+    } else {
+        (rgb, PixelType::U8x3)
+    };
+    let src = fast_image_resize::Image::from_vec_u8(
+        // We unwrap below because we know the images's dimensions should never be 0
+        NonZeroU32::new(src.0).unwrap(),
+        NonZeroU32::new(src.1).unwrap(),
+        buf,
+        pixel_type,
+    )
+    .map_err(|e| e.to_string())?;
+
+    // We unwrap below because we know the outputs's dimensions should never be 0
+    let new_w = NonZeroU32::new(dst.0).unwrap();
+    let new_h = NonZeroU32::new(dst.1).unwrap();
+    let mut src_view = src.view();
+    if let Some(centering) = centering {
+        src_view.set_crop_box_to_fit_dst_size(new_w, new_h, Some(centering));
+    }
+
+    let mut dst = fast_image_resize::Image::new(new_w, new_h, pixel_type);
+    let mut resizer = Resizer::new(fast_image_resize::ResizeAlg::Convolution(scaling.filter));
+    resizer
+        .resize(&src_view, &mut dst.view_mut())
+        .map_err(|e| e.to_string())?;
+
+    let resized = dst.into_vec();
+    if !scaling.linear {
+        return Ok(resized);
+    }
+    let to_srgb = gamma::to_srgb_table();
+    Ok(resized
+        .chunks_exact(2)
+        .map(|b| to_srgb[u16::from_ne_bytes([b[0], b[1]]) as usize])
+        .collect())
+}
+
 /// Resize an image to fit within the given dimensions, covering as much space as possible without
 /// cropping.
 pub fn img_resize_fit(
     img: RgbImage,
     dimensions: (u32, u32),
-    filter: FilterType,
+    scaling: Scaling,
     padding_color: &[u8; 3],
 ) -> Result<Vec<u8>, String> {
     let (width, height) = dimensions;
@@ -286,31 +1144,16 @@ pub fn img_resize_fit(
             (width, (img_h as f32 * scale) as u32)
         };
 
-        let src = match fast_image_resize::Image::from_vec_u8(
-            // We unwrap below because we know the images's dimensions should never be 0
-            NonZeroU32::new(img_w).unwrap(),
-            NonZeroU32::new(img_h).unwrap(),
+        let resized = scale(
             img.into_raw(),
-            PixelType::U8x3,
-        ) {
-            Ok(i) => i,
-            Err(e) => return Err(e.to_string()),
-        };
-
-        // We unwrap below because we know the outputs's dimensions should never be 0
-        let new_w = NonZeroU32::new(trg_w).unwrap();
-        let new_h = NonZeroU32::new(trg_h).unwrap();
-
-        let mut dst = fast_image_resize::Image::new(new_w, new_h, PixelType::U8x3);
-        let mut dst_view = dst.view_mut();
-
-        let mut resizer = Resizer::new(fast_image_resize::ResizeAlg::Convolution(filter));
-        if let Err(e) = resizer.resize(&src.view(), &mut dst_view) {
-            return Err(e.to_string());
-        }
+            (img_w, img_h),
+            (trg_w, trg_h),
+            scaling,
+            None,
+        )?;
 
         img_pad(
-            image::RgbImage::from_raw(trg_w, trg_h, dst.into_vec()).unwrap(),
+            image::RgbImage::from_raw(trg_w, trg_h, resized).unwrap(),
             dimensions,
             padding_color,
         )
@@ -323,40 +1166,143 @@ pub fn img_resize_fit(
     }
 }
 
-pub fn img_resize_crop(
+/// Resize an image to exactly the given dimensions, without preserving its aspect ratio
+pub fn img_resize_stretch(
     img: RgbImage,
     dimensions: (u32, u32),
-    filter: FilterType,
+    scaling: Scaling,
 ) -> Result<Vec<u8>, String> {
     let (width, height) = dimensions;
     let (img_w, img_h) = img.dimensions();
     let mut resized_img = if (img_w, img_h) != (width, height) {
-        let src = match fast_image_resize::Image::from_vec_u8(
-            // We unwrap below because we know the images's dimensions should never be 0
-            NonZeroU32::new(img_w).unwrap(),
-            NonZeroU32::new(img_h).unwrap(),
-            img.into_raw(),
-            PixelType::U8x3,
-        ) {
-            Ok(i) => i,
-            Err(e) => return Err(e.to_string()),
-        };
+        scale(img.into_raw(), (img_w, img_h), dimensions, scaling, None)?
+    } else {
+        img.into_vec()
+    };
+    rgb_to_brg(&mut resized_img);
+    Ok(resized_img)
+}
 
-        // We unwrap below because we know the outputs's dimensions should never be 0
-        let new_w = NonZeroU32::new(width).unwrap();
-        let new_h = NonZeroU32::new(height).unwrap();
-        let mut src_view = src.view();
-        src_view.set_crop_box_to_fit_dst_size(new_w, new_h, Some((0.5, 0.5)));
+/// Repeat an image at its original size until it covers the given dimensions
+pub fn img_tile(img: &RgbImage, dimensions: (u32, u32)) -> Vec<u8> {
+    let (width, height) = dimensions;
+    let (img_w, img_h) = img.dimensions();
+    let mut tiled = Vec::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b] = img.get_pixel(x % img_w, y % img_h).0;
+            tiled.extend_from_slice(&[b, g, r]);
+        }
+    }
+    tiled
+}
 
-        let mut dst = fast_image_resize::Image::new(new_w, new_h, PixelType::U8x3);
-        let mut dst_view = dst.view_mut();
+/// Transforms `pixels`, `dim` in size and with `N` bytes per pixel, the way the daemon's buffers
+/// for an output with that transform are
+pub fn transform_pixels<const N: usize>(
+    pixels: Vec<u8>,
+    dim: (u32, u32),
+    transform: ipc::Transform,
+) -> Vec<u8> {
+    if transform == ipc::Transform::Normal {
+        return pixels;
+    }
+    let (width, height) = (dim.0 as usize, dim.1 as usize);
+    let (buf_width, _) = transform.buffer_dim((width, height));
+    // the last pixel of each row and column, since `Transform::to_buffer` works with lengths
+    let last = ((width - 1) as f32, (height - 1) as f32);
+    let mut transformed = vec![0; pixels.len()];
+    for (i, pixel) in pixels.chunks_exact(N).enumerate() {
+        let (x, y) = transform.to_buffer(((i % width) as f32, (i / width) as f32), last);
+        let j = (y as usize * buf_width + x as usize) * N;
+        transformed[j..j + N].copy_from_slice(pixel);
+    }
+    transformed
+}
 
-        let mut resizer = Resizer::new(fast_image_resize::ResizeAlg::Convolution(filter));
-        if let Err(e) = resizer.resize(&src_view, &mut dst_view) {
-            return Err(e.to_string());
-        }
+/// Resize an image so it always covers the full width of the screen, adding bars on the top and
+/// bottom if it is too short, and cropping its top and bottom if it is too tall.
+pub fn img_resize_letterbox(
+    img: RgbImage,
+    dimensions: (u32, u32),
+    scaling: Scaling,
+    padding_color: &[u8; 3],
+) -> Result<Vec<u8>, String> {
+    let (width, height) = dimensions;
+    let (img_w, img_h) = img.dimensions();
+    let trg_h = ((img_h as f32 * width as f32 / img_w as f32).round() as u32).max(1);
+    img_resize_to_bars(
+        img,
+        dimensions,
+        (width, trg_h.min(height)),
+        scaling,
+        padding_color,
+    )
+}
 
-        dst.into_vec()
+/// Resize an image so it always covers the full height of the screen, adding bars on the left and
+/// right if it is too narrow, and cropping its sides if it is too wide.
+pub fn img_resize_pillarbox(
+    img: RgbImage,
+    dimensions: (u32, u32),
+    scaling: Scaling,
+    padding_color: &[u8; 3],
+) -> Result<Vec<u8>, String> {
+    let (width, height) = dimensions;
+    let (img_w, img_h) = img.dimensions();
+    let trg_w = ((img_w as f32 * height as f32 / img_h as f32).round() as u32).max(1);
+    img_resize_to_bars(
+        img,
+        dimensions,
+        (trg_w.min(width), height),
+        scaling,
+        padding_color,
+    )
+}
+
+/// Resizes (and crops, if the aspect ratios differ) the image to exactly `target`, and then pads it
+/// to `dimensions`
+fn img_resize_to_bars(
+    img: RgbImage,
+    dimensions: (u32, u32),
+    target: (u32, u32),
+    scaling: Scaling,
+    padding_color: &[u8; 3],
+) -> Result<Vec<u8>, String> {
+    let mut resized = img_resize_crop(img, target, scaling, CENTER)?;
+    if target == dimensions {
+        return Ok(resized);
+    }
+    // img_pad expects RGB
+    rgb_to_brg(&mut resized);
+    img_pad(
+        image::RgbImage::from_raw(target.0, target.1, resized).unwrap(),
+        dimensions,
+        padding_color,
+    )
+}
+
+/// Resize an image to fill the given dimensions, cropping out what doesn't fit.
+///
+/// `centering` controls where the crop window goes, like in fast_image_resize's
+/// `set_crop_box_to_fit_dst_size`: (0.0, 0.0) keeps the top left of the image, while [`CENTER`]
+/// keeps its middle.
+pub fn img_resize_crop(
+    img: RgbImage,
+    dimensions: (u32, u32),
+    scaling: Scaling,
+    centering: (f32, f32),
+) -> Result<Vec<u8>, String> {
+    let (width, height) = dimensions;
+    let (img_w, img_h) = img.dimensions();
+    let mut resized_img = if (img_w, img_h) != (width, height) {
+        scale(
+            img.into_raw(),
+            (img_w, img_h),
+            dimensions,
+            scaling,
+            Some(centering),
+        )?
     } else {
         img.into_vec()
     };
@@ -368,10 +1314,366 @@ pub fn img_resize_crop(
     Ok(resized_img)
 }
 
-pub fn make_transition(img: &cli::Img) -> ipc::Transition {
-    let mut angle = img.transition_angle;
-    let mut step = img.transition_step;
+/// Makes `img` into the overlay `swww overlay` draws on outputs `dim` in size, with `transform`.
+/// The overlay is premultiplied, so that the daemon blends it with a multiplication less per
+/// channel, and so that its transparent pixels don't bleed their color into the others when we
+/// scale it
+pub fn make_overlay(
+    img: RgbaImage,
+    dim: (u32, u32),
+    transform: ipc::Transform,
+    anchor: cli::OverlayAnchor,
+    resize: cli::OverlayResize,
+    opacity: f32,
+) -> Result<ipc::Overlay, String> {
+    let (img_w, img_h) = img.dimensions();
+    let fit = || {
+        let scale = (dim.0 as f32 / img_w as f32).min(dim.1 as f32 / img_h as f32);
+        (
+            ((img_w as f32 * scale) as u32).clamp(1, dim.0),
+            ((img_h as f32 * scale) as u32).clamp(1, dim.1),
+        )
+    };
+    let target = match resize {
+        cli::OverlayResize::No if img_w <= dim.0 && img_h <= dim.1 => (img_w, img_h),
+        cli::OverlayResize::No | cli::OverlayResize::Fit => fit(),
+        cli::OverlayResize::Crop | cli::OverlayResize::Stretch => dim,
+    };
+
+    // We unwrap below because we know the dimensions should never be 0
+    let mut src = fast_image_resize::Image::from_vec_u8(
+        NonZeroU32::new(img_w).unwrap(),
+        NonZeroU32::new(img_h).unwrap(),
+        img.into_raw(),
+        PixelType::U8x4,
+    )
+    .map_err(|e| e.to_string())?;
+    fast_image_resize::MulDiv::default()
+        .multiply_alpha_inplace(&mut src.view_mut())
+        .map_err(|e| e.to_string())?;
+    let mut pixels = if target == (img_w, img_h) {
+        src.into_vec()
+    } else {
+        let (new_w, new_h) = (
+            NonZeroU32::new(target.0).unwrap(),
+            NonZeroU32::new(target.1).unwrap(),
+        );
+        let mut src_view = src.view();
+        if resize == cli::OverlayResize::Crop {
+            // keep the part of the image nearest to where it goes
+            src_view.set_crop_box_to_fit_dst_size(new_w, new_h, Some(anchor.position()));
+        }
+        let mut dst = fast_image_resize::Image::new(new_w, new_h, PixelType::U8x4);
+        Resizer::new(fast_image_resize::ResizeAlg::Convolution(
+            FilterType::Lanczos3,
+        ))
+        .resize(&src_view, &mut dst.view_mut())
+        .map_err(|e| e.to_string())?;
+        dst.into_vec()
+    };
 
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        if opacity < 1.0 {
+            for c in pixel {
+                *c = (*c as f32 * opacity).round() as u8;
+            }
+        }
+    }
+    let (x, y) = anchor.position();
+    Ok(ipc::Overlay {
+        img: transform_pixels::<4>(pixels, target, transform).into_boxed_slice(),
+        dim: transform.buffer_dim(target),
+        anchor: transform.to_buffer((x, y), (1.0, 1.0)),
+    })
+}
+
+/// Lays out the outputs for `--span`, given the logical position, logical size and scale of each
+/// one. The layout uses the largest scale, so that no output has to upscale its part of the image.
+///
+/// Returns the size of the whole layout, and the region of it each output displays.
+#[allow(clippy::type_complexity)]
+pub fn span_layout(outputs: &[((i32, i32), (u32, u32), i32)]) -> ((u32, u32), Vec<Region>) {
+    let scale = outputs
+        .iter()
+        .map(|(_, _, scale)| *scale)
+        .max()
+        .unwrap_or(1)
+        .max(1) as i64;
+    let left = outputs.iter().map(|((x, _), _, _)| *x as i64).min();
+    let top = outputs.iter().map(|((_, y), _, _)| *y as i64).min();
+    let right = outputs
+        .iter()
+        .map(|((x, _), (w, _), _)| *x as i64 + *w as i64)
+        .max();
+    let bottom = outputs
+        .iter()
+        .map(|((_, y), (_, h), _)| *y as i64 + *h as i64)
+        .max();
+    let (Some(left), Some(top), Some(right), Some(bottom)) = (left, top, right, bottom) else {
+        return ((0, 0), Vec::new());
+    };
+
+    let size = (
+        ((right - left) * scale) as u32,
+        ((bottom - top) * scale) as u32,
+    );
+    let regions = outputs
+        .iter()
+        .map(|((x, y), (w, h), _)| {
+            (
+                ((*x as i64 - left) * scale) as u32,
+                ((*y as i64 - top) * scale) as u32,
+                (*w as i64 * scale) as u32,
+                (*h as i64 * scale) as u32,
+            )
+        })
+        .collect();
+    (size, regions)
+}
+
+/// The smallest grid `count` images fit in that is at least as wide as it is high, as columns and
+/// rows
+pub fn collage_grid(count: u32) -> (u32, u32) {
+    let mut columns = 1;
+    while columns * columns < count {
+        columns += 1;
+    }
+    (columns, (count.max(1) + columns - 1) / columns)
+}
+
+/// The cells of a `columns`x`rows` grid over `dim`, row by row, with `gap` pixels between them. The
+/// pixels left over when the size doesn't divide evenly go to the first cells of every row and
+/// column. Cells are empty if the gaps take the whole size
+pub fn collage_cells(dim: (u32, u32), (columns, rows): (u32, u32), gap: u32) -> Vec<Region> {
+    // where every cell starts, and how long it is, along one axis
+    let split = |length: u32, count: u32| {
+        let available = length.saturating_sub(gap.saturating_mul(count - 1));
+        let (base, extra) = (available / count, available % count);
+        let mut start = 0;
+        (0..count)
+            .map(|i| {
+                let cell = (start, base + u32::from(i < extra));
+                start += cell.1 + gap;
+                cell
+            })
+            .collect::<Vec<_>>()
+    };
+    let (xs, ys) = (split(dim.0, columns), split(dim.1, rows));
+    ys.iter()
+        .flat_map(|&(y, height)| xs.iter().map(move |&(x, width)| (x, y, width, height)))
+        .collect()
+}
+
+/// Computes the `centering` we should give to [`img_resize_crop`] so that the crop window is
+/// centered on `focus`, as much as the image's borders allow it. Without a focus, we just center
+/// the crop on the image.
+pub fn crop_centering(
+    img_dim: (u32, u32),
+    dimensions: (u32, u32),
+    focus: Option<Region>,
+) -> (f32, f32) {
+    let Some((focus_x, focus_y, focus_w, focus_h)) = focus else {
+        return CENTER;
+    };
+    let (img_w, img_h) = (img_dim.0 as f32, img_dim.1 as f32);
+    let (width, height) = (dimensions.0 as f32, dimensions.1 as f32);
+
+    // the crop window keeps the output's aspect ratio and as much of the image as possible
+    let (crop_w, crop_h) = if img_w / img_h > width / height {
+        (img_h * width / height, img_h)
+    } else {
+        (img_w, img_w * height / width)
+    };
+
+    let center_on = |slack: f32, focus_center: f32, crop_len: f32| {
+        if slack <= 0.0 {
+            0.5
+        } else {
+            ((focus_center - crop_len / 2.0) / slack).clamp(0.0, 1.0)
+        }
+    };
+
+    (
+        center_on(
+            img_w - crop_w,
+            focus_x as f32 + focus_w as f32 / 2.0,
+            crop_w,
+        ),
+        center_on(
+            img_h - crop_h,
+            focus_y as f32 + focus_h as f32 / 2.0,
+            crop_h,
+        ),
+    )
+}
+
+/// Finds the largest face in the image, using the face detection model at `model`
+#[cfg(feature = "face-detect")]
+pub fn detect_largest_face(img: &RgbImage, model: &Path) -> Result<Option<Region>, String> {
+    let mut detector = rustface::create_detector(&model.to_string_lossy())
+        .map_err(|e| format!("failed to load face detection model {model:?}: {e}"))?;
+    detector.set_min_face_size(20);
+    detector.set_score_thresh(2.0);
+    detector.set_pyramid_scale_factor(0.8);
+    detector.set_slide_window_step(4, 4);
+
+    let gray = DynamicImage::ImageRgb8(img.clone()).into_luma8();
+    let faces = detector.detect(&rustface::ImageData::new(
+        gray.as_raw(),
+        gray.width(),
+        gray.height(),
+    ));
+
+    Ok(faces
+        .iter()
+        .map(|face| face.bbox())
+        .max_by_key(|bbox| bbox.width() * bbox.height())
+        .map(|bbox| {
+            (
+                bbox.x().max(0) as u32,
+                bbox.y().max(0) as u32,
+                bbox.width(),
+                bbox.height(),
+            )
+        }))
+}
+
+#[cfg(not(feature = "face-detect"))]
+pub fn detect_largest_face(_img: &RgbImage, _model: &Path) -> Result<Option<Region>, String> {
+    Err("swww was compiled without the `face-detect` feature".to_string())
+}
+
+/// The color of a black body at `kelvin` degrees, approximated from Mitchell Charity's blackbody
+/// color table (http://www.vendian.org/mncharity/dir3/blackbody/), like Tanner Helland's
+fn blackbody_rgb(kelvin: u32) -> [f32; 3] {
+    let t = kelvin as f32 / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (t - 60.0).powf(-0.13320476)
+    };
+    let g = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12217 * (t - 60.0).powf(-0.07551485)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    [
+        r.clamp(0.0, 255.0),
+        g.clamp(0.0, 255.0),
+        b.clamp(0.0, 255.0),
+    ]
+}
+
+/// How much to multiply each channel by to shift an image's white balance to `kelvin` degrees.
+///
+/// 6500K is neutral. We also keep the luminance of white the same, so that warmer temperatures
+/// actually make the reds stronger, instead of only dimming greens and blues.
+pub fn color_temp_gains(kelvin: u32) -> [f32; 3] {
+    let neutral = blackbody_rgb(6500);
+    let target = blackbody_rgb(kelvin);
+    let gains = [
+        target[0] / neutral[0],
+        target[1] / neutral[1],
+        target[2] / neutral[2],
+    ];
+    // Rec. 709 luma coefficients
+    let luma = 0.2126 * gains[0] + 0.7152 * gains[1] + 0.0722 * gains[2];
+    gains.map(|gain| gain / luma)
+}
+
+/// Shifts the image's white balance to `kelvin` degrees (see [`color_temp_gains`])
+pub fn apply_color_temp(img: &mut RgbImage, kelvin: u32) {
+    let gains = color_temp_gains(kelvin);
+    for pixel in img.pixels_mut() {
+        for (col, gain) in pixel.0.iter_mut().zip(gains) {
+            *col = (*col as f32 * gain).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Local luma variance at and above which a pixel is fully replaced by its blurred version. This
+/// is a standard deviation of 64, about half of what a black and white checkerboard has
+const HEATMAP_SATURATION: f64 = 64.0 * 64.0;
+
+/// Blurs the busy (high-contrast) regions of the image, leaving the flat ones alone. Every pixel is
+/// blended with a gaussian blur of standard deviation `sigma`, in proportion to the variance of the
+/// luma around it
+pub fn apply_heatmap_blur(img: &mut RgbImage, sigma: f32) {
+    if img.width() == 0 || img.height() == 0 {
+        return;
+    }
+    let blurred = image::imageops::blur(img, sigma);
+    let radius = (sigma * 2.0).ceil().max(1.0) as usize;
+    let variance = local_variance(img, radius);
+    for ((pixel, blurred), variance) in img.pixels_mut().zip(blurred.pixels()).zip(variance) {
+        let weight = (variance / HEATMAP_SATURATION).min(1.0);
+        for (col, blurred) in pixel.0.iter_mut().zip(blurred.0) {
+            let blended = *col as f64 + (blurred as f64 - *col as f64) * weight;
+            *col = blended.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// Variance of the luma in the box of `radius` around every pixel, in row-major order. We compute
+/// it as a box filter of the luma and of its square, through summed-area tables
+fn local_variance(img: &RgbImage, radius: usize) -> Vec<f64> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    // the tables have an extra row and column of zeroes at the top and left
+    let stride = width + 1;
+    let mut sums = vec![0.0; stride * (height + 1)];
+    let mut squares = vec![0.0; stride * (height + 1)];
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let [r, g, b] = pixel.0.map(f64::from);
+        // Rec. 709 luma coefficients
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let i = (y as usize + 1) * stride + x as usize + 1;
+        sums[i] = luma + sums[i - 1] + sums[i - stride] - sums[i - stride - 1];
+        squares[i] = luma * luma + squares[i - 1] + squares[i - stride] - squares[i - stride - 1];
+    }
+
+    let mut variance = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let area = ((x1 - x0) * (y1 - y0)) as f64;
+            let boxed = |table: &[f64]| {
+                table[y1 * stride + x1] - table[y0 * stride + x1] - table[y1 * stride + x0]
+                    + table[y0 * stride + x0]
+            };
+            let mean = boxed(&sums) / area;
+            variance.push((boxed(&squares) / area - mean * mean).max(0.0));
+        }
+    }
+    variance
+}
+
+/// With `quality`, images of more than `threshold_mib` are encoded as a JPEG, so that they are
+/// practical to send to the daemon. Returns the bytes to send and whether they are a JPEG
+pub fn transcode_large_img(
+    pixels: Vec<u8>,
+    dim: (u32, u32),
+    quality: Option<u8>,
+    threshold_mib: u32,
+) -> Result<(Box<[u8]>, bool), String> {
+    match quality {
+        Some(quality) if pixels.len() > threshold_mib as usize * 1024 * 1024 => {
+            let jpeg = utils::jpeg::encode(&pixels, dim, quality)?;
+            Ok((jpeg.into_boxed_slice(), true))
+        }
+        _ => Ok((pixels.into_boxed_slice(), false)),
+    }
+}
+
+pub fn make_transition(img: &cli::Img) -> ipc::Transition {
     let x = match img.transition_pos.x {
         cli::CliCoord::Percent(x) => {
             if !(0.0..=1.0).contains(&x) {
@@ -396,9 +1698,67 @@ pub fn make_transition(img: &cli::Img) -> ipc::Transition {
         cli::CliCoord::Pixel(y) => Coord::Pixel(y),
     };
 
-    let mut pos = Position::new(x, y);
+    let pos = Position {
+        follow_cursor: img.transition_pos.cursor,
+        ..Position::new(x, y)
+    };
+    let (startup_type, startup_step, startup_angle, startup_pos) = transition_shape(
+        &img.transition_on_startup,
+        img.transition_step,
+        img.transition_angle,
+        pos.clone(),
+    );
+    let (transition_type, step, angle, pos) = transition_shape(
+        &img.transition_type,
+        img.transition_step,
+        img.transition_angle,
+        pos,
+    );
+
+    ipc::Transition {
+        duration: img.transition_duration,
+        step,
+        fps: img.transition_fps,
+        fps_adaptive: img.transition_fps_adaptive,
+        bezier: img.transition_bezier,
+        angle,
+        pos,
+        transition_type,
+        wave: img.transition_wave,
+        invert_y: img.invert_y,
+        direction: match img.transition_direction {
+            cli::Direction::Left => ipc::Direction::Left,
+            cli::Direction::Right => ipc::Direction::Right,
+            cli::Direction::Up => ipc::Direction::Up,
+            cli::Direction::Down => ipc::Direction::Down,
+        },
+        bg_color: img.fill_color,
+        reversed: img.transition_reverse,
+        gamma_correct: img.gamma_correct,
+        loops: img.transition_loop,
+        first_frame: !img.no_transition_on_startup,
+        startup: ipc::StartupTransition {
+            transition_type: startup_type,
+            step: startup_step,
+            angle: startup_angle,
+            pos: startup_pos,
+        },
+        queue: img.queue,
+        scene_change_threshold: img
+            .detect_scene_change
+            .then_some(img.scene_change_threshold),
+    }
+}
 
-    let transition_type = match img.transition_type {
+/// Resolves the aliases of `transition_type` (like 'left', which is a 'wipe' with a 180 degree
+/// angle), returning the actual type, step, angle and position to use
+fn transition_shape(
+    transition_type: &cli::TransitionType,
+    mut step: u8,
+    mut angle: f64,
+    mut pos: Position,
+) -> (ipc::TransitionType, u8, f64, Position) {
+    let transition_type = match transition_type {
         cli::TransitionType::None => {
             step = u8::MAX;
             ipc::TransitionType::Simple
@@ -409,6 +1769,10 @@ pub fn make_transition(img: &cli::Img) -> ipc::Transition {
         cli::TransitionType::Outer => ipc::TransitionType::Outer,
         cli::TransitionType::Grow => ipc::TransitionType::Grow,
         cli::TransitionType::Wave => ipc::TransitionType::Wave,
+        cli::TransitionType::Slide => ipc::TransitionType::Slide,
+        cli::TransitionType::ZoomOut => ipc::TransitionType::ZoomOut,
+        cli::TransitionType::Burn => ipc::TransitionType::Burn,
+        cli::TransitionType::Crossfade => ipc::TransitionType::Crossfade,
         cli::TransitionType::Right => {
             angle = 0.0;
             ipc::TransitionType::Wipe
@@ -455,16 +1819,614 @@ pub fn make_transition(img: &cli::Img) -> ipc::Transition {
             }
         }
     };
+    (transition_type, step, angle, pos)
+}
 
-    ipc::Transition {
-        duration: img.transition_duration,
-        step,
-        fps: img.transition_fps,
-        bezier: img.transition_bezier,
-        angle,
-        pos,
-        transition_type,
-        wave: img.transition_wave,
-        invert_y: img.invert_y,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_tell_missing_images_apart_from_broken_ones() {
+        let dir = std::env::temp_dir().join(format!("swww-open-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let missing = ImgBuf::open(&dir.join("missing.png"), None);
+        assert_eq!(
+            missing.err().map(|e| e.kind),
+            Some(ipc::ErrorKind::FileNotFound)
+        );
+
+        let broken = dir.join("broken.png");
+        std::fs::write(&broken, b"not a png").unwrap();
+        let broken = ImgBuf::open(&broken, Some(ImageFormat::Png));
+        assert_eq!(
+            broken.err().map(|e| e.kind),
+            Some(ipc::ErrorKind::UnsupportedFormat)
+        );
+
+        let png = dir.join("image.png");
+        RgbImage::new(2, 2).save(&png).unwrap();
+        assert!(!ImgBuf::open(&png, None).unwrap().is_animated());
+        assert_eq!(
+            ImgBuf::new(&png).unwrap().decode().unwrap().dimensions(),
+            (2, 2)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn views_should_pick_their_region_of_the_image() {
+        let view = |crop, zoom, offset| View { crop, zoom, offset };
+        assert_eq!(View::default().region((100, 50)), None);
+        assert_eq!(
+            view(Some((10, 20, 30, 40)), 1.0, (0.0, 0.0)).region((100, 50)),
+            Some((10, 20, 30, 30))
+        );
+        assert_eq!(
+            view(None, 2.0, (0.0, 0.0)).region((100, 50)),
+            Some((25, 13, 50, 25))
+        );
+        assert_eq!(
+            view(None, 2.0, (1.0, -1.0)).region((100, 50)),
+            Some((50, 0, 50, 25))
+        );
+        // zooming into a crop stays inside of it
+        assert_eq!(
+            view(Some((200, 0, 10, 10)), 2.0, (-1.0, 1.0)).region((100, 50)),
+            Some((99, 5, 1, 5))
+        );
+    }
+
+    #[test]
+    fn ken_burns_should_zoom_in_and_back_out() {
+        // the right half is red
+        let red = |x| if x < 2 { 0 } else { 200 };
+        let img = RgbaImage::from_fn(4, 2, |x, _| image::Rgba([red(x), 0, 0, 255]));
+        let ken_burns = KenBurns {
+            duration: Duration::from_secs(1),
+            fps: 2.0,
+        };
+        let frames = ken_burns_frames(img.clone(), Some((2, 1, 2, 1)), ken_burns)
+            .collect_frames()
+            .unwrap();
+        assert!(frames.iter().all(|f| f.buffer().dimensions() == (4, 2)));
+        assert_eq!(*frames[0].buffer(), img);
+        assert!(frames[2].buffer().pixels().all(|p| p.0 == [200, 0, 0, 255]));
+        // halfway, the part is 3 by 1.5 pixels, which we resample rather than round to whole ones
+        let halfway = frames[1].buffer().get_pixel(1, 0).0[0];
+        assert!(halfway > 0 && halfway < 200, "{halfway}");
+        assert_eq!(frames[1].buffer(), frames[3].buffer());
+        assert!(frames
+            .iter()
+            .all(|f| Duration::from(f.delay()) == Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn overlays_should_be_premultiplied_and_transformed() {
+        let img = RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 0, 0, 255, 128]).unwrap();
+        let overlay = make_overlay(
+            img.clone(),
+            (4, 4),
+            ipc::Transform::Normal,
+            cli::OverlayAnchor::BottomRight,
+            cli::OverlayResize::No,
+            0.5,
+        )
+        .unwrap();
+        assert_eq!(overlay.dim, (2, 1));
+        assert_eq!(overlay.anchor, (1.0, 1.0));
+        // `[b, g, r, a]`, with the blue pixel's color multiplied by its alpha, and then both by
+        // the opacity
+        assert_eq!(&overlay.img[..], [0, 0, 128, 128, 64, 0, 0, 64]);
+
+        let overlay = make_overlay(
+            img.clone(),
+            (4, 4),
+            ipc::Transform::Rotated90,
+            cli::OverlayAnchor::BottomRight,
+            cli::OverlayResize::No,
+            1.0,
+        )
+        .unwrap();
+        assert_eq!(overlay.dim, (1, 2));
+        assert_eq!(overlay.anchor, (1.0, 0.0));
+
+        // too big for the output, so it is shrunk to fit
+        let overlay = make_overlay(
+            img,
+            (1, 1),
+            ipc::Transform::Normal,
+            cli::OverlayAnchor::Center,
+            cli::OverlayResize::No,
+            1.0,
+        )
+        .unwrap();
+        assert_eq!(overlay.dim, (1, 1));
+    }
+
+    #[test]
+    fn transformed_pixels_should_match_the_buffers_of_rotated_outputs() {
+        // a 3x2 image, one byte per pixel:
+        // 0 1 2
+        // 3 4 5
+        let pixels: Vec<u8> = (0..6).collect();
+        let transformed = |transform| transform_pixels::<1>(pixels.clone(), (3, 2), transform);
+        assert_eq!(transformed(ipc::Transform::Normal), pixels);
+        // rotated counter-clockwise, the right column is the first row
+        assert_eq!(transformed(ipc::Transform::Rotated90), [2, 5, 1, 4, 0, 3]);
+        assert_eq!(transformed(ipc::Transform::Rotated180), [5, 4, 3, 2, 1, 0]);
+        assert_eq!(transformed(ipc::Transform::Rotated270), [3, 0, 4, 1, 5, 2]);
+        assert_eq!(transformed(ipc::Transform::Flipped), [2, 1, 0, 5, 4, 3]);
+        assert_eq!(transformed(ipc::Transform::Flipped90), [0, 3, 1, 4, 2, 5]);
+    }
+
+    #[test]
+    fn letterbox_should_fill_the_width_and_add_bars_top_and_bottom() {
+        let (red, blue) = ([255, 0, 0], [0, 0, 255]);
+        let img = RgbImage::from_pixel(10, 10, image::Rgb(red));
+        let out = img_resize_letterbox(img, (10, 20), FilterType::Bilinear.into(), &blue).unwrap();
+        assert_eq!(out.len(), 10 * 20 * 3);
+        let rows: Vec<&[u8]> = out.chunks_exact(10 * 3).collect();
+        // the output is in BGR
+        for (y, row) in rows.iter().enumerate() {
+            let expected = if (5..15).contains(&y) {
+                [0, 0, 255]
+            } else {
+                [255, 0, 0]
+            };
+            for pixel in row.chunks_exact(3) {
+                assert_eq!(pixel, expected, "wrong pixel at row {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn letterbox_and_pillarbox_should_crop_when_there_is_no_room_for_bars() {
+        let black = [0, 0, 0];
+        let tall = RgbImage::from_fn(10, 20, |_, y| image::Rgb([y as u8 * 10 + 10, 0, 0]));
+        let out =
+            img_resize_letterbox(tall, (10, 10), FilterType::Bilinear.into(), &black).unwrap();
+        assert_eq!(out.len(), 10 * 10 * 3);
+        // no bar on the top: the first pixel comes from the image
+        assert_ne!(out[2], 0);
+
+        let wide = RgbImage::from_fn(20, 10, |x, _| image::Rgb([x as u8 * 10 + 10, 0, 0]));
+        let out =
+            img_resize_pillarbox(wide, (10, 10), FilterType::Bilinear.into(), &black).unwrap();
+        assert_eq!(out.len(), 10 * 10 * 3);
+        // no bar on the left: the first pixel comes from the image
+        assert_ne!(out[2], 0);
+    }
+
+    #[test]
+    fn pad_should_center_the_image_on_the_fill_color() {
+        let (red, blue) = ([255, 0, 0], [0, 0, 255]);
+        for (img_dim, dim) in [((4, 2), (7, 5)), ((3, 3), (8, 6)), ((9, 1), (11, 4))] {
+            let img = RgbImage::from_pixel(img_dim.0, img_dim.1, image::Rgb(red));
+            let out = img_pad(img, dim, &blue).unwrap();
+            assert_eq!(out.len(), (dim.0 * dim.1 * 3) as usize);
+            let (left, top) = ((dim.0 - img_dim.0) / 2, (dim.1 - img_dim.1) / 2);
+            for (i, pixel) in out.chunks_exact(3).enumerate() {
+                let (x, y) = (i as u32 % dim.0, i as u32 / dim.0);
+                let inside =
+                    (left..left + img_dim.0).contains(&x) && (top..top + img_dim.1).contains(&y);
+                // the output is in BGR
+                let expected = if inside { [0, 0, 255] } else { [255, 0, 0] };
+                assert_eq!(
+                    pixel, expected,
+                    "wrong pixel at {x},{y} padding {img_dim:?} to {dim:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pad_should_keep_the_middle_of_large_images() {
+        let img = RgbImage::from_fn(6, 6, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        let out = img_pad(img, (2, 4), &[0, 0, 0]).unwrap();
+        assert_eq!(&out[..6], [0, 1, 2, 0, 1, 3]);
+    }
+
+    #[test]
+    fn stretch_should_ignore_the_aspect_ratio() {
+        let img = RgbImage::from_fn(2, 2, |x, _| {
+            image::Rgb(if x == 0 { [255, 0, 0] } else { [0, 0, 255] })
+        });
+        let out = img_resize_stretch(img, (20, 5), FilterType::Box.into()).unwrap();
+        assert_eq!(out.len(), 20 * 5 * 3);
+        // the output is in BGR: the left half is red, and the right half is blue
+        for row in out.chunks_exact(20 * 3) {
+            assert_eq!(&row[..3], [0, 0, 255]);
+            assert_eq!(&row[row.len() - 3..], [255, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn scaling_in_linear_light_should_keep_its_brightness() {
+        // a black and white checkerboard gives off half the light of white
+        let img = RgbImage::from_fn(4, 4, |x, y| image::Rgb([((x + y) % 2 * 255) as u8; 3]));
+        let shrink = |linear| {
+            let scaling = Scaling {
+                filter: FilterType::Box,
+                linear,
+            };
+            img_resize_stretch(img.clone(), (1, 1), scaling).unwrap()
+        };
+        assert_eq!(shrink(false), [128; 3]);
+        assert_eq!(shrink(true), [188; 3]);
+    }
+
+    /// A horizontal gradient, from black to white, as BGR
+    #[test]
+    fn tile_should_repeat_the_image_from_the_top_left() {
+        let img = RgbImage::from_fn(2, 3, |x, y| image::Rgb([x as u8, y as u8, 7]));
+        let out = img_tile(&img, (5, 4));
+        assert_eq!(out.len(), 5 * 4 * 3);
+        for (i, pixel) in out.chunks_exact(3).enumerate() {
+            let (x, y) = (i % 5, i / 5);
+            assert_eq!(
+                pixel,
+                [7, (y % 3) as u8, (x % 2) as u8],
+                "wrong pixel at {x},{y}"
+            );
+        }
+    }
+
+    #[test]
+    fn collage_cells_should_split_the_output_between_the_gaps() {
+        assert_eq!(collage_grid(1), (1, 1));
+        assert_eq!(collage_grid(3), (2, 2));
+        assert_eq!(collage_grid(4), (2, 2));
+        assert_eq!(collage_grid(5), (3, 2));
+
+        assert_eq!(
+            collage_cells((1921, 1080), (2, 2), 10),
+            [
+                (0, 0, 956, 535),
+                (966, 0, 955, 535),
+                (0, 545, 956, 535),
+                (966, 545, 955, 535)
+            ]
+        );
+        assert_eq!(
+            collage_cells((100, 100), (3, 1), 50),
+            [(0, 0, 0, 100), (50, 0, 0, 100), (100, 0, 0, 100)]
+        );
+    }
+
+    #[test]
+    fn span_layout_should_follow_the_outputs_positions() {
+        // a scale 2 laptop screen below and to the right of a scale 1 monitor
+        let outputs = [((0, 0), (1920, 1080), 1), ((1920, 1080), (1280, 800), 2)];
+        let (size, regions) = span_layout(&outputs);
+        assert_eq!(size, (6400, 3760));
+        assert_eq!(regions, [(0, 0, 3840, 2160), (3840, 2160, 2560, 1600)]);
+
+        // positions may be negative
+        let outputs = [((-1920, 0), (1920, 1080), 1), ((0, 0), (1920, 1080), 1)];
+        let (size, regions) = span_layout(&outputs);
+        assert_eq!(size, (3840, 1080));
+        assert_eq!(regions, [(0, 0, 1920, 1080), (1920, 0, 1920, 1080)]);
+
+        assert_eq!(span_layout(&[]), ((0, 0), Vec::new()));
+    }
+
+    #[test]
+    fn neutral_color_temp_should_be_near_identity() {
+        let original = RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8])
+        });
+        let mut img = original.clone();
+        apply_color_temp(&mut img, 6500);
+        for (new, old) in img.pixels().zip(original.pixels()) {
+            for (new_col, old_col) in new.0.iter().zip(old.0) {
+                assert!(new_col.abs_diff(old_col) <= 2, "{new:?} vs {old:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn warm_color_temp_should_increase_red() {
+        let mut img = RgbImage::from_pixel(4, 4, image::Rgb([128, 128, 128]));
+        apply_color_temp(&mut img, 3000);
+        let [r, g, b] = img.get_pixel(0, 0).0;
+        assert!(r >= 150, "red: {r}");
+        assert!(r > g && g > b, "{:?}", [r, g, b]);
+
+        let mut img = RgbImage::from_pixel(4, 4, image::Rgb([128, 128, 128]));
+        apply_color_temp(&mut img, 10000);
+        let [r, _, b] = img.get_pixel(0, 0).0;
+        assert!(b > r, "{:?}", [r, b]);
+    }
+
+    #[test]
+    fn premultiplied_alpha_should_scale_the_colors() {
+        let bgr = [255, 128, 0, 10, 20, 30];
+        let alpha = [128, 128, 128, 255, 255, 255];
+        assert_eq!(
+            premultiply_alpha(&bgr, &alpha),
+            [128, 64, 0, 128, 10, 20, 30, 255]
+        );
+    }
+
+    #[test]
+    fn heatmap_blur_should_only_blur_busy_regions() {
+        // solid on the left half, a checkerboard of single pixels on the right one
+        let solid = [50, 100, 150];
+        let mut img = RgbImage::from_fn(64, 32, |x, y| match (x < 32, (x + y) % 2 == 0) {
+            (true, _) => image::Rgb(solid),
+            (false, true) => image::Rgb([0; 3]),
+            (false, false) => image::Rgb([255; 3]),
+        });
+        apply_heatmap_blur(&mut img, 2.0);
+
+        for (x, y, pixel) in img.enumerate_pixels() {
+            if x < 16 {
+                assert_eq!(pixel.0, solid, "solid pixel ({x}, {y}) changed");
+            } else if (40..56).contains(&x) && (8..24).contains(&y) {
+                assert!(
+                    pixel.0.iter().all(|&c| (c as i32 - 128).abs() < 32),
+                    "checkerboard pixel ({x}, {y}) is still sharp: {:?}",
+                    pixel.0
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn limiting_the_frame_rate_should_merge_frames_that_come_too_soon() {
+        let frames: Vec<_> = (0..5u32)
+            .map(|i| {
+                let img = RgbaImage::from_pixel(2, 2, image::Rgba([i as u8, 0, 0, 255]));
+                let delay = image::Delay::from_numer_denom_ms(10 * (i + 1), 1);
+                Ok(image::Frame::from_parts(img, 0, 0, delay))
+            })
+            .collect();
+        // at most one frame every 40ms
+        let kept: Vec<_> = limit_fps(Frames::new(Box::new(frames.into_iter())), Some(25))
+            .map(|frame| frame.unwrap())
+            .map(|frame| (frame.buffer().get_pixel(0, 0).0[0], frame.delay()))
+            .collect();
+        let ms = |ms| image::Delay::from_numer_denom_ms(ms, 1);
+        assert_eq!(kept, [(0, ms(60)), (3, ms(40)), (4, ms(50))]);
+    }
+
+    #[test]
+    fn frames_of_the_same_scene_should_transition_and_others_cut() {
+        let colors = [[220, 0, 0], [210, 10, 0], [0, 0, 200]];
+        let frames: Vec<_> = colors
+            .iter()
+            .map(|[r, g, b]| {
+                let img = RgbaImage::from_pixel(2, 2, image::Rgba([*r, *g, *b, 255]));
+                let delay = image::Delay::from_numer_denom_ms(100, 1);
+                Ok(image::Frame::from_parts(img, 0, 0, delay))
+            })
+            .collect();
+        // halfway between both frames, and then the second one
+        let halfway = |old: &RgbaImage, new: &RgbaImage, delay| {
+            assert_eq!(delay, Duration::from_millis(100));
+            let mut mid = old.clone();
+            for (mid, new) in mid.pixels_mut().zip(new.pixels()) {
+                for (m, n) in mid.0.iter_mut().zip(new.0) {
+                    *m = ((*m as u16 + n as u16) / 2) as u8;
+                }
+            }
+            Ok(vec![mid, new.clone()])
+        };
+        let played: Vec<_> =
+            transition_scenes(Frames::new(Box::new(frames.into_iter())), 0.4, halfway)
+                .map(|frame| frame.unwrap())
+                .map(|frame| {
+                    (
+                        frame.buffer().get_pixel(0, 0).0[..3].to_vec(),
+                        frame.delay(),
+                    )
+                })
+                .collect();
+        let ms = |ms| image::Delay::from_numer_denom_ms(ms, 1);
+        assert_eq!(
+            played,
+            [
+                (vec![220, 0, 0], ms(100)),
+                (vec![215, 5, 0], ms(50)),
+                (vec![210, 10, 0], ms(50)),
+                // a new scene
+                (vec![0, 0, 200], ms(100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn time_stretch_should_scale_every_delay() {
+        let delays = [Duration::from_millis(100); 10];
+        let stretched: Vec<_> = delays.iter().map(|d| stretch_delay(*d, 0.5)).collect();
+        assert_eq!(stretched, vec![Duration::from_millis(200); 10]);
+        assert_eq!(
+            stretch_delay(Duration::from_millis(100), 10.0),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn images_in_memory_should_be_detected_as_animated() {
+        let mut gif = Vec::new();
+        let frames = (0..3u32).map(|i| {
+            let img = RgbaImage::from_pixel(4, 4, image::Rgba([i as u8 * 50, 0, 0, 255]));
+            image::Frame::from_parts(img, 0, 0, image::Delay::from_numer_denom_ms(20 * i, 1))
+        });
+        image::codecs::gif::GifEncoder::new(&mut gif)
+            .encode_frames(frames)
+            .unwrap();
+        let imgbuf = ImgBuf::from_memory(gif, None).unwrap();
+        assert!(imgbuf.is_animated());
+        let delays: Vec<_> = imgbuf
+            .try_clone()
+            .unwrap()
+            .into_frames()
+            .unwrap()
+            .map(|frame| frame.unwrap().delay().numer_denom_ms())
+            .collect();
+        assert_eq!(delays, [(0, 1), (20, 1), (40, 1)]);
+
+        let mut png = Vec::new();
+        RgbImage::from_pixel(4, 4, image::Rgb([1, 2, 3]))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let imgbuf = ImgBuf::from_memory(png, None).unwrap();
+        assert!(!imgbuf.is_animated());
+        assert_eq!(imgbuf.decode().unwrap().get_pixel(0, 0).0, [1, 2, 3]);
+    }
+
+    #[test]
+    fn format_hint_should_decode_images_we_cannot_guess() {
+        // tga files have no signature
+        let mut tga = Vec::new();
+        RgbImage::from_pixel(4, 4, image::Rgb([1, 2, 3]))
+            .write_to(&mut Cursor::new(&mut tga), ImageFormat::Tga)
+            .unwrap();
+        assert!(ImgBuf::from_memory(tga.clone(), None)
+            .unwrap()
+            .decode()
+            .is_err());
+
+        let format = parse_format("tga").unwrap();
+        let img = ImgBuf::from_memory(tga, Some(format)).unwrap().decode();
+        assert_eq!(img.unwrap().get_pixel(0, 0).0, [1, 2, 3]);
+        assert!(parse_format("not-a-format").is_err());
+    }
+
+    #[test]
+    fn compressed_frames_should_play_back_in_order() {
+        let color = |i: u8| [i * 4, 255 - i * 4, i];
+        let frames: Vec<_> = (0..40)
+            .map(|i| {
+                let [r, g, b] = color(i);
+                let img = RgbaImage::from_pixel(8, 8, image::Rgba([r, g, b, 255]));
+                let delay = image::Delay::from_numer_denom_ms(10 + i as u32, 1);
+                Ok(image::Frame::from_parts(img, 0, 0, delay))
+            })
+            .collect();
+        let mut chunks = Vec::new();
+        let (forward, reverse) = compress_frames(
+            Frames::new(Box::new(frames.into_iter())),
+            (8, 8),
+            ipc::Transform::Normal,
+            FilterType::Bilinear.into(),
+            ResizeStrategy::No,
+            &[0, 0, 0],
+            None,
+            None,
+            0,
+            false,
+            cli::PlaybackDirection::PingPong,
+            CompressionBackend::Lz4,
+            None,
+            true,
+            &mut |frames, last| {
+                chunks.push((frames.len(), last));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(forward.len(), 40);
+        assert_eq!(reverse.len(), 39);
+        // every frame is handed out once, as they are made
+        assert_eq!(chunks.iter().map(|(len, _)| len).sum::<usize>(), 40);
+        assert_eq!(chunks.iter().filter(|(_, last)| *last).count(), 1);
+        assert!(chunks.last().unwrap().1);
+
+        let [r, g, b] = color(0);
+        let mut canvas = [b, g, r, 0].repeat(8 * 8);
+        let mut check = |frame: &BitPack, i: u8| {
+            assert!(frame.unpack(&mut canvas));
+            let [r, g, b] = color(i);
+            for pixel in canvas.chunks_exact(4) {
+                assert_eq!(pixel[..3], [b, g, r], "wrong pixel in frame {i}");
+            }
+        };
+        for (i, (frame, delay)) in forward.iter().enumerate() {
+            let i = (i as u8 + 1) % 40;
+            check(frame, i);
+            assert_eq!(*delay, Duration::from_millis(10 + i as u64));
+        }
+        // back to the last frame, and then backward from it
+        for (i, (frame, _)) in forward.iter().take(39).enumerate() {
+            check(frame, i as u8 + 1);
+        }
+        for (i, (frame, delay)) in reverse.iter().enumerate().rev() {
+            check(frame, i as u8);
+            assert_eq!(*delay, Duration::from_millis(10 + i as u64));
+        }
+    }
+
+    #[test]
+    fn reversed_frames_should_play_backward_from_the_first() {
+        let frames: Vec<_> = (0..5u8)
+            .map(|i| {
+                let img = RgbaImage::from_pixel(4, 4, image::Rgba([i * 50, 0, 0, 255]));
+                let delay = image::Delay::from_numer_denom_ms(10 + i as u32, 1);
+                Ok(image::Frame::from_parts(img, 0, 0, delay))
+            })
+            .collect();
+        let mut chunks = Vec::new();
+        let (backward, reverse) = compress_frames(
+            Frames::new(Box::new(frames.into_iter())),
+            (4, 4),
+            ipc::Transform::Normal,
+            FilterType::Bilinear.into(),
+            ResizeStrategy::No,
+            &[0, 0, 0],
+            None,
+            None,
+            0,
+            false,
+            cli::PlaybackDirection::Reverse,
+            CompressionBackend::Lz4,
+            None,
+            true,
+            &mut |frames, last| {
+                chunks.push((frames.len(), last));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(reverse.is_empty());
+        assert_eq!(chunks, [(5, true)]);
+
+        // from the first frame to the last one, and down to the first again, twice
+        let mut canvas = [0, 0, 0, 0].repeat(4 * 4);
+        for i in [4u8, 3, 2, 1, 0, 4, 3, 2, 1, 0] {
+            let (frame, delay) = &backward[(4 - i as usize) % 5];
+            assert!(frame.unpack(&mut canvas));
+            assert_eq!(canvas[..3], [0, 0, i * 50], "wrong pixel in frame {i}");
+            assert_eq!(*delay, Duration::from_millis(10 + i as u64));
+        }
+    }
+
+    #[test]
+    fn alpha_threshold_should_make_pixels_either_transparent_or_opaque() {
+        let mut img = RgbaImage::from_raw(2, 1, vec![10, 20, 30, 100, 40, 50, 60, 200]).unwrap();
+        threshold_alpha(&mut img, 128);
+        assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(img.get_pixel(1, 0).0, [40, 50, 60, 255]);
+
+        let flat = flatten_alpha(img, &[1, 2, 3], 128);
+        assert_eq!(flat.get_pixel(0, 0).0, [1, 2, 3]);
+        assert_eq!(flat.get_pixel(1, 0).0, [40, 50, 60]);
+    }
+
+    #[test]
+    fn only_images_above_the_threshold_should_be_transcoded() {
+        let dim = (1024, 1024);
+        let pixels = vec![128; 1024 * 1024 * 3];
+        let (bytes, jpeg) = transcode_large_img(pixels.clone(), dim, Some(85), 2).unwrap();
+        assert!(jpeg);
+        assert!(bytes.len() < pixels.len());
+
+        let (bytes, jpeg) = transcode_large_img(pixels.clone(), dim, Some(85), 3).unwrap();
+        assert!(!jpeg);
+        assert_eq!(bytes.len(), pixels.len());
+        let (_, jpeg) = transcode_large_img(pixels, dim, None, 0).unwrap();
+        assert!(!jpeg);
     }
 }
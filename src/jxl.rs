@@ -0,0 +1,140 @@
+//! Decodes JPEG XL images, both static and animated, with `jxl-oxide`.
+//!
+//! `image` doesn't know about JPEG XL at all, so we recognize the files by their signature
+//! ourselves. Decoding them requires the `jxl` feature.
+
+/// The signatures of a bare codestream, and of a codestream in an ISOBMFF container
+const SIGNATURES: [&[u8]; 2] = [
+    &[0xFF, 0x0A],
+    &[0, 0, 0, 0xC, b'J', b'X', b'L', b' ', 0xD, 0xA, 0x87, 0xA],
+];
+
+pub fn is_jxl(data: &[u8]) -> bool {
+    SIGNATURES
+        .iter()
+        .any(|signature| data.starts_with(signature))
+}
+
+#[cfg(feature = "jxl")]
+pub fn is_animated(data: &[u8]) -> Result<bool, String> {
+    let image = decode(data)?;
+    Ok(image.image_header().metadata.animation.is_some() && image.num_loaded_keyframes() > 1)
+}
+
+#[cfg(feature = "jxl")]
+pub fn into_frames<'a>(data: Vec<u8>) -> Result<image::Frames<'a>, String> {
+    let image = decode(&data)?;
+    // ticks per second
+    let tps = match &image.image_header().metadata.animation {
+        Some(animation) => (animation.tps_numerator, animation.tps_denominator),
+        None => (1, 1),
+    };
+    Ok(image::Frames::new(Box::new(JxlFrames {
+        image,
+        tps,
+        next: 0,
+    })))
+}
+
+#[cfg(feature = "jxl")]
+fn decode(data: &[u8]) -> Result<jxl_oxide::JxlImage, String> {
+    jxl_oxide::JxlImage::builder()
+        .read(data)
+        .map_err(|e| format!("failed to decode jxl image: {e}"))
+}
+
+#[cfg(feature = "jxl")]
+struct JxlFrames {
+    image: jxl_oxide::JxlImage,
+    tps: (u32, u32),
+    next: usize,
+}
+
+#[cfg(feature = "jxl")]
+impl Iterator for JxlFrames {
+    type Item = image::ImageResult<image::Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.image.num_loaded_keyframes() {
+            return None;
+        }
+        let render = self.image.render_frame(self.next);
+        self.next += 1;
+        let frame = render
+            .map_err(|e| e.to_string())
+            .and_then(|render| {
+                let img = to_rgba(&render.image(), self.image.pixel_format())?;
+                // a frame lasts for `duration` ticks
+                let (tps_num, tps_den) = self.tps;
+                let delay = image::Delay::from_numer_denom_ms(
+                    render
+                        .duration()
+                        .saturating_mul(1000)
+                        .saturating_mul(tps_den),
+                    tps_num.max(1),
+                );
+                Ok(image::Frame::from_parts(img, 0, 0, delay))
+            })
+            .map_err(|e| {
+                image::ImageError::Decoding(image::error::DecodingError::new(
+                    image::error::ImageFormatHint::Name("jxl".to_string()),
+                    e,
+                ))
+            });
+        Some(frame)
+    }
+}
+
+#[cfg(feature = "jxl")]
+fn to_rgba(
+    fb: &jxl_oxide::FrameBuffer,
+    format: jxl_oxide::PixelFormat,
+) -> Result<image::RgbaImage, String> {
+    use jxl_oxide::PixelFormat;
+
+    let to_u8 = |sample: f32| (sample.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let pixels = fb.buf().chunks_exact(fb.channels());
+    let pixels: Vec<u8> = match format {
+        PixelFormat::Gray => pixels
+            .flat_map(|p| [to_u8(p[0]), to_u8(p[0]), to_u8(p[0]), u8::MAX])
+            .collect(),
+        PixelFormat::Graya => pixels
+            .flat_map(|p| [to_u8(p[0]), to_u8(p[0]), to_u8(p[0]), to_u8(p[1])])
+            .collect(),
+        PixelFormat::Rgb => pixels
+            .flat_map(|p| [to_u8(p[0]), to_u8(p[1]), to_u8(p[2]), u8::MAX])
+            .collect(),
+        PixelFormat::Rgba => pixels
+            .flat_map(|p| [to_u8(p[0]), to_u8(p[1]), to_u8(p[2]), to_u8(p[3])])
+            .collect(),
+        PixelFormat::Cmyk | PixelFormat::Cmyka => {
+            return Err("cmyk jxl images are not supported".to_string())
+        }
+    };
+    image::RgbaImage::from_raw(fb.width() as u32, fb.height() as u32, pixels)
+        .ok_or_else(|| "jxl frame has the wrong size".to_string())
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn is_animated(_: &[u8]) -> Result<bool, String> {
+    Err("swww was compiled without the `jxl` feature".to_string())
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn into_frames<'a>(_: Vec<u8>) -> Result<image::Frames<'a>, String> {
+    Err("swww was compiled without the `jxl` feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_recognize_jxl_signatures() {
+        assert!(is_jxl(&[0xFF, 0x0A, 0xFA, 0x7F]));
+        assert!(is_jxl(b"\0\0\0\x0CJXL \r\n\x87\n\0\0\0\x14ftypjxl "));
+        // a jpeg starts with FF D8
+        assert!(!is_jxl(&[0xFF, 0xD8, 0xFF]));
+        assert!(!is_jxl(&[]));
+    }
+}
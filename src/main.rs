@@ -1,19 +1,96 @@
 use clap::Parser;
-use std::{os::unix::net::UnixStream, path::PathBuf, process::Stdio, time::Duration};
+use std::{
+    ffi::OsString,
+    io::{BufRead, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::{ExitCode, Stdio},
+    sync::{mpsc, OnceLock},
+    time::Duration,
+};
 
 use utils::{
     cache,
+    comp_decomp::{BitPack, CompressionBackend},
+    config::Config,
     ipc::{self, get_socket_path, read_socket, AnimationRequest, Answer, ArchivedAnswer, Request},
 };
 
+mod apng;
+mod batch;
+mod bench;
+mod cancel;
+mod download;
+mod gif;
+#[cfg(feature = "icc")]
+mod icc;
 mod imgproc;
 use imgproc::*;
+mod jxl;
+mod pick;
+mod sequence;
+mod svg;
+mod sync;
+mod video;
 
 mod cli;
 use cli::{ResizeStrategy, Swww};
 
-fn main() -> Result<(), String> {
-    let swww = Swww::parse();
+fn main() -> ExitCode {
+    let args = match with_img_config(std::env::args_os().collect()) {
+        Ok(args) => args,
+        Err(e) => return report_error(&e.into(), cli::ErrorFormat::Text),
+    };
+    let mut cli = cli::Cli::parse_from(args);
+    if let Swww::Img(img) = &mut cli.command {
+        img.remake_args = remake_args(&std::env::args_os().collect::<Vec<_>>(), &img.path);
+    }
+    let error_format = cli.error_format;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report_error(&e, error_format),
+    }
+}
+
+/// Prints `error` like `--error-format` asks, and returns its exit code
+fn report_error(error: &ipc::Error, format: cli::ErrorFormat) -> ExitCode {
+    match format {
+        cli::ErrorFormat::Text => eprintln!("Error: {:?}", error.msg),
+        cli::ErrorFormat::Json => eprintln!("{}", error_json(error)),
+    }
+    ExitCode::from(error.kind.exit_code())
+}
+
+fn error_json(error: &ipc::Error) -> String {
+    serde_json::json!({
+        "error": {
+            "kind": error.kind.name(),
+            "message": error.msg,
+            "exit_code": error.kind.exit_code(),
+        }
+    })
+    .to_string()
+}
+
+fn run(cli: cli::Cli) -> Result<(), ipc::Error> {
+    // for `ipc` and `cache`, and the daemon `swww init` spawns
+    if let Some(namespace) = &cli.namespace {
+        ipc::check_namespace(namespace)
+            .map_err(|e| ipc::Error::new(ipc::ErrorKind::InvalidArgument, e))?;
+        std::env::set_var(ipc::NAMESPACE_VAR, namespace);
+    }
+    let swww = cli.command;
+    // the daemon checks them too, but we'd only see it failed to start
+    if let Swww::Init { size, margin, .. } = &swww {
+        let invalid =
+            |arg, e| ipc::Error::new(ipc::ErrorKind::InvalidArgument, format!("{arg}: {e}"));
+        if let Some(size) = size {
+            utils::placement::parse_size(size).map_err(|e| invalid("--size", e))?;
+        }
+        if let Some(margin) = margin {
+            utils::placement::parse_margin(margin).map_err(|e| invalid("--margin", e))?;
+        }
+    }
     if let Swww::Init { no_daemon, .. } = &swww {
         match is_daemon_running() {
             Ok(false) => {
@@ -24,108 +101,182 @@ fn main() -> Result<(), String> {
                         socket_path.to_string_lossy()
                     );
                     if let Err(e) = std::fs::remove_file(socket_path) {
-                        return Err(format!("failed to delete previous socket: {e}"));
+                        return Err(format!("failed to delete previous socket: {e}").into());
                     }
                 }
             }
             Ok(true) => {
-                return Err("There seems to already be another instance running...".to_string())
+                return Err(ipc::Error::new(
+                    ipc::ErrorKind::Busy,
+                    "There seems to already be another instance running...",
+                ))
             }
             Err(e) => {
                 eprintln!("WARNING: failed to read '/proc' directory to determine whether the daemon is running: {e}
                           Falling back to trying to checking if the socket file exists...");
                 let socket_path = get_socket_path();
                 if socket_path.exists() {
-                    return Err(format!(
-                        "Found socket at {}. There seems to be an instance already running...",
-                        socket_path.to_string_lossy()
+                    return Err(ipc::Error::new(
+                        ipc::ErrorKind::Busy,
+                        format!(
+                            "Found socket at {}. There seems to be an instance already running...",
+                            socket_path.to_string_lossy()
+                        ),
                     ));
                 }
             }
         }
-        spawn_daemon(*no_daemon)?;
+        spawn_daemon(*no_daemon, daemon_args(&swww))?;
         if *no_daemon {
             return Ok(());
         }
     }
 
+    // before we pick an image from it, if it is a directory
+    let watched_path = match &swww {
+        Swww::Img(img) if img.watch => Some(img.path.clone()),
+        _ => None,
+    };
+    let swww = match swww {
+        // the list is shared with the daemon's slideshows, which the cli can't see from build.rs
+        Swww::Img(mut img) if img.extensions.is_empty() => {
+            img.extensions = utils::IMAGE_EXTENSIONS
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            Swww::Img(img)
+        }
+        swww => swww,
+    };
+    let swww = match swww {
+        Swww::Img(mut img) if img.cache && download::is_url(&img.path) => {
+            let format = img.format.as_deref().map(parse_format).transpose()?;
+            img.path = download::fetch_to_cache(&img.path.to_string_lossy(), format)?;
+            Swww::Img(img)
+        }
+        // `.` is the placeholder for a missing path
+        Swww::Img(mut img)
+            if img.path != Path::new(".") && img.path.is_dir() && img.fps.is_none() =>
+        {
+            img.path = pick::random_image(&img.path, img.recursive, &img.extensions)?;
+            // so scripts know what we picked
+            println!("{}", img.path.display());
+            Swww::Img(img)
+        }
+        swww => swww,
+    };
+    let watch = match (&swww, watched_path) {
+        (Swww::Img(img), Some(original)) => Some(make_watch_request(img, &original)?),
+        _ => None,
+    };
+    let sync = match &swww {
+        Swww::Img(img) if img.sync => Some((
+            sync::Sync::subscribe(Duration::from_secs(img.sync_timeout))?,
+            sync_outputs(img)?,
+        )),
+        _ => None,
+    };
+    if let Swww::Img(_) = swww {
+        follow_cpu_limits();
+    }
     process_swww_args(&swww)?;
+    if let Swww::Img(img) = &swww {
+        if let Err(e) = cache::evict_animation_frames(img.cache_limit << 20) {
+            eprintln!("WARNING: failed to trim the cache: {e}");
+        }
+    }
+    if let Some((sync, outputs)) = sync {
+        sync.wait(&outputs)?;
+    }
+    if let Some(watch) = watch {
+        let socket = ipc::connect(5, 100)?;
+        send_request(&watch, &socket)?;
+        let bytes = read_socket(&socket)?;
+        if let ArchivedAnswer::Err(e) = Answer::receive(&bytes)? {
+            let e = e.de();
+            return Err(ipc::Error::new(
+                e.kind,
+                format!("daemon error when watching the image: {e}"),
+            ));
+        }
+    }
 
     Ok(())
 }
 
-fn process_swww_args(args: &Swww) -> Result<(), String> {
+fn process_swww_args(args: &Swww) -> Result<(), ipc::Error> {
     let request = match make_request(args)? {
         Some(request) => request,
         None => return Ok(()),
     };
-    let socket = connect_to_socket(5, 100)?;
-    request.send(&socket)?;
+    let socket = ipc::connect(5, 100)?;
+    send_request(&request, &socket)?;
+    if let Request::Subscribe | Request::Log(ipc::LogRequest { follow: true, .. }) = request {
+        return Ok(print_events(socket)?);
+    }
     let bytes = read_socket(&socket)?;
     drop(socket);
-    match Answer::receive(&bytes) {
-        ArchivedAnswer::Err(msg) => return Err(msg.to_string()),
-        ArchivedAnswer::Info(info) => info.iter().for_each(|i| println!("{}", i)),
-        ArchivedAnswer::Ok => {
-            if let Swww::Kill = args {
-                #[cfg(debug_assertions)]
-                let tries = 20;
-                #[cfg(not(debug_assertions))]
-                let tries = 10;
-                let socket_path = get_socket_path();
-                for _ in 0..tries {
-                    if !socket_path.exists() {
-                        return Ok(());
-                    }
-                    std::thread::sleep(Duration::from_millis(100));
-                }
-                return Err(format!(
-                    "Could not confirm socket deletion at: {socket_path:?}"
-                ));
+    match Answer::receive(&bytes)? {
+        ArchivedAnswer::Err(e) => return Err(e.de()),
+        ArchivedAnswer::Info(info) => {
+            info.iter().for_each(|i| println!("{}", i));
+            if let Swww::Query { verbose: true, .. } = args {
+                print_stats()?;
             }
         }
+        ArchivedAnswer::Json(json) => println!("{json}"),
+        ArchivedAnswer::Palette(palettes) => {
+            let format = match args {
+                Swww::Palette { format, .. } => *format,
+                _ => {
+                    return Err(ipc::Error::new(
+                        ipc::ErrorKind::ProtocolMismatch,
+                        "daemon answered with a palette we didn't ask for",
+                    ))
+                }
+            };
+            let palettes: Vec<_> = palettes
+                .iter()
+                .map(|(name, colors)| (name.as_str(), colors.to_vec()))
+                .collect();
+            println!("{}", palette_output(&palettes, format)?);
+        }
+        ArchivedAnswer::Stats(stats) => match args {
+            Swww::Stats {
+                format: cli::QueryFormat::Json,
+            } => println!("{}", stats_json(stats)),
+            _ => println!("{}", stats_text(stats)),
+        },
+        ArchivedAnswer::Log(filter) => println!("{filter}"),
+        // the daemon only answers `swww kill` once it destroyed its surfaces and deleted its socket
+        ArchivedAnswer::Ok => (),
         ArchivedAnswer::Init(configured) => {
             let mut configured = *configured;
             while !configured {
                 std::thread::sleep(Duration::from_millis(1));
-                let socket = connect_to_socket(5, 100)?;
+                let socket = ipc::connect(5, 100)?;
                 Request::Init.send(&socket)?;
                 let bytes = read_socket(&socket)?;
-                let answer = Answer::receive(&bytes);
+                let answer = Answer::receive(&bytes)?;
                 if let ArchivedAnswer::Init(c) = answer {
                     configured = *c;
                 } else {
-                    return Err("Daemon did not return Answer::Init, as expected".to_string());
+                    return Err(ipc::Error::new(
+                        ipc::ErrorKind::ProtocolMismatch,
+                        "Daemon did not return Answer::Init, as expected",
+                    ));
                 }
             }
             if let Swww::Init { no_cache, .. } = args {
                 if *no_cache {
                     return Ok(());
                 }
-                let (_, outputs) = get_dimensions_and_outputs(&[])?;
+                let (_, _, outputs, _) = get_dimensions_and_outputs(&[])?;
                 for output in outputs.iter().flatten() {
-                    let img_path = utils::cache::get_previous_image_path(output)?;
-                    #[allow(deprecated)]
-                    if let Err(e) = process_swww_args(&Swww::Img(cli::Img {
-                        path: PathBuf::from(img_path),
-                        outputs: output.to_string(),
-                        no_resize: false,
-                        resize: ResizeStrategy::Crop,
-                        fill_color: [0, 0, 0],
-                        filter: cli::Filter::Lanczos3,
-                        transition_type: cli::TransitionType::None,
-                        transition_step: u8::MAX,
-                        transition_duration: 0.0,
-                        transition_fps: u8::MAX,
-                        transition_angle: 0.0,
-                        transition_pos: cli::CliPosition {
-                            x: cli::CliCoord::Pixel(0.0),
-                            y: cli::CliCoord::Pixel(0.0),
-                        },
-                        invert_y: false,
-                        transition_bezier: (0.0, 0.0, 0.0, 0.0),
-                        transition_wave: (0.0, 0.0),
-                    })) {
+                    let Some((img_path, remake)) = utils::cache::previous_image(output)? else {
+                        continue;
+                    };
+                    if let Err(e) = restore_img(output, img_path, remake) {
                         eprintln!("WARNING: failed to load cache for output {output}: {e}");
                     }
                 }
@@ -135,92 +286,923 @@ fn process_swww_args(args: &Swww) -> Result<(), String> {
     Ok(())
 }
 
-fn make_request(args: &Swww) -> Result<Option<Request>, String> {
+fn make_request(args: &Swww) -> Result<Option<Request>, ipc::Error> {
     match args {
-        Swww::Clear(c) => Ok(Some(Request::Clear(ipc::Clear {
-            color: c.color,
-            outputs: split_cmdline_outputs(&c.outputs),
-        }))),
+        Swww::Adjust {
+            adjustments,
+            outputs,
+        } => Ok(Some(Request::Adjust(
+            ipc_adjustments(adjustments),
+            split_cmdline_outputs(outputs),
+        ))),
+        Swww::Clear(c) => Ok(Some(Request::Clear(make_clear_request(c)?))),
         Swww::ClearCache => {
             cache::clean()?;
             Ok(None)
         }
+        Swww::Collage(collage) => Ok(Some(Request::Img(make_collage_request(collage)?))),
+        Swww::Img(
+            img @ cli::Img {
+                batch_file: Some(batch_file),
+                ..
+            },
+        ) => make_batch_request(img, batch_file),
+        Swww::Img(
+            img @ cli::Img {
+                transition_preview_file: Some(prefix),
+                ..
+            },
+        ) => {
+            preview_img_transition(img, prefix)?;
+            Ok(None)
+        }
         Swww::Img(img) => {
+            if let Some(entries) = batch::parse_output_map(&img.outputs)? {
+                if img.span {
+                    return Err(ipc::Error::new(
+                        ipc::ErrorKind::InvalidArgument,
+                        "--span can't give each output its own image",
+                    ));
+                }
+                return send_batch(img, &entries);
+            }
+            if img.path == Path::new(".") {
+                return Err(ipc::Error::new(
+                    ipc::ErrorKind::InvalidArgument,
+                    "missing the path to the image",
+                ));
+            }
+            if !img.output_transition.is_empty() {
+                return send_each_output(img);
+            }
+            if img.span {
+                return Ok(Some(Request::Img(make_span_request(img)?)));
+            }
             let requested_outputs = split_cmdline_outputs(&img.outputs);
-            let (dims, outputs) = get_dimensions_and_outputs(&requested_outputs)?;
-            let imgbuf = ImgBuf::new(&img.path)?;
+            let (dims, transforms, outputs, _) = get_dimensions_and_outputs(&requested_outputs)?;
+            // stdin can only be read once, so its images only get the defaults of `[img]`
+            let config = Config::read()?;
+            if img.path != Path::new("-")
+                && outputs
+                    .iter()
+                    .flatten()
+                    .any(|output| !config.output_args(output).is_empty())
+            {
+                return send_each_output(img);
+            }
+            let imgbuf = open_img(img)?;
             if imgbuf.is_animated() {
+                let animation_imgbuf = imgbuf.try_clone()?;
+                let (shown_tx, shown) = mpsc::channel();
                 match std::thread::scope::<_, Result<_, String>>(|s1| {
-                    let animations = s1.spawn(|| make_animation_request(img, &dims, &outputs));
+                    // dropped if we fail to show the first frame, so that nothing waits for it
+                    let shown_tx = shown_tx;
+                    let animations = s1.spawn(|| {
+                        let shown = shown;
+                        make_animation_request(
+                            img,
+                            &animation_imgbuf,
+                            &dims,
+                            &transforms,
+                            &outputs,
+                            Some(&shown),
+                        )
+                    });
                     let first_frame = imgbuf
                         .into_frames()?
                         .next()
                         .ok_or("missing first frame".to_owned())?
                         .map_err(|e| format!("unable to decode first frame: {e}"))?;
 
-                    let img_request =
-                        make_img_request(img, frame_to_rgb(first_frame), &dims, &outputs)?;
-                    let animations = animations.join().unwrap_or_else(|e| Err(format!("{e:?}")));
+                    // faces are not used for animations, since every frame must be cropped the
+                    // same way
+                    let mut img_request = make_img_request(
+                        img,
+                        frame_to_rgb(first_frame, &img.fill_color, img.alpha_threshold),
+                        &dims,
+                        &transforms,
+                        &outputs,
+                        None,
+                    )?;
+                    expect_animation(&mut img_request);
 
-                    let socket = connect_to_socket(5, 100)?;
-                    Request::Img(img_request).send(&socket)?;
+                    // we show the first frame while we make the others
+                    let socket = ipc::connect(5, 100)?;
+                    send_request(&Request::Img(img_request), &socket)?;
                     let bytes = read_socket(&socket)?;
-                    drop(socket);
-                    if let ArchivedAnswer::Err(e) = Answer::receive(&bytes) {
+                    if let ArchivedAnswer::Err(e) = Answer::receive(&bytes)? {
                         return Err(format!("daemon error when sending image: {e}"));
                     }
-                    animations
+                    cancel::watch(socket)?;
+                    // the animation thread may be gone already, having sent its request whole
+                    let _ = shown_tx.send(());
+                    animations.join().unwrap_or_else(|e| Err(format!("{e:?}")))
                 }) {
+                    // we already sent all of them
+                    Ok(animations) if animations.is_empty() => Ok(None),
                     Ok(animations) => Ok(Some(Request::Animation(animations))),
-                    Err(e) => Err(format!("failed to create animated request: {e}")),
+                    Err(_) if cancel::is_cancelled() => {
+                        eprintln!(
+                            "another image replaced {} before its animation was ready",
+                            img.path.display()
+                        );
+                        Ok(None)
+                    }
+                    Err(e) => Err(format!("failed to create animated request: {e}").into()),
                 }
+            } else if let Some(path) = imgbuf.svg_path() {
+                let svg = svg::Svg::open(path)?;
+                Ok(Some(Request::Img(make_svg_request(
+                    img,
+                    &svg,
+                    &dims,
+                    &transforms,
+                    &outputs,
+                )?)))
             } else {
-                let img_raw = imgbuf.decode()?;
+                let img_raw = imgbuf.decode_over(&img.fill_color, img.alpha_threshold)?;
+                let face = if img.auto_crop_faces && img.resize == ResizeStrategy::Crop {
+                    find_face(img, &img_raw)
+                } else {
+                    None
+                };
                 Ok(Some(Request::Img(make_img_request(
-                    img, img_raw, &dims, &outputs,
+                    img,
+                    img_raw,
+                    &dims,
+                    &transforms,
+                    &outputs,
+                    face,
                 )?)))
             }
         }
         Swww::Init { .. } => Ok(Some(Request::Init)),
         Swww::Kill => Ok(Some(Request::Kill)),
-        Swww::Query => Ok(Some(Request::Query)),
+        Swww::PlaybackSpeed { speed, outputs } => Ok(Some(Request::PlaybackSpeed(
+            *speed,
+            split_cmdline_outputs(outputs),
+        ))),
+        Swww::Palette { count, outputs, .. } => Ok(Some(Request::Palette(
+            *count,
+            split_cmdline_outputs(outputs),
+        ))),
+        Swww::Pause { outputs } => Ok(Some(Request::Pause(split_cmdline_outputs(outputs)))),
+        Swww::ReloadConfig => Ok(Some(Request::ReloadConfig)),
+        Swww::Resume { outputs } => Ok(Some(Request::Resume(split_cmdline_outputs(outputs)))),
+        Swww::Slideshow {
+            stop: true,
+            outputs,
+            ..
+        } => Ok(Some(Request::StopSlideshow(split_cmdline_outputs(outputs)))),
+        Swww::Slideshow {
+            dir,
+            interval,
+            shuffle,
+            transition_type,
+            outputs,
+            img_args,
+            ..
+        } => {
+            // the directory is required without `--stop`
+            let dir = dir.as_deref().unwrap();
+            let dir = dir
+                .canonicalize()
+                .map_err(|e| format!("failed to find {dir:?}: {e}"))?;
+            if !dir.is_dir() {
+                return Err(ipc::Error::new(
+                    ipc::ErrorKind::InvalidArgument,
+                    format!("{dir:?} is not a directory"),
+                ));
+            }
+            // every image is shown with them, so they had better be right
+            let mut args = vec![OsString::from("img")];
+            args.extend(img_args.iter().map(OsString::from));
+            args.extend(["--", &dir.to_string_lossy()].map(OsString::from));
+            cli::Img::try_parse_from(args)
+                .map_err(|e| format!("invalid `swww img` options: {e}"))?;
+            let cwd = std::env::current_dir()
+                .map_err(|e| format!("failed to get the current directory: {e}"))?;
+            Ok(Some(Request::Slideshow(ipc::Slideshow {
+                dir: dir.to_string_lossy().into_owned(),
+                interval_secs: *interval,
+                shuffle: *shuffle,
+                transition_type: transition_type.clone(),
+                remake: ipc::Remake {
+                    cwd: cwd.to_string_lossy().into_owned(),
+                    args: img_args.clone().into(),
+                },
+                outputs: split_cmdline_outputs(outputs),
+            })))
+        }
+        Swww::Shader(shader) => Ok(Some(Request::Shader(make_shader_request(shader)?))),
+        Swww::Step { frames, outputs } => {
+            Ok(Some(Request::Step(*frames, split_cmdline_outputs(outputs))))
+        }
+        Swww::Subscribe => Ok(Some(Request::Subscribe)),
+        Swww::Log { level, follow } => Ok(Some(Request::Log(ipc::LogRequest {
+            filter: level.clone(),
+            follow: *follow,
+        }))),
+        Swww::Mirror { source, targets } => Ok(Some(Request::Mirror(
+            source.clone(),
+            targets.clone().into_boxed_slice(),
+        ))),
+        Swww::Query {
+            verbose,
+            format: cli::QueryFormat::Json,
+        } => Ok(Some(Request::QueryJson(*verbose))),
+        Swww::Query { .. } => Ok(Some(Request::Query)),
+        Swww::Stats { .. } => Ok(Some(Request::Stats)),
+        Swww::Overlay {
+            path,
+            anchor,
+            opacity,
+            resize,
+            outputs,
+        } => {
+            let outputs = split_cmdline_outputs(outputs);
+            if path == Path::new("none") {
+                return Ok(Some(Request::Overlay(Box::new([(None, outputs)]))));
+            }
+            let img = ImgBuf::new(path)?.decode_rgba()?;
+            let (dims, transforms, outputs, _) = get_dimensions_and_outputs(&outputs)?;
+            let (dims, transforms, outputs) = share_geometry(&dims, &transforms, &outputs);
+            let overlays = dims
+                .iter()
+                .zip(transforms)
+                .zip(outputs)
+                .map(|((dim, transform), outputs)| {
+                    let overlay =
+                        make_overlay(img.clone(), *dim, transform, *anchor, *resize, *opacity)?;
+                    Ok((Some(overlay), outputs.into_boxed_slice()))
+                })
+                .collect::<Result<_, String>>()?;
+            Ok(Some(Request::Overlay(overlays)))
+        }
+        Swww::Tint { tint, outputs } => {
+            let gains = match *tint {
+                cli::Tint::Kelvin(kelvin) => color_temp_gains(kelvin),
+                cli::Tint::Gains(gains) => gains,
+            };
+            Ok(Some(Request::Tint(gains, split_cmdline_outputs(outputs))))
+        }
+        Swww::Bench(bench) => {
+            bench::run(bench)?;
+            Ok(None)
+        }
+        Swww::TransitionPreview(preview) => {
+            transition_preview(preview)?;
+            Ok(None)
+        }
+        Swww::Preview(preview) => {
+            self::preview(preview)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Sends `request`, and stores what it displays in the cache, for the daemon to restore later
+fn send_request(request: &Request, socket: &UnixStream) -> Result<(), String> {
+    std::thread::scope(|s| {
+        if let Request::Animation(animations) = request {
+            s.spawn(|| {
+                for (animation, _, _) in animations.iter().filter(|(a, _, _)| a.cache_key.is_some())
+                {
+                    if let Err(e) = cache::store_animation_frames(animation) {
+                        eprintln!("Error storing cache for {}: {e}", animation.path);
+                    }
+                }
+            });
+        }
+        request.send(socket)?;
+        let img_requests = match request {
+            Request::Img(request) => std::slice::from_ref(request),
+            Request::Batch(requests) => requests,
+            _ => &[],
+        };
+        for (_, imgs) in img_requests.iter() {
+            for (ipc::Img { path, remake, .. }, outputs) in imgs.iter() {
+                for output in outputs.iter() {
+                    if let Err(e) = cache::store(output, path, remake) {
+                        eprintln!("ERROR: failed to store cache: {e}");
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Displays `path` on `output` again, with the options it was made with, but without a transition
+fn restore_img(output: &str, path: String, remake: ipc::Remake) -> Result<(), ipc::Error> {
+    let mut args = vec![OsString::from("swww"), OsString::from("img")];
+    args.extend(remake.args.iter().map(OsString::from));
+    args.extend(
+        [
+            format!("--outputs={output}"),
+            "--transition-type=none".to_string(),
+            "--".to_string(),
+            path,
+        ]
+        .map(OsString::from),
+    );
+    let args = with_config(args, &Config::read()?, Some(output));
+    let Swww::Img(mut img) = cli::Cli::try_parse_from(args)
+        .map_err(|e| format!("with the options it was made with: {e}"))?
+        .command
+    else {
+        unreachable!("we parsed a `swww img` command");
+    };
+    img.remake_args = remake.args.into_vec();
+    // relative paths among the options are relative to where they were given, if it's still there
+    if Path::new(&remake.cwd).is_dir() {
+        std::env::set_current_dir(&remake.cwd)
+            .map_err(|e| format!("failed to go to {:?}: {e}", remake.cwd))?;
+    }
+    process_swww_args(&Swww::Img(img))
+}
+
+/// Prints the events the daemon sends us after answering `Request::Subscribe`, or the logs after
+/// `Request::Log`, until it hangs up
+fn print_events(socket: UnixStream) -> Result<(), String> {
+    let bytes = read_socket(&socket)?;
+    if let ArchivedAnswer::Err(msg) = Answer::receive(&bytes)? {
+        return Err(msg.to_string());
+    }
+    // there may be a long time between events
+    socket
+        .set_read_timeout(None)
+        .map_err(|e| format!("failed to unset read timeout for socket: {e}"))?;
+
+    let mut stdout = std::io::stdout().lock();
+    for line in std::io::BufReader::new(socket).lines() {
+        let line = line.map_err(|e| format!("failed to read event: {e}"))?;
+        // we are often piped into another program, which should see every event right away
+        writeln!(stdout, "{line}")
+            .and_then(|()| stdout.flush())
+            .map_err(|e| format!("failed to print event: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Renders the frames with `swww-daemon --render-transition`, so that we use the exact same code
+/// the daemon uses when drawing to the screen
+fn transition_preview(preview: &cli::TransitionPreview) -> Result<(), String> {
+    let img = cli::Img::try_parse_from(transition_spec_to_args(&preview.transition, &preview.to)?)
+        .map_err(|e| format!("invalid transition: {e}"))?;
+    let dim = (preview.width, preview.height);
+    if dim.0 == 0 || dim.1 == 0 {
+        return Err("--width and --height must be greater than 0".to_string());
+    }
+    let scaling = make_scaling(&img.filter, img.gamma_correct);
+    let old_img = img_resize_crop(ImgBuf::new(&preview.from)?.decode()?, dim, scaling, CENTER)?;
+    let new_img = img_resize_crop(ImgBuf::new(&preview.to)?.decode()?, dim, scaling, CENTER)?;
+    let frames = render_transition(make_transition(&img), dim, old_img, new_img)?;
+
+    std::fs::create_dir_all(&preview.output_dir)
+        .map_err(|e| format!("failed to create {:?}: {e}", preview.output_dir))?;
+    for (i, frame) in frames.iter().enumerate() {
+        let path = preview.output_dir.join(format!("frame_{i}.png"));
+        image::save_buffer(&path, frame, dim.0, dim.1, image::ColorType::Rgb8)
+            .map_err(|e| format!("failed to save {path:?}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Processes the image like `swww img` would for an output of `--size`, and writes what the output
+/// would show, or the first frame of the `--transition` to it
+fn preview(preview: &cli::Preview) -> Result<(), ipc::Error> {
+    let mut img = cli::Img::try_parse_from(match &preview.transition {
+        Some(transition) => transition_spec_to_args(transition, &preview.path)?,
+        None => vec![
+            "img".to_string(),
+            preview.path.to_string_lossy().to_string(),
+        ],
+    })
+    .map_err(|e| {
+        ipc::Error::new(
+            ipc::ErrorKind::InvalidArgument,
+            format!("invalid transition: {e}"),
+        )
+    })?;
+    img.resize = preview.resize;
+    img.filter = preview.filter.clone();
+    img.fill_color = preview.fill_color;
+
+    let dim = preview.size;
+    let img_raw = open_img(&img)?.decode_over(&img.fill_color, img.alpha_threshold)?;
+    let (transition, requests) = make_img_request(
+        &img,
+        img_raw,
+        &[dim],
+        &[ipc::Transform::Normal],
+        &[Vec::new()],
+        None,
+    )?;
+    let mut pixels = requests[0].0.img.to_vec();
+    if let Some(from) = &preview.from {
+        let mut from_img = img.clone();
+        from_img.path = from.clone();
+        let from_raw = open_img(&from_img)?.decode_over(&img.fill_color, img.alpha_threshold)?;
+        let old_img = resize_to(&img, from_raw, dim, None)?;
+        pixels = render_transition(transition, dim, old_img, pixels)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| "the transition has no frames".to_string())?;
+    } else {
+        // our images are in the daemon's byte order
+        rgb_to_brg(&mut pixels);
+    }
+    image::save_buffer(
+        &preview.output,
+        &pixels,
+        dim.0,
+        dim.1,
+        image::ColorType::Rgb8,
+    )
+    .map_err(|e| format!("failed to save {:?}: {e}", preview.output))?;
+    Ok(())
+}
+
+/// Saves the start, middle and end of the transition `swww img` would make on the first requested
+/// output, without applying it
+fn preview_img_transition(img: &cli::Img, prefix: &Path) -> Result<(), ipc::Error> {
+    let requested_outputs = split_cmdline_outputs(&img.outputs);
+    let (dims, _, outputs, imgs) = get_dimensions_and_outputs(&requested_outputs)?;
+    let img_raw = open_img(img)?.decode_over(&img.fill_color, img.alpha_threshold)?;
+    // the preview shows the output as we see it, not as the daemon's buffers are
+    let (transition, requests) = make_img_request(
+        img,
+        img_raw,
+        &dims[..1],
+        &[ipc::Transform::Normal],
+        &outputs[..1],
+        None,
+    )?;
+
+    let dim = dims[0];
+    let old_img = displayed_img(img, &imgs[0], dim)?;
+    let new_img = match &requests[0].0 {
+        ipc::Img {
+            img, jpeg: true, ..
+        } => utils::jpeg::decode(img, dim)?,
+        ipc::Img { img, .. } => img.to_vec(),
+    };
+    let frames = render_transition(transition, dim, old_img.clone(), new_img)?;
+    Ok(save_preview_frames(prefix, dim, &old_img, &frames)?)
+}
+
+/// What an output `dim` in size displays, `bg`, as the daemon has it, in its BGR byte order.
+/// Images are resized like `img` says, since that is what the preview of its transition is about
+fn displayed_img(img: &cli::Img, bg: &ipc::BgImg, dim: (u32, u32)) -> Result<Vec<u8>, String> {
+    Ok(match bg {
+        ipc::BgImg::Color([r, g, b]) => [*b, *g, *r].repeat(dim.0 as usize * dim.1 as usize),
+        ipc::BgImg::Gradient(gradient) => {
+            let mut pixels = gradient.render(dim, ipc::Transform::Normal);
+            rgb_to_brg(&mut pixels);
+            pixels
+        }
+        ipc::BgImg::Shader { path, .. } => {
+            return Err(format!(
+            "can't preview a transition from the shader {path}, since only the daemon renders it"
+        ))
+        }
+        ipc::BgImg::Img(path) => {
+            let current =
+                ImgBuf::new(Path::new(path))?.decode_over(&img.fill_color, img.alpha_threshold)?;
+            resize_to(img, current, dim, None)?
+        }
+    })
+}
+
+/// Writes the frames at t=0, t=0.5 and t=1.0 to `<prefix>_start.png`, `<prefix>_mid.png` and
+/// `<prefix>_end.png`. The rendered `frames` don't include t=0, since that is just `old_img`, which
+/// is still in the daemon's byte order
+fn save_preview_frames(
+    prefix: &Path,
+    dim: (u32, u32),
+    old_img: &[u8],
+    frames: &[Vec<u8>],
+) -> Result<(), String> {
+    let mut start = old_img.to_vec();
+    rgb_to_brg(&mut start);
+    let frame_at = |i: usize| if i == 0 { &start } else { &frames[i - 1] };
+    for (suffix, i) in [
+        ("start", 0),
+        ("mid", frames.len() / 2),
+        ("end", frames.len()),
+    ] {
+        let mut path = prefix.as_os_str().to_owned();
+        path.push(format!("_{suffix}.png"));
+        image::save_buffer(&path, frame_at(i), dim.0, dim.1, image::ColorType::Rgb8)
+            .map_err(|e| format!("failed to save {path:?}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Renders the frames with `swww-daemon --render-transition`, and returns them with 3 bytes per
+/// pixel, like our images
+fn render_transition(
+    transition: ipc::Transition,
+    dim: (u32, u32),
+    old_img: Vec<u8>,
+    new_img: Vec<u8>,
+) -> Result<Vec<Vec<u8>>, String> {
+    let bytes = ipc::TransitionPreview {
+        transition,
+        dimensions: dim,
+        old_img: old_img.into_boxed_slice(),
+        new_img: new_img.into_boxed_slice(),
+    }
+    .serialize()?;
+
+    let mut daemon = std::process::Command::new("swww-daemon")
+        .arg("--render-transition")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("error spawning swww-daemon: {e}"))?;
+    // the daemon only starts writing once it has read everything, so this can't deadlock
+    if let Some(mut stdin) = daemon.stdin.take() {
+        stdin
+            .write_all(&bytes)
+            .map_err(|e| format!("failed to send transition to swww-daemon: {e}"))?;
+    }
+    let output = daemon
+        .wait_with_output()
+        .map_err(|e| format!("failed to read frames from swww-daemon: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "swww-daemon failed to render transition: {}",
+            output.status
+        ));
+    }
+
+    let frame_len = dim.0 as usize * dim.1 as usize * 4;
+    Ok(output
+        .stdout
+        .chunks_exact(frame_len)
+        // frames are in the daemon's canvas format, where each pixel is BGRX
+        .map(|frame| {
+            frame
+                .chunks_exact(4)
+                .flat_map(|pix| [pix[2], pix[1], pix[0]])
+                .collect()
+        })
+        .collect())
+}
+
+/// Turns something like `fade:duration=200,fps=60` into the arguments `swww img` would need to
+/// make that same transition (`img <path> --transition-type fade --transition-duration 0.2
+/// --transition-fps 60`)
+fn transition_spec_to_args(spec: &str, path: &Path) -> Result<Vec<String>, String> {
+    let (transition_type, options) = spec.split_once(':').unwrap_or((spec, ""));
+    let mut args = vec![
+        "img".to_string(),
+        path.to_string_lossy().to_string(),
+        "--transition-type".to_string(),
+        transition_type.to_string(),
+    ];
+    for option in options.split(',').filter(|o| !o.is_empty()) {
+        let Some((key, value)) = option.split_once('=') else {
+            return Err(format!(
+                "transition option '{option}' should be in the form key=value"
+            ));
+        };
+        let value = match key {
+            "duration" => match value.parse::<f32>() {
+                Ok(ms) => (ms / 1000.0).to_string(),
+                Err(e) => return Err(format!("invalid duration '{value}': {e}")),
+            },
+            "reverse" => match value {
+                "true" => {
+                    args.push("--transition-reverse".to_string());
+                    continue;
+                }
+                "false" => continue,
+                _ => return Err(format!("reverse should be true or false, got '{value}'")),
+            },
+            _ => value.to_string(),
+        };
+        args.push(format!("--transition-{key}"));
+        args.push(value);
+    }
+    Ok(args)
+}
+
+fn make_clear_request(clear: &cli::Clear) -> Result<ipc::Clear, String> {
+    let gradient = clear.gradient.as_deref().map(|colors| ipc::Gradient {
+        from: colors[0],
+        to: colors[1],
+        angle: clear.angle,
+    });
+    let transition = match &clear.transition {
+        Some(spec) => {
+            let mut args = vec![OsString::from("swww")];
+            args.extend(
+                transition_spec_to_args(spec, Path::new("."))?
+                    .into_iter()
+                    .map(OsString::from),
+            );
+            let Swww::Img(img) = Swww::try_parse_from(with_img_config(args)?)
+                .map_err(|e| format!("invalid transition: {e}"))?
+            else {
+                unreachable!("we parsed a `swww img` command");
+            };
+            Some(make_transition(&img))
+        }
+        None => None,
+    };
+    Ok(ipc::Clear {
+        color: clear.color,
+        gradient,
+        transition,
+        outputs: split_cmdline_outputs(&clear.outputs),
+    })
+}
+
+/// Reads the shader, so that the daemon needn't be able to. It still sends the daemon where it is,
+/// for it to render it again on outputs that change
+fn make_shader_request(shader: &cli::Shader) -> Result<ipc::Shader, ipc::Error> {
+    let path = &shader.path;
+    let file_error = |e: std::io::Error| {
+        let kind = match e.kind() {
+            std::io::ErrorKind::NotFound => ipc::ErrorKind::FileNotFound,
+            std::io::ErrorKind::PermissionDenied => ipc::ErrorKind::PermissionDenied,
+            _ => ipc::ErrorKind::Other,
+        };
+        ipc::Error::new(kind, format!("failed to read {path:?}: {e}"))
+    };
+    let source = std::fs::read_to_string(path).map_err(file_error)?;
+    Ok(ipc::Shader {
+        source,
+        path: path
+            .canonicalize()
+            .map_err(file_error)?
+            .to_string_lossy()
+            .into_owned(),
+        fps: shader.fps,
+        outputs: split_cmdline_outputs(&shader.outputs),
+    })
+}
+
+/// Validates every entry in the batch file before making the request, so that, if anything is
+/// wrong, we don't change anything
+fn make_batch_request(img: &cli::Img, batch_file: &Path) -> Result<Option<Request>, ipc::Error> {
+    let json = std::fs::read_to_string(batch_file)
+        .map_err(|e| format!("failed to read batch file {batch_file:?}: {e}"))?;
+    let entries = batch::parse(&json)?;
+    send_batch(img, &entries)
+}
+
+/// Shows the image on every output like a batch with one entry for each of them, so that each can
+/// have a transition of its own, with `--output-transition`, or the defaults the configuration file
+/// gives it
+fn send_each_output(img: &cli::Img) -> Result<Option<Request>, ipc::Error> {
+    if img.span {
+        return Err(ipc::Error::new(
+            ipc::ErrorKind::InvalidArgument,
+            "--span can't play a different transition on each output",
+        ));
+    }
+    if img.path == Path::new("-") {
+        return Err(ipc::Error::new(
+            ipc::ErrorKind::InvalidArgument,
+            "--output-transition can't read the image from stdin",
+        ));
+    }
+    let entries: Vec<_> = query_outputs(&split_cmdline_outputs(&img.outputs))?
+        .into_iter()
+        .map(|info| batch::BatchEntry {
+            output: info.name,
+            path: img.path.clone(),
+            transition: None,
+            duration_ms: None,
+        })
+        .collect();
+    send_batch(img, &entries)
+}
+
+/// Shows every entry at once. Animated entries show their first frame along with the others, and
+/// after that, we return the request that starts playing them
+fn send_batch(
+    img: &cli::Img,
+    entries: &[batch::BatchEntry],
+) -> Result<Option<Request>, ipc::Error> {
+    if let Some((output, _)) = img
+        .output_transition
+        .iter()
+        .find(|(output, _)| entries.iter().all(|entry| entry.output != *output))
+    {
+        return Err(ipc::Error::new(
+            ipc::ErrorKind::InvalidArgument,
+            format!("--output-transition for {output}, which isn't getting an image"),
+        ));
+    }
+    let config = Config::read()?;
+    let mut requests = Vec::with_capacity(entries.len());
+    let mut animated: Vec<(bool, cli::Img, ImgBuf, Vec<_>, Vec<_>, Vec<_>)> = Vec::new();
+    for entry in entries.iter() {
+        let own_defaults = img_for_output(&config, &entry.output)?;
+        let shared = own_defaults.is_none();
+        let img = entry.apply(own_defaults.as_ref().unwrap_or(img))?;
+        let (dims, transforms, outputs, _) =
+            get_dimensions_and_outputs(std::slice::from_ref(&entry.output))
+                .map_err(|e| format!("batch entry for {}: {e}", entry.output))?;
+        let imgbuf = open_img(&img)?;
+        if imgbuf.is_animated() {
+            let first_frame = imgbuf
+                .try_clone()?
+                .into_frames()?
+                .next()
+                .ok_or("missing first frame".to_owned())?
+                .map_err(|e| format!("unable to decode first frame: {e}"))?;
+            let mut img_request = make_img_request(
+                &img,
+                frame_to_rgb(first_frame, &img.fill_color, img.alpha_threshold),
+                &dims,
+                &transforms,
+                &outputs,
+                None,
+            )?;
+            expect_animation(&mut img_request);
+            requests.push(img_request);
+            // entries without defaults of their own only differ in their transitions otherwise,
+            // so those showing the same file play the same frames
+            match animated
+                .iter_mut()
+                .find(|(other_shared, other, ..)| shared && *other_shared && other.path == img.path)
+            {
+                Some((_, _, _, shared_dims, shared_transforms, shared_outputs)) => {
+                    shared_dims.extend(dims);
+                    shared_transforms.extend(transforms);
+                    shared_outputs.extend(outputs);
+                }
+                None => animated.push((shared, img, imgbuf, dims, transforms, outputs)),
+            }
+            continue;
+        }
+        if let Some(path) = imgbuf.svg_path() {
+            let svg = svg::Svg::open(path)?;
+            requests.push(make_svg_request(&img, &svg, &dims, &transforms, &outputs)?);
+            continue;
+        }
+        let img_raw = imgbuf.decode_over(&img.fill_color, img.alpha_threshold)?;
+        let face = if img.auto_crop_faces && img.resize == ResizeStrategy::Crop {
+            find_face(&img, &img_raw)
+        } else {
+            None
+        };
+        requests.push(make_img_request(
+            &img,
+            img_raw,
+            &dims,
+            &transforms,
+            &outputs,
+            face,
+        )?);
+    }
+
+    let mut animations = Vec::new();
+    for (_, img, imgbuf, dims, transforms, outputs) in &animated {
+        animations.extend(
+            make_animation_request(img, imgbuf, dims, transforms, outputs, None)?.into_vec(),
+        );
+    }
+    let batch = Request::Batch(requests.into_boxed_slice());
+    if animations.is_empty() {
+        return Ok(Some(batch));
+    }
+    let socket = ipc::connect(5, 100)?;
+    send_request(&batch, &socket)?;
+    let bytes = read_socket(&socket)?;
+    if let ArchivedAnswer::Err(e) = Answer::receive(&bytes)? {
+        let e = e.de();
+        return Err(ipc::Error::new(
+            e.kind,
+            format!("daemon error when sending images: {e}"),
+        ));
+    }
+    cancel::watch(socket)?;
+    Ok(Some(Request::Animation(animations.into_boxed_slice())))
+}
+
+/// Tells the daemon an animation follows the images of `request`, see [`cancel`]
+fn expect_animation(request: &mut ipc::ImageRequest) {
+    for (img, _) in request.1.iter_mut() {
+        img.request = Some(cancel::request_id());
+    }
+}
+
+fn open_img(img: &cli::Img) -> Result<ImgBuf, ipc::Error> {
+    let imgbuf = match img.fps {
+        Some(fps) if img.path.is_dir() => ImgBuf::sequence(&img.path, &img.extensions, fps)?,
+        _ => {
+            let format = img
+                .format
+                .as_deref()
+                .map(parse_format)
+                .transpose()
+                .map_err(|e| ipc::Error::new(ipc::ErrorKind::InvalidArgument, e))?;
+            ImgBuf::open(&img.path, format)?
+        }
+    };
+    Ok(imgbuf
+        .with_video_fps(img.video_fps)
+        .with_options(DecodeOptions {
+            color_profile: img.color_profile,
+            view: View {
+                crop: img.crop,
+                zoom: img.zoom,
+                offset: img.offset,
+            },
+            ken_burns: img.ken_burns.then(|| KenBurns {
+                duration: Duration::from_secs_f32(img.ken_burns_duration),
+                fps: img.fps.unwrap_or(10.0),
+            }),
+            gif_min_delay: Duration::from_millis(img.gif_min_delay),
+        }))
+}
+
+/// Like [`make_img_request`], but renders the svg at the size each output needs, instead of
+/// resizing it
+fn make_svg_request(
+    img: &cli::Img,
+    svg: &svg::Svg,
+    dims: &[(u32, u32)],
+    transforms: &[ipc::Transform],
+    outputs: &[Vec<String>],
+) -> Result<ipc::ImageRequest, String> {
+    let mut transition = None;
+    let mut requests = Vec::with_capacity(dims.len());
+    for i in 0..dims.len() {
+        let size = svg::render_size(img.resize, svg.size(), dims[i]);
+        let img_raw = rgba_to_rgb(svg.render(size)?, &img.fill_color, img.alpha_threshold);
+        let (t, request) = make_img_request(
+            img,
+            img_raw,
+            &dims[i..=i],
+            &transforms[i..=i],
+            &outputs[i..=i],
+            None,
+        )?;
+        transition.get_or_insert(t);
+        requests.extend(request.into_vec());
     }
+    let transition = transition.ok_or("no outputs to display the svg at")?;
+    Ok((transition, requests.into_boxed_slice()))
 }
 
 fn make_img_request(
     img: &cli::Img,
-    img_raw: image::RgbImage,
+    mut img_raw: image::RgbImage,
     dims: &[(u32, u32)],
+    transforms: &[ipc::Transform],
     outputs: &[Vec<String>],
+    crop_focus: Option<Region>,
 ) -> Result<ipc::ImageRequest, String> {
+    if let Some(kelvin) = img.color_temp {
+        apply_color_temp(&mut img_raw, kelvin);
+    }
+    if let Some(sigma) = img.heatmap_blur {
+        apply_heatmap_blur(&mut img_raw, sigma);
+    }
     let transition = make_transition(img);
     let mut unique_requests = Vec::with_capacity(dims.len());
-    for (dim, outputs) in dims.iter().zip(outputs) {
+    for ((dim, transform), outputs) in dims.iter().zip(transforms).zip(outputs) {
+        let pixels = resize_to(img, img_raw.clone(), *dim, crop_focus)?;
+        let pixels = transform_pixels::<3>(pixels, *dim, *transform);
+        let (pixels, jpeg) = transcode_large_img(
+            pixels,
+            transform.buffer_dim(*dim),
+            img.quality,
+            img.quality_threshold,
+        )?;
         unique_requests.push((
             ipc::Img {
-                img: match img.resize {
-                    ResizeStrategy::No => img_pad(img_raw.clone(), *dim, &img.fill_color)?,
-                    ResizeStrategy::Crop => {
-                        img_resize_crop(img_raw.clone(), *dim, make_filter(&img.filter))?
-                    }
-                    ResizeStrategy::Fit => img_resize_fit(
-                        img_raw.clone(),
-                        *dim,
-                        make_filter(&img.filter),
-                        &img.fill_color,
-                    )?,
-                }
-                .into_boxed_slice(),
+                img: pixels,
+                jpeg,
+                remake: ipc::Remake {
+                    cwd: std::env::current_dir()
+                        .map(|cwd| cwd.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    args: img.remake_args.clone().into(),
+                },
+                adjustments: ipc_adjustments(&img.adjustments),
+                dither: img.dither.map(ipc_dither),
                 path: match img.path.canonicalize() {
                     Ok(p) => p.to_string_lossy().to_string(),
                     Err(e) => {
                         if let Some("-") = img.path.to_str() {
                             "STDIN".to_string()
+                        } else if download::is_url(&img.path) {
+                            img.path.to_string_lossy().to_string()
                         } else {
                             return Err(format!("failed no canonicalize image path: {e}"));
                         }
                     }
                 },
+                request: None,
             },
             outputs.to_owned().into_boxed_slice(),
         ));
@@ -229,68 +1211,674 @@ fn make_img_request(
     Ok((transition, unique_requests.into_boxed_slice()))
 }
 
-#[allow(clippy::type_complexity)]
-fn get_dimensions_and_outputs(
-    requested_outputs: &[String],
-) -> Result<(Vec<(u32, u32)>, Vec<Vec<String>>), String> {
-    let mut outputs: Vec<Vec<String>> = Vec::new();
-    let mut dims: Vec<(u32, u32)> = Vec::new();
-    let mut imgs: Vec<ipc::BgImg> = Vec::new();
+/// Resizes `img_raw` to `dim` following `--resize`. The pixels we return are BGR
+fn resize_to(
+    img: &cli::Img,
+    img_raw: image::RgbImage,
+    dim: (u32, u32),
+    crop_focus: Option<Region>,
+) -> Result<Vec<u8>, String> {
+    let img_dim = img_raw.dimensions();
+    let scaling = make_scaling(&img.filter, img.gamma_correct);
+    match img.resize {
+        ResizeStrategy::No => img_pad(img_raw, dim, &img.fill_color),
+        ResizeStrategy::Crop => img_resize_crop(
+            img_raw,
+            dim,
+            scaling,
+            crop_centering(img_dim, dim, crop_focus),
+        ),
+        ResizeStrategy::Fit => img_resize_fit(img_raw, dim, scaling, &img.fill_color),
+        ResizeStrategy::Letterbox => img_resize_letterbox(img_raw, dim, scaling, &img.fill_color),
+        ResizeStrategy::Pillarbox => img_resize_pillarbox(img_raw, dim, scaling, &img.fill_color),
+        ResizeStrategy::Stretch => img_resize_stretch(img_raw, dim, scaling),
+        ResizeStrategy::Tile => Ok(img_tile(&img_raw, dim)),
+    }
+}
+
+/// Displays one image across all the outputs, as if they were a single big screen
+fn make_span_request(img: &cli::Img) -> Result<ipc::ImageRequest, ipc::Error> {
+    let requested_outputs = split_cmdline_outputs(&img.outputs);
+    let infos = query_outputs(&requested_outputs)?;
+    let imgbuf = open_img(img)?;
+    if imgbuf.is_animated() {
+        return Err(ipc::Error::new(
+            ipc::ErrorKind::InvalidArgument,
+            "--span does not support animated images",
+        ));
+    }
+    let layout: Vec<_> = infos
+        .iter()
+        .map(|info| (info.position, info.dim, info.scale_factor))
+        .collect();
+    let (layout_dim, regions) = span_layout(&layout);
+
+    let mut img_raw = match imgbuf.svg_path() {
+        Some(path) => {
+            let svg = svg::Svg::open(path)?;
+            let rendered = svg.render(svg::render_size(img.resize, svg.size(), layout_dim))?;
+            rgba_to_rgb(rendered, &img.fill_color, img.alpha_threshold)
+        }
+        None => imgbuf.decode_over(&img.fill_color, img.alpha_threshold)?,
+    };
+    // these have to see the whole image, or we would get seams between the outputs
+    if let Some(kelvin) = img.color_temp {
+        apply_color_temp(&mut img_raw, kelvin);
+    }
+    if let Some(sigma) = img.heatmap_blur {
+        apply_heatmap_blur(&mut img_raw, sigma);
+    }
+    let face = if img.auto_crop_faces && img.resize == ResizeStrategy::Crop {
+        find_face(img, &img_raw)
+    } else {
+        None
+    };
+    let mut whole = resize_to(img, img_raw, layout_dim, face)?;
+    rgb_to_brg(&mut whole);
+    let whole = image::RgbImage::from_raw(layout_dim.0, layout_dim.1, whole).unwrap();
+
+    // every part already has the right aspect ratio, so cropping only scales it to its output
+    let mut part_img = img.clone();
+    part_img.resize = ResizeStrategy::Crop;
+    part_img.color_temp = None;
+    part_img.heatmap_blur = None;
+    let mut transition = None;
+    let mut requests = Vec::with_capacity(infos.len());
+    for (info, (x, y, width, height)) in infos.iter().zip(regions) {
+        let part = image::imageops::crop_imm(&whole, x, y, width, height).to_image();
+        let (t, request) = make_img_request(
+            &part_img,
+            part,
+            &[info.real_dim()],
+            &[info.transform],
+            &[vec![info.name.clone()]],
+            None,
+        )?;
+        transition.get_or_insert(t);
+        requests.extend(request.into_vec());
+    }
+    let transition = transition.ok_or("no outputs to span the image across".to_string())?;
+    Ok((transition, requests.into_boxed_slice()))
+}
+
+/// An image of a collage, read once for every output
+enum CollageTile {
+    Raster(image::RgbImage),
+    /// Rendered at the size of every cell, instead of resized
+    Svg(Box<svg::Svg>),
+}
+
+/// Displays the images side by side, in one image per output, with the rest of the options from
+/// the configuration file
+fn make_collage_request(collage: &cli::Collage) -> Result<ipc::ImageRequest, ipc::Error> {
+    let invalid = |e: String| ipc::Error::new(ipc::ErrorKind::InvalidArgument, e);
+    let count = collage.paths.len();
+    let (columns, rows) = collage.grid.unwrap_or_else(|| collage_grid(count as u32));
+    if ((columns * rows) as usize) < count {
+        return Err(invalid(format!(
+            "a {columns}x{rows} grid only has room for {} images, not {count}",
+            columns * rows
+        )));
+    }
+    if collage.resize.len() != 1 && collage.resize.len() != count {
+        return Err(invalid(format!(
+            "--resize must be given once, or once for every image, got {} for {count} images",
+            collage.resize.len()
+        )));
+    }
+
+    // the first image stands for the collage, in `swww query` and when restoring the outputs
+    let first = &collage.paths[0];
+    let mut args = vec![OsString::from("swww")];
+    match &collage.transition {
+        Some(spec) => args.extend(
+            transition_spec_to_args(spec, first)?
+                .into_iter()
+                .map(OsString::from),
+        ),
+        None => args.extend([OsString::from("img"), first.into()]),
+    }
+    let Swww::Img(mut img) = Swww::try_parse_from(with_img_config(args)?)
+        .map_err(|e| format!("invalid transition: {e}"))?
+    else {
+        unreachable!("we parsed a `swww img` command");
+    };
+    if let Some(fill_color) = collage.fill_color {
+        img.fill_color = fill_color;
+    }
+    img.outputs = collage.outputs.clone();
 
-    let socket = connect_to_socket(5, 100)?;
+    let mut tiles = Vec::with_capacity(count);
+    for (i, path) in collage.paths.iter().enumerate() {
+        let mut tile_img = img.clone();
+        tile_img.path = path.clone();
+        tile_img.resize = collage.resize[i.min(collage.resize.len() - 1)];
+        let imgbuf = open_img(&tile_img)?;
+        if imgbuf.is_animated() {
+            return Err(invalid(format!(
+                "collages do not support animated images, like {path:?}"
+            )));
+        }
+        let tile = match imgbuf.svg_path() {
+            Some(path) => CollageTile::Svg(Box::new(svg::Svg::open(path)?)),
+            None => CollageTile::Raster(
+                imgbuf.decode_over(&tile_img.fill_color, tile_img.alpha_threshold)?,
+            ),
+        };
+        tiles.push((tile_img, tile));
+    }
+
+    let infos = query_outputs(&split_cmdline_outputs(&collage.outputs))?;
+    // every collage already has the size of its output
+    let mut whole_img = img.clone();
+    whole_img.resize = ResizeStrategy::Crop;
+    let mut transition = None;
+    let mut requests = Vec::with_capacity(infos.len());
+    for info in &infos {
+        let dim = info.real_dim();
+        let cells = collage_cells(dim, (columns, rows), collage.gap);
+        if cells
+            .iter()
+            .any(|&(_, _, width, height)| width == 0 || height == 0)
+        {
+            return Err(invalid(format!(
+                "{} is too small for a {columns}x{rows} grid with gaps of {} pixels",
+                info.name, collage.gap
+            )));
+        }
+        let mut whole = image::RgbImage::from_pixel(dim.0, dim.1, image::Rgb(img.fill_color));
+        for ((tile_img, tile), (x, y, width, height)) in tiles.iter().zip(cells) {
+            let tile_raw = match tile {
+                CollageTile::Raster(raw) => raw.clone(),
+                CollageTile::Svg(svg) => {
+                    let size = svg::render_size(tile_img.resize, svg.size(), (width, height));
+                    rgba_to_rgb(
+                        svg.render(size)?,
+                        &tile_img.fill_color,
+                        tile_img.alpha_threshold,
+                    )
+                }
+            };
+            let mut pixels = resize_to(tile_img, tile_raw, (width, height), None)?;
+            rgb_to_brg(&mut pixels);
+            let pixels = image::RgbImage::from_raw(width, height, pixels).unwrap();
+            image::imageops::replace(&mut whole, &pixels, x.into(), y.into());
+        }
+        let (t, request) = make_img_request(
+            &whole_img,
+            whole,
+            &[dim],
+            &[info.transform],
+            &[vec![info.name.clone()]],
+            None,
+        )?;
+        transition.get_or_insert(t);
+        requests.extend(request.into_vec());
+    }
+    let transition = transition.ok_or("no outputs to display the collage at".to_string())?;
+    Ok((transition, requests.into_boxed_slice()))
+}
+
+/// Looks for a face to center the crop on. Failing to find one is not an error, we simply fall back
+/// to cropping in the center of the image
+fn find_face(img: &cli::Img, img_raw: &image::RgbImage) -> Option<Region> {
+    let Some(model) = &img.face_model else {
+        eprintln!(
+            "WARNING: --auto-crop-faces requires --face-model. Cropping in the center instead"
+        );
+        return None;
+    };
+    match detect_largest_face(img_raw, model) {
+        Ok(face) => face,
+        Err(e) => {
+            eprintln!("WARNING: face detection failed: {e}. Cropping in the center instead");
+            None
+        }
+    }
+}
+
+fn print_stats() -> Result<(), String> {
+    query_stats(|stats| println!("{stats}"))
+}
+
+/// Asks the daemon to display `img` again whenever its file changes. `original` is the path we
+/// got, which may be the directory we picked the image from
+fn make_watch_request(img: &cli::Img, original: &Path) -> Result<Request, String> {
+    if img.path == Path::new("-")
+        || download::is_url(&img.path)
+        || batch::parse_output_map(&img.outputs)?.is_some()
+    {
+        return Err("--watch needs the path to an image file".to_string());
+    }
+    let path = img
+        .path
+        .canonicalize()
+        .map_err(|e| format!("failed to find {:?}: {e}", img.path))?;
+    let cwd =
+        std::env::current_dir().map_err(|e| format!("failed to get the current directory: {e}"))?;
+    Ok(Request::Watch(ipc::Watch {
+        path: path.to_string_lossy().into_owned(),
+        cwd: cwd.to_string_lossy().into_owned(),
+        args: watch_args(std::env::args_os().skip(1), original, &path),
+        outputs: split_cmdline_outputs(&img.outputs),
+    }))
+}
+
+/// The arguments to display the watched image again: without `--watch`, so that the daemon doesn't
+/// get a new watch every time, and with the path to the image, instead of the one we got
+fn watch_args(args: impl Iterator<Item = OsString>, original: &Path, path: &Path) -> Box<[String]> {
+    let mut args: Vec<_> = args.filter(|arg| arg != "--watch").collect();
+    // an option may have the same value as the path, but the path usually comes last
+    if let Some(arg) = args.iter_mut().rev().find(|arg| Path::new(arg) == original) {
+        *arg = path.as_os_str().to_owned();
+    }
+    args.iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// What the daemon limits the images we make for it to
+struct DaemonLimits {
+    cpu: ipc::CpuLimits,
+    /// The `--max-fps` of every output, by name
+    max_fps: Vec<(String, Option<u32>)>,
+}
+
+/// Asks the daemon for its limits the first time only, so that however many images we make, we
+/// only ask once
+fn daemon_limits() -> Result<&'static DaemonLimits, String> {
+    static LIMITS: OnceLock<Result<DaemonLimits, String>> = OnceLock::new();
+    LIMITS
+        .get_or_init(|| {
+            query_stats(|stats| {
+                let cpu = &stats.cpu_limits;
+                DaemonLimits {
+                    cpu: ipc::CpuLimits {
+                        threads: cpu.threads.as_ref().copied(),
+                        nice: cpu.nice.as_ref().copied(),
+                        idle: cpu.idle,
+                    },
+                    max_fps: stats
+                        .outputs
+                        .iter()
+                        .map(|output| (output.name.to_string(), output.max_fps.as_ref().copied()))
+                        .collect(),
+                }
+            })
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+/// Processes images with no more of the CPU than the daemon may use, see `ipc::CpuLimits`. If we
+/// can't, we process them as we would otherwise
+fn follow_cpu_limits() {
+    let Ok(DaemonLimits { cpu, .. }) = daemon_limits() else {
+        return;
+    };
+    let ipc::CpuLimits {
+        threads,
+        nice,
+        idle,
+    } = *cpu;
+    // rayon's threads inherit our priority
+    if let Err(e) = utils::cpu::lower_priority(nice, idle) {
+        eprintln!("WARNING: {e}");
+    }
+    if let Some(threads) = threads {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+        {
+            eprintln!("WARNING: failed to limit the processing threads: {e}");
+        }
+    }
+}
+
+fn query_stats<T>(f: impl FnOnce(&ipc::ArchivedStats) -> T) -> Result<T, String> {
+    let socket = ipc::connect(5, 100)?;
+    Request::Stats.send(&socket)?;
+    let bytes = read_socket(&socket)?;
+    drop(socket);
+    match Answer::receive(&bytes)? {
+        ArchivedAnswer::Stats(stats) => Ok(f(stats)),
+        ArchivedAnswer::Err(e) => Err(format!("daemon error when sending stats query: {e}")),
+        _ => Err("daemon did not answer the stats query with its statistics".to_string()),
+    }
+}
+
+/// The `swww palette` output, with every color as `#rrggbb`
+fn palette_output(
+    palettes: &[(&str, Vec<[u8; 3]>)],
+    format: cli::PaletteFormat,
+) -> Result<String, String> {
+    let hex = |[r, g, b]: [u8; 3]| format!("#{r:02x}{g:02x}{b:02x}");
+    match format {
+        cli::PaletteFormat::Json => {
+            let outputs: Vec<_> = palettes
+                .iter()
+                .map(|(name, colors)| {
+                    let colors: Vec<_> = colors.iter().map(|color| hex(*color)).collect();
+                    serde_json::json!({ "name": name, "colors": colors })
+                })
+                .collect();
+            Ok(serde_json::json!({ "outputs": outputs }).to_string())
+        }
+        cli::PaletteFormat::Sh => match palettes {
+            [(_, colors)] => Ok(colors
+                .iter()
+                .enumerate()
+                .map(|(i, color)| format!("color{i}='{}'", hex(*color)))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            _ => Err(format!(
+                "--format sh needs exactly one output, but got {}. Pick one with --outputs",
+                palettes.len()
+            )),
+        },
+    }
+}
+
+/// The `swww stats` output: the daemon's own statistics, and then one line per output
+fn stats_text(stats: &ipc::ArchivedStats) -> String {
+    let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    let mut text = format!("{stats}\nframes: {:.2} MiB", mib(stats.resident_bytes));
+    match stats.memory_limit.as_ref() {
+        Some(limit) => text.push_str(&format!(" of {:.2} MiB", mib(*limit))),
+        None => text.push_str(", no limit (see swww-daemon --memory-limit)"),
+    }
+    text.push_str(&format!("\n{}", stats.cpu_limits));
+    for output in stats.outputs.iter() {
+        text.push_str(&format!("\n{output}"));
+    }
+    text
+}
+
+fn stats_json(stats: &ipc::ArchivedStats) -> String {
+    let outputs: Vec<_> = stats
+        .outputs
+        .iter()
+        .map(|output| {
+            serde_json::json!({
+                "name": output.name.as_str(),
+                "fps": output.fps,
+                "frames_shown": output.frames_shown,
+                "frames_skipped": output.frames_skipped,
+                "resident_bytes": output.resident_bytes,
+                "animations": output.animations,
+                "cached_animations": output.cached_animations,
+                "cache_hit_rate": output.cache_hit_rate(),
+                "max_fps": output.max_fps.as_ref(),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "gc_freed_bytes": stats.gc_freed_bytes.as_ref(),
+        "memory_limit": stats.memory_limit.as_ref(),
+        "resident_bytes": stats.resident_bytes,
+        "cpu_limits": {
+            "threads": stats.cpu_limits.threads.as_ref(),
+            "nice": stats.cpu_limits.nice.as_ref(),
+            "idle": stats.cpu_limits.idle,
+        },
+        "outputs": outputs,
+    })
+    .to_string()
+}
+
+/// Asks the daemon about the requested outputs, or about all of them, if none were requested
+fn query_outputs(requested_outputs: &[String]) -> Result<Vec<ipc::BgInfo>, ipc::Error> {
+    let socket = ipc::connect(5, 100)?;
     Request::Query.send(&socket)?;
     let bytes = read_socket(&socket)?;
     drop(socket);
-    let answer = Answer::receive(&bytes);
+    let answer = Answer::receive(&bytes)?;
     match answer {
         ArchivedAnswer::Info(infos) => {
-            for info in infos.iter() {
-                let info_img = info.img.de();
-                let name = info.name.to_string();
-                if !requested_outputs.is_empty() && !requested_outputs.contains(&name) {
-                    continue;
-                }
-                let real_dim = (
-                    info.dim.0 * info.scale_factor as u32,
-                    info.dim.1 * info.scale_factor as u32,
-                );
-                if let Some((_, output)) = dims
-                    .iter_mut()
-                    .zip(&imgs)
-                    .zip(&mut outputs)
-                    .find(|((dim, img), _)| real_dim == **dim && info_img == **img)
-                {
-                    output.push(name);
-                } else {
-                    outputs.push(vec![name]);
-                    dims.push(real_dim);
-                    imgs.push(info_img);
-                }
-            }
-            if outputs.is_empty() {
-                Err("none of the requested outputs are valid".to_owned())
+            let names = utils::outputs::resolve(
+                requested_outputs,
+                infos.iter().map(|info| info.name.as_str()),
+            )?;
+            let infos: Vec<_> = infos
+                .iter()
+                .filter(|info| names.contains(&info.name.as_str()))
+                .map(|info| ipc::BgInfo {
+                    name: info.name.to_string(),
+                    dim: (info.dim.0, info.dim.1),
+                    scale_factor: info.scale_factor,
+                    fractional_scale: info.fractional_scale.as_ref().copied(),
+                    transform: info.transform.de(),
+                    position: (info.position.0, info.position.1),
+                    img: info.img.de(),
+                    transitioning: info.transitioning,
+                    animation_frame: info.animation_frame.as_ref().copied(),
+                    set_at_ms: info.set_at_ms.as_ref().copied(),
+                })
+                .collect();
+            if infos.is_empty() {
+                Err(ipc::Error::new(
+                    ipc::ErrorKind::NoSuchOutput,
+                    "the daemon has no outputs",
+                ))
             } else {
-                Ok((dims, outputs))
+                Ok(infos)
+            }
+        }
+        ArchivedAnswer::Err(e) => {
+            let e = e.de();
+            Err(ipc::Error::new(
+                e.kind,
+                format!("daemon error when sending query: {e}"),
+            ))
+        }
+        _ => Err(ipc::Error::new(
+            ipc::ErrorKind::ProtocolMismatch,
+            "daemon did not answer the query with its outputs",
+        )),
+    }
+}
+
+/// The outputs `swww img --sync` waits for
+fn sync_outputs(img: &cli::Img) -> Result<Vec<String>, ipc::Error> {
+    let requested = match batch::parse_output_map(&img.outputs)? {
+        Some(entries) => entries.into_iter().map(|entry| entry.output).collect(),
+        None => split_cmdline_outputs(&img.outputs),
+    };
+    Ok(query_outputs(&requested)?
+        .into_iter()
+        .map(|info| info.name)
+        .collect())
+}
+
+/// Groups the outputs that need the same image: those of the same size and transform, that are
+/// displaying the same thing
+#[allow(clippy::type_complexity)]
+fn get_dimensions_and_outputs(
+    requested_outputs: &[String],
+) -> Result<
+    (
+        Vec<(u32, u32)>,
+        Vec<ipc::Transform>,
+        Vec<Vec<String>>,
+        Vec<ipc::BgImg>,
+    ),
+    ipc::Error,
+> {
+    let mut outputs: Vec<Vec<String>> = Vec::new();
+    let mut dims: Vec<(u32, u32)> = Vec::new();
+    let mut transforms: Vec<ipc::Transform> = Vec::new();
+    let mut imgs: Vec<ipc::BgImg> = Vec::new();
+
+    for info in query_outputs(requested_outputs)? {
+        let real_dim = info.real_dim();
+        if let Some((_, output)) = dims
+            .iter_mut()
+            .zip(&transforms)
+            .zip(&imgs)
+            .zip(&mut outputs)
+            .find(|(((dim, transform), img), _)| {
+                real_dim == **dim && info.transform == **transform && info.img == **img
+            })
+        {
+            output.push(info.name);
+        } else {
+            outputs.push(vec![info.name]);
+            dims.push(real_dim);
+            transforms.push(info.transform);
+            imgs.push(info.img);
+        }
+    }
+    Ok((dims, transforms, outputs, imgs))
+}
+
+/// Puts the outputs of the same size and transform back together, since they play the same frames,
+/// whatever images they show now, which [`get_dimensions_and_outputs`] tells apart
+#[allow(clippy::type_complexity)]
+fn share_geometry(
+    dims: &[(u32, u32)],
+    transforms: &[ipc::Transform],
+    outputs: &[Vec<String>],
+) -> (Vec<(u32, u32)>, Vec<ipc::Transform>, Vec<Vec<String>>) {
+    let mut shared: (Vec<_>, Vec<_>, Vec<Vec<String>>) = (Vec::new(), Vec::new(), Vec::new());
+    for ((dim, transform), outputs) in dims.iter().zip(transforms).zip(outputs) {
+        match (shared.0.iter().zip(&shared.1)).position(|(d, t)| d == dim && t == transform) {
+            Some(i) => shared.2[i].extend_from_slice(outputs),
+            None => {
+                shared.0.push(*dim);
+                shared.1.push(*transform);
+                shared.2.push(outputs.clone());
             }
         }
-        ArchivedAnswer::Err(e) => Err(format!("daemon error when sending query: {e}")),
-        _ => unreachable!(),
     }
+    shared
 }
 
+/// With `shown`, which says when the daemon shows the animation's first frame, we may send the
+/// daemon long animations a few frames at a time ourselves, as we make them, see
+/// `ipc::Playback::more_frames`. Those we sent are not in the request we return
 fn make_animation_request(
     img: &cli::Img,
+    imgbuf: &ImgBuf,
     dims: &[(u32, u32)],
+    transforms: &[ipc::Transform],
     outputs: &[Vec<String>],
+    shown: Option<&mpsc::Receiver<()>>,
 ) -> Result<AnimationRequest, String> {
-    let filter = make_filter(&img.filter);
+    let (dims, transforms, outputs) = share_geometry(dims, transforms, outputs);
+    let (dims, transforms, outputs) = (&dims[..], &transforms[..], &outputs[..]);
+    let scaling = make_scaling(&img.filter, img.gamma_correct);
+    let compression = make_compression(img.compression);
+    //TODO: make cache work for all resize strategies, color temperatures, heatmap blurs, alpha
+    //thresholds, kept alpha channels, time stretches, playback directions, video frame rates,
+    //image sequences and views
+    let cacheable = img.resize == ResizeStrategy::Crop
+        && img.color_temp.is_none()
+        && img.heatmap_blur.is_none()
+        && img.alpha_threshold == 0
+        && !img.keep_alpha
+        && img.time_stretch.is_none()
+        && img.playback == cli::PlaybackDirection::Forward
+        && img.video_fps.is_none()
+        && !(img.detect_scene_change && imgbuf.is_video())
+        && img.fps.is_none()
+        && img.crop.is_none()
+        && img.zoom == 1.0
+        && !img.ken_burns
+        && img.path.to_str() != Some("-")
+        && !download::is_url(&img.path);
+    let cache_key = if cacheable {
+        // frames scaled in linear light look different, and gifs clamped differently play
+        // differently, so they get keys of their own
+        let linear = if img.gamma_correct { ", linear" } else { "" };
+        let options = format!("{:?}{linear}, {}ms", img.filter, img.gif_min_delay);
+        match cache::animation_key(&img.path, &options) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                eprintln!("Error hashing {:?} for the cache: {e}", img.path);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let playback = || ipc::Playback {
+        speed: img.playback_speed,
+        loops: match img
+            .video_loop
+            .filter(|_| imgbuf.is_video())
+            .unwrap_or(img.loops)
+        {
+            cli::Loops::Infinite => None,
+            cli::Loops::Times(n) => Some(n),
+        },
+        at_end: match img.at_end {
+            cli::AtEnd::HoldLast => ipc::AtEnd::HoldLast,
+            cli::AtEnd::FirstFrame => ipc::AtEnd::FirstFrame,
+            cli::AtEnd::Clear => ipc::AtEnd::Clear(img.fill_color),
+        },
+        start_frame: img.start_frame,
+        request: cancel::request_id(),
+        more_frames: false,
+        stream: false,
+        from_cache: false,
+    };
+    let max_fps = &daemon_limits()?.max_fps;
+    let max_fps = |outputs: &[String]| {
+        ipc::shared_max_fps(outputs.iter().map(|name| {
+            max_fps
+                .iter()
+                .find(|(output, _)| output == name)
+                .and_then(|(_, fps)| *fps)
+        }))
+    };
+    // the daemon must get every output's first frames at once, and the frames to play backward
+    // after the others
+    let streamable = shown.filter(|_| {
+        dims.len() == 1 && img.playback == cli::PlaybackDirection::Forward && img.start_frame == 0
+    });
+    if img.stream && shown.is_some() && streamable.is_none() {
+        eprintln!(
+            "WARNING: can't stream the animation of {:?}, sending it whole",
+            img.path
+        );
+    }
     let mut animations = Vec::with_capacity(dims.len());
-    for (dim, outputs) in dims.iter().zip(outputs) {
-        //TODO: make cache work for all resize strategies
-        if img.resize == ResizeStrategy::Crop {
-            match cache::load_animation_frames(&img.path, *dim) {
+    for ((dim, transform), outputs) in dims.iter().zip(transforms).zip(outputs) {
+        let max_fps = max_fps(outputs);
+        // the cache doesn't know about transforms or frame rates, so it only keeps frames that
+        // have neither
+        let cache_key =
+            cache_key.filter(|_| *transform == ipc::Transform::Normal && max_fps.is_none());
+        let stream = |compression, cache_key| {
+            let playback = ipc::Playback {
+                more_frames: true,
+                stream: true,
+                ..playback()
+            };
+            stream_animation(
+                img,
+                imgbuf,
+                (*dim, *transform),
+                outputs,
+                streamable.unwrap(),
+                playback,
+                compression,
+                cache_key,
+                max_fps,
+            )
+        };
+        if img.stream && streamable.is_some() {
+            stream(compression, cache_key)?;
+            continue;
+        }
+        if let Some(key) = cache_key {
+            match cache::load_animation_frames(key, *dim, compression) {
                 Ok(Some(animation)) => {
-                    animations.push((animation, outputs.to_owned().into_boxed_slice()));
+                    let playback = ipc::Playback {
+                        from_cache: true,
+                        ..playback()
+                    };
+                    animations.push((animation, playback, outputs.to_owned().into_boxed_slice()));
                     continue;
                 }
                 Ok(None) => (),
@@ -298,24 +1886,256 @@ fn make_animation_request(
             }
         }
 
-        let imgbuf = ImgBuf::new(&img.path)?;
+        let mut streamed = false;
+        let mut send_frames = |frames: &[(BitPack, Duration)], last: bool| {
+            // short animations are sent whole, like the others
+            let Some(shown) = streamable.filter(|_| streamed || !last) else {
+                return Ok(());
+            };
+            let mut frames = frames.to_vec();
+            if let Some(factor) = img.time_stretch {
+                for (_, delay) in frames.iter_mut() {
+                    *delay = stretch_delay(*delay, factor);
+                }
+            }
+            let request = if streamed {
+                Request::AnimationFrames(ipc::AnimationFrames {
+                    request: cancel::request_id(),
+                    animation: 0,
+                    frames: frames.into_boxed_slice(),
+                    last,
+                })
+            } else {
+                // the daemon only plays animations whose first frame it shows
+                shown
+                    .recv()
+                    .map_err(|_| "failed to show the first frame".to_string())?;
+                let animation = ipc::Animation {
+                    path: img.path.to_string_lossy().to_string(),
+                    dimensions: *dim,
+                    animation: frames.into_boxed_slice(),
+                    reverse: Box::new([]),
+                    cache_key: None,
+                    compression,
+                };
+                let playback = ipc::Playback {
+                    more_frames: true,
+                    ..playback()
+                };
+                let outputs = outputs.to_owned().into_boxed_slice();
+                Request::Animation(Box::new([(animation, playback, outputs)]))
+            };
+            let socket = ipc::connect(5, 100)?;
+            request.send(&socket)?;
+            let bytes = read_socket(&socket)?;
+            if let ArchivedAnswer::Err(e) = Answer::receive(&bytes)? {
+                cancel::cancel();
+                return Err(format!("daemon error when sending animation frames: {e}"));
+            }
+            cancel::playing();
+            streamed = true;
+            Ok(())
+        };
+
+        let (mut frames, mut reverse) = compress_frames(
+            animation_frames(img, imgbuf, max_fps)?,
+            *dim,
+            *transform,
+            scaling,
+            img.resize,
+            &img.fill_color,
+            img.color_temp,
+            img.heatmap_blur,
+            img.alpha_threshold,
+            img.keep_alpha,
+            img.playback,
+            compression,
+            img.diff_block_size,
+            true,
+            &mut send_frames,
+        )?;
+        if let Some(factor) = img.time_stretch {
+            for (_, delay) in frames.iter_mut().chain(reverse.iter_mut()) {
+                *delay = stretch_delay(*delay, factor);
+            }
+        }
         let animation = ipc::Animation {
             path: img.path.to_string_lossy().to_string(),
             dimensions: *dim,
-            animation: compress_frames(
-                imgbuf.into_frames()?,
-                *dim,
-                filter,
-                img.resize,
-                &img.fill_color,
-            )?
-            .into_boxed_slice(),
+            animation: frames.into_boxed_slice(),
+            reverse: reverse.into_boxed_slice(),
+            cache_key,
+            compression,
         };
-        animations.push((animation, outputs.to_owned().into_boxed_slice()));
+        if streamed {
+            if let Err(e) = cache::store_animation_frames(&animation) {
+                eprintln!("Error storing cache for {}: {e}", animation.path);
+            }
+            continue;
+        }
+        animations.push((animation, playback(), outputs.to_owned().into_boxed_slice()));
     }
     Ok(animations.into_boxed_slice())
 }
 
+/// The frames of `imgbuf`, at most `max_fps` of them a second. Frames of videos that show the same
+/// scene play the transition we were given between them, see `--detect-scene-change`
+fn animation_frames<'a>(
+    img: &'a cli::Img,
+    imgbuf: &ImgBuf,
+    max_fps: Option<u32>,
+) -> Result<image::Frames<'a>, String> {
+    let frames = imgbuf.try_clone()?.into_frames()?;
+    if !(img.detect_scene_change && imgbuf.is_video()) {
+        return Ok(limit_fps(frames, max_fps));
+    }
+    let transition = |old: &image::RgbaImage, new: &image::RgbaImage, delay: Duration| {
+        let mut transition = make_transition(img);
+        // it must be over by the next frame
+        transition.duration = transition.duration.min(delay.as_secs_f32());
+        let dim = old.dimensions();
+        let bgr = |img: &image::RgbaImage| img.pixels().flat_map(|p| [p[2], p[1], p[0]]).collect();
+        Ok(render_transition(transition, dim, bgr(old), bgr(new))?
+            .into_iter()
+            .map(|rgb| {
+                let rgb = image::RgbImage::from_raw(dim.0, dim.1, rgb).unwrap();
+                image::DynamicImage::ImageRgb8(rgb).into_rgba8()
+            })
+            .collect())
+    };
+    let frames = transition_scenes(frames, img.scene_change_threshold, transition);
+    Ok(limit_fps(frames, max_fps))
+}
+
+/// Streams an animation to the daemon, see `ipc::Playback::stream`: for every loop, we make its
+/// frames again (or read them from the cache, if they are there), a few at a time, sending them
+/// as we go. The daemon only answers once it plays them, so we never get far ahead of it. We are
+/// done once it played every loop, or no longer plays the animation
+#[allow(clippy::too_many_arguments)]
+fn stream_animation(
+    img: &cli::Img,
+    imgbuf: &ImgBuf,
+    (dim, transform): ((u32, u32), ipc::Transform),
+    outputs: &[String],
+    shown: &mpsc::Receiver<()>,
+    playback: ipc::Playback,
+    compression: CompressionBackend,
+    mut cache_key: Option<u64>,
+    max_fps: Option<u32>,
+) -> Result<(), String> {
+    let loops = playback.loops;
+    let mut playback = Some(playback);
+    // whether the frames of the first loop came from the cache
+    let from_cache = std::cell::Cell::new(false);
+    // whether the daemon played any of our frames
+    let mut started = false;
+    let mut send = |frames: &[(BitPack, Duration)], last: bool| {
+        let mut frames = frames.to_vec();
+        if let Some(factor) = img.time_stretch {
+            for (_, delay) in frames.iter_mut() {
+                *delay = stretch_delay(*delay, factor);
+            }
+        }
+        let request = match playback.take() {
+            Some(playback) => {
+                // the daemon only plays animations whose first frame it shows
+                shown
+                    .recv()
+                    .map_err(|_| "failed to show the first frame".to_string())?;
+                let animation = ipc::Animation {
+                    path: img.path.to_string_lossy().to_string(),
+                    dimensions: dim,
+                    animation: frames.into_boxed_slice(),
+                    reverse: Box::new([]),
+                    cache_key: None,
+                    compression,
+                };
+                let playback = ipc::Playback {
+                    from_cache: from_cache.get(),
+                    ..playback
+                };
+                let outputs = outputs.to_owned().into_boxed_slice();
+                Request::Animation(Box::new([(animation, playback, outputs)]))
+            }
+            None => Request::AnimationFrames(ipc::AnimationFrames {
+                request: cancel::request_id(),
+                animation: 0,
+                frames: frames.into_boxed_slice(),
+                last,
+            }),
+        };
+        let socket = ipc::connect(5, 100)?;
+        request.send(&socket)?;
+        if started {
+            // it answers once it plays them, after the frames before them
+            socket
+                .set_read_timeout(None)
+                .map_err(|e| format!("failed to clear read timeout for socket: {e}"))?;
+        }
+        // and hangs up without answering if the animation stops before that
+        let bytes = read_socket(&socket);
+        if !matches!(
+            bytes.as_deref().map(Answer::receive),
+            Ok(Ok(ArchivedAnswer::Ok))
+        ) {
+            cancel::cancel();
+            return Err("the daemon no longer plays the animation".to_string());
+        }
+        cancel::playing();
+        started = true;
+        Ok(())
+    };
+
+    let mut play_once = || {
+        let cached = match cache_key {
+            Some(key) => cache::load_animation_frames(key, dim, compression).unwrap_or_else(|e| {
+                eprintln!("Error loading cache for {:?}: {e}", img.path);
+                cache_key = None;
+                None
+            }),
+            None => None,
+        };
+        from_cache.set(cached.is_some());
+        if let Some(animation) = cached {
+            // in chunks as big as those we make
+            let mut chunks = animation
+                .animation
+                .chunks(rayon::current_num_threads() * 2)
+                .peekable();
+            while let Some(chunk) = chunks.next() {
+                send(chunk, chunks.peek().is_none())?;
+            }
+            return Ok(());
+        }
+        compress_frames(
+            animation_frames(img, imgbuf, max_fps)?,
+            dim,
+            transform,
+            make_scaling(&img.filter, img.gamma_correct),
+            img.resize,
+            &img.fill_color,
+            img.color_temp,
+            img.heatmap_blur,
+            img.alpha_threshold,
+            img.keep_alpha,
+            cli::PlaybackDirection::Forward,
+            compression,
+            img.diff_block_size,
+            false,
+            &mut send,
+        )?;
+        Ok(())
+    };
+    let result = (0..)
+        .take_while(|played| loops.map_or(true, |loops| *played < loops))
+        .try_for_each(|_| play_once());
+    match result {
+        // which is how infinite animations end
+        Err(_) if started && cancel::is_cancelled() => Ok(()),
+        result => result,
+    }
+}
+
 fn split_cmdline_outputs(outputs: &str) -> Box<[String]> {
     outputs
         .split(',')
@@ -324,8 +2144,171 @@ fn split_cmdline_outputs(outputs: &str) -> Box<[String]> {
         .collect()
 }
 
-fn spawn_daemon(no_daemon: bool) -> Result<(), String> {
+/// Puts the `[img]` section of the configuration file right after `swww img`, so that the
+/// arguments the user gave override it
+fn with_img_config(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    Ok(with_config(args, &Config::read()?, None))
+}
+
+/// Like [`with_img_config`], with the `[output."<pattern>"]` sections that match `output`, if any,
+/// after `[img]`
+fn with_config(mut args: Vec<OsString>, config: &Config, output: Option<&str>) -> Vec<OsString> {
+    let command = command_index(&args);
+    if args.get(command).is_some_and(|arg| arg == "img") {
+        let mut defaults = config.args("img");
+        if let Some(output) = output {
+            defaults.extend(config.output_args(output));
+        }
+        args.splice(
+            command + 1..command + 1,
+            defaults.into_iter().map(OsString::from),
+        );
+    }
+    args
+}
+
+/// Where the subcommand is in `args`. `--namespace` is the only option that may come before it
+fn command_index(args: &[OsString]) -> usize {
+    let mut command = 1;
+    while let Some(arg) = args.get(command).and_then(|arg| arg.to_str()) {
+        match arg {
+            "--namespace" => command += 2,
+            _ if arg.starts_with("--namespace=") => command += 1,
+            _ => break,
+        }
+    }
+    command
+}
+
+/// The arguments of this `swww img` the daemon makes its image again with, see `ipc::Remake`:
+/// without the path, which it gives itself, and without the options that only make sense once
+fn remake_args(args: &[OsString], path: &Path) -> Vec<String> {
+    let mut args: Vec<_> = args
+        .iter()
+        .skip(command_index(args) + 1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    // an option may have the same value as the path, but the path usually comes last
+    if let Some(i) = args.iter().rposition(|arg| Path::new(arg) == path) {
+        args.remove(i);
+        if i > 0 && args[i - 1] == "--" {
+            args.remove(i - 1);
+        }
+    }
+    let mut remake = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" | "--sync" | "--queue" => (),
+            "--batch-file" => {
+                args.next();
+            }
+            _ if arg.starts_with("--batch-file=") => (),
+            _ => remake.push(arg),
+        }
+    }
+    remake
+}
+
+/// The options of this `swww img` for `output`, if the `[output."<pattern>"]` sections of the
+/// configuration file have defaults of their own for it: ours, over those, over `[img]`
+fn img_for_output(config: &Config, output: &str) -> Result<Option<cli::Img>, String> {
+    if config.output_args(output).is_empty() {
+        return Ok(None);
+    }
+    let args = with_config(std::env::args_os().collect(), config, Some(output));
+    match cli::Cli::try_parse_from(args) {
+        Ok(cli::Cli {
+            command: Swww::Img(mut img),
+            ..
+        }) => {
+            img.remake_args = remake_args(&std::env::args_os().collect::<Vec<_>>(), &img.path);
+            Ok(Some(img))
+        }
+        Ok(_) => Ok(None),
+        Err(e) => Err(format!("with the defaults of {output}: {e}")),
+    }
+}
+
+/// The `swww init` options we forward to the daemon
+fn daemon_args(init: &Swww) -> Vec<OsString> {
+    let mut args = Vec::new();
+    let Swww::Init {
+        heartbeat_socket,
+        gc_interval,
+        prefer_compositor_scaling,
+        lock_file,
+        statsd_host,
+        script,
+        track_cursor,
+        wayland_socket,
+        layer,
+        layer_namespace,
+        size,
+        anchor,
+        margin,
+        ..
+    } = init
+    else {
+        return args;
+    };
+    if let Some(path) = heartbeat_socket {
+        args.extend(["--heartbeat-socket".into(), path.into()]);
+    }
+    if let Some(secs) = gc_interval {
+        args.extend(["--gc-interval".into(), secs.to_string().into()]);
+    }
+    if *prefer_compositor_scaling {
+        args.push("--prefer-compositor-scaling".into());
+    }
+    if let Some(path) = lock_file {
+        args.extend(["--lock-file".into(), path.into()]);
+    }
+    if let Some(host) = statsd_host {
+        args.extend([
+            "--metrics-backend".into(),
+            "statsd".into(),
+            "--statsd-host".into(),
+            host.into(),
+        ]);
+    }
+    if let Some(path) = script {
+        args.extend(["--script".into(), path.into()]);
+    }
+    if *track_cursor {
+        args.push("--track-cursor".into());
+    }
+    if let Some(path) = wayland_socket {
+        args.extend([
+            "--wayland-backend".into(),
+            "manual".into(),
+            "--wayland-socket".into(),
+            path.into(),
+        ]);
+    }
+    if let Some(layer) = layer {
+        let layer = clap::ValueEnum::to_possible_value(layer).unwrap();
+        args.extend(["--layer".into(), layer.get_name().into()]);
+    }
+    if let Some(namespace) = layer_namespace {
+        args.extend(["--layer-namespace".into(), namespace.into()]);
+    }
+    if let Some(size) = size {
+        args.extend(["--size".into(), size.into()]);
+    }
+    if let Some(anchor) = anchor {
+        let anchor = clap::ValueEnum::to_possible_value(anchor).unwrap();
+        args.extend(["--anchor".into(), anchor.get_name().into()]);
+    }
+    if let Some(margin) = margin {
+        args.extend(["--margin".into(), margin.into()]);
+    }
+    args
+}
+
+fn spawn_daemon(no_daemon: bool, args: Vec<OsString>) -> Result<(), String> {
     let mut cmd = std::process::Command::new("swww-daemon");
+    cmd.args(args);
     if no_daemon {
         match cmd.status() {
             Ok(_) => Ok(()),
@@ -339,44 +2322,6 @@ fn spawn_daemon(no_daemon: bool) -> Result<(), String> {
     }
 }
 
-/// We make sure the Stream is always set to blocking mode
-///
-/// * `tries` -  how make times to attempt the connection
-/// * `interval` - how long to wait between attempts, in milliseconds
-fn connect_to_socket(tries: u8, interval: u64) -> Result<UnixStream, String> {
-    //Make sure we try at least once
-    let tries = if tries == 0 { 1 } else { tries };
-    let path = get_socket_path();
-    let mut error = None;
-    for _ in 0..tries {
-        match UnixStream::connect(&path) {
-            Ok(socket) => {
-                if let Err(e) = socket.set_nonblocking(false) {
-                    return Err(format!("Failed to set blocking connection: {e}"));
-                }
-                #[cfg(debug_assertions)]
-                let timeout = Duration::from_secs(30); //Some operations take a while to respond in debug mode
-                #[cfg(not(debug_assertions))]
-                let timeout = Duration::from_secs(5);
-
-                if let Err(e) = socket.set_read_timeout(Some(timeout)) {
-                    return Err(format!("failed to set read timeout for socket: {e}"));
-                }
-
-                return Ok(socket);
-            }
-            Err(e) => error = Some(e),
-        }
-        std::thread::sleep(Duration::from_millis(interval));
-    }
-    let error = error.unwrap();
-    if error.kind() == std::io::ErrorKind::NotFound {
-        return Err("Socket file not found. Are you sure swww-daemon is running?".to_string());
-    }
-
-    Err(format!("Failed to connect to socket: {error}"))
-}
-
 fn is_daemon_running() -> Result<bool, String> {
     let proc = PathBuf::from("/proc");
 
@@ -396,7 +2341,10 @@ fn is_daemon_running() -> Result<bool, String> {
             if let Ok(cmd) = std::fs::read_to_string(entry_path) {
                 let mut args = cmd.split(&[' ', '\0']);
                 if let Some(arg0) = args.next() {
-                    if arg0.ends_with("swww-daemon") {
+                    // daemons in other namespaces don't get in our way
+                    if arg0.ends_with("swww-daemon")
+                        && daemon_namespace(args, &entry.path()) == ipc::namespace()
+                    {
                         return Ok(true);
                     }
                 }
@@ -406,3 +2354,365 @@ fn is_daemon_running() -> Result<bool, String> {
 
     Ok(false)
 }
+
+/// The namespace of the daemon with these arguments, whose process is at `proc`: the one it got
+/// with `--namespace`, or else the one in its environment
+fn daemon_namespace<'a>(mut args: impl Iterator<Item = &'a str>, proc: &Path) -> Option<String> {
+    let namespace = args.by_ref().find(|arg| *arg == "--namespace");
+    if let Some(namespace) = namespace.and_then(|_| args.next()) {
+        return Some(namespace.to_string());
+    }
+    let environ = std::fs::read(proc.join("environ")).ok()?;
+    environ
+        .split(|&b| b == 0)
+        .find_map(|var| var.strip_prefix(format!("{}=", ipc::NAMESPACE_VAR).as_bytes()))
+        .filter(|namespace| !namespace.is_empty())
+        .map(|namespace| String::from_utf8_lossy(namespace).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_refuse_what_the_daemon_would_before_talking_to_it() {
+        use clap::Parser;
+        for args in [
+            &["swww", "--namespace", "../up", "query"][..],
+            &["swww", "init", "--size", "800"],
+            &["swww", "init", "--size", "800x600", "--margin", "1,2"],
+        ] {
+            let e = run(cli::Cli::try_parse_from(args).unwrap()).unwrap_err();
+            assert_eq!(e.kind, ipc::ErrorKind::InvalidArgument, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn crops_should_keep_the_face_they_were_given() {
+        // a portrait image, gray but for a red face near the top, on a landscape output
+        let mut img_raw = image::RgbImage::from_pixel(20, 40, image::Rgb([128; 3]));
+        for (x, y) in (6..14).flat_map(|x| (2..10).map(move |y| (x, y))) {
+            img_raw.put_pixel(x, y, image::Rgb([255, 0, 0]));
+        }
+        let img = cli::Img::try_parse_from(["img", "a.png", "--resize=crop"]).unwrap();
+        // the resized pixels are BGR
+        let has_face = |pixels: Vec<u8>| pixels.chunks_exact(3).any(|p| p[2] > 200 && p[0] < 50);
+
+        let centered = resize_to(&img, img_raw.clone(), (40, 20), None).unwrap();
+        assert!(
+            !has_face(centered),
+            "the face is above the center of the image"
+        );
+        let cropped = resize_to(&img, img_raw, (40, 20), Some((6, 2, 8, 8))).unwrap();
+        assert_eq!(cropped.len(), 40 * 20 * 3);
+        assert!(has_face(cropped));
+    }
+
+    #[cfg(feature = "face-detect")]
+    #[test]
+    #[ignore = "needs the SeetaFace model and a photo with faces, which CI downloads"]
+    fn crops_should_move_toward_detected_faces() {
+        let var = |name| PathBuf::from(std::env::var_os(name).expect(name));
+        let model = var("SWWW_TEST_FACE_MODEL");
+        let photo = image::open(var("SWWW_TEST_FACE_IMAGE"))
+            .unwrap()
+            .into_rgb8();
+
+        // put the photo in the top left corner of a taller canvas, so a centered crop misses it
+        let (w, h) = photo.dimensions();
+        let mut img_raw = image::RgbImage::from_pixel(w, h * 3, image::Rgb([128; 3]));
+        image::imageops::replace(&mut img_raw, &photo, 0, 0);
+
+        let (x, y, fw, fh) = imgproc::detect_largest_face(&img_raw, &model)
+            .unwrap()
+            .expect("there is a face in the photo");
+        assert!(y + fh <= h, "the face should be found in the photo");
+
+        let dimensions = (w, w / 2);
+        let (_, centered) = imgproc::crop_centering(img_raw.dimensions(), dimensions, None);
+        let (_, focused) =
+            imgproc::crop_centering(img_raw.dimensions(), dimensions, Some((x, y, fw, fh)));
+        assert!(
+            focused < centered,
+            "the crop should move up toward the face: {focused} >= {centered}"
+        );
+
+        // and the crop window, once resized, should hold the face's pixels
+        let img = cli::Img::try_parse_from(["img", "a.png", "--resize=crop"]).unwrap();
+        let cropped = resize_to(&img, img_raw.clone(), dimensions, Some((x, y, fw, fh))).unwrap();
+        let gray = cropped.chunks_exact(3).filter(|p| p == &[128; 3]).count();
+        assert!(
+            gray < cropped.len() / 3,
+            "the crop should be mostly the photo"
+        );
+    }
+
+    #[test]
+    fn watched_images_should_be_displayed_with_the_same_arguments() {
+        let args = ["img", "--watch", "-t", "fade", "walls", "--resize=fit"].map(OsString::from);
+        assert_eq!(
+            &*watch_args(
+                args.into_iter(),
+                Path::new("walls"),
+                Path::new("/home/me/walls/sun.png")
+            ),
+            [
+                "img",
+                "-t",
+                "fade",
+                "/home/me/walls/sun.png",
+                "--resize=fit"
+            ]
+        );
+    }
+
+    #[test]
+    fn images_should_be_made_again_without_their_path_or_one_off_options() {
+        let args = [
+            "swww",
+            "--namespace=a",
+            "img",
+            "--resize=fit",
+            "--sync",
+            "--filter",
+            "Nearest",
+            "--queue",
+            "--",
+            "sun.png",
+        ]
+        .map(OsString::from);
+        assert_eq!(
+            remake_args(&args, Path::new("sun.png")),
+            ["--resize=fit", "--filter", "Nearest"]
+        );
+        let args = ["swww", "img", "--batch-file", "b.json", "-t", "fade"].map(OsString::from);
+        assert_eq!(remake_args(&args, Path::new("")), ["-t", "fade"]);
+    }
+
+    #[test]
+    fn outputs_of_the_same_geometry_should_share_frames() {
+        let (dims, transforms, outputs) = share_geometry(
+            &[(1920, 1080), (1920, 1080), (1920, 1080), (1080, 1920)],
+            &[
+                ipc::Transform::Normal,
+                ipc::Transform::Normal,
+                ipc::Transform::Flipped,
+                ipc::Transform::Normal,
+            ],
+            &[
+                vec!["DP-1".to_string()],
+                vec!["DP-2".to_string(), "DP-3".to_string()],
+                vec!["HDMI-A-1".to_string()],
+                vec!["HDMI-A-2".to_string()],
+            ],
+        );
+        assert_eq!(dims, [(1920, 1080), (1920, 1080), (1080, 1920)]);
+        assert_eq!(transforms.len(), 3);
+        assert_eq!(outputs[0], ["DP-1", "DP-2", "DP-3"]);
+        assert_eq!(outputs[1], ["HDMI-A-1"]);
+    }
+
+    #[test]
+    fn should_convert_transition_spec_to_img_args() {
+        let args =
+            transition_spec_to_args("fade:duration=200,fps=60,reverse=true", Path::new("a.png"))
+                .unwrap();
+        assert_eq!(
+            args,
+            [
+                "img",
+                "a.png",
+                "--transition-type",
+                "fade",
+                "--transition-duration",
+                "0.2",
+                "--transition-fps",
+                "60",
+                "--transition-reverse"
+            ]
+        );
+
+        let img = cli::Img::try_parse_from(args).unwrap();
+        assert_eq!(img.transition_duration, 0.2);
+        assert_eq!(img.transition_fps, 60);
+        assert!(img.transition_reverse);
+    }
+
+    #[test]
+    fn errors_should_print_their_kind_as_json() {
+        let error = ipc::Error::new(ipc::ErrorKind::NoSuchOutput, "no output matches HDMI-*");
+        let json: serde_json::Value = serde_json::from_str(&error_json(&error)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"error": {
+                "kind": "no-such-output",
+                "message": "no output matches HDMI-*",
+                "exit_code": 68,
+            }})
+        );
+        // plain strings are the errors we know nothing more about
+        let error: ipc::Error = "oops".to_string().into();
+        assert_eq!(error.kind.exit_code(), 1);
+    }
+
+    #[test]
+    fn should_print_stats() {
+        let answer = Answer::Stats(ipc::Stats {
+            gc_freed_bytes: None,
+            memory_limit: Some(64 << 20),
+            resident_bytes: 3 << 20,
+            cpu_limits: ipc::CpuLimits {
+                threads: Some(2),
+                nice: Some(10),
+                idle: false,
+            },
+            outputs: Box::new([ipc::OutputStats {
+                name: "DP-1".to_string(),
+                fps: 29.5,
+                frames_shown: 1200,
+                frames_skipped: 4,
+                resident_bytes: 3 << 20,
+                animations: 4,
+                cached_animations: 3,
+                max_fps: Some(30),
+            }]),
+        });
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        answer.send(&sender).unwrap();
+        let bytes = read_socket(&receiver).unwrap();
+        let ArchivedAnswer::Stats(stats) = Answer::receive(&bytes).unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(
+            stats_text(stats),
+            "gc: disabled (see swww-daemon --gc-interval)\n\
+             frames: 3.00 MiB of 64.00 MiB\n\
+             cpu: 2 threads, nice 10\n\
+             DP-1: 29.5 fps (at most 30), 1200 frames shown, 4 skipped, 3.00 MiB of frames, \
+             3 of 4 animations from the cache (75%)"
+        );
+        let json: serde_json::Value = serde_json::from_str(&stats_json(stats)).unwrap();
+        assert_eq!(json["memory_limit"], 64 << 20);
+        assert_eq!(json["outputs"][0]["frames_skipped"], 4);
+        assert_eq!(json["outputs"][0]["cache_hit_rate"], 0.75);
+        assert_eq!(json["outputs"][0]["max_fps"], 30);
+        assert_eq!(json["cpu_limits"]["nice"], 10);
+    }
+
+    #[test]
+    fn should_print_palettes() {
+        let palettes = [("DP-1", vec![[0x28, 0x28, 0x28], [0xff, 0x80, 0x00]])];
+        let json: serde_json::Value =
+            serde_json::from_str(&palette_output(&palettes, cli::PaletteFormat::Json).unwrap())
+                .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"outputs": [{"name": "DP-1", "colors": ["#282828", "#ff8000"]}]})
+        );
+        assert_eq!(
+            palette_output(&palettes, cli::PaletteFormat::Sh).unwrap(),
+            "color0='#282828'\ncolor1='#ff8000'"
+        );
+        let two = [("DP-1", vec![]), ("DP-2", vec![])];
+        assert!(palette_output(&two, cli::PaletteFormat::Sh).is_err());
+    }
+
+    #[test]
+    fn should_save_start_mid_and_end_frames() {
+        let dir = std::env::temp_dir().join(format!("swww-preview-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("wipe");
+        // what the output displays is in the daemon's byte order, the rendered frames aren't
+        let old_img = [30, 20, 10].repeat(4);
+        let half = [[10, 20, 30].repeat(2), [200, 100, 0].repeat(2)].concat();
+        let new_img = [200, 100, 0].repeat(4);
+        save_preview_frames(&prefix, (2, 2), &old_img, &[half, new_img]).unwrap();
+
+        let read = |suffix: &str| {
+            let path = dir.join(format!("wipe_{suffix}.png"));
+            image::open(path).unwrap().into_rgb8().into_raw()
+        };
+        let (start, mid, end) = (read("start"), read("mid"), read("end"));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(start, [10, 20, 30].repeat(4));
+        assert_eq!(
+            mid,
+            [[10, 20, 30].repeat(2), [200, 100, 0].repeat(2)].concat()
+        );
+        assert_eq!(end, [200, 100, 0].repeat(4));
+    }
+
+    #[test]
+    fn transitions_should_start_from_what_the_output_displays_resized_like_the_new_image() {
+        assert_eq!(
+            displayed_img(
+                &cli::Img::try_parse_from(["img", "a.png"]).unwrap(),
+                &ipc::BgImg::Color([10, 20, 30]),
+                (2, 1)
+            )
+            .unwrap(),
+            [30, 20, 10].repeat(2)
+        );
+
+        let dir = std::env::temp_dir().join(format!("swww-displayed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let current = dir.join("current.png");
+        image::save_buffer(&current, &[10, 20, 30], 1, 1, image::ColorType::Rgb8).unwrap();
+        let img = cli::Img::try_parse_from(["img", "a.png", "--resize=no", "--fill-color=ff0000"])
+            .unwrap();
+        let bg = ipc::BgImg::Img(current.to_string_lossy().to_string());
+        let displayed = displayed_img(&img, &bg, (3, 1));
+        let _ = std::fs::remove_dir_all(&dir);
+        // not cropped to fill the output, but padded with the fill color
+        assert_eq!(
+            displayed.unwrap(),
+            [[0, 0, 255], [30, 20, 10], [0, 0, 255]].concat()
+        );
+    }
+
+    #[test]
+    fn should_reject_malformed_transition_specs() {
+        let path = Path::new("a.png");
+        assert!(transition_spec_to_args("wipe", path).is_ok());
+        assert!(transition_spec_to_args("wipe:angle", path).is_err());
+        assert!(transition_spec_to_args("wipe:duration=soon", path).is_err());
+        assert!(transition_spec_to_args("wipe:reverse=maybe", path).is_err());
+        let args = transition_spec_to_args("wipe:colour=red", path).unwrap();
+        assert!(cli::Img::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn loop_boomerang_should_play_ping_pong() {
+        let playback = |args: &[&str]| {
+            cli::Img::try_parse_from(["img", "a.gif"].iter().chain(args))
+                .map(|img| img.playback)
+                .ok()
+        };
+        assert_eq!(playback(&[]), Some(cli::PlaybackDirection::Forward));
+        assert_eq!(
+            playback(&["--loop-boomerang"]),
+            Some(cli::PlaybackDirection::PingPong)
+        );
+        assert_eq!(
+            playback(&["--playback", "reverse"]),
+            Some(cli::PlaybackDirection::Reverse)
+        );
+        // like when the configuration file sets one, and the command line the other
+        assert_eq!(
+            playback(&["--loop-boomerang", "--playback", "reverse"]),
+            Some(cli::PlaybackDirection::Reverse)
+        );
+    }
+
+    #[test]
+    fn ken_burns_should_zoom_in_by_default() {
+        let zoom = |args: &[&str]| {
+            cli::Img::try_parse_from(["img", "a.png"].iter().chain(args))
+                .map(|img| img.zoom)
+                .ok()
+        };
+        assert_eq!(zoom(&[]), Some(1.0));
+        assert_eq!(zoom(&["--ken-burns"]), Some(1.1));
+        assert_eq!(zoom(&["--ken-burns", "--zoom", "1.5"]), Some(1.5));
+    }
+}
@@ -0,0 +1,78 @@
+//! Picks a random image when `swww img` gets a directory.
+//!
+//! We only look at the extensions, rather than opening every file to check whether it is an
+//! image, since directories of wallpapers can be big, and we only need one of them.
+
+use std::path::{Path, PathBuf};
+
+use rand::seq::SliceRandom;
+
+/// A random image from `dir`, or from its subdirectories too, if `recursive`. An image is any file
+/// with one of `extensions`, ignoring their case
+pub fn random_image(dir: &Path, recursive: bool, extensions: &[String]) -> Result<PathBuf, String> {
+    let mut images = Vec::new();
+    find_images(dir, recursive, extensions, &mut images)?;
+    images
+        .choose(&mut rand::thread_rng())
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "found no images in {dir:?} (looking for the extensions: {})",
+                extensions.join(", ")
+            )
+        })
+}
+
+fn find_images(
+    dir: &Path,
+    recursive: bool,
+    extensions: &[String],
+    images: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let entries = dir
+        .read_dir()
+        .map_err(|e| format!("failed to read directory {dir:?}: {e}"))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // follow symlinks, like the ones to where the wallpapers really are
+        if path.is_dir() {
+            if recursive {
+                find_images(&path, recursive, extensions, images)?;
+            }
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+        {
+            images.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_find_images_with_the_extensions() {
+        let dir = std::env::temp_dir().join(format!("swww-pick-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["a.PNG", "b.txt", "nested/c.jpg"] {
+            std::fs::write(dir.join(file), []).unwrap();
+        }
+        let extensions = ["png".to_string(), "jpg".to_string()];
+
+        let mut images = Vec::new();
+        find_images(&dir, false, &extensions, &mut images).unwrap();
+        assert_eq!(images, [dir.join("a.PNG")]);
+
+        images.clear();
+        find_images(&dir, true, &extensions, &mut images).unwrap();
+        images.sort();
+        assert_eq!(images, [dir.join("a.PNG"), dir.join("nested/c.jpg")]);
+
+        assert!(random_image(&dir, false, &["gif".to_string()]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
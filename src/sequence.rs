@@ -0,0 +1,142 @@
+//! Plays a directory of numbered images, like `frame_0001.png`, `frame_0002.png`, …, as an
+//! animation, for `swww img --fps`.
+//!
+//! This is how render pipelines and screen recorders export loops. The frames go through the same
+//! diffing and compression as those of gifs, without the colors gifs lose. We decode them one at a
+//! time, as the animation asks for them, so we never hold more than one of them.
+
+use image::{Delay, Frame, Frames, ImageError};
+
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The images in `dir` with one of `extensions`, ignoring their case, in the order to play them
+pub fn frames(dir: &Path, extensions: &[String]) -> Result<Vec<PathBuf>, String> {
+    let entries = dir
+        .read_dir()
+        .map_err(|e| format!("failed to read directory {dir:?}: {e}"))?;
+    let mut frames = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            !path.is_dir()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+        })
+        .collect::<Vec<_>>();
+    if frames.is_empty() {
+        return Err(format!(
+            "found no frames in {dir:?} (looking for the extensions: {})",
+            extensions.join(", ")
+        ));
+    }
+    frames.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(frames)
+}
+
+/// Compares names with the numbers in them as numbers, so that `frame_2` comes before `frame_10`,
+/// for sequences whose numbers aren't zero padded
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let (na, ra) = split_number(a);
+            let (nb, rb) = split_number(b);
+            // without the leading zeros, longer numbers are bigger ones
+            let (ta, tb) = (na.trim_start_matches('0'), nb.trim_start_matches('0'));
+            match ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb)) {
+                Ordering::Equal => (a, b) = (ra, rb),
+                ordering => return ordering,
+            }
+        } else {
+            match ca.cmp(&cb) {
+                Ordering::Equal => (a, b) = (&a[ca.len_utf8()..], &b[cb.len_utf8()..]),
+                ordering => return ordering,
+            }
+        }
+    }
+}
+
+fn split_number(s: &str) -> (&str, &str) {
+    s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()))
+}
+
+/// Every one of `frames`, each shown for `1 / fps` seconds
+pub fn into_frames<'a>(frames: Vec<PathBuf>, fps: f32) -> Frames<'a> {
+    Frames::new(Box::new(SequenceFrames {
+        frames: frames.into_iter(),
+        delay: Delay::from_saturating_duration(Duration::from_secs_f32(1.0 / fps)),
+    }))
+}
+
+struct SequenceFrames {
+    frames: std::vec::IntoIter<PathBuf>,
+    delay: Delay,
+}
+
+impl Iterator for SequenceFrames {
+    type Item = Result<Frame, ImageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let img = image::open(self.frames.next()?);
+        Some(img.map(|img| Frame::from_parts(img.into_rgba8(), 0, 0, self.delay)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_should_be_sorted_by_their_numbers() {
+        let dir = std::env::temp_dir().join(format!("swww-sequence-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested.png")).unwrap();
+        for file in ["frame_10.png", "frame_2.PNG", "frame_1.png", "notes.txt"] {
+            std::fs::write(dir.join(file), []).unwrap();
+        }
+        let names = frames(&dir, &["png".to_string()])
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["frame_1.png", "frame_2.PNG", "frame_10.png"]);
+        assert!(frames(&dir, &["gif".to_string()]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(natural_cmp("frame_0009", "frame_0010"), Ordering::Less);
+        assert_eq!(natural_cmp("frame_010", "frame_9"), Ordering::Greater);
+        assert_eq!(natural_cmp("a1b", "a1"), Ordering::Greater);
+    }
+
+    #[test]
+    fn every_frame_should_be_shown_for_its_share_of_a_second() {
+        let dir = std::env::temp_dir().join(format!("swww-sequence-fps-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for i in 1..=3u8 {
+            image::RgbImage::from_pixel(2, 2, image::Rgb([i; 3]))
+                .save(dir.join(format!("{i}.png")))
+                .unwrap();
+        }
+        let frames = into_frames(frames(&dir, &["png".to_string()]).unwrap(), 4.0)
+            .collect_frames()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(frames.len(), 3);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(
+                frame.buffer().get_pixel(0, 0).0,
+                [i as u8 + 1, i as u8 + 1, i as u8 + 1, 255]
+            );
+            assert_eq!(Duration::from(frame.delay()), Duration::from_millis(250));
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Renders SVGs with `resvg`.
+//!
+//! Unlike every other image, an SVG has no pixels to resize, so we render it at the exact size
+//! each output needs instead, see [`render_size`].
+
+use image::RgbaImage;
+use resvg::{
+    tiny_skia::{Pixmap, Transform},
+    usvg::{self, fontdb, PostProcessingSteps, TreeParsing, TreePostProc},
+};
+
+use std::{ffi::OsStr, path::Path};
+
+use crate::cli::ResizeStrategy;
+
+/// We only recognize SVGs by their extension, since `image` can't guess their format
+pub fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg") || ext.eq_ignore_ascii_case("svgz"))
+}
+
+pub struct Svg {
+    tree: usvg::Tree,
+}
+
+impl Svg {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("failed to read {path:?}: {e}"))?;
+        Self::from_data(&data)
+    }
+
+    pub fn from_data(data: &[u8]) -> Result<Self, String> {
+        let mut tree = usvg::Tree::from_data(data, &usvg::Options::default())
+            .map_err(|e| format!("failed to parse svg: {e}"))?;
+        let mut fonts = fontdb::Database::new();
+        fonts.load_system_fonts();
+        let steps = PostProcessingSteps {
+            convert_text_into_paths: true,
+        };
+        tree.postprocess(steps, &fonts);
+        Ok(Self { tree })
+    }
+
+    /// The size the SVG says it should be rendered at
+    pub fn size(&self) -> (f32, f32) {
+        (self.tree.size.width(), self.tree.size.height())
+    }
+
+    /// Renders the SVG stretched to exactly `dim`
+    pub fn render(&self, dim: (u32, u32)) -> Result<RgbaImage, String> {
+        let mut pixmap = Pixmap::new(dim.0, dim.1)
+            .ok_or_else(|| format!("can't render svg at {}x{}", dim.0, dim.1))?;
+        let (width, height) = self.size();
+        let transform = Transform::from_scale(dim.0 as f32 / width, dim.1 as f32 / height);
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        // tiny-skia's pixels are premultiplied, while `image`'s are not
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let pixel = pixel.demultiply();
+                [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+            })
+            .collect();
+        Ok(RgbaImage::from_raw(dim.0, dim.1, pixels).unwrap())
+    }
+}
+
+/// The size to render an SVG of size `svg` at, so that `resize` leaves it as it is on an output of
+/// size `dim`, instead of scaling it
+pub fn render_size(resize: ResizeStrategy, svg: (f32, f32), dim: (u32, u32)) -> (u32, u32) {
+    if resize == ResizeStrategy::Stretch {
+        return dim;
+    }
+    let (width_scale, height_scale) = (dim.0 as f32 / svg.0, dim.1 as f32 / svg.1);
+    let scale = match resize {
+        ResizeStrategy::No | ResizeStrategy::Tile | ResizeStrategy::Stretch => 1.0,
+        ResizeStrategy::Crop => width_scale.max(height_scale),
+        ResizeStrategy::Fit => width_scale.min(height_scale),
+        ResizeStrategy::Letterbox => width_scale,
+        ResizeStrategy::Pillarbox => height_scale,
+    };
+    let side = |len: f32| ((len * scale).round() as u32).max(1);
+    (side(svg.0), side(svg.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+        <rect width="5" height="10" fill="red"/>
+    </svg>"#;
+
+    #[test]
+    fn should_render_at_the_requested_size() {
+        let svg = Svg::from_data(SQUARE).unwrap();
+        assert_eq!(svg.size(), (10.0, 10.0));
+        let img = svg.render((100, 40)).unwrap();
+        assert_eq!(img.dimensions(), (100, 40));
+        assert_eq!(img.get_pixel(10, 20).0, [255, 0, 0, 255]);
+        assert_eq!(img.get_pixel(90, 20).0[3], 0);
+    }
+
+    #[test]
+    fn render_size_should_match_the_resize_strategy() {
+        let svg = (100.0, 50.0);
+        let dim = (1920, 1080);
+        assert_eq!(render_size(ResizeStrategy::No, svg, dim), (100, 50));
+        assert_eq!(render_size(ResizeStrategy::Crop, svg, dim), (2160, 1080));
+        assert_eq!(render_size(ResizeStrategy::Fit, svg, dim), (1920, 960));
+        assert_eq!(
+            render_size(ResizeStrategy::Letterbox, svg, dim),
+            (1920, 960)
+        );
+        assert_eq!(
+            render_size(ResizeStrategy::Pillarbox, svg, dim),
+            (2160, 1080)
+        );
+        assert_eq!(render_size(ResizeStrategy::Stretch, svg, dim), dim);
+        assert_eq!(render_size(ResizeStrategy::Tile, svg, dim), (100, 50));
+    }
+}
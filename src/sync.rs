@@ -0,0 +1,129 @@
+//! Waits for the transitions of `swww img` to be over, see `swww img --sync`.
+//!
+//! We subscribe to the daemon's events before sending the image, so we can't miss any. An output
+//! is done once a transition started on it, and then finished. Transitions that were already
+//! playing when we subscribed only finish, so they don't count. If another request interrupts
+//! ours, we wait for its transition instead, since that is what ends up on screen.
+
+use std::{
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use utils::ipc::{self, read_socket, Answer, ArchivedAnswer, Request};
+
+#[derive(Deserialize)]
+struct Event {
+    event: String,
+    output: String,
+}
+
+pub struct Sync {
+    events: BufReader<UnixStream>,
+    timeout: Duration,
+}
+
+impl Sync {
+    /// Starts listening to the daemon's events. Call it before sending the request to wait for
+    pub fn subscribe(timeout: Duration) -> Result<Self, String> {
+        let socket = ipc::connect(5, 100)?;
+        Request::Subscribe.send(&socket)?;
+        let bytes = read_socket(&socket)?;
+        if let ArchivedAnswer::Err(msg) = Answer::receive(&bytes)? {
+            return Err(msg.to_string());
+        }
+        Ok(Self {
+            events: BufReader::new(socket),
+            timeout,
+        })
+    }
+
+    /// Waits until the transitions on all of these outputs are over, or the timeout runs out
+    pub fn wait(mut self, outputs: &[String]) -> Result<(), String> {
+        let deadline = Instant::now() + self.timeout;
+        let mut pending: Vec<_> = outputs.iter().map(|output| (output, false)).collect();
+        let mut line = String::new();
+        while !pending.is_empty() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(timed_out(self.timeout, &pending));
+            }
+            self.events
+                .get_ref()
+                .set_read_timeout(Some(left))
+                .map_err(|e| format!("failed to set read timeout for socket: {e}"))?;
+            line.clear();
+            match self.events.read_line(&mut line) {
+                Ok(0) => return Err("the daemon exited before the transitions were over".into()),
+                Ok(_) => track(&mut pending, &line),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(timed_out(self.timeout, &pending));
+                }
+                Err(e) => return Err(format!("failed to read event: {e}")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Marks the outputs whose transition started, and forgets those whose transition is over
+fn track(pending: &mut Vec<(&String, bool)>, line: &str) {
+    let Ok(event) = serde_json::from_str::<Event>(line) else {
+        // not every event is about an output's transition
+        return;
+    };
+    match event.event.as_str() {
+        "transition_started" => {
+            for (output, started) in pending.iter_mut() {
+                *started |= **output == event.output;
+            }
+        }
+        "transition_finished" => {
+            pending.retain(|(output, started)| !(*started && **output == event.output));
+        }
+        _ => (),
+    }
+}
+
+fn timed_out(timeout: Duration, pending: &[(&String, bool)]) -> String {
+    let outputs: Vec<_> = pending.iter().map(|(output, _)| output.as_str()).collect();
+    format!(
+        "timed out after {}s, waiting for the transitions on: {}",
+        timeout.as_secs_f32(),
+        outputs.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_count_transitions_that_started_after_subscribing() {
+        let (dp1, dp2) = ("DP-1".to_string(), "DP-2".to_string());
+        let mut pending = vec![(&dp1, false), (&dp2, false)];
+        // a transition that was already playing
+        track(
+            &mut pending,
+            r#"{"event":"transition_finished","output":"DP-1"}"#,
+        );
+        assert_eq!(pending.len(), 2);
+        track(
+            &mut pending,
+            r#"{"event":"transition_started","output":"DP-1"}"#,
+        );
+        track(&mut pending, r#"{"event":"output_added","output":"DP-3"}"#);
+        track(
+            &mut pending,
+            r#"{"event":"transition_finished","output":"DP-1"}"#,
+        );
+        assert_eq!(pending, [(&dp2, false)]);
+    }
+}
@@ -0,0 +1,193 @@
+//! Decodes video files by spawning `ffmpeg`, which streams their frames to us as raw RGBA.
+//!
+//! Raw frames have no header, so we first ask `ffprobe` for the size and frame rate of the
+//! video's first stream, and how it is rotated. `ffmpeg` turns rotated videos upright, like phones
+//! record them, so their frames are as big as the stream once rotated.
+
+use image::{Delay, Frame, Frames, ImageError, RgbaImage};
+
+use std::{
+    ffi::OsStr,
+    io::{BufReader, ErrorKind, Read},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+    time::Duration,
+};
+
+/// We only recognize videos by their extension, since `image` can't guess their format
+const EXTENSIONS: [&str; 6] = ["mp4", "webm", "mkv", "mov", "avi", "m4v"];
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+#[derive(Debug, PartialEq)]
+struct Probe {
+    width: u32,
+    height: u32,
+    /// `None` when the container doesn't say
+    fps: Option<f32>,
+}
+
+fn probe(path: &Path) -> Result<Probe, String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args([
+            "-show_entries",
+            "stream=width,height,avg_frame_rate:stream_tags=rotate",
+        ])
+        .args(["-show_entries", "stream_side_data=rotation"])
+        .args(["-of", "default=noprint_wrappers=1"])
+        .arg(path)
+        .output()
+        .map_err(|e| format!("failed to spawn ffprobe (is ffmpeg installed?): {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe failed to read {path:?}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    parse_probe(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses lines like `width=1920`, `height=1080`, `avg_frame_rate=30000/1001` and `rotation=-90`,
+/// or `TAG:rotate=90` from older containers
+fn parse_probe(raw: &str) -> Result<Probe, String> {
+    let field = |name| {
+        raw.lines()
+            .find_map(|line| line.trim().strip_prefix(name)?.strip_prefix('='))
+    };
+    let size = |name| {
+        field(name)
+            .and_then(|s| s.parse::<u32>().ok())
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("bad video {name} from ffprobe: {raw}"))
+    };
+    let (width, height) = (size("width")?, size("height")?);
+    let fps = field("avg_frame_rate")
+        .and_then(|rate| rate.split_once('/'))
+        .and_then(|(num, den)| {
+            let fps = num.parse::<f32>().ok()? / den.parse::<f32>().ok()?;
+            (fps.is_finite() && fps > 0.0).then_some(fps)
+        });
+    let rotation = field("rotation")
+        .or_else(|| field("TAG:rotate"))
+        .and_then(|r| r.parse::<f32>().ok())
+        .unwrap_or_default();
+    // a quarter turn either way
+    let (width, height) = if (rotation.round() as i32).rem_euclid(180) == 90 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    Ok(Probe { width, height, fps })
+}
+
+/// Every frame of the video, played at `fps` if set, and at the video's own frame rate otherwise
+pub fn into_frames<'a>(path: &Path, fps: Option<f32>) -> Result<Frames<'a>, String> {
+    let probe = probe(path)?;
+    let fps = fps.or(probe.fps).ok_or_else(|| {
+        format!("couldn't find the frame rate of {path:?}, set one with --video-fps")
+    })?;
+
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-i"]).arg(path);
+    if fps != probe.fps.unwrap_or_default() {
+        command.arg("-vf").arg(format!("fps={fps}"));
+    }
+    let mut child = command
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to spawn ffmpeg: {e}"))?;
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+
+    Ok(Frames::new(Box::new(VideoFrames {
+        child,
+        stdout,
+        width: probe.width,
+        height: probe.height,
+        delay: Delay::from_saturating_duration(Duration::from_secs_f32(1.0 / fps)),
+    })))
+}
+
+struct VideoFrames {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    width: u32,
+    height: u32,
+    delay: Delay,
+}
+
+impl Iterator for VideoFrames {
+    type Item = Result<Frame, ImageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = vec![0; self.width as usize * self.height as usize * 4];
+        match self.stdout.read_exact(&mut buf) {
+            Ok(()) => (),
+            // the video is over
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(ImageError::IoError(e))),
+        }
+        let img = RgbaImage::from_raw(self.width, self.height, buf).unwrap();
+        Some(Ok(Frame::from_parts(img, 0, 0, self.delay)))
+    }
+}
+
+impl Drop for VideoFrames {
+    fn drop(&mut self) {
+        // we may stop reading before the end, like when we only want the first frame
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_recognize_videos_by_their_extension() {
+        assert!(is_video(Path::new("/some/clip.mp4")));
+        assert!(is_video(Path::new("clip.WEBM")));
+        assert!(!is_video(Path::new("clip.gif")));
+        assert!(!is_video(Path::new("-")));
+    }
+
+    #[test]
+    fn should_parse_ffprobe_output() {
+        assert_eq!(
+            parse_probe("width=1920\nheight=1080\navg_frame_rate=30000/1001\n").unwrap(),
+            Probe {
+                width: 1920,
+                height: 1080,
+                fps: Some(30000.0 / 1001.0)
+            }
+        );
+        assert_eq!(
+            parse_probe("width=640\nheight=480\navg_frame_rate=0/0")
+                .unwrap()
+                .fps,
+            None
+        );
+        assert!(parse_probe("width=640\nheight=0\navg_frame_rate=30/1").is_err());
+        assert!(parse_probe("").is_err());
+    }
+
+    #[test]
+    fn rotated_videos_should_have_their_frames_upright() {
+        let size = |raw: &str| {
+            let probe = parse_probe(raw).unwrap();
+            (probe.width, probe.height)
+        };
+        let video = "width=1920\nheight=1080\navg_frame_rate=30/1\n";
+        assert_eq!(size(&format!("{video}rotation=-90")), (1080, 1920));
+        assert_eq!(size(&format!("{video}TAG:rotate=270")), (1080, 1920));
+        assert_eq!(size(&format!("{video}rotation=180")), (1920, 1080));
+        assert_eq!(size(video), (1920, 1080));
+    }
+}
@@ -67,6 +67,8 @@ fn general_commands() {
     sending_img_to_individual_monitors(&output);
     sending_img_to_monitor_that_does_not_exist();
     sending_img_with_custom_transition();
+    pausing_and_resuming();
+    sending_img_that_plays_once();
     clear_outputs();
     killing_daemon();
     cmd().arg("query").assert().failure(); //daemon is dead, so this should fail
@@ -179,6 +181,34 @@ fn sending_img_with_custom_transition() {
         .success();
 }
 
+fn pausing_and_resuming() {
+    // only paused outputs can be stepped
+    cmd().arg("step").assert().failure();
+    cmd().arg("pause").assert().success();
+    cmd().arg("img").arg(TEST_IMGS[2]).assert().success();
+    cmd().arg("step").arg("3").assert().success();
+    cmd().arg("resume").assert().success();
+}
+
+fn sending_img_that_plays_once() {
+    cmd()
+        .arg("img")
+        .arg(TEST_IMGS[1])
+        .arg("--loop")
+        .arg("1")
+        .arg("--at-end")
+        .arg("first-frame")
+        .assert()
+        .success();
+    cmd()
+        .arg("img")
+        .arg(TEST_IMGS[1])
+        .arg("--loop")
+        .arg("0")
+        .assert()
+        .failure();
+}
+
 fn clear_outputs() {
     cmd().arg("clear").assert().success();
 }
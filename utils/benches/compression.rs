@@ -1,5 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use utils::comp_decomp::BitPack;
+use utils::comp_decomp::{BitPack, CompressionBackend};
 
 fn generate_data() -> (Box<[u8]>, Box<[u8]>) {
     let v1 = vec![120; 1920 * 1080 * 3];
@@ -39,23 +39,33 @@ fn buf_from(slice: &[u8]) -> Vec<u8> {
 pub fn compression_and_decompression(c: &mut Criterion) {
     let (prev, cur) = generate_data();
 
+    let backends = [
+        CompressionBackend::Lz4,
+        CompressionBackend::Zstd,
+        CompressionBackend::None,
+    ];
+
     let mut comp = c.benchmark_group("compression");
-    comp.bench_function("Full", |b| {
-        b.iter(|| {
-            black_box(BitPack::pack(&prev, &cur).ok());
-        })
-    });
+    for backend in backends {
+        comp.bench_function(format!("Full {backend:?}"), |b| {
+            b.iter(|| {
+                black_box(BitPack::pack(&prev, &cur, backend, 1).ok());
+            })
+        });
+    }
     comp.finish();
 
     let mut decomp = c.benchmark_group("decompression");
-    let bitpack = BitPack::pack(&prev, &cur).unwrap();
-    let mut canvas = buf_from(&prev);
+    for backend in backends {
+        let bitpack = BitPack::pack(&prev, &cur, backend, 1).unwrap();
+        let mut canvas = buf_from(&prev);
 
-    decomp.bench_function("Full", |b| {
-        b.iter(|| {
-            black_box(bitpack.unpack(&mut canvas));
-        })
-    });
+        decomp.bench_function(format!("Full {backend:?}"), |b| {
+            b.iter(|| {
+                black_box(bitpack.unpack(&mut canvas));
+            })
+        });
+    }
 
     decomp.finish();
 }
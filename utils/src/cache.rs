@@ -1,32 +1,71 @@
 //! Implements basic cache functionality.
 //!
 //! The idea is:
-//!   1. the client registers the last image sent for each output in a file
-//!   2. the daemon spawns a client that reloads that image when an output is created
+//!   1. the client registers the last image sent for each output in a file, with how it made it
+//!      (see [`Remake`])
+//!   2. the daemon spawns a client that makes that image again, the same way, when an output is
+//!      created
+//!
+//! Animation frames are cached too, with a checksum at the end of the file. A file that doesn't
+//! match it (because it was cut short, or written by an older version) gets deleted, so that the
+//! client processes the animation again and caches it anew. Their files are named after the
+//! file they come from, the filter, and the size in pixels they were made for, so that moving a
+//! wallpaper between outputs of different sizes, and back, finds the frames made for each of
+//! them. Once the frames take more than the client's `--cache-limit`, those used the longest time
+//! ago go first, see [`evict_animation_frames`].
 
 use std::{
     fs::File,
     io::{BufReader, BufWriter, Read, Write},
+    os::fd::AsRawFd,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
 };
 
 use rkyv::{Deserialize, Infallible};
 
-use crate::ipc::Animation;
+use crate::{
+    comp_decomp::{checksum, CompressionBackend},
+    ipc::{Animation, Remake},
+};
+
+/// Ends every file of cached frames, after their checksum
+const TRAILER_MAGIC: &[u8; 4] = b"swww";
 
-pub fn store(output_name: &str, img_path: &str) -> Result<(), String> {
+pub fn store(output_name: &str, img_path: &str, remake: &Remake) -> Result<(), String> {
     let mut filepath = cache_dir()?;
     filepath.push(output_name);
     let file = File::create(filepath).map_err(|e| e.to_string())?;
+    write_through(file, previous_image_contents(img_path, remake).as_bytes())
+}
 
-    let mut writer = BufWriter::new(file);
-    writer
-        .write_all(img_path.as_bytes())
-        .map_err(|e| format!("failed to write cache: {e}"))
+/// The path, where the client ran, and its arguments, separated by NUL, which none of them can hold
+fn previous_image_contents(img_path: &str, remake: &Remake) -> String {
+    let mut contents = img_path.to_string();
+    for field in std::iter::once(&remake.cwd).chain(remake.args.iter()) {
+        contents.push('\0');
+        contents.push_str(field);
+    }
+    contents
+}
+
+/// The opposite of [`previous_image_contents`]. Files from older versions only hold the path
+fn parse_previous_image(contents: &str) -> (String, Remake) {
+    let mut fields = contents.split('\0').map(str::to_string);
+    let path = fields.next().unwrap_or_default();
+    let remake = Remake {
+        cwd: fields.next().unwrap_or_default(),
+        args: fields.collect(),
+    };
+    (path, remake)
 }
 
 pub fn store_animation_frames(animation: &Animation) -> Result<(), String> {
-    let filename = animation_filename(&PathBuf::from(&animation.path), animation.dimensions);
+    let Some(key) = animation.cache_key else {
+        return Ok(());
+    };
+    let filename = animation_filename(key, animation.dimensions, animation.compression);
     let mut filepath = cache_dir()?;
     filepath.push(&filename);
 
@@ -37,21 +76,66 @@ pub fn store_animation_frames(animation: &Animation) -> Result<(), String> {
 
     if !filepath.is_file() {
         let file = File::create(filepath).map_err(|e| e.to_string())?;
-
-        let mut writer = BufWriter::new(file);
-        writer
-            .write_all(&bytes)
-            .map_err(|e| format!("failed to write cache: {e}"))
+        write_through(file, &seal(&bytes))
     } else {
         Ok(())
     }
 }
 
+/// Identifies the frames of an animation in the cache by the contents of its file and the filter
+/// it was resized with. That way, we never load stale frames after the file changes
+pub fn animation_key(path: &Path, filter: &str) -> Result<u64, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {path:?}: {e}"))?;
+    let mut reader = BufReader::new(file);
+    let mut hash = Fnv1a::new();
+    let mut buf = [0; 1 << 16];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hash.write(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(format!("failed to read {path:?}: {e}")),
+        }
+    }
+    hash.write(filter.as_bytes());
+    Ok(hash.0)
+}
+
+/// Where to keep the image downloaded from `url`. The same url always goes to the same file, so
+/// downloading it again replaces the old download
+pub fn download_path(url: &str, extension: &str) -> Result<PathBuf, String> {
+    let mut path = cache_dir()?;
+    path.push("downloads");
+    create_dir(&path)?;
+    let mut hash = Fnv1a::new();
+    hash.write(url.as_bytes());
+    path.push(format!("{:016x}.{extension}", hash.0));
+    Ok(path)
+}
+
+/// We need a hash that stays the same across builds, since the keys are stored on disk, which
+/// std's `DefaultHasher` doesn't promise
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
 pub fn load_animation_frames(
-    path: &Path,
+    key: u64,
     dimensions: (u32, u32),
+    compression: CompressionBackend,
 ) -> Result<Option<Animation>, String> {
-    let filename = animation_filename(path, dimensions);
+    let filename = animation_filename(key, dimensions, compression);
     let cache_dir = cache_dir()?;
     let mut filepath = cache_dir.clone();
     filepath.push(filename);
@@ -69,8 +153,17 @@ pub fn load_animation_frames(
                 .read_to_end(&mut buf)
                 .map_err(|e| format!("failed to read file `{filepath:?}`: {e}"))?;
 
+            let Some(len) = unseal(&buf) else {
+                let _ = std::fs::remove_file(&filepath);
+                return Err(format!(
+                    "cached frames in {filepath:?} are corrupt, so we deleted them"
+                ));
+            };
+            buf.truncate(len);
             let frames = unsafe { rkyv::archived_root::<Animation>(&buf) };
             let frames: Animation = frames.deserialize(&mut Infallible).unwrap();
+            // the last time frames were used is what decides which go first
+            touch(buf_reader.get_ref());
 
             return Ok(Some(frames));
         }
@@ -78,13 +171,121 @@ pub fn load_animation_frames(
     Ok(None)
 }
 
-pub fn get_previous_image_path(output_name: &str) -> Result<String, String> {
+/// Deletes the cached animation frames used the longest time ago, until the rest take at most
+/// `limit` bytes. Returns how many bytes we freed
+pub fn evict_animation_frames(limit: u64) -> Result<u64, String> {
+    evict_oldest(&cache_dir()?, limit)
+}
+
+fn evict_oldest(cache_dir: &Path, limit: u64) -> Result<u64, String> {
+    let read_dir = cache_dir
+        .read_dir()
+        .map_err(|e| format!("failed to read cache directory ({cache_dir:?}): {e}"))?;
+    let mut files: Vec<_> = read_dir
+        .flatten()
+        .filter(|entry| is_animation_file(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            Some((used, metadata.len(), entry.path()))
+        })
+        .collect();
+    // the most recently used last
+    files.sort();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    let mut freed = 0;
+    for (_, len, path) in files {
+        if total <= limit {
+            break;
+        }
+        std::fs::remove_file(&path)
+            .map_err(|e| format!("failed to remove cached frames {path:?}: {e}"))?;
+        total -= len;
+        freed += len;
+    }
+    Ok(freed)
+}
+
+/// A new file in the cache directory, for the daemon to write the frames that don't fit under its
+/// `--memory-limit` to. It is already gone from the directory, so it goes away with the last handle
+/// to it, even if the daemon dies
+pub fn spill_file() -> Result<File, String> {
+    static SPILLED: AtomicU64 = AtomicU64::new(0);
+    let mut path = cache_dir()?;
+    let n = SPILLED.fetch_add(1, Ordering::Relaxed);
+    path.push(format!("spilled-{}-{n}", std::process::id()));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| format!("failed to create {path:?}: {e}"))?;
+    std::fs::remove_file(&path).map_err(|e| format!("failed to unlink {path:?}: {e}"))?;
+    Ok(file)
+}
+
+/// Files of frames are named by [`animation_filename`]
+fn is_animation_file(name: &str) -> bool {
+    name.get(..16)
+        .is_some_and(|key| key.bytes().all(|b| b.is_ascii_hexdigit()))
+        && name.get(16..18) == Some("__")
+}
+
+/// Writes `bytes` to `file`, and waits for them to get to the disk, so that we only return once
+/// the cache can be loaded again, even if we, or the daemon restoring from it, die right after
+fn write_through(file: File, bytes: &[u8]) -> Result<(), String> {
+    let mut writer = BufWriter::new(file);
+    writer
+        .write_all(bytes)
+        .and_then(|()| writer.flush())
+        .and_then(|()| writer.get_ref().sync_all())
+        .map_err(|e| format!("failed to write cache: {e}"))
+}
+
+/// Makes `file` look like it was just modified
+fn touch(file: &File) {
+    unsafe { libc::futimens(file.as_raw_fd(), std::ptr::null()) };
+}
+
+/// Makes `file` look like it was last modified `secs` seconds after the epoch
+#[cfg(test)]
+fn set_modified(file: &File, secs: i64) {
+    let time = libc::timespec {
+        tv_sec: secs as libc::time_t,
+        tv_nsec: 0,
+    };
+    assert_eq!(
+        unsafe { libc::futimens(file.as_raw_fd(), [time, time].as_ptr()) },
+        0
+    );
+}
+
+/// `bytes`, followed by their checksum and [`TRAILER_MAGIC`]. We put them at the end so that the
+/// archive keeps starting at the start of the file, where it is aligned
+fn seal(bytes: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(bytes.len() + 12);
+    sealed.extend_from_slice(bytes);
+    sealed.extend_from_slice(&checksum(bytes).to_le_bytes());
+    sealed.extend_from_slice(TRAILER_MAGIC);
+    sealed
+}
+
+/// How many bytes of `sealed` came before the trailer, if they still match its checksum
+fn unseal(sealed: &[u8]) -> Option<usize> {
+    let len = sealed.len().checked_sub(12)?;
+    let (bytes, trailer) = sealed.split_at(len);
+    let (sum, magic) = trailer.split_at(8);
+    (magic == TRAILER_MAGIC && checksum(bytes).to_le_bytes() == sum).then_some(len)
+}
+
+/// The image we last displayed on the output, and how we made it, if we know
+pub fn previous_image(output_name: &str) -> Result<Option<(String, Remake)>, String> {
     let mut filepath = cache_dir()?;
     clean_previous_verions(&filepath);
 
     filepath.push(output_name);
     if !filepath.is_file() {
-        return Ok("".to_string());
+        return Ok(None);
     }
     let file = std::fs::File::open(filepath).map_err(|e| format!("failed to open file: {e}"))?;
     let mut reader = BufReader::new(file);
@@ -93,39 +294,14 @@ pub fn get_previous_image_path(output_name: &str) -> Result<String, String> {
         .read_to_end(&mut buf)
         .map_err(|e| format!("failed to read file: {e}"))?;
 
-    String::from_utf8(buf).map_err(|e| format!("failed to decode bytes: {e}"))
-}
-
-pub fn load(output_name: &str) -> Result<(), String> {
-    let img_path = get_previous_image_path(output_name)?;
-    if img_path.is_empty() {
-        return Ok(());
-    }
-
-    if let Ok(mut child) = std::process::Command::new("pidof").arg("swww").spawn() {
-        if let Ok(status) = child.wait() {
-            if status.success() {
-                return Err("there is already another swww process running".to_string());
-            }
-        }
-    }
-
-    match std::process::Command::new("swww")
-        .arg("img")
-        .args([
-            &format!("--outputs={output_name}"),
-            "--transition-type=none",
-            &img_path,
-        ])
-        .spawn()
-    {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("failed to spawn child process: {e}")),
-    }
+    let contents = String::from_utf8(buf).map_err(|e| format!("failed to decode bytes: {e}"))?;
+    let (path, remake) = parse_previous_image(&contents);
+    Ok((!path.is_empty()).then_some((path, remake)))
 }
 
 pub fn clean() -> Result<(), String> {
-    std::fs::remove_dir_all(cache_dir()?).map_err(|e| format!("failed to remove cache directory: {e}"))
+    std::fs::remove_dir_all(cache_dir()?)
+        .map_err(|e| format!("failed to remove cache directory: {e}"))
 }
 
 fn clean_previous_verions(cache_dir: &Path) {
@@ -173,15 +349,17 @@ fn create_dir(p: &Path) -> Result<(), String> {
 }
 
 fn cache_dir() -> Result<PathBuf, String> {
+    // every namespace has a cache of its own, so `swww clear-cache` only clears that one
+    let name = crate::ipc::namespaced("swww");
     if let Ok(path) = std::env::var("XDG_CACHE_HOME") {
         let mut path: PathBuf = path.into();
-        path.push("swww");
+        path.push(name);
         create_dir(&path)?;
         Ok(path)
     } else if let Ok(path) = std::env::var("HOME") {
         let mut path: PathBuf = path.into();
         path.push(".cache");
-        path.push("swww");
+        path.push(name);
         create_dir(&path)?;
         Ok(path)
     } else {
@@ -190,13 +368,100 @@ fn cache_dir() -> Result<PathBuf, String> {
 }
 
 #[must_use]
-fn animation_filename(path: &Path, dimensions: (u32, u32)) -> PathBuf {
+fn animation_filename(
+    key: u64,
+    dimensions: (u32, u32),
+    compression: CompressionBackend,
+) -> PathBuf {
+    // lz4 animations keep the names they had before we had other backends
+    let compression = match compression {
+        CompressionBackend::Lz4 => "",
+        CompressionBackend::Zstd => "_zstd",
+        CompressionBackend::None => "_uncompressed",
+    };
     format!(
-        "{}__{}x{}_v{}",
-        path.to_string_lossy().replace('/', "_"),
+        "{key:016x}__{}x{}{compression}_v{}",
         dimensions.0,
         dimensions.1,
         env!("CARGO_PKG_VERSION"),
     )
     .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previous_images_should_keep_how_they_were_made() {
+        let remake = Remake {
+            cwd: "/home/me".to_string(),
+            args: Box::new([
+                "--resize=fit".to_string(),
+                "-f".to_string(),
+                "Nearest".to_string(),
+            ]),
+        };
+        let contents = previous_image_contents("/walls/a b.png", &remake);
+        assert_eq!(
+            parse_previous_image(&contents),
+            ("/walls/a b.png".to_string(), remake)
+        );
+        // like those older versions wrote
+        assert_eq!(
+            parse_previous_image("/walls/a.png"),
+            ("/walls/a.png".to_string(), Remake::default())
+        );
+    }
+
+    #[test]
+    fn animation_key_should_change_with_the_file_contents_and_the_filter() {
+        let path = std::env::temp_dir().join(format!("swww-cache-key-{}", std::process::id()));
+        std::fs::write(&path, b"some gif").unwrap();
+        let key = animation_key(&path, "Lanczos3").unwrap();
+        assert_eq!(key, animation_key(&path, "Lanczos3").unwrap());
+        assert_ne!(key, animation_key(&path, "Nearest").unwrap());
+
+        std::fs::write(&path, b"another gif").unwrap();
+        let changed = animation_key(&path, "Lanczos3");
+        let _ = std::fs::remove_file(&path);
+        assert_ne!(key, changed.unwrap());
+    }
+
+    #[test]
+    fn eviction_should_delete_the_least_recently_used_frames() {
+        let dir = std::env::temp_dir().join(format!("swww-cache-lru-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = |key: u64| animation_filename(key, (4, 4), CompressionBackend::Lz4);
+        for key in 1..=3 {
+            std::fs::write(dir.join(name(key)), [0; 100]).unwrap();
+            set_modified(&File::open(dir.join(name(key))).unwrap(), key as i64 * 60);
+        }
+        // what isn't frames is never evicted
+        std::fs::write(dir.join("DP-1"), [0; 100]).unwrap();
+        // the first ones are used again
+        touch(&File::open(dir.join(name(1))).unwrap());
+
+        let freed = evict_oldest(&dir, 200);
+        let left = |key| dir.join(name(key)).is_file();
+        let (first, second, third) = (left(1), left(2), left(3));
+        let output = dir.join("DP-1").is_file();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(freed, Ok(100));
+        assert_eq!((first, second, third, output), (true, false, true, true));
+    }
+
+    #[test]
+    fn corrupt_cached_frames_should_not_unseal() {
+        let sealed = seal(b"some frames");
+        assert_eq!(unseal(&sealed), Some(11));
+        // cut short
+        assert_eq!(unseal(&sealed[..sealed.len() - 1]), None);
+        assert_eq!(unseal(b"swww"), None);
+        // from before we had checksums
+        assert_eq!(unseal(b"some frames"), None);
+        let mut flipped = sealed.clone();
+        flipped[3] ^= 1;
+        assert_eq!(unseal(&flipped), None);
+    }
+}
@@ -0,0 +1,341 @@
+//! Reads the configuration file, `$XDG_CONFIG_HOME/swww/config.toml`.
+//!
+//! Most sections of the file hold the defaults of one program: `[daemon]` for `swww-daemon`, and
+//! `[img]` for `swww img`. Their keys are the program's long options without the dashes, like
+//! `gc-interval = 60`. We turn them into command line arguments, which the program parses before
+//! its real ones, so the command line always wins. `[output."<pattern>"]` sections hold more
+//! `swww img` defaults, for the outputs that match the pattern, like `--outputs` takes. Others,
+//! like the daemon's `[schedule]`, are read as they are, see [`Config::entries`].
+//!
+//! Going through the arguments means every default is checked exactly like the option it stands
+//! for, so all we need from the file are strings, numbers, booleans, and arrays of them, which
+//! become lists separated by commas, like `margin = [10, 20, 10, 20]`.
+
+use std::path::PathBuf;
+
+use toml_edit::{Document, Item, TableLike, Value as Toml};
+
+use crate::outputs::OutputPattern;
+
+pub fn path() -> Option<PathBuf> {
+    let mut path = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let mut home = PathBuf::from(std::env::var_os("HOME")?);
+            home.push(".config");
+            home
+        }
+    };
+    path.push("swww");
+    path.push("config.toml");
+    Some(path)
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Every section but `[output]`, with their `key = value` pairs in order
+    sections: Vec<(String, Vec<(String, Value)>)>,
+    /// The `[output."<pattern>"]` sections, in order
+    outputs: Vec<(OutputPattern, Vec<(String, Value)>)>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Bool(bool),
+    /// Strings, numbers and arrays, which we pass along as they are
+    Other(String),
+}
+
+impl Config {
+    /// Reads the configuration file. Not having one is not an error, we just have no defaults
+    pub fn read() -> Result<Self, String> {
+        let Some(path) = path() else {
+            return Ok(Self::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(toml) => Self::parse(&toml).map_err(|e| format!("in {path:?}: {e}")),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("failed to read {path:?}: {e}")),
+        }
+    }
+
+    /// Checks every value of every section, even those we aren't reading, so that a mistake
+    /// anywhere in the file is caught by whichever program reads it first
+    pub fn parse(toml: &str) -> Result<Self, String> {
+        let document: Document = toml.parse().map_err(|e| format!("{e}"))?;
+        let mut config = Self::default();
+        for (name, item) in document.iter() {
+            let Some(table) = item.as_table_like() else {
+                return Err(format!("`{name}` must be a section, like [{name}]"));
+            };
+            if name == "output" {
+                for (pattern, item) in table.iter() {
+                    let name = format!("output.\"{pattern}\"");
+                    let Some(table) = item.as_table_like() else {
+                        return Err(format!("`{name}` must be a section, like [{name}]"));
+                    };
+                    let pattern =
+                        OutputPattern::new(pattern).map_err(|e| format!("[{name}]: {e}"))?;
+                    config.outputs.push((pattern, parse_section(&name, table)?));
+                }
+            } else {
+                config
+                    .sections
+                    .push((name.to_string(), parse_section(name, table)?));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Turns every `key = value` of `section` into `--key value`. A `true` value becomes just
+    /// `--key`, and a `false` one is left out
+    pub fn args(&self, section: &str) -> Vec<String> {
+        to_args(self.section(section))
+    }
+
+    /// The arguments in every `[output."<pattern>"]` section whose pattern matches `output`, in
+    /// the order they come in, so that the last one wins
+    pub fn output_args(&self, output: &str) -> Vec<String> {
+        to_args(
+            self.outputs
+                .iter()
+                .filter(|(pattern, _)| pattern.matches(output))
+                .flat_map(|(_, entries)| entries),
+        )
+    }
+
+    /// Every `key = value` of `section`, in order, with the values as strings, and booleans as
+    /// `true` and `false`
+    pub fn entries(&self, section: &str) -> Vec<(String, String)> {
+        self.section(section)
+            .map(|(key, value)| match value {
+                Value::Bool(b) => (key.clone(), b.to_string()),
+                Value::Other(value) => (key.clone(), value.clone()),
+            })
+            .collect()
+    }
+
+    fn section<'a>(&'a self, section: &'a str) -> impl Iterator<Item = &'a (String, Value)> {
+        self.sections
+            .iter()
+            .filter(move |(name, _)| name == section)
+            .flat_map(|(_, entries)| entries)
+    }
+}
+
+fn to_args<'a>(entries: impl Iterator<Item = &'a (String, Value)>) -> Vec<String> {
+    let mut args = Vec::new();
+    for (key, value) in entries {
+        match value {
+            Value::Bool(true) => args.push(format!("--{key}")),
+            Value::Bool(false) => (),
+            Value::Other(value) => {
+                args.push(format!("--{key}"));
+                args.push(value.clone());
+            }
+        }
+    }
+    args
+}
+
+fn parse_section(section: &str, table: &dyn TableLike) -> Result<Vec<(String, Value)>, String> {
+    table
+        .iter()
+        .map(|(key, item)| {
+            let value = match item {
+                Item::Value(value) => parse_value(value),
+                _ => Err("sections can't be nested here".to_string()),
+            };
+            match value {
+                Ok(value) if !key.is_empty() => Ok((key.to_string(), value)),
+                Ok(_) => Err(format!("[{section}] has an empty key")),
+                Err(e) => Err(format!("[{section}] {key}: {e}")),
+            }
+        })
+        .collect()
+}
+
+fn parse_value(value: &Toml) -> Result<Value, String> {
+    match value {
+        Toml::Boolean(b) => Ok(Value::Bool(*b.value())),
+        Toml::Array(array) => {
+            let elements = array
+                .iter()
+                .map(|value| match value {
+                    Toml::Boolean(_) | Toml::Array(_) | Toml::InlineTable(_) => {
+                        Err("arrays may only hold strings and numbers".to_string())
+                    }
+                    scalar => Ok(scalar_to_string(scalar)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Other(elements.join(",")))
+        }
+        Toml::InlineTable(_) => {
+            Err("expected a string, a number, a boolean or an array".to_string())
+        }
+        scalar => Ok(Value::Other(scalar_to_string(scalar))),
+    }
+}
+
+fn scalar_to_string(value: &Toml) -> String {
+    match value {
+        Toml::String(s) => s.value().clone(),
+        Toml::Integer(i) => i.value().to_string(),
+        Toml::Float(f) => f.value().to_string(),
+        // like `07:00:00`, unquoted
+        other => other.to_string().trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+# swww's configuration
+[daemon]
+gc-interval = 60 # seconds
+memory-limit = 1_024
+transition-interrupt-policy = "keep"
+track-cursor = true
+prefer-compositor-scaling = false
+margin = [10, 20, 10, 20]
+
+[img]
+transition-type = 'grow'
+fill-color = "#112233"
+transition-bezier = "ease-in"
+
+[output."DP-*"]
+resize = "fit"
+transition-fps = 144
+
+[output.eDP-1]
+transition-type = "fade"
+
+[output."re:DP-[12]"]
+transition-fps = 60
+"##;
+
+    #[test]
+    fn should_turn_a_section_into_args() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(
+            config.args("daemon"),
+            [
+                "--gc-interval",
+                "60",
+                "--memory-limit",
+                "1024",
+                "--transition-interrupt-policy",
+                "keep",
+                "--track-cursor",
+                "--margin",
+                "10,20,10,20"
+            ]
+        );
+        assert_eq!(
+            config.args("img"),
+            [
+                "--transition-type",
+                "grow",
+                "--fill-color",
+                "#112233",
+                "--transition-bezier",
+                "ease-in"
+            ]
+        );
+        assert!(config.args("query").is_empty());
+        assert!(Config::parse("").unwrap().args("daemon").is_empty());
+    }
+
+    #[test]
+    fn outputs_should_get_the_args_of_every_section_that_matches_them() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(
+            config.output_args("DP-1"),
+            [
+                "--resize",
+                "fit",
+                "--transition-fps",
+                "144",
+                "--transition-fps",
+                "60"
+            ]
+        );
+        assert_eq!(
+            config.output_args("DP-3"),
+            ["--resize", "fit", "--transition-fps", "144"]
+        );
+        assert_eq!(config.output_args("eDP-1"), ["--transition-type", "fade"]);
+        assert!(config.output_args("HDMI-A-1").is_empty());
+        // they aren't sections of their own
+        assert!(config.args("output").is_empty());
+    }
+
+    #[test]
+    fn should_read_a_section_as_it_is() {
+        let toml = "[schedule]\n\"07:00\" = \"day.png\"\n'sunset+1h' = 'night.png'\nx = true\n";
+        assert_eq!(
+            Config::parse(toml).unwrap().entries("schedule"),
+            [
+                ("07:00".to_string(), "day.png".to_string()),
+                ("sunset+1h".to_string(), "night.png".to_string()),
+                ("x".to_string(), "true".to_string())
+            ]
+        );
+        assert!(Config::parse("[schedule]\n\"\" = 1").is_err());
+        assert!(Config::parse("[schedule]\n\"07:00 = 1").is_err());
+    }
+
+    #[test]
+    fn should_read_values_like_toml_does() {
+        let toml = r#"
+[img]
+a = "a \"quoted\" \\ path"
+b = 'C:\literal'
+c = -1.5
+d = """
+multi"""
+e = 0x10
+"#;
+        assert_eq!(
+            Config::parse(toml).unwrap().entries("img"),
+            [
+                ("a", r#"a "quoted" \ path"#),
+                ("b", r"C:\literal"),
+                ("c", "-1.5"),
+                ("d", "multi"),
+                ("e", "16")
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        // dotted keys and inline tables are other ways of writing sections
+        let toml = "output.DP-1.resize = \"fit\"\nimg = { resize = \"no\" }";
+        let config = Config::parse(toml).unwrap();
+        assert_eq!(config.output_args("DP-1"), ["--resize", "fit"]);
+        assert_eq!(config.args("img"), ["--resize", "no"]);
+    }
+
+    #[test]
+    fn should_reject_what_programs_can_not_take() {
+        for invalid in [
+            "[daemon]\ngc-interval",
+            "[daemon\na = 1",
+            "[daemon]\na = \"unterminated",
+            "[daemon]\na = yes",
+            "[daemon]\n = 1",
+            "[daemon]\na b = 1",
+            "[daemon]\na = \"bad \\x escape\"",
+            "[daemon]\na = { b = 1 }",
+            "[daemon]\na = [[1], 2]",
+            "[daemon]\na = [true]",
+            "[daemon.nested]\na = 1",
+            "a = 1",
+            "[output]\na = 1",
+            "[output.\"re:(\"]\na = 1",
+        ] {
+            assert!(Config::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
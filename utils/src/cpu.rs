@@ -0,0 +1,53 @@
+//! Keeps image processing from taking over the CPU.
+//!
+//! The daemon's `--nice` and `--idle-priority` lower the priority of the threads it processes
+//! frames with, and clients processing images for it follow them too, see `ipc::CpuLimits`. On
+//! Linux, both the niceness and the scheduling policy belong to threads, not to processes, and
+//! threads start with those of the thread that spawned them.
+
+use std::io;
+
+/// The most a thread can be niced to, which gives it the least CPU time
+pub const MAX_NICE: u8 = 19;
+
+/// Makes the calling thread, and the threads it spawns from now on, `nice` (from 0 to
+/// [`MAX_NICE`]), and, with `idle`, only run when nothing else wants to
+pub fn lower_priority(nice: Option<u8>, idle: bool) -> Result<(), String> {
+    if let Some(nice) = nice {
+        // `0` is the calling thread, on Linux
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice.min(MAX_NICE).into()) } != 0 {
+            return Err(format!(
+                "failed to set niceness to {nice}: {}",
+                io::Error::last_os_error()
+            ));
+        }
+    }
+    if idle {
+        let param = libc::sched_param { sched_priority: 0 };
+        if unsafe { libc::sched_setscheduler(0, libc::SCHED_IDLE, &param) } != 0 {
+            return Err(format!(
+                "failed to set the idle scheduling policy: {}",
+                io::Error::last_os_error()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_should_be_able_to_lower_their_priority() {
+        // in a thread of its own, so that the other tests run as usual
+        std::thread::spawn(|| {
+            lower_priority(Some(MAX_NICE), true).unwrap();
+            assert_eq!(unsafe { libc::sched_getscheduler(0) }, libc::SCHED_IDLE);
+            // the niceness can't go back down without privileges, but it can stay
+            lower_priority(Some(MAX_NICE), false).unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+}
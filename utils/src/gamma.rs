@@ -0,0 +1,91 @@
+//! Moves pixels in and out of linear light, for `swww img --gamma-correct`.
+//!
+//! Image bytes are sRGB encoded, so averaging them, which is all scaling and blending do, comes out
+//! darker than averaging the light they stand for: fine detail dims when shrunk, and fades dip in
+//! the middle. Converting to linear light fixes that. We keep 16 bits per channel while there, so
+//! that the dark shades, which sRGB spends the most codes on, all make it back. Both ways are
+//! lookups in tables we build once, so the hot loops never call `powf`.
+
+use std::sync::OnceLock;
+
+static TO_LINEAR: OnceLock<[u16; 256]> = OnceLock::new();
+static TO_SRGB: OnceLock<Box<[u8]>> = OnceLock::new();
+
+/// Maps every sRGB byte to its linear light, from 0 to `u16::MAX`
+pub fn to_linear_table() -> &'static [u16; 256] {
+    TO_LINEAR.get_or_init(|| {
+        let mut table = [0; 256];
+        for (i, linear) in table.iter_mut().enumerate() {
+            let v = i as f64 / 255.0;
+            let v = if v <= 0.04045 {
+                v / 12.92
+            } else {
+                ((v + 0.055) / 1.055).powf(2.4)
+            };
+            *linear = (v * u16::MAX as f64).round() as u16;
+        }
+        table
+    })
+}
+
+/// Maps every linear light, from 0 to `u16::MAX`, back to the closest sRGB byte
+pub fn to_srgb_table() -> &'static [u8] {
+    TO_SRGB.get_or_init(|| {
+        (0..=u16::MAX as u32)
+            .map(|i| {
+                let v = i as f64 / u16::MAX as f64;
+                let v = if v <= 0.0031308 {
+                    v * 12.92
+                } else {
+                    1.055 * v.powf(1.0 / 2.4) - 0.055
+                };
+                (v * 255.0).round() as u8
+            })
+            .collect()
+    })
+}
+
+/// Converts every sRGB byte to its linear light
+pub fn to_linear(bytes: &[u8]) -> Vec<u16> {
+    let table = to_linear_table();
+    bytes.iter().map(|&b| table[b as usize]).collect()
+}
+
+/// Converts every linear light back to sRGB bytes
+pub fn to_srgb(linear: &[u16]) -> Vec<u8> {
+    let table = to_srgb_table();
+    linear.iter().map(|&l| table[l as usize]).collect()
+}
+
+/// The sRGB byte `weight` 256ths of the way from `from` to `to` in linear light. Takes the tables,
+/// so that callers look them up once rather than once for every byte
+#[inline]
+pub fn mix(from: u8, to: u8, weight: u32, to_linear: &[u16; 256], to_srgb: &[u8]) -> u8 {
+    let (from, to) = (
+        to_linear[from as usize] as u32,
+        to_linear[to as usize] as u32,
+    );
+    to_srgb[((from * (256 - weight) + to * weight) >> 8) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_byte_should_survive_the_round_trip() {
+        let bytes = (0..=u8::MAX).collect::<Vec<_>>();
+        assert_eq!(to_srgb(&to_linear(&bytes)), bytes);
+        assert_eq!(to_linear(&[0, 255]), [0, u16::MAX]);
+    }
+
+    #[test]
+    fn mixing_in_linear_light_should_be_brighter() {
+        let (to_linear, to_srgb) = (to_linear_table(), to_srgb_table());
+        assert_eq!(mix(0, 255, 0, to_linear, to_srgb), 0);
+        assert_eq!(mix(0, 255, 256, to_linear, to_srgb), 255);
+        // half the light of white is much brighter than the byte halfway to it
+        assert!(mix(0, 255, 128, to_linear, to_srgb) > 180);
+        assert_eq!(mix(77, 77, 100, to_linear, to_srgb), 77);
+    }
+}
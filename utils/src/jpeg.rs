@@ -0,0 +1,114 @@
+//! JPEG transcoding, for images so large that sending their raw pixels to the daemon is
+//! impractical. The client encodes them with `swww img --quality`, and the daemon decodes them
+//! back before drawing.
+//!
+//! Like in [`crate::ipc::Img`], images have 3 bytes per pixel, in BGR order. The JPEGs themselves
+//! are ordinary ones, so that their colors are compressed like those of any other.
+
+/// Encodes `bgr` with the given quality, from 1 to 100
+pub fn encode(bgr: &[u8], dim: (u32, u32), quality: u8) -> Result<Vec<u8>, String> {
+    let (Ok(width), Ok(height)) = (u16::try_from(dim.0), u16::try_from(dim.1)) else {
+        return Err(format!(
+            "{}x{} is too large for a JPEG, which is limited to {}x{}",
+            dim.0,
+            dim.1,
+            u16::MAX,
+            u16::MAX
+        ));
+    };
+    let mut jpeg = Vec::new();
+    jpeg_encoder::Encoder::new(&mut jpeg, quality)
+        .encode(bgr, width, height, jpeg_encoder::ColorType::Bgr)
+        .map_err(|e| format!("failed to encode image as JPEG: {e}"))?;
+    Ok(jpeg)
+}
+
+/// Decodes a JPEG made by [`encode`] back to BGR, making sure it has the dimensions we expect
+pub fn decode(jpeg: &[u8], dim: (u32, u32)) -> Result<Vec<u8>, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(jpeg);
+    let mut bgr = decoder
+        .decode()
+        .map_err(|e| format!("failed to decode JPEG: {e}"))?;
+    match decoder.info() {
+        Some(info) if info.pixel_format == jpeg_decoder::PixelFormat::RGB24 => {
+            if (info.width as u32, info.height as u32) == dim {
+                for pixel in bgr.chunks_exact_mut(3) {
+                    pixel.swap(0, 2);
+                }
+                Ok(bgr)
+            } else {
+                Err(format!(
+                    "JPEG is {}x{}, expected {}x{}",
+                    info.width, info.height, dim.0, dim.1
+                ))
+            }
+        }
+        _ => Err("JPEG is not in the RGB format".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Peak signal-to-noise ratio, in dB
+    fn psnr(a: &[u8], b: &[u8]) -> f64 {
+        let mse = a
+            .iter()
+            .zip(b)
+            .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+            .sum::<f64>()
+            / a.len() as f64;
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+
+    #[test]
+    fn should_round_trip_large_images_at_high_quality() {
+        let dim = (3840, 2160);
+        let mut original = Vec::with_capacity(dim.0 as usize * dim.1 as usize * 3);
+        for y in 0..dim.1 {
+            for x in 0..dim.0 {
+                let stripe = if (x / 64 + y / 64) % 2 == 0 { 40 } else { 0 };
+                original.extend_from_slice(&[
+                    (x * 200 / dim.0) as u8 + stripe,
+                    (y * 200 / dim.1) as u8 + stripe,
+                    ((x + y) % 256) as u8,
+                ]);
+            }
+        }
+
+        let jpeg = encode(&original, dim, 85).unwrap();
+        assert!(
+            jpeg.len() < original.len() / 4,
+            "jpeg is {} bytes",
+            jpeg.len()
+        );
+        let decoded = decode(&jpeg, dim).unwrap();
+        assert_eq!(decoded.len(), original.len());
+        let psnr = psnr(&original, &decoded);
+        assert!(psnr > 30.0, "psnr was only {psnr:.1}dB");
+
+        assert!(decode(&jpeg, (dim.1, dim.0)).is_err());
+        assert!(encode(&[0; 3], (u16::MAX as u32 + 1, 1), 85).is_err());
+    }
+
+    #[test]
+    fn should_make_jpegs_with_the_colors_we_meant() {
+        let dim = (16, 16);
+        // red, in BGR
+        let red = [0, 0, 255].repeat(16 * 16);
+        let jpeg = encode(&red, dim, 90).unwrap();
+
+        // what any other program decoding it would see
+        let rgb = jpeg_decoder::Decoder::new(&jpeg[..]).decode().unwrap();
+        let [r, g, b] = [rgb[0], rgb[1], rgb[2]];
+        assert!(r > 240 && g < 16 && b < 16, "red looked like {r}, {g}, {b}");
+
+        let bgr = decode(&jpeg, dim).unwrap();
+        let [b, g, r] = [bgr[0], bgr[1], bgr[2]];
+        assert!(
+            r > 240 && g < 16 && b < 16,
+            "red came back as {r}, {g}, {b}"
+        );
+    }
+}
@@ -1,3 +1,20 @@
 pub mod cache;
-pub mod comp_decomp;
-pub mod ipc;
+pub mod config;
+pub mod cpu;
+pub mod gamma;
+pub mod jpeg;
+pub mod outputs;
+pub mod placement;
+pub mod scene;
+
+/// The protocol moved to its own crate, `fswww-ipc`. We re-export it so `swww` and `swww-daemon`
+/// can keep getting everything from here
+pub use fswww_ipc as ipc;
+pub use fswww_ipc::comp_decomp;
+
+/// The extensions of the files we take for images when looking through a directory, like
+/// `swww img` given one, and `swww slideshow`
+pub const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "bmp", "tif", "tiff", "tga", "pnm", "qoi", "avif", "jxl",
+    "svg", "ico", "hdr", "exr", "farbfeld",
+];
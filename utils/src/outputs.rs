@@ -0,0 +1,153 @@
+//! Picks outputs by their names, for `--outputs`.
+//!
+//! Names shift around as monitors get plugged into different docks and ports (`DP-3` one day,
+//! `DP-4` the next), so besides exact names we take globs, like `DP-*`, and, after a `re:`, regular
+//! expressions, like `re:eDP.*`. Both must match the whole name. The daemon resolves them against
+//! the outputs it has when the request arrives, so later outputs only get them if they match too.
+
+use regex::Regex;
+
+use crate::ipc::{Error, ErrorKind};
+
+/// One of the outputs a request asked for. Those we keep around are parsed once, since compiling
+/// a regex is much slower than matching it
+#[derive(Clone, Debug)]
+pub enum OutputPattern {
+    Name(String),
+    /// With `*` for any number of characters, and `?` for exactly one
+    Glob(String),
+    Regex(Regex),
+}
+
+impl OutputPattern {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        if let Some(re) = pattern.strip_prefix("re:") {
+            Regex::new(&format!("^(?:{re})$"))
+                .map(Self::Regex)
+                .map_err(|e| format!("invalid output regex {re:?}: {e}"))
+        } else if pattern.contains(['*', '?']) {
+            Ok(Self::Glob(pattern.to_string()))
+        } else {
+            Ok(Self::Name(pattern.to_string()))
+        }
+    }
+
+    /// Every one of `patterns`, or the error of the first that doesn't parse
+    pub fn parse_all<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<Self>, String> {
+        patterns.iter().map(|p| Self::new(p.as_ref())).collect()
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Name(n) => n == name,
+            Self::Glob(glob) => glob_matches(glob.as_bytes(), name.as_bytes()),
+            Self::Regex(re) => re.is_match(name),
+        }
+    }
+}
+
+impl PartialEq for OutputPattern {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Name(a), Self::Name(b)) | (Self::Glob(a), Self::Glob(b)) => a == b,
+            (Self::Regex(a), Self::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+/// Picks which of `names` a request for `patterns` is about: all of them, if there are no patterns.
+/// When there are, but none of them match, the error lists the names we have
+pub fn resolve<'a, S: AsRef<str>>(
+    patterns: &[S],
+    names: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<&'a str>, Error> {
+    let parsed = OutputPattern::parse_all(patterns)
+        .map_err(|e| Error::new(ErrorKind::InvalidArgument, e))?;
+    let names: Vec<_> = names.into_iter().collect();
+    let matched: Vec<_> = names
+        .iter()
+        .copied()
+        .filter(|name| parsed.is_empty() || parsed.iter().any(|p| p.matches(name)))
+        .collect();
+    if matched.is_empty() && !parsed.is_empty() {
+        let patterns: Vec<_> = patterns.iter().map(|p| p.as_ref()).collect();
+        return Err(Error::new(
+            ErrorKind::NoSuchOutput,
+            format!(
+                "no output matches {}; the outputs are: {}",
+                patterns.join(", "),
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            ),
+        ));
+    }
+    Ok(matched)
+}
+
+fn glob_matches(glob: &[u8], name: &[u8]) -> bool {
+    // where the last `*` was, and where in the name it started matching, to backtrack to
+    let mut star = None;
+    let (mut g, mut n) = (0, 0);
+    while n < name.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the `*` take one more character
+                Some((star_g, star_n)) => {
+                    star = Some((star_g, star_n + 1));
+                    g = star_g + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_should_match_whole_names() {
+        let matches =
+            |pattern: &str, name: &str| OutputPattern::new(pattern).unwrap().matches(name);
+        assert!(matches("DP-3", "DP-3"));
+        assert!(!matches("DP-3", "DP-30"));
+        assert!(matches("DP-*", "DP-4"));
+        assert!(!matches("DP-*", "eDP-1"));
+        assert!(matches("*-?", "HDMI-A"));
+        assert!(matches("*DP*1", "eDP-1"));
+        assert!(!matches("DP-?", "DP-10"));
+        assert!(matches("re:e?DP-\\d+", "eDP-12"));
+        assert!(!matches("re:DP", "eDP-1"));
+        assert!(OutputPattern::new("re:(").is_err());
+
+        let names = ["eDP-1", "DP-3", "DP-4"];
+        assert_eq!(resolve(&["DP-*"], names).unwrap(), ["DP-3", "DP-4"]);
+        assert_eq!(resolve::<&str>(&[], names).unwrap(), names);
+        assert_eq!(
+            resolve(&["HDMI-*"], names).unwrap_err(),
+            Error::new(
+                ErrorKind::NoSuchOutput,
+                "no output matches HDMI-*; the outputs are: eDP-1, DP-3, DP-4"
+            )
+        );
+        assert_eq!(
+            resolve(&["re:("], names).unwrap_err().kind,
+            ErrorKind::InvalidArgument
+        );
+    }
+}
@@ -0,0 +1,42 @@
+//! Parses `--size` and `--margin`, which `swww init` checks before passing them on to
+//! `swww-daemon`.
+
+/// Parses `--margin`: one value for every edge, or four, for the top, right, bottom and left
+pub fn parse_margin(s: &str) -> Result<[i32; 4], String> {
+    let margins: Vec<_> = s
+        .split(',')
+        .map(|m| m.trim().parse::<u16>().map(i32::from))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("must be one or four numbers of pixels: {e}"))?;
+    match margins[..] {
+        [m] => Ok([m; 4]),
+        [top, right, bottom, left] => Ok([top, right, bottom, left]),
+        _ => Err(format!("must be one or four numbers of pixels, got: {s}")),
+    }
+}
+
+/// Parses `--size`, like `800x600`
+pub fn parse_size(s: &str) -> Result<(i32, i32), String> {
+    let parse = |n: &str| n.parse::<u16>().ok().filter(|n| *n > 0).map(i32::from);
+    match s.split_once('x') {
+        Some((width, height)) => parse(width).zip(parse(height)),
+        None => None,
+    }
+    .ok_or_else(|| format!("must be a positive width and height, like 800x600, got: {s}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_margins_and_sizes() {
+        assert_eq!(parse_margin("20").unwrap(), [20; 4]);
+        assert_eq!(parse_margin("1,2,3,4").unwrap(), [1, 2, 3, 4]);
+        assert!(parse_margin("1,2").is_err());
+        assert!(parse_margin("-1").is_err());
+        assert_eq!(parse_size("800x600").unwrap(), (800, 600));
+        assert!(parse_size("800").is_err());
+        assert!(parse_size("0x600").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! Tells whether two images show different scenes, see `swww img --detect-scene-change`.
+//!
+//! We compare their color histograms, with 4 levels per channel, so 64 bins. The daemon checks
+//! every transition as it starts, against what each output displays, and the client checks every
+//! pair of frames of the videos it decodes. Both images must have their channels in the same
+//! order, but not the same number of bytes per pixel: only the first three are looked at.
+
+/// More than enough pixels to tell two scenes apart, and little enough to do it right away
+const MAX_SAMPLES: usize = 1 << 16;
+
+/// How many levels every channel is quantized into
+const LEVELS: usize = 4;
+
+/// A normalized color histogram, whose bins sum to 1.0, unless the image was empty
+pub type Histogram = [f32; LEVELS * LEVELS * LEVELS];
+
+/// Whether `new` differs from `old` by more than `threshold`, see [`difference`]
+pub fn is_scene_change(old: (&[u8], usize), new: (&[u8], usize), threshold: f32) -> bool {
+    difference(&histogram(old.0, old.1), &histogram(new.0, new.1)) > threshold
+}
+
+/// The L1 distance between two histograms, from 0.0 (the same colors) to 2.0 (no colors in
+/// common)
+pub fn difference(a: &Histogram, b: &Histogram) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum()
+}
+
+/// The histogram of `pixels`, `bytes_per_pixel` each
+pub fn histogram(pixels: &[u8], bytes_per_pixel: usize) -> Histogram {
+    let mut histogram = [0.0; LEVELS * LEVELS * LEVELS];
+    let pixels = pixels.chunks_exact(bytes_per_pixel);
+    let step = (pixels.len() / MAX_SAMPLES).max(1);
+    let mut count = 0;
+    let level = |c: u8| c as usize * LEVELS / 256;
+    for pixel in pixels.step_by(step) {
+        histogram[(level(pixel[0]) * LEVELS + level(pixel[1])) * LEVELS + level(pixel[2])] += 1.0;
+        count += 1;
+    }
+    if count > 0 {
+        histogram.iter_mut().for_each(|bin| *bin /= count as f32);
+    }
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drawn([b, g, r]: [u8; 3]) -> Vec<u8> {
+        [b, g, r, 0xff].repeat(256)
+    }
+
+    #[test]
+    fn sudden_color_changes_should_be_scene_changes() {
+        let red = drawn([0, 0, 255]);
+        assert!(is_scene_change(
+            (&red, 4),
+            (&[255, 0, 0].repeat(256), 3),
+            0.4
+        ));
+        // the same color, in the same order
+        assert!(!is_scene_change(
+            (&red, 4),
+            (&[0, 0, 255].repeat(256), 3),
+            0.0
+        ));
+    }
+
+    #[test]
+    fn similar_images_should_not_be_scene_changes() {
+        let mut new = [50, 100, 200].repeat(256);
+        new[..3].copy_from_slice(&[0, 0, 0]);
+        assert!(!is_scene_change(
+            (&drawn([50, 100, 200]), 4),
+            (&new, 3),
+            0.4
+        ));
+        // nor should images of different sizes, or with nothing in them
+        assert!(!is_scene_change(
+            (&drawn([1, 2, 3]), 4),
+            (&[1, 2, 3], 3),
+            0.4
+        ));
+        assert!(!is_scene_change((&[], 4), (&[], 3), 0.0));
+    }
+}
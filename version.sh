@@ -28,6 +28,9 @@ sed "s/^version = .*/version = \"$1\"/" utils/Cargo.toml > TMP \
 sed "s/^version = .*/version = \"$1\"/" daemon/Cargo.toml > TMP \
 	&& mv TMP daemon/Cargo.toml
 
+sed "s/^version = .*/version = \"$1\"/" ipc/Cargo.toml > TMP \
+	&& mv TMP ipc/Cargo.toml
+
 # CHANGELOG:
 sed -e "s/^### Unreleased/### $1/" \
 	-e '1s/^/### Unreleased\n\n\n/' CHANGELOG.md > TMP \